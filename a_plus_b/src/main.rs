@@ -13,7 +13,6 @@ fn main() {
         .expect("reading stdin");
 
     let result: i32 = line
-        .trim()
         .split_whitespace()
         .map(|x| x.parse::<i32>().expect("not an integer"))
         .sum();
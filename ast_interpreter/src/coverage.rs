@@ -0,0 +1,145 @@
+use super::{is_print_list, is_statement, InterpreterHooks};
+use lexical_analyzer::error::*;
+use syntax_analyzer::ast_node::ASTNode;
+
+use std::collections::BTreeMap;
+
+/// 文ごとの実行回数。行ごとに、その行から始まる文を実行した回数の合計を数える。
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    counts: BTreeMap<usize, u64>,
+}
+
+impl Coverage {
+    /// ast の文がある行を、実行回数 0 として登録する
+    pub fn new(ast: &ASTNode) -> Self {
+        let mut counts = BTreeMap::new();
+        // 深い AST でもスタックを使い切らないように、再帰せずにたどる
+        let mut nodes = vec![ast];
+        while let Some(node) = nodes.pop() {
+            if let Some(line) = node.line().filter(|_| is_statement(node)) {
+                counts.insert(line, 0);
+            }
+            // 出力する引数の並びは全体で 1 つの文なので、引数の行は登録しない
+            if is_print_list(node) {
+                continue;
+            }
+            nodes.extend(node.lhs());
+            nodes.extend(node.rhs());
+        }
+        Coverage { counts }
+    }
+
+    /// line から始まる文の実行回数。文のない行なら None。
+    pub fn hits(&self, line: usize) -> Option<u64> {
+        self.counts.get(&line).copied()
+    }
+
+    /// 一度も実行しなかった文のある行
+    pub fn missed_lines(&self) -> Vec<usize> {
+        self.counts
+            .iter()
+            .filter(|(_, &count)| count == 0)
+            .map(|(&line, _)| line)
+            .collect()
+    }
+
+    /// 各行の先頭に実行回数を付けたソース。
+    /// 文のない行は "-"、実行しなかった文のある行は "#####" とする。
+    pub fn annotate(&self, src: &str) -> String {
+        let mut listing = String::new();
+        for (i, text) in src.lines().enumerate() {
+            let count = match self.hits(i + 1) {
+                Some(0) => "#####".to_string(),
+                Some(count) => count.to_string(),
+                None => "-".to_string(),
+            };
+            listing += &format!("{:>9}:{:>5}: {}\n", count, i + 1, text);
+        }
+        let statements = self.counts.len();
+        let executed = statements - self.missed_lines().len();
+        listing += &format!(
+            "{} of {} lines with statements executed\n",
+            executed, statements
+        );
+        listing
+    }
+}
+
+impl InterpreterHooks for Coverage {
+    fn on_statement(&mut self, node: &ASTNode) -> Result<()> {
+        if let Some(line) = node.line() {
+            *self.counts.entry(line).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ASTInterpreter, InterpreterOptions};
+
+    #[test]
+    fn test_coverage() {
+        let src = r#"i = 0;
+while (i < 3) {
+    /* odd numbers */
+    if (i % 2)
+        print(i);
+    else
+        ;
+    i = i + 1;
+}
+if (i > 5) {
+    print("unreachable");
+}
+"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let mut coverage = Coverage::new(&ast);
+        assert_eq!(Some(0), coverage.hits(1));
+        assert_eq!(None, coverage.hits(3));
+
+        let mut out = Vec::new();
+        ASTInterpreter::interpret_with_hooks(
+            &ast,
+            &mut out,
+            &InterpreterOptions::default(),
+            &mut coverage,
+        )
+        .unwrap();
+        assert_eq!(b"1", &out[..]);
+        assert_eq!(Some(1), coverage.hits(1));
+        assert_eq!(Some(1), coverage.hits(2));
+        assert_eq!(Some(3), coverage.hits(4));
+        assert_eq!(Some(1), coverage.hits(5));
+        assert_eq!(Some(3), coverage.hits(8));
+        assert_eq!(vec![11], coverage.missed_lines());
+
+        let listing = coverage.annotate(src);
+        assert!(listing.starts_with("        1:    1: i = 0;\n"));
+        assert!(listing.contains("        -:    3:     /* odd numbers */\n"));
+        assert!(listing.contains("    #####:   11:     print(\"unreachable\");\n"));
+        assert!(listing.ends_with("6 of 7 lines with statements executed\n"));
+    }
+
+    #[test]
+    fn test_coverage_print() {
+        // 引数が幾つあっても、出力する文は 1 回の実行を 1 回と数える
+        let src = "i = 0;\nwhile (i < 2) {\n  print(\"a\", i, \"\\n\");\n  printf(\"%d%c\", i,\n    10);\n  i = i + 1;\n}\n";
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let mut coverage = Coverage::new(&ast);
+        ASTInterpreter::interpret_with_hooks(
+            &ast,
+            &mut Vec::new(),
+            &InterpreterOptions::default(),
+            &mut coverage,
+        )
+        .unwrap();
+        assert_eq!(Some(2), coverage.hits(3));
+        assert_eq!(Some(2), coverage.hits(4));
+        assert_eq!(None, coverage.hits(5));
+        assert_eq!(Some(2), coverage.hits(6));
+        assert!(coverage.missed_lines().is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use super::{ASTInterpreter, InterpreterHooks, InterpreterOptions};
+use lexical_analyzer::error::*;
+use lexical_analyzer::float::Float;
+use syntax_analyzer::ast_node::ASTNode;
+
+use std::fmt;
+
+/// print 文や putc 文の 1 回の出力。書き出したバイト列ではなく、出力した値を持つ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    Int(i64),
+    Float(Float),
+    Str(String),
+    Char(char),
+}
+
+/// 書き出す文字列と同じ
+impl fmt::Display for OutputEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputEvent::Int(i) => write!(f, "{}", i),
+            OutputEvent::Float(value) => write!(f, "{}", value),
+            OutputEvent::Str(s) => write!(f, "{}", s),
+            OutputEvent::Char(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+/// 出力を OutputEvent の列として記録するコールバック。
+/// エラーで止まった場合も、それまでの出力が残る。
+#[derive(Debug, Default)]
+pub struct OutputRecorder {
+    pub events: Vec<OutputEvent>,
+}
+
+impl InterpreterHooks for OutputRecorder {
+    fn on_output(&mut self, event: &OutputEvent) -> Result<()> {
+        self.events.push(event.clone());
+        Ok(())
+    }
+}
+
+impl ASTInterpreter<'_> {
+    /// バイト列を書き出さずに実行し、出力を OutputEvent の列として返す。
+    pub fn interpret_events(
+        node: &ASTNode,
+        options: &InterpreterOptions,
+    ) -> Result<Vec<OutputEvent>> {
+        let mut recorder = OutputRecorder::default();
+        ASTInterpreter::interpret_with_hooks(node, &mut std::io::sink(), options, &mut recorder)?;
+        Ok(recorder.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntax_analyzer::parse_str;
+
+    #[test]
+    fn test_interpret_events() {
+        let ast = parse_str(r#"x = 42; print("x = ", x, "\n"); putc(65); printf("%d%c", 7, 'z');"#)
+            .unwrap();
+        assert_eq!(
+            vec![
+                OutputEvent::Str("x = ".to_string()),
+                OutputEvent::Int(42),
+                OutputEvent::Str("\n".to_string()),
+                OutputEvent::Char('A'),
+                OutputEvent::Int(7),
+                OutputEvent::Char('z'),
+            ],
+            ASTInterpreter::interpret_events(&ast, &InterpreterOptions::default()).unwrap()
+        );
+
+        // エラーで止まっても、記録した出力は残る
+        let ast = parse_str("print(1); x = 1 / 0; print(2);").unwrap();
+        let mut recorder = OutputRecorder::default();
+        let mut out = Vec::new();
+        let result = ASTInterpreter::interpret_with_hooks(
+            &ast,
+            &mut out,
+            &InterpreterOptions::default(),
+            &mut recorder,
+        );
+        assert!(result.is_err());
+        assert_eq!(vec![OutputEvent::Int(1)], recorder.events);
+        assert_eq!(b"1", &out[..]);
+    }
+
+    #[test]
+    fn test_99_bottles_of_beer() {
+        let src = r#"
+bottles = 99;
+while (bottles > 0) {
+    print(bottles, " bottles of beer on the wall\n");
+    print(bottles, " bottles of beer\n");
+    print("Take one down, pass it around\n");
+    bottles = bottles - 1;
+    print(bottles, " bottles of beer on the wall\n\n");
+}
+"#;
+        let ast = parse_str(src).unwrap();
+        let events =
+            ASTInterpreter::interpret_events(&ast, &InterpreterOptions::default()).unwrap();
+
+        // 1 節は 7 回の出力。数を出力した順に並べると 99, 99, 98, 98, 98, 97, ... になる
+        assert_eq!(99 * 7, events.len());
+        let numbers: Vec<i64> = events
+            .iter()
+            .filter_map(|e| match e {
+                OutputEvent::Int(i) => Some(*i),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<i64> = (1..=99).rev().flat_map(|n| vec![n, n, n - 1]).collect();
+        assert_eq!(expected, numbers);
+
+        // 最後の節
+        assert_eq!(
+            vec![
+                OutputEvent::Int(1),
+                OutputEvent::Str(" bottles of beer on the wall\n".to_string()),
+                OutputEvent::Int(1),
+                OutputEvent::Str(" bottles of beer\n".to_string()),
+                OutputEvent::Str("Take one down, pass it around\n".to_string()),
+                OutputEvent::Int(0),
+                OutputEvent::Str(" bottles of beer on the wall\n\n".to_string()),
+            ],
+            &events[events.len() - 7..]
+        );
+    }
+}
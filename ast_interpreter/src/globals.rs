@@ -0,0 +1,108 @@
+use super::{ASTInterpreter, InterpreterOptions, Value};
+use lexical_analyzer::symbol::Symbol;
+
+use std::collections::HashMap;
+
+impl ASTInterpreter<'static> {
+    /// globals の変数に値を入れた解釈器を作る。
+    /// run で解釈し、終わった後の変数の値を global や globals で読む。
+    /// 組み込む側が値を渡して、結果の値を受け取るのに使う。
+    pub fn with_globals(globals: HashMap<Symbol, Value>) -> Self {
+        Self::with_globals_and_options(globals, &InterpreterOptions::default())
+    }
+
+    pub fn with_globals_and_options(
+        globals: HashMap<Symbol, Value>,
+        options: &InterpreterOptions,
+    ) -> Self {
+        ASTInterpreter::new(globals, options, None)
+    }
+}
+
+impl ASTInterpreter<'_> {
+    /// 変数 name の値。代入されていなければ None。
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.global.get(&Symbol::intern(name)).copied()
+    }
+
+    /// すべての変数の値
+    pub fn globals(&self) -> &HashMap<Symbol, Value> {
+        &self.global
+    }
+
+    pub fn into_globals(self) -> HashMap<Symbol, Value> {
+        self.global
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexical_analyzer::dialect::Dialect;
+    use lexical_analyzer::float::Float;
+    use syntax_analyzer::{parse_str, parse_str_with, ParserOptions};
+
+    #[test]
+    fn test_with_globals() {
+        let ast = parse_str("i = 1; f = 1; while (i <= n) { f = f * i; i = i + 1; }").unwrap();
+        let globals = vec![(Symbol::intern("n"), Value::Integer(10))]
+            .into_iter()
+            .collect();
+        let mut interpreter = ASTInterpreter::with_globals(globals);
+        let mut out = Vec::new();
+        assert_eq!(0, interpreter.run(&ast, &mut out).unwrap().exit_code);
+        assert_eq!(Some(Value::Integer(3628800)), interpreter.global("f"));
+        assert_eq!(Some(Value::Integer(11)), interpreter.global("i"));
+        assert_eq!(None, interpreter.global("g"));
+
+        // 続けて解釈すると、前の実行の変数を使える
+        let ast = parse_str(r#"print(f / n, "\n"); n = 0;"#).unwrap();
+        interpreter.run(&ast, &mut out).unwrap();
+        assert_eq!(b"362880\n", &out[..]);
+        let globals = interpreter.into_globals();
+        assert_eq!(Some(&Value::Integer(0)), globals.get(&Symbol::intern("n")));
+        assert_eq!(3, globals.len());
+
+        // 渡していない変数を読むとエラー
+        let ast = parse_str("x = n + 1;").unwrap();
+        let e = ASTInterpreter::with_globals(HashMap::new())
+            .run(&ast, &mut Vec::new())
+            .unwrap_err();
+        assert_eq!("unknown identifier: n", e.message());
+    }
+
+    #[test]
+    fn test_global_types() {
+        // 値だけを渡した変数も、入っている値の型として扱う
+        let options = ParserOptions {
+            dialect: Dialect::float(),
+            ..Default::default()
+        };
+        let ast = parse_str_with("y = x; z = x * 2; s = t;", &options).unwrap();
+        let globals = vec![
+            (Symbol::intern("x"), Value::Float(Float(1.5))),
+            (Symbol::intern("t"), Value::String(Symbol::intern("abc"))),
+        ]
+        .into_iter()
+        .collect();
+        let mut interpreter = ASTInterpreter::with_globals(globals);
+        interpreter.run(&ast, &mut Vec::new()).unwrap();
+        assert_eq!(Some(Value::Float(Float(1.5))), interpreter.global("y"));
+        assert_eq!(Some(Value::Float(Float(3.0))), interpreter.global("z"));
+        assert_eq!(
+            Some(Value::String(Symbol::intern("abc"))),
+            interpreter.global("t")
+        );
+        assert_eq!(
+            Some(Value::String(Symbol::intern("abc"))),
+            interpreter.global("s")
+        );
+
+        // エラーで止まっても、それまでに代入した値は残る
+        let ast = parse_str("a = 1; b = a / (a - 1); c = 2;").unwrap();
+        let mut interpreter = ASTInterpreter::with_globals(HashMap::new());
+        assert!(interpreter.run(&ast, &mut Vec::new()).is_err());
+        assert_eq!(Some(Value::Integer(1)), interpreter.global("a"));
+        assert_eq!(None, interpreter.global("c"));
+    }
+}
@@ -1,43 +1,345 @@
+use isa::random::{Random, DEFAULT_SEED};
 use lexical_analyzer::error::*;
+use lexical_analyzer::float::Float;
+use lexical_analyzer::input::Input;
+use lexical_analyzer::limits::Limits;
+use lexical_analyzer::output::OutputPolicy;
+use lexical_analyzer::report::RunReport;
+use lexical_analyzer::symbol::Symbol;
+use lexical_analyzer::width::Width;
 use syntax_analyzer::ast_node::*;
+use syntax_analyzer::typed::{self, Type};
 
 use std::collections::HashMap;
-use std::io::Write;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::time::Instant;
+
+mod coverage;
+mod events;
+mod globals;
+mod trace;
+
+pub use coverage::Coverage;
+pub use events::{OutputEvent, OutputRecorder};
+pub use trace::VariableTrace;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Value<'a> {
-    Integer(i32),
-    String(&'a str),
+pub enum Value {
+    Integer(i64),
+    String(Symbol),
+    Float(Float),
+}
+
+/// AST の深さの既定の上限
+pub const DEFAULT_MAX_DEPTH: usize = 500;
+
+#[derive(Debug)]
+pub struct InterpreterOptions {
+    /// AST の深さの上限。これを超えるとスタックを使い切る前にエラーにする。
+    pub max_depth: usize,
+    /// rand の種
+    pub seed: u32,
+    /// 出力先が閉じられたときの扱い
+    pub output: OutputPolicy,
+    /// 整数の値の幅。演算の結果はこの幅で折り返す。
+    pub width: Width,
+    /// 資源の上限。出力のバイト数と、 max_depth に加えて AST の深さを制限する。
+    pub limits: Limits,
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> Self {
+        InterpreterOptions {
+            max_depth: DEFAULT_MAX_DEPTH,
+            seed: DEFAULT_SEED,
+            output: OutputPolicy::default(),
+            width: Width::default(),
+            limits: Limits::default(),
+        }
+    }
 }
 
-pub struct ASTInterpreter<'a> {
-    global: HashMap<&'a str, Value<'a>>,
+/// InterpreterHooks::on_statement を呼ぶ文なら true。ブロックと空文は含まない。
+/// 引数の並びを出力する文は、引数ごとではなく並び全体で 1 つの文とする。
+fn is_statement(node: &ASTNode) -> bool {
+    matches!(
+        node.kind(),
+        NodeKind::Assign
+            | NodeKind::If
+            | NodeKind::While
+            | NodeKind::DoWhile
+            | NodeKind::Prtc
+            | NodeKind::Exit
+            | NodeKind::Assert
+    ) || is_print_list(node)
 }
 
-impl<'a> ASTInterpreter<'a> {
-    pub fn interpret(node: &'a ASTNode, writer: &mut Write) -> Result<Option<Value<'a>>> {
-        let mut interpreter = ASTInterpreter {
-            global: HashMap::new(),
+/// print, putc, printf の引数を出力する Prtc, Prti, Prts を並べた Sequence なら true
+fn is_print_list(node: &ASTNode) -> bool {
+    matches!(node.kind(), NodeKind::Sequence)
+        && node.rhs().is_some()
+        && node.sequence_items().iter().all(|item| {
+            matches!(
+                item.kind(),
+                NodeKind::Prtc | NodeKind::Prti | NodeKind::Prts
+            )
+        })
+}
+
+/// ノードを実行した後の制御の流れ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// 次に進む。式なら値を持つ。
+    Next(Option<Value>),
+    /// exit 文で実行を終える。終了コードを持つ。
+    Exit(i32),
+}
+
+impl Flow {
+    /// 終了コード。exit 文を実行せずに終わったなら 0。
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Flow::Exit(code) => *code,
+            Flow::Next(_) => 0,
+        }
+    }
+}
+
+impl Default for Flow {
+    fn default() -> Self {
+        Flow::Next(None)
+    }
+}
+
+/// 実行中に呼ばれるコールバック。組み込む側がトレースやカバレッジの計測、
+/// ウォッチポイントなどを作るために使う。 Err を返すとそこで実行を止める。
+pub trait InterpreterHooks {
+    /// 文を実行する直前に呼ばれる。引数の並びを出力する文でも、呼ばれるのは 1 回だけ。
+    fn on_statement(&mut self, _node: &ASTNode) -> Result<()> {
+        Ok(())
+    }
+
+    /// 変数に値を代入した直後に呼ばれる
+    fn on_assign(&mut self, _identifier: Symbol, _value: &Value) -> Result<()> {
+        Ok(())
+    }
+
+    /// print 文や putc 文が text を書き出した直後に呼ばれる
+    fn on_print(&mut self, _text: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// on_print と同じときに、出力した値を持って呼ばれる
+    fn on_output(&mut self, _event: &OutputEvent) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// 何もしないコールバック
+impl InterpreterHooks for () {}
+
+pub struct ASTInterpreter<'h> {
+    global: HashMap<Symbol, Value>,
+    /// 変数の型。浮動小数点数の変数に代入する整数を変換するのに使う。
+    variable_types: HashMap<Symbol, Type>,
+    depth: usize,
+    max_depth: usize,
+    random: Random,
+    /// clock の基準時刻
+    start: Instant,
+    /// getc と geti で読む入力。 set_input しなければ空。
+    input: Input,
+    /// 実行中に呼ぶコールバック。 None なら呼ばない。
+    hooks: Option<&'h mut dyn InterpreterHooks>,
+    output: OutputPolicy,
+    width: Width,
+    limits: Limits,
+    /// 出力先に書き出したバイト数
+    written: usize,
+    /// run で実行した文の数
+    statements: u64,
+    /// run で評価したノードの数
+    nodes: u64,
+}
+
+impl<'h> ASTInterpreter<'h> {
+    pub fn interpret(node: &ASTNode, writer: &mut dyn Write) -> Result<RunReport> {
+        Self::interpret_with(node, writer, &InterpreterOptions::default())
+    }
+
+    pub fn interpret_with(
+        node: &ASTNode,
+        writer: &mut dyn Write,
+        options: &InterpreterOptions,
+    ) -> Result<RunReport> {
+        ASTInterpreter::new(HashMap::new(), options, None).run(node, writer)
+    }
+
+    /// 実行中に hooks を呼びながら解釈する。
+    /// exit 文で終わった場合は、その終了コードを RunReport に入れて返す。
+    pub fn interpret_with_hooks(
+        node: &ASTNode,
+        writer: &mut dyn Write,
+        options: &InterpreterOptions,
+        hooks: &'h mut dyn InterpreterHooks,
+    ) -> Result<RunReport> {
+        ASTInterpreter::new(HashMap::new(), options, Some(hooks)).run(node, writer)
+    }
+
+    fn new(
+        global: HashMap<Symbol, Value>,
+        options: &InterpreterOptions,
+        hooks: Option<&'h mut dyn InterpreterHooks>,
+    ) -> Self {
+        ASTInterpreter {
+            global,
+            variable_types: HashMap::new(),
+            depth: 0,
+            max_depth: options.limits.recursion_depth_or(options.max_depth),
+            random: Random::new(options.seed),
+            start: Instant::now(),
+            input: Input::default(),
+            hooks,
+            output: options.output,
+            width: options.width,
+            limits: options.limits,
+            written: 0,
+            statements: 0,
+            nodes: 0,
+        }
+    }
+
+    /// getc と geti で読む入力を input にする
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = Input::new(input);
+    }
+
+    /// それまでの変数の値を引き継いで node を解釈する。
+    /// 返す RunReport の文とノードの数は、この run で実行した分だけ数える。
+    pub fn run(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<RunReport> {
+        typed::check(node)?;
+        self.statements = 0;
+        self.nodes = 0;
+        self.variable_types = typed::variable_types(node);
+        // 代入を書いていない変数は、入っている値の型とする
+        for (identifier, value) in &self.global {
+            self.variable_types
+                .entry(*identifier)
+                .or_insert(match value {
+                    Value::Integer(_) => Type::Int,
+                    Value::Float(_) => Type::Float,
+                    Value::String(_) => Type::Str,
+                });
+        }
+        let result = self.interpret_body(node, writer);
+        let flow = self.output.finish(result)?;
+        Ok(self.report(flow))
+    }
+
+    /// 実行を終えたときの統計と変数の値
+    fn report(&self, flow: Flow) -> RunReport {
+        let mut variables: Vec<(String, String)> = self
+            .global
+            .iter()
+            .map(|(identifier, value)| {
+                let value = match value {
+                    Value::Integer(i) => i.to_string(),
+                    Value::String(s) => format!("{:?}", s),
+                    Value::Float(f) => f.to_string(),
+                };
+                (identifier.to_string(), value)
+            })
+            .collect();
+        variables.sort();
+        RunReport {
+            steps: self.statements,
+            instructions_retired: self.nodes,
+            variables,
+            exit_code: flow.exit_code(),
+        }
+    }
+
+    fn interpret_body(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        self.enter()?;
+        let result = self.interpret_node(node, writer);
+        self.depth -= 1;
+        // エラーには、それを起こした最も内側の文の行を付ける
+        result.map_err(|e| node.locate(e))
+    }
+
+    /// 出力する文の引数を 1 つ出力する。文は並び全体で数えたので、ここでは数えない。
+    fn interpret_print(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        self.enter()?;
+        let result = match node.kind() {
+            NodeKind::Prtc => self.interpret_prtc(node, writer),
+            NodeKind::Prti => self.interpret_prti(node, writer),
+            _ => self.interpret_prts(node, writer),
         };
-        interpreter.interpret_body(node, writer)
+        self.depth -= 1;
+        result.map_err(|e| node.locate(e))
     }
 
-    fn interpret_body(
-        &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
+    /// AST を 1 段深く進む。深さの上限を超えるならエラーにする。
+    fn enter(&mut self) -> Result<()> {
+        if self.depth >= self.max_depth {
+            return Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                format!("AST is too deep (limit: {})", self.max_depth),
+            ));
+        }
+        self.depth += 1;
+        self.nodes += 1;
+        Ok(())
+    }
+
+    fn interpret_node(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        if is_statement(node) {
+            self.statements += 1;
+            if let Some(hooks) = self.hooks.as_deref_mut() {
+                hooks.on_statement(node)?;
+            }
+        }
         match node.kind() {
-            NodeKind::Sequence => {
-                if let Some(ref lhs) = node.lhs() {
-                    self.interpret_body(lhs, writer)?;
+            NodeKind::Sequence if is_print_list(node) => {
+                for item in node.sequence_items() {
+                    self.interpret_print(item, writer)?;
                 }
-                if let Some(ref rsh) = node.rhs() {
-                    self.interpret_body(rsh, writer)?;
+                Ok(Flow::Next(None))
+            }
+            NodeKind::Sequence => {
+                for item in node.sequence_items() {
+                    if let Flow::Exit(code) = self.interpret_body(item, writer)? {
+                        return Ok(Flow::Exit(code));
+                    }
                 }
-                Ok(None)
+                Ok(Flow::Next(None))
             }
             NodeKind::Assign => self.interpret_assign(node, writer),
+            NodeKind::If => self.interpret_if(node, writer),
+            NodeKind::While => self.interpret_while(node, writer),
+            NodeKind::DoWhile => self.interpret_do_while(node, writer),
+            NodeKind::Prtc => self.interpret_prtc(node, writer),
+            NodeKind::Prti => self.interpret_prti(node, writer),
+            NodeKind::Prts => self.interpret_prts(node, writer),
+            NodeKind::Exit => self.interpret_exit(node, writer),
+            NodeKind::Assert => self.interpret_assert(node, writer),
+            _ => self
+                .evaluate_node(node, writer)
+                .map(|value| Flow::Next(Some(value))),
+        }
+    }
+
+    /// 式を評価する。式は exit で止まらないので、文とは別に値だけを返す。
+    fn evaluate(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Value> {
+        self.enter()?;
+        let result = self.evaluate_node(node, writer);
+        self.depth -= 1;
+        result
+    }
+
+    fn evaluate_node(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Value> {
+        let value = match node.kind() {
             NodeKind::Multiply
             | NodeKind::Divide
             | NodeKind::Mod
@@ -50,39 +352,61 @@ impl<'a> ASTInterpreter<'a> {
             | NodeKind::Equal
             | NodeKind::NotEqual
             | NodeKind::And
-            | NodeKind::Or => self.interpret_binary_op(node, writer),
-            NodeKind::Negate | NodeKind::Not => self.interpret_unary_op(node, writer),
-            NodeKind::If => self.interpret_if(node, writer),
-            NodeKind::While => self.interpret_while(node, writer),
-            NodeKind::Identifier(value) => self.interpret_identifier(value),
-            NodeKind::Prtc => self.interpret_prtc(node, writer),
-            NodeKind::Prti => self.interpret_prti(node, writer),
-            NodeKind::Prts => self.interpret_prts(node, writer),
-            NodeKind::String(value) => Ok(Some(Value::String(value))),
-            NodeKind::Integer(value) => Ok(Some(Value::Integer(*value))),
-            _ => Err(CompileError::new(
-                ErrorKind::InterpretationError,
-                "unknown node.",
+            | NodeKind::Or
+            | NodeKind::BitAnd
+            | NodeKind::BitOr
+            | NodeKind::BitXor
+            | NodeKind::ShiftLeft
+            | NodeKind::ShiftRight
+            | NodeKind::Power => self.interpret_binary_op(node, writer)?,
+            NodeKind::Negate | NodeKind::Not | NodeKind::ToInt | NodeKind::ToFloat => {
+                self.interpret_unary_op(node, writer)?
+            }
+            NodeKind::Rand => self.interpret_rand(node, writer)?,
+            NodeKind::Clock => Some(self.clock()),
+            NodeKind::Getc => Some(Value::Integer(
+                self.input.read_char(ErrorKind::InterpretationError)?.into(),
             )),
-        }
+            NodeKind::Geti => Some(Value::Integer(
+                self.input
+                    .read_integer_in(ErrorKind::InterpretationError, self.width)?,
+            )),
+            NodeKind::Identifier(value) => self.interpret_identifier(*value)?,
+            NodeKind::String(value) => Some(Value::String(*value)),
+            NodeKind::Integer(value) => Some(Value::Integer(*value)),
+            NodeKind::Float(value) => Some(Value::Float(*value)),
+            _ => None,
+        };
+        value.ok_or_else(|| CompileError::new(ErrorKind::InterpretationError, "unknown node."))
     }
 
-    fn interpret_identifier(&mut self, identifier: &'a str) -> Result<Option<Value<'a>>> {
-        Ok(Some(self.global[identifier]))
+    fn interpret_identifier(&mut self, identifier: Symbol) -> Result<Option<Value>> {
+        // with_globals で渡し忘れた変数も、ここでエラーになる
+        match self.global.get(&identifier) {
+            Some(value) => Ok(Some(*value)),
+            None => Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                format!("unknown identifier: {}", identifier),
+            )),
+        }
     }
 
-    fn interpret_assign(
-        &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
+    fn interpret_assign(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
         let variable = node.lhs().unwrap();
-        let value = self.interpret_body(node.rhs().unwrap(), writer)?.unwrap();
+        let mut value = self.evaluate(node.rhs().unwrap(), writer)?;
 
         match variable.kind() {
-            NodeKind::Identifier(ref identifier) => {
-                self.global.insert(identifier, value);
-                Ok(None)
+            NodeKind::Identifier(identifier) => {
+                if let (Value::Integer(i), Some(Type::Float)) =
+                    (value, self.variable_types.get(identifier))
+                {
+                    value = Value::Float(Float(i as f32));
+                }
+                self.global.insert(*identifier, value);
+                if let Some(hooks) = self.hooks.as_deref_mut() {
+                    hooks.on_assign(*identifier, &value)?;
+                }
+                Ok(Flow::Next(None))
             }
             _ => Err(CompileError::new(
                 ErrorKind::InterpretationError,
@@ -93,109 +417,135 @@ impl<'a> ASTInterpreter<'a> {
 
     fn interpret_binary_op(
         &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
-        let loperand = self.interpret_body(&node.lhs().unwrap(), writer)?.unwrap();
-        let roperand = self.interpret_body(&node.rhs().unwrap(), writer)?.unwrap();
-
-        match loperand {
-            Value::Integer(lop) => match roperand {
-                Value::Integer(rop) => match node.kind() {
-                    NodeKind::Multiply => Ok(Some(Value::Integer(lop * rop))),
-                    NodeKind::Divide => Ok(Some(Value::Integer(lop / rop))),
-                    NodeKind::Mod => Ok(Some(Value::Integer(lop % rop))),
-                    NodeKind::Add => Ok(Some(Value::Integer(lop + rop))),
-                    NodeKind::Subtract => Ok(Some(Value::Integer(lop - rop))),
-                    NodeKind::Less => {
-                        if lop < rop {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
-                    }
-                    NodeKind::LessEqual => {
-                        if lop <= rop {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+        node: &ASTNode,
+        writer: &mut dyn Write,
+    ) -> Result<Option<Value>> {
+        let loperand = self.evaluate(node.lhs().unwrap(), writer)?;
+        let roperand = self.evaluate(node.rhs().unwrap(), writer)?;
+        Self::binary_value(self.width, node.kind(), loperand, roperand)
+    }
+
+    /// 二項演算の値。深い式で再帰するたびにスタックを使わないように、評価とは別の関数にする。
+    fn binary_value(
+        width: Width,
+        kind: &NodeKind,
+        loperand: Value,
+        roperand: Value,
+    ) -> Result<Option<Value>> {
+        match (loperand, roperand) {
+            (Value::Integer(lop), Value::Integer(rop)) => match kind {
+                NodeKind::Multiply => Ok(Some(Value::Integer(width.mul(lop, rop)))),
+                NodeKind::Divide => width
+                    .div(lop, rop)
+                    .map(|value| Some(Value::Integer(value)))
+                    .ok_or_else(division_by_zero),
+                NodeKind::Mod => width
+                    .rem(lop, rop)
+                    .map(|value| Some(Value::Integer(value)))
+                    .ok_or_else(division_by_zero),
+                NodeKind::Add => Ok(Some(Value::Integer(width.add(lop, rop)))),
+                NodeKind::Subtract => Ok(Some(Value::Integer(width.sub(lop, rop)))),
+                NodeKind::Less => {
+                    if lop < rop {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    NodeKind::Greater => {
-                        if lop > rop {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+                }
+                NodeKind::LessEqual => {
+                    if lop <= rop {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    NodeKind::GreaterEqual => {
-                        if lop >= rop {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+                }
+                NodeKind::Greater => {
+                    if lop > rop {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    NodeKind::Equal => {
-                        if lop == rop {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+                }
+                NodeKind::GreaterEqual => {
+                    if lop >= rop {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    NodeKind::NotEqual => {
-                        if lop != rop {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+                }
+                NodeKind::Equal => {
+                    if lop == rop {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    NodeKind::And => {
-                        if lop != 0 && rop != 0 {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+                }
+                NodeKind::NotEqual => {
+                    if lop != rop {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    NodeKind::Or => {
-                        if lop != 0 || rop != 0 {
-                            Ok(Some(Value::Integer(1)))
-                        } else {
-                            Ok(Some(Value::Integer(0)))
-                        }
+                }
+                NodeKind::And => {
+                    if lop != 0 && rop != 0 {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                    _ => {
-                        return Err(CompileError::new(
-                            ErrorKind::InterpretationError,
-                            "Unknown Node.",
-                        ));
+                }
+                NodeKind::Or => {
+                    if lop != 0 || rop != 0 {
+                        Ok(Some(Value::Integer(1)))
+                    } else {
+                        Ok(Some(Value::Integer(0)))
                     }
-                },
-                _ => {
-                    return Err(CompileError::new(
-                        ErrorKind::InterpretationError,
-                        "Integer value is expected",
-                    ));
                 }
-            },
-            _ => {
-                return Err(CompileError::new(
+                NodeKind::BitAnd => Ok(Some(Value::Integer(lop & rop))),
+                NodeKind::BitOr => Ok(Some(Value::Integer(lop | rop))),
+                NodeKind::BitXor => Ok(Some(Value::Integer(lop ^ rop))),
+                // シフト量は幅のビット数で割った余りを使い、右シフトは算術シフト
+                NodeKind::ShiftLeft => Ok(Some(Value::Integer(width.shl(lop, rop)))),
+                NodeKind::ShiftRight => Ok(Some(Value::Integer(width.shr(lop, rop)))),
+                // 0 の負の乗は 0 で割ることになる
+                NodeKind::Power => width
+                    .pow(lop, rop)
+                    .map(|value| Some(Value::Integer(value)))
+                    .ok_or_else(division_by_zero),
+                _ => Err(CompileError::new(
                     ErrorKind::InterpretationError,
-                    "Integer value is expected",
-                ));
-            }
+                    "Unknown Node.",
+                )),
+            },
+            _ => float_op(kind, loperand, roperand),
         }
     }
 
     fn interpret_unary_op(
         &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
-        let operand = self.interpret_body(node.lhs().unwrap(), writer)?.unwrap();
+        node: &ASTNode,
+        writer: &mut dyn Write,
+    ) -> Result<Option<Value>> {
+        let operand = self.evaluate(node.lhs().unwrap(), writer)?;
 
+        // int(x) は 0 の方向に丸め、範囲外の値は値の幅の端に、 NaN は 0 にする
+        match (node.kind(), operand) {
+            (NodeKind::ToInt, Value::Float(val)) => {
+                let value = (val.value() as i64).clamp(self.width.min(), self.width.max());
+                return Ok(Some(Value::Integer(value)));
+            }
+            (NodeKind::ToFloat, Value::Integer(val)) => {
+                return Ok(Some(Value::Float(Float(val as f32))))
+            }
+            (NodeKind::Negate, Value::Float(val)) => {
+                return Ok(Some(Value::Float(Float(-val.value()))))
+            }
+            (NodeKind::ToInt | NodeKind::ToFloat, _) => return Ok(Some(operand)),
+            _ => {}
+        }
         match operand {
             Value::Integer(val) => match node.kind() {
-                NodeKind::Negate => Ok(Some(Value::Integer(-val))),
+                NodeKind::Negate => Ok(Some(Value::Integer(self.width.neg(val)))),
                 NodeKind::Not => {
                     if val == 0 {
                         Ok(Some(Value::Integer(1)))
@@ -203,67 +553,115 @@ impl<'a> ASTInterpreter<'a> {
                         Ok(Some(Value::Integer(0)))
                     }
                 }
-                _ => {
-                    return Err(CompileError::new(
-                        ErrorKind::InterpretationError,
-                        "Integer value is expected",
-                    ));
-                }
-            },
-            _ => {
-                return Err(CompileError::new(
+                _ => Err(CompileError::new(
                     ErrorKind::InterpretationError,
                     "Integer value is expected",
-                ));
-            }
+                )),
+            },
+            _ => Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                "Integer value is expected",
+            )),
+        }
+    }
+
+    fn interpret_rand(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Option<Value>> {
+        let operand = self.evaluate(node.lhs().unwrap(), writer)?;
+
+        match operand {
+            // 乱数の範囲は 64 ビットでも i32 までにする
+            Value::Integer(n) => match self
+                .random
+                .next(n.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+            {
+                Some(value) => Ok(Some(Value::Integer(value as i64))),
+                None => Err(CompileError::new(
+                    ErrorKind::InterpretationError,
+                    format!("rand: {} is not positive", n),
+                )),
+            },
+            _ => Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                "Integer value is expected",
+            )),
         }
     }
 
-    fn interpret_if(&mut self, node: &'a ASTNode, writer: &mut Write) -> Result<Option<Value<'a>>> {
-        let condition = self.interpret_body(node.lhs().unwrap(), writer)?.unwrap();
+    /// 実行を始めてからのミリ秒。値の幅に収まらなくなると折り返す。
+    fn clock(&self) -> Value {
+        Value::Integer(self.width.wrap(self.start.elapsed().as_millis() as i64))
+    }
+
+    fn interpret_if(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        let condition = self.evaluate(node.lhs().unwrap(), writer)?;
         let statement_node = node.rhs().unwrap();
 
         if condition != Value::Integer(0) {
-            self.interpret_body(statement_node.lhs().unwrap(), writer)?;
+            self.interpret_body(statement_node.lhs().unwrap(), writer)
         } else {
-            if let Some(else_clause) = statement_node.rhs() {
-                self.interpret_body(else_clause, writer)?;
+            match statement_node.rhs() {
+                Some(else_clause) => self.interpret_body(else_clause, writer),
+                None => Ok(Flow::Next(None)),
             }
         }
+    }
 
-        Ok(None)
+    fn interpret_while(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        let condition = node.lhs().unwrap();
+        let statement = node.rhs().unwrap();
+
+        while self.evaluate(condition, writer)? != Value::Integer(0) {
+            if let Flow::Exit(code) = self.interpret_body(statement, writer)? {
+                return Ok(Flow::Exit(code));
+            }
+        }
+        Ok(Flow::Next(None))
     }
 
-    fn interpret_while(
-        &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
+    fn interpret_do_while(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
         let condition = node.lhs().unwrap();
         let statement = node.rhs().unwrap();
 
-        while self.interpret_body(condition, writer)? != Some(Value::Integer(0)) {
-            self.interpret_body(statement, writer)?;
+        loop {
+            if let Flow::Exit(code) = self.interpret_body(statement, writer)? {
+                return Ok(Flow::Exit(code));
+            }
+            if self.evaluate(condition, writer)? == Value::Integer(0) {
+                break;
+            }
         }
-        Ok(None)
+        Ok(Flow::Next(None))
     }
 
-    fn interpret_prtc(
-        &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
-        let value = self.interpret_body(node.lhs().unwrap(), writer)?.unwrap();
+    /// text を書き出す。書き込みのエラーは出力の方針に従って扱う。
+    /// 出力のバイト数が上限を超えるなら、何も書き出さずにエラーにする。
+    fn write(&mut self, writer: &mut dyn Write, text: &str) -> Result<()> {
+        self.limits
+            .check_output(ErrorKind::InterpretationError, self.written, text.len())?;
+        self.written += text.len();
+        self.output.write(
+            ErrorKind::InterpretationError,
+            writer.write_all(text.as_bytes()),
+        )
+    }
+
+    /// event を書き出し、コールバックに渡す
+    fn print(&mut self, writer: &mut dyn Write, event: OutputEvent) -> Result<Flow> {
+        let text = event.to_string();
+        self.write(writer, &text)?;
+        if let Some(hooks) = self.hooks.as_deref_mut() {
+            hooks.on_print(&text)?;
+            hooks.on_output(&event)?;
+        }
+        Ok(Flow::Next(None))
+    }
+
+    fn interpret_prtc(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        let value = self.evaluate(node.lhs().unwrap(), writer)?;
 
         if let Value::Integer(i) = value {
-            match std::char::from_u32(i as u32) {
-                Some(c) => match writer.write(format!("{}", c).as_bytes()) {
-                    Ok(_) => Ok(None),
-                    Err(e) => Err(CompileError::new(
-                        ErrorKind::InterpretationError,
-                        e.to_string(),
-                    )),
-                },
+            match u32::try_from(i).ok().and_then(std::char::from_u32) {
+                Some(c) => self.print(writer, OutputEvent::Char(c)),
                 None => Err(CompileError::new(
                     ErrorKind::InterpretationError,
                     "non-integer value appeared.",
@@ -277,21 +675,16 @@ impl<'a> ASTInterpreter<'a> {
         }
     }
 
-    fn interpret_prti(
-        &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
-        let value = self.interpret_body(node.lhs().unwrap(), writer)?.unwrap();
+    fn interpret_prti(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        let value = self.evaluate(node.lhs().unwrap(), writer)?;
 
-        if let Value::Integer(i) = value {
-            match writer.write(format!("{}", i).as_bytes()) {
-                Ok(_) => Ok(None),
-                Err(e) => Err(CompileError::new(
-                    ErrorKind::InterpretationError,
-                    e.to_string(),
-                )),
-            }
+        let event = match value {
+            Value::Integer(i) => Some(OutputEvent::Int(i)),
+            Value::Float(f) => Some(OutputEvent::Float(f)),
+            Value::String(_) => None,
+        };
+        if let Some(event) = event {
+            self.print(writer, event)
         } else {
             Err(CompileError::new(
                 ErrorKind::InterpretationError,
@@ -300,21 +693,32 @@ impl<'a> ASTInterpreter<'a> {
         }
     }
 
-    fn interpret_prts(
-        &mut self,
-        node: &'a ASTNode,
-        writer: &mut Write,
-    ) -> Result<Option<Value<'a>>> {
-        let value = self.interpret_body(node.lhs().unwrap(), writer)?.unwrap();
+    fn interpret_exit(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        match self.evaluate(node.lhs().unwrap(), writer)? {
+            // 終了コードは下位 32 ビットだけを使う
+            Value::Integer(code) => Ok(Flow::Exit(code as i32)),
+            _ => Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                "integer is expected.",
+            )),
+        }
+    }
+
+    fn interpret_assert(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        if self.evaluate(node.lhs().unwrap(), writer)? == Value::Integer(0) {
+            return Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                node.assertion_message(),
+            ));
+        }
+        Ok(Flow::Next(None))
+    }
+
+    fn interpret_prts(&mut self, node: &ASTNode, writer: &mut dyn Write) -> Result<Flow> {
+        let value = self.evaluate(node.lhs().unwrap(), writer)?;
 
         if let Value::String(s) = value {
-            match writer.write(s.as_bytes()) {
-                Ok(_) => Ok(None),
-                Err(e) => Err(CompileError::new(
-                    ErrorKind::InterpretationError,
-                    e.to_string(),
-                )),
-            }
+            self.print(writer, OutputEvent::Str(s.to_string()))
         } else {
             Err(CompileError::new(
                 ErrorKind::InterpretationError,
@@ -324,9 +728,57 @@ impl<'a> ASTInterpreter<'a> {
     }
 }
 
+fn division_by_zero() -> CompileError {
+    CompileError::new(ErrorKind::InterpretationError, "division by zero")
+}
+
+/// 数の値を浮動小数点数として読む。文字列なら None。
+fn as_float(value: Value) -> Option<f32> {
+    match value {
+        Value::Integer(i) => Some(i as f32),
+        Value::Float(f) => Some(f.value()),
+        Value::String(_) => None,
+    }
+}
+
+/// 被演算子のどちらかが浮動小数点数の二項演算。整数の被演算子は浮動小数点数にする。
+/// 比較の結果は整数の 0 か 1。
+fn float_op(kind: &NodeKind, loperand: Value, roperand: Value) -> Result<Option<Value>> {
+    let (lop, rop) = match (as_float(loperand), as_float(roperand)) {
+        (Some(lop), Some(rop)) => (lop, rop),
+        _ => {
+            return Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                "number is expected.",
+            ))
+        }
+    };
+    let value = match kind {
+        NodeKind::Multiply => Value::Float(Float(lop * rop)),
+        NodeKind::Divide => Value::Float(Float(lop / rop)),
+        NodeKind::Add => Value::Float(Float(lop + rop)),
+        NodeKind::Subtract => Value::Float(Float(lop - rop)),
+        NodeKind::Less => Value::Integer((lop < rop) as i64),
+        NodeKind::LessEqual => Value::Integer((lop <= rop) as i64),
+        NodeKind::Greater => Value::Integer((lop > rop) as i64),
+        NodeKind::GreaterEqual => Value::Integer((lop >= rop) as i64),
+        NodeKind::Equal => Value::Integer((lop == rop) as i64),
+        NodeKind::NotEqual => Value::Integer((lop != rop) as i64),
+        _ => {
+            return Err(CompileError::new(
+                ErrorKind::InterpretationError,
+                "Integer value is expected",
+            ))
+        }
+    };
+    Ok(Some(value))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lexical_analyzer::output::ClosedAfter;
+    use lexical_analyzer::snapshot::assert_snapshot;
 
     #[test]
     fn test_hello_world() {
@@ -339,7 +791,7 @@ String        "Hello, World!\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -364,7 +816,7 @@ String        "\n"
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -393,7 +845,7 @@ String        "Print a slash n - \\n.\n"
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -434,7 +886,7 @@ Integer       1
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -479,7 +931,7 @@ Integer        1
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -556,7 +1008,7 @@ String        "\n"
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -697,9 +1149,395 @@ String        "\n"
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+    }
+
+    #[test]
+    fn test_type_error() {
+        // 文字列を算術に使うプログラムは、実行を始める前にエラーにする
+        let s = r#"Sequence
+Sequence
+;
+Prts
+String        "before\n"
+;
+Prti
+Add
+Integer       1
+String        "a"
+;
+"#;
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
+        let mut out = Vec::new();
+        let e = ASTInterpreter::interpret(&ast, &mut out).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::TypeError));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        // 文の数は深さに数えない
+        let src = "x = 1;".repeat(10_000);
+        let ast = syntax_analyzer::parse_str(&src).unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+
+        // 1 + 1 + ... は左に深い木になる
+        let src = format!("print({});", vec!["1"; 400].join(" + "));
+        let ast = syntax_analyzer::parse_str(&src).unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!(b"400", &out[..]);
+
+        let src = format!("print({});", vec!["1"; 1000].join(" + "));
+        let ast = syntax_analyzer::parse_str(&src).unwrap();
+        let mut out = Vec::new();
+        assert!(ASTInterpreter::interpret(&ast, &mut out).is_err());
+
+        let options = InterpreterOptions {
+            max_depth: 4,
+            ..Default::default()
+        };
+        let ast = syntax_analyzer::parse_str("print(1 + 2 + 3);").unwrap();
+        let mut out = Vec::new();
+        assert!(ASTInterpreter::interpret_with(&ast, &mut out, &options).is_err());
+    }
+
+    #[test]
+    fn test_do_while() {
+        // 条件が最初から偽でも本体は 1 回実行する
+        let ast = syntax_analyzer::parse_str(
+            r#"i = 0; do { print(i); i = i + 1; } while (i < 3); do print("x"); while (0);"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!(b"012x", &out[..]);
+    }
+
+    #[test]
+    fn test_assert() {
+        let ast = syntax_analyzer::parse_str(
+            r#"i = 0;
+while (i < 5) {
+    assert(i < 3, "i is small");
+    print(i);
+    i = i + 1;
+}"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let e = ASTInterpreter::interpret(&ast, &mut out).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::InterpretationError));
+        assert_eq!("assertion failed at line 3: i is small", e.message());
+        assert_eq!(b"012", &out[..]);
+    }
+
+    #[test]
+    fn test_string_variables() {
+        let ast = syntax_analyzer::parse_str(
+            r#"fizz = "Fizz"; buzz = "Buzz"; word = fizz;
+i = 1;
+while (i <= 5) {
+    if (i % 3 == 0) print(word); else if (i % 5 == 0) print(buzz); else print(i);
+    print(" ");
+    i = i + 1;
+}"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!(b"1 2 Fizz 4 Buzz ", &out[..]);
+    }
+
+    #[test]
+    fn test_exit() {
+        // ループや if の中の exit でも、残りの文を実行せずに終わる
+        let ast = syntax_analyzer::parse_str(
+            r#"i = 0;
+while (1) {
+    do {
+        print(i);
+        if (i == 3) exit(i * 10);
+        i = i + 1;
+    } while (1);
+}
+print("unreachable");"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let report = ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!(30, report.exit_code);
+        assert_eq!(b"0123", &out[..]);
+
+        let ast = syntax_analyzer::parse_str("print(1);").unwrap();
+        let report = ASTInterpreter::interpret(&ast, &mut Vec::new()).unwrap();
+        assert_eq!(0, report.exit_code);
+    }
+
+    #[test]
+    fn test_run_report() {
+        let src = r#"s = "abc"; n = -1; i = 0; while (i < 3) i = i + 1; print(i);"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let report = ASTInterpreter::interpret(&ast, &mut Vec::new()).unwrap();
+        // 代入 3 つ、 while、その中の代入 3 回、 print
+        assert_eq!(8, report.steps);
+        assert!(report.instructions_retired > report.steps);
+        assert_eq!(
+            vec![
+                ("i".to_string(), "3".to_string()),
+                ("n".to_string(), "-1".to_string()),
+                ("s".to_string(), "\"abc\"".to_string()),
+            ],
+            report.variables
+        );
+        assert_eq!(0, report.exit_code);
+
+        // 引数が幾つあっても print は 1 つの文と数える
+        let src = r#"i = 0; while (i < 2) { print("a", i, "\n"); putc('b', 'c'); i = i + 1; }"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let report = ASTInterpreter::interpret(&ast, &mut Vec::new()).unwrap();
+        // 代入、 while、その中の print, putc, 代入を 2 回ずつ
+        assert_eq!(8, report.steps);
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let ast = syntax_analyzer::parse_str(
+            r#"x = 12; print(x & 10, " ", x | 10, " ", x ^ 10, " ", 1 << 4, " ", -16 >> 2, " ", 1 << 33);"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!("8 14 6 16 -4 2", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn test_power() {
+        let ast = syntax_analyzer::parse_str(
+            r#"n = 3; print(2 ** 10, " ", -2 ** 2, " ", 2 ** n ** 2, " ", 2 ** 31, " ", 10 ** -1);"#,
+        )
+        .unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!("1024 -4 512 -2147483648 0", String::from_utf8(out).unwrap());
+
+        let ast = syntax_analyzer::parse_str("n = -1;\nprint(0 ** n);").unwrap();
+        let e = ASTInterpreter::interpret(&ast, &mut Vec::new()).unwrap_err();
+        assert_eq!("division by zero", e.message());
+        // エラーには文の行が付く
+        assert_eq!(Some(2), e.line());
+    }
+
+    #[test]
+    fn test_floats() {
+        let options = syntax_analyzer::ParserOptions {
+            dialect: lexical_analyzer::dialect::Dialect::float(),
+            ..syntax_analyzer::ParserOptions::default()
+        };
+        let src = r#"x = 1.5e3 / 4; y = 0; y = y + x;
+i = 0; while (i < 3) { y = y / 2; i = i + 1; }
+print(x, " ", int(x), " ", y, " ", float(7) / 2, " ", x > 100, " ", -x, " ", int(-2.5), "\n");"#;
+        let ast = syntax_analyzer::parse_str_with(src, &options).unwrap();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        assert_eq!(
+            "375.0 375 46.875 3.5 1 -375.0 -2\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_width() {
+        let src =
+            "f = 1; i = 1; while (i <= 20) { f = f * i; i = i + 1; } print(f, \" \", 1 << 40);";
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let run = |width| {
+            let mut out = Vec::new();
+            let options = InterpreterOptions {
+                width,
+                ..Default::default()
+            };
+            ASTInterpreter::interpret_with(&ast, &mut out, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!("-2102132736 256", run(Width::W32));
+        assert_eq!("2432902008176640000 1099511627776", run(Width::W64));
+
+        let ast = syntax_analyzer::parse_str("x = 0; print(1 / x);").unwrap();
+        let e = ASTInterpreter::interpret(&ast, &mut Vec::new()).unwrap_err();
+        assert_eq!("division by zero", e.message());
+    }
+
+    #[test]
+    fn test_limits() {
+        let ast = syntax_analyzer::parse_str("while (1) print(\"abc\");").unwrap();
+        let options = InterpreterOptions {
+            limits: Limits {
+                output_size: Some(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let e = ASTInterpreter::interpret_with(&ast, &mut out, &options).unwrap_err();
+        assert_eq!("output exceeds the limit of 10 bytes", e.message());
+        assert_eq!(b"abcabcabc", &out[..]);
+
+        // max_depth より小さい上限なら、そちらで止める
+        let ast = syntax_analyzer::parse_str("print(1 + 2 + 3);").unwrap();
+        let options = InterpreterOptions {
+            limits: Limits {
+                recursion_depth: Some(4),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let e = ASTInterpreter::interpret_with(&ast, &mut Vec::new(), &options).unwrap_err();
+        assert_eq!("AST is too deep (limit: 4)", e.message());
+    }
+
+    #[test]
+    fn test_rand() {
+        let src = r#"i = 0; while (i < 5) { print(rand(100), " "); i = i + 1; }"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let run = |seed| {
+            let mut out = Vec::new();
+            let options = InterpreterOptions {
+                seed,
+                ..Default::default()
+            };
+            ASTInterpreter::interpret_with(&ast, &mut out, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!("38 58 13 15 51 ", run(DEFAULT_SEED));
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(DEFAULT_SEED), run(7));
+
+        let ast = syntax_analyzer::parse_str("x = rand(0);").unwrap();
+        let mut out = Vec::new();
+        assert!(ASTInterpreter::interpret(&ast, &mut out).is_err());
+    }
+
+    #[test]
+    fn test_clock() {
+        let src =
+            r#"t0 = clock(); i = 0; while (i < 1000) i = i + 1; print(t0, " ", clock() - t0);"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let start = Instant::now();
+        let mut out = Vec::new();
+        ASTInterpreter::interpret(&ast, &mut out).unwrap();
+        let elapsed = start.elapsed().as_millis() as i32;
+
+        // どちらも実行を始めてからの時間に収まる
+        let out = String::from_utf8(out).unwrap();
+        for value in out.split(' ') {
+            let value: i32 = value.parse().unwrap();
+            assert!(0 <= value && value <= elapsed, "{}", out);
+        }
+    }
+
+    #[test]
+    fn test_input() {
+        // A + B: 入力の 2 つの整数の和を書き、残りを 1 バイトずつ読む
+        let src = r#"a = geti(); b = geti(); print(a + b, " ", getc(), " ", getc(), " ", getc());"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let mut interpreter = ASTInterpreter::with_globals(HashMap::new());
+        interpreter.set_input(Box::new(&b" 2 -5\nx"[..]));
+        let mut out = Vec::new();
+        interpreter.run(&ast, &mut out).unwrap();
+        assert_eq!(b"-3 10 120 -1", &out[..]);
+
+        // 入力を渡さなければ空の入力から読む
+        let e = ASTInterpreter::interpret(&ast, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            "input error: expected an integer, found end of input",
+            e.message()
+        );
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        /// この変数に代入したら止める
+        watch: Option<Symbol>,
+    }
+
+    impl InterpreterHooks for Recorder {
+        fn on_statement(&mut self, node: &ASTNode) -> Result<()> {
+            self.events.push(format!("{:?}", node.kind()));
+            Ok(())
+        }
+
+        fn on_assign(&mut self, identifier: Symbol, value: &Value) -> Result<()> {
+            self.events.push(format!("{} = {:?}", identifier, value));
+            if self.watch == Some(identifier) {
+                return Err(CompileError::new(
+                    ErrorKind::InterpretationError,
+                    format!("{} is changed", identifier),
+                ));
+            }
+            Ok(())
+        }
+
+        fn on_print(&mut self, text: &str) -> Result<()> {
+            self.events.push(format!("print {:?}", text));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let ast = syntax_analyzer::parse_str(
+            r#"i = 0; while (i < 2) { i = i + 1; } if (i) print("i=", i); putc('\n');"#,
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let mut recorder = Recorder::default();
+        ASTInterpreter::interpret_with_hooks(
+            &ast,
+            &mut out,
+            &InterpreterOptions::default(),
+            &mut recorder,
+        )
+        .unwrap();
+        assert_eq!("i=2\n", String::from_utf8(out).unwrap());
+        assert_eq!(
+            vec![
+                "Assign",
+                "i = Integer(0)",
+                "While",
+                "Assign",
+                "i = Integer(1)",
+                "Assign",
+                "i = Integer(2)",
+                "If",
+                "Sequence",
+                "print \"i=\"",
+                "print \"2\"",
+                "Prtc",
+                "print \"\\n\"",
+            ],
+            recorder.events
+        );
+
+        // コールバックがエラーを返すとそこで止まる
+        let mut out = Vec::new();
+        let mut recorder = Recorder {
+            watch: Some(Symbol::intern("i")),
+            ..Default::default()
+        };
+        let e = ASTInterpreter::interpret_with_hooks(
+            &ast,
+            &mut out,
+            &InterpreterOptions::default(),
+            &mut recorder,
+        )
+        .unwrap_err();
+        assert_eq!("i is changed", e.message());
+        assert_eq!(2, recorder.events.len());
     }
 
     #[test]
@@ -742,7 +1580,7 @@ Identifier     a
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -789,7 +1627,7 @@ Identifier     result
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -852,7 +1690,7 @@ String         "\n"
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
     }
@@ -927,7 +1765,7 @@ Integer        1
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
 
@@ -1095,40 +1933,72 @@ String         " bottles of beer on the wall\n\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
 
-        assert_eq!(
-            r#"99 bottles of beer on the wall
+        assert_snapshot(
+            &out,
+            r#"bytes: 11060
+fnv1a64: 5d6acab96f50f26f
+99 bottles of beer on the wall
 99 bottles of beer
 Take one down, pass it around
 98 bottles of beer on the wall
 
-98 bottles of beer on the wall
-98 bottles of beer
-Take one down, pass it around
-97 bottles of beer on the wall
-"#
-            .as_bytes(),
-            &out[..223]
+...
+"#,
         );
+    }
 
-        assert_eq!(
-            r#"2 bottles of beer on the wall
-2 bottles of beer
-Take one down, pass it around
-1 bottles of beer on the wall
+    #[test]
+    fn test_closed_output() {
+        let src = r#"i = 0; while (i < 5) { print(i); i = i + 1; } x = 42;"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let run = |output| {
+            let mut out = ClosedAfter::new(2);
+            let options = InterpreterOptions {
+                output,
+                ..Default::default()
+            };
+            let mut assigned = Vec::new();
+            let result = ASTInterpreter::interpret_with_hooks(
+                &ast,
+                &mut out,
+                &options,
+                &mut Assignments(&mut assigned),
+            );
+            (result.map(|_| ()), out.written, assigned)
+        };
 
-1 bottles of beer on the wall
-1 bottles of beer
-Take one down, pass it around
-0 bottles of beer on the wall
+        let (result, written, _) = run(OutputPolicy::Fail);
+        let e = result.unwrap_err();
+        assert!(e.is_output_closed());
+        assert_eq!(b"01", &written[..]);
 
-"#
-            .as_bytes(),
-            &out[10842..]
-        );
+        // Stop は書けなくなったところで正常に終わる
+        let (result, written, assigned) = run(OutputPolicy::Stop);
+        assert!(result.is_ok());
+        assert_eq!(b"01", &written[..]);
+        assert!(!assigned.contains(&42));
+
+        // Discard は最後まで実行する
+        let (result, written, assigned) = run(OutputPolicy::Discard);
+        assert!(result.is_ok());
+        assert_eq!(b"01", &written[..]);
+        assert!(assigned.contains(&42));
+    }
+
+    /// 代入した整数の値を記録する
+    struct Assignments<'v>(&'v mut Vec<i64>);
+
+    impl InterpreterHooks for Assignments<'_> {
+        fn on_assign(&mut self, _identifier: Symbol, value: &Value) -> Result<()> {
+            if let Value::Integer(i) = value {
+                self.0.push(*i);
+            }
+            Ok(())
+        }
     }
 
     #[test]
@@ -1231,7 +2101,7 @@ String        "\n"
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
 
@@ -1442,7 +2312,7 @@ Identifier     y_step
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         let mut out = Vec::new();
         ASTInterpreter::interpret(&ast, &mut out).unwrap();
 
@@ -1,31 +1,49 @@
-use ast_interpreter::ASTInterpreter;
+use ast_interpreter::{ASTInterpreter, InterpreterOptions, VariableTrace};
+use lexical_analyzer::cli::{Command, Flag, Options};
+use lexical_analyzer::diagnostic::Diagnostics;
+use lexical_analyzer::error::*;
 use syntax_analyzer::ast_node::*;
+use syntax_analyzer::lint;
 
-use std::env;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::io::{stderr, Write};
+use std::process;
 
-fn main() {
-    let mut str_in = String::new();
+const COMMAND: Command = Command {
+    name: "ast_interpreter",
+    description: "Reads a flattened AST and interprets it.",
+    flags: &[Flag::TraceVars, Flag::Width, Flag::DenyWarnings],
+};
 
-    let mut reader: Box<dyn BufRead> = match env::args().nth(1) {
-        None => Box::new(BufReader::new(stdin())),
-        Some(filename) => Box::new(BufReader::new(
-            File::open(filename).expect("cannot open file"),
-        )),
-    };
+fn run(options: &Options) -> Result<()> {
+    let str_in = options.read_input()?;
+    let mut writer = options.create_output()?;
 
-    let mut writer: Box<dyn Write> = match env::args().nth(2) {
-        None => Box::new(BufWriter::new(stdout())),
-        Some(filename) => Box::new(BufWriter::new(
-            File::create(filename).expect("cannot create file"),
-        )),
+    let ast = ASTReader::read_ast(str_in.lines())?;
+    let mut diagnostics = Diagnostics::new(options.deny_warnings);
+    lint::check(&ast, &mut diagnostics);
+    options.report(COMMAND.name, &diagnostics)?;
+
+    let interpreter_options = InterpreterOptions {
+        width: options.dialect.width,
+        ..Default::default()
+    };
+    let report = if options.trace_vars {
+        let mut trace = VariableTrace::new(stderr());
+        ASTInterpreter::interpret_with_hooks(&ast, &mut writer, &interpreter_options, &mut trace)?
+    } else {
+        ASTInterpreter::interpret_with(&ast, &mut writer, &interpreter_options)?
     };
+    writer
+        .flush()
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
 
-    reader.read_to_string(&mut str_in).expect("read failed");
-    let ast = ASTReader::read_ast(str_in.lines());
-    match ASTInterpreter::interpret(&ast, &mut writer) {
-        Ok(_) => {}
-        Err(e) => println!("{:?}", e),
+    // exit 文の終了コードをプロセスの終了コードにする
+    if report.exit_code != 0 {
+        process::exit(report.exit_code);
     }
+    Ok(())
+}
+
+fn main() {
+    COMMAND.main(run);
 }
@@ -0,0 +1,114 @@
+use super::{InterpreterHooks, Value};
+use lexical_analyzer::error::*;
+use lexical_analyzer::symbol::Symbol;
+use syntax_analyzer::ast_node::{ASTNode, NodeKind};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+/// 代入のたびに、変数名と代入前後の値、代入文の行を writer に書き出す。
+/// 1 行の形式は "line 3: a = 10 (was 4)"。初めての代入では "(was unset)" とする。
+pub struct VariableTrace<W: Write> {
+    writer: W,
+    /// 最後に実行を始めた代入文の行
+    line: Option<usize>,
+    /// 代入前の値を知るために、これまでに代入した値を覚えておく
+    values: HashMap<Symbol, String>,
+}
+
+impl<W: Write> VariableTrace<W> {
+    pub fn new(writer: W) -> Self {
+        VariableTrace {
+            writer,
+            line: None,
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// トレースに書く値。文字列は引用符で囲む。
+struct Shown<'v>(&'v Value);
+
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl<W: Write> InterpreterHooks for VariableTrace<W> {
+    fn on_statement(&mut self, node: &ASTNode) -> Result<()> {
+        if let NodeKind::Assign = node.kind() {
+            self.line = node.line();
+        }
+        Ok(())
+    }
+
+    fn on_assign(&mut self, identifier: Symbol, value: &Value) -> Result<()> {
+        let new = Shown(value).to_string();
+        let old = self
+            .values
+            .insert(identifier, new.clone())
+            .unwrap_or_else(|| "unset".to_string());
+        let line = match self.line {
+            Some(line) => format!("line {}", line),
+            None => "line ?".to_string(),
+        };
+        writeln!(
+            self.writer,
+            "{}: {} = {} (was {})",
+            line, identifier, new, old
+        )
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ASTInterpreter, InterpreterOptions};
+
+    #[test]
+    fn test_variable_trace() {
+        let src = r#"a = 12;
+b = 8;
+while (b != 0) {
+    t = b;
+    b = a % b;
+    a = t;
+}
+print(a);
+"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let mut trace = VariableTrace::new(Vec::new());
+        let mut out = Vec::new();
+        ASTInterpreter::interpret_with_hooks(
+            &ast,
+            &mut out,
+            &InterpreterOptions::default(),
+            &mut trace,
+        )
+        .unwrap();
+        assert_eq!(b"4", &out[..]);
+        assert_eq!(
+            "line 1: a = 12 (was unset)
+line 2: b = 8 (was unset)
+line 4: t = 8 (was unset)
+line 5: b = 4 (was 8)
+line 6: a = 8 (was 12)
+line 4: t = 4 (was 8)
+line 5: b = 0 (was 4)
+line 6: a = 4 (was 8)
+",
+            String::from_utf8(trace.into_inner()).unwrap()
+        );
+    }
+}
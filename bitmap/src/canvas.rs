@@ -0,0 +1,291 @@
+use super::{Bitmap, Color};
+
+/// Bitmap::draw_ellipse に渡す半径の上限。判定式が i64 に収まるようにする。
+/// これより大きな楕円は、画像の範囲の行と列ごとに浮動小数点数で求めて描く。
+const MAX_ELLIPSE_RADIUS: f64 = 16384.0;
+
+/// 座標変換をしながら Bitmap に描く。
+/// 点 (x, y) は画素 (origin.0 + scale.0 * x, origin.1 + scale.1 * y) に描かれる。
+pub struct Canvas<'a> {
+    bitmap: &'a mut Bitmap,
+    origin: (f64, f64),
+    scale: (f64, f64),
+}
+
+impl<'a> Canvas<'a> {
+    /// 変換なし (1 単位 = 1 画素、原点は左上) で始める
+    pub fn new(bitmap: &'a mut Bitmap) -> Self {
+        Canvas {
+            bitmap,
+            origin: (0.0, 0.0),
+            scale: (1.0, 1.0),
+        }
+    }
+
+    pub fn bitmap(&self) -> &Bitmap {
+        self.bitmap
+    }
+
+    /// 原点を現在の座標系の (dx, dy) に移す
+    pub fn translate(&mut self, dx: f64, dy: f64) -> &mut Self {
+        self.origin.0 += self.scale.0 * dx;
+        self.origin.1 += self.scale.1 * dy;
+        self
+    }
+
+    /// 座標系を x 方向に sx 倍、y 方向に sy 倍する。負の値で反転する。
+    pub fn scale(&mut self, sx: f64, sy: f64) -> &mut Self {
+        self.scale.0 *= sx;
+        self.scale.1 *= sy;
+        self
+    }
+
+    /// 変換をなくす
+    pub fn reset(&mut self) -> &mut Self {
+        self.origin = (0.0, 0.0);
+        self.scale = (1.0, 1.0);
+        self
+    }
+
+    /// 点 (x, y) の画素上の位置。丸める前の値。
+    fn transform(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.origin.0 + self.scale.0 * x,
+            self.origin.1 + self.scale.1 * y,
+        )
+    }
+
+    /// 点 (x, y) を描く画素の座標。 i32 に収まらなければ None。
+    pub fn to_pixel(&self, x: f64, y: f64) -> Option<(i32, i32)> {
+        let (px, py) = self.transform(x, y);
+        Some((to_i32(px.round())?, to_i32(py.round())?))
+    }
+
+    /// 線を切り取る範囲 (xmin, xmax, ymin, ymax)。画像の周りに 1 画素の余白をとる。
+    /// 境界を整数にして、切り取った端点を丸めても線の傾きが変わりにくいようにする。
+    fn bounds(&self) -> (f64, f64, f64, f64) {
+        (
+            -1.0,
+            self.bitmap.width() as f64,
+            -1.0,
+            self.bitmap.height() as f64,
+        )
+    }
+
+    /// 画像の外の点は描かない
+    pub fn plot(&mut self, x: f64, y: f64, c: Color) {
+        if let Some((px, py)) = self.to_pixel(x, y) {
+            self.bitmap.plot(px, py, c);
+        }
+    }
+
+    /// 線分を画像の範囲で切り取ってから描く
+    pub fn line(&mut self, x0: f64, y0: f64, x1: f64, y1: f64, c: Color) {
+        let p0 = self.transform(x0, y0);
+        let p1 = self.transform(x1, y1);
+        if let Some(((x0, y0), (x1, y1))) = clip_line(p0, p1, self.bounds()) {
+            // 切り取った端点は画像の近くにあるので i32 に収まる
+            self.bitmap.draw_line(
+                x0.round() as i32,
+                y0.round() as i32,
+                x1.round() as i32,
+                y1.round() as i32,
+                c,
+            );
+        }
+    }
+
+    /// 中心 (cx, cy)、半径 r の円。x と y の倍率が違う場合は楕円になる。
+    /// 画像にかからない円は描かない。
+    pub fn circle(&mut self, cx: f64, cy: f64, r: f64, c: Color) {
+        let (px, py) = self.transform(cx, cy);
+        let rx = (r * self.scale.0).abs();
+        let ry = (r * self.scale.1).abs();
+        if ![px, py, rx, ry].iter().all(|v| v.is_finite()) {
+            return;
+        }
+        let (xmin, xmax, ymin, ymax) = self.bounds();
+        if px + rx < xmin || px - rx > xmax || py + ry < ymin || py - ry > ymax {
+            return;
+        }
+        if rx <= MAX_ELLIPSE_RADIUS && ry <= MAX_ELLIPSE_RADIUS {
+            // 画像にかかるので中心も i32 に収まる
+            self.bitmap.draw_ellipse(
+                px.round() as i32,
+                py.round() as i32,
+                rx.round() as i32,
+                ry.round() as i32,
+                c,
+            );
+        } else {
+            self.large_ellipse(px, py, rx, ry, c);
+        }
+    }
+
+    /// 大きな楕円の、画像にかかる部分を描く。
+    /// 緩やかな部分を列ごとに、急な部分を行ごとに求めるので、どちらの向きにも隙間ができない。
+    fn large_ellipse(&mut self, cx: f64, cy: f64, rx: f64, ry: f64, c: Color) {
+        let (width, height) = (self.bitmap.width(), self.bitmap.height());
+        for x in 0..width {
+            let t = (x as f64 - cx) / rx;
+            if t.abs() <= 1.0 {
+                let dy = ry * (1.0 - t * t).sqrt();
+                for y in [cy - dy, cy + dy] {
+                    if let Some(y) = to_i32(y.round()) {
+                        self.bitmap.plot(x as i32, y, c);
+                    }
+                }
+            }
+        }
+        for y in 0..height {
+            let t = (y as f64 - cy) / ry;
+            if t.abs() <= 1.0 {
+                let dx = rx * (1.0 - t * t).sqrt();
+                for x in [cx - dx, cx + dx] {
+                    if let Some(x) = to_i32(x.round()) {
+                        self.bitmap.plot(x, y as i32, c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 丸めた座標を i32 にする。有限でないか範囲外なら None。
+fn to_i32(v: f64) -> Option<i32> {
+    if v >= i32::MIN as f64 && v <= i32::MAX as f64 {
+        Some(v as i32)
+    } else {
+        None
+    }
+}
+
+/// Liang-Barsky のアルゴリズムで、線分 p0 p1 の (xmin, xmax, ymin, ymax) の中にある部分を返す。
+/// 中を通らないか、座標が有限でなければ None。
+fn clip_line(
+    p0: (f64, f64),
+    p1: (f64, f64),
+    (xmin, xmax, ymin, ymax): (f64, f64, f64, f64),
+) -> Option<((f64, f64), (f64, f64))> {
+    let (dx, dy) = (p1.0 - p0.0, p1.1 - p0.1);
+    if ![p0.0, p0.1, dx, dy].iter().all(|v| v.is_finite()) {
+        return None;
+    }
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for &(p, q) in &[
+        (-dx, p0.0 - xmin),
+        (dx, xmax - p0.0),
+        (-dy, p0.1 - ymin),
+        (dy, ymax - p0.1),
+    ] {
+        if p == 0.0 {
+            // 辺に平行で外側にある
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some((
+        (p0.0 + t0 * dx, p0.1 + t0 * dy),
+        (p0.0 + t1 * dx, p0.1 + t1 * dy),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let mut bmp = Bitmap::new(21, 21);
+        let mut canvas = Canvas::new(&mut bmp);
+        assert_eq!(Some((3, 4)), canvas.to_pixel(3.0, 4.0));
+
+        // 中央を原点にして、y 軸を上向きにする
+        canvas.translate(10.0, 10.0).scale(10.0, -10.0);
+        assert_eq!(Some((10, 10)), canvas.to_pixel(0.0, 0.0));
+        assert_eq!(Some((20, 0)), canvas.to_pixel(1.0, 1.0));
+        assert_eq!(Some((5, 15)), canvas.to_pixel(-0.5, -0.5));
+
+        // 変換後の座標系で移動する
+        canvas.translate(0.5, 0.5);
+        assert_eq!(Some((15, 5)), canvas.to_pixel(0.0, 0.0));
+
+        // i32 に収まらない座標
+        assert_eq!(None, canvas.to_pixel(1e10, 0.0));
+        assert_eq!(None, canvas.to_pixel(f64::NAN, 0.0));
+
+        canvas.reset();
+        assert_eq!(Some((3, 4)), canvas.to_pixel(3.0, 4.0));
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut bmp = Bitmap::new(21, 21);
+        let mut canvas = Canvas::new(&mut bmp);
+        canvas.translate(10.0, 10.0).scale(10.0, -10.0);
+        canvas.line(-1.0, 0.0, 1.0, 0.0, Color::WHITE);
+        canvas.plot(0.5, -0.5, Color::RED);
+        canvas.circle(0.0, 0.0, 0.5, Color::BLUE);
+        // 範囲外は描かない
+        canvas.line(-5.0, -5.0, 5.0, 5.0, Color::GREEN);
+
+        let bmp = canvas.bitmap();
+        assert_eq!(Color::WHITE, *bmp.color(0, 10));
+        assert_eq!(Color::WHITE, *bmp.color(20, 10));
+        assert_eq!(Color::RED, *bmp.color(15, 15));
+        assert_eq!(Color::BLUE, *bmp.color(10, 5));
+        assert_eq!(Color::BLUE, *bmp.color(10, 15));
+        assert_eq!(Color::GREEN, *bmp.color(0, 20));
+    }
+
+    /// 描いた画素の座標
+    fn pixels(bmp: &Bitmap, c: Color) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..bmp.height() {
+            for x in 0..bmp.width() {
+                if *bmp.color(x, y) == c {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn test_clip() {
+        // i32 に収まらない端点でも、線の傾きは変わらない
+        let mut bmp = Bitmap::new(10, 10);
+        let mut canvas = Canvas::new(&mut bmp);
+        canvas.line(-1e12, -1e12 + 3.0, 1e12, 1e12 + 3.0, Color::WHITE);
+        let expected: Vec<_> = (0..7).map(|x| (x, x + 3)).collect();
+        assert_eq!(expected, pixels(canvas.bitmap(), Color::WHITE));
+
+        // 画像の外だけを通る線、有限でない座標は描かない
+        canvas.line(-1e12, -5.0, 1e12, -5.0, Color::RED);
+        canvas.line(f64::NAN, 0.0, 5.0, 5.0, Color::RED);
+        canvas.line(f64::INFINITY, 0.0, 5.0, 5.0, Color::RED);
+        canvas.plot(f64::NAN, f64::NAN, Color::RED);
+        canvas.plot(1e12, 1e12, Color::RED);
+        canvas.circle(1e12, 1e12, 5.0, Color::RED);
+        canvas.circle(5.0, 5.0, f64::INFINITY, Color::RED);
+        // 画像を内側に含む円の輪郭は画像にかからない
+        canvas.circle(5.0, 5.0, 1e12, Color::RED);
+        assert!(pixels(canvas.bitmap(), Color::RED).is_empty());
+
+        // 中心が遠くにある大きな円は、画像にかかる弧だけを描く
+        canvas.circle(5.0, 1e6 + 2.0, 1e6, Color::BLUE);
+        let expected: Vec<_> = (0..10).map(|x| (x, 2)).collect();
+        assert_eq!(expected, pixels(canvas.bitmap(), Color::BLUE));
+        canvas.circle(1e6 + 7.0, 5.0, 1e6, Color::GREEN);
+        let expected: Vec<_> = (0..10).map(|y| (7, y)).collect();
+        assert_eq!(expected, pixels(canvas.bitmap(), Color::GREEN));
+    }
+}
@@ -0,0 +1,127 @@
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Color {
+    pub(crate) r: u8,
+    pub(crate) g: u8,
+    pub(crate) b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0, 0, 0);
+    pub const WHITE: Color = Color::new(255, 255, 255);
+    pub const GRAY: Color = Color::new(128, 128, 128);
+    pub const RED: Color = Color::new(255, 0, 0);
+    pub const GREEN: Color = Color::new(0, 255, 0);
+    pub const BLUE: Color = Color::new(0, 0, 255);
+    pub const YELLOW: Color = Color::new(255, 255, 0);
+    pub const CYAN: Color = Color::new(0, 255, 255);
+    pub const MAGENTA: Color = Color::new(255, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Color { r, g, b }
+    }
+
+    pub fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub fn g(&self) -> u8 {
+        self.g
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+
+    /// 色相 h (度)、彩度 s と明度 v (0.0 - 1.0) から色を作る。
+    /// h は 360 で割った余りを使い、s と v は 0.0 - 1.0 に丸める。
+    pub fn from_hsv(h: f64, s: f64, v: f64) -> Self {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+        let c = v * s;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        let to_u8 = |f: f64| ((f + m) * 255.0).round() as u8;
+        Color::new(to_u8(r), to_u8(g), to_u8(b))
+    }
+
+    /// (色相 (度), 彩度, 明度) を返す。無彩色の色相は 0 とする。
+    pub fn to_hsv(&self) -> (f64, f64, f64) {
+        let r = self.r as f64 / 255.0;
+        let g = self.g as f64 / 255.0;
+        let b = self.b as f64 / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let c = max - min;
+        let h = if c == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / c).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / c + 2.0)
+        } else {
+            60.0 * ((r - g) / c + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { c / max };
+        (h, s, max)
+    }
+
+    /// 輝度 (ITU-R BT.709)
+    pub fn luminance(&self) -> u8 {
+        (0.2126 * self.r as f64 + 0.7152 * self.g as f64 + 0.0722 * self.b as f64).round() as u8
+    }
+
+    pub(crate) fn channel(&self, i: usize) -> u8 {
+        match i {
+            0 => self.r,
+            1 => self.g,
+            _ => self.b,
+        }
+    }
+
+    pub(crate) fn distance2(&self, other: &Color) -> u32 {
+        (0..3)
+            .map(|i| {
+                let d = self.channel(i) as i32 - other.channel(i) as i32;
+                (d * d) as u32
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsv() {
+        assert_eq!(Color::RED, Color::from_hsv(0.0, 1.0, 1.0));
+        assert_eq!(Color::YELLOW, Color::from_hsv(60.0, 1.0, 1.0));
+        assert_eq!(Color::BLUE, Color::from_hsv(240.0, 1.0, 1.0));
+        assert_eq!(Color::RED, Color::from_hsv(360.0, 1.0, 1.0));
+        assert_eq!(Color::WHITE, Color::from_hsv(123.0, 0.0, 1.0));
+        assert_eq!(Color::BLACK, Color::from_hsv(123.0, 1.0, 0.0));
+
+        assert_eq!((300.0, 1.0, 1.0), Color::MAGENTA.to_hsv());
+        assert_eq!((0.0, 0.0, 0.0), Color::BLACK.to_hsv());
+
+        // 変換して戻すと元の色になる
+        for &c in &[
+            Color::new(12, 34, 56),
+            Color::new(200, 100, 50),
+            Color::new(1, 255, 128),
+            Color::GRAY,
+        ] {
+            let (h, s, v) = c.to_hsv();
+            assert_eq!(c, Color::from_hsv(h, s, v));
+        }
+    }
+}
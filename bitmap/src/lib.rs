@@ -0,0 +1,964 @@
+#[cfg(feature = "png")]
+mod png;
+
+mod canvas;
+mod color;
+
+pub use canvas::Canvas;
+pub use color::Color;
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    image: Vec<Color>,
+}
+
+impl Bitmap {
+    pub fn new(width: usize, height: usize) -> Self {
+        Bitmap {
+            width,
+            height,
+            image: vec![Color::new(0, 0, 0); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn fill(&mut self, c: Color) {
+        for pixel in &mut self.image {
+            *pixel = c;
+        }
+    }
+
+    pub fn color(&self, x: usize, y: usize) -> &Color {
+        let idx = self.width * y + x;
+        &self.image[idx]
+    }
+
+    pub fn color_mut(&mut self, x: usize, y: usize) -> &mut Color {
+        let idx = self.width * y + x;
+        &mut self.image[idx]
+    }
+
+    /// 範囲外の座標は無視して (x, y) を c にする
+    pub fn plot(&mut self, x: i32, y: i32, c: Color) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            *self.color_mut(x as usize, y as usize) = c;
+        }
+    }
+
+    /// Bresenham のアルゴリズムで (x0, y0) から (x1, y1) まで線を引く。
+    /// 画像からはみ出した部分は描かない。
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, c: Color) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        loop {
+            self.plot(x, y, c);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// 中点アルゴリズムで中心 (cx, cy)、半径 r の円を描く
+    pub fn draw_circle(&mut self, cx: i32, cy: i32, r: i32, c: Color) {
+        self.circle_octants(r, |bmp, x, y| {
+            for &(dx, dy) in &[(x, y), (y, x), (-x, y), (-y, x)] {
+                bmp.plot(cx + dx, cy + dy, c);
+                bmp.plot(cx + dx, cy - dy, c);
+            }
+        });
+    }
+
+    /// 中心 (cx, cy)、半径 r の円を塗りつぶす
+    pub fn fill_circle(&mut self, cx: i32, cy: i32, r: i32, c: Color) {
+        self.circle_octants(r, |bmp, x, y| {
+            bmp.draw_line(cx - x, cy + y, cx + x, cy + y, c);
+            bmp.draw_line(cx - x, cy - y, cx + x, cy - y, c);
+            bmp.draw_line(cx - y, cy + x, cx + y, cy + x, c);
+            bmp.draw_line(cx - y, cy - x, cx + y, cy - x, c);
+        });
+    }
+
+    /// 半径 r の円の 1/8 (x >= y >= 0) の各点で f を呼ぶ
+    fn circle_octants<F>(&mut self, r: i32, mut f: F)
+    where
+        F: FnMut(&mut Self, i32, i32),
+    {
+        if r < 0 {
+            return;
+        }
+        let (mut x, mut y) = (r, 0);
+        let mut err = 1 - r;
+        while x >= y {
+            f(self, x, y);
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// 中点アルゴリズムで中心 (cx, cy)、半径 rx, ry の楕円を描く
+    pub fn draw_ellipse(&mut self, cx: i32, cy: i32, rx: i32, ry: i32, c: Color) {
+        if rx < 0 || ry < 0 {
+            return;
+        }
+        if rx == 0 || ry == 0 {
+            self.draw_line(cx - rx, cy - ry, cx + rx, cy + ry, c);
+            return;
+        }
+
+        let plot4 = |bmp: &mut Self, x: i64, y: i64| {
+            let (x, y) = (x as i32, y as i32);
+            bmp.plot(cx + x, cy + y, c);
+            bmp.plot(cx - x, cy + y, c);
+            bmp.plot(cx + x, cy - y, c);
+            bmp.plot(cx - x, cy - y, c);
+        };
+        // 判定式は小数を避けるために 4 倍している
+        let (rx2, ry2) = (rx as i64 * rx as i64, ry as i64 * ry as i64);
+        let (mut x, mut y) = (0, ry as i64);
+        let (mut px, mut py) = (0, 2 * rx2 * y);
+
+        // 傾きが -1 より緩やかな部分は x を 1 ずつ進める
+        let mut p = 4 * ry2 - 4 * rx2 * y + rx2;
+        while px < py {
+            plot4(self, x, y);
+            x += 1;
+            px += 2 * ry2;
+            if p < 0 {
+                p += 4 * (ry2 + px);
+            } else {
+                y -= 1;
+                py -= 2 * rx2;
+                p += 4 * (ry2 + px - py);
+            }
+        }
+
+        // 残りは y を 1 ずつ進める
+        let mut p = ry2 * (2 * x + 1) * (2 * x + 1) + 4 * rx2 * (y - 1) * (y - 1) - 4 * rx2 * ry2;
+        while y >= 0 {
+            plot4(self, x, y);
+            y -= 1;
+            py -= 2 * rx2;
+            if p > 0 {
+                p += 4 * (rx2 - py);
+            } else {
+                x += 1;
+                px += 2 * ry2;
+                p += 4 * (rx2 - py + px);
+            }
+        }
+    }
+
+    /// p0 から p2 までの、p1 を制御点とする 2 次ベジェ曲線を描く
+    pub fn draw_quadratic_bezier(
+        &mut self,
+        p0: (i32, i32),
+        p1: (i32, i32),
+        p2: (i32, i32),
+        c: Color,
+    ) {
+        self.draw_bezier(&[p0, p1, p2], c);
+    }
+
+    /// p0 から p3 までの、p1, p2 を制御点とする 3 次ベジェ曲線を描く
+    pub fn draw_cubic_bezier(
+        &mut self,
+        p0: (i32, i32),
+        p1: (i32, i32),
+        p2: (i32, i32),
+        p3: (i32, i32),
+        c: Color,
+    ) {
+        self.draw_bezier(&[p0, p1, p2, p3], c);
+    }
+
+    /// 曲線上の点を等間隔の t で求めて、線分でつなぐ。
+    /// 分割数は制御点を結んだ折れ線の長さから決める。
+    fn draw_bezier(&mut self, points: &[(i32, i32)], c: Color) {
+        let length: f64 = points
+            .windows(2)
+            .map(|w| {
+                let dx = (w[1].0 - w[0].0) as f64;
+                let dy = (w[1].1 - w[0].1) as f64;
+                dx.hypot(dy)
+            })
+            .sum();
+        let segments = ((length / BEZIER_STEP).ceil() as usize).max(1);
+
+        let mut prev = points[0];
+        for i in 1..=segments {
+            let next = bezier_point(points, i as f64 / segments as f64);
+            self.draw_line(prev.0, prev.1, next.0, next.1, c);
+            prev = next;
+        }
+    }
+
+    /// (x, y) からつながっている target の色の領域を replacement で塗る。
+    /// 再帰せずにスタックを使い、横一列ずつ塗る。
+    pub fn flood_fill(&mut self, x: usize, y: usize, target: Color, replacement: Color) {
+        if target == replacement || x >= self.width || y >= self.height {
+            return;
+        }
+
+        let mut stack = vec![(x, y)];
+        while let Some((x, y)) = stack.pop() {
+            if *self.color(x, y) != target {
+                continue;
+            }
+            let mut left = x;
+            while left > 0 && *self.color(left - 1, y) == target {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < self.width && *self.color(right + 1, y) == target {
+                right += 1;
+            }
+
+            for x in left..=right {
+                *self.color_mut(x, y) = replacement;
+            }
+            // 上下の行は、target の色が続く区間ごとに 1 点だけ積む
+            for &ny in &[y.wrapping_sub(1), y + 1] {
+                if ny >= self.height {
+                    continue;
+                }
+                let mut in_span = false;
+                for x in left..=right {
+                    let matched = *self.color(x, ny) == target;
+                    if matched && !in_span {
+                        stack.push((x, ny));
+                    }
+                    in_span = matched;
+                }
+            }
+        }
+    }
+
+    /// 輝度をグレーにした画像を返す
+    pub fn to_grayscale(&self) -> Bitmap {
+        self.map(|c| {
+            let l = c.luminance();
+            Color::new(l, l, l)
+        })
+    }
+
+    /// 輝度ごとの画素数
+    pub fn histogram(&self) -> [usize; 256] {
+        let mut histogram = [0; 256];
+        for c in &self.image {
+            histogram[c.luminance() as usize] += 1;
+        }
+        histogram
+    }
+
+    /// メディアンカットで n_colors 色以下に減色した画像を返す
+    pub fn quantize(&self, n_colors: usize) -> Bitmap {
+        if self.image.is_empty() {
+            return self.map(|c| c);
+        }
+
+        let mut boxes = vec![self.image.clone()];
+        while boxes.len() < n_colors.max(1) {
+            // 色の幅が最も大きい箱を、その幅が最も大きいチャンネルの中央値で分ける
+            let (index, channel, range) = boxes
+                .iter()
+                .enumerate()
+                .map(|(index, colors)| {
+                    let (channel, range) = widest_channel(colors);
+                    (index, channel, range)
+                })
+                .max_by_key(|&(_, _, range)| range)
+                .unwrap();
+            if range == 0 {
+                break;
+            }
+            let mut colors = boxes.swap_remove(index);
+            colors.sort_by_key(|c| c.channel(channel));
+            let upper = colors.split_off(colors.len() / 2);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+
+        let palette: Vec<Color> = boxes.iter().map(|colors| average(colors)).collect();
+        self.map(|c| *palette.iter().min_by_key(|p| p.distance2(&c)).unwrap())
+    }
+
+    fn map<F>(&self, f: F) -> Bitmap
+    where
+        F: Fn(Color) -> Color,
+    {
+        Bitmap {
+            width: self.width,
+            height: self.height,
+            image: self.image.iter().map(|&c| f(c)).collect(),
+        }
+    }
+
+    pub fn write_ppm(&self, out: &mut dyn Write) -> Result<()> {
+        let header = format!("P6\n{} {}\n255\n", self.width(), self.height());
+        out.write_all(header.as_bytes())?;
+
+        for c in &self.image {
+            out.write_all(&[c.r, c.g, c.b])?;
+        }
+
+        Ok(())
+    }
+
+    /// 24 bit の無圧縮 BMP を書く
+    pub fn write_bmp(&self, out: &mut dyn Write) -> Result<()> {
+        const HEADER_SIZE: u32 = 14 + 40;
+        // 各行は 4 バイト境界まで埋める
+        let row_size = (self.width * 3).div_ceil(4) * 4;
+        let image_size = (row_size * self.height) as u32;
+
+        out.write_all(b"BM")?;
+        out.write_all(&(HEADER_SIZE + image_size).to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&HEADER_SIZE.to_le_bytes())?;
+
+        out.write_all(&40u32.to_le_bytes())?;
+        out.write_all(&(self.width as i32).to_le_bytes())?;
+        out.write_all(&(self.height as i32).to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&24u16.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&image_size.to_le_bytes())?;
+        // 72 dpi
+        out.write_all(&2835i32.to_le_bytes())?;
+        out.write_all(&2835i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        // 下の行から、BGR の順に並べる
+        let mut row = Vec::with_capacity(row_size);
+        for y in (0..self.height).rev() {
+            row.clear();
+            for x in 0..self.width {
+                let c = self.color(x, y);
+                row.extend_from_slice(&[c.b, c.g, c.r]);
+            }
+            row.resize(row_size, 0);
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// 無圧縮の PNG を書く
+    #[cfg(feature = "png")]
+    pub fn write_png(&self, out: &mut dyn Write) -> Result<()> {
+        png::write_png(self, out)
+    }
+
+    /// P3 (テキスト) と P6 (バイナリ) の PPM を読む。
+    /// 最大値が 255 以外の場合は 0 - 255 に変換する。
+    pub fn read_ppm(input: &mut impl Read) -> Result<Self> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut parser = PpmParser { data, pos: 0 };
+
+        let binary = match parser.token()? {
+            b"P3" => false,
+            b"P6" => true,
+            _ => return Err(invalid_data("unsupported magic number")),
+        };
+        let width = parser.number()? as usize;
+        let height = parser.number()? as usize;
+        let max_value = parser.number()?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_data("invalid max value"));
+        }
+        if binary {
+            // 最大値の後には空白がちょうど 1 つある
+            parser.pos += 1;
+        }
+        // 確保する前に、画素数が残りのデータに収まることを確かめる。
+        // 標本は ASCII では少なくとも 1 バイト、バイナリでは 1 か 2 バイトある。
+        let samples = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .ok_or_else(|| invalid_data("image is too large"))?;
+        let sample_size = if binary && max_value > 255 { 2 } else { 1 };
+        if samples
+            .checked_mul(sample_size)
+            .is_none_or(|size| size > parser.remaining())
+        {
+            return Err(invalid_data("pixel data is shorter than the header says"));
+        }
+
+        let mut sample = || -> Result<u8> {
+            let value = if !binary {
+                parser.number()?
+            } else if max_value < 256 {
+                parser.byte()? as u32
+            } else {
+                (parser.byte()? as u32) << 8 | parser.byte()? as u32
+            };
+            if value > max_value {
+                return Err(invalid_data("sample exceeds max value"));
+            }
+            Ok(((value * 255 + max_value / 2) / max_value) as u8)
+        };
+
+        let mut bitmap = Bitmap::new(width, height);
+        for pixel in &mut bitmap.image {
+            let r = sample()?;
+            let g = sample()?;
+            let b = sample()?;
+            *pixel = Color::new(r, g, b);
+        }
+        Ok(bitmap)
+    }
+}
+
+/// 色の幅が最も大きいチャンネルとその幅
+fn widest_channel(colors: &[Color]) -> (usize, u8) {
+    (0..3)
+        .map(|i| {
+            let min = colors.iter().map(|c| c.channel(i)).min().unwrap_or(0);
+            let max = colors.iter().map(|c| c.channel(i)).max().unwrap_or(0);
+            (i, max - min)
+        })
+        .max_by_key(|&(i, range)| (range, std::cmp::Reverse(i)))
+        .unwrap()
+}
+
+fn average(colors: &[Color]) -> Color {
+    let n = colors.len() as u64;
+    let sum = |i| colors.iter().map(|c| c.channel(i) as u64).sum::<u64>();
+    Color::new(
+        ((sum(0) + n / 2) / n) as u8,
+        ((sum(1) + n / 2) / n) as u8,
+        ((sum(2) + n / 2) / n) as u8,
+    )
+}
+
+/// ベジェ曲線を近似する線分の (おおよその) 長さ
+const BEZIER_STEP: f64 = 4.0;
+
+/// de Casteljau のアルゴリズムで t における曲線上の点を求める
+fn bezier_point(points: &[(i32, i32)], t: f64) -> (i32, i32) {
+    let mut work: Vec<(f64, f64)> = points.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+    for n in (1..work.len()).rev() {
+        for i in 0..n {
+            work[i] = (
+                work[i].0 + (work[i + 1].0 - work[i].0) * t,
+                work[i].1 + (work[i + 1].1 - work[i].1) * t,
+            );
+        }
+    }
+    (work[0].0.round() as i32, work[0].1.round() as i32)
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct PpmParser {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl PpmParser {
+    /// 空白とコメント ('#' から行末まで) を読み飛ばす
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.data.get(self.pos) {
+            if c == b'#' {
+                while self.data.get(self.pos).is_some_and(|&c| c != b'\n') {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<&[u8]> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|c| !c.is_ascii_whitespace() && *c != b'#')
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "unexpected end of PPM",
+            ));
+        }
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u32> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid_data("number expected"))
+    }
+
+    /// まだ読んでいないバイト数
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = self
+            .data
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "unexpected end of PPM"))?;
+        self.pos += 1;
+        Ok(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_fill() {
+        let mut bmp = Bitmap::new(10, 20);
+        bmp.fill(Color::new(0, 128, 255));
+
+        assert_eq!(Color::new(0, 128, 255), *bmp.color(0, 0));
+        assert_eq!(Color::new(0, 128, 255), *bmp.color(5, 10));
+        assert_eq!(Color::new(0, 128, 255), *bmp.color(9, 19));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut bmp = Bitmap::new(10, 20);
+        *bmp.color_mut(2, 3) = Color::new(255, 128, 0);
+
+        assert_eq!(Color::new(0, 0, 0), *bmp.color(0, 0));
+        assert_eq!(Color::new(255, 128, 0), *bmp.color(2, 3));
+    }
+
+    /// c で塗られた画素の座標
+    fn plotted(bmp: &Bitmap, c: Color) -> Vec<(usize, usize)> {
+        let mut points = Vec::new();
+        for y in 0..bmp.height() {
+            for x in 0..bmp.width() {
+                if *bmp.color(x, y) == c {
+                    points.push((x, y));
+                }
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let white = Color::new(255, 255, 255);
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_line(3, 1, 0, 1, white);
+        assert_eq!(vec![(0, 1), (1, 1), (2, 1), (3, 1)], plotted(&bmp, white));
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_line(2, 0, 2, 2, white);
+        assert_eq!(vec![(2, 0), (2, 1), (2, 2)], plotted(&bmp, white));
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_line(4, 0, 0, 4, white);
+        assert_eq!(
+            vec![(4, 0), (3, 1), (2, 2), (1, 3), (0, 4)],
+            plotted(&bmp, white)
+        );
+
+        // どの向きでも、両端を含めて長い方の軸の長さ + 1 個の画素を塗る
+        for &(x0, y0, x1, y1) in &[(0, 0, 4, 2), (0, 0, 2, 4), (4, 0, 0, 3), (1, 4, 3, 0)] {
+            for &(x0, y0, x1, y1) in &[(x0, y0, x1, y1), (x1, y1, x0, y0)] {
+                let mut bmp = Bitmap::new(5, 5);
+                bmp.draw_line(x0, y0, x1, y1, white);
+                let points = plotted(&bmp, white);
+                assert!(points.contains(&(x0 as usize, y0 as usize)));
+                assert!(points.contains(&(x1 as usize, y1 as usize)));
+                let length = (x1 - x0).abs().max((y1 - y0).abs());
+                assert_eq!(length as usize + 1, points.len());
+            }
+        }
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_line(-2, -2, 10, 10, white);
+        assert_eq!(5, plotted(&bmp, white).len());
+    }
+
+    #[test]
+    fn test_draw_circle() {
+        let white = Color::new(255, 255, 255);
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_circle(2, 2, 0, white);
+        assert_eq!(vec![(2, 2)], plotted(&bmp, white));
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_circle(2, 2, 2, white);
+        assert_eq!(
+            vec![
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (0, 1),
+                (4, 1),
+                (0, 2),
+                (4, 2),
+                (0, 3),
+                (4, 3),
+                (1, 4),
+                (2, 4),
+                (3, 4)
+            ],
+            plotted(&bmp, white)
+        );
+
+        // 画像からはみ出しても panic しない
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_circle(0, 0, 3, white);
+        bmp.draw_circle(-10, 20, 3, white);
+        assert_eq!(
+            vec![(3, 0), (3, 1), (2, 2), (0, 3), (1, 3)],
+            plotted(&bmp, white)
+        );
+    }
+
+    #[test]
+    fn test_fill_circle() {
+        let white = Color::new(255, 255, 255);
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.fill_circle(2, 2, 1, white);
+        assert_eq!(
+            vec![(2, 1), (1, 2), (2, 2), (3, 2), (2, 3)],
+            plotted(&bmp, white)
+        );
+
+        let mut bmp = Bitmap::new(41, 41);
+        bmp.fill_circle(20, 20, 20, white);
+        let area = plotted(&bmp, white).len() as f64;
+        let expected = std::f64::consts::PI * 20.0 * 20.0;
+        assert!((area - expected).abs() / expected < 0.05);
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.fill_circle(4, 4, 10, white);
+        assert_eq!(25, plotted(&bmp, white).len());
+    }
+
+    #[test]
+    fn test_draw_ellipse() {
+        let white = Color::new(255, 255, 255);
+
+        let mut bmp = Bitmap::new(21, 11);
+        bmp.draw_ellipse(10, 5, 8, 4, white);
+        let points = plotted(&bmp, white);
+        for p in &[(2, 5), (18, 5), (10, 1), (10, 9)] {
+            assert!(points.contains(p));
+        }
+        for &(x, y) in &points {
+            // 上下左右に対称
+            assert!(points.contains(&(20 - x, y)));
+            assert!(points.contains(&(x, 10 - y)));
+            // 楕円の近くにある
+            let (dx, dy) = (x as f64 - 10.0, y as f64 - 5.0);
+            let d = (dx / 8.0).powi(2) + (dy / 4.0).powi(2);
+            assert!((d - 1.0).abs() < 0.35, "({}, {})", x, y);
+        }
+
+        // 半径が等しい楕円は円と同じ
+        let mut circle = Bitmap::new(11, 11);
+        circle.draw_circle(5, 5, 4, white);
+        let mut ellipse = Bitmap::new(11, 11);
+        ellipse.draw_ellipse(5, 5, 4, 4, white);
+        assert_eq!(plotted(&circle, white), plotted(&ellipse, white));
+
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_ellipse(2, 2, 0, 2, white);
+        assert_eq!(
+            vec![(2, 0), (2, 1), (2, 2), (2, 3), (2, 4)],
+            plotted(&bmp, white)
+        );
+        bmp.draw_ellipse(-5, 2, 100, 50, white);
+    }
+
+    #[test]
+    fn test_bezier_point() {
+        let points = [(0, 0), (10, 20), (20, 0)];
+        assert_eq!((0, 0), bezier_point(&points, 0.0));
+        assert_eq!((10, 10), bezier_point(&points, 0.5));
+        assert_eq!((20, 0), bezier_point(&points, 1.0));
+
+        let points = [(0, 0), (0, 30), (30, 30), (30, 0)];
+        assert_eq!((15, 23), bezier_point(&points, 0.5));
+    }
+
+    #[test]
+    fn test_draw_bezier() {
+        let white = Color::new(255, 255, 255);
+
+        let mut bmp = Bitmap::new(21, 21);
+        bmp.draw_quadratic_bezier((0, 20), (10, 0), (20, 20), white);
+        let quadratic = plotted(&bmp, white);
+
+        let mut bmp = Bitmap::new(21, 21);
+        bmp.draw_cubic_bezier((0, 20), (5, 0), (15, 0), (20, 20), white);
+        let cubic = plotted(&bmp, white);
+
+        for points in &[quadratic, cubic] {
+            assert!(points.contains(&(0, 20)));
+            assert!(points.contains(&(20, 20)));
+            // 制御点が左右対称なら、曲線もほぼ左右対称になる
+            for &(x, y) in points {
+                let mirrored = (20 - x as i32, y as i32);
+                assert!(points.iter().any(|&(px, py)| {
+                    (px as i32 - mirrored.0).abs() <= 1 && (py as i32 - mirrored.1).abs() <= 1
+                }));
+            }
+            // 途切れずにつながっている
+            for &(x, y) in points {
+                let neighbors = points
+                    .iter()
+                    .filter(|&&(px, py)| {
+                        (px, py) != (x, y)
+                            && (px as i32 - x as i32).abs() <= 1
+                            && (py as i32 - y as i32).abs() <= 1
+                    })
+                    .count();
+                assert!(neighbors >= 1);
+            }
+        }
+
+        // 制御点が一直線上にあれば直線になる
+        let mut bmp = Bitmap::new(21, 21);
+        bmp.draw_cubic_bezier((0, 5), (7, 5), (14, 5), (20, 5), white);
+        assert_eq!(
+            (0..=20).map(|x| (x, 5)).collect::<Vec<_>>(),
+            plotted(&bmp, white)
+        );
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let black = Color::new(0, 0, 0);
+        let white = Color::new(255, 255, 255);
+        let red = Color::new(255, 0, 0);
+
+        // 円の内側だけ塗られる
+        let mut bmp = Bitmap::new(11, 11);
+        bmp.draw_circle(5, 5, 4, white);
+        bmp.flood_fill(5, 5, black, red);
+        let inside = plotted(&bmp, red);
+        assert!(inside.contains(&(5, 5)));
+        assert!(inside.contains(&(2, 5)));
+        assert!(!inside.contains(&(0, 0)));
+        assert_eq!(Color::new(0, 0, 0), *bmp.color(10, 10));
+
+        // 凹んだ形も塗り残さない
+        let mut bmp = Bitmap::new(5, 5);
+        bmp.draw_line(1, 0, 1, 3, white);
+        bmp.draw_line(3, 1, 3, 4, white);
+        bmp.flood_fill(0, 0, black, red);
+        assert_eq!(25 - 8, plotted(&bmp, red).len());
+
+        // 開始点が target の色でなければ何もしない
+        bmp.flood_fill(1, 0, black, red);
+        assert_eq!(8, plotted(&bmp, white).len());
+
+        // 大きな領域でもスタックがあふれない
+        let mut bmp = Bitmap::new(1000, 1000);
+        bmp.flood_fill(500, 500, black, red);
+        assert_eq!(1000 * 1000, plotted(&bmp, red).len());
+    }
+
+    #[test]
+    fn test_grayscale() {
+        let mut bmp = Bitmap::new(3, 1);
+        *bmp.color_mut(0, 0) = Color::new(255, 0, 0);
+        *bmp.color_mut(1, 0) = Color::new(0, 255, 0);
+        *bmp.color_mut(2, 0) = Color::new(255, 255, 255);
+        let gray = bmp.to_grayscale();
+        assert_eq!(Color::new(54, 54, 54), *gray.color(0, 0));
+        assert_eq!(Color::new(182, 182, 182), *gray.color(1, 0));
+        assert_eq!(Color::new(255, 255, 255), *gray.color(2, 0));
+
+        let histogram = bmp.histogram();
+        assert_eq!(1, histogram[54]);
+        assert_eq!(1, histogram[182]);
+        assert_eq!(1, histogram[255]);
+        assert_eq!(3, histogram.iter().sum::<usize>());
+    }
+
+    #[test]
+    fn test_quantize() {
+        let mut bmp = Bitmap::new(4, 4);
+        bmp.fill(Color::new(250, 0, 0));
+        for x in 0..4 {
+            *bmp.color_mut(x, 0) = Color::new(0, 0, 250);
+            *bmp.color_mut(x, 1) = Color::new(0, 0, 254);
+        }
+
+        // 2 色にすると、近い色はまとめられる
+        let quantized = bmp.quantize(2);
+        assert_eq!(Color::new(0, 0, 252), *quantized.color(0, 0));
+        assert_eq!(Color::new(0, 0, 252), *quantized.color(3, 1));
+        assert_eq!(Color::new(250, 0, 0), *quantized.color(2, 2));
+
+        // 色数が十分なら変わらない
+        let quantized = bmp.quantize(16);
+        assert_eq!(bmp.image, quantized.image);
+
+        let quantized = bmp.quantize(1);
+        let first = *quantized.color(0, 0);
+        assert!(quantized.image.iter().all(|&c| c == first));
+    }
+
+    /// テスト用の画像。PPM を経由して読み直したもの。
+    fn round_tripped() -> Bitmap {
+        let mut bmp = Bitmap::new(5, 3);
+        for y in 0..3 {
+            for x in 0..5 {
+                *bmp.color_mut(x, y) = Color::new(x as u8 * 50, y as u8 * 100, 7);
+            }
+        }
+        let mut ppm = Vec::new();
+        bmp.write_ppm(&mut ppm).unwrap();
+        Bitmap::read_ppm(&mut &ppm[..]).unwrap()
+    }
+
+    fn le32(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[cfg(feature = "png")]
+    fn be32(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_write_bmp() {
+        let bmp = round_tripped();
+        let mut data = Vec::new();
+        bmp.write_bmp(&mut data).unwrap();
+
+        assert_eq!(b"BM", &data[0..2]);
+        assert_eq!(data.len() as u32, le32(&data, 2));
+        let offset = le32(&data, 10) as usize;
+        assert_eq!(5, le32(&data, 18));
+        assert_eq!(3, le32(&data, 22));
+        // 1 行 15 バイトを 16 バイトに埋める
+        assert_eq!(offset + 16 * 3, data.len());
+        for y in 0..3 {
+            let row = offset + (2 - y) * 16;
+            for x in 0..5 {
+                let p = &data[row + x * 3..row + x * 3 + 3];
+                let c = bmp.color(x, y);
+                assert_eq!([c.b, c.g, c.r], p);
+            }
+        }
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_write_png() {
+        let bmp = round_tripped();
+        let mut data = Vec::new();
+        bmp.write_png(&mut data).unwrap();
+
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &data[0..8]);
+        // チャンクを読みながら CRC を確かめる
+        let mut pos = 8;
+        let mut chunks = Vec::new();
+        while pos < data.len() {
+            let len = be32(&data, pos) as usize;
+            let body = &data[pos + 4..pos + 8 + len];
+            assert_eq!(png::crc32(body), be32(&data, pos + 8 + len));
+            chunks.push((&body[0..4], &body[4..]));
+            pos += 12 + len;
+        }
+        let kinds: Vec<&[u8]> = chunks.iter().map(|c| c.0).collect();
+        assert_eq!(vec![&b"IHDR"[..], b"IDAT", b"IEND"], kinds);
+        assert_eq!(5, be32(chunks[0].1, 0));
+        assert_eq!(3, be32(chunks[0].1, 4));
+
+        // stored ブロック 1 つに収まる: zlib ヘッダ 2 + ブロックヘッダ 5
+        let raw = &chunks[1].1[7..chunks[1].1.len() - 4];
+        assert_eq!(3 * (1 + 5 * 3), raw.len());
+        for y in 0..3 {
+            let row = &raw[y * 16..(y + 1) * 16];
+            assert_eq!(0, row[0]);
+            for x in 0..5 {
+                let c = bmp.color(x, y);
+                assert_eq!([c.r, c.g, c.b], row[1 + x * 3..4 + x * 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_ppm() {
+        let mut bmp = Bitmap::new(2, 1);
+        *bmp.color_mut(1, 0) = Color::new(255, 128, 0);
+        let mut ppm = Vec::new();
+        bmp.write_ppm(&mut ppm).unwrap();
+        let read = Bitmap::read_ppm(&mut &ppm[..]).unwrap();
+        assert_eq!(2, read.width());
+        assert_eq!(1, read.height());
+        assert_eq!(Color::new(255, 128, 0), *read.color(1, 0));
+
+        let p3 = b"P3 # comment\n2 1\n# max value\n15\n0 0 0 15 8 0";
+        let read = Bitmap::read_ppm(&mut &p3[..]).unwrap();
+        assert_eq!(Color::new(255, 136, 0), *read.color(1, 0));
+
+        let p6 = b"P6\n1 1\n65535\n\xff\xff\x80\x00\x00\x00";
+        let read = Bitmap::read_ppm(&mut &p6[..]).unwrap();
+        assert_eq!(Color::new(255, 128, 0), *read.color(0, 0));
+
+        assert!(Bitmap::read_ppm(&mut &b"P5 1 1 255 0"[..]).is_err());
+        assert!(Bitmap::read_ppm(&mut &b"P3 1 1 255 0 0"[..]).is_err());
+        assert!(Bitmap::read_ppm(&mut &b"P3 1 1 15 0 0 16"[..]).is_err());
+
+        // 画素数が大きすぎるヘッダは、確保する前にエラーにする
+        for header in [
+            &b"P6 4294967295 4294967295 255\n\0\0\0"[..],
+            b"P6 100000 100000 255\n\0\0\0",
+            b"P6 1 1 65535\n\0\0\0",
+        ] {
+            let e = Bitmap::read_ppm(&mut &header[..]).err().unwrap();
+            assert_eq!(ErrorKind::InvalidData, e.kind());
+        }
+    }
+}
@@ -1,115 +1,111 @@
+use bitmap::{Bitmap, Color};
 use std::fs::File;
-use std::io::{BufWriter, Result, Write};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::process;
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-}
+/// コマンドライン引数の誤り。コンパイラのコマンドと同じ終了コードにする。
+const EXIT_USAGE: i32 = 2;
 
-impl Color {
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
-        Color { r, g, b }
-    }
+fn print_usage(name: &str) {
+    eprintln!("Usage: {} [options] filename [input]", name);
+    eprintln!();
+    eprintln!("    writes a sample image, or input (P3 or P6), to filename");
+    eprintln!("    filename ending with .bmp or .png is written in that format, otherwise as P6");
+    eprintln!();
+    eprintln!("    --grayscale     convert the image to grayscale");
+    eprintln!("    --quantize n    reduce the image to n colors");
+    eprintln!("    --histogram     print the luminance histogram");
 }
 
-pub struct Bitmap {
-    width: usize,
-    height: usize,
-    image: Vec<Color>,
+/// 引数の誤りを標準エラー出力に書いて終了する
+fn usage_error(name: &str, message: &str) -> ! {
+    eprintln!("{}: {}\n", name, message);
+    print_usage(name);
+    process::exit(EXIT_USAGE);
 }
 
-impl Bitmap {
-    pub fn new(width: usize, height: usize) -> Self {
-        Bitmap {
-            width,
-            height,
-            image: vec![Color::new(0, 0, 0); width * height],
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut grayscale = false;
+    let mut quantize = None;
+    let mut histogram = false;
+    while args.len() > 1 {
+        match args[1].as_str() {
+            "--grayscale" => grayscale = true,
+            "--histogram" => histogram = true,
+            "--quantize" => match args.get(2).map(|n| n.parse()) {
+                Some(Ok(n)) => {
+                    quantize = Some(n);
+                    args.remove(1);
+                }
+                Some(Err(_)) => {
+                    usage_error(&args[0], &format!("invalid number of colors: {}", args[2]))
+                }
+                None => usage_error(&args[0], "--quantize needs the number of colors"),
+            },
+            _ => break,
         }
+        args.remove(1);
     }
-
-    pub fn width(&self) -> usize {
-        self.width
+    if args.len() < 2 {
+        debug_assert!(args.len() == 1);
+        print_usage(&args[0]);
+        process::exit(EXIT_USAGE);
     }
 
-    pub fn height(&self) -> usize {
-        self.height
-    }
+    let f = File::create(&args[1]).unwrap_or_else(|_| panic!("{} cannot be created.", args[1]));
+    let mut f = BufWriter::new(f);
 
-    pub fn fill(&mut self, c: Color) {
-        for pixel in &mut self.image {
-            *pixel = c;
+    let mut bmp = match args.get(2) {
+        Some(input) => {
+            let input = File::open(input).unwrap_or_else(|_| panic!("{} cannot be opened.", input));
+            Bitmap::read_ppm(&mut BufReader::new(input)).expect("read PPM failed.")
         }
+        None => sample(),
+    };
+    if grayscale {
+        bmp = bmp.to_grayscale();
     }
-
-    pub fn color(&self, x: usize, y: usize) -> &Color {
-        let idx = self.width * y + x;
-        &self.image[idx]
+    if let Some(n) = quantize {
+        bmp = bmp.quantize(n);
     }
-
-    pub fn color_mut(&mut self, x: usize, y: usize) -> &mut Color {
-        let idx = self.width * y + x;
-        &mut self.image[idx]
-    }
-
-    pub fn write_ppm(&self, out: &mut Write) -> Result<()> {
-        let header = format!("P6\n{} {}\n255\n", self.width(), self.height());
-        out.write(header.as_bytes())?;
-
-        for c in &self.image {
-            out.write(&[c.r, c.g, c.b])?;
+    if histogram {
+        for (luminance, count) in bmp.histogram().iter().enumerate() {
+            println!("{:3} {}", luminance, count);
         }
-
-        Ok(())
     }
-}
 
-fn print_usage(name: &str) {
-    println!("Usage: {} filename", name);
+    let written = match Path::new(&args[1]).extension().and_then(|e| e.to_str()) {
+        Some("bmp") => bmp.write_bmp(&mut f),
+        #[cfg(feature = "png")]
+        Some("png") => bmp.write_png(&mut f),
+        _ => bmp.write_ppm(&mut f),
+    };
+    written.expect("write image failed.");
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        debug_assert!(args.len() == 1);
-        print_usage(&args[0]);
-        return;
-    }
-
-    let f = File::create(&args[1]).expect(&format!("{} cannot be created.", args[1]));
-    let mut f = BufWriter::new(f);
-
+fn sample() -> Bitmap {
     let mut bmp = Bitmap::new(300, 400);
 
     bmp.fill(Color::new(128, 64, 255));
     for x in 10..100 {
-        *bmp.color_mut(x, 20) = Color::new(255, 255, 255);
-    }
-
-    bmp.write_ppm(&mut f).expect("write PPM failed.");
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_fill() {
-        let mut bmp = Bitmap::new(10, 20);
-        bmp.fill(Color::new(0, 128, 255));
-
-        assert_eq!(Color::new(0, 128, 255), *bmp.color(0, 0));
-        assert_eq!(Color::new(0, 128, 255), *bmp.color(5, 10));
-        assert_eq!(Color::new(0, 128, 255), *bmp.color(9, 19));
-    }
-
-    #[test]
-    fn test_set() {
-        let mut bmp = Bitmap::new(10, 20);
-        *bmp.color_mut(2, 3) = Color::new(255, 128, 0);
-
-        assert_eq!(Color::new(0, 0, 0), *bmp.color(0, 0));
-        assert_eq!(Color::new(255, 128, 0), *bmp.color(2, 3));
+        *bmp.color_mut(x, 20) = Color::WHITE;
     }
+    bmp.draw_line(10, 40, 290, 390, Color::YELLOW);
+    bmp.draw_line(290, 40, 10, 100, Color::CYAN);
+    bmp.fill_circle(150, 200, 50, Color::RED);
+    bmp.draw_circle(150, 200, 80, Color::GREEN);
+    bmp.flood_fill(150, 130, Color::new(128, 64, 255), Color::new(64, 32, 128));
+    bmp.draw_ellipse(150, 200, 140, 60, Color::BLUE);
+    bmp.draw_circle(0, 0, 60, Color::WHITE);
+    bmp.draw_quadratic_bezier((10, 390), (150, 250), (290, 390), Color::new(255, 128, 0));
+    bmp.draw_cubic_bezier(
+        (10, 300),
+        (100, 200),
+        (200, 400),
+        (290, 300),
+        Color::new(128, 255, 0),
+    );
+    bmp
 }
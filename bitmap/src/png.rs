@@ -0,0 +1,104 @@
+//! 無圧縮 (deflate の stored ブロック) の PNG エンコーダ
+use super::Bitmap;
+
+use std::io::{Result, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// stored ブロック 1 つに入る最大のバイト数
+const MAX_BLOCK: usize = 65535;
+
+pub(crate) fn write_png(bitmap: &Bitmap, out: &mut dyn Write) -> Result<()> {
+    out.write_all(&SIGNATURE)?;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(bitmap.width() as u32).to_be_bytes());
+    header.extend_from_slice(&(bitmap.height() as u32).to_be_bytes());
+    // ビット深度 8、トゥルーカラー、圧縮・フィルタ・インターレースは標準
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &header)?;
+
+    // 各行の先頭にフィルタの種類 (0: なし) を置く
+    let mut raw = Vec::with_capacity((bitmap.width() * 3 + 1) * bitmap.height());
+    for y in 0..bitmap.height() {
+        raw.push(0);
+        for x in 0..bitmap.width() {
+            let c = bitmap.color(x, y);
+            raw.extend_from_slice(&[c.r, c.g, c.b]);
+        }
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(out, b"IEND", &[])
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(kind.iter().chain(data));
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// 圧縮しない zlib ストリームを作る
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+pub(crate) fn crc32<'a>(data: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data {
+        a = (a + d as u32) % MOD;
+        b = (b + a) % MOD;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+        assert_eq!(0x11e6_0398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        let data = vec![7u8; MAX_BLOCK + 1];
+        let stream = zlib_stored(&data);
+        // ヘッダ + 2 ブロック + adler32
+        assert_eq!(2 + (5 + MAX_BLOCK) + (5 + 1) + 4, stream.len());
+        assert_eq!(0, stream[2]);
+        assert_eq!(1, stream[2 + 5 + MAX_BLOCK]);
+    }
+}
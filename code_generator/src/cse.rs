@@ -0,0 +1,151 @@
+use lexical_analyzer::width::Width;
+use syntax_analyzer::ast_node::*;
+
+use std::collections::HashMap;
+
+/// 式の中の演算の出現。 collect が前順で並べる。
+struct Occurrence<'a> {
+    node: &'a ASTNode,
+    /// 同じ計算なら同じになる文字列。 rand や clock を含む式は None。
+    key: Option<String>,
+    /// 生成する命令の数
+    cost: usize,
+    /// 子孫の出現はこの出現の次から end の前まで
+    end: usize,
+}
+
+/// ast の key と命令の数を返し、演算を occurrences に加える。
+/// hoisted の式はループの前で計算済みで、 fetch 1 命令になる。
+fn collect<'a>(
+    ast: &'a ASTNode,
+    hoisted: &HashMap<*const ASTNode, u32>,
+    width: Width,
+    occurrences: &mut Vec<Occurrence<'a>>,
+) -> Option<(String, usize)> {
+    if let Some(addr) = hoisted.get(&(ast as *const ASTNode)) {
+        return Some((format!("[{}]", addr), 1));
+    }
+    // 畳み込まれる定数式は push 1 命令になる
+    if let Some(value) = ast.constant_value_in(width) {
+        return Some((value.to_string(), 1));
+    }
+    match ast.kind() {
+        NodeKind::Identifier(identifier) => return Some((identifier.to_string(), 1)),
+        NodeKind::Integer(value) => return Some((value.to_string(), 1)),
+        NodeKind::Float(_) => return Some((format!("{:?}", ast.kind()), 1)),
+        NodeKind::Clock | NodeKind::Getc | NodeKind::Geti => return None,
+        _ => {}
+    }
+
+    let index = occurrences.len();
+    occurrences.push(Occurrence {
+        node: ast,
+        key: None,
+        cost: 0,
+        end: 0,
+    });
+    let lhs = ast
+        .lhs()
+        .and_then(|lhs| collect(lhs, hoisted, width, occurrences));
+    let rhs = ast
+        .rhs()
+        .map(|rhs| collect(rhs, hoisted, width, occurrences));
+    occurrences[index].end = occurrences.len();
+
+    let (lhs_key, lhs_cost) = lhs?;
+    let (key, cost) = match (ast.kind(), rhs) {
+        (NodeKind::Rand, _) => return None,
+        (_, None) => (format!("({:?} {})", ast.kind(), lhs_key), lhs_cost + 1),
+        (_, Some(rhs)) => {
+            let (rhs_key, rhs_cost) = rhs?;
+            (
+                format!("({:?} {} {})", ast.kind(), lhs_key, rhs_key),
+                lhs_cost + rhs_cost + 1,
+            )
+        }
+    };
+    occurrences[index].key = Some(key.clone());
+    occurrences[index].cost = cost;
+    Some((key, cost))
+}
+
+/// 1 つの式の中で 2 回以上計算される部分式を探す。
+/// 見つけた部分式ごとに、最初に計算する出現と、一時変数から読み直す残りの出現を返す。
+/// 式の中では代入が起きないので、変数を含む式も同じ値になる。
+pub(crate) fn common_subexpressions<'a>(
+    ast: &'a ASTNode,
+    hoisted: &HashMap<*const ASTNode, u32>,
+    width: Width,
+) -> Vec<(&'a ASTNode, Vec<&'a ASTNode>)> {
+    let mut occurrences = Vec::new();
+    collect(ast, hoisted, width, &mut occurrences);
+
+    let mut groups: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, occurrence) in occurrences.iter().enumerate() {
+        if let Some(key) = &occurrence.key {
+            groups.entry(key).or_default().push(index);
+        }
+    }
+    // 外側の式を先に決める。外側を読み直すと、その中の出現は計算しなくなる。
+    let mut groups: Vec<Vec<usize>> = groups.into_values().collect();
+    groups.sort_by_key(|group| (std::cmp::Reverse(occurrences[group[0]].cost), group[0]));
+
+    let mut skipped = vec![false; occurrences.len()];
+    let mut common = Vec::new();
+    for group in groups {
+        let live: Vec<usize> = group.into_iter().filter(|&i| !skipped[i]).collect();
+        if live.len() < 2 {
+            continue;
+        }
+        // 最初の出現の後に store と fetch の 2 命令が増え、残りの出現は fetch の 1 命令になる
+        if (live.len() - 1) * (occurrences[live[0]].cost - 1) <= 2 {
+            continue;
+        }
+        for &i in &live[1..] {
+            skipped[i + 1..occurrences[i].end]
+                .iter_mut()
+                .for_each(|s| *s = true);
+        }
+        common.push((
+            occurrences[live[0]].node,
+            live[1..].iter().map(|&i| occurrences[i].node).collect(),
+        ));
+    }
+    common
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(src: &str) -> Vec<(usize, usize)> {
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let expression = ast.sequence_items()[0].rhs().unwrap();
+        let hoisted = HashMap::new();
+        common_subexpressions(expression, &hoisted, Width::default())
+            .iter()
+            .map(|(first, reuses)| {
+                let mut occurrences = Vec::new();
+                let (_, cost) =
+                    collect(first, &hoisted, Width::default(), &mut occurrences).unwrap();
+                (cost, reuses.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        // i * i + 1 を 1 回だけ計算する。中の i * i は数えない。
+        assert_eq!(vec![(5, 1)], count("x = (i * i + 1) * (i * i + 1);"));
+        // 2 回だけの i * i は命令が減らない
+        assert!(count("x = i * i + i * i;").is_empty());
+        assert_eq!(vec![(3, 2)], count("x = i * i + i * i + i * i;"));
+        // 畳み込む定数式は 1 命令
+        assert!(count("x = (2 * 3 + i) + (2 * 3 + i);").is_empty());
+        // rand と clock は呼ぶたびに値が変わる
+        assert!(count("x = (rand(6) * 2 + 1) + (rand(6) * 2 + 1);").is_empty());
+        assert!(count("x = (clock() * 2 + 1) + (clock() * 2 + 1);").is_empty());
+        // rand の引数の中は共通にできる
+        assert_eq!(vec![(5, 1)], count("x = rand(i * j + k) + (i * j + k);"));
+    }
+}
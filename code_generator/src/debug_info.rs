@@ -0,0 +1,56 @@
+use super::SymbolTable;
+
+/// 生成したコードとソースの対応。デバッガで使う。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// 文の最初の命令のアドレスと、その文が始まるソースの行 (アドレス順)
+    pub lines: Vec<(usize, usize)>,
+    pub symbols: SymbolTable,
+}
+
+impl DebugInfo {
+    /// address の命令を含む文の行
+    pub fn line_at(&self, address: usize) -> Option<usize> {
+        let end = self.lines.partition_point(|&(a, _)| a <= address);
+        end.checked_sub(1).map(|i| self.lines[i].1)
+    }
+
+    /// line から始まる文の最初の命令のアドレス。ループの条件などで複数になることがある。
+    pub fn addresses_of(&self, line: usize) -> Vec<usize> {
+        self.lines
+            .iter()
+            .filter(|&&(_, l)| l == line)
+            .map(|&(address, _)| address)
+            .collect()
+    }
+
+    /// address が文の最初の命令なら true
+    pub fn is_statement_start(&self, address: usize) -> bool {
+        self.lines
+            .binary_search_by_key(&address, |&(a, _)| a)
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_info() {
+        let info = DebugInfo {
+            lines: vec![(0, 1), (10, 2), (26, 3), (44, 2)],
+            symbols: SymbolTable::default(),
+        };
+        assert_eq!(Some(1), info.line_at(5));
+        assert_eq!(Some(2), info.line_at(10));
+        assert_eq!(Some(3), info.line_at(43));
+        assert_eq!(Some(2), info.line_at(100));
+        assert_eq!(vec![10, 44], info.addresses_of(2));
+        assert!(info.addresses_of(4).is_empty());
+        assert!(info.is_statement_start(26));
+        assert!(!info.is_statement_start(27));
+
+        assert_eq!(None, DebugInfo::default().line_at(0));
+    }
+}
@@ -0,0 +1,580 @@
+use super::DEFAULT_MAX_DEPTH;
+use isa::random::{DEFAULT_SEED, RAND_MAX};
+use lexical_analyzer::error::*;
+use lexical_analyzer::symbol::Symbol;
+use lexical_analyzer::width::Width;
+use syntax_analyzer::ast_node::*;
+use syntax_analyzer::typed::{self, Type};
+
+use std::collections::{HashMap, HashSet};
+
+/// AST と同じ動作をする C のソースを生成する。
+/// 生成したソースは C コンパイラでネイティブの実行ファイルにできる。
+pub fn emit_c(ast: &ASTNode) -> Result<String> {
+    let mut emitter = CEmitter {
+        variables: Vec::new(),
+        types: typed::variable_types(ast),
+        declared: HashSet::new(),
+        body: String::new(),
+        indent: 1,
+        depth: 0,
+        uses_rand: false,
+        uses_clock: false,
+        uses_geti: false,
+        uses_exit: false,
+        uses_pow: false,
+    };
+    emitter.emit_stmt(ast)?;
+
+    let mut code = String::from("#include <stdio.h>\n");
+    if emitter.uses_rand || emitter.uses_exit || emitter.uses_pow || emitter.uses_geti {
+        code += "#include <stdlib.h>\n";
+    }
+    if emitter.uses_clock {
+        code += "#include <time.h>\n";
+    }
+    code += "\n";
+    for variable in &emitter.variables {
+        match emitter.types.get(variable) {
+            Some(Type::Str) => {
+                code += &format!("static const char *{} = \"\";\n", variable_name(*variable))
+            }
+            _ => code += &format!("static int {};\n", variable_name(*variable)),
+        }
+    }
+    if !emitter.variables.is_empty() {
+        code += "\n";
+    }
+    if emitter.uses_rand {
+        code += &rand_function();
+    }
+    if emitter.uses_clock {
+        code += CLOCK_FUNCTION;
+    }
+    if emitter.uses_pow {
+        code += POW_FUNCTION;
+    }
+    if emitter.uses_geti {
+        code += GETI_FUNCTION;
+    }
+    code += "int main(void) {\n";
+    code += &emitter.body;
+    code += "    return 0;\n}\n";
+    Ok(code)
+}
+
+/// C の予約語やライブラリの名前と衝突しないように接頭辞を付ける。
+/// ASCII 以外の文字は C99 の国際文字名 (\uXXXX) にする。
+fn variable_name(identifier: Symbol) -> String {
+    let mut name = String::from("v_");
+    for c in identifier.text().chars() {
+        match c as u32 {
+            0..=0x7f => name.push(c),
+            n @ 0x80..=0xffff => name += &format!("\\u{:04x}", n),
+            n => name += &format!("\\U{:08x}", n),
+        }
+    }
+    name
+}
+
+/// C の文字列リテラルにする。ASCII の印字可能文字以外は 8 進数でエスケープする。
+fn string_literal(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' => literal += "\\\"",
+            b'\\' => literal += "\\\\",
+            b'\n' => literal += "\\n",
+            b' '..=b'~' => literal.push(b as char),
+            _ => literal += &format!("\\{:03o}", b),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// インタプリタや仮想マシンと同じ列を返す rand。
+/// ただし 1 つの式の中で何度も呼ぶと、 C では呼ぶ順序が決まらない。
+fn rand_function() -> String {
+    format!(
+        r#"static unsigned rand_state = {}u;
+
+static int tiny_rand(int n) {{
+    if (n <= 0) {{
+        fprintf(stderr, "rand: %d is not positive\n", n);
+        exit(1);
+    }}
+    rand_state = rand_state * 1103515245u + 12345u;
+    return (int)((rand_state >> 16) & {}) % n;
+}}
+
+"#,
+        DEFAULT_SEED,
+        RAND_MAX - 1
+    )
+}
+
+/// 実行を始めてからのミリ秒。 C ではプロセスが使った CPU 時間で代用する。
+const CLOCK_FUNCTION: &str = r#"static int tiny_clock(void) {
+    return (int)((long long)clock() * 1000 / CLOCKS_PER_SEC);
+}
+
+"#;
+
+/// インタプリタや仮想マシンと同じく、 32 ビットで折り返すべき乗。
+/// 負の指数では 0 の方向に丸め、 0 の負の乗は 0 除算のエラーにする。
+const POW_FUNCTION: &str = r#"static int tiny_pow(int a, int b) {
+    unsigned result = 1u, base = (unsigned)a;
+    if (b < 0) {
+        if (a == 0) {
+            fflush(stdout);
+            fprintf(stderr, "division by zero\n");
+            exit(1);
+        }
+        return a == 1 ? 1 : a == -1 ? ((b & 1) ? -1 : 1) : 0;
+    }
+    while (b > 0) {
+        if (b & 1) {
+            result *= base;
+        }
+        base *= base;
+        b >>= 1;
+    }
+    return (int)result;
+}
+
+"#;
+
+/// 空白を読み飛ばして整数を読む。インタプリタや仮想マシンと同じく、整数がなければエラーにする。
+const GETI_FUNCTION: &str = r#"static int tiny_geti(void) {
+    int value;
+    if (scanf("%d", &value) != 1) {
+        fflush(stdout);
+        fprintf(stderr, "expected an integer\n");
+        exit(1);
+    }
+    return value;
+}
+
+"#;
+
+struct CEmitter {
+    /// 宣言する変数 (最初に代入された順)
+    variables: Vec<Symbol>,
+    /// 変数の型。文字列を入れる変数は const char * で宣言する。
+    types: HashMap<Symbol, Type>,
+    declared: HashSet<Symbol>,
+    body: String,
+    indent: usize,
+    depth: usize,
+    /// rand を使っていれば true
+    uses_rand: bool,
+    /// clock を使っていれば true
+    uses_clock: bool,
+    /// geti を使っていれば true
+    uses_geti: bool,
+    /// exit を使っていれば true
+    uses_exit: bool,
+    /// ** を使っていれば true
+    uses_pow: bool,
+}
+
+impl CEmitter {
+    fn line(&mut self, s: &str) {
+        for _ in 0..self.indent {
+            self.body += "    ";
+        }
+        self.body += s;
+        self.body += "\n";
+    }
+
+    /// 入れ子を一段深くして f を呼ぶ。
+    fn nested<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if self.depth >= DEFAULT_MAX_DEPTH {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                format!("AST is too deep (limit: {})", DEFAULT_MAX_DEPTH),
+            ));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// 文を書く。エラーには文の行を付ける。
+    fn emit_stmt(&mut self, ast: &ASTNode) -> Result<()> {
+        self.nested(|emitter| emitter.emit_stmt_body(ast))
+            .map_err(|e| ast.locate(e))
+    }
+
+    fn emit_stmt_body(&mut self, ast: &ASTNode) -> Result<()> {
+        match ast.kind() {
+            NodeKind::Sequence => {
+                for item in ast.sequence_items() {
+                    self.emit_stmt(item)?;
+                }
+            }
+            NodeKind::If => {
+                let condition = self.emit_expr(ast.lhs().unwrap())?;
+                let body = ast.rhs().unwrap();
+                self.line(&format!("if ({}) {{", condition));
+                self.emit_block(body.lhs())?;
+                if let Some(else_clause) = body.rhs() {
+                    self.line("} else {");
+                    self.emit_block(Some(else_clause))?;
+                }
+                self.line("}");
+            }
+            NodeKind::While => {
+                let condition = self.emit_expr(ast.lhs().unwrap())?;
+                self.line(&format!("while ({}) {{", condition));
+                self.emit_block(ast.rhs())?;
+                self.line("}");
+            }
+            NodeKind::DoWhile => {
+                self.line("do {");
+                self.emit_block(ast.rhs())?;
+                let condition = self.emit_expr(ast.lhs().unwrap())?;
+                self.line(&format!("}} while ({});", condition));
+            }
+            NodeKind::Assign => {
+                let value = self.emit_expr(ast.rhs().unwrap())?;
+                match *ast.lhs().unwrap().kind() {
+                    NodeKind::Identifier(identifier) => {
+                        if self.declared.insert(identifier) {
+                            self.variables.push(identifier);
+                        }
+                        self.line(&format!("{} = {};", variable_name(identifier), value));
+                    }
+                    _ => {
+                        return Err(CompileError::new(
+                            ErrorKind::CodeGenerationError,
+                            "identifier is expected",
+                        ))
+                    }
+                }
+            }
+            NodeKind::Prtc => {
+                let value = self.emit_expr(ast.lhs().unwrap())?;
+                self.line(&format!("putchar({});", value));
+            }
+            NodeKind::Prti => {
+                let value = self.emit_expr(ast.lhs().unwrap())?;
+                self.line(&format!("printf(\"%d\", {});", value));
+            }
+            NodeKind::Assert => {
+                let condition = self.emit_expr(ast.lhs().unwrap())?;
+                self.uses_exit = true;
+                self.line(&format!("if (!{}) {{", condition));
+                self.indent += 1;
+                self.line("fflush(stdout);");
+                self.line(&format!(
+                    "fprintf(stderr, \"%s\\n\", {});",
+                    string_literal(&ast.assertion_message())
+                ));
+                self.line("exit(1);");
+                self.indent -= 1;
+                self.line("}");
+            }
+            NodeKind::Exit => {
+                let value = self.emit_expr(ast.lhs().unwrap())?;
+                self.uses_exit = true;
+                self.line(&format!("exit({});", value));
+            }
+            NodeKind::Prts => {
+                let value = self.emit_expr(ast.lhs().unwrap())?;
+                self.line(&format!("fputs({}, stdout);", value));
+            }
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    "unknown instruction",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_block(&mut self, ast: Option<&ASTNode>) -> Result<()> {
+        self.indent += 1;
+        let result = match ast {
+            Some(ast) => self.emit_stmt(ast),
+            None => Ok(()),
+        };
+        self.indent -= 1;
+        result
+    }
+
+    fn emit_expr(&mut self, ast: &ASTNode) -> Result<String> {
+        self.nested(|emitter| emitter.emit_expr_body(ast))
+    }
+
+    fn emit_expr_body(&mut self, ast: &ASTNode) -> Result<String> {
+        let op = match ast.kind() {
+            NodeKind::Identifier(identifier) => {
+                if !self.declared.contains(identifier) {
+                    return Err(CompileError::new(
+                        ErrorKind::CodeGenerationError,
+                        format!("unknown identifier: {}", identifier),
+                    ));
+                }
+                return Ok(variable_name(*identifier));
+            }
+            NodeKind::String(s) => return Ok(string_literal(&s.text())),
+            NodeKind::Integer(value) if !Width::W32.contains(*value) => {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    "64-bit integers are not supported by the C backend",
+                ))
+            }
+            NodeKind::Integer(value) => {
+                // -2147483648 は C では int の範囲外の定数の符号反転になる
+                return Ok(if *value == i32::MIN as i64 {
+                    "(-2147483647 - 1)".to_string()
+                } else if *value < 0 {
+                    format!("({})", value)
+                } else {
+                    format!("{}", value)
+                });
+            }
+            NodeKind::Float(_) | NodeKind::ToInt | NodeKind::ToFloat => {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    "floating-point numbers are not supported by the C backend",
+                ))
+            }
+            NodeKind::Negate => {
+                return Ok(format!("(-{})", self.emit_expr(ast.lhs().unwrap())?));
+            }
+            NodeKind::Not => {
+                return Ok(format!("(!{})", self.emit_expr(ast.lhs().unwrap())?));
+            }
+            NodeKind::Rand => {
+                self.uses_rand = true;
+                return Ok(format!(
+                    "tiny_rand({})",
+                    self.emit_expr(ast.lhs().unwrap())?
+                ));
+            }
+            NodeKind::Clock => {
+                self.uses_clock = true;
+                return Ok("tiny_clock()".to_string());
+            }
+            // getchar も入力の終わりでは -1 (EOF) を返す
+            NodeKind::Getc => return Ok("getchar()".to_string()),
+            NodeKind::Geti => {
+                self.uses_geti = true;
+                return Ok("tiny_geti()".to_string());
+            }
+            NodeKind::Power => {
+                self.uses_pow = true;
+                let lhs = self.emit_expr(ast.lhs().unwrap())?;
+                let rhs = self.emit_expr(ast.rhs().unwrap())?;
+                return Ok(format!("tiny_pow({}, {})", lhs, rhs));
+            }
+            NodeKind::ShiftLeft | NodeKind::ShiftRight => {
+                // 負の値の左シフトと 32 以上のシフトは C では未定義なので、
+                // VM と同じくシフト量の下位 5 ビットを使い、左シフトは unsigned で行う
+                let lhs = self.emit_expr(ast.lhs().unwrap())?;
+                let rhs = self.emit_expr(ast.rhs().unwrap())?;
+                return Ok(match ast.kind() {
+                    NodeKind::ShiftLeft => format!("((int)((unsigned){} << ({} & 31)))", lhs, rhs),
+                    _ => format!("({} >> ({} & 31))", lhs, rhs),
+                });
+            }
+            NodeKind::Multiply => "*",
+            NodeKind::Divide => "/",
+            NodeKind::Mod => "%",
+            NodeKind::Add => "+",
+            NodeKind::Subtract => "-",
+            NodeKind::Less => "<",
+            NodeKind::LessEqual => "<=",
+            NodeKind::Greater => ">",
+            NodeKind::GreaterEqual => ">=",
+            NodeKind::Equal => "==",
+            NodeKind::NotEqual => "!=",
+            NodeKind::And => "&&",
+            NodeKind::Or => "||",
+            NodeKind::BitAnd => "&",
+            NodeKind::BitOr => "|",
+            NodeKind::BitXor => "^",
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    "invalid expression",
+                ))
+            }
+        };
+        let lhs = self.emit_expr(ast.lhs().unwrap())?;
+        let rhs = self.emit_expr(ast.rhs().unwrap())?;
+        Ok(format!("({} {} {})", lhs, op, rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_string_literal() {
+        assert_eq!(r#""a\"b\\c\n""#, string_literal("a\"b\\c\n"));
+        assert_eq!(r#""\303\251""#, string_literal("é"));
+    }
+
+    #[test]
+    fn test_variable_name() {
+        assert_eq!("v_count", variable_name(Symbol::intern("count")));
+        assert_eq!(
+            r"v_caf\u00e9_\u5909\U0002000b",
+            variable_name(Symbol::intern("café_変\u{2000b}"))
+        );
+    }
+
+    #[test]
+    fn test_emit_c() {
+        let ast = syntax_analyzer::parse_str(
+            r#"count = 1; while (count < 4) { print(count, "\n"); count = count + 1; } if (!count) putc('x'); else putc('y');"#,
+        )
+        .unwrap();
+        assert_eq!(
+            r#"#include <stdio.h>
+
+static int v_count;
+
+int main(void) {
+    v_count = 1;
+    while ((v_count < 4)) {
+        printf("%d", v_count);
+        fputs("\n", stdout);
+        v_count = (v_count + 1);
+    }
+    if ((!v_count)) {
+        putchar(120);
+    } else {
+        putchar(121);
+    }
+    return 0;
+}
+"#,
+            emit_c(&ast).unwrap()
+        );
+
+        let ast = syntax_analyzer::parse_str("do x = 1; while (!x);").unwrap();
+        assert_eq!(
+            r#"#include <stdio.h>
+
+static int v_x;
+
+int main(void) {
+    do {
+        v_x = 1;
+    } while ((!v_x));
+    return 0;
+}
+"#,
+            emit_c(&ast).unwrap()
+        );
+
+        let ast = syntax_analyzer::parse_str("x = 1 << 2 >> 1 & 3 | 4 ^ 5;").unwrap();
+        assert!(emit_c(&ast)
+            .unwrap()
+            .contains("v_x = (((((int)((unsigned)1 << (2 & 31))) >> (1 & 31)) & 3) | (4 ^ 5));"));
+
+        let ast = syntax_analyzer::parse_str("exit(2);").unwrap();
+        let code = emit_c(&ast).unwrap();
+        assert!(code.starts_with("#include <stdio.h>\n#include <stdlib.h>\n"));
+        assert!(code.contains("    exit(2);\n"));
+
+        let ast = syntax_analyzer::parse_str("x = 1;\nassert(x, \"x\");").unwrap();
+        assert!(emit_c(&ast).unwrap().contains(
+            r#"    if (!v_x) {
+        fflush(stdout);
+        fprintf(stderr, "%s\n", "assertion failed at line 2: x");
+        exit(1);
+    }
+"#
+        ));
+
+        let ast = syntax_analyzer::parse_str(r#"s = "a"; t = s; print(t);"#).unwrap();
+        let code = emit_c(&ast).unwrap();
+        assert!(code.contains("static const char *v_s = \"\";\nstatic const char *v_t = \"\";\n"));
+        assert!(code.contains("    v_s = \"a\";\n    v_t = v_s;\n    fputs(v_t, stdout);\n"));
+
+        let ast = syntax_analyzer::parse_str("print(x);").unwrap();
+        assert!(emit_c(&ast).is_err());
+    }
+
+    /// 落とすときに中身ごと消す一時ディレクトリ。テストが途中で失敗しても残さない。
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// C コンパイラがあれば、コンパイルして実行結果を確かめる
+    #[test]
+    fn test_compile_and_run() {
+        let ast = syntax_analyzer::parse_str(
+            r#"
+/* greatest common divisor */
+a = 1071; b = 1029;
+while (b != 0) { new_a = b; b = a % b; a = new_a; }
+print("gcd: ", a, "\n");
+print(-16 >> 2, " ", 1 << 33, " ", (a ^ 5) & 12 | 1, "\n");
+print(3 ** a, " ", -2 ** 3, " ", 2 ** -1, "\n");
+print(rand(100), " ", rand(100), "\n");
+print(clock() >= 0, "\n");
+greeting = "hello"; print(greeting, "\n");
+print(geti() + geti(), " ", getc(), " ", getc(), " ", getc(), "\n");
+"#,
+        )
+        .unwrap();
+        let code = emit_c(&ast).unwrap();
+
+        let dir = TempDir::new("emit_c_test");
+        let source = dir.0.join("main.c");
+        let executable = dir.0.join("main");
+        fs::write(&source, code).unwrap();
+
+        let compiled = Command::new("cc")
+            .arg("-o")
+            .arg(&executable)
+            .arg(&source)
+            .status();
+        if !compiled.map(|status| status.success()).unwrap_or(false) {
+            // C コンパイラが使えない環境では確かめない
+            return;
+        }
+        let mut child = Command::new(&executable)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"2 3\nx").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(
+            b"gcd: 21\n-4 2 1\n1870418611 -8 0\n38 58\n1\nhello\n5 10 120 -1\n",
+            &output.stdout[..]
+        );
+    }
+}
@@ -1,10 +1,17 @@
+use lexical_analyzer::float::Float;
+use std::convert::TryFrom;
 use std::fmt;
 
 #[derive(Debug)]
 pub enum InstructionKind {
     Fetch(u32),
     Store(u32),
-    Push(i32),
+    /// i32 に収まらない値は pushw になり、 8 バイトのオペランドを持つ
+    Push(i64),
+    /// 文字列プールの番号を、文字列の値として積む
+    PushS(i32),
+    /// 浮動小数点数を f32 のビット列として積む
+    PushF(Float),
     Jump(i32),
     Jz(i32),
     Add,
@@ -20,12 +27,135 @@ pub enum InstructionKind {
     Ne,
     And,
     Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Pow,
     Neg,
     Not,
+    Rand,
+    Clock,
+    /// 入力から 1 バイト読んで積む
+    Getc,
+    /// 入力から整数を読んで積む
+    Geti,
     Prtc,
     Prti,
     Prts,
     Halt,
+    /// スタックトップを終了コードにして止まる
+    Exit,
+    /// 2 番目の値が 0 なら、スタックトップの番号の文字列をエラーにして止まる
+    Assert,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FNeg,
+    FLt,
+    FGt,
+    FLe,
+    FGe,
+    FEq,
+    FNe,
+    /// スタックトップの整数を浮動小数点数にする
+    Itof,
+    /// スタックトップの浮動小数点数を 0 の方向に丸めて整数にする
+    Ftoi,
+    Prtf,
+}
+
+impl InstructionKind {
+    /// 命令コード
+    pub fn opcode(&self) -> u8 {
+        match self {
+            InstructionKind::Fetch(_) => isa::FETCH,
+            InstructionKind::Store(_) => isa::STORE,
+            InstructionKind::Push(val) if !is_word(*val) => isa::PUSHW,
+            InstructionKind::Push(_) => isa::PUSH,
+            InstructionKind::PushS(_) => isa::PUSHS,
+            InstructionKind::PushF(_) => isa::PUSHF,
+            InstructionKind::Jump(_) => isa::JMP,
+            InstructionKind::Jz(_) => isa::JZ,
+            InstructionKind::Add => isa::ADD,
+            InstructionKind::Sub => isa::SUB,
+            InstructionKind::Mul => isa::MUL,
+            InstructionKind::Div => isa::DIV,
+            InstructionKind::Mod => isa::MOD,
+            InstructionKind::Lt => isa::LT,
+            InstructionKind::Gt => isa::GT,
+            InstructionKind::Le => isa::LE,
+            InstructionKind::Ge => isa::GE,
+            InstructionKind::Eq => isa::EQ,
+            InstructionKind::Ne => isa::NE,
+            InstructionKind::And => isa::AND,
+            InstructionKind::Or => isa::OR,
+            InstructionKind::BitAnd => isa::BAND,
+            InstructionKind::BitOr => isa::BOR,
+            InstructionKind::BitXor => isa::BXOR,
+            InstructionKind::Shl => isa::SHL,
+            InstructionKind::Shr => isa::SHR,
+            InstructionKind::Pow => isa::POW,
+            InstructionKind::Neg => isa::NEG,
+            InstructionKind::Not => isa::NOT,
+            InstructionKind::Rand => isa::RAND,
+            InstructionKind::Clock => isa::CLOCK,
+            InstructionKind::Getc => isa::GETC,
+            InstructionKind::Geti => isa::GETI,
+            InstructionKind::Prtc => isa::PRTC,
+            InstructionKind::Prti => isa::PRTI,
+            InstructionKind::Prts => isa::PRTS,
+            InstructionKind::Halt => isa::HALT,
+            InstructionKind::Exit => isa::EXIT,
+            InstructionKind::Assert => isa::ASSERT,
+            InstructionKind::FAdd => isa::FADD,
+            InstructionKind::FSub => isa::FSUB,
+            InstructionKind::FMul => isa::FMUL,
+            InstructionKind::FDiv => isa::FDIV,
+            InstructionKind::FNeg => isa::FNEG,
+            InstructionKind::FLt => isa::FLT,
+            InstructionKind::FGt => isa::FGT,
+            InstructionKind::FLe => isa::FLE,
+            InstructionKind::FGe => isa::FGE,
+            InstructionKind::FEq => isa::FEQ,
+            InstructionKind::FNe => isa::FNE,
+            InstructionKind::Itof => isa::ITOF,
+            InstructionKind::Ftoi => isa::FTOI,
+            InstructionKind::Prtf => isa::PRTF,
+        }
+    }
+
+    /// 命令のバイト数。オペランドを持つ命令は 4 バイトのオペランドが続く。
+    pub fn size(&self) -> u32 {
+        isa::instruction_size(self.opcode()) as u32
+    }
+
+    /// 命令が取り出す値と積む値の数
+    pub fn stack_effect(&self) -> (u32, u32) {
+        let (pops, pushes) = isa::stack_effect(self.opcode());
+        (pops as u32, pushes as u32)
+    }
+}
+
+/// val が push の 4 バイトのオペランドに収まるなら true
+fn is_word(val: i64) -> bool {
+    i32::try_from(val).is_ok()
+}
+
+/// ジャンプ先。アドレスは bind するまで決まらない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+impl Label {
+    pub fn new(id: usize) -> Self {
+        Label(id)
+    }
+
+    pub fn id(self) -> usize {
+        self.0
+    }
 }
 
 #[derive(Debug)]
@@ -42,43 +172,19 @@ impl Instruction {
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.address, isa::mnemonic(self.kind.opcode()))?;
         match self.kind {
-            InstructionKind::Fetch(val) => write!(f, "{} fetch [{}]", self.address, val),
-            InstructionKind::Store(val) => write!(f, "{} store [{}]", self.address, val),
-            InstructionKind::Push(val) => write!(f, "{} push {}", self.address, val),
-            InstructionKind::Jump(val) => write!(
-                f,
-                "{} jmp ({}) {}",
-                self.address,
-                val,
-                (self.address + 1).wrapping_add(val as u32)
-            ),
-            InstructionKind::Jz(val) => write!(
+            InstructionKind::Fetch(val) | InstructionKind::Store(val) => write!(f, " [{}]", val),
+            InstructionKind::Push(val) => write!(f, " {}", val),
+            InstructionKind::PushS(val) => write!(f, " {}", val),
+            InstructionKind::PushF(val) => write!(f, " {}", val),
+            InstructionKind::Jump(val) | InstructionKind::Jz(val) => write!(
                 f,
-                "{} jz ({}) {}",
-                self.address,
+                " ({}) {}",
                 val,
                 (self.address + 1).wrapping_add(val as u32)
             ),
-            InstructionKind::Add => write!(f, "{} add", self.address),
-            InstructionKind::Sub => write!(f, "{} sub", self.address),
-            InstructionKind::Mul => write!(f, "{} mul", self.address),
-            InstructionKind::Div => write!(f, "{} div", self.address),
-            InstructionKind::Mod => write!(f, "{} mod", self.address),
-            InstructionKind::Lt => write!(f, "{} lt", self.address),
-            InstructionKind::Gt => write!(f, "{} gt", self.address),
-            InstructionKind::Le => write!(f, "{} le", self.address),
-            InstructionKind::Ge => write!(f, "{} ge", self.address),
-            InstructionKind::Eq => write!(f, "{} eq", self.address),
-            InstructionKind::Ne => write!(f, "{} ne", self.address),
-            InstructionKind::And => write!(f, "{} and", self.address),
-            InstructionKind::Or => write!(f, "{} or", self.address),
-            InstructionKind::Neg => write!(f, "{} neg", self.address),
-            InstructionKind::Not => write!(f, "{} not", self.address),
-            InstructionKind::Prtc => write!(f, "{} prtc", self.address),
-            InstructionKind::Prti => write!(f, "{} prti", self.address),
-            InstructionKind::Prts => write!(f, "{} prts", self.address),
-            InstructionKind::Halt => write!(f, "{} halt", self.address),
+            _ => Ok(()),
         }
     }
 }
@@ -0,0 +1,118 @@
+use super::instruction::{Instruction, InstructionKind, Label};
+use super::CodeGenerator;
+
+use std::collections::HashMap;
+
+impl CodeGenerator {
+    /// ジャンプ先を解決する前に、ジャンプ命令を整理する。
+    ///
+    /// - jmp や jz の先が jmp なら、その jmp の行き先に直接ジャンプする
+    /// - 次の命令に進むだけの jmp を取り除く
+    ///
+    /// else 節が空の if や、入れ子の if の終わりで生じる。
+    /// ここでは call を生成しないので、自分自身の末尾呼び出しは仮想マシンが命令列に変換するときに扱う。
+    pub(crate) fn fuse_jumps(&mut self) {
+        let len = self.instructions.len();
+        // Label の位置を命令の添字で表す。最後の命令の後なら len
+        let index_of: HashMap<u32, usize> = self
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| (instruction.address, index))
+            .chain(std::iter::once((self.pc, len)))
+            .collect();
+        let targets: Vec<Option<usize>> = self
+            .labels
+            .iter()
+            .map(|address| address.map(|address| index_of[&address]))
+            .collect();
+        // jmp 命令の添字と、その行き先
+        let jumps: HashMap<usize, Label> = self
+            .fixups
+            .iter()
+            .copied()
+            .filter(|&(index, _)| matches!(self.instructions[index].kind, InstructionKind::Jump(_)))
+            .collect();
+
+        // ジャンプの連鎖をたどる。無限ループの jmp で止まらないよう、たどる回数を制限する
+        for (_, label) in self.fixups.iter_mut() {
+            for _ in 0..jumps.len() {
+                let next = targets[label.id()].and_then(|target| jumps.get(&target));
+                match next {
+                    Some(&next) if next != *label => *label = next,
+                    _ => break,
+                }
+            }
+        }
+
+        let jumps: HashMap<usize, Label> = self
+            .fixups
+            .iter()
+            .copied()
+            .filter(|(index, _)| jumps.contains_key(index))
+            .collect();
+
+        // 取り除いた命令の位置は、その後で最初に残る命令の位置と同じ
+        let mut removed = vec![false; len + 1];
+        let canonical = |removed: &[bool], mut index: usize| {
+            while removed[index] {
+                index += 1;
+            }
+            index
+        };
+        loop {
+            let mut changed = false;
+            for (&index, &label) in &jumps {
+                if removed[index] {
+                    continue;
+                }
+                if let Some(target) = targets[label.id()] {
+                    if canonical(&removed, target) == canonical(&removed, index + 1) {
+                        removed[index] = true;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        if !removed.contains(&true) {
+            return;
+        }
+
+        // 残った命令を詰め、アドレスと添字を付け直す
+        let mut new_index = vec![0; len + 1];
+        let mut instructions = Vec::new();
+        let mut pc = 0;
+        for (index, instruction) in std::mem::take(&mut self.instructions)
+            .into_iter()
+            .enumerate()
+        {
+            new_index[index] = instructions.len();
+            if !removed[index] {
+                let size = instruction.kind.size();
+                instructions.push(Instruction::new(instruction.kind, pc));
+                pc += size;
+            }
+        }
+        new_index[len] = instructions.len();
+        let address_of = |index: usize| instructions.get(index).map_or(pc, |i| i.address);
+
+        self.labels = targets
+            .iter()
+            .map(|target| target.map(|t| address_of(new_index[canonical(&removed, t)])))
+            .collect();
+        self.fixups = self
+            .fixups
+            .iter()
+            .filter(|&&(index, _)| !removed[index])
+            .map(|&(index, label)| (new_index[index], label))
+            .collect();
+        for mark in self.line_marks.iter_mut() {
+            mark.0 = new_index[canonical(&removed, mark.0)];
+        }
+        self.instructions = instructions;
+        self.pc = pc;
+    }
+}
@@ -3,68 +3,358 @@ use std::string::ToString;
 
 use instruction::*;
 use lexical_analyzer::error::*;
+use lexical_analyzer::symbol::Symbol;
+use lexical_analyzer::width::Width;
 use syntax_analyzer::ast_node::*;
+use syntax_analyzer::typed::{self, Type};
 
+mod cse;
+mod debug_info;
+pub mod emit_c;
 mod instruction;
+mod jumps;
+mod loop_invariant;
+mod symbol_table;
+
+pub use debug_info::DebugInfo;
+pub use symbol_table::{SymbolTable, SYMBOLS_HEADER};
+
+/// AST の深さの既定の上限
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct GeneratorOptions {
+    /// 定数式を畳み込み、式の中で 2 回以上計算する部分式を 1 回の計算にまとめる。
+    /// while ループの中で値の変わらない式はループの前で計算する。
+    /// ジャンプ先の jmp を飛ばし、次の命令に進むだけの jmp を取り除く。
+    pub optimize: bool,
+    /// AST の深さの上限。これを超えるとスタックを使い切る前にエラーにする。
+    pub max_depth: usize,
+    /// 出力の最後に変数名の表をコメントとして付ける
+    pub symbols: bool,
+    /// ヘッダに必要なスタックの深さを "Stack: 3" のように書く
+    pub stack_size: bool,
+    /// 整数の値の幅。 64 ビットならヘッダに "Width: 64" と書き、
+    /// i32 に収まらない値は pushw で積む。
+    pub width: Width,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        GeneratorOptions {
+            optimize: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            symbols: false,
+            stack_size: false,
+            width: Width::default(),
+        }
+    }
+}
+
+/// 共通部分式の出現の扱い
+#[derive(Debug, Clone, Copy)]
+enum Common {
+    /// 計算した値を一時変数に保存する
+    Save(u32),
+    /// 一時変数から読み直す
+    Reuse(u32),
+}
 
-pub struct CodeGenerator<'a> {
-    data_addr: HashMap<&'a str, u32>,
-    string_pool: Vec<&'a str>,
+pub struct CodeGenerator {
+    data_addr: HashMap<Symbol, u32>,
+    /// 変数と一時変数を合わせた data の大きさ
+    data_size: u32,
+    /// 共通部分式の値を置く一時変数の data の添字。文をまたいで使い回す。
+    temporaries: Vec<u32>,
+    /// 生成中の式の共通部分式。ノードのアドレスで引く。
+    common: HashMap<*const ASTNode, Common>,
+    /// ループの前で計算した式の値を置く一時変数の data の添字と、そのうち使用中の数
+    invariant_temporaries: Vec<u32>,
+    invariants_in_use: usize,
+    /// 生成中のループの前で計算した式と、その値を置いた一時変数
+    hoisted: HashMap<*const ASTNode, u32>,
+    string_pool: Vec<Symbol>,
     pc: u32,
     instructions: Vec<Instruction>,
+    /// Label ごとの bind されたアドレス
+    labels: Vec<Option<u32>>,
+    /// 解決待ちのジャンプ命令の位置とジャンプ先
+    fixups: Vec<(usize, Label)>,
+    optimize: bool,
+    depth: usize,
+    max_depth: usize,
+    symbols: bool,
+    stack_size: bool,
+    /// 必要なスタックの深さ。ジャンプ先を解決した後に求める。
+    max_stack_depth: u32,
+    /// 文の最初の命令の位置と、その文が始まるソースの行
+    line_marks: Vec<(usize, usize)>,
+    /// 式のノードの型。ノードのアドレスで引き、浮動小数点数の命令を選ぶのに使う。
+    types: HashMap<*const ASTNode, Type>,
+    width: Width,
 }
 
-impl<'a> CodeGenerator<'a> {
-    pub fn generate(ast: &'a ASTNode) -> Result<String> {
-        let mut generator = CodeGenerator {
+impl CodeGenerator {
+    pub fn generate(ast: &ASTNode) -> Result<String> {
+        Self::generate_with(ast, &GeneratorOptions::default())
+    }
+
+    fn new(options: &GeneratorOptions) -> Self {
+        CodeGenerator {
             data_addr: HashMap::new(),
+            data_size: 0,
+            temporaries: Vec::new(),
+            common: HashMap::new(),
+            invariant_temporaries: Vec::new(),
+            invariants_in_use: 0,
+            hoisted: HashMap::new(),
             string_pool: Vec::new(),
             pc: 0,
             instructions: Vec::new(),
-        };
+            labels: Vec::new(),
+            fixups: Vec::new(),
+            optimize: options.optimize,
+            depth: 0,
+            max_depth: options.max_depth,
+            symbols: options.symbols,
+            stack_size: options.stack_size,
+            max_stack_depth: 0,
+            line_marks: Vec::new(),
+            types: HashMap::new(),
+            width: options.width,
+        }
+    }
 
-        generator.generate_body(ast)?;
+    pub fn generate_with(ast: &ASTNode, options: &GeneratorOptions) -> Result<String> {
+        let generator = Self::generate_code(ast, options)?;
+        Ok(generator.write_code(None))
+    }
 
-        generator
-            .instructions
-            .push(Instruction::new(InstructionKind::Halt, generator.pc));
+    /// generate_with と同じだが、各文の命令の前に
+    /// "; line 12: count = count + 1;" のようなソースの行のコメントを入れる。
+    /// ソースから解析した AST でなければ、行の情報がないのでコメントは入らない。
+    pub fn generate_listing(
+        ast: &ASTNode,
+        src: &str,
+        options: &GeneratorOptions,
+    ) -> Result<String> {
+        let generator = Self::generate_code(ast, options)?;
+        Ok(generator.write_code(Some(src)))
+    }
+
+    /// generate_with と同じだが、変数と文字列に割り当てた番号の表も返す。
+    pub fn generate_with_symbols(
+        ast: &ASTNode,
+        options: &GeneratorOptions,
+    ) -> Result<(String, SymbolTable)> {
+        let generator = Self::generate_code(ast, options)?;
+        Ok((generator.write_code(None), generator.symbol_table()))
+    }
+
+    /// generate_with と同じだが、デバッガのためのソースとの対応も返す。
+    pub fn generate_debug(
+        ast: &ASTNode,
+        options: &GeneratorOptions,
+    ) -> Result<(String, DebugInfo)> {
+        let generator = Self::generate_code(ast, options)?;
+        let lines = generator
+            .line_marks
+            .iter()
+            .map(|&(index, line)| (generator.instructions[index].address as usize, line))
+            .collect();
+        let symbols = generator.symbol_table();
+        Ok((generator.write_code(None), DebugInfo { lines, symbols }))
+    }
+
+    fn symbol_table(&self) -> SymbolTable {
+        let mut variables: Vec<(String, usize)> = self
+            .data_addr
+            .iter()
+            .map(|(name, &index)| (name.to_string(), index as usize))
+            .collect();
+        variables.sort_by_key(|&(_, index)| index);
+        SymbolTable {
+            variables,
+            strings: self.string_pool.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn generate_code(ast: &ASTNode, options: &GeneratorOptions) -> Result<Self> {
+        let types = typed::check(ast)?.expression_types();
+        let mut generator = Self::new(options);
+        generator.types = types;
 
+        generator.generate_body(ast)?;
+        generator.emit(InstructionKind::Halt);
+        if generator.optimize {
+            generator.fuse_jumps();
+        }
+        generator.resolve_labels()?;
+        generator.max_stack_depth = generator.stack_depth()?;
+        Ok(generator)
+    }
+
+    /// アセンブリを書き出す。src があれば行のコメントを入れる。
+    fn write_code(&self, src: Option<&str>) -> String {
         let mut code = format!(
-            "Datasize: {} Strings: {}\n",
-            generator.data_addr.len(),
-            generator.string_pool.len()
+            "Datasize: {} Strings: {}",
+            self.data_size,
+            self.string_pool.len()
         );
-        if generator.string_pool.len() > 0 {
-            code += &generator
+        if self.stack_size {
+            code += &format!(" Stack: {}", self.max_stack_depth);
+        }
+        if self.width != Width::default() {
+            code += &format!(" Width: {}", self.width);
+        }
+        code += "\n";
+        if !self.string_pool.is_empty() {
+            code += &self
                 .string_pool
                 .iter()
-                .map(|s| format!("{:?}", s))
+                .map(|s| escape::quote(&s.text()))
                 .collect::<Vec<String>>()
                 .join("\n");
             code += "\n";
         }
-        code += &generator
-            .instructions
-            .iter()
-            .map(|i| i.to_string())
-            .collect::<Vec<String>>()
-            .join("\n");
 
-        Ok(code)
+        let source_lines: Vec<&str> = src.map_or_else(Vec::new, |src| src.lines().collect());
+        let mut marks = self.line_marks.iter().peekable();
+        let mut lines = Vec::new();
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            while let Some(&&(_, line)) = marks.peek().filter(|&&&(i, _)| i == index) {
+                if let Some(text) = line.checked_sub(1).and_then(|i| source_lines.get(i)) {
+                    lines.push(format!("; line {}: {}", line, text.trim()));
+                }
+                marks.next();
+            }
+            lines.push(instruction.to_string());
+        }
+        code += &lines.join("\n");
+        if self.symbols {
+            code += "\n";
+            code += &self.symbol_table().footer();
+        }
+        code
+    }
+
+    /// 次に出力する命令から line の文が始まることを記録する
+    fn mark_line(&mut self, line: usize) {
+        // 同じ行の文が続く場合 (print の引数など) は 1 つにまとめる
+        if self.line_marks.last().map(|&(_, l)| l) != Some(line) {
+            self.line_marks.push((self.instructions.len(), line));
+        }
+    }
+
+    fn generate_body(&mut self, ast: &ASTNode) -> Result<()> {
+        if self.depth >= self.max_depth {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                format!("AST is too deep (limit: {})", self.max_depth),
+            ));
+        }
+        self.depth += 1;
+        if let Some(line) = ast.line() {
+            self.mark_line(line);
+        }
+        let key = ast as *const ASTNode;
+        let result = match self.hoisted.get(&key).copied() {
+            Some(addr) => {
+                self.emit(InstructionKind::Fetch(addr));
+                Ok(())
+            }
+            None => self.generate_common(ast),
+        };
+        self.depth -= 1;
+        // エラーには、それを含む最も内側の文の行を付ける
+        result.map_err(|e| ast.locate(e))
+    }
+
+    fn generate_common(&mut self, ast: &ASTNode) -> Result<()> {
+        match self.common.get(&(ast as *const ASTNode)).copied() {
+            Some(Common::Save(addr)) => self.generate_node(ast).map(|_| {
+                self.emit(InstructionKind::Store(addr));
+                self.emit(InstructionKind::Fetch(addr));
+            }),
+            Some(Common::Reuse(addr)) => {
+                self.emit(InstructionKind::Fetch(addr));
+                Ok(())
+            }
+            None => self.generate_node(ast),
+        }
+    }
+
+    /// 文の中の 1 つの式を生成する。最適化するなら共通部分式をまとめる。
+    fn generate_expression(&mut self, ast: &ASTNode) -> Result<()> {
+        if self.optimize {
+            let common = cse::common_subexpressions(ast, &self.hoisted, self.width);
+            for (i, (first, reuses)) in common.into_iter().enumerate() {
+                let addr = self.temporary(i);
+                self.common
+                    .insert(first as *const ASTNode, Common::Save(addr));
+                for reuse in reuses {
+                    self.common
+                        .insert(reuse as *const ASTNode, Common::Reuse(addr));
+                }
+            }
+        }
+        let result = self.generate_body(ast);
+        self.common.clear();
+        result
+    }
+
+    /// i 番目の一時変数の data の添字
+    fn temporary(&mut self, i: usize) -> u32 {
+        while self.temporaries.len() <= i {
+            self.temporaries.push(self.data_size);
+            self.data_size += 1;
+        }
+        self.temporaries[i]
     }
 
-    fn generate_body(&mut self, ast: &'a ASTNode) -> Result<()> {
+    fn generate_node(&mut self, ast: &ASTNode) -> Result<()> {
         match ast.kind() {
-            NodeKind::Identifier(identifier) => self.generate_fetch(identifier),
+            NodeKind::Identifier(identifier) => self.generate_fetch(*identifier),
             NodeKind::Integer(value) => self.generate_integer(*value),
+            NodeKind::Float(value) => {
+                self.emit(InstructionKind::PushF(*value));
+                Ok(())
+            }
+            NodeKind::String(s) => {
+                let addr = self.intern_string(*s) as i32;
+                self.emit(InstructionKind::PushS(addr));
+                Ok(())
+            }
             NodeKind::Sequence => self.generate_sequence(ast),
             NodeKind::If => self.generate_if(ast),
             NodeKind::Prtc => self.generate_prtc(ast),
             NodeKind::Prts => self.generate_prts(ast),
             NodeKind::Prti => self.generate_prti(ast),
+            NodeKind::Exit => self.generate_exit(ast),
+            NodeKind::Assert => self.generate_assert(ast),
             NodeKind::While => self.generate_while(ast),
+            NodeKind::DoWhile => self.generate_do_while(ast),
             NodeKind::Assign => self.generate_assign(ast),
-            NodeKind::Negate | NodeKind::Not => self.generate_unary_op(ast),
+            NodeKind::Rand => self.generate_rand(ast),
+            NodeKind::ToInt | NodeKind::ToFloat => self.generate_conversion(ast),
+            NodeKind::Clock => {
+                self.emit(InstructionKind::Clock);
+                Ok(())
+            }
+            NodeKind::Getc => {
+                self.emit(InstructionKind::Getc);
+                Ok(())
+            }
+            NodeKind::Geti => {
+                self.emit(InstructionKind::Geti);
+                Ok(())
+            }
+            NodeKind::Negate | NodeKind::Not => {
+                match ast.constant_value_in(self.width).filter(|_| self.optimize) {
+                    Some(value) => self.generate_integer(value),
+                    None => self.generate_unary_op(ast),
+                }
+            }
             NodeKind::Multiply
             | NodeKind::Divide
             | NodeKind::Mod
@@ -77,7 +367,18 @@ impl<'a> CodeGenerator<'a> {
             | NodeKind::Equal
             | NodeKind::NotEqual
             | NodeKind::And
-            | NodeKind::Or => self.generate_binary_op(ast),
+            | NodeKind::Or
+            | NodeKind::BitAnd
+            | NodeKind::BitOr
+            | NodeKind::BitXor
+            | NodeKind::ShiftLeft
+            | NodeKind::ShiftRight
+            | NodeKind::Power => {
+                match ast.constant_value_in(self.width).filter(|_| self.optimize) {
+                    Some(value) => self.generate_integer(value),
+                    None => self.generate_binary_op(ast),
+                }
+            }
             _ => Err(CompileError::new(
                 ErrorKind::CodeGenerationError,
                 "unknown instruction",
@@ -85,12 +386,10 @@ impl<'a> CodeGenerator<'a> {
         }
     }
 
-    fn generate_fetch(&mut self, identifier: &'a str) -> Result<()> {
-        match self.data_addr.get(identifier) {
+    fn generate_fetch(&mut self, identifier: Symbol) -> Result<()> {
+        match self.data_addr.get(&identifier) {
             Some(addr) => {
-                self.instructions
-                    .push(Instruction::new(InstructionKind::Fetch(*addr), self.pc));
-                self.pc += 1 + 4;
+                self.emit(InstructionKind::Fetch(*addr));
                 Ok(())
             }
             None => Err(CompileError::new(
@@ -100,37 +399,152 @@ impl<'a> CodeGenerator<'a> {
         }
     }
 
-    fn generate_integer(&mut self, value: i32) -> Result<()> {
-        self.instructions
-            .push(Instruction::new(InstructionKind::Push(value), self.pc));
-        self.pc += 1 + 4;
+    fn is_float(&self, ast: &ASTNode) -> bool {
+        self.types.get(&(ast as *const ASTNode)) == Some(&Type::Float)
+    }
+
+    /// 積んだ式 ast の値が整数なら浮動小数点数にする
+    fn convert_to_float(&mut self, ast: &ASTNode) {
+        if !self.is_float(ast) {
+            self.emit(InstructionKind::Itof);
+        }
+    }
+
+    /// int(x) と float(x)。値がすでに目的の型なら何も出力しない。
+    fn generate_conversion(&mut self, ast: &ASTNode) -> Result<()> {
+        let operand = ast.lhs().unwrap();
+        self.generate_body(operand)?;
+        match ast.kind() {
+            NodeKind::ToInt if self.is_float(operand) => self.emit(InstructionKind::Ftoi),
+            NodeKind::ToFloat => self.convert_to_float(operand),
+            _ => {}
+        }
         Ok(())
     }
 
-    fn backpatch(&mut self, instruction_index: usize) {
-        let instruction = &mut self.instructions[instruction_index];
-        if let Instruction {
-            kind: InstructionKind::Jump(ref mut rel),
-            address,
+    fn generate_integer(&mut self, value: i64) -> Result<()> {
+        if !self.width.contains(value) {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                format!("{} does not fit in {}-bit integer", value, self.width),
+            ));
         }
-        | Instruction {
-            kind: InstructionKind::Jz(ref mut rel),
-            address,
-        } = instruction
-        {
-            *rel = (self.pc - (*address + 1)) as i32;
-        } else {
-            unreachable!();
+        self.emit(InstructionKind::Push(value));
+        Ok(())
+    }
+
+    fn emit(&mut self, kind: InstructionKind) {
+        let size = kind.size();
+        self.instructions.push(Instruction::new(kind, self.pc));
+        self.pc += size;
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label::new(self.labels.len() - 1)
+    }
+
+    /// label を次に出力する命令のアドレスに結びつける
+    fn bind(&mut self, label: Label) {
+        self.labels[label.id()] = Some(self.pc);
+    }
+
+    fn jump_to(&mut self, label: Label) {
+        self.fixups.push((self.instructions.len(), label));
+        self.emit(InstructionKind::Jump(0));
+    }
+
+    fn jz_to(&mut self, label: Label) {
+        self.fixups.push((self.instructions.len(), label));
+        self.emit(InstructionKind::Jz(0));
+    }
+
+    /// ジャンプ命令の相対アドレスを、ジャンプ先の Label のアドレスから求める
+    fn resolve_labels(&mut self) -> Result<()> {
+        for &(index, label) in &self.fixups {
+            let target = self.labels[label.id()].ok_or_else(|| {
+                CompileError::new(ErrorKind::CodeGenerationError, "unbound label")
+            })?;
+            let instruction = &mut self.instructions[index];
+            match instruction.kind {
+                InstructionKind::Jump(ref mut rel) | InstructionKind::Jz(ref mut rel) => {
+                    *rel = target.wrapping_sub(instruction.address + 1) as i32;
+                }
+                _ => unreachable!(),
+            }
+        }
+        self.fixups.clear();
+        Ok(())
+    }
+
+    /// 仮想マシンの max_stack_depth と同じく、命令の流れをたどって各命令の前後の深さを求め、
+    /// その最大値を返す。合流する経路で深さが異なる場合と、空のスタックから取り出す場合はエラーにする。
+    fn stack_depth(&self) -> Result<u32> {
+        let invalid = |address: u32, message: String| {
+            CompileError::new(
+                ErrorKind::CodeGenerationError,
+                format!("{}: {}", address, message),
+            )
+        };
+        let index_of = |address: u32| {
+            self.instructions
+                .binary_search_by_key(&address, |instruction| instruction.address)
+                .ok()
+        };
+
+        let mut depths: Vec<Option<u32>> = vec![None; self.instructions.len()];
+        let mut max_depth = 0;
+        let mut pending = vec![(0, 0)];
+        while let Some((index, depth)) = pending.pop() {
+            let instruction = match self.instructions.get(index) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            match depths[index] {
+                Some(d) if d == depth => continue,
+                Some(d) => {
+                    return Err(invalid(
+                        instruction.address,
+                        format!("stack depth is {} or {} depending on the path", d, depth),
+                    ))
+                }
+                None => depths[index] = Some(depth),
+            }
+
+            let (pops, pushes) = instruction.kind.stack_effect();
+            if depth < pops {
+                return Err(invalid(instruction.address, "stack underflow".to_string()));
+            }
+            let depth = depth - pops + pushes;
+            max_depth = max_depth.max(depth);
+
+            match instruction.kind {
+                InstructionKind::Jump(rel) | InstructionKind::Jz(rel) => {
+                    let target = (instruction.address + 1).wrapping_add(rel as u32);
+                    let target = index_of(target).ok_or_else(|| {
+                        invalid(
+                            instruction.address,
+                            format!("jump target {} is not an instruction", target),
+                        )
+                    })?;
+                    pending.push((target, depth));
+                }
+                _ => {}
+            }
+            match instruction.kind {
+                InstructionKind::Jump(_) | InstructionKind::Halt | InstructionKind::Exit => {}
+                _ => pending.push((index + 1, depth)),
+            }
         }
+        Ok(max_depth)
     }
 
-    fn generate_if(&mut self, ast: &'a ASTNode) -> Result<()> {
+    fn generate_if(&mut self, ast: &ASTNode) -> Result<()> {
+        let else_label = self.new_label();
+
         // condition
-        self.generate_body(ast.lhs().unwrap())?;
-        self.instructions
-            .push(Instruction::new(InstructionKind::Jz(0), self.pc));
-        self.pc += 1 + 4;
-        let jump_if_clause_idx = &self.instructions.len() - 1;
+        self.generate_expression(ast.lhs().unwrap())?;
+        self.jz_to(else_label);
 
         // if-clause
         let body = ast.rhs().unwrap();
@@ -139,118 +553,167 @@ impl<'a> CodeGenerator<'a> {
         // else-clause
         match body.rhs() {
             Some(else_clause) => {
-                self.instructions
-                    .push(Instruction::new(InstructionKind::Jump(0), self.pc));
-                let jump_instruction_idx = self.instructions.len() - 1;
-                self.pc += 1 + 4;
-                self.backpatch(jump_if_clause_idx);
-
+                let end_label = self.new_label();
+                self.jump_to(end_label);
+                self.bind(else_label);
                 self.generate_body(else_clause)?;
-                self.backpatch(jump_instruction_idx);
-            }
-            None => {
-                self.backpatch(jump_if_clause_idx);
+                self.bind(end_label);
             }
+            None => self.bind(else_label),
         }
         Ok(())
     }
 
-    fn generate_while(&mut self, ast: &'a ASTNode) -> Result<()> {
+    fn generate_while(&mut self, ast: &ASTNode) -> Result<()> {
+        let hoisted = if self.optimize {
+            self.hoist_invariants(ast)?
+        } else {
+            Vec::new()
+        };
+        let result = self.generate_loop(ast);
+
+        let temporaries = hoisted
+            .iter()
+            .filter_map(|node| self.hoisted.remove(node))
+            .collect::<std::collections::HashSet<u32>>();
+        self.invariants_in_use -= temporaries.len();
+        result
+    }
+
+    /// ループの中で値の変わらない式を、ループの前で計算して一時変数に置く。
+    /// 置いた式を返す。
+    fn hoist_invariants(&mut self, ast: &ASTNode) -> Result<Vec<*const ASTNode>> {
+        let mut hoisted = Vec::new();
+        for nodes in loop_invariant::loop_invariants(ast, &self.hoisted, self.width) {
+            if self.invariants_in_use == self.invariant_temporaries.len() {
+                self.invariant_temporaries.push(self.data_size);
+                self.data_size += 1;
+            }
+            let addr = self.invariant_temporaries[self.invariants_in_use];
+            self.invariants_in_use += 1;
+
+            self.generate_expression(nodes[0])?;
+            self.emit(InstructionKind::Store(addr));
+            for &node in &nodes {
+                self.hoisted.insert(node, addr);
+                hoisted.push(node as *const ASTNode);
+            }
+        }
+        Ok(hoisted)
+    }
+
+    fn generate_loop(&mut self, ast: &ASTNode) -> Result<()> {
+        let entry_label = self.new_label();
+        let exit_label = self.new_label();
+
         // condition
-        let entry_address = self.pc;
-        self.generate_body(ast.lhs().unwrap())?;
-        self.instructions
-            .push(Instruction::new(InstructionKind::Jz(0), self.pc));
-        let entry_index = self.instructions.len() - 1;
-        self.pc += 1 + 4;
+        self.bind(entry_label);
+        self.generate_expression(ast.lhs().unwrap())?;
+        self.jz_to(exit_label);
 
         // body
         self.generate_body(ast.rhs().unwrap())?;
-        self.instructions.push(Instruction::new(
-            InstructionKind::Jump(entry_address.wrapping_sub(self.pc + 1) as i32),
-            self.pc,
-        ));
+        self.jump_to(entry_label);
+        self.bind(exit_label);
+        Ok(())
+    }
 
-        self.pc += 1 + 4;
-        self.backpatch(entry_index);
+    fn generate_do_while(&mut self, ast: &ASTNode) -> Result<()> {
+        let entry_label = self.new_label();
+
+        // body
+        self.bind(entry_label);
+        self.generate_body(ast.rhs().unwrap())?;
+
+        // 条件が 0 でなければ先頭に戻る
+        self.generate_expression(ast.lhs().unwrap())?;
+        self.emit(InstructionKind::Not);
+        self.jz_to(entry_label);
         Ok(())
     }
 
-    fn intern_string(&mut self, s: &'a str) -> u32 {
-        for (i, &st) in self.string_pool.iter().enumerate() {
-            if s == st {
-                return i as u32;
-            }
+    fn intern_string(&mut self, s: Symbol) -> u32 {
+        if let Some(i) = self.string_pool.iter().position(|&st| st == s) {
+            return i as u32;
         }
         self.string_pool.push(s);
         (self.string_pool.len() - 1) as u32
     }
 
-    fn generate_prts(&mut self, ast: &'a ASTNode) -> Result<()> {
+    /// 文字列のリテラルは、タスクの出力と同じく push で番号を積む
+    fn generate_prts(&mut self, ast: &ASTNode) -> Result<()> {
         let string_node = ast.lhs().unwrap();
         if let NodeKind::String(s) = string_node.kind() {
-            let addr = self.intern_string(s) as i32;
-            self.instructions
-                .push(Instruction::new(InstructionKind::Push(addr), self.pc));
-            self.pc += 1 + 4;
+            let addr = self.intern_string(*s) as i64;
+            self.emit(InstructionKind::Push(addr));
         } else {
-            return Err(CompileError::new(
-                ErrorKind::CodeGenerationError,
-                "string expected",
-            ));
+            self.generate_expression(string_node)?;
         }
-        self.instructions
-            .push(Instruction::new(InstructionKind::Prts, self.pc));
-        self.pc += 1;
+        self.emit(InstructionKind::Prts);
         Ok(())
     }
 
-    fn generate_prtc(&mut self, ast: &'a ASTNode) -> Result<()> {
-        self.generate_body(ast.lhs().unwrap())?;
-        self.instructions
-            .push(Instruction::new(InstructionKind::Prtc, self.pc));
-        self.pc += 1;
+    fn generate_prtc(&mut self, ast: &ASTNode) -> Result<()> {
+        self.generate_expression(ast.lhs().unwrap())?;
+        self.emit(InstructionKind::Prtc);
         Ok(())
     }
 
-    fn generate_prti(&mut self, ast: &'a ASTNode) -> Result<()> {
-        self.generate_body(ast.lhs().unwrap())?;
-        self.instructions
-            .push(Instruction::new(InstructionKind::Prti, self.pc));
-        self.pc += 1;
+    fn generate_prti(&mut self, ast: &ASTNode) -> Result<()> {
+        let value = ast.lhs().unwrap();
+        self.generate_expression(value)?;
+        if self.is_float(value) {
+            self.emit(InstructionKind::Prtf);
+        } else {
+            self.emit(InstructionKind::Prti);
+        }
         Ok(())
     }
 
-    fn generate_sequence(&mut self, ast: &'a ASTNode) -> Result<()> {
-        if let Some(lhs) = ast.lhs() {
-            self.generate_body(lhs)?;
-        }
-        if let Some(rhs) = ast.rhs() {
-            self.generate_body(rhs)?;
+    fn generate_exit(&mut self, ast: &ASTNode) -> Result<()> {
+        self.generate_expression(ast.lhs().unwrap())?;
+        self.emit(InstructionKind::Exit);
+        Ok(())
+    }
+
+    /// 失敗したときのエラーの文は、行を含めて文字列プールに入れる
+    fn generate_assert(&mut self, ast: &ASTNode) -> Result<()> {
+        self.generate_expression(ast.lhs().unwrap())?;
+        let message = self.intern_string(Symbol::intern(&ast.assertion_message()));
+        self.emit(InstructionKind::Push(message as i64));
+        self.emit(InstructionKind::Assert);
+        Ok(())
+    }
+
+    fn generate_sequence(&mut self, ast: &ASTNode) -> Result<()> {
+        for item in ast.sequence_items() {
+            self.generate_body(item)?;
         }
         Ok(())
     }
 
-    fn intern(&mut self, name: &'a str) -> u32 {
-        match self.data_addr.get(name) {
+    fn intern(&mut self, name: Symbol) -> u32 {
+        match self.data_addr.get(&name) {
             Some(addr) => *addr,
             None => {
-                let addr = self.data_addr.len() as u32;
+                let addr = self.data_size;
+                self.data_size += 1;
                 self.data_addr.insert(name, addr);
                 addr
             }
         }
     }
 
-    fn generate_assign(&mut self, ast: &'a ASTNode) -> Result<()> {
+    fn generate_assign(&mut self, ast: &ASTNode) -> Result<()> {
         let identifier_node = ast.lhs().unwrap();
-        self.generate_body(ast.rhs().unwrap())?;
+        self.generate_expression(ast.rhs().unwrap())?;
+        if self.is_float(identifier_node) {
+            self.convert_to_float(ast.rhs().unwrap());
+        }
 
-        if let NodeKind::Identifier(ref identifier) = identifier_node.kind() {
+        if let NodeKind::Identifier(identifier) = *identifier_node.kind() {
             let addr = self.intern(identifier);
-            self.instructions
-                .push(Instruction::new(InstructionKind::Store(addr), self.pc));
-            self.pc += 1 + 4;
+            self.emit(InstructionKind::Store(addr));
         } else {
             return Err(CompileError::new(
                 ErrorKind::CodeGenerationError,
@@ -260,10 +723,12 @@ impl<'a> CodeGenerator<'a> {
         Ok(())
     }
 
-    fn generate_unary_op(&mut self, ast: &'a ASTNode) -> Result<()> {
-        self.generate_body(ast.lhs().unwrap())?;
+    fn generate_unary_op(&mut self, ast: &ASTNode) -> Result<()> {
+        let operand = ast.lhs().unwrap();
+        self.generate_body(operand)?;
 
         let instruction_kind = match ast.kind() {
+            NodeKind::Negate if self.is_float(operand) => InstructionKind::FNeg,
             NodeKind::Negate => InstructionKind::Neg,
             NodeKind::Not => InstructionKind::Not,
             _ => {
@@ -273,15 +738,23 @@ impl<'a> CodeGenerator<'a> {
                 ))
             }
         };
-        self.instructions
-            .push(Instruction::new(instruction_kind, self.pc));
-        self.pc += 1;
+        self.emit(instruction_kind);
         Ok(())
     }
 
-    fn generate_binary_op(&mut self, ast: &'a ASTNode) -> Result<()> {
+    fn generate_rand(&mut self, ast: &ASTNode) -> Result<()> {
         self.generate_body(ast.lhs().unwrap())?;
-        self.generate_body(ast.rhs().unwrap())?;
+        self.emit(InstructionKind::Rand);
+        Ok(())
+    }
+
+    fn generate_binary_op(&mut self, ast: &ASTNode) -> Result<()> {
+        let (lhs, rhs) = (ast.lhs().unwrap(), ast.rhs().unwrap());
+        if self.is_float(lhs) || self.is_float(rhs) {
+            return self.generate_float_op(ast);
+        }
+        self.generate_body(lhs)?;
+        self.generate_body(rhs)?;
 
         let instruction_kind = match ast.kind() {
             NodeKind::Multiply => InstructionKind::Mul,
@@ -297,6 +770,12 @@ impl<'a> CodeGenerator<'a> {
             NodeKind::NotEqual => InstructionKind::Ne,
             NodeKind::And => InstructionKind::And,
             NodeKind::Or => InstructionKind::Or,
+            NodeKind::BitAnd => InstructionKind::BitAnd,
+            NodeKind::BitOr => InstructionKind::BitOr,
+            NodeKind::BitXor => InstructionKind::BitXor,
+            NodeKind::ShiftLeft => InstructionKind::Shl,
+            NodeKind::ShiftRight => InstructionKind::Shr,
+            NodeKind::Power => InstructionKind::Pow,
             _ => {
                 return Err(CompileError::new(
                     ErrorKind::CodeGenerationError,
@@ -304,9 +783,37 @@ impl<'a> CodeGenerator<'a> {
                 ))
             }
         };
-        self.instructions
-            .push(Instruction::new(instruction_kind, self.pc));
-        self.pc += 1;
+        self.emit(instruction_kind);
+        Ok(())
+    }
+
+    /// 被演算子のどちらかが浮動小数点数の二項演算。整数の被演算子は浮動小数点数にする。
+    fn generate_float_op(&mut self, ast: &ASTNode) -> Result<()> {
+        let (lhs, rhs) = (ast.lhs().unwrap(), ast.rhs().unwrap());
+        self.generate_body(lhs)?;
+        self.convert_to_float(lhs);
+        self.generate_body(rhs)?;
+        self.convert_to_float(rhs);
+
+        let instruction_kind = match ast.kind() {
+            NodeKind::Multiply => InstructionKind::FMul,
+            NodeKind::Divide => InstructionKind::FDiv,
+            NodeKind::Add => InstructionKind::FAdd,
+            NodeKind::Subtract => InstructionKind::FSub,
+            NodeKind::Less => InstructionKind::FLt,
+            NodeKind::LessEqual => InstructionKind::FLe,
+            NodeKind::Greater => InstructionKind::FGt,
+            NodeKind::GreaterEqual => InstructionKind::FGe,
+            NodeKind::Equal => InstructionKind::FEq,
+            NodeKind::NotEqual => InstructionKind::FNe,
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    "invalid floating-point operator",
+                ))
+            }
+        };
+        self.emit(instruction_kind);
         Ok(())
     }
 }
@@ -318,7 +825,7 @@ mod tests {
     #[test]
     fn test_integer() {
         let s = r#"Integer 1"#.to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -331,7 +838,7 @@ Integer 1
 Integer 2
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -344,7 +851,7 @@ Integer 1
 Integer 2
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -357,7 +864,7 @@ Integer 1
 ;
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -370,7 +877,7 @@ Identifier count
 Integer 1
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -392,7 +899,7 @@ Identifier count
 Integer 1
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -414,7 +921,7 @@ Identifier count
 ;
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -436,7 +943,7 @@ Identifier count
 ;
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -461,7 +968,7 @@ String "abc"
 ;
 "#
         .to_string();
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -495,7 +1002,7 @@ Integer 3
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
 
         println!("{:?}", CodeGenerator::generate(&ast));
     }
@@ -511,7 +1018,7 @@ String        "Hello, World!\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 0 Strings: 1
 "Hello, World!\n"
@@ -522,6 +1029,711 @@ String        "Hello, World!\n"
         );
     }
 
+    #[test]
+    fn test_optimize() {
+        // print(2 * 3 + -(4 / 2), 1 / 0);
+        let s = r#"Sequence
+;
+Sequence
+Sequence
+;
+Prti
+Add
+Multiply
+Integer 2
+Integer 3
+Negate
+Divide
+Integer 4
+Integer 2
+;
+;
+Prti
+Divide
+Integer 1
+Integer 0
+;
+"#
+        .to_string();
+
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
+        assert_eq!(
+            r#"Datasize: 0 Strings: 0
+0 push 4
+5 prti
+6 push 1
+11 push 0
+16 div
+17 prti
+18 halt"#,
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize: true,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_common_subexpressions() {
+        let generate = |src: &str, optimize: bool| {
+            let ast = syntax_analyzer::parse_str(src).unwrap();
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let instruction_count = |code: &str| code.lines().skip(1).count();
+
+        let src = "i = 3; x = (i * i + 1) * (i * i + 1); print(x);";
+        assert_eq!(17, instruction_count(&generate(src, false)));
+        let code = generate(src, true);
+        assert_eq!(15, instruction_count(&code));
+        // 一時変数は変数の後ろに置く
+        assert_eq!(
+            r#"Datasize: 3 Strings: 0
+0 push 3
+5 store [0]
+10 fetch [0]
+15 fetch [0]
+20 mul
+21 push 1
+26 add
+27 store [1]
+32 fetch [1]
+37 fetch [1]
+42 mul
+43 store [2]
+48 fetch [2]
+53 prti
+54 halt"#,
+            code
+        );
+
+        // 一時変数は文ごとに使い回す
+        let src = r#"i = 1;
+while (i * i + i < 100 && i * i + i > 0) {
+    print(i * 2 * i + i * 2 * i + i * 2 * i, "\n");
+    i = i + 1;
+}
+"#;
+        let before = instruction_count(&generate(src, false));
+        let code = generate(src, true);
+        assert!(code.starts_with("Datasize: 2 "));
+        assert_eq!(before - 8, instruction_count(&code));
+    }
+
+    #[test]
+    fn test_loop_invariant_code_motion() {
+        let generate = |src: &str, optimize: bool| {
+            let ast = syntax_analyzer::parse_str(src).unwrap();
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        // 最初の後ろ向きの jmp から、そのジャンプ先までの命令の数
+        let loop_size = |code: &str| {
+            let instructions: Vec<(i32, &str)> = code
+                .lines()
+                .filter_map(|line| {
+                    let (address, rest) = line.split_once(' ')?;
+                    Some((address.parse().ok()?, rest))
+                })
+                .collect();
+            let (jmp, target) = instructions
+                .iter()
+                .find_map(|&(address, rest)| {
+                    let target = rest.strip_prefix("jmp ")?.rsplit(' ').next()?;
+                    Some((address, target.parse::<i32>().ok()?))
+                        .filter(|&(address, target)| target < address)
+                })
+                .unwrap();
+            instructions
+                .iter()
+                .filter(|&&(address, _)| target <= address && address <= jmp)
+                .count()
+        };
+
+        let src = r#"n = 10; scale = 3; i = 0;
+while (i < n * scale) {
+    print(i * (scale + 1) - n * scale, " ");
+    i = i + 1;
+}
+"#;
+        assert_eq!(23, loop_size(&generate(src, false)));
+        let code = generate(src, true);
+        assert_eq!(17, loop_size(&code));
+        // n * scale と scale + 1 をループの前で計算する
+        assert!(code.starts_with("Datasize: 5 "));
+        assert!(code.contains(
+            "30 fetch [0]\n35 fetch [1]\n40 mul\n41 store [3]\n46 fetch [1]\n51 push 1\n56 add\n57 store [4]\n62 fetch [2]\n67 fetch [3]\n72 lt\n"
+        ));
+
+        // 外側のループで変わる y を含む式も、内側のループの前で計算する
+        let src = r#"n = 4; y = 0;
+while (y < n) {
+    x = 0;
+    while (x < n) {
+        print((y * n + 1) * (n - 1) + x, " ");
+        x = x + 1;
+    }
+    y = y + 1;
+}
+"#;
+        let before = generate(src, false);
+        let code = generate(src, true);
+        assert_eq!(23, loop_size(&before));
+        assert_eq!(15, loop_size(&code));
+    }
+
+    #[test]
+    fn test_fuse_jumps() {
+        let generate = |src: &str, optimize: bool| {
+            let ast = syntax_analyzer::parse_str(src).unwrap();
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        // 命令の数と、 jmp の数と、ジャンプ先が jmp であるジャンプの数
+        let count = |code: &str| {
+            let instructions: Vec<Vec<&str>> = code
+                .lines()
+                .skip(1)
+                .map(|line| line.split_whitespace().collect())
+                .collect();
+            let is_jump_at = |address: &str| {
+                instructions
+                    .iter()
+                    .any(|i| i[0] == address && i[1] == "jmp")
+            };
+            let jumps = instructions.iter().filter(|i| i[1] == "jmp").count();
+            let chained = instructions
+                .iter()
+                .filter(|i| (i[1] == "jmp" || i[1] == "jz") && is_jump_at(i[3]))
+                .count();
+            (instructions.len(), jumps, chained)
+        };
+
+        // 空の else 節の前の jmp は次の命令に進むだけ
+        let src = "a = 1; x = 0; if (a) x = 1; else {}\nprint(x);";
+        assert_eq!((12, 1, 0), count(&generate(src, false)));
+        assert_eq!((11, 0, 0), count(&generate(src, true)));
+
+        // 内側の if の終わりから、外側の if の終わりに直接ジャンプする
+        let src =
+            "a = 1; b = 0; x = 0;\nif (a) { if (b) x = 1; else x = 2; } else x = 3;\nprint(x);";
+        assert_eq!((21, 2, 1), count(&generate(src, false)));
+        assert_eq!((21, 2, 0), count(&generate(src, true)));
+
+        // ループの中の if の終わりから、ループの先頭に直接戻る
+        let src = "a = 1; b = 0; x = 0;\nwhile (a) { if (b) x = 1; else {} }";
+        assert_eq!((15, 2, 2), count(&generate(src, false)));
+        assert_eq!((15, 2, 0), count(&generate(src, true)));
+
+        // 取り除いた命令の後ろのアドレスと行のコメントを付け直す
+        let src = "x = 0;\nif (x) x = 1; else {}\nprint(x);\n";
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let options = GeneratorOptions {
+            optimize: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+; line 1: x = 0;
+0 push 0
+5 store [0]
+; line 2: if (x) x = 1; else {}
+10 fetch [0]
+15 jz (14) 30
+20 push 1
+25 store [0]
+; line 3: print(x);
+30 fetch [0]
+35 prti
+36 halt"#,
+            CodeGenerator::generate_listing(&ast, src, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut generator = CodeGenerator::new(&GeneratorOptions::default());
+        let back = generator.new_label();
+        let forward = generator.new_label();
+        generator.bind(back);
+        generator.emit(InstructionKind::Push(1));
+        generator.jz_to(forward);
+        generator.jump_to(back);
+        generator.bind(forward);
+        generator.emit(InstructionKind::Halt);
+        generator.resolve_labels().unwrap();
+
+        let code = generator
+            .instructions
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            vec!["0 push 1", "5 jz (9) 15", "10 jmp (-11) 0", "15 halt"],
+            code
+        );
+
+        let mut generator = CodeGenerator::new(&GeneratorOptions::default());
+        let label = generator.new_label();
+        generator.jump_to(label);
+        assert!(generator.resolve_labels().is_err());
+    }
+
+    #[test]
+    fn test_do_while() {
+        let ast = syntax_analyzer::parse_str("i = 0; do i = i + 1; while (i < 3);").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 0
+5 store [0]
+10 fetch [0]
+15 push 1
+20 add
+21 store [0]
+26 fetch [0]
+31 push 3
+36 lt
+37 not
+38 jz (-29) 10
+43 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rand() {
+        // rand は定数の引数でも畳み込まない
+        let ast = syntax_analyzer::parse_str("x = rand(2 + 4);").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 6
+5 rand
+6 store [0]
+11 halt"#,
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize: true,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_clock() {
+        let ast = syntax_analyzer::parse_str("t = clock() / 1000;").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 clock
+1 push 1000
+6 div
+7 store [0]
+12 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_assert() {
+        let ast = syntax_analyzer::parse_str("x = 2;\nassert(x > 1, \"x > 1\");").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 1
+"assertion failed at line 2: x > 1"
+0 push 2
+5 store [0]
+10 fetch [0]
+15 push 1
+20 gt
+21 push 0
+26 assert
+27 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_string_variables() {
+        // print の文字列のリテラルは push、値としての文字列は pushs で積む
+        let ast = syntax_analyzer::parse_str(r#"s = "a"; print(s, "a");"#).unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 1
+"a"
+0 pushs 0
+5 store [0]
+10 fetch [0]
+15 prts
+16 push 0
+21 prts
+22 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let ast = syntax_analyzer::parse_str("x = 2; if (x) exit(x + 1);").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 2
+5 store [0]
+10 fetch [0]
+15 jz (16) 32
+20 fetch [0]
+25 push 1
+30 add
+31 exit
+32 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let src = r#"count = 1;
+while (count < 3) {
+    print("count is: ",
+          count, "\n");
+    count = count + 1;
+}
+"#;
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let listing =
+            CodeGenerator::generate_listing(&ast, src, &GeneratorOptions::default()).unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 2
+"count is: "
+"\n"
+; line 1: count = 1;
+0 push 1
+5 store [0]
+; line 2: while (count < 3) {
+10 fetch [0]
+15 push 3
+20 lt
+21 jz (43) 65
+; line 3: print("count is: ",
+26 push 0
+31 prts
+; line 4: count, "\n");
+32 fetch [0]
+37 prti
+38 push 1
+43 prts
+; line 5: count = count + 1;
+44 fetch [0]
+49 push 1
+54 add
+55 store [0]
+60 jmp (-51) 10
+65 halt"#,
+            listing
+        );
+
+        let (debug_code, info) =
+            CodeGenerator::generate_debug(&ast, &GeneratorOptions::default()).unwrap();
+        assert_eq!(vec![(0, 1), (10, 2), (26, 3), (32, 4), (44, 5)], info.lines);
+        assert_eq!(vec![("count".to_string(), 0)], info.symbols.variables);
+
+        // コメントを除けば generate_with と同じ
+        let code = CodeGenerator::generate(&ast).unwrap();
+        assert_eq!(code, debug_code);
+        let stripped: Vec<&str> = listing.lines().filter(|l| !l.starts_with(';')).collect();
+        assert_eq!(code, stripped.join("\n"));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let ast = syntax_analyzer::parse_str(r#"b = 1; a = b; print("a\n", a, "b");"#).unwrap();
+        let (code, symbols) =
+            CodeGenerator::generate_with_symbols(&ast, &GeneratorOptions::default()).unwrap();
+        assert_eq!(CodeGenerator::generate(&ast).unwrap(), code);
+        assert_eq!(
+            SymbolTable {
+                variables: vec![("b".to_string(), 0), ("a".to_string(), 1)],
+                strings: vec!["a\n".to_string(), "b".to_string()],
+            },
+            symbols
+        );
+
+        let options = GeneratorOptions {
+            symbols: true,
+            ..Default::default()
+        };
+        let code = CodeGenerator::generate_with(&ast, &options).unwrap();
+        assert!(code.ends_with("halt\n; Symbols:\n; 0 b\n; 1 a"), "{}", code);
+    }
+
+    #[test]
+    fn test_stack_size() {
+        let options = GeneratorOptions {
+            stack_size: true,
+            ..Default::default()
+        };
+        let ast = syntax_analyzer::parse_str("x = 1 + 2 * (3 - 4); print(x);").unwrap();
+        let code = CodeGenerator::generate_with(&ast, &options).unwrap();
+        assert!(
+            code.starts_with("Datasize: 1 Strings: 0 Stack: 4\n"),
+            "{}",
+            code
+        );
+
+        let ast = syntax_analyzer::parse_str("while (1) { }").unwrap();
+        let code = CodeGenerator::generate_with(&ast, &options).unwrap();
+        assert!(
+            code.starts_with("Datasize: 0 Strings: 0 Stack: 1\n"),
+            "{}",
+            code
+        );
+
+        // 分岐の先で深さを数え直す
+        let ast = syntax_analyzer::parse_str("if (1) print(1 + 2 * 3); else x = 1;").unwrap();
+        let code = CodeGenerator::generate_with(&ast, &options).unwrap();
+        assert!(
+            code.starts_with("Datasize: 1 Strings: 0 Stack: 3\n"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let ast = syntax_analyzer::parse_str("x = 1; x = x << 2 | x >> 1 & x ^ 3;").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 1
+5 store [0]
+10 fetch [0]
+15 push 2
+20 shl
+21 fetch [0]
+26 push 1
+31 shr
+32 fetch [0]
+37 band
+38 push 3
+43 bxor
+44 bor
+45 store [0]
+50 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+
+        // 定数は畳み込む
+        let ast = syntax_analyzer::parse_str("x = 1 << 4 | 6 & 3 ^ 1;").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 19
+5 store [0]
+10 halt"#,
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize: true,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_power() {
+        let ast = syntax_analyzer::parse_str("x = 2; x = -x ** x ** 3;").unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 2
+5 store [0]
+10 fetch [0]
+15 fetch [0]
+20 push 3
+25 pow
+26 pow
+27 neg
+28 store [0]
+33 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+
+        // 定数は畳み込むが、 0 の負の乗は実行時のエラーに残す
+        let ast = syntax_analyzer::parse_str("x = 2 ** 3 ** 2; y = 0 ** -1;").unwrap();
+        assert_eq!(
+            r#"Datasize: 2 Strings: 0
+0 push 512
+5 store [0]
+10 push 0
+15 push -1
+20 pow
+21 store [1]
+26 halt"#,
+            CodeGenerator::generate_with(
+                &ast,
+                &GeneratorOptions {
+                    optimize: true,
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        let options = syntax_analyzer::ParserOptions {
+            dialect: lexical_analyzer::dialect::Dialect::float(),
+            ..syntax_analyzer::ParserOptions::default()
+        };
+        let src = "x = 1; x = x / 2.5; print(-x, int(x) < 1);";
+        let ast = syntax_analyzer::parse_str_with(src, &options).unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0
+0 push 1
+5 itof
+6 store [0]
+11 fetch [0]
+16 pushf 2.5
+21 fdiv
+22 store [0]
+27 fetch [0]
+32 fneg
+33 prtf
+34 fetch [0]
+39 ftoi
+40 push 1
+45 lt
+46 prti
+47 halt"#,
+            CodeGenerator::generate(&ast).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_width() {
+        let mut dialect = lexical_analyzer::dialect::Dialect::extended();
+        dialect.width = Width::W64;
+        let parser_options = syntax_analyzer::ParserOptions {
+            dialect,
+            ..syntax_analyzer::ParserOptions::default()
+        };
+        let src = "x = 4294967296; print(x + 1, 65536 * 65536);";
+        let ast = syntax_analyzer::parse_str_with(src, &parser_options).unwrap();
+        let options = GeneratorOptions {
+            optimize: true,
+            width: Width::W64,
+            ..Default::default()
+        };
+        assert_eq!(
+            r#"Datasize: 1 Strings: 0 Width: 64
+0 pushw 4294967296
+9 store [0]
+14 fetch [0]
+19 push 1
+24 add
+25 prti
+26 pushw 4294967296
+35 prti
+36 halt"#,
+            CodeGenerator::generate_with(&ast, &options).unwrap()
+        );
+
+        // 32 ビットの幅では畳み込まず、実行時に折り返す
+        let ast = syntax_analyzer::parse_str("print(65536 * 65536);").unwrap();
+        let options = GeneratorOptions {
+            optimize: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "Datasize: 0 Strings: 0\n0 push 65536\n5 push 65536\n10 mul\n11 prti\n12 halt",
+            CodeGenerator::generate_with(&ast, &options).unwrap()
+        );
+
+        // 幅に収まらないリテラルはエラー
+        let ast = ASTReader::read_ast("Prti\nInteger 4294967296\n;\n".lines()).unwrap();
+        let e = CodeGenerator::generate(&ast).unwrap_err();
+        assert_eq!("4294967296 does not fit in 32-bit integer", e.message());
+    }
+
+    #[test]
+    fn test_error_line() {
+        // エラーには、それを含む文の行が付く
+        let ast = syntax_analyzer::parse_str("x = 1;\nwhile (x) {\n  y = z;\n}").unwrap();
+        let e = CodeGenerator::generate(&ast).unwrap_err();
+        assert_eq!("unknown identifier: z", e.message());
+        assert_eq!(Some(3), e.line());
+        assert_eq!(None, e.position());
+        let e = emit_c::emit_c(&ast).unwrap_err();
+        assert_eq!(Some(3), e.line());
+    }
+
+    #[test]
+    fn test_type_error() {
+        let s = r#"If
+String        "yes"
+If
+Prts
+String        "then"
+;
+;
+"#;
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
+        let e = CodeGenerator::generate(&ast).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::TypeError));
+        assert_eq!("string used as a condition", e.message());
+
+        // 行は位置として付ける
+        let ast = syntax_analyzer::parse_str("x = 1;\nif (\"a\") x = 2;").unwrap();
+        let e = CodeGenerator::generate(&ast).unwrap_err();
+        assert_eq!("string used as a condition", e.message());
+        assert_eq!(Some("2".to_string()), e.location());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        // 文の数は深さに数えない
+        let src = "x = 1;".repeat(10_000);
+        let ast = syntax_analyzer::parse_str(&src).unwrap();
+        assert!(CodeGenerator::generate(&ast).is_ok());
+
+        // 1 + 1 + ... は左に深い木になる
+        let src = format!("print({});", vec!["1"; 900].join(" + "));
+        let ast = syntax_analyzer::parse_str(&src).unwrap();
+        assert!(CodeGenerator::generate(&ast).is_ok());
+
+        let src = format!("print({});", vec!["1"; 2000].join(" + "));
+        let ast = syntax_analyzer::parse_str(&src).unwrap();
+        assert!(CodeGenerator::generate(&ast).is_err());
+
+        let options = GeneratorOptions {
+            max_depth: 4,
+            ..Default::default()
+        };
+        let ast = syntax_analyzer::parse_str("print(1 + 2 + 3);").unwrap();
+        assert!(CodeGenerator::generate_with(&ast, &options).is_err());
+    }
+
     #[test]
     fn test_phoenix_number() {
         let s = r#"Sequence
@@ -541,7 +1753,7 @@ String        "\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 1 Strings: 1
 "\n"
@@ -592,7 +1804,7 @@ Integer       1
 "#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 1 Strings: 2
 "count is: "
@@ -654,7 +1866,7 @@ Identifier    count
 Integer       1"#
             .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 1 Strings: 2
 "count is: "
@@ -720,7 +1932,7 @@ Identifier     i
 Integer        1"#
             .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 1 Strings: 2
 "door "
@@ -822,7 +2034,7 @@ String        "\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 2 Strings: 1
 "\n"
@@ -999,7 +2211,7 @@ String        "\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 0 Strings: 1
 "\n"
@@ -1106,7 +2318,7 @@ Identifier     a
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 3 Strings: 0
 0 push 1071
@@ -1174,7 +2386,7 @@ Identifier     result
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 3 Strings: 0
 0 push 12
@@ -1260,7 +2472,7 @@ String         "\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 5 Strings: 1
 "\n"
@@ -1367,7 +2579,7 @@ Identifier     i
 Integer        1"#
             .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 1 Strings: 4
 "FizzBuzz"
@@ -1475,7 +2687,7 @@ String         " bottles of beer on the wall\n\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 1 Strings: 4
 " bottles of beer on the wall\n"
@@ -1611,7 +2823,7 @@ String        "\n"
 ;"#
         .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 5 Strings: 3
 " is prime\n"
@@ -1851,7 +3063,7 @@ Identifier     y0
 Identifier     y_step"#
             .to_string();
 
-        let ast = ASTReader::read_ast(s.lines());
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
         assert_eq!(
             r#"Datasize: 15 Strings: 0
 0 push 420
@@ -0,0 +1,223 @@
+use lexical_analyzer::symbol::Symbol;
+use lexical_analyzer::width::Width;
+use syntax_analyzer::ast_node::*;
+
+use std::collections::{HashMap, HashSet};
+
+/// 代入される変数を集める
+struct AssignedVariables(HashSet<Symbol>);
+
+impl Visitor<'_> for AssignedVariables {
+    fn visit(&mut self, ast: &ASTNode) {
+        match ast.kind() {
+            NodeKind::Assign => {
+                if let NodeKind::Identifier(identifier) = *ast.lhs().unwrap().kind() {
+                    self.0.insert(identifier);
+                }
+            }
+            _ => walk(self, ast),
+        }
+    }
+}
+
+/// 演算のノードなら true
+fn is_operation(ast: &ASTNode) -> bool {
+    matches!(
+        ast.kind(),
+        NodeKind::Negate
+            | NodeKind::Not
+            | NodeKind::Multiply
+            | NodeKind::Divide
+            | NodeKind::Mod
+            | NodeKind::Add
+            | NodeKind::Subtract
+            | NodeKind::Less
+            | NodeKind::LessEqual
+            | NodeKind::Greater
+            | NodeKind::GreaterEqual
+            | NodeKind::Equal
+            | NodeKind::NotEqual
+            | NodeKind::And
+            | NodeKind::Or
+            | NodeKind::BitAnd
+            | NodeKind::BitOr
+            | NodeKind::BitXor
+            | NodeKind::ShiftLeft
+            | NodeKind::ShiftRight
+            | NodeKind::Power
+            | NodeKind::ToInt
+            | NodeKind::ToFloat
+    )
+}
+
+struct Finder<'a, 'h> {
+    /// ループの中で代入される変数
+    assigned: HashSet<Symbol>,
+    /// 外側のループで一時変数に移した式と、その data の添字
+    hoisted: &'h HashMap<*const ASTNode, u32>,
+    /// 移せる式と、同じ計算なら同じになる文字列 (出現順)
+    found: Vec<(&'a ASTNode, String)>,
+    /// 定数式を畳み込むときの値の幅
+    width: Width,
+}
+
+impl<'a, 'h> Finder<'a, 'h> {
+    /// ast がループの中で値の変わらない式なら、同じ計算なら同じになる文字列を返す。
+    /// そうでなければ、子のうち値の変わらない演算を found に加える。
+    fn visit(&mut self, ast: &'a ASTNode) -> Option<String> {
+        if let Some(addr) = self.hoisted.get(&(ast as *const ASTNode)) {
+            return Some(format!("[{}]", addr));
+        }
+        if let Some(value) = ast.constant_value_in(self.width) {
+            return Some(value.to_string());
+        }
+        match ast.kind() {
+            NodeKind::Identifier(identifier) if self.assigned.contains(identifier) => return None,
+            NodeKind::Identifier(identifier) => return Some(identifier.to_string()),
+            NodeKind::Integer(value) => return Some(value.to_string()),
+            NodeKind::Float(_) => return Some(format!("{:?}", ast.kind())),
+            NodeKind::Sequence => {
+                for item in ast.sequence_items() {
+                    self.visit_child(item);
+                }
+                return None;
+            }
+            _ => {}
+        }
+
+        let lhs = ast.lhs().map(|lhs| self.visit(lhs));
+        let rhs = ast.rhs().map(|rhs| self.visit(rhs));
+        if is_operation(ast) && self.can_move(ast) {
+            match (&lhs, &rhs) {
+                (Some(Some(lhs)), None) => return Some(format!("({:?} {})", ast.kind(), lhs)),
+                (Some(Some(lhs)), Some(Some(rhs))) => {
+                    return Some(format!("({:?} {} {})", ast.kind(), lhs, rhs))
+                }
+                _ => {}
+            }
+        }
+
+        // ast は移せないので、移せる子をそれぞれ移す
+        for (child, key) in ast.lhs().into_iter().zip(lhs) {
+            self.add(child, key);
+        }
+        for (child, key) in ast.rhs().into_iter().zip(rhs) {
+            self.add(child, key);
+        }
+        None
+    }
+
+    fn visit_child(&mut self, ast: &'a ASTNode) {
+        let key = self.visit(ast);
+        self.add(ast, key);
+    }
+
+    fn add(&mut self, ast: &'a ASTNode, key: Option<String>) {
+        let hoisted = self.hoisted.contains_key(&(ast as *const ASTNode));
+        if let Some(key) = key.filter(|_| is_operation(ast) && !hoisted) {
+            if ast.constant_value_in(self.width).is_none() {
+                self.found.push((ast, key));
+            }
+        }
+    }
+
+    /// ループの前で計算しても実行時エラーを起こさない演算なら true。
+    /// ループが 1 回も回らない場合や、 if の中で実行されない場合にも計算することになるので、
+    /// 0 で割るかもしれない除算と、 0 の負の乗になるかもしれない累乗は移さない。
+    fn can_move(&self, ast: &ASTNode) -> bool {
+        match ast.kind() {
+            NodeKind::Divide | NodeKind::Mod => {
+                matches!(ast.rhs().unwrap().constant_value_in(self.width), Some(d) if d != 0 && d != -1)
+            }
+            NodeKind::Power => {
+                matches!(ast.rhs().unwrap().constant_value_in(self.width), Some(e) if e >= 0)
+                    || matches!(ast.lhs().unwrap().constant_value_in(self.width), Some(b) if b != 0)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// while ループの条件と本体から、ループの中で値の変わらない式を探す。
+/// 同じ計算になる式をまとめ、最初に現れる順に返す。
+/// hoisted の式は外側のループで移したもので、変わらない値として扱う。
+pub(crate) fn loop_invariants<'a>(
+    ast: &'a ASTNode,
+    hoisted: &HashMap<*const ASTNode, u32>,
+    width: Width,
+) -> Vec<Vec<&'a ASTNode>> {
+    let mut assigned = AssignedVariables(HashSet::new());
+    assigned.visit(ast);
+    let mut finder = Finder {
+        assigned: assigned.0,
+        hoisted,
+        found: Vec::new(),
+        width,
+    };
+    finder.visit_child(ast.lhs().unwrap());
+    finder.visit_child(ast.rhs().unwrap());
+
+    let mut groups: Vec<(String, Vec<&ASTNode>)> = Vec::new();
+    for (node, key) in finder.found {
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, nodes)) => nodes.push(node),
+            None => groups.push((key, vec![node])),
+        }
+    }
+    groups.into_iter().map(|(_, nodes)| nodes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syntax_analyzer::unparser::unparse_expr;
+
+    /// 最初の while ループから移せる式を、ソースの形で返す
+    fn invariants(src: &str) -> Vec<(String, usize)> {
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let while_node = ast
+            .sequence_items()
+            .into_iter()
+            .find(|item| matches!(item.kind(), NodeKind::While))
+            .unwrap();
+        loop_invariants(while_node, &HashMap::new(), Width::default())
+            .iter()
+            .map(|nodes| (unparse_expr(nodes[0]), nodes.len()))
+            .collect()
+    }
+
+    #[test]
+    fn test_loop_invariants() {
+        assert_eq!(
+            vec![("n * 2".to_string(), 2), ("-k".to_string(), 1)],
+            invariants(
+                "n = 5; k = 1; i = 0; while (i < n * 2) { print(i + n * 2, -k); i = i + 1; }"
+            )
+        );
+        // 最も大きな式だけを移す
+        assert_eq!(
+            vec![("(a + b) * c".to_string(), 1)],
+            invariants("a = 1; b = 2; c = 3; i = 0; while (i < (a + b) * c) i = i + 1;")
+        );
+        // ループの中で代入される変数を含む式は移さない
+        assert!(invariants("a = 1; i = 0; while (i < a * 2) { a = a + 1; i = i + 1; }").is_empty());
+        // 0 で割るかもしれない除算と rand は移さない。 rand の引数は移せる。
+        assert_eq!(
+            vec![("a + 1".to_string(), 1)],
+            invariants("a = 1; i = 0; while (i < 3) { i = i + 100 / a + rand(a + 1); }")
+        );
+        assert_eq!(
+            vec![("a / 2".to_string(), 1)],
+            invariants("a = 1; i = 0; while (i < 3) i = i + a / 2;")
+        );
+        // 0 の負の乗になるかもしれない累乗は移さない
+        assert!(invariants(
+            "x = 0; n = -1; i = 0; while (i < 3) { if (x) print(x ** n); i = i + 1; }"
+        )
+        .is_empty());
+        assert_eq!(
+            vec![("x ** 2".to_string(), 1), ("2 ** n".to_string(), 1)],
+            invariants("x = 0; n = -1; i = 0; while (i < 3) { print(x ** 2, 2 ** n); i = i + 1; }")
+        );
+    }
+}
@@ -1,29 +1,60 @@
-use code_generator::CodeGenerator;
+use code_generator::emit_c::emit_c;
+use code_generator::{CodeGenerator, GeneratorOptions};
+use lexical_analyzer::cli::{Command, Flag, Options, Target};
+use lexical_analyzer::diagnostic::Diagnostics;
+use lexical_analyzer::error::*;
+use lexical_analyzer::width::Width;
 use syntax_analyzer::ast_node::*;
+use syntax_analyzer::lint;
 
-use std::env;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::io::Write;
 
-fn main() {
-    let mut reader: Box<dyn BufRead> = match env::args().nth(1) {
-        None => Box::new(BufReader::new(stdin())),
-        Some(filename) => Box::new(BufReader::new(
-            File::open(filename).expect("cannot open file"),
-        )),
-    };
+const COMMAND: Command = Command {
+    name: "code_generator",
+    description: "Reads a flattened AST and writes virtual machine assembly or C source.",
+    flags: &[
+        Flag::Optimize,
+        Flag::Target,
+        Flag::Symbols,
+        Flag::StackSize,
+        Flag::Width,
+        Flag::DenyWarnings,
+    ],
+};
+
+fn run(options: &Options) -> Result<()> {
+    let ast_str = options.read_input()?;
+    let mut writer = options.create_output()?;
+
+    let ast = ASTReader::read_ast(ast_str.lines())?;
+    let mut diagnostics = Diagnostics::new(options.deny_warnings);
+    lint::check(&ast, &mut diagnostics);
+    options.report(COMMAND.name, &diagnostics)?;
 
-    let mut writer: Box<dyn Write> = match env::args().nth(2) {
-        None => Box::new(BufWriter::new(stdout())),
-        Some(filename) => Box::new(BufWriter::new(
-            File::create(filename).expect("cannot create file"),
-        )),
+    let generator_options = GeneratorOptions {
+        optimize: options.optimize,
+        symbols: options.symbols,
+        stack_size: options.stack_size,
+        width: options.dialect.width,
+        ..Default::default()
+    };
+    let code = match options.target {
+        Target::Vm => CodeGenerator::generate_with(&ast, &generator_options)?,
+        Target::C if options.dialect.width != Width::W32 => {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                "64-bit integers are not supported by the C backend",
+            ))
+        }
+        Target::C => emit_c(&ast)?,
     };
 
-    let mut ast_str = String::new();
-    reader.read_to_string(&mut ast_str).expect("read error");
-    let ast = ASTReader::read_ast(ast_str.lines());
-    let code = CodeGenerator::generate(&ast).unwrap();
+    writer
+        .write_all(code.as_bytes())
+        .and_then(|_| writer.flush())
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+}
 
-    writer.write(code.as_bytes()).expect("write error");
+fn main() {
+    COMMAND.main(run);
 }
@@ -0,0 +1,57 @@
+/// 変数と文字列に割り当てた番号の表
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    /// 変数の名前と data の添字 (添字順)
+    pub variables: Vec<(String, usize)>,
+    /// 文字列プール (番号順)
+    pub strings: Vec<String>,
+}
+
+/// 出力の最後に付ける変数名の表の見出し
+pub const SYMBOLS_HEADER: &str = "; Symbols:";
+
+impl SymbolTable {
+    /// 変数 name の data の添字
+    pub fn variable(&self, name: &str) -> Option<usize> {
+        self.variables
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|&(_, index)| index)
+    }
+
+    /// data の index 番目の変数の名前
+    pub fn variable_name(&self, index: usize) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|&&(_, i)| i == index)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// アセンブリの最後に付ける変数名の表。
+    /// コメントなので、表を知らない仮想マシンでもそのまま読み込める。
+    pub fn footer(&self) -> String {
+        let mut footer = SYMBOLS_HEADER.to_string();
+        for (name, index) in &self.variables {
+            footer += &format!("\n; {} {}", index, name);
+        }
+        footer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_table() {
+        let symbols = SymbolTable {
+            variables: vec![("count".to_string(), 0), ("total".to_string(), 1)],
+            strings: vec!["\n".to_string()],
+        };
+        assert_eq!(Some(1), symbols.variable("total"));
+        assert_eq!(None, symbols.variable("other"));
+        assert_eq!(Some("count"), symbols.variable_name(0));
+        assert_eq!(None, symbols.variable_name(2));
+        assert_eq!("; Symbols:\n; 0 count\n; 1 total", symbols.footer());
+    }
+}
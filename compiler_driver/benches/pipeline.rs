@@ -0,0 +1,94 @@
+//! 字句解析、構文解析、コード生成、仮想マシンの実行をそれぞれ測る。
+//!
+//!     cargo bench -p compiler_driver
+//!
+//! シンボルの intern や仮想マシンの命令のデコード、のぞき穴最適化のような
+//! 性能のための変更を、変更の前後で比べるために使う。
+use code_generator::{CodeGenerator, GeneratorOptions};
+use compiler_driver::ProgramGenerator;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use lexical_analyzer::lex_all;
+use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+use std::io;
+
+const MANDELBROT: &str = include_str!("mandelbrot.t");
+
+/// ランダムなプログラムをつなげた、100 KB 程度のソース
+fn large_source() -> String {
+    let mut src = String::new();
+    let mut seed = 1;
+    while src.len() < 100_000 {
+        src += &ProgramGenerator::new(seed).program();
+        seed += 1;
+    }
+    src
+}
+
+/// 項が n 個の式と、括弧が n 重の式
+fn deep_expressions(n: usize) -> (String, String) {
+    let long = format!("x = {};", vec!["1"; n].join(" + "));
+    let nested = format!("x = {}1{};", "(1 + ".repeat(n), ")".repeat(n));
+    (long, nested)
+}
+
+fn lexer(c: &mut Criterion) {
+    let src = large_source();
+    c.bench_function("lex large source", |b| b.iter(|| lex_all(&src).unwrap()));
+}
+
+fn parser(c: &mut Criterion) {
+    let (long, nested) = deep_expressions(200);
+    c.bench_function("parse long expression", |b| {
+        b.iter(|| syntax_analyzer::parse_str(&long).unwrap())
+    });
+    c.bench_function("parse nested expression", |b| {
+        b.iter(|| syntax_analyzer::parse_str(&nested).unwrap())
+    });
+    let src = large_source();
+    c.bench_function("parse large source", |b| {
+        b.iter(|| syntax_analyzer::parse_str(&src).unwrap())
+    });
+}
+
+fn code_generation(c: &mut Criterion) {
+    let ast = syntax_analyzer::parse_str(&large_source()).unwrap();
+    let mut group = c.benchmark_group("generate large source");
+    for optimize in [false, true].iter() {
+        let options = GeneratorOptions {
+            optimize: *optimize,
+            ..Default::default()
+        };
+        let name = if *optimize { "optimize" } else { "plain" };
+        group.bench_function(name, |b| {
+            b.iter(|| CodeGenerator::generate_with(&ast, &options).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn vm(c: &mut Criterion) {
+    let ast = syntax_analyzer::parse_str(MANDELBROT).unwrap();
+    let mut group = c.benchmark_group("vm mandelbrot");
+    group.sample_size(20);
+    for optimize in [false, true].iter() {
+        let options = GeneratorOptions {
+            optimize: *optimize,
+            ..Default::default()
+        };
+        let code = CodeGenerator::generate_with(&ast, &options).unwrap();
+        let name = if *optimize { "optimize" } else { "plain" };
+        // 変数の値が残らないように、読み込みは毎回やり直す。読み込みは時間に含めない。
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || VirtualMachineInterpreter::load(code.lines()).unwrap(),
+                |mut vm| vm.execute(&mut io::sink()).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lexer, parser, code_generation, vm);
+criterion_main!(benches);
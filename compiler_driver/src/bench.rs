@@ -0,0 +1,170 @@
+use ast_interpreter::ASTInterpreter;
+use code_generator::{CodeGenerator, GeneratorOptions};
+use lexical_analyzer::error::*;
+use register_machine::{Program, RegisterMachine};
+use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// 1 つの実行方法で測った、各回の実行時間
+#[derive(Debug, Clone)]
+pub struct Timing {
+    pub runs: Vec<Duration>,
+}
+
+impl Timing {
+    /// f を runs 回実行し、それぞれの時間を測る
+    fn measure<F>(runs: usize, mut f: F) -> Result<Self>
+    where
+        F: FnMut() -> Result<Duration>,
+    {
+        let runs = (0..runs).map(|_| f()).collect::<Result<_>>()?;
+        Ok(Timing { runs })
+    }
+
+    pub fn total(&self) -> Duration {
+        self.runs.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.runs.len() {
+            0 => Duration::default(),
+            n => self.total() / n as u32,
+        }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.runs.iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.runs.iter().max().copied().unwrap_or_default()
+    }
+}
+
+/// AST インタプリタと仮想マシン、レジスタマシンの実行時間を比べる
+#[derive(Debug, Clone)]
+pub struct Bench {
+    pub interpreter: Timing,
+    pub vm: Timing,
+    pub register: Timing,
+}
+
+impl Bench {
+    /// src を解析し、それぞれの方法で runs 回実行する。
+    /// 解析とコード生成、仮想マシンへの読み込みは時間に含めない。プログラムの出力は捨てる。
+    pub fn run(src: &str, options: &GeneratorOptions, runs: usize) -> Result<Self> {
+        let ast = syntax_analyzer::parse_str(src)?;
+        let interpreter = Timing::measure(runs, || {
+            let start = Instant::now();
+            ASTInterpreter::interpret(&ast, &mut io::sink())?;
+            Ok(start.elapsed())
+        })?;
+
+        let code = CodeGenerator::generate_with(&ast, options)?;
+        let vm = Timing::measure(runs, || {
+            // 変数の値が残らないように毎回読み込み直す
+            let mut vm = VirtualMachineInterpreter::load(code.lines())?;
+            let start = Instant::now();
+            vm.execute(&mut io::sink())?;
+            Ok(start.elapsed())
+        })?;
+
+        // レジスタマシンは実行のたびにレジスタを 0 に戻すので、読み込みは 1 回でよい
+        let mut machine = RegisterMachine::new(Program::lower(&ast)?)?;
+        let register = Timing::measure(runs, || {
+            let start = Instant::now();
+            machine.execute(&mut io::sink())?;
+            Ok(start.elapsed())
+        })?;
+
+        Ok(Bench {
+            interpreter,
+            vm,
+            register,
+        })
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for Bench {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>5} {:>12} {:>12} {:>12}",
+            "backend", "runs", "mean (ms)", "min (ms)", "max (ms)"
+        )?;
+        for (name, timing) in &[
+            ("interpreter", &self.interpreter),
+            ("vm", &self.vm),
+            ("register", &self.register),
+        ] {
+            writeln!(
+                f,
+                "{:<12} {:>5} {:>12.3} {:>12.3} {:>12.3}",
+                name,
+                timing.runs.len(),
+                millis(timing.mean()),
+                millis(timing.min()),
+                millis(timing.max())
+            )?;
+        }
+        for (name, timing) in &[("vm", &self.vm), ("register", &self.register)] {
+            let mean = millis(timing.mean());
+            if mean > 0.0 {
+                writeln!(
+                    f,
+                    "{} is {:.2}x as fast as interpreter",
+                    name,
+                    millis(self.interpreter.mean()) / mean
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing() {
+        let timing = Timing {
+            runs: vec![
+                Duration::from_millis(3),
+                Duration::from_millis(1),
+                Duration::from_millis(2),
+            ],
+        };
+        assert_eq!(Duration::from_millis(6), timing.total());
+        assert_eq!(Duration::from_millis(2), timing.mean());
+        assert_eq!(Duration::from_millis(1), timing.min());
+        assert_eq!(Duration::from_millis(3), timing.max());
+
+        let empty = Timing { runs: Vec::new() };
+        assert_eq!(Duration::default(), empty.mean());
+    }
+
+    #[test]
+    fn test_bench() {
+        let src = "i = 0; while (i < 100) i = i + 1; print(i);";
+        let bench = Bench::run(src, &GeneratorOptions::default(), 3).unwrap();
+        assert_eq!(3, bench.interpreter.runs.len());
+        assert_eq!(3, bench.vm.runs.len());
+        assert_eq!(3, bench.register.runs.len());
+        let report = bench.to_string();
+        assert!(report.starts_with("backend"));
+        assert!(report.contains("\ninterpreter      3 "));
+        assert!(report.contains("\nvm               3 "));
+        assert!(report.contains("\nregister         3 "));
+
+        assert!(Bench::run("print(", &GeneratorOptions::default(), 3).is_err());
+        assert!(Bench::run("x = rand(0);", &GeneratorOptions::default(), 3).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use code_generator::GeneratorOptions;
+use compiler_driver::{read_source, Check};
+use lexical_analyzer::cli::{Command, Flag, Options, EXIT_FAILURE};
+use lexical_analyzer::error::*;
+
+use std::io::Write;
+use std::process;
+
+const COMMAND: Command = Command {
+    name: "check_pipeline",
+    description: "Runs tiny-language source with both the AST interpreter and the virtual machine, and reports where the outputs differ.",
+    flags: &[Flag::Optimize, Flag::Expected],
+};
+
+fn run(options: &Options) -> Result<()> {
+    let source = read_source(options)?;
+    let expected = options.read_expected()?;
+    let mut writer = options.create_output()?;
+
+    let generator_options = GeneratorOptions {
+        optimize: options.optimize,
+        ..Default::default()
+    };
+    let check =
+        Check::run(&source.text, &generator_options, expected).map_err(|e| source.relocate(e))?;
+    write!(writer, "{}", check)
+        .and_then(|_| writer.flush())
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+    if !check.is_ok() {
+        process::exit(EXIT_FAILURE);
+    }
+    Ok(())
+}
+
+fn main() {
+    COMMAND.main(run);
+}
@@ -0,0 +1,44 @@
+use ast_interpreter::{ASTInterpreter, Coverage, InterpreterOptions};
+use compiler_driver::read_source;
+use lexical_analyzer::cli::{Command, Flag, Options};
+use lexical_analyzer::error::*;
+use syntax_analyzer::ParserOptions;
+
+use std::io::Write;
+
+const COMMAND: Command = Command {
+    name: "coverage",
+    description: "Runs tiny-language source with the AST interpreter, then writes the source annotated with how many times each statement ran to stderr.",
+    flags: &[Flag::Dialect],
+};
+
+fn run(options: &Options) -> Result<()> {
+    let source = read_source(options)?;
+    let src = &source.text;
+    let mut writer = options.create_output()?;
+
+    let parser_options = ParserOptions {
+        dialect: options.dialect.clone(),
+        ..Default::default()
+    };
+    let ast =
+        syntax_analyzer::parse_str_with(src, &parser_options).map_err(|e| source.relocate(e))?;
+    let mut coverage = Coverage::new(&ast);
+    let result = ASTInterpreter::interpret_with_hooks(
+        &ast,
+        &mut writer,
+        &InterpreterOptions::default(),
+        &mut coverage,
+    );
+    writer
+        .flush()
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+
+    // エラーで止まった場合も、そこまでの実行回数を出力する
+    eprint!("{}", coverage.annotate(src));
+    result.map(|_| ()).map_err(|e| source.relocate(e))
+}
+
+fn main() {
+    COMMAND.main(run);
+}
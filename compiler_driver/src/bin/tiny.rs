@@ -0,0 +1,354 @@
+use code_generator::{CodeGenerator, GeneratorOptions};
+use compiler_driver::{
+    artifact_path, emit_with_diagnostics, output_diff, read_source, run_vm_with_input,
+    write_sections, Bench, Execution, Source, Watcher,
+};
+use lexical_analyzer::cli::{Command, Flag, Options, EXIT_SUCCESS, EXIT_USAGE};
+use lexical_analyzer::diagnostic::Diagnostics;
+use lexical_analyzer::error::*;
+use syntax_analyzer::ast_node::ASTNode;
+use syntax_analyzer::ParserOptions;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+const BENCH: Command = Command {
+    name: "tiny bench",
+    description: "Runs tiny-language source repeatedly with the AST interpreter, the virtual machine and the register machine, and reports how long each run took.",
+    flags: &[Flag::Optimize, Flag::Runs],
+};
+
+const LISTING: Command = Command {
+    name: "tiny listing",
+    description: "Compiles tiny-language source and writes virtual machine assembly with each statement's source line as a comment.",
+    flags: &[
+        Flag::Optimize,
+        Flag::Dialect,
+        Flag::Width,
+        Flag::DenyWarnings,
+    ],
+};
+
+const EMIT: Command = Command {
+    name: "tiny emit",
+    description: "Compiles tiny-language source and writes the tokens, AST, virtual machine assembly and bytecode next to the source file, or to the output with a header before each.",
+    flags: &[
+        Flag::Emit,
+        Flag::Optimize,
+        Flag::Dialect,
+        Flag::Width,
+        Flag::DenyWarnings,
+    ],
+};
+
+const RUN: Command = Command {
+    name: "tiny run",
+    description: "Compiles tiny-language source and runs it on the virtual machine. With --watch, reruns it whenever the source changes and shows how the output changed.",
+    flags: &[
+        Flag::Optimize,
+        Flag::Dialect,
+        Flag::Width,
+        Flag::Watch,
+        Flag::DenyWarnings,
+    ],
+};
+
+const LINT: Command = Command {
+    name: "tiny lint",
+    description: "Checks tiny-language source and reports unused variables, variables read before assignment, constant conditions, unreachable statements and chained comparisons.",
+    flags: &[Flag::Dialect, Flag::DenyWarnings],
+};
+
+type Run = fn(&Options) -> Result<()>;
+
+/// サブコマンドの名前と定義、実行する関数
+const SUBCOMMANDS: &[(&str, &Command, Run)] = &[
+    ("bench", &BENCH, bench),
+    ("emit", &EMIT, emit_artifacts),
+    ("listing", &LISTING, listing),
+    ("lint", &LINT, lint),
+    ("run", &RUN, run),
+];
+
+fn bench(options: &Options) -> Result<()> {
+    let source = read_source(options)?;
+    let mut writer = options.create_output()?;
+
+    let generator_options = GeneratorOptions {
+        optimize: options.optimize,
+        ..Default::default()
+    };
+    let bench = Bench::run(&source.text, &generator_options, options.runs)
+        .map_err(|e| source.relocate(e))?;
+    write!(writer, "{}", bench)
+        .and_then(|_| writer.flush())
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+}
+
+fn listing(options: &Options) -> Result<()> {
+    let source = read_source(options)?;
+    let src = &source.text;
+    let mut writer = options.create_output()?;
+
+    let ast = parse(&source, options, LISTING.name)?;
+    let generator_options = GeneratorOptions {
+        optimize: options.optimize,
+        width: options.dialect.width,
+        ..Default::default()
+    };
+    let code = CodeGenerator::generate_listing(&ast, src, &generator_options)?;
+    writeln!(writer, "{}", code)
+        .and_then(|_| writer.flush())
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+}
+
+fn emit_artifacts(options: &Options) -> Result<()> {
+    let source = read_source(options)?;
+    let parser_options = ParserOptions {
+        dialect: options.dialect.clone(),
+        ..Default::default()
+    };
+    let generator_options = GeneratorOptions {
+        optimize: options.optimize,
+        width: options.dialect.width,
+        ..Default::default()
+    };
+    let mut diagnostics = diagnostics(&source, options);
+    let result = emit_with_diagnostics(
+        &source.text,
+        &options.emit,
+        &parser_options,
+        &generator_options,
+        &mut diagnostics,
+    );
+    let reported = report(&source, options, EMIT.name, diagnostics);
+    let artifacts = result.map_err(|e| source.relocate(e))?;
+    reported?;
+
+    // ソースのファイルがあり、出力先の指定がなければ、ソースの隣に 1 つずつ書く
+    match (&options.input, &options.output) {
+        (Some(input), None) => {
+            for (artifact, bytes) in &artifacts {
+                let path = artifact_path(Path::new(input), *artifact);
+                fs::write(&path, bytes).map_err(|e| {
+                    CompileError::new(ErrorKind::IOError, format!("{}: {}", path.display(), e))
+                })?;
+            }
+            Ok(())
+        }
+        _ => {
+            let mut writer = options.create_output()?;
+            write_sections(&mut writer, &artifacts)?;
+            writer
+                .flush()
+                .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+        }
+    }
+}
+
+fn lint(options: &Options) -> Result<()> {
+    let source = read_source(options)?;
+    parse(&source, options, LINT.name).map(|_| ())
+}
+
+/// source のコメントで抑制する警告を読んだ Diagnostics
+fn diagnostics(source: &Source, options: &Options) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new(options.deny_warnings);
+    diagnostics.allow_comments_in(&source.text, &options.dialect);
+    diagnostics
+}
+
+/// 警告の位置を元のファイルの位置に直して、 command の警告として書き出す。
+/// --deny-warnings でエラーにした警告があれば Err を返す。
+fn report(
+    source: &Source,
+    options: &Options,
+    command: &str,
+    mut diagnostics: Diagnostics,
+) -> Result<()> {
+    diagnostics.relocate_columns(|line, column| source.column(line, column));
+    diagnostics.relocate(|line| {
+        source
+            .locate(line)
+            .map(|(file, line)| (file.to_string(), line))
+    });
+    options.report(command, &diagnostics)
+}
+
+/// source を解析し、解析と lint の警告を command の警告として書き出す。
+/// 解析のエラーは元のファイルの位置に直す。
+fn parse(source: &Source, options: &Options, command: &str) -> Result<ASTNode> {
+    let parser_options = ParserOptions {
+        dialect: options.dialect.clone(),
+        ..Default::default()
+    };
+    let mut diagnostics = diagnostics(source, options);
+    let result = syntax_analyzer::parse_str_with_diagnostics(
+        &source.text,
+        &parser_options,
+        &mut diagnostics,
+    );
+    if let Ok(ast) = &result {
+        syntax_analyzer::lint::check(ast, &mut diagnostics);
+    }
+    let reported = report(source, options, command, diagnostics);
+    let ast = result.map_err(|e| source.relocate(e))?;
+    reported?;
+    Ok(ast)
+}
+
+/// --watch でソースの変化を調べる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// --watch で表示する出力の変化の行数の上限
+const MAX_CHANGES: usize = 20;
+
+/// source を解析して仮想マシンで実行する。解析と実行のエラーは元のファイルの位置に直す。
+/// getc と geti は input から読む。
+fn compile_and_run(source: &Source, options: &Options, input: Box<dyn Read>) -> Result<Execution> {
+    let ast = parse(source, options, RUN.name)?;
+    let generator_options = GeneratorOptions {
+        optimize: options.optimize,
+        width: options.dialect.width,
+        ..Default::default()
+    };
+    let mut execution = run_vm_with_input(&ast, &generator_options, input);
+    execution.error = execution.error.map(|e| source.relocate(e));
+    Ok(execution)
+}
+
+fn run(options: &Options) -> Result<()> {
+    if options.watch {
+        return watch(options);
+    }
+    let source = read_source(options)?;
+    // ソースを標準入力から読んだ場合は、プログラムの入力は空になる
+    let execution = compile_and_run(&source, options, Box::new(io::stdin()))?;
+    let mut writer = options.create_output()?;
+    writer
+        .write_all(&execution.output)
+        .and_then(|_| writer.flush())
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+    if let Some(e) = execution.error {
+        return Err(e);
+    }
+    // exit 文の終了コードをプロセスの終了コードにする
+    if execution.exit_code != 0 {
+        process::exit(execution.exit_code);
+    }
+    Ok(())
+}
+
+/// ソースが変わるたびに実行し直し、前回の出力との違いを書く。
+/// エラーは表示するだけで、次の変更を待つ。 Ctrl-C で止める。
+fn watch(options: &Options) -> Result<()> {
+    let input = options
+        .input
+        .as_ref()
+        .ok_or_else(|| CompileError::new(ErrorKind::IOError, "--watch requires an input file"))?;
+    let mut writer = options.create_output()?;
+    let mut previous: Option<Vec<u8>> = None;
+    for count in 1.. {
+        let source = read_source(options);
+        let files = match &source {
+            Ok(source) => source.files().to_vec(),
+            Err(_) => vec![input.clone()],
+        };
+        // 実行中に変わった場合も次の実行に反映するように、実行の前の状態を覚える
+        let mut watcher = Watcher::new(&files);
+        // 実行し直すたびに同じ入力を渡せないので、 --watch の入力は空にする
+        let result =
+            source.and_then(|source| compile_and_run(&source, options, Box::new(io::empty())));
+
+        let report = match result {
+            Ok(execution) => {
+                let mut report = match &previous {
+                    None => format!(
+                        "=== run {} ===\n{}",
+                        count,
+                        String::from_utf8_lossy(&execution.output)
+                    ),
+                    Some(previous) => {
+                        let changes = output_diff(previous, &execution.output);
+                        let mut report = match changes.len() {
+                            0 => format!("=== run {}: output unchanged ===\n", count),
+                            n => format!("=== run {}: {} lines differ ===\n", count, n),
+                        };
+                        for change in changes.iter().take(MAX_CHANGES) {
+                            report += &format!("{}\n", change);
+                        }
+                        if changes.len() > MAX_CHANGES {
+                            report += &format!("... and {} more\n", changes.len() - MAX_CHANGES);
+                        }
+                        report
+                    }
+                };
+                if !report.ends_with('\n') {
+                    report.push('\n');
+                }
+                if let Some(e) = &execution.error {
+                    match e.location() {
+                        Some(location) => report += &format!("error: {}: {}\n", location, e),
+                        None => report += &format!("error: {}\n", e),
+                    }
+                }
+                if execution.exit_code != 0 {
+                    report += &format!("exit code {}\n", execution.exit_code);
+                }
+                previous = Some(execution.output);
+                report
+            }
+            Err(e) => match e.location() {
+                Some(location) => format!("=== run {} ===\nerror: {}: {}\n", count, location, e),
+                None => format!("=== run {} ===\nerror: {}\n", count, e),
+            },
+        };
+        write!(writer, "{}", report)
+            .and_then(|_| writer.flush())
+            .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+
+        while !watcher.changed() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok(())
+}
+
+fn usage() -> String {
+    let mut s =
+        "Compiles and runs tiny-language source.\n\nUsage: tiny <COMMAND> [OPTIONS]\n\nCommands:\n"
+            .to_string();
+    for (name, command, _) in SUBCOMMANDS {
+        s += &format!("    {:<10} {}\n", name, command.description);
+    }
+    s += "\nRun 'tiny <COMMAND> --help' for the options of each command.\n";
+    s
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let name = match args.next() {
+        Some(name) => name,
+        None => {
+            eprint!("{}", usage());
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if name == "-h" || name == "--help" {
+        print!("{}", usage());
+        process::exit(EXIT_SUCCESS);
+    }
+    match SUBCOMMANDS.iter().find(|(n, _, _)| *n == name) {
+        Some((_, command, run)) => command.main_with_args(args, run),
+        None => {
+            eprintln!("tiny: unknown command: {}\n", name);
+            eprint!("{}", usage());
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
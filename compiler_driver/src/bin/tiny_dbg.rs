@@ -0,0 +1,49 @@
+use compiler_driver::{read_source, Debugger};
+use lexical_analyzer::cli::{Command, Flag, Options};
+use lexical_analyzer::error::*;
+use syntax_analyzer::ParserOptions;
+
+use std::io::{stdin, BufRead, Write};
+
+const COMMAND: Command = Command {
+    name: "tiny_dbg",
+    description: "Debugs tiny-language source on the virtual machine. The source must be given as a file; debugger commands are read from stdin (type 'help' for the list).",
+    flags: &[Flag::Dialect],
+};
+
+fn run(options: &Options) -> Result<()> {
+    if options.input.is_none() {
+        return Err(CompileError::new(
+            ErrorKind::IOError,
+            "the source must be given as a file, because commands are read from stdin",
+        ));
+    }
+    let source = read_source(options)?;
+    let mut writer = options.create_output()?;
+
+    let parser_options = ParserOptions {
+        dialect: options.dialect.clone(),
+        ..Default::default()
+    };
+    let mut debugger =
+        Debugger::new(&source.text, &parser_options).map_err(|e| source.relocate(e))?;
+    let io_error = |e| CompileError::new(ErrorKind::IOError, e);
+    let mut lines = stdin().lock().lines();
+    loop {
+        write!(writer, "(tiny_dbg) ")
+            .and_then(|_| writer.flush())
+            .map_err(io_error)?;
+        let line = match lines.next() {
+            Some(line) => line.map_err(io_error)?,
+            None => break,
+        };
+        if !debugger.command(&line, &mut writer)? {
+            break;
+        }
+    }
+    writer.flush().map_err(io_error)
+}
+
+fn main() {
+    COMMAND.main(run);
+}
@@ -0,0 +1,310 @@
+use code_generator::{CodeGenerator, DebugInfo, GeneratorOptions};
+use lexical_analyzer::error::*;
+use syntax_analyzer::ParserOptions;
+use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::result;
+
+const HELP: &str = r#"break LINE   (b)  stop before the statement on LINE
+delete LINE  (d)  remove the breakpoint on LINE
+run, continue (r, c)
+                  run until a breakpoint or the end of the program
+step, next   (s, n)
+                  run the next statement
+print NAME   (p)  print the value of a variable
+info         (i)  print all variables and breakpoints
+list         (l)  print the current line
+quit         (q)  exit the debugger
+"#;
+
+/// 仮想マシンの上で、ソースの行単位で実行を止めるデバッガ
+pub struct Debugger {
+    vm: VirtualMachineInterpreter,
+    info: DebugInfo,
+    source: Vec<String>,
+    /// ブレークポイントを置いた行
+    breakpoints: BTreeSet<usize>,
+    /// 一度でも実行を始めたら true
+    started: bool,
+    /// halt に達したか、実行時エラーで止まったら true
+    finished: bool,
+}
+
+impl Debugger {
+    /// src をコンパイルして仮想マシンに読み込む。最初の命令の前で止まった状態になる。
+    pub fn new(src: &str, options: &ParserOptions) -> Result<Self> {
+        let ast = syntax_analyzer::parse_str_with(src, options)?;
+        let (code, info) = CodeGenerator::generate_debug(&ast, &GeneratorOptions::default())?;
+        Ok(Debugger {
+            vm: VirtualMachineInterpreter::load(code.lines())?,
+            info,
+            source: src.lines().map(|line| line.to_string()).collect(),
+            breakpoints: BTreeSet::new(),
+            started: false,
+            finished: false,
+        })
+    }
+
+    /// line にブレークポイントを置く。 line から始まる文がなければエラー。
+    pub fn set_breakpoint(&mut self, line: usize) -> result::Result<(), String> {
+        if self.info.addresses_of(line).is_empty() {
+            return Err(format!("no statement starts on line {}", line));
+        }
+        self.breakpoints.insert(line);
+        Ok(())
+    }
+
+    /// line のブレークポイントを外す。なければ false。
+    pub fn clear_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.remove(&line)
+    }
+
+    /// 次に実行する文の行
+    pub fn line(&self) -> Option<usize> {
+        self.info.line_at(self.vm.pc())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// 変数 name の現在の値
+    pub fn variable(&self, name: &str) -> Option<i64> {
+        self.info
+            .symbols
+            .variable(name)
+            .map(|index| self.vm.data()[index])
+    }
+
+    /// ブレークポイントのある文の前まで、またはプログラムの終わりまで実行する
+    pub fn cont(&mut self, out: &mut dyn Write) -> Result<()> {
+        if !self.started && self.at_breakpoint() {
+            self.started = true;
+            return Ok(());
+        }
+        self.run_until(out, Self::at_breakpoint)
+    }
+
+    /// 次の文の前まで実行する
+    pub fn step(&mut self, out: &mut dyn Write) -> Result<()> {
+        self.run_until(out, |debugger| {
+            debugger.info.is_statement_start(debugger.vm.pc())
+        })
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let pc = self.vm.pc();
+        self.info.is_statement_start(pc)
+            && self
+                .info
+                .line_at(pc)
+                .is_some_and(|line| self.breakpoints.contains(&line))
+    }
+
+    /// 少なくとも 1 命令実行し、 stop が true になるか終わるまで続ける
+    fn run_until<F>(&mut self, out: &mut dyn Write, stop: F) -> Result<()>
+    where
+        F: Fn(&Self) -> bool,
+    {
+        self.started = true;
+        while !self.finished {
+            match self.vm.step(out) {
+                Ok(true) if stop(self) => break,
+                Ok(true) => {}
+                Ok(false) => self.finished = true,
+                Err(e) => {
+                    self.finished = true;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 止まっている位置を "line 3: count = count + 1;" の形で書く
+    fn write_location(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        if self.finished {
+            return writeln!(out, "program finished");
+        }
+        match self.line() {
+            Some(line) => writeln!(
+                out,
+                "line {}: {}",
+                line,
+                self.source.get(line - 1).map_or("", |s| s.trim())
+            ),
+            None => writeln!(out, "at address {}", self.vm.pc()),
+        }
+    }
+
+    /// コマンドを 1 つ実行し、結果を out に書く。 quit なら false を返す。
+    /// プログラムの出力も out に書く。
+    pub fn command(&mut self, command: &str, out: &mut dyn Write) -> Result<bool> {
+        let io_error = |e| CompileError::new(ErrorKind::IOError, e);
+        let mut words = command.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(true),
+        };
+        let argument = words.next();
+        let line_argument = || argument.and_then(|s| s.parse::<usize>().ok());
+
+        match name {
+            "b" | "break" => match line_argument() {
+                Some(line) => match self.set_breakpoint(line) {
+                    Ok(()) => writeln!(out, "breakpoint at line {}", line),
+                    Err(message) => writeln!(out, "{}", message),
+                },
+                None => writeln!(out, "usage: break LINE"),
+            },
+            "d" | "delete" => match line_argument() {
+                Some(line) if self.clear_breakpoint(line) => {
+                    writeln!(out, "deleted breakpoint at line {}", line)
+                }
+                Some(line) => writeln!(out, "no breakpoint at line {}", line),
+                None => writeln!(out, "usage: delete LINE"),
+            },
+            "r" | "run" | "c" | "continue" | "s" | "step" | "n" | "next" => {
+                if self.finished {
+                    writeln!(out, "program finished")
+                } else {
+                    let result = if matches!(name, "r" | "run" | "c" | "continue") {
+                        self.cont(out)
+                    } else {
+                        self.step(out)
+                    };
+                    match result {
+                        Ok(()) if !self.finished && self.at_breakpoint() => {
+                            write!(out, "breakpoint, ").and_then(|_| self.write_location(out))
+                        }
+                        Ok(()) => self.write_location(out),
+                        Err(e) => writeln!(out, "error: {}", e),
+                    }
+                }
+            }
+            "p" | "print" => match argument {
+                Some(variable) => match self.info.symbols.variable(variable) {
+                    Some(index) => writeln!(out, "{} = {}", variable, self.vm.format_data(index)),
+                    None => writeln!(out, "unknown variable: {}", variable),
+                },
+                None => writeln!(out, "usage: print NAME"),
+            },
+            "i" | "info" => {
+                for (name, index) in &self.info.symbols.variables {
+                    writeln!(out, "{} = {}", name, self.vm.format_data(*index))
+                        .map_err(io_error)?;
+                }
+                for line in &self.breakpoints {
+                    writeln!(out, "breakpoint at line {}", line).map_err(io_error)?;
+                }
+                Ok(())
+            }
+            "l" | "list" => self.write_location(out),
+            "h" | "help" => write!(out, "{}", HELP),
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(out, "unknown command: {} (try 'help')", name),
+        }
+        .map_err(io_error)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_commands(src: &str, commands: &[&str]) -> String {
+        let mut debugger = Debugger::new(src, &ParserOptions::default()).unwrap();
+        let mut out = Vec::new();
+        for command in commands {
+            if !debugger.command(command, &mut out).unwrap() {
+                break;
+            }
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let src = r#"count = 1;
+while (count < 4) {
+    print(count, "\n");
+    count = count + 1;
+}
+done = 1;
+"#;
+        assert_eq!(
+            r#"breakpoint at line 4
+no statement starts on line 5
+1
+breakpoint, line 4: count = count + 1;
+count = 1
+2
+breakpoint, line 4: count = count + 1;
+count = 2
+deleted breakpoint at line 4
+3
+program finished
+count = 4
+done = 1
+unknown variable: other
+"#,
+            run_commands(
+                src,
+                &[
+                    "break 4",
+                    "b 5",
+                    "run",
+                    "p count",
+                    "c",
+                    "print count",
+                    "d 4",
+                    "c",
+                    "info",
+                    "p other",
+                    "quit",
+                    "c"
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_string_variable() {
+        let src = "s = \"a\\n\";\nn = 1;\nprint(s);\n";
+        assert_eq!(
+            "breakpoint at line 3\nbreakpoint, line 3: print(s);\ns = \"a\\n\"\nn = 1\nbreakpoint at line 3\n",
+            run_commands(src, &["b 3", "run", "info"])
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let src = "x = 1;\nif (x) {\n    x = x + 1;\n}\nprint(x);\n";
+        assert_eq!(
+            r#"line 1: x = 1;
+line 2: if (x) {
+line 3: x = x + 1;
+line 5: print(x);
+2program finished
+program finished
+"#,
+            run_commands(src, &["list", "s", "next", "s", "s", "s"])
+        );
+
+        // ブレークポイントが最初の文にあれば、実行する前に止まる
+        assert_eq!(
+            "breakpoint at line 1\nbreakpoint, line 1: x = 1;\nx = 0\n",
+            run_commands(src, &["b 1", "run", "p x"])
+        );
+
+        // 実行時エラーで止まる
+        let src = "x = 0;\ny = rand(x);\n";
+        assert_eq!(
+            "error: VirtualMachineError, rand: 0 is not positive\nprogram finished\n",
+            run_commands(src, &["run", "s"])
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use lexical_analyzer::error::*;
+
+use std::collections::HashMap;
+
+/// `#define NAME value` を展開したソース
+#[derive(Debug)]
+pub struct Expanded {
+    pub text: String,
+    /// 違う値での再定義
+    pub redefinitions: Vec<Redefinition>,
+    /// 展開後の列と元の列の対応
+    pub columns: ColumnMap,
+}
+
+/// 展開後のソースの列を、展開前の列に戻すための対応。
+/// 行は展開の前後で変わらないので、行ごとに置き換えた場所を覚えておく。
+#[derive(Debug, Default)]
+pub struct ColumnMap {
+    /// 行ごとの置き換え。列の順に並ぶ。
+    lines: Vec<Vec<Substitution>>,
+}
+
+/// 1 つの名前の置き換え。列は 0 からの文字数で、終わりは含まない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Substitution {
+    expanded: (usize, usize),
+    original: (usize, usize),
+}
+
+impl ColumnMap {
+    /// 展開後の line 行 column 列 (どちらも 1 から) の、展開前の列。
+    /// 置き換えた値の中の列は、置き換える前の名前の先頭の列にする。
+    pub fn original(&self, line: usize, column: usize) -> usize {
+        let substitutions = match line.checked_sub(1).and_then(|i| self.lines.get(i)) {
+            Some(substitutions) => substitutions,
+            None => return column,
+        };
+        let index = column.saturating_sub(1);
+        let mut original = index;
+        for substitution in substitutions {
+            let (start, end) = substitution.expanded;
+            if index < start {
+                break;
+            }
+            if index < end {
+                return substitution.original.0 + 1;
+            }
+            original = index - end + substitution.original.1;
+        }
+        original + 1
+    }
+}
+
+/// 違う値での再定義。行は 1 から。
+#[derive(Debug, PartialEq, Eq)]
+pub struct Redefinition {
+    pub name: String,
+    pub line: usize,
+    /// 前の定義の行
+    pub previous: usize,
+}
+
+/// `#define NAME value` の行を読み、それより後の NAME を (value) で置き換える。
+/// value はその行の残りで、それまでに定義した名前を展開してから覚える。
+/// 置き換えるのは識別子だけで、文字列や文字のリテラル、コメントの中はそのままにする。
+/// 行の数を変えないように、 #define の行は空行にする。
+pub fn expand_defines(src: &str) -> Result<Expanded> {
+    let mut defines: HashMap<String, (String, usize)> = HashMap::new();
+    let mut expanded = Expanded {
+        text: String::with_capacity(src.len()),
+        redefinitions: Vec::new(),
+        columns: ColumnMap::default(),
+    };
+    let mut in_comment = false;
+    for (index, line) in src.lines().enumerate() {
+        let line_number = index + 1;
+        let directive = line.trim_start();
+        if !in_comment && directive.starts_with('#') {
+            let error = |message: String| {
+                // 列は文字数で数える
+                let column = line[..line.len() - directive.len()].chars().count() + 1;
+                CompileError::new(ErrorKind::SyntaxError, message)
+                    .with_position(line_number, column)
+            };
+            let rest = directive
+                .strip_prefix("#define")
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .ok_or_else(|| error("unknown directive".to_string()))?
+                .trim();
+            let name_len = rest.find(|c: char| !is_alnum(c)).unwrap_or(rest.len());
+            let (name, value) = rest.split_at(name_len);
+            if !name.starts_with(is_alpha) {
+                return Err(error("a name is expected after #define".to_string()));
+            }
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(error(format!("a value is expected for {}", name)));
+            }
+            let (value, ends_in_comment) = substitute(value, &defines, false, &mut Vec::new());
+            if ends_in_comment {
+                return Err(error(format!("the value of {} is not closed", name)));
+            }
+            let value = format!("({})", value);
+            match defines.get(name) {
+                // 同じ値での再定義は何もしない
+                Some((previous, _)) if *previous == value => {}
+                Some(&(_, previous)) => {
+                    expanded.redefinitions.push(Redefinition {
+                        name: name.to_string(),
+                        line: line_number,
+                        previous,
+                    });
+                    defines.insert(name.to_string(), (value, line_number));
+                }
+                None => {
+                    defines.insert(name.to_string(), (value, line_number));
+                }
+            }
+        } else {
+            let mut substitutions = Vec::new();
+            let (line, ends_in_comment) =
+                substitute(line, &defines, in_comment, &mut substitutions);
+            expanded.text.push_str(&line);
+            in_comment = ends_in_comment;
+            expanded.columns.lines.resize(index, Vec::new());
+            expanded.columns.lines.push(substitutions);
+        }
+        expanded.text.push('\n');
+    }
+    Ok(expanded)
+}
+
+/// c が名前の先頭に使える文字なら true。
+/// unicode の方言の識別子も途中で切らないように、 ASCII 以外の文字も含める。
+fn is_alpha(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+/// c が名前の 2 文字目から使える文字なら true
+fn is_alnum(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+/// line の識別子のうち定義したものを置き換える。
+/// in_comment は line がコメントの中から始まるかで、 line の終わりでコメントの中にいるかも返す。
+/// 置き換えた場所は substitutions に足す。
+fn substitute(
+    line: &str,
+    defines: &HashMap<String, (String, usize)>,
+    mut in_comment: bool,
+    substitutions: &mut Vec<Substitution>,
+) -> (String, bool) {
+    let mut substituted = String::with_capacity(line.len());
+    let mut chars = line.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        substituted.push(c);
+        if in_comment {
+            if c == '*' && chars.peek().map(|&(_, c)| c) == Some('/') {
+                substituted.push('/');
+                chars.next();
+                in_comment = false;
+            }
+            continue;
+        }
+        match c {
+            '/' if chars.peek().map(|&(_, c)| c) == Some('*') => {
+                substituted.push('*');
+                chars.next();
+                in_comment = true;
+            }
+            '"' | '\'' => {
+                while let Some((_, d)) = chars.next() {
+                    substituted.push(d);
+                    match d {
+                        '\\' => {
+                            if let Some((_, e)) = chars.next() {
+                                substituted.push(e);
+                            }
+                        }
+                        d if d == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            c if is_alnum(c) => {
+                // 数の後に続く文字も 1 つの語として読み飛ばす
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, d)) = chars.peek() {
+                    if !is_alnum(d) {
+                        break;
+                    }
+                    substituted.push(d);
+                    end = i + d.len_utf8();
+                    chars.next();
+                }
+                if let Some((value, _)) = defines.get(&line[start..end]).filter(|_| is_alpha(c)) {
+                    substituted.truncate(substituted.len() - (end - start));
+                    let original = line[..start].chars().count();
+                    let expanded = substituted.chars().count();
+                    substitutions.push(Substitution {
+                        expanded: (expanded, expanded + value.chars().count()),
+                        original: (original, original + line[start..end].chars().count()),
+                    });
+                    substituted.push_str(value);
+                }
+            }
+            _ => {}
+        }
+    }
+    (substituted, in_comment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_defines() {
+        let src = r#"#define WIDTH 4
+  #define AREA WIDTH * HEIGHT_2
+/* #define WIDTH 5 */
+#define HEIGHT_2 3 + 1
+print(AREA, " WIDTH ", 'W', WIDTH2 * 2WIDTH * WIDTH); /* WIDTH
+WIDTH */ x = AREA;
+"#;
+        let expanded = expand_defines(src).unwrap();
+        assert_eq!(
+            r#"
+
+/* #define WIDTH 5 */
+
+print(((4) * HEIGHT_2), " WIDTH ", 'W', WIDTH2 * 2WIDTH * (4)); /* WIDTH
+WIDTH */ x = ((4) * HEIGHT_2);
+"#,
+            expanded.text
+        );
+        assert!(expanded.redefinitions.is_empty());
+
+        // 展開した結果は式の一部として読める
+        let expanded = expand_defines("#define 幅 2\nx = 幅 + 幅x + x幅;").unwrap();
+        assert_eq!("\nx = (2) + 幅x + x幅;\n", expanded.text);
+        let expanded = expand_defines("#define N 2 + 3\nprint(N * 2);").unwrap();
+        let ast = syntax_analyzer::parse_str(&expanded.text).unwrap();
+        assert_eq!(b"10", &crate::interpret(&ast).output[..]);
+    }
+
+    #[test]
+    fn test_columns() {
+        let src = "#define WIDTH 40\n#define 高さ WIDTH\nx = WIDTH + y;\n/*\n*/ z = 高さ(w, y);\n";
+        let expanded = expand_defines(src).unwrap();
+        assert_eq!(
+            "\n\nx = (40) + y;\n/*\n*/ z = ((40))(w, y);\n",
+            expanded.text
+        );
+        let columns = &expanded.columns;
+        // 置き換えより前
+        assert_eq!(1, columns.original(3, 1));
+        // 置き換えた値の中は名前の先頭
+        assert_eq!(5, columns.original(3, 5));
+        assert_eq!(5, columns.original(3, 8));
+        // 置き換えより後ろは長さの差だけずらす
+        assert_eq!(11, columns.original(3, 10));
+        assert_eq!(13, columns.original(3, 12));
+        assert_eq!(8, columns.original(5, 10));
+        assert_eq!(11, columns.original(5, 15));
+        // 置き換えのない行や範囲外はそのまま
+        assert_eq!(2, columns.original(4, 2));
+        assert_eq!(3, columns.original(9, 3));
+
+        // 解析のエラーの列。単項の + の後の ; で止まる
+        let e = syntax_analyzer::parse_str(&expanded.text.replace("+ y", "+ +")).unwrap_err();
+        let (line, column) = e.position().unwrap();
+        assert_eq!((3, 14), (line, columns.original(line, column)));
+    }
+
+    #[test]
+    fn test_redefinition() {
+        let expanded =
+            expand_defines("#define N 1\n#define N 1\n#define M N\n#define N 2\nx = M + N;\n")
+                .unwrap();
+        assert_eq!("\n\n\n\nx = ((1)) + (2);\n", expanded.text);
+        assert_eq!(
+            vec![Redefinition {
+                name: "N".to_string(),
+                line: 4,
+                previous: 1
+            }],
+            expanded.redefinitions
+        );
+
+        let e = expand_defines("x = 1;\n  #include \"a.t\"").unwrap_err();
+        assert_eq!("unknown directive", e.message());
+        assert_eq!(Some((2, 3)), e.position());
+        let e = expand_defines("\u{3000}#undef N").unwrap_err();
+        assert_eq!(Some((1, 2)), e.position());
+        let e = expand_defines("#define 1 2").unwrap_err();
+        assert_eq!("a name is expected after #define", e.message());
+        let e = expand_defines("#define N").unwrap_err();
+        assert_eq!("a value is expected for N", e.message());
+        let e = expand_defines("#define N 1 /*").unwrap_err();
+        assert_eq!("the value of N is not closed", e.message());
+    }
+}
@@ -0,0 +1,187 @@
+use code_generator::{CodeGenerator, GeneratorOptions};
+use lexical_analyzer::cli::Artifact;
+use lexical_analyzer::diagnostic::Diagnostics;
+use lexical_analyzer::error::*;
+use lexical_analyzer::token::TokenKind;
+use lexical_analyzer::LexicalAnalyzer;
+use syntax_analyzer::ParserOptions;
+use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// src をコンパイルし、 artifacts の中間表現をその順に返す。
+/// 各段階は後ろの中間表現に必要な場合だけ実行する。
+pub fn emit(
+    src: &str,
+    artifacts: &[Artifact],
+    parser_options: &ParserOptions,
+    generator_options: &GeneratorOptions,
+) -> Result<Vec<(Artifact, Vec<u8>)>> {
+    emit_with_diagnostics(
+        src,
+        artifacts,
+        parser_options,
+        generator_options,
+        &mut Diagnostics::default(),
+    )
+}
+
+/// emit と同じだが、 AST を作った場合は解析と lint の警告を diagnostics に記録する。
+pub fn emit_with_diagnostics(
+    src: &str,
+    artifacts: &[Artifact],
+    parser_options: &ParserOptions,
+    generator_options: &GeneratorOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<(Artifact, Vec<u8>)>> {
+    let wants = |artifact| artifacts.contains(&artifact);
+
+    let tokens = if wants(Artifact::Tokens) {
+        Some(tokens(src, parser_options)?)
+    } else {
+        None
+    };
+    let ast = if wants(Artifact::Ast) || wants(Artifact::Asm) || wants(Artifact::Bytecode) {
+        let ast = syntax_analyzer::parse_str_with_diagnostics(src, parser_options, diagnostics)?;
+        syntax_analyzer::lint::check(&ast, diagnostics);
+        Some(ast)
+    } else {
+        None
+    };
+    let code = match &ast {
+        Some(ast) if wants(Artifact::Asm) || wants(Artifact::Bytecode) => {
+            Some(CodeGenerator::generate_with(ast, generator_options)?)
+        }
+        _ => None,
+    };
+
+    artifacts
+        .iter()
+        .map(|&artifact| {
+            let bytes = match artifact {
+                Artifact::Tokens => tokens.clone().unwrap(),
+                Artifact::Ast => ast.as_ref().unwrap().to_string().into_bytes(),
+                Artifact::Asm => format!("{}\n", code.as_ref().unwrap()).into_bytes(),
+                Artifact::Bytecode => {
+                    VirtualMachineInterpreter::load(code.as_ref().unwrap().lines())?.to_bytes()
+                }
+            };
+            Ok((artifact, bytes))
+        })
+        .collect()
+}
+
+/// lexical_analyzer と同じ形式のトークン列
+fn tokens(src: &str, options: &ParserOptions) -> Result<Vec<u8>> {
+    let mut lex = LexicalAnalyzer::with_dialect(src.chars(), options.dialect.clone());
+    let mut out = String::new();
+    loop {
+        let token = lex.next_token()?;
+        out += &format!("{}\n", token);
+        if *token.kind() == TokenKind::EndOfInput {
+            return Ok(out.into_bytes());
+        }
+    }
+}
+
+/// source の隣に書き出す artifact のファイルのパス。拡張子だけを置き換える。
+pub fn artifact_path(source: &Path, artifact: Artifact) -> PathBuf {
+    source.with_extension(artifact.extension())
+}
+
+/// 中間表現を 1 つの出力にまとめて書く。各部分の前に "=== tokens ===" のような見出しを付ける。
+/// バイトコードはそのままでは読めないので、16 バイトごとの 16 進ダンプにする。
+pub fn write_sections(out: &mut dyn Write, artifacts: &[(Artifact, Vec<u8>)]) -> Result<()> {
+    let io_error = |e| CompileError::new(ErrorKind::IOError, e);
+    for (artifact, bytes) in artifacts {
+        writeln!(out, "=== {} ===", artifact.name()).map_err(io_error)?;
+        match artifact {
+            Artifact::Bytecode => {
+                for (i, chunk) in bytes.chunks(16).enumerate() {
+                    let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                    writeln!(out, "{:08x}  {}", i * 16, hex.join(" ")).map_err(io_error)?;
+                }
+            }
+            _ => {
+                out.write_all(bytes).map_err(io_error)?;
+                if !bytes.ends_with(b"\n") {
+                    writeln!(out).map_err(io_error)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit() {
+        let src = "x = 42;\nprint(x);\n";
+        let artifacts = emit(
+            src,
+            Artifact::ALL,
+            &ParserOptions::default(),
+            &GeneratorOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            Artifact::ALL,
+            &artifacts.iter().map(|(a, _)| *a).collect::<Vec<_>>()[..]
+        );
+
+        let text = |i: usize| String::from_utf8(artifacts[i].1.clone()).unwrap();
+        assert!(text(0).starts_with("1 1 Identifier x\n"));
+        assert!(text(0).ends_with("End_of_input\n"));
+        assert!(text(1).starts_with("Sequence\n"));
+        let code = CodeGenerator::generate(&syntax_analyzer::parse_str(src).unwrap()).unwrap();
+        assert_eq!(format!("{}\n", code), text(2));
+
+        // バイトコードは読み込んで実行できる
+        let mut vm = VirtualMachineInterpreter::load_bytes(&artifacts[3].1).unwrap();
+        let mut out = Vec::new();
+        vm.execute(&mut out).unwrap();
+        assert_eq!(b"42", &out[..]);
+
+        // 必要な段階だけ実行するので、構文エラーがあってもトークン列は書ける
+        let (parser_options, generator_options) = Default::default();
+        let broken = |artifact| emit("x = ;", &[artifact], &parser_options, &generator_options);
+        assert!(broken(Artifact::Tokens).is_ok());
+        let e = broken(Artifact::Asm).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::SyntaxError));
+    }
+
+    #[test]
+    fn test_artifact_path() {
+        assert_eq!(
+            PathBuf::from("dir/prog.lex"),
+            artifact_path(Path::new("dir/prog.t"), Artifact::Tokens)
+        );
+        assert_eq!(
+            PathBuf::from("prog.tvm"),
+            artifact_path(Path::new("prog"), Artifact::Bytecode)
+        );
+    }
+
+    #[test]
+    fn test_write_sections() {
+        let artifacts = vec![
+            (Artifact::Asm, b"halt".to_vec()),
+            (Artifact::Bytecode, (0..18).collect()),
+        ];
+        let mut out = Vec::new();
+        write_sections(&mut out, &artifacts).unwrap();
+        assert_eq!(
+            "=== asm ===
+halt
+=== bytecode ===
+00000000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f
+00000010  10 11
+",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use isa::random::Random;
+
+/// ランダムな正しいプログラムを作る。
+/// インタプリタと仮想マシンの実行結果を比べるためのもので、ループは必ず止まり、
+/// 変数は最初にすべて初期化する。 0 除算のような実行時エラーは起こりうる。
+pub struct ProgramGenerator {
+    random: Random,
+    /// 式や文の入れ子の深さの上限
+    max_depth: usize,
+    /// 1 つのブロックの文の数の上限
+    max_statements: usize,
+    /// 入れ子になっているループの数。ループの変数の名前に使う。
+    loops: usize,
+}
+
+/// 式や代入に使う変数
+const VARIABLES: [&str; 4] = ["a", "b", "c", "d"];
+
+/// 二項演算子。 && と || の右辺は、左辺によっては評価されない。
+const OPERATORS: [&str; 18] = [
+    "*", "/", "%", "+", "-", "<", "<=", ">", ">=", "==", "!=", "&&", "||", "&", "|", "^", "<<",
+    ">>",
+];
+
+/// 値が大きくなる場合も試すための定数
+const INTEGERS: [i32; 8] = [0, 1, 2, 3, 7, 10, 255, 2_147_483_647];
+
+impl ProgramGenerator {
+    pub fn new(seed: u32) -> Self {
+        ProgramGenerator {
+            random: Random::new(seed),
+            max_depth: 4,
+            max_statements: 5,
+            loops: 0,
+        }
+    }
+
+    /// 0 以上 n 未満の値
+    fn below(&mut self, n: usize) -> usize {
+        self.random.next(n as i32).unwrap() as usize
+    }
+
+    fn choose<'s, T>(&mut self, items: &'s [T]) -> &'s T {
+        &items[self.below(items.len())]
+    }
+
+    /// プログラムのソースを 1 つ作る
+    pub fn program(&mut self) -> String {
+        let mut src = String::new();
+        for variable in VARIABLES.iter() {
+            let value = self.integer();
+            src += &format!("{} = {};\n", variable, value);
+        }
+        src += &self.statements(0);
+        // 最後に変数の値を出力し、途中の計算の違いも見つける
+        for variable in VARIABLES.iter() {
+            src += &format!("print({}, \" \");\n", variable);
+        }
+        src
+    }
+
+    fn integer(&mut self) -> String {
+        let value = *self.choose(&INTEGERS);
+        match self.below(3) {
+            0 => format!("-{}", value),
+            1 => format!("'{}'", self.choose(&['a', 'Z', '0', ' '])),
+            _ => value.to_string(),
+        }
+    }
+
+    fn statements(&mut self, depth: usize) -> String {
+        let count = 1 + self.below(self.max_statements);
+        (0..count).map(|_| self.statement(depth)).collect()
+    }
+
+    fn statement(&mut self, depth: usize) -> String {
+        let indent = "    ".repeat(depth);
+        let kinds = if depth < self.max_depth { 7 } else { 4 };
+        match self.below(kinds) {
+            0 | 1 => {
+                let variable = self.choose(&VARIABLES);
+                let value = self.expression(0);
+                format!("{}{} = {};\n", indent, variable, value)
+            }
+            2 => {
+                let value = self.expression(0);
+                format!("{}print(\"[\", {}, \"]\\n\");\n", indent, value)
+            }
+            3 => {
+                // 印字できる文字にする
+                let value = self.expression(0);
+                format!("{}putc(65 + (({}) % 26 + 26) % 26);\n", indent, value)
+            }
+            4 => {
+                let condition = self.expression(0);
+                let then = self.statements(depth + 1);
+                let mut s = format!("{}if ({}) {{\n{}{}}}", indent, condition, then, indent);
+                if self.below(2) == 0 {
+                    let otherwise = self.statements(depth + 1);
+                    s += &format!(" else {{\n{}{}}}", otherwise, indent);
+                }
+                s + "\n"
+            }
+            _ => self.bounded_loop(depth, &indent),
+        }
+    }
+
+    /// 回数の決まったループ。ループの変数は本体の中で代入しない。
+    fn bounded_loop(&mut self, depth: usize, indent: &str) -> String {
+        let counter = format!("i{}", self.loops);
+        let count = self.below(4);
+        self.loops += 1;
+        let body = self.statements(depth + 1);
+        self.loops -= 1;
+        let inner = "    ".repeat(depth + 1);
+        if self.below(2) == 0 {
+            format!(
+                "{i}{c} = 0;\n{i}while ({c} < {n}) {{\n{b}{j}{c} = {c} + 1;\n{i}}}\n",
+                i = indent,
+                j = inner,
+                c = counter,
+                n = count,
+                b = body
+            )
+        } else {
+            format!(
+                "{i}{c} = 0;\n{i}do {{\n{b}{j}{c} = {c} + 1;\n{i}}} while ({c} < {n});\n",
+                i = indent,
+                j = inner,
+                c = counter,
+                n = count,
+                b = body
+            )
+        }
+    }
+
+    fn expression(&mut self, depth: usize) -> String {
+        let kinds = if depth < self.max_depth { 6 } else { 2 };
+        match self.below(kinds) {
+            0 => self.integer(),
+            1 => self.choose(&VARIABLES).to_string(),
+            2 => {
+                let operand = self.expression(depth + 1);
+                format!("{}({})", self.choose(&["-", "!", "+"]), operand)
+            }
+            3 => {
+                let limit = 1 + self.below(100);
+                format!("rand({})", limit)
+            }
+            _ => {
+                let lhs = self.expression(depth + 1);
+                let rhs = self.expression(depth + 1);
+                format!("({} {} {})", lhs, self.choose(&OPERATORS), rhs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Check;
+    use code_generator::GeneratorOptions;
+
+    #[test]
+    fn test_program() {
+        // 同じ種なら同じプログラムになる
+        let src = ProgramGenerator::new(7).program();
+        assert_eq!(src, ProgramGenerator::new(7).program());
+        assert_ne!(src, ProgramGenerator::new(8).program());
+        assert!(syntax_analyzer::parse_str(&src).is_ok(), "{}", src);
+    }
+
+    #[test]
+    fn test_interpreter_and_vm_agree() {
+        for optimize in [false, true].iter() {
+            let options = GeneratorOptions {
+                optimize: *optimize,
+                ..Default::default()
+            };
+            for seed in 1..=300 {
+                let src = ProgramGenerator::new(seed).program();
+                let check = Check::run(&src, &options, None).unwrap();
+                assert!(
+                    check.is_ok(),
+                    "seed {} (optimize: {})\n{}\n{}",
+                    seed,
+                    optimize,
+                    src,
+                    check
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::define::{expand_defines, ColumnMap};
+use lexical_analyzer::cli::Options;
+use lexical_analyzer::error::*;
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// include を展開したソースと、その各行が元のどのファイルの何行目かの対応
+#[derive(Debug)]
+pub struct Source {
+    pub text: String,
+    files: Vec<String>,
+    /// 展開後の行ごとの、 files の添字と元の行 (1 から)
+    lines: Vec<(usize, usize)>,
+    /// #define を展開した後の列と元の列の対応
+    columns: ColumnMap,
+}
+
+impl Source {
+    /// 展開後の line 行目 (1 から) のファイル名と、そのファイルでの行
+    pub fn locate(&self, line: usize) -> Option<(&str, usize)> {
+        let &(file, line) = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[file], line))
+    }
+
+    /// 展開したファイルの名前。最初が入力のファイルで、あとは include した順。
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// 展開後の line 行 column 列の、 #define を展開する前の列
+    pub fn column(&self, line: usize, column: usize) -> usize {
+        self.columns.original(line, column)
+    }
+
+    /// 展開後のソースの位置を持つエラーを、元のファイルの位置に直す
+    pub fn relocate(&self, e: CompileError) -> CompileError {
+        if e.file().is_some() {
+            return e;
+        }
+        let e = match e.position() {
+            Some((line, column)) => e.relocate_column(self.column(line, column)),
+            None => e,
+        };
+        match e.line() {
+            Some(line) => match self.locate(line) {
+                Some((file, line)) => e.relocate(file, line),
+                None => e,
+            },
+            _ => e,
+        }
+    }
+}
+
+/// 入力を読み、 include と #define を展開する。
+/// 標準入力から読んだソースの include は、カレントディレクトリからの相対パスで探す。
+/// #define の警告は標準エラー出力に書く。
+pub fn read_source(options: &Options) -> Result<Source> {
+    let src = options.read_input()?;
+    let name = options.input.as_deref().unwrap_or("<stdin>");
+    let mut source = preprocess(&src, name, |path| fs::read_to_string(path))?;
+    let expanded = expand_defines(&source.text).map_err(|e| source.relocate(e))?;
+    for redefinition in &expanded.redefinitions {
+        let at = |line| match source.locate(line) {
+            Some((file, line)) => format!("{}:{}", file, line),
+            None => format!("line {}", line),
+        };
+        eprintln!(
+            "warning: {}: {} is redefined (previous definition at {})",
+            at(redefinition.line),
+            redefinition.name,
+            at(redefinition.previous)
+        );
+    }
+    source.text = expanded.text;
+    source.columns = expanded.columns;
+    Ok(source)
+}
+
+/// src の `include "file.t";` を、そのファイルの中身で置き換える。
+/// name は src のファイル名で、 include するファイルは include した側のファイルからの相対パスで探す。
+/// include は 1 行に 1 つだけ書け、コメントの中のものは無視する。
+pub fn preprocess<F>(src: &str, name: &str, read: F) -> Result<Source>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    let mut preprocessor = Preprocessor {
+        read,
+        source: Source {
+            text: String::new(),
+            files: Vec::new(),
+            lines: Vec::new(),
+            columns: ColumnMap::default(),
+        },
+        stack: Vec::new(),
+    };
+    preprocessor.expand(src, &normalize(Path::new(name)))?;
+    Ok(preprocessor.source)
+}
+
+struct Preprocessor<F> {
+    read: F,
+    source: Source,
+    /// 展開中のファイル。 include の循環を見つけるために使う。
+    stack: Vec<PathBuf>,
+}
+
+impl<F> Preprocessor<F>
+where
+    F: FnMut(&Path) -> io::Result<String>,
+{
+    fn expand(&mut self, src: &str, path: &Path) -> Result<()> {
+        self.stack.push(path.to_path_buf());
+        let file = self.source.files.len();
+        self.source.files.push(path.display().to_string());
+
+        let mut in_comment = false;
+        for (index, line) in src.lines().enumerate() {
+            let starts_in_comment = in_comment;
+            in_comment = ends_in_comment(line, in_comment);
+            let directive = if starts_in_comment {
+                None
+            } else {
+                include_directive(line)
+            };
+            let included = match directive {
+                Some(included) => included.map_err(|message| {
+                    CompileError::new(ErrorKind::SyntaxError, message)
+                        .with_position(index + 1, 1)
+                        .relocate(&self.source.files[file], index + 1)
+                })?,
+                None => {
+                    self.source.text.push_str(line);
+                    self.source.text.push('\n');
+                    self.source.lines.push((file, index + 1));
+                    continue;
+                }
+            };
+
+            let included = normalize(&path.parent().unwrap_or(Path::new("")).join(included));
+            if let Some(start) = self.stack.iter().position(|p| *p == included) {
+                let cycle: Vec<String> = self.stack[start..]
+                    .iter()
+                    .chain(Some(&included))
+                    .map(|p| p.display().to_string())
+                    .collect();
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    format!("include cycle: {}", cycle.join(" -> ")),
+                )
+                .with_position(index + 1, 1)
+                .relocate(&self.source.files[file], index + 1));
+            }
+            let src = (self.read)(&included).map_err(|e| {
+                CompileError::new(
+                    ErrorKind::IOError,
+                    format!("cannot include {}: {}", included.display(), e),
+                )
+                .with_position(index + 1, 1)
+                .relocate(&self.source.files[file], index + 1)
+            })?;
+            self.expand(&src, &included)?;
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/// line が include の行なら、 include するファイル名を返す
+fn include_directive(line: &str) -> Option<std::result::Result<String, String>> {
+    let rest = line.trim_start().strip_prefix("include")?;
+    if !rest.starts_with(|c: char| c == '"' || c.is_whitespace()) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    if !rest.starts_with('"') {
+        // include という名前の変数への代入など
+        return None;
+    }
+    Some(match escape::unquote(rest) {
+        Ok((name, rest)) if rest.trim() == ";" => Ok(name),
+        Ok(_) => Err("';' is expected after include".to_string()),
+        Err(e) => Err(format!("invalid file name in include: {}", e)),
+    })
+}
+
+/// line の終わりでブロックコメントの中にいるなら true
+fn ends_in_comment(line: &str, mut in_comment: bool) -> bool {
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                in_comment = false;
+            }
+            continue;
+        }
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                in_comment = true;
+            }
+            '"' | '\'' => {
+                // リテラルの中の /* はコメントではない
+                while let Some(d) = chars.next() {
+                    match d {
+                        '\\' => {
+                            chars.next();
+                        }
+                        d if d == c => break,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    in_comment
+}
+
+/// . と .. を取り除き、同じファイルが同じパスになるようにする
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn files(files: &[(&str, &str)]) -> impl FnMut(&Path) -> io::Result<String> {
+        let files: HashMap<PathBuf, String> = files
+            .iter()
+            .map(|(name, src)| (PathBuf::from(name), src.to_string()))
+            .collect();
+        move |path| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not found"))
+        }
+    }
+
+    #[test]
+    fn test_preprocess() {
+        let read = files(&[
+            ("samples/lib/size.t", "width = 4;\nheight = 2;\n"),
+            (
+                "samples/common.t",
+                "include \"lib/size.t\";\nsize = width * height;\n",
+            ),
+        ]);
+        let src = r#"/* include "ignored.t"; */
+include "common.t";
+print(size, "\n");
+"#;
+        let source = preprocess(src, "samples/./main.t", read).unwrap();
+        assert_eq!(
+            "/* include \"ignored.t\"; */\nwidth = 4;\nheight = 2;\nsize = width * height;\nprint(size, \"\\n\");\n",
+            source.text
+        );
+        assert_eq!(Some(("samples/main.t", 1)), source.locate(1));
+        assert_eq!(Some(("samples/lib/size.t", 2)), source.locate(3));
+        assert_eq!(Some(("samples/common.t", 2)), source.locate(4));
+        assert_eq!(Some(("samples/main.t", 3)), source.locate(5));
+        assert_eq!(None, source.locate(6));
+        assert_eq!(
+            &["samples/main.t", "samples/common.t", "samples/lib/size.t"],
+            source.files()
+        );
+
+        // 解析のエラーは元のファイルの位置で報告する
+        let read = files(&[("lib.t", "x = 1;\ny = (x;\n")]);
+        let source = preprocess("include \"lib.t\";\nprint(y);\n", "main.t", read).unwrap();
+        let e = source.relocate(syntax_analyzer::parse_str(&source.text).unwrap_err());
+        assert_eq!(Some("lib.t"), e.file());
+        assert_eq!(2, e.position().unwrap().0);
+
+        // #define を展開したソースのエラーは展開前の列で報告する
+        let read = files(&[("lib.t", "#define WIDTH 40\n")]);
+        let mut source =
+            preprocess("include \"lib.t\";\nx = WIDTH + );\n", "main.t", read).unwrap();
+        let expanded = expand_defines(&source.text).unwrap();
+        source.text = expanded.text;
+        source.columns = expanded.columns;
+        let e = source.relocate(syntax_analyzer::parse_str(&source.text).unwrap_err());
+        assert_eq!(Some("main.t:2:13".to_string()), e.location());
+
+        // include という名前の変数は使える
+        let source = preprocess("include = 1;\n", "main.t", files(&[])).unwrap();
+        assert_eq!("include = 1;\n", source.text);
+    }
+
+    #[test]
+    fn test_preprocess_errors() {
+        let read = files(&[
+            ("a.t", "include \"b.t\";\n"),
+            ("b.t", "x = 1;\ninclude \"./a.t\";\n"),
+        ]);
+        let e = preprocess("include \"a.t\";", "main.t", read).unwrap_err();
+        assert_eq!("include cycle: a.t -> b.t -> a.t", e.message());
+        assert_eq!((Some("b.t"), Some((2, 1))), (e.file(), e.position()));
+
+        let e = preprocess("x = 1;\ninclude \"none.t\";", "main.t", files(&[])).unwrap_err();
+        assert_eq!("cannot include none.t: not found", e.message());
+        assert_eq!((Some("main.t"), Some((2, 1))), (e.file(), e.position()));
+
+        let e = preprocess("include \"a.t\"", "main.t", files(&[])).unwrap_err();
+        assert_eq!("';' is expected after include", e.message());
+        let e = preprocess("include \"a.t", "main.t", files(&[])).unwrap_err();
+        assert_eq!(
+            "invalid file name in include: '\"' is not found",
+            e.message()
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use ast_interpreter::ASTInterpreter;
+use code_generator::{CodeGenerator, GeneratorOptions};
+use lexical_analyzer::error::*;
+use lexical_analyzer::report::RunReport;
+use syntax_analyzer::ast_node::ASTNode;
+use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+use std::fmt;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+
+mod bench;
+mod debugger;
+mod define;
+mod emit;
+mod generator;
+mod include;
+mod pipeline;
+mod watch;
+
+pub use bench::{Bench, Timing};
+pub use debugger::Debugger;
+pub use define::{expand_defines, Expanded, Redefinition};
+pub use emit::{artifact_path, emit, emit_with_diagnostics, write_sections};
+pub use generator::ProgramGenerator;
+pub use include::{preprocess, read_source, Source};
+pub use pipeline::{Artifact, Generate, Interpret, Lex, Parse, Pipeline, RunVm, Stage};
+pub use watch::{output_diff, LineChange, Watcher};
+
+/// 実行の結果。エラーで止まった場合も、それまでの出力を持つ。
+#[derive(Debug)]
+pub struct Execution {
+    pub output: Vec<u8>,
+    pub error: Option<CompileError>,
+    /// exit 文で終わったならその終了コード。それ以外は 0。
+    pub exit_code: i32,
+    /// 最後まで実行できたときの統計と変数の値
+    pub report: Option<RunReport>,
+}
+
+impl Execution {
+    /// f に出力先を渡して実行する。f は実行の統計を返す。
+    /// f が panic した場合は、 kind の段階の処理系の誤り (ErrorKind::InternalError) として扱う。
+    fn capture<F>(kind: ErrorKind, f: F) -> Self
+    where
+        F: FnOnce(&mut Vec<u8>) -> Result<RunReport>,
+    {
+        let mut output = Vec::new();
+        let mut report = None;
+        let error = match panic::catch_unwind(AssertUnwindSafe(|| f(&mut output))) {
+            Ok(Ok(r)) => {
+                report = Some(r);
+                None
+            }
+            Ok(Err(e)) => Some(e),
+            Err(cause) => {
+                let message = cause
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| cause.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Some(CompileError::new(
+                    ErrorKind::InternalError,
+                    format!("{} panicked: {}", kind.stage(), message),
+                ))
+            }
+        };
+        Execution {
+            output,
+            error,
+            exit_code: report.as_ref().map_or(0, |report| report.exit_code),
+            report,
+        }
+    }
+}
+
+/// AST インタプリタで実行する
+pub fn interpret(ast: &ASTNode) -> Execution {
+    Execution::capture(ErrorKind::InterpretationError, |out| {
+        ASTInterpreter::interpret(ast, out)
+    })
+}
+
+/// コードを生成し、仮想マシンで実行する。 getc と geti は空の入力から読む。
+pub fn run_vm(ast: &ASTNode, options: &GeneratorOptions) -> Execution {
+    run_vm_with_input(ast, options, Box::new(io::empty()))
+}
+
+/// run_vm と同じだが、 getc と geti は input から読む
+pub fn run_vm_with_input(
+    ast: &ASTNode,
+    options: &GeneratorOptions,
+    input: Box<dyn Read>,
+) -> Execution {
+    Execution::capture(ErrorKind::VirtualMachineError, |out| {
+        // 実行時のエラーにソースの行を付けるため、命令と行の対応も作る
+        let (code, debug_info) = CodeGenerator::generate_debug(ast, options)?;
+        let mut vm = VirtualMachineInterpreter::load(code.lines())?;
+        vm.set_lines(debug_info.lines);
+        vm.set_input(input);
+        vm.execute(out)
+    })
+}
+
+/// 2 つの出力が最初に食い違う位置
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    /// 先頭からのバイト数
+    pub offset: usize,
+    /// 1 から始まる行数
+    pub line: usize,
+    /// 1 から始まる列数 (バイト単位)
+    pub column: usize,
+}
+
+/// a と b が異なれば、最初に食い違う位置を返す。
+pub fn first_difference(a: &[u8], b: &[u8]) -> Option<Difference> {
+    let offset = match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(offset) => offset,
+        None if a.len() == b.len() => return None,
+        None => a.len().min(b.len()),
+    };
+    let line_start = a[..offset]
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |p| p + 1);
+    Some(Difference {
+        offset,
+        line: 1 + a[..offset].iter().filter(|&&c| c == b'\n').count(),
+        column: 1 + offset - line_start,
+    })
+}
+
+/// インタプリタと仮想マシンの実行結果を比べる
+#[derive(Debug)]
+pub struct Check {
+    pub interpreter: Execution,
+    pub vm: Execution,
+    /// 期待する出力。None なら 2 つの実行結果だけを比べる。
+    pub expected: Option<Vec<u8>>,
+}
+
+impl Check {
+    /// src を解析して両方の方法で実行する。
+    /// 解析に失敗した場合と、どちらかが panic した場合は Err を返す。
+    /// panic は出力の食い違いではなく処理系の誤りなので、比べずに報告する。
+    pub fn run(src: &str, options: &GeneratorOptions, expected: Option<Vec<u8>>) -> Result<Self> {
+        let ast = syntax_analyzer::parse_str(src)?;
+        Self::compare(interpret(&ast), run_vm(&ast, options), expected)
+    }
+
+    fn compare(interpreter: Execution, vm: Execution, expected: Option<Vec<u8>>) -> Result<Self> {
+        let mut check = Check {
+            interpreter,
+            vm,
+            expected,
+        };
+        for execution in [&mut check.interpreter, &mut check.vm] {
+            if let Some(ErrorKind::InternalError) = execution.error.as_ref().map(|e| e.kind()) {
+                return Err(execution.error.take().unwrap());
+            }
+        }
+        Ok(check)
+    }
+
+    /// 食い違いの一覧。空なら一致している。
+    pub fn mismatches(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let mut compare = |name0: &str, output0: &[u8], name1: &str, output1: &[u8]| {
+            if let Some(d) = first_difference(output0, output1) {
+                mismatches.push(format!(
+                    "{} and {} differ at line {}, column {}: {:?} / {:?}",
+                    name0,
+                    name1,
+                    d.line,
+                    d.column,
+                    excerpt(output0, d.offset),
+                    excerpt(output1, d.offset)
+                ));
+            }
+        };
+        compare(
+            "interpreter",
+            &self.interpreter.output,
+            "vm",
+            &self.vm.output,
+        );
+        if let Some(expected) = &self.expected {
+            compare(
+                "expected",
+                expected,
+                "interpreter",
+                &self.interpreter.output,
+            );
+            compare("expected", expected, "vm", &self.vm.output);
+        }
+
+        match (&self.interpreter.error, &self.vm.error) {
+            (Some(e), None) => mismatches.push(format!("only interpreter failed: {}", e)),
+            (None, Some(e)) => mismatches.push(format!("only vm failed: {}", e)),
+            _ => {}
+        }
+        if self.interpreter.exit_code != self.vm.exit_code {
+            mismatches.push(format!(
+                "exit codes differ: interpreter {} / vm {}",
+                self.interpreter.exit_code, self.vm.exit_code
+            ));
+        }
+        mismatches
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.mismatches().is_empty()
+    }
+}
+
+/// 食い違った位置から最大 20 バイト
+fn excerpt(output: &[u8], offset: usize) -> String {
+    let end = output.len().min(offset + 20);
+    String::from_utf8_lossy(&output[offset.min(end)..end]).into_owned()
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, execution) in &[("interpreter", &self.interpreter), ("vm", &self.vm)] {
+            write!(f, "{}: {} bytes", name, execution.output.len())?;
+            if execution.exit_code != 0 {
+                write!(f, ", exit code {}", execution.exit_code)?;
+            }
+            if let Some(report) = &execution.report {
+                write!(f, ", {} steps", report.steps)?;
+            }
+            match &execution.error {
+                Some(e) => writeln!(f, ", {}", e)?,
+                None => writeln!(f)?,
+            }
+        }
+        let mismatches = self.mismatches();
+        if mismatches.is_empty() {
+            writeln!(f, "ok")
+        } else {
+            for m in &mismatches {
+                writeln!(f, "{}", m)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(None, first_difference(b"abc", b"abc"));
+        assert_eq!(
+            Some(Difference {
+                offset: 5,
+                line: 2,
+                column: 2
+            }),
+            first_difference(b"abc\nde", b"abc\ndx")
+        );
+        assert_eq!(
+            Some(Difference {
+                offset: 3,
+                line: 1,
+                column: 4
+            }),
+            first_difference(b"abc", b"abc\n")
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let src = r#"i = 0; while (i < 3) { print(i, "\n"); i = i + 1; }"#;
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"0\n1\n2\n", &check.vm.output[..]);
+
+        let check = Check::run(
+            src,
+            &GeneratorOptions::default(),
+            Some(b"0\n1\n3\n".to_vec()),
+        )
+        .unwrap();
+        assert_eq!(2, check.mismatches().len());
+        assert!(check.mismatches()[0].contains("line 3, column 1"));
+
+        let check = Check {
+            interpreter: Execution {
+                output: b"1".to_vec(),
+                error: Some(CompileError::new(ErrorKind::InterpretationError, "error")),
+                exit_code: 0,
+                report: None,
+            },
+            vm: Execution {
+                output: b"1".to_vec(),
+                error: None,
+                exit_code: 0,
+                report: Some(RunReport::default()),
+            },
+            expected: None,
+        };
+        assert_eq!(
+            vec!["only interpreter failed: InterpretationError, error".to_string()],
+            check.mismatches()
+        );
+
+        assert!(Check::run("print(", &GeneratorOptions::default(), None).is_err());
+
+        // panic は食い違いではなく処理系の誤りとして返す
+        let vm = Execution::capture(ErrorKind::VirtualMachineError, |_| panic!("broken"));
+        assert!(matches!(
+            vm.error.as_ref().unwrap().kind(),
+            ErrorKind::InternalError
+        ));
+        let interpreter = Execution::capture(ErrorKind::InterpretationError, |out| {
+            out.extend_from_slice(b"1");
+            Ok(RunReport::default())
+        });
+        let e = Check::compare(interpreter, vm, None).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::InternalError));
+        assert_eq!("vm panicked: broken", e.message());
+
+        // rand はどちらの実行方法でも同じ列になる
+        let src = "i = 0; while (i < 10) { print(rand(1000), \" \"); i = i + 1; }";
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+
+        // 引用符やタブを含む文字列も、コード生成と仮想マシンの読み込みで変わらない
+        let src = "print(\"say \\\"hi\\\"\tand\\\\\\n\");";
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"say \"hi\"\tand\\\n", &check.vm.output[..]);
+
+        // putc に並べた式は順に出力する
+        let src = "c = 72; putc(c, c + 33, 10);";
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"Hi\n", &check.vm.output[..]);
+
+        let src = r#"i = 3; printf("%d%% of %s: %c\n", i * 10, "abc", 'a' + i);"#;
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"30% of abc: d\n", &check.vm.output[..]);
+
+        // 文字列を入れる変数
+        let src = r#"a = "tick"; b = "tock"; i = 0; while (i < 4) { print(a, "\n"); t = a; a = b; b = t; i = i + 1; }"#;
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"tick\ntock\ntick\ntock\n", &check.vm.output[..]);
+
+        // exit の終了コードも比べる
+        let src = "i = 0; while (1) { i = i + 1; if (i * i > 50) exit(i); }";
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(8, check.interpreter.exit_code);
+        assert_eq!(8, check.vm.exit_code);
+        assert!(check.to_string().contains("vm: 0 bytes, exit code 8, "));
+        for execution in &[&check.interpreter, &check.vm] {
+            let report = execution.report.as_ref().unwrap();
+            assert_eq!(8, report.exit_code);
+            assert!(report.steps > 0);
+        }
+        assert_eq!(
+            Some("8"),
+            check.interpreter.report.as_ref().unwrap().variable("i")
+        );
+
+        // assert はどちらの実行方法でも同じメッセージで止まる
+        let src =
+            "i = 0;\nwhile (1) {\n    print(i);\n    assert(i < 3, \"i < 3\");\n    i = i + 1;\n}";
+        let check = Check::run(src, &GeneratorOptions::default(), None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"0123", &check.vm.output[..]);
+        for execution in &[&check.interpreter, &check.vm] {
+            assert_eq!(
+                "assertion failed at line 4: i < 3",
+                execution.error.as_ref().unwrap().message()
+            );
+        }
+
+        // 共通部分式をまとめても同じ出力になる
+        let src =
+            "i = 1; while (i * i + i < 50) { print((i * i + i) * (i * i + i), \" \"); i = i + 1; }";
+        let options = GeneratorOptions {
+            optimize: true,
+            ..Default::default()
+        };
+        let check = Check::run(src, &options, None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"4 36 144 400 900 1764 ", &check.vm.output[..]);
+
+        // ループの前に移した式でも同じ出力になる。ループが 1 回も回らない場合も含む。
+        let src = r#"n = 3; k = 2; y = 0;
+while (y < n * k) {
+    x = 0;
+    while (x < n + k) { x = x + 1; }
+    while (x < n) { print(100 / (x - n)); }
+    print(y * (n + k) + x, " ");
+    y = y + 1;
+}"#;
+        let check = Check::run(src, &options, None).unwrap();
+        assert!(check.is_ok(), "{}", check);
+        assert_eq!(b"5 10 15 20 25 30 ", &check.vm.output[..]);
+
+        // 整数の演算は桁あふれしても折り返す。回らないループから移した式でもエラーにならない。
+        let src = r#"b = 1; i = 0;
+while (i < 0) { a = 2147483647 + b; i = i + 1; }
+print(2147483647 + b, " ", -(-2147483647 - b), " ", 65536 * 65536);"#;
+        for optimize in [false, true].iter() {
+            let options = GeneratorOptions {
+                optimize: *optimize,
+                ..Default::default()
+            };
+            let check = Check::run(src, &options, None).unwrap();
+            assert!(check.is_ok(), "{}", check);
+            assert_eq!(b"-2147483648 -2147483648 0", &check.vm.output[..]);
+        }
+    }
+
+    #[test]
+    fn test_width() {
+        // 64 ビットの幅なら 20 の階乗も桁あふれしない
+        let src = "f = 1; i = 1; while (i <= 20) { f = f * i; i = i + 1; } print(f);";
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        for optimize in [false, true] {
+            let options = GeneratorOptions {
+                optimize,
+                width: lexical_analyzer::width::Width::W64,
+                ..Default::default()
+            };
+            let execution = run_vm(&ast, &options);
+            assert!(execution.error.is_none());
+            assert_eq!(b"2432902008176640000", &execution.output[..]);
+        }
+        let execution = run_vm(&ast, &GeneratorOptions::default());
+        assert_eq!(b"-2102132736", &execution.output[..]);
+    }
+
+    #[test]
+    fn test_error_line() {
+        // 実行時のエラーにはソースの行が付く
+        let ast = syntax_analyzer::parse_str("x = 0;\nprint(1);\ny = 1 / x;").unwrap();
+        for optimize in [false, true] {
+            let options = GeneratorOptions {
+                optimize,
+                ..Default::default()
+            };
+            let e = run_vm(&ast, &options).error.unwrap();
+            assert_eq!("division by zero", e.message());
+            assert_eq!(Some(3), e.line());
+        }
+    }
+
+    #[test]
+    fn test_run_vm_with_input() {
+        // A + B: 入力の 2 つの整数の和を書く
+        let ast =
+            syntax_analyzer::parse_str(r#"a = geti(); b = geti(); print(a + b, "\n");"#).unwrap();
+        let execution =
+            run_vm_with_input(&ast, &GeneratorOptions::default(), Box::new(&b"2 3\n"[..]));
+        assert!(execution.error.is_none());
+        assert_eq!(b"5\n", &execution.output[..]);
+
+        // 入力がなければ geti はエラー、 getc は -1
+        let execution = run_vm(&ast, &GeneratorOptions::default());
+        assert_eq!(
+            "input error: expected an integer, found end of input",
+            execution.error.unwrap().message()
+        );
+        let ast = syntax_analyzer::parse_str("print(getc());").unwrap();
+        assert_eq!(
+            b"-1",
+            &run_vm(&ast, &GeneratorOptions::default()).output[..]
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use crate::Execution;
+use code_generator::{CodeGenerator, GeneratorOptions};
+use lexical_analyzer::error::*;
+use lexical_analyzer::token::Token;
+use syntax_analyzer::ast_node::ASTNode;
+use syntax_analyzer::{ParserOptions, SyntaxAnalyzer};
+use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+use std::fmt;
+
+/// 段階の間で受け渡す値。
+/// `--emit` で選ぶ lexical_analyzer::cli::Artifact と違い、中身を持つ。
+#[derive(Debug)]
+pub enum Artifact {
+    Source(String),
+    /// EndOfInput までのトークン列
+    Tokens(Vec<Token>),
+    Ast(ASTNode),
+    /// 仮想マシンのアセンブリ
+    Asm(String),
+    /// 実行した結果
+    Output(Execution),
+}
+
+impl Artifact {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Artifact::Source(_) => "source",
+            Artifact::Tokens(_) => "tokens",
+            Artifact::Ast(_) => "ast",
+            Artifact::Asm(_) => "asm",
+            Artifact::Output(_) => "output",
+        }
+    }
+}
+
+/// パイプラインの 1 つの段階。入力を受け取り、次の段階に渡す値を返す。
+/// 最適化や検査のように、入力と同じ種類の値を返す段階もある。
+pub trait Stage {
+    /// insert_after で位置を指定するときの名前
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    fn run(&self, input: Artifact) -> Result<Artifact>;
+}
+
+/// クロージャもそのまま段階として使える
+impl<F> Stage for F
+where
+    F: Fn(Artifact) -> Result<Artifact>,
+{
+    fn run(&self, input: Artifact) -> Result<Artifact> {
+        self(input)
+    }
+}
+
+/// 段階が受け取れない種類の値を渡された
+fn unexpected(kind: ErrorKind, stage: &str, expected: &str, input: &Artifact) -> CompileError {
+    CompileError::new(
+        kind,
+        format!("{} stage expects {}, got {}", stage, expected, input.name()),
+    )
+}
+
+/// ソースをトークン列にする
+#[derive(Default)]
+pub struct Lex {
+    options: ParserOptions,
+}
+
+impl Lex {
+    pub fn new(options: &ParserOptions) -> Self {
+        Lex {
+            options: options.clone(),
+        }
+    }
+}
+
+impl Stage for Lex {
+    fn name(&self) -> &str {
+        "lex"
+    }
+
+    fn run(&self, input: Artifact) -> Result<Artifact> {
+        match input {
+            Artifact::Source(src) => {
+                lexical_analyzer::lex_all_with(&src, &self.options.dialect).map(Artifact::Tokens)
+            }
+            _ => Err(unexpected(
+                ErrorKind::LexicalAnalyzerError,
+                self.name(),
+                "source",
+                &input,
+            )),
+        }
+    }
+}
+
+/// トークン列を AST にする
+#[derive(Default)]
+pub struct Parse {
+    options: ParserOptions,
+}
+
+impl Parse {
+    pub fn new(options: &ParserOptions) -> Self {
+        Parse {
+            options: options.clone(),
+        }
+    }
+}
+
+impl Stage for Parse {
+    fn name(&self) -> &str {
+        "parse"
+    }
+
+    fn run(&self, input: Artifact) -> Result<Artifact> {
+        match input {
+            Artifact::Tokens(tokens) => {
+                SyntaxAnalyzer::parse_ref_with(&tokens, &self.options).map(Artifact::Ast)
+            }
+            _ => Err(unexpected(
+                ErrorKind::SyntaxError,
+                self.name(),
+                "tokens",
+                &input,
+            )),
+        }
+    }
+}
+
+/// AST からアセンブリを生成する
+#[derive(Default)]
+pub struct Generate {
+    options: GeneratorOptions,
+}
+
+impl Generate {
+    pub fn new(options: &GeneratorOptions) -> Self {
+        Generate {
+            options: options.clone(),
+        }
+    }
+}
+
+impl Stage for Generate {
+    fn name(&self) -> &str {
+        "generate"
+    }
+
+    fn run(&self, input: Artifact) -> Result<Artifact> {
+        match input {
+            Artifact::Ast(ast) => {
+                CodeGenerator::generate_with(&ast, &self.options).map(Artifact::Asm)
+            }
+            _ => Err(unexpected(
+                ErrorKind::CodeGenerationError,
+                self.name(),
+                "ast",
+                &input,
+            )),
+        }
+    }
+}
+
+/// アセンブリを仮想マシンで実行する。
+/// 実行時のエラーは Err ではなく、それまでの出力と一緒に Execution に入る。
+#[derive(Default)]
+pub struct RunVm;
+
+impl Stage for RunVm {
+    fn name(&self) -> &str {
+        "vm"
+    }
+
+    fn run(&self, input: Artifact) -> Result<Artifact> {
+        match input {
+            Artifact::Asm(code) => Ok(Artifact::Output(Execution::capture(
+                ErrorKind::VirtualMachineError,
+                |out| {
+                    let mut vm = VirtualMachineInterpreter::load(code.lines())?;
+                    vm.execute(out)
+                },
+            ))),
+            _ => Err(unexpected(
+                ErrorKind::VirtualMachineError,
+                self.name(),
+                "asm",
+                &input,
+            )),
+        }
+    }
+}
+
+/// AST をインタプリタで実行する
+#[derive(Default)]
+pub struct Interpret;
+
+impl Stage for Interpret {
+    fn name(&self) -> &str {
+        "interpret"
+    }
+
+    fn run(&self, input: Artifact) -> Result<Artifact> {
+        match input {
+            Artifact::Ast(ast) => Ok(Artifact::Output(crate::interpret(&ast))),
+            _ => Err(unexpected(
+                ErrorKind::InterpretationError,
+                self.name(),
+                "ast",
+                &input,
+            )),
+        }
+    }
+}
+
+/// 段階を順につなげたもの。前の段階の結果を次の段階に渡す。
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// lex, parse, generate, vm の順にソースを実行する
+    pub fn compile(parser_options: &ParserOptions, generator_options: &GeneratorOptions) -> Self {
+        Self::new()
+            .stage(Lex::new(parser_options))
+            .stage(Parse::new(parser_options))
+            .stage(Generate::new(generator_options))
+            .stage(RunVm)
+    }
+
+    /// 最後に段階を加える
+    pub fn stage<S: Stage + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// name という名前の段階の直後に段階を加える。
+    /// 同じ名前の段階が複数あれば、最初のものの後に加える。
+    /// name という名前の段階がなければエラーにする。
+    pub fn insert_after<S: Stage + 'static>(mut self, name: &str, stage: S) -> Result<Self> {
+        let index = self
+            .stages
+            .iter()
+            .position(|s| s.name() == name)
+            .ok_or_else(|| {
+                CompileError::new(
+                    ErrorKind::PipelineError,
+                    format!(
+                        "no stage named {} (stages: {})",
+                        name,
+                        self.names().join(", ")
+                    ),
+                )
+            })?;
+        self.stages.insert(index + 1, Box::new(stage));
+        Ok(self)
+    }
+
+    /// 段階の名前を順に返す
+    pub fn names(&self) -> Vec<&str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+
+    /// input を各段階に順に渡す。いずれかの段階がエラーを返せばそこで止まる。
+    pub fn run(&self, input: Artifact) -> Result<Artifact> {
+        self.stages
+            .iter()
+            .try_fold(input, |artifact, stage| stage.run(artifact))
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use syntax_analyzer::ast_node::{fold_children, Fold, NodeKind};
+
+    fn output(artifact: Artifact) -> Execution {
+        match artifact {
+            Artifact::Output(execution) => execution,
+            other => panic!("unexpected {}", other.name()),
+        }
+    }
+
+    #[test]
+    fn test_compile() {
+        let pipeline = Pipeline::compile(&ParserOptions::default(), &GeneratorOptions::default());
+        assert_eq!(vec!["lex", "parse", "generate", "vm"], pipeline.names());
+
+        let src = "x = 6 * 7;\nprint(x, \"\\n\");\n";
+        let execution = output(pipeline.run(Artifact::Source(src.to_string())).unwrap());
+        assert_eq!(b"42\n", &execution.output[..]);
+        assert!(execution.error.is_none());
+
+        // 段階の途中のエラーはそのまま返す
+        let e = pipeline
+            .run(Artifact::Source("x = ;".to_string()))
+            .unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::SyntaxError));
+
+        // 受け取れない種類の値
+        let e = pipeline.run(Artifact::Asm(String::new())).unwrap_err();
+        assert_eq!("lex stage expects source, got asm", e.message());
+    }
+
+    #[test]
+    fn test_custom_stage() {
+        // print を数える検査と、 AST のすべての整数を 2 倍にするパスを parse の後に挟む
+        struct Double;
+        impl Stage for Double {
+            fn name(&self) -> &str {
+                "double"
+            }
+
+            fn run(&self, input: Artifact) -> Result<Artifact> {
+                struct Doubler;
+                impl Fold for Doubler {
+                    fn fold(&mut self, node: ASTNode) -> ASTNode {
+                        match node.kind() {
+                            NodeKind::Integer(n) => ASTNode::leaf_int(n * 2),
+                            _ => fold_children(self, node),
+                        }
+                    }
+                }
+                match input {
+                    Artifact::Ast(ast) => Ok(Artifact::Ast(Doubler.fold(ast))),
+                    other => Ok(other),
+                }
+            }
+        }
+
+        let seen = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&seen);
+        let pipeline = Pipeline::compile(&ParserOptions::default(), &GeneratorOptions::default())
+            .insert_after("parse", Double)
+            .and_then(|pipeline| {
+                pipeline.insert_after("parse", move |input: Artifact| {
+                    if let Artifact::Ast(ast) = &input {
+                        counter.set(ast.to_string().matches("Prti").count());
+                    }
+                    Ok(input)
+                })
+            })
+            .unwrap();
+        assert_eq!(
+            vec!["lex", "parse", "custom", "double", "generate", "vm"],
+            pipeline.names()
+        );
+
+        let execution = output(
+            pipeline
+                .run(Artifact::Source("print(21);".to_string()))
+                .unwrap(),
+        );
+        assert_eq!(b"42", &execution.output[..]);
+        assert_eq!(1, seen.get());
+
+        // 知らない名前の段階の後には加えられない
+        let e = Pipeline::new()
+            .stage(Double)
+            .insert_after("parse", Double)
+            .unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::PipelineError));
+        assert_eq!("no stage named parse (stages: double)", e.message());
+
+        // AST から始めてインタプリタで実行する
+        let ast = syntax_analyzer::parse_str("print(1 + 2);").unwrap();
+        let pipeline = Pipeline::new().stage(Double).stage(Interpret);
+        let execution = output(pipeline.run(Artifact::Ast(ast)).unwrap());
+        assert_eq!(b"6", &execution.output[..]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// ファイルの更新を調べる。更新時刻か大きさが変われば更新されたとみなす。
+/// 外部のクレートに頼らず、呼び出し側が一定の間隔で changed を呼んで調べる。
+#[derive(Debug)]
+pub struct Watcher {
+    /// ファイルと、最後に調べたときの更新時刻と大きさ。読めなければ None。
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+fn stamp(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+impl Watcher {
+    /// files の今の状態を覚える
+    pub fn new<I, P>(files: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        Watcher {
+            files: files
+                .into_iter()
+                .map(|path| {
+                    let path = path.into();
+                    let stamp = stamp(&path);
+                    (path, stamp)
+                })
+                .collect(),
+        }
+    }
+
+    /// 前に調べたときからいずれかのファイルが変わっていれば true
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, last) in &mut self.files {
+            let now = stamp(path);
+            if now != *last {
+                *last = now;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// 出力の 1 行の変化。行番号は 1 から。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineChange {
+    /// 前回の出力の行
+    Removed(usize, String),
+    /// 今回の出力の行
+    Added(usize, String),
+}
+
+impl fmt::Display for LineChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineChange::Removed(line, text) => write!(f, "-{}: {}", line, text),
+            LineChange::Added(line, text) => write!(f, "+{}: {}", line, text),
+        }
+    }
+}
+
+/// 最長共通部分列を求める行数の積の上限。これを超えると、食い違う範囲をまるごと入れ替えたことにする。
+const MAX_DIFF_CELLS: usize = 1 << 20;
+
+/// 前回と今回の出力の行ごとの差分。変わらなかった行は含めない。
+pub fn output_diff(previous: &[u8], current: &[u8]) -> Vec<LineChange> {
+    let previous = String::from_utf8_lossy(previous);
+    let current = String::from_utf8_lossy(current);
+    let old: Vec<&str> = previous.lines().collect();
+    let new: Vec<&str> = current.lines().collect();
+
+    // 先頭と末尾の共通部分を除いてから比べる
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let removed = |i: usize| LineChange::Removed(prefix + i + 1, old_middle[i].to_string());
+    let added = |j: usize| LineChange::Added(prefix + j + 1, new_middle[j].to_string());
+    let (n, m) = (old_middle.len(), new_middle.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        return (0..n).map(removed).chain((0..m).map(added)).collect();
+    }
+
+    // lcs[i][j] は old_middle[i..] と new_middle[j..] の最長共通部分列の長さ
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_middle[i] == new_middle[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_middle[i] == new_middle[j] {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(removed(i));
+            i += 1;
+        } else {
+            changes.push(added(j));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_diff() {
+        assert!(output_diff(b"a\nb\n", b"a\nb\n").is_empty());
+        assert_eq!(
+            vec![
+                LineChange::Removed(2, "b".to_string()),
+                LineChange::Added(2, "x".to_string()),
+                LineChange::Added(5, "e".to_string()),
+            ],
+            output_diff(b"a\nb\nc\nd\n", b"a\nx\nc\nd\ne\n")
+        );
+        assert_eq!(
+            vec!["-1: 1", "+2: 3"],
+            output_diff(b"1\n2\n", b"2\n3\n")
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![LineChange::Added(1, "hello".to_string())],
+            output_diff(b"", b"hello")
+        );
+    }
+
+    #[test]
+    fn test_watcher() {
+        let path = std::env::temp_dir().join(format!("watch_test_{}.t", std::process::id()));
+        fs::write(&path, "print(1);").unwrap();
+        let mut watcher = Watcher::new([&path]);
+        assert!(!watcher.changed());
+
+        fs::write(&path, "print(12);").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        // 消えたファイルも変化として扱う
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+    }
+}
@@ -0,0 +1,159 @@
+//! 文字列リテラルのエスケープ。
+//! 字句解析器、 AST の読み書き、コード生成の出力と仮想マシンの読み込みで同じ規則を使う。
+//! エスケープシーケンスは \n, \\, \" の 3 つだけで、それ以外の文字はそのまま書く。
+
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EscapeError {
+    /// \ の後に続く文字が知らないもの
+    UnknownEscape(char),
+    /// \ で終わっている
+    UnterminatedEscape,
+    /// 先頭が " でない
+    MissingQuote,
+    /// 閉じる " がない
+    UnterminatedString,
+}
+
+impl fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EscapeError::UnknownEscape(c) => write!(f, "unknown escape sequence: \\{}", c),
+            EscapeError::UnterminatedEscape => write!(f, "unterminated escape sequence"),
+            EscapeError::MissingQuote => write!(f, "'\"' is expected"),
+            EscapeError::UnterminatedString => write!(f, "'\"' is not found"),
+        }
+    }
+}
+
+impl Error for EscapeError {}
+
+/// \ の後の文字が表す文字。エスケープシーケンスでなければ None。
+pub fn unescape_char(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        _ => None,
+    }
+}
+
+/// 文字列リテラルの中に書ける形にする。引用符は付けない。
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// escape した s を引用符で囲む
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/// escape の逆。s 全体をリテラルの中身として読む。
+pub fn unescape(s: &str) -> Result<String, EscapeError> {
+    let mut value = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let escaped = chars.next().ok_or(EscapeError::UnterminatedEscape)?;
+            value.push(unescape_char(escaped).ok_or(EscapeError::UnknownEscape(escaped))?);
+        } else {
+            value.push(c);
+        }
+    }
+    Ok(value)
+}
+
+/// 先頭の " から閉じる " までを読み、文字列の値と、閉じる " より後の残りを返す
+pub fn unquote(s: &str) -> Result<(String, &str), EscapeError> {
+    let body = s.strip_prefix('"').ok_or(EscapeError::MissingQuote)?;
+    let mut value = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &body[i + 1..])),
+            '\\' => {
+                let (_, escaped) = chars.next().ok_or(EscapeError::UnterminatedEscape)?;
+                value.push(unescape_char(escaped).ok_or(EscapeError::UnknownEscape(escaped))?);
+            }
+            c => value.push(c),
+        }
+    }
+    Err(EscapeError::UnterminatedString)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(r#"Hello, World!\n"#, escape("Hello, World!\n"));
+        assert_eq!(r#""a\\b \"c\"""#, quote("a\\b \"c\""));
+        // タブや制御文字はそのまま書く
+        assert_eq!("\"\t\u{1b}é\"", quote("\t\u{1b}é"));
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(Ok("a\nb\\\"".to_string()), unescape(r#"a\nb\\\""#));
+        assert_eq!(Err(EscapeError::UnknownEscape('t')), unescape(r#"a\tb"#));
+        assert_eq!(Err(EscapeError::UnterminatedEscape), unescape("a\\"));
+
+        assert_eq!(
+            Ok(("say \"hi\"".to_string(), " rest")),
+            unquote(r#""say \"hi\"" rest"#)
+        );
+        assert_eq!(Err(EscapeError::MissingQuote), unquote("abc\""));
+        assert_eq!(Err(EscapeError::UnterminatedString), unquote(r#""abc\""#));
+        assert_eq!(
+            "unknown escape sequence: \\t",
+            unquote(r#""\t""#).unwrap_err().to_string()
+        );
+    }
+
+    /// テスト用の xorshift32
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as usize % n
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // エスケープに関わる文字が多く出るように選ぶ
+        let alphabet = [
+            '"', '\\', '\n', 'n', '\r', '\t', ' ', 'a', 'Z', '0', '\'', '\u{0}', '\u{7f}', 'é',
+            'あ', '🦀',
+        ];
+        let mut random = Random(2463534242);
+        for _ in 0..2000 {
+            let len = random.next(20);
+            let s: String = (0..len)
+                .map(|_| alphabet[random.next(alphabet.len())])
+                .collect();
+
+            let escaped = escape(&s);
+            assert!(!escaped.contains('\n'), "{:?}", escaped);
+            assert_eq!(Ok(s.clone()), unescape(&escaped));
+
+            let line = quote(&s) + " tail";
+            assert_eq!(Ok((s.clone(), " tail")), unquote(&line));
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! 最適化の有無で実行時間を比べる。
+//!
+//!     cargo run --release --example benchmark -- mandelbrot.b
+use execute_brainxxxx::{compile, execute, optimize, Instruction};
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+fn measure(program: &[Instruction]) -> Duration {
+    let start = Instant::now();
+    execute(program, io::empty(), io::sink()).expect("execution failed");
+    start.elapsed()
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            println!("Usage: benchmark filename");
+            return;
+        }
+    };
+    let src = fs::read_to_string(&path).expect("cannot read file.");
+    let program = compile(&src).expect("compile failed");
+    let optimized = optimize(&program);
+
+    let plain = measure(&program);
+    let fast = measure(&optimized);
+    println!(
+        "plain:     {:>6} instructions {:>10.3?}",
+        program.len(),
+        plain
+    );
+    println!(
+        "optimized: {:>6} instructions {:>10.3?}",
+        optimized.len(),
+        fast
+    );
+    println!(
+        "speedup:   {:.2}x",
+        plain.as_secs_f64() / fast.as_secs_f64()
+    );
+}
@@ -0,0 +1,534 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::result;
+
+pub mod to_vm;
+
+pub const MEMORY_SIZE: usize = 30000;
+
+/// テープの長さとポインタが範囲外に出た場合の動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tape {
+    /// 長さ固定。範囲外に出るとエラー。
+    Fixed(usize),
+    /// 長さ固定。範囲外に出ると反対側の端に回り込む。
+    Wrapping(usize),
+    /// 右側に必要なだけ伸びる。0 より左に出るとエラー。
+    Growing,
+}
+
+/// 入力が EOF の場合にセルに入れる値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eof {
+    Zero,
+    /// -1 (セルの最大値)
+    MinusOne,
+    /// セルを変更しない
+    Unchanged,
+}
+
+/// セルのビット数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellSize {
+    U8,
+    U32,
+}
+
+impl CellSize {
+    fn mask(self) -> u32 {
+        match self {
+            CellSize::U8 => 0xff,
+            CellSize::U32 => 0xffff_ffff,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub tape: Tape,
+    pub eof: Eof,
+    pub cell_size: CellSize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            tape: Tape::Fixed(MEMORY_SIZE),
+            eof: Eof::MinusOne,
+            cell_size: CellSize::U8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    IncrementPointer,
+    DecrementPointer,
+    IncrementValue,
+    DecrementValue,
+    Output,
+    Input,
+    BranchIfZero(usize),
+    BranchIfNotZero(usize),
+    /// ポインタを n 動かす (optimize で生成)
+    MovePointer(isize),
+    /// セルに n を足す (optimize で生成)
+    AddValue(i32),
+    /// セルを 0 にする。`[-]` (optimize で生成)
+    Clear,
+    /// 現在のセルの値を offset 先のセルに足し、現在のセルを 0 にする。
+    /// `[->+<]` など (optimize で生成)
+    MoveAdd(isize),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// 対応する ']' がない '['。値はソース中の文字の位置。
+    UnmatchedOpen(usize),
+    /// 対応する '[' がない ']'。値はソース中の文字の位置。
+    UnmatchedClose(usize),
+    /// ポインタがメモリの範囲外に出た。値は命令の位置。
+    PointerOutOfRange(usize),
+    /// 文字として出力できない値
+    IllegalCharacter(u32),
+    /// 長さ 0 の Tape::Fixed と Tape::Wrapping
+    EmptyTape,
+    IO(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnmatchedOpen(pos) => write!(f, "'[' at {} has no matching ']'", pos),
+            Error::UnmatchedClose(pos) => write!(f, "']' at {} has no matching '['", pos),
+            Error::PointerOutOfRange(pc) => {
+                write!(f, "pointer is out of range at instruction {}", pc)
+            }
+            Error::IllegalCharacter(value) => write!(f, "illegal character value: {}", value),
+            Error::EmptyTape => write!(f, "the tape needs at least one cell"),
+            Error::IO(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::IO(e)
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+pub fn compile(src: &str) -> Result<Vec<Instruction>> {
+    let mut program = Vec::new();
+    // '[' の命令の位置と、ソース中の位置
+    let mut stack = Vec::new();
+    for (pos, c) in src.chars().enumerate() {
+        match c {
+            '>' => {
+                program.push(Instruction::IncrementPointer);
+            }
+            '<' => {
+                program.push(Instruction::DecrementPointer);
+            }
+            '+' => {
+                program.push(Instruction::IncrementValue);
+            }
+            '-' => {
+                program.push(Instruction::DecrementValue);
+            }
+            '.' => {
+                program.push(Instruction::Output);
+            }
+            ',' => {
+                program.push(Instruction::Input);
+            }
+            '[' => {
+                stack.push((program.len(), pos));
+                program.push(Instruction::BranchIfZero(0));
+            }
+            ']' => {
+                let (dst, _) = stack.pop().ok_or(Error::UnmatchedClose(pos))?;
+                program[dst] = Instruction::BranchIfZero(program.len());
+                program.push(Instruction::BranchIfNotZero(dst));
+            }
+            // コメントは読み飛ばす
+            _ => {}
+        }
+    }
+    if let Some(&(_, pos)) = stack.last() {
+        return Err(Error::UnmatchedOpen(pos));
+    }
+
+    Ok(program)
+}
+
+/// 連続する `+-<>` をまとめ、`[-]` と `[->+<]` を 1 命令に置き換える。
+pub fn optimize(program: &[Instruction]) -> Vec<Instruction> {
+    let mut optimized = Vec::new();
+    let mut stack = Vec::new();
+    let mut pc = 0;
+    while pc < program.len() {
+        match program[pc] {
+            Instruction::IncrementPointer | Instruction::DecrementPointer => {
+                let mut offset = 0;
+                while let Some(d) = program.get(pc).and_then(pointer_delta) {
+                    offset += d;
+                    pc += 1;
+                }
+                if offset != 0 {
+                    optimized.push(Instruction::MovePointer(offset));
+                }
+                continue;
+            }
+            Instruction::IncrementValue | Instruction::DecrementValue => {
+                let mut n = 0i32;
+                while let Some(d) = program.get(pc).and_then(value_delta) {
+                    n = n.wrapping_add(d);
+                    pc += 1;
+                }
+                if n != 0 {
+                    optimized.push(Instruction::AddValue(n));
+                }
+                continue;
+            }
+            Instruction::BranchIfZero(end) => {
+                if let Some(idiom) = loop_idiom(&program[pc + 1..end]) {
+                    optimized.push(idiom);
+                    pc = end + 1;
+                    continue;
+                }
+                stack.push(optimized.len());
+                optimized.push(Instruction::BranchIfZero(0));
+            }
+            Instruction::BranchIfNotZero(_) => {
+                let dst = stack.pop().unwrap();
+                optimized[dst] = Instruction::BranchIfZero(optimized.len());
+                optimized.push(Instruction::BranchIfNotZero(dst));
+            }
+            instruction => optimized.push(instruction),
+        }
+        pc += 1;
+    }
+    optimized
+}
+
+fn pointer_delta(instruction: &Instruction) -> Option<isize> {
+    match instruction {
+        Instruction::IncrementPointer => Some(1),
+        Instruction::DecrementPointer => Some(-1),
+        _ => None,
+    }
+}
+
+fn value_delta(instruction: &Instruction) -> Option<i32> {
+    match instruction {
+        Instruction::IncrementValue => Some(1),
+        Instruction::DecrementValue => Some(-1),
+        _ => None,
+    }
+}
+
+/// ループの中身が `-` (または `+`) だけなら Clear、
+/// 現在のセルから 1 引いて別の 1 つのセルに 1 足すだけなら MoveAdd にする。
+fn loop_idiom(body: &[Instruction]) -> Option<Instruction> {
+    let mut offset = 0;
+    // (offset, そのセルに足す値)
+    let mut deltas: Vec<(isize, i32)> = Vec::new();
+    for instruction in body {
+        if let Some(d) = pointer_delta(instruction) {
+            offset += d;
+        } else if let Some(d) = value_delta(instruction) {
+            match deltas.iter_mut().find(|(o, _)| *o == offset) {
+                Some(delta) => delta.1 = delta.1.wrapping_add(d),
+                None => deltas.push((offset, d)),
+            }
+        } else {
+            return None;
+        }
+    }
+    deltas.retain(|&(_, d)| d != 0);
+    if offset != 0 {
+        return None;
+    }
+
+    match deltas[..] {
+        [(0, 1)] | [(0, -1)] => Some(Instruction::Clear),
+        [(0, -1), (target, 1)] | [(target, 1), (0, -1)] => Some(Instruction::MoveAdd(target)),
+        _ => None,
+    }
+}
+
+/// program を実行する。入力が EOF の場合、セルは 255 になる。
+pub fn execute<R, W>(program: &[Instruction], input: R, output: W) -> Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    execute_with(program, input, output, &Options::default())
+}
+
+/// options のテープ、EOF、セルの設定で program を実行する。
+pub fn execute_with<R, W>(
+    program: &[Instruction],
+    mut input: R,
+    mut output: W,
+    options: &Options,
+) -> Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mask = options.cell_size.mask();
+    let mut memory = Memory::new(options.tape)?;
+    let mut pc = 0;
+    let mut ptr: usize = 0;
+    while pc < program.len() {
+        match &program[pc] {
+            Instruction::IncrementPointer => {
+                ptr = memory
+                    .move_pointer(ptr, 1)
+                    .ok_or(Error::PointerOutOfRange(pc))?;
+            }
+            Instruction::DecrementPointer => {
+                ptr = memory
+                    .move_pointer(ptr, -1)
+                    .ok_or(Error::PointerOutOfRange(pc))?;
+            }
+            Instruction::IncrementValue => {
+                memory.cells[ptr] = memory.cells[ptr].wrapping_add(1) & mask;
+            }
+            Instruction::DecrementValue => {
+                memory.cells[ptr] = memory.cells[ptr].wrapping_sub(1) & mask;
+            }
+            Instruction::Output => {
+                let value = memory.cells[ptr];
+                let c = std::char::from_u32(value).ok_or(Error::IllegalCharacter(value))?;
+                write!(output, "{}", c)?;
+            }
+            Instruction::Input => {
+                let mut buf = [0u8; 1];
+                if input.read(&mut buf)? == 0 {
+                    match options.eof {
+                        Eof::Zero => memory.cells[ptr] = 0,
+                        Eof::MinusOne => memory.cells[ptr] = mask,
+                        Eof::Unchanged => {}
+                    }
+                } else {
+                    memory.cells[ptr] = buf[0] as u32;
+                }
+            }
+            Instruction::BranchIfZero(dst) => {
+                if memory.cells[ptr] == 0 {
+                    pc = *dst;
+                }
+            }
+            Instruction::BranchIfNotZero(dst) => {
+                if memory.cells[ptr] != 0 {
+                    pc = *dst;
+                }
+            }
+            Instruction::MovePointer(offset) => {
+                ptr = memory
+                    .move_pointer(ptr, *offset)
+                    .ok_or(Error::PointerOutOfRange(pc))?;
+            }
+            Instruction::AddValue(n) => {
+                memory.cells[ptr] = memory.cells[ptr].wrapping_add(*n as u32) & mask;
+            }
+            Instruction::Clear => {
+                memory.cells[ptr] = 0;
+            }
+            Instruction::MoveAdd(offset) => {
+                if memory.cells[ptr] != 0 {
+                    let target = memory
+                        .move_pointer(ptr, *offset)
+                        .ok_or(Error::PointerOutOfRange(pc))?;
+                    memory.cells[target] =
+                        memory.cells[target].wrapping_add(memory.cells[ptr]) & mask;
+                    memory.cells[ptr] = 0;
+                }
+            }
+        }
+        pc += 1;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+struct Memory {
+    cells: Vec<u32>,
+    tape: Tape,
+}
+
+impl Memory {
+    /// 長さ 0 のテープならエラー
+    fn new(tape: Tape) -> Result<Self> {
+        let size = match tape {
+            Tape::Fixed(0) | Tape::Wrapping(0) => return Err(Error::EmptyTape),
+            Tape::Fixed(size) | Tape::Wrapping(size) => size,
+            Tape::Growing => 1,
+        };
+        Ok(Memory {
+            cells: vec![0; size],
+            tape,
+        })
+    }
+
+    /// ptr を offset 動かした位置を返す。範囲外に出た場合は None。
+    fn move_pointer(&mut self, ptr: usize, offset: isize) -> Option<usize> {
+        let len = self.cells.len();
+        match self.tape {
+            Tape::Fixed(_) => ptr.checked_add_signed(offset).filter(|&ptr| ptr < len),
+            Tape::Wrapping(_) => Some((ptr as isize + offset).rem_euclid(len as isize) as usize),
+            Tape::Growing => {
+                let ptr = ptr.checked_add_signed(offset)?;
+                if ptr >= len {
+                    self.cells.resize((ptr + 1).max(len * 2), 0);
+                }
+                Some(ptr)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(src: &str, input: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        execute(&compile(src)?, input, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_compile() {
+        assert_eq!(
+            vec![
+                Instruction::IncrementValue,
+                Instruction::BranchIfZero(4),
+                Instruction::IncrementPointer,
+                Instruction::DecrementValue,
+                Instruction::BranchIfNotZero(1),
+            ],
+            compile("+ [>-] comment").unwrap()
+        );
+        assert!(matches!(compile("+[[]"), Err(Error::UnmatchedOpen(1))));
+        assert!(matches!(compile("+]"), Err(Error::UnmatchedClose(1))));
+    }
+
+    #[test]
+    fn test_optimize() {
+        assert_eq!(
+            vec![
+                Instruction::AddValue(2),
+                Instruction::MovePointer(-1),
+                Instruction::Clear,
+                Instruction::MoveAdd(3),
+                Instruction::BranchIfZero(7),
+                Instruction::MovePointer(1),
+                Instruction::Output,
+                Instruction::BranchIfNotZero(4),
+            ],
+            optimize(&compile("+++-<>< [-] [>>>+<<<-] [>.]").unwrap())
+        );
+        // 2 つのセルに足すループや、ポインタが戻らないループはそのまま
+        let loops = optimize(&compile("[->+>+<<][->+]").unwrap())
+            .into_iter()
+            .filter(|instruction| matches!(instruction, Instruction::BranchIfZero(_)))
+            .count();
+        assert_eq!(2, loops);
+    }
+
+    #[test]
+    fn test_execute() {
+        let src = r#"
+++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
+"#;
+        assert_eq!(b"Hello World!\n", &run(src, b"").unwrap()[..]);
+        let mut out = Vec::new();
+        execute(&optimize(&compile(src).unwrap()), &b""[..], &mut out).unwrap();
+        assert_eq!(b"Hello World!\n", &out[..]);
+        assert_eq!(b"ab", &run(",+[-.,+]", b"ab").unwrap()[..]);
+        assert!(matches!(run("<", b""), Err(Error::PointerOutOfRange(0))));
+    }
+
+    fn run_with(src: &str, input: &[u8], options: Options) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        execute_with(&compile(src)?, input, &mut out, &options)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::default();
+
+        // テープ
+        let tape = Options {
+            tape: Tape::Fixed(3),
+            ..options
+        };
+        assert!(matches!(
+            run_with(">>>", b"", tape),
+            Err(Error::PointerOutOfRange(2))
+        ));
+        let tape = Options {
+            tape: Tape::Wrapping(3),
+            ..options
+        };
+        assert_eq!(b"\x01", &run_with("<+>>>.", b"", tape).unwrap()[..]);
+        for &tape in &[Tape::Fixed(0), Tape::Wrapping(0)] {
+            let tape = Options { tape, ..options };
+            assert!(matches!(run_with("+>.", b"", tape), Err(Error::EmptyTape)));
+            assert!(matches!(run_with("", b"", tape), Err(Error::EmptyTape)));
+        }
+        let tape = Options {
+            tape: Tape::Growing,
+            ..options
+        };
+        let src = format!(
+            "+{}+{}.",
+            ">".repeat(MEMORY_SIZE * 2),
+            "<".repeat(MEMORY_SIZE * 2)
+        );
+        assert_eq!(b"\x01", &run_with(&src, b"", tape).unwrap()[..]);
+        assert!(matches!(
+            run_with("<", b"", tape),
+            Err(Error::PointerOutOfRange(0))
+        ));
+
+        // EOF
+        let eof = |eof| Options { eof, ..options };
+        assert_eq!(b"\0", &run_with("+,.", b"", eof(Eof::Zero)).unwrap()[..]);
+        assert_eq!(
+            "\u{ff}".as_bytes(),
+            &run_with("+,.", b"", eof(Eof::MinusOne)).unwrap()[..]
+        );
+        assert_eq!(
+            b"\x01",
+            &run_with("+,.", b"", eof(Eof::Unchanged)).unwrap()[..]
+        );
+
+        // セルのビット数
+        let u32_cell = Options {
+            cell_size: CellSize::U32,
+            ..options
+        };
+        let src = format!("{}[->+<]>.", "+".repeat(16));
+        assert_eq!(b"\x10", &run_with(&src, b"", u32_cell).unwrap()[..]);
+        // 8 bit では 256 で 0 に戻るが、32 bit では戻らない
+        let src = format!("{}>+<[>-<[-]]>.", "+".repeat(256));
+        assert_eq!(b"\x01", &run_with(&src, b"", options).unwrap()[..]);
+        assert_eq!(b"\0", &run_with(&src, b"", u32_cell).unwrap()[..]);
+        assert!(matches!(
+            run_with("-.", b"", u32_cell),
+            Err(Error::IllegalCharacter(0xffff_ffff))
+        ));
+        let optimized = optimize(&compile("-.").unwrap());
+        let mut out = Vec::new();
+        assert!(execute_with(&optimized, &b""[..], &mut out, &u32_cell).is_err());
+    }
+}
@@ -1,129 +1,134 @@
+use execute_brainxxxx::{compile, execute_with, optimize, to_vm, CellSize, Eof, Options, Tape};
 use std::fs::File;
-use std::io::Read;
-use std::num::Wrapping;
-use std::str::Chars;
+use std::io::{self, Read, Write};
+use std::process;
 
-const MEMORY_SIZE: usize = 30000;
+/// 実行時のエラーの終了コード (lexical_analyzer::cli と同じ)
+const EXIT_FAILURE: i32 = 1;
+/// 引数の誤りの終了コード
+const EXIT_USAGE: i32 = 2;
 
-#[derive(Debug)]
-enum Instruction {
-    IncrementPointer,
-    DecrementPointer,
-    IncrementValue,
-    DecrementValue,
-    Output,
-    Input,
-    BranchIfZero(usize),
-    BranchIfNotZero(usize),
+fn print_usage(name: &str) {
+    eprintln!(
+        "Usage: {} [--vm] [--optimize] [memory options] filename",
+        name
+    );
+    eprintln!();
+    eprintln!(
+        "    --vm          print the program as virtual machine assembly instead of running it"
+    );
+    eprintln!("    --optimize    merge repeated instructions and replace common loops");
+    eprintln!();
+    eprintln!("memory options:");
+    eprintln!("    --tape <n|grow>           number of cells (default: 30000) or a growing tape");
+    eprintln!("    --wrap                    wrap the pointer around the ends of the tape");
+    eprintln!("    --eof <0|-1|unchanged>    cell value on end of input (default: -1)");
+    eprintln!("    --cell <8|32>             bits per cell (default: 8)");
 }
 
-fn compile(src: Chars) -> Vec<Instruction> {
-    let mut program = Vec::new();
-    let mut stack = Vec::new();
-    for c in src {
-        match c {
-            '>' => {
-                program.push(Instruction::IncrementPointer);
-            }
-            '<' => {
-                program.push(Instruction::DecrementPointer);
-            }
-            '+' => {
-                program.push(Instruction::IncrementValue);
-            }
-            '-' => {
-                program.push(Instruction::DecrementValue);
-            }
-            '.' => {
-                program.push(Instruction::Output);
-            }
-            ',' => {
-                program.push(Instruction::Input);
-            }
-            '[' => {
-                stack.push(program.len());
-                program.push(Instruction::BranchIfZero(0));
-            }
-            ']' => {
-                let dst = stack.pop().expect("']' appeared before '['");
-                program[dst] = Instruction::BranchIfZero(program.len());
-                program.push(Instruction::BranchIfNotZero(dst));
-            }
-            // コメントは読み飛ばす
-            _ => {}
+/// メモリのオプションを読んで options に設定する。読んだ引数の数を返す。
+fn parse_memory_option(args: &[String], options: &mut Options) -> Result<usize, String> {
+    let value = || {
+        args.get(1)
+            .map(String::as_str)
+            .ok_or_else(|| format!("{} requires an argument", args[0]))
+    };
+    match args[0].as_str() {
+        "--tape" => {
+            options.tape = match value()? {
+                "grow" => Tape::Growing,
+                n => match n.parse() {
+                    Ok(size) if size > 0 => Tape::Fixed(size),
+                    _ => return Err(format!("invalid tape size: {}", n)),
+                },
+            };
+            Ok(2)
         }
-    }
-    if stack.len() > 0 {
-        panic!("there are '[' whithout matching ']'");
-    }
-
-    program
-}
-
-fn execute(program: &Vec<Instruction>) {
-    let mut pc = 0;
-    let mut ptr = 0;
-    let mut mem = vec![Wrapping(0); MEMORY_SIZE];
-    let stdin = std::io::stdin();
-    let mut input = stdin.lock().bytes();
-    while pc < program.len() {
-        match &program[pc] {
-            Instruction::IncrementPointer => {
-                ptr += 1;
-            }
-            Instruction::DecrementPointer => {
-                ptr -= 1;
-            }
-            Instruction::IncrementValue => {
-                mem[ptr] += Wrapping(1);
-            }
-            Instruction::DecrementValue => {
-                mem[ptr] -= Wrapping(1);
-            }
-            Instruction::Output => {
-                print!("{}", mem[ptr].0 as char);
-            }
-            Instruction::Input => {
-                let v = input.next().unwrap().unwrap();
-                mem[ptr] = Wrapping(v);
-            }
-            Instruction::BranchIfZero(dst) => {
-                if mem[ptr].0 == 0 {
-                    pc = *dst;
-                }
-            }
-            Instruction::BranchIfNotZero(dst) => {
-                if mem[ptr].0 != 0 {
-                    pc = *dst;
-                }
-            }
+        "--eof" => {
+            options.eof = match value()? {
+                "0" => Eof::Zero,
+                "-1" => Eof::MinusOne,
+                "unchanged" => Eof::Unchanged,
+                v => return Err(format!("invalid eof value: {}", v)),
+            };
+            Ok(2)
+        }
+        "--cell" => {
+            options.cell_size = match value()? {
+                "8" => CellSize::U8,
+                "32" => CellSize::U32,
+                v => return Err(format!("invalid cell size: {}", v)),
+            };
+            Ok(2)
         }
-        pc += 1;
+        _ => Ok(0),
     }
 }
 
-fn print_usage(name: &str) {
-    println!("Usage: {} filename", name);
+/// エラーを標準エラー出力に書いて終了する
+fn fail(name: &str, message: impl std::fmt::Display, code: i32) -> ! {
+    eprintln!("{}: {}", name, message);
+    process::exit(code);
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let name = args[0].clone();
+    let mut to_vm = false;
+    let mut optimized = false;
+    let mut wrap = false;
+    let mut options = Options::default();
+    while args.len() > 1 {
+        match args[1].as_str() {
+            "--vm" => to_vm = true,
+            "--optimize" => optimized = true,
+            "--wrap" => wrap = true,
+            _ => match parse_memory_option(&args[1..], &mut options) {
+                Ok(0) => break,
+                Ok(n) => {
+                    args.drain(1..1 + n);
+                    continue;
+                }
+                Err(e) => fail(&name, e, EXIT_USAGE),
+            },
+        }
+        args.remove(1);
+    }
+    if wrap {
+        options.tape = match options.tape {
+            Tape::Fixed(size) | Tape::Wrapping(size) => Tape::Wrapping(size),
+            Tape::Growing => fail(
+                &name,
+                "--wrap cannot be used with a growing tape",
+                EXIT_USAGE,
+            ),
+        };
+    }
     if args.len() < 2 {
         debug_assert!(args.len() == 1);
-        print_usage(&args[0]);
-        return;
+        print_usage(&name);
+        process::exit(EXIT_USAGE);
     }
 
     let mut buf = String::new();
-    match File::open(&args[1]) {
-        Ok(mut f) => {
-            f.read_to_string(&mut buf).expect("cannot read file.");
-        }
-        Err(e) => {
-            println!("{}", e);
-            return;
-        }
+    if let Err(e) = File::open(&args[1]).and_then(|mut f| f.read_to_string(&mut buf)) {
+        fail(&name, format!("{}: {}", args[1], e), EXIT_FAILURE);
     }
 
-    execute(&compile(buf.chars()));
+    let program = match compile(&buf) {
+        Ok(program) if optimized => optimize(&program),
+        Ok(program) => program,
+        Err(e) => fail(&name, e, EXIT_FAILURE),
+    };
+    if to_vm {
+        print!("{}", to_vm::translate(&program));
+    } else {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        if let Err(e) = execute_with(&program, stdin.lock(), stdout.lock(), &options) {
+            // エラーまでに書いた出力を捨てない
+            let _ = io::stdout().flush();
+            fail(&name, e, EXIT_FAILURE);
+        }
+    }
 }
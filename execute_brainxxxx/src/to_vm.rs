@@ -0,0 +1,204 @@
+use super::{Instruction, MEMORY_SIZE};
+
+/// ポインタを置く data のアドレス。セルは 0 番地から MEMORY_SIZE 個並べ、ポインタはその後に置く。
+/// セルの範囲を出るポインタの移動は実行時に止めるので、セルを書き換えてもポインタは壊れない。
+const POINTER: usize = MEMORY_SIZE;
+
+/// ポインタがセルの範囲を出たときのエラー。文字列プールの 0 番。
+const OUT_OF_RANGE: &str = "pointer is out of range";
+
+/// brainfuck のプログラムを仮想マシンのアセンブリに変換する。
+/// セルは 8 bit で、入力が EOF の場合は 255 になる (Options::default() と同じ)。
+pub fn translate(program: &[Instruction]) -> String {
+    let mut code = format!("Datasize: {} Strings: 1\n", MEMORY_SIZE + 1);
+    code += &format!("\"{}\"\n", OUT_OF_RANGE);
+    code += &format!("    push 0\n    store [{}]\n", POINTER);
+
+    let mut pc = 0;
+    while pc < program.len() {
+        // 同じ命令の連続はまとめて 1 回の加減算にする
+        let mut count = 1;
+        while is_foldable(&program[pc])
+            && pc + count < program.len()
+            && same_kind(&program[pc], &program[pc + count])
+        {
+            count += 1;
+        }
+
+        match program[pc] {
+            Instruction::IncrementPointer => move_pointer(&mut code, count as i32),
+            Instruction::DecrementPointer => move_pointer(&mut code, -(count as i32)),
+            Instruction::IncrementValue => add_value(&mut code, count % 256),
+            Instruction::DecrementValue => add_value(&mut code, 256 - count % 256),
+            Instruction::Output => {
+                code += &format!("    fetch [{}]\n    fetchi\n    prtc\n", POINTER);
+            }
+            Instruction::Input => {
+                code += &format!("    fetch [{}]\n    getc\n", POINTER);
+                code += "    push 256\n    add\n    push 256\n    mod\n    storei\n";
+            }
+            Instruction::BranchIfZero(_) => {
+                code += &format!("loop_{}:\n", pc);
+                code += &format!("    fetch [{}]\n    fetchi\n    jz end_{}\n", POINTER, pc);
+            }
+            Instruction::BranchIfNotZero(dst) => {
+                code += &format!("    jmp loop_{}\nend_{}:\n", dst, dst);
+            }
+            Instruction::MovePointer(offset) => move_pointer(&mut code, offset as i32),
+            Instruction::AddValue(n) => add_value(&mut code, n.rem_euclid(256) as usize),
+            Instruction::Clear => {
+                code += &format!("    fetch [{}]\n    push 0\n    storei\n", POINTER);
+            }
+            Instruction::MoveAdd(offset) => {
+                // data[p + offset] = (data[p + offset] + data[p]) % 256; data[p] = 0
+                // セルが 0 なら何もしない。移す先の範囲も、インタプリタと同じく調べない
+                code += &format!("    fetch [{}]\n    fetchi\n    jz moved_{}\n", POINTER, pc);
+                check_pointer(&mut code, offset as i32);
+                code += &format!(
+                    "    fetch [{0}]\n    push {1}\n    add\n    fetch [{0}]\n    push {1}\n    add\n    fetchi\n",
+                    POINTER, offset
+                );
+                code += &format!("    fetch [{}]\n    fetchi\n", POINTER);
+                code += "    add\n    push 256\n    mod\n    storei\n";
+                code += &format!("    fetch [{}]\n    push 0\n    storei\n", POINTER);
+                code += &format!("moved_{}:\n", pc);
+            }
+        }
+        pc += count;
+    }
+    code += "    halt\n";
+    code
+}
+
+fn is_foldable(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::IncrementPointer
+            | Instruction::DecrementPointer
+            | Instruction::IncrementValue
+            | Instruction::DecrementValue
+    )
+}
+
+fn same_kind(a: &Instruction, b: &Instruction) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+fn move_pointer(code: &mut String, offset: i32) {
+    check_pointer(code, offset);
+    *code += &format!(
+        "    fetch [{0}]\n    push {1}\n    add\n    store [{0}]\n",
+        POINTER, offset
+    );
+}
+
+/// ポインタに offset を足した位置がセルの範囲になければ OUT_OF_RANGE で止める
+fn check_pointer(code: &mut String, offset: i32) {
+    *code += &format!(
+        "    fetch [{0}]\n    push {1}\n    add\n    push 0\n    ge\n",
+        POINTER, offset
+    );
+    *code += &format!(
+        "    fetch [{0}]\n    push {1}\n    add\n    push {2}\n    lt\n",
+        POINTER, offset, MEMORY_SIZE
+    );
+    *code += "    and
+    pushs 0
+    assert
+";
+}
+
+fn add_value(code: &mut String, n: usize) {
+    *code += &format!(
+        "    fetch [{0}]\n    fetch [{0}]\n    fetchi\n    push {1}\n    add\n    push 256\n    mod\n    storei\n",
+        POINTER, n
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{compile, optimize};
+    use super::*;
+    use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+    fn run(src: &str, input: &'static [u8]) -> Vec<u8> {
+        let program = compile(src).unwrap();
+        let out = run_program(&program, input);
+        // 最適化した命令列からも同じ出力になること
+        assert_eq!(out, run_program(&optimize(&program), input));
+        out
+    }
+
+    fn run_program(program: &[Instruction], input: &'static [u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        execute(program, input, &mut out).unwrap();
+        out
+    }
+
+    fn execute(
+        program: &[Instruction],
+        input: &'static [u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let code = translate(program);
+        let mut vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        vm.set_input(Box::new(input));
+        vm.execute(out)
+            .map(|_| ())
+            .map_err(|e| e.message().to_string())
+    }
+
+    #[test]
+    fn test_hello_world() {
+        let src = r#"
+++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
+"#;
+        assert_eq!(b"Hello World!\n", &run(src, b"")[..]);
+    }
+
+    #[test]
+    fn test_wrap_and_input() {
+        // 0 から 1 引くと 255、さらに 1 足すと 0 に戻る
+        assert_eq!("\u{ff}".as_bytes(), &run("-.", b"")[..]);
+        assert_eq!(b"\0", &run("-+.", b"")[..]);
+        // EOF は 255 になるので、1 足して 0 になるまで入力をそのまま出力する
+        assert_eq!(b"ab", &run(",+[-.,+]", b"ab")[..]);
+    }
+
+    #[test]
+    fn test_idioms() {
+        // 2 + 3 を移して出力し、移した元は 0 になっている
+        assert_eq!(b"\x05\0", &run("++>+++<[->+<]>.<.", b"")[..]);
+        assert_eq!(b"\0", &run("+++++[-].", b"")[..]);
+    }
+
+    #[test]
+    fn test_pointer_out_of_range() {
+        // セルの範囲を出る移動はポインタを書き換える前に止める
+        let right = format!("+.{}.>+.", ">".repeat(MEMORY_SIZE - 1));
+        let cases = [("+.<.", &b"\x01"[..]), (&right[..], &b"\x01\0"[..])];
+        for (src, expected) in cases.iter() {
+            let program = compile(src).unwrap();
+            for program in [optimize(&program), program] {
+                let mut out = Vec::new();
+                let e = execute(&program, b"", &mut out).unwrap_err();
+                assert_eq!(OUT_OF_RANGE, e);
+                assert_eq!(*expected, &out[..]);
+            }
+        }
+        // 最後のセルは使える
+        let src = format!("{}+[<+>-]<.", ">".repeat(MEMORY_SIZE - 1));
+        assert_eq!(b"\x01", &run(&src, b"")[..]);
+        // 範囲の外に移して足す
+        let program = optimize(&compile("+[-<+>]").unwrap());
+        assert!(program.iter().any(|i| matches!(i, Instruction::MoveAdd(_))));
+        let e = execute(&program, b"", &mut Vec::new()).unwrap_err();
+        assert_eq!(OUT_OF_RANGE, e);
+        // セルが 0 なら移す先が範囲の外でも止めない
+        let src = "[-<+>]+.";
+        let mut expected = Vec::new();
+        super::super::execute(&compile(src).unwrap(), &b""[..], &mut expected).unwrap();
+        assert_eq!(b"\x01", &expected[..]);
+        assert_eq!(expected, run(src, b""));
+    }
+}
@@ -0,0 +1,270 @@
+//! 仮想マシンの命令セット。
+//! 命令コード、ニーモニック、オペランドの種類とエンコード、スタックへの作用をここで一度だけ定め、
+//! コード生成器の出力と仮想マシンの読み込み、実行の両方がこの表を使う。
+//! 命令を加えるときは instruction_set! の表に 1 行加える。
+
+pub mod random;
+
+use std::convert::TryInto;
+
+/// オペランドの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// オペランドを持たない
+    None,
+    /// 変数の番号。アセンブリでは `[n]` と書く
+    Address,
+    /// 4 バイトの整数
+    Int,
+    /// 8 バイトの整数
+    Wide,
+    /// 文字列プールの番号
+    Str,
+    /// f32 のビット列
+    Float,
+    /// 次の命令からの相対アドレス。アセンブリでは `(offset) address` かラベル名で書く
+    Offset,
+    /// 出力の幅。負なら左に寄せる
+    Width,
+}
+
+impl Operand {
+    /// エンコードしたオペランドのバイト数
+    pub fn size(self) -> usize {
+        match self {
+            Operand::None => 0,
+            Operand::Wide => 8,
+            _ => 4,
+        }
+    }
+}
+
+/// 1 つの命令の定義
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpInfo {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+    /// スタックから取り出す値の数
+    pub pops: usize,
+    /// スタックに積む値の数
+    pub pushes: usize,
+}
+
+impl OpInfo {
+    /// オペランドを含めた命令のバイト数
+    pub fn size(&self) -> usize {
+        1 + self.operand.size()
+    }
+}
+
+macro_rules! instruction_set {
+    ($(
+        $(#[$doc:meta])*
+        $name:ident = $opcode:literal, $mnemonic:literal, $operand:ident, ($pops:literal, $pushes:literal);
+    )*) => {
+        $(
+            $(#[$doc])*
+            pub const $name: u8 = $opcode;
+        )*
+
+        /// 命令コードの順に並べた、すべての命令の定義
+        pub const INSTRUCTIONS: &[OpInfo] = &[$(
+            OpInfo {
+                opcode: $opcode,
+                mnemonic: $mnemonic,
+                operand: Operand::$operand,
+                pops: $pops,
+                pushes: $pushes,
+            },
+        )*];
+    };
+}
+
+instruction_set! {
+    FETCH = 0, "fetch", Address, (0, 1);
+    STORE = 1, "store", Address, (1, 0);
+    PUSH = 2, "push", Int, (0, 1);
+    ADD = 3, "add", None, (2, 1);
+    SUB = 4, "sub", None, (2, 1);
+    MUL = 5, "mul", None, (2, 1);
+    DIV = 6, "div", None, (2, 1);
+    MOD = 7, "mod", None, (2, 1);
+    LT = 8, "lt", None, (2, 1);
+    GT = 9, "gt", None, (2, 1);
+    LE = 10, "le", None, (2, 1);
+    GE = 11, "ge", None, (2, 1);
+    EQ = 12, "eq", None, (2, 1);
+    NE = 13, "ne", None, (2, 1);
+    AND = 14, "and", None, (2, 1);
+    OR = 15, "or", None, (2, 1);
+    NEG = 16, "neg", None, (1, 1);
+    NOT = 17, "not", None, (1, 1);
+    JMP = 18, "jmp", Offset, (0, 0);
+    JZ = 19, "jz", Offset, (1, 0);
+    PRTC = 20, "prtc", None, (1, 0);
+    PRTS = 21, "prts", None, (1, 0);
+    PRTI = 22, "prti", None, (1, 0);
+    HALT = 23, "halt", None, (0, 0);
+    GETC = 24, "getc", None, (0, 1);
+    FETCHI = 25, "fetchi", None, (1, 1);
+    STOREI = 26, "storei", None, (2, 0);
+    BAND = 27, "band", None, (2, 1);
+    BOR = 28, "bor", None, (2, 1);
+    BXOR = 29, "bxor", None, (2, 1);
+    SHL = 30, "shl", None, (2, 1);
+    SHR = 31, "shr", None, (2, 1);
+    RAND = 32, "rand", None, (1, 1);
+    CLOCK = 33, "clock", None, (0, 1);
+    /// スタックトップを終了コードにして止まる
+    EXIT = 34, "exit", None, (1, 0);
+    /// 2 番目の値が 0 なら、スタックトップの番号の文字列をエラーにして止まる
+    ASSERT = 35, "assert", None, (2, 0);
+    /// 文字列プールの番号を、文字列の値として積む
+    PUSHS = 36, "pushs", Str, (0, 1);
+    /// 入力から 10 進の整数を読んで積む
+    GETI = 37, "geti", None, (0, 1);
+    /// スタックトップの整数を、オペランドの幅に揃えて出力する。負の幅なら左に寄せる。
+    PRTIW = 38, "prtiw", Width, (1, 0);
+    /// オペランドのビット列を浮動小数点数として積む。
+    /// 浮動小数点数の値は f32 のビット列をそのまま i32 の値として持つ。
+    PUSHF = 39, "pushf", Float, (0, 1);
+    FADD = 40, "fadd", None, (2, 1);
+    FSUB = 41, "fsub", None, (2, 1);
+    FMUL = 42, "fmul", None, (2, 1);
+    FDIV = 43, "fdiv", None, (2, 1);
+    FNEG = 44, "fneg", None, (1, 1);
+    /// 比較の結果は整数の 0 か 1
+    FLT = 45, "flt", None, (2, 1);
+    FGT = 46, "fgt", None, (2, 1);
+    FLE = 47, "fle", None, (2, 1);
+    FGE = 48, "fge", None, (2, 1);
+    FEQ = 49, "feq", None, (2, 1);
+    FNE = 50, "fne", None, (2, 1);
+    /// 整数を浮動小数点数にする
+    ITOF = 51, "itof", None, (1, 1);
+    /// 浮動小数点数を 0 の方向に丸めた整数にする。範囲外の値は飽和し、 NaN は 0 になる。
+    FTOI = 52, "ftoi", None, (1, 1);
+    PRTF = 53, "prtf", None, (1, 0);
+    /// 8 バイトのオペランドの値を積む。ヘッダに "Width: 64" がある場合だけ使える。
+    PUSHW = 54, "pushw", Wide, (0, 1);
+    // 関数呼び出しの命令。戻り先と保存したフレームポインタはスタックとは別のコールスタックに積む。
+    // call と leave のスタックへの作用は呼び出す関数で決まるので、表では 0 とする。
+    /// 戻り先をコールスタックに積んでジャンプする。ジャンプ先は enter でなければならない。
+    /// 呼び出し側から見ると、 enter のオペランドの数の引数を取り出して戻り値を 1 つ積む。
+    CALL = 55, "call", Offset, (0, 0);
+    /// コールスタックの戻り先にジャンプする
+    RET = 56, "ret", None, (0, 0);
+    /// フレームポインタを保存し、スタックの上のオペランドの数の値を引数とするフレームを作る
+    ENTER = 57, "enter", Int, (0, 0);
+    /// スタックトップを戻り値として残してフレームを捨て、フレームポインタを戻す
+    LEAVE = 58, "leave", None, (0, 0);
+    /// フレームの先頭からオペランドの位置の値を積む。引数は 0 から順に並ぶ。
+    FETCHL = 59, "fetchl", Int, (0, 1);
+    /// スタックトップをフレームの先頭からオペランドの位置に書き込む
+    STOREL = 60, "storel", Int, (1, 0);
+    /// 2 番目の値のスタックトップ乗。 0 の負の乗は 0 除算のエラーにする。
+    POW = 61, "pow", None, (2, 1);
+    /// 埋め込み側が登録したオペランドの番号のホスト関数を呼ぶ。
+    /// 登録した引数の数の値を取り出して戻り値を 1 つ積む。作用は登録した関数で決まるので、表では 0 とする。
+    XCALL = 62, "xcall", Int, (0, 0);
+}
+
+/// 命令コードの定義。知らない命令コードなら None。
+pub fn info(opcode: u8) -> Option<&'static OpInfo> {
+    INSTRUCTIONS.get(opcode as usize)
+}
+
+/// ニーモニックの命令の定義。知らないニーモニックなら None。
+pub fn from_mnemonic(mnemonic: &str) -> Option<&'static OpInfo> {
+    INSTRUCTIONS.iter().find(|info| info.mnemonic == mnemonic)
+}
+
+/// トレース出力用のニーモニック。知らない命令コードなら "???"。
+pub fn mnemonic(opcode: u8) -> &'static str {
+    info(opcode).map_or("???", |info| info.mnemonic)
+}
+
+/// 4 バイトのオペランドを持つ命令なら true
+pub fn has_operand(opcode: u8) -> bool {
+    info(opcode).is_some_and(|info| info.operand.size() == 4)
+}
+
+/// オペランドを含めた命令のバイト数。知らない命令コードは 1 バイトとする。
+pub fn instruction_size(opcode: u8) -> usize {
+    info(opcode).map_or(1, OpInfo::size)
+}
+
+/// 命令が取り出す値と積む値の数
+pub fn stack_effect(opcode: u8) -> (usize, usize) {
+    info(opcode).map_or((0, 0), |info| (info.pops, info.pushes))
+}
+
+/// オペランドはリトルエンディアンの 4 バイトで持つ。
+/// バイトコードをファイルに書き出しても、ホストのバイト順によらずに読み戻せる。
+pub fn encode_operand(value: i32) -> [u8; 4] {
+    value.to_le_bytes()
+}
+
+/// pushw のオペランド。リトルエンディアンの 8 バイト。
+pub fn encode_wide_operand(value: i64) -> [u8; 8] {
+    value.to_le_bytes()
+}
+
+/// bytes の先頭 4 バイトのオペランド。足りなければ None。
+pub fn decode_operand(bytes: &[u8]) -> Option<i32> {
+    bytes
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(i32::from_le_bytes)
+}
+
+/// bytes の先頭 8 バイトの pushw のオペランド。足りなければ None。
+pub fn decode_wide_operand(bytes: &[u8]) -> Option<i64> {
+    bytes
+        .get(..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(i64::from_le_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        // 表の位置が命令コードと一致し、ニーモニックに重複がない
+        for (i, info) in INSTRUCTIONS.iter().enumerate() {
+            assert_eq!(i, info.opcode as usize, "{}", info.mnemonic);
+            assert_eq!(Some(info), from_mnemonic(info.mnemonic));
+        }
+        assert_eq!("fetch", mnemonic(FETCH));
+        assert_eq!("pushw", mnemonic(PUSHW));
+        assert_eq!("???", mnemonic(XCALL + 1));
+        assert!(from_mnemonic("callq").is_none());
+    }
+
+    #[test]
+    fn test_size() {
+        assert_eq!(5, instruction_size(FETCH));
+        assert_eq!(5, instruction_size(JZ));
+        assert_eq!(9, instruction_size(PUSHW));
+        assert_eq!(1, instruction_size(ADD));
+        assert!(has_operand(PRTIW));
+        assert!(!has_operand(PUSHW));
+        assert!(!has_operand(HALT));
+        assert_eq!((2, 0), stack_effect(ASSERT));
+        assert_eq!((0, 1), stack_effect(CLOCK));
+    }
+
+    #[test]
+    fn test_operand() {
+        assert_eq!(Some(-2), decode_operand(&encode_operand(-2)));
+        assert_eq!(
+            Some(1 << 40),
+            decode_wide_operand(&encode_wide_operand(1 << 40))
+        );
+        assert_eq!(None, decode_operand(&[1, 2, 3]));
+        assert_eq!(None, decode_wide_operand(&encode_operand(1)));
+    }
+}
@@ -0,0 +1,59 @@
+//! rand 命令と、 AST インタプリタの rand(n) が返す擬似乱数。
+
+/// rand の既定の種
+pub const DEFAULT_SEED: u32 = 1;
+
+/// rand(n) が返す値の上限 (この値は含まない)
+pub const RAND_MAX: i32 = 0x8000;
+
+/// rand(n) の擬似乱数。線形合同法で、インタプリタと仮想マシン、
+/// 生成した C のソースがすべて同じ列を返すようにする。
+#[derive(Debug, Clone)]
+pub struct Random {
+    state: u32,
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new(DEFAULT_SEED)
+    }
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        Random { state: seed }
+    }
+
+    /// 0 以上 n 未満の値を返す。 n が正でなければ None。
+    /// 返す値は RAND_MAX 未満なので、 n が RAND_MAX より大きくても RAND_MAX 未満になる。
+    pub fn next(&mut self, n: i32) -> Option<i32> {
+        if n <= 0 {
+            return None;
+        }
+        self.state = self.state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        Some(((self.state >> 16) as i32 & (RAND_MAX - 1)) % n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random() {
+        let mut random = Random::default();
+        let values: Vec<i32> = (0..5).map(|_| random.next(100).unwrap()).collect();
+        assert_eq!(vec![38, 58, 13, 15, 51], values);
+
+        // 同じ種なら同じ列になる
+        let mut random = Random::new(42);
+        let mut other = Random::new(42);
+        for _ in 0..100 {
+            let value = random.next(6).unwrap();
+            assert!((0..6).contains(&value));
+            assert_eq!(Some(value), other.next(6));
+        }
+        assert_eq!(None, random.next(0));
+        assert_eq!(None, random.next(-1));
+    }
+}
@@ -0,0 +1,206 @@
+mod turtle;
+
+pub use turtle::{Segment, Turtle};
+
+use bitmap::{Bitmap, Canvas, Color};
+use std::collections::HashMap;
+
+/// 画像の縁に空ける画素数
+const MARGIN: f64 = 4.0;
+
+/// L-system。開始記号列 (axiom) の各記号を書き換え規則で置き換えることを繰り返し、
+/// できた記号列をタートルグラフィックスの命令として描く。
+///
+/// | 記号           | 意味                         |
+/// |----------------|------------------------------|
+/// | F, G, A, B     | 線を引きながら 1 進む        |
+/// | f              | 線を引かずに 1 進む          |
+/// | +              | 左に angle 度回る            |
+/// | -              | 右に angle 度回る            |
+/// | [              | 位置と向きを退避する         |
+/// | ]              | 退避した位置と向きに戻る     |
+///
+/// それ以外の記号 (X, Y など) は書き換えにだけ使い、描くときは無視する。
+#[derive(Debug, Clone, PartialEq)]
+pub struct LSystem {
+    axiom: String,
+    rules: HashMap<char, String>,
+    angle: f64,
+}
+
+impl LSystem {
+    /// 書き換え規則のない L-system。 angle は + と - で回る角度 (度)。
+    pub fn new(axiom: &str, angle: f64) -> Self {
+        LSystem {
+            axiom: axiom.to_string(),
+            rules: HashMap::new(),
+            angle,
+        }
+    }
+
+    /// ドラゴン曲線
+    pub fn dragon_curve() -> Self {
+        let mut l_system = LSystem::new("FX", 90.0);
+        l_system.add_rule('X', "X+YF+");
+        l_system.add_rule('Y', "-FX-Y");
+        l_system
+    }
+
+    /// シェルピンスキーの矢じり曲線
+    pub fn sierpinski_arrowhead() -> Self {
+        let mut l_system = LSystem::new("A", 60.0);
+        l_system.add_rule('A', "B-A-B");
+        l_system.add_rule('B', "A+B+A");
+        l_system
+    }
+
+    /// 記号 from を to に書き換える規則を加える。同じ記号の規則は置き換える。
+    pub fn add_rule(&mut self, from: char, to: &str) -> &mut Self {
+        self.rules.insert(from, to.to_string());
+        self
+    }
+
+    pub fn axiom(&self) -> &str {
+        &self.axiom
+    }
+
+    pub fn angle(&self) -> f64 {
+        self.angle
+    }
+
+    /// + と - で回る角度 (度) を変える
+    pub fn set_angle(&mut self, angle: f64) -> &mut Self {
+        self.angle = angle;
+        self
+    }
+
+    /// axiom を iterations 回書き換えた記号列
+    pub fn expand(&self, iterations: usize) -> String {
+        let mut s = self.axiom.clone();
+        for _ in 0..iterations {
+            let mut next = String::with_capacity(s.len() * 2);
+            for c in s.chars() {
+                match self.rules.get(&c) {
+                    Some(to) => next.push_str(to),
+                    None => next.push(c),
+                }
+            }
+            s = next;
+        }
+        s
+    }
+
+    /// iterations 回書き換えた記号列を、長さ 1 の歩幅で描いたときの線分
+    pub fn segments(&self, iterations: usize) -> Vec<Segment> {
+        let mut turtle = Turtle::new();
+        for c in self.expand(iterations).chars() {
+            match c {
+                'F' | 'G' | 'A' | 'B' => turtle.forward(1.0),
+                'f' => turtle.move_forward(1.0),
+                '+' => turtle.left(self.angle),
+                '-' => turtle.right(self.angle),
+                '[' => turtle.push(),
+                ']' => turtle.pop(),
+                _ => {}
+            }
+        }
+        turtle.into_segments()
+    }
+
+    /// iterations 回書き換えた図形を、長いほうの辺が size 画素の画像に収まるように
+    /// 縦横比を保って拡大し、黒地に c で描く。
+    pub fn render(&self, iterations: usize, size: usize, c: Color) -> Bitmap {
+        let segments = self.segments(iterations);
+        let (mut min_x, mut min_y) = (0.0f64, 0.0f64);
+        let (mut max_x, mut max_y) = (0.0f64, 0.0f64);
+        for &((x0, y0), (x1, y1)) in &segments {
+            min_x = min_x.min(x0).min(x1);
+            min_y = min_y.min(y0).min(y1);
+            max_x = max_x.max(x0).max(x1);
+            max_y = max_y.max(y0).max(y1);
+        }
+        let extent = (max_x - min_x).max(max_y - min_y);
+        let room = (size as f64 - 1.0 - 2.0 * MARGIN).max(0.0);
+        let scale = if extent > 0.0 { room / extent } else { 0.0 };
+        let width = ((max_x - min_x) * scale + 2.0 * MARGIN).round() as usize + 1;
+        let height = ((max_y - min_y) * scale + 2.0 * MARGIN).round() as usize + 1;
+
+        let mut bmp = Bitmap::new(width, height);
+        let mut canvas = Canvas::new(&mut bmp);
+        // 画像の y 軸は下向きなので反転する
+        canvas
+            .translate(MARGIN, MARGIN)
+            .scale(scale, -scale)
+            .translate(-min_x, -max_y);
+        for &((x0, y0), (x1, y1)) in &segments {
+            canvas.line(x0, y0, x1, y1, c);
+        }
+        bmp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let dragon = LSystem::dragon_curve();
+        assert_eq!("FX", dragon.expand(0));
+        assert_eq!("FX+YF+", dragon.expand(1));
+        assert_eq!("FX+YF++-FX-YF+", dragon.expand(2));
+
+        let arrowhead = LSystem::sierpinski_arrowhead();
+        assert_eq!("B-A-B", arrowhead.expand(1));
+        assert_eq!("A+B+A-B-A-B-A+B+A", arrowhead.expand(2));
+        let drawn = arrowhead
+            .expand(6)
+            .chars()
+            .filter(|&c| c == 'A' || c == 'B')
+            .count();
+        assert_eq!(3usize.pow(6), drawn);
+    }
+
+    #[test]
+    fn test_segments() {
+        // 2 回書き換えたドラゴン曲線は F+F+-F-F で、 (0,0) (1,0) (1,1) (0,1) (0,2) を通る
+        let segments = LSystem::dragon_curve().segments(2);
+        let points: Vec<(i32, i32)> = segments
+            .iter()
+            .map(|&(_, (x, y))| (x.round() as i32, y.round() as i32))
+            .collect();
+        assert_eq!(vec![(1, 0), (1, 1), (0, 1), (0, 2)], points);
+
+        // 矢じり曲線の端点は 2^iterations 離れる
+        for iterations in 0..6 {
+            let segments = LSystem::sierpinski_arrowhead().segments(iterations);
+            let (x, y) = segments.last().unwrap().1;
+            assert!((x.hypot(y) - 2f64.powi(iterations as i32)).abs() < 1e-9);
+        }
+
+        // 枝分かれ
+        let mut plant = LSystem::new("F[+F]F", 90.0);
+        plant.add_rule('F', "FF");
+        let segments = plant.segments(0);
+        assert_eq!(3, segments.len());
+        assert_eq!(segments[0].1, segments[2].0);
+        assert_eq!(6, plant.segments(1).len());
+    }
+
+    #[test]
+    fn test_render() {
+        let bmp = LSystem::dragon_curve().render(2, 29, Color::WHITE);
+        // 幅 1、高さ 2 の図形を、高さ 20 画素に拡大する
+        assert_eq!((19, 29), (bmp.width(), bmp.height()));
+        // 始点 (0, 0) は左下、終点 (0, 2) は左上
+        assert_eq!(Color::WHITE, *bmp.color(4, 24));
+        assert_eq!(Color::WHITE, *bmp.color(4, 4));
+        assert_eq!(Color::WHITE, *bmp.color(14, 19));
+        assert_eq!(Color::BLACK, *bmp.color(9, 9));
+        assert_eq!(Color::BLACK, *bmp.color(0, 0));
+
+        // 線を引かない L-system は空の画像になる
+        let bmp = LSystem::new("X", 90.0).render(3, 10, Color::WHITE);
+        assert_eq!((9, 9), (bmp.width(), bmp.height()));
+    }
+}
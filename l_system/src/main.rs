@@ -0,0 +1,172 @@
+use bitmap::Color;
+use l_system::LSystem;
+use std::fs::File;
+use std::io::BufWriter;
+
+struct Options {
+    l_system: LSystem,
+    iterations: usize,
+    size: usize,
+    color: Color,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            l_system: LSystem::dragon_curve(),
+            iterations: 12,
+            size: 512,
+            color: Color::WHITE,
+        }
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    // --axiom や --rule を指定したら、 --curve の代わりにそれで L-system を作る
+    let mut axiom = None;
+    let mut rules = Vec::new();
+    let mut angle = None;
+    let mut i = 0;
+    while i < args.len() {
+        let value = args
+            .get(i + 1)
+            .ok_or_else(|| format!("{} requires an argument", args[i]))?;
+        match args[i].as_str() {
+            "--curve" => {
+                options.l_system = match value.as_str() {
+                    "dragon" => LSystem::dragon_curve(),
+                    "arrowhead" => LSystem::sierpinski_arrowhead(),
+                    _ => return Err(format!("unknown curve: {}", value)),
+                };
+            }
+            "--iterations" => {
+                options.iterations = value
+                    .parse()
+                    .map_err(|_| format!("invalid iterations: {}", value))?;
+            }
+            "--size" => {
+                options.size = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid size: {}", value))?;
+            }
+            "--angle" => {
+                angle = Some(
+                    value
+                        .parse::<f64>()
+                        .map_err(|_| format!("invalid angle: {}", value))?,
+                );
+            }
+            "--axiom" => axiom = Some(value.clone()),
+            "--rule" => {
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(from), Some('=')) => rules.push((from, chars.as_str().to_string())),
+                    _ => return Err(format!("invalid rule: {}", value)),
+                }
+            }
+            "--color" => {
+                options.color = match value.as_str() {
+                    "white" => Color::WHITE,
+                    "red" => Color::RED,
+                    "green" => Color::GREEN,
+                    "blue" => Color::BLUE,
+                    "yellow" => Color::YELLOW,
+                    _ => return Err(format!("unknown color: {}", value)),
+                };
+            }
+            _ => return Err(format!("unknown option: {}", args[i])),
+        }
+        i += 2;
+    }
+
+    if axiom.is_some() || !rules.is_empty() {
+        let axiom = axiom.ok_or_else(|| "--rule requires --axiom".to_string())?;
+        let mut l_system = LSystem::new(&axiom, 90.0);
+        for (from, to) in &rules {
+            l_system.add_rule(*from, to);
+        }
+        options.l_system = l_system;
+    }
+    if let Some(angle) = angle {
+        options.l_system.set_angle(angle);
+    }
+    Ok(options)
+}
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] filename", name);
+    println!();
+    println!("    writes an L-system curve to filename as P6");
+    println!();
+    println!("    --curve <dragon|arrowhead>  predefined curve (default: dragon)");
+    println!("    --iterations n              number of rewrites (default: 12)");
+    println!("    --size n                    length of the longer side in pixels (default: 512)");
+    println!("    --angle degrees             turning angle of + and - (default: 90 for custom)");
+    println!("    --axiom symbols             initial symbols of a custom L-system");
+    println!("    --rule X=symbols            rewrite rule of a custom L-system (repeatable)");
+    println!("    --color <white|red|green|blue|yellow>  line color (default: white)");
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        print_usage(&args[0]);
+        return;
+    }
+
+    let filename = &args[args.len() - 1];
+    let options = match parse_options(&args[1..args.len() - 1]) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let bmp = options
+        .l_system
+        .render(options.iterations, options.size, options.color);
+    let f = File::create(filename).unwrap_or_else(|_| panic!("{} cannot be created.", filename));
+    bmp.write_ppm(&mut BufWriter::new(f))
+        .expect("write PPM failed.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse_options(&args(&["--curve", "arrowhead", "--iterations", "4"])).unwrap();
+        assert_eq!(LSystem::sierpinski_arrowhead(), options.l_system);
+        assert_eq!(4, options.iterations);
+        assert_eq!(512, options.size);
+
+        let options = parse_options(&args(&[
+            "--axiom", "F", "--rule", "F=F+F-F", "--angle", "60",
+        ]))
+        .unwrap();
+        assert_eq!("F+F-F", options.l_system.expand(1));
+        assert_eq!(60.0, options.l_system.angle());
+
+        let options = parse_options(&args(&["--angle", "45"])).unwrap();
+        assert_eq!(
+            LSystem::dragon_curve().expand(3),
+            options.l_system.expand(3)
+        );
+        assert_eq!(45.0, options.l_system.angle());
+
+        assert!(parse_options(&args(&["--curve", "koch"])).is_err());
+        assert!(parse_options(&args(&["--rule", "F"])).is_err());
+        assert!(parse_options(&args(&["--rule", "F=FF"])).is_err());
+        assert!(parse_options(&args(&["--size", "0"])).is_err());
+        assert!(parse_options(&args(&["--iterations"])).is_err());
+    }
+}
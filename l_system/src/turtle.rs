@@ -0,0 +1,129 @@
+/// 線分の始点と終点
+pub type Segment = ((f64, f64), (f64, f64));
+
+/// タートルグラフィックスの亀。 y 軸は上向きで、角度は x 軸から反時計回りに度で測る。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turtle {
+    x: f64,
+    y: f64,
+    heading: f64,
+    /// push で退避した位置と向き
+    saved: Vec<(f64, f64, f64)>,
+    segments: Vec<Segment>,
+}
+
+impl Turtle {
+    /// 原点で x 軸の正の向きを向いて始める
+    pub fn new() -> Self {
+        Turtle {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            saved: Vec::new(),
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn position(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    pub fn heading(&self) -> f64 {
+        self.heading
+    }
+
+    /// 線を引きながら distance だけ進む
+    pub fn forward(&mut self, distance: f64) {
+        let from = (self.x, self.y);
+        self.advance(distance);
+        self.segments.push((from, (self.x, self.y)));
+    }
+
+    /// 線を引かずに distance だけ進む
+    pub fn move_forward(&mut self, distance: f64) {
+        self.advance(distance);
+    }
+
+    fn advance(&mut self, distance: f64) {
+        let radians = self.heading.to_radians();
+        self.x += distance * radians.cos();
+        self.y += distance * radians.sin();
+    }
+
+    /// 左 (反時計回り) に angle 度回る
+    pub fn left(&mut self, angle: f64) {
+        self.heading = (self.heading + angle) % 360.0;
+    }
+
+    /// 右 (時計回り) に angle 度回る
+    pub fn right(&mut self, angle: f64) {
+        self.left(-angle);
+    }
+
+    /// 位置と向きを退避する
+    pub fn push(&mut self) {
+        self.saved.push((self.x, self.y, self.heading));
+    }
+
+    /// 最後に退避した位置と向きに戻る。退避していなければ何もしない。
+    pub fn pop(&mut self) {
+        if let Some((x, y, heading)) = self.saved.pop() {
+            self.x = x;
+            self.y = y;
+            self.heading = heading;
+        }
+    }
+
+    /// これまでに引いた線分
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn into_segments(self) -> Vec<Segment> {
+        self.segments
+    }
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Turtle::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(expected: (f64, f64), actual: (f64, f64)) {
+        assert!(
+            (expected.0 - actual.0).abs() < 1e-9 && (expected.1 - actual.1).abs() < 1e-9,
+            "expected {:?}, actual {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_turtle() {
+        let mut turtle = Turtle::new();
+        turtle.forward(2.0);
+        turtle.left(90.0);
+        turtle.move_forward(1.0);
+        turtle.push();
+        turtle.right(45.0);
+        turtle.forward(2.0f64.sqrt());
+        assert_near((3.0, 2.0), turtle.position());
+        turtle.pop();
+        assert_near((2.0, 1.0), turtle.position());
+        assert_eq!(90.0, turtle.heading());
+        turtle.pop();
+        assert_near((2.0, 1.0), turtle.position());
+
+        let segments = turtle.into_segments();
+        assert_eq!(2, segments.len());
+        assert_near((0.0, 0.0), segments[0].0);
+        assert_near((2.0, 0.0), segments[0].1);
+        assert_near((2.0, 1.0), segments[1].0);
+        assert_near((3.0, 2.0), segments[1].1);
+    }
+}
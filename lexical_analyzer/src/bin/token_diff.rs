@@ -0,0 +1,40 @@
+use lexical_analyzer::cli::{Command, Flag, Options, EXIT_FAILURE};
+use lexical_analyzer::error::*;
+use lexical_analyzer::token_diff::diff_listings;
+
+use std::io::Write;
+use std::process;
+
+const COMMAND: Command = Command {
+    name: "token_diff",
+    description: "Compares a token listing with the listing given by --expected and writes the first token that differs. Exits with 1 if they differ.",
+    flags: &[Flag::Expected, Flag::IgnorePositions],
+};
+
+fn run(options: &Options) -> Result<()> {
+    let left = options.read_input()?;
+    let right = match options.read_expected()? {
+        Some(right) => String::from_utf8(right).map_err(|e| {
+            CompileError::new(ErrorKind::IOError, format!("expected listing: {}", e))
+        })?,
+        None => {
+            return Err(CompileError::new(
+                ErrorKind::IOError,
+                "the listing to compare with is not given (use --expected <FILE>)",
+            ))
+        }
+    };
+    let divergence = diff_listings(&left, &right, options.positions)?;
+    let mut writer = options.create_output()?;
+    if let Some(divergence) = &divergence {
+        write!(writer, "{}", divergence)
+            .and_then(|_| writer.flush())
+            .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+        process::exit(EXIT_FAILURE);
+    }
+    Ok(())
+}
+
+fn main() {
+    COMMAND.main(run);
+}
@@ -0,0 +1,766 @@
+use super::diagnostic::Diagnostics;
+use super::dialect::Dialect;
+use super::error::*;
+use super::highlight::Style;
+use super::token::TokenFormat;
+use super::token_diff::Positions;
+use super::width::Width;
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader, BufWriter, Read, Write};
+use std::process;
+use std::result;
+
+/// 正常終了
+pub const EXIT_SUCCESS: i32 = 0;
+/// コンパイル・実行時のエラー
+pub const EXIT_FAILURE: i32 = 1;
+/// コマンドライン引数の誤り
+pub const EXIT_USAGE: i32 = 2;
+
+/// コマンドごとに受け付けるオプション
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Format,
+    Trace,
+    Optimize,
+    Profile,
+    Target,
+    Dialect,
+    Expected,
+    Highlight,
+    Runs,
+    Symbols,
+    StackSize,
+    Trivia,
+    TraceVars,
+    DenyWarnings,
+    Emit,
+    Watch,
+    Width,
+    Compat,
+    LegacyTokens,
+    IgnorePositions,
+}
+
+/// 出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Rosetta Code のタスクで定められた形式
+    Text,
+    /// 内部構造をそのまま出力する
+    Debug,
+}
+
+/// エラーの出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticFormat {
+    /// 人が読むための形式
+    Text,
+    /// 1 行に 1 つの JSON オブジェクト
+    Json,
+}
+
+/// コード生成の出力先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// 仮想マシンのアセンブリ
+    Vm,
+    /// C のソース
+    C,
+}
+
+/// --emit で書き出す中間表現
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    /// lexical_analyzer と同じトークン列
+    Tokens,
+    /// syntax_analyzer と同じ平坦化した AST
+    Ast,
+    /// 仮想マシンのアセンブリ
+    Asm,
+    /// 仮想マシンのバイトコードのファイル
+    Bytecode,
+}
+
+impl Artifact {
+    /// パイプラインの順
+    pub const ALL: &'static [Artifact] = &[
+        Artifact::Tokens,
+        Artifact::Ast,
+        Artifact::Asm,
+        Artifact::Bytecode,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Artifact::Tokens => "tokens",
+            Artifact::Ast => "ast",
+            Artifact::Asm => "asm",
+            Artifact::Bytecode => "bytecode",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Artifact> {
+        Artifact::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    /// ソースの隣に書き出すファイルの拡張子
+    pub fn extension(self) -> &'static str {
+        match self {
+            Artifact::Tokens => "lex",
+            Artifact::Ast => "ast",
+            Artifact::Asm => "asm",
+            Artifact::Bytecode => "tvm",
+        }
+    }
+}
+
+/// --runs を指定しない場合の実行回数
+pub const DEFAULT_RUNS: usize = 10;
+
+/// コマンドの定義。usage の生成と引数のチェックに使う。
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub flags: &'static [Flag],
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub format: Format,
+    pub trace: bool,
+    pub optimize: bool,
+    pub profile: bool,
+    pub target: Target,
+    pub dialect: Dialect,
+    /// 期待する出力のファイル
+    pub expected: Option<String>,
+    pub diagnostics: DiagnosticFormat,
+    /// 色分けしたソースを出力する
+    pub highlight: Option<Style>,
+    /// 時間を測るために実行する回数
+    pub runs: usize,
+    /// 変数名の表を出力する
+    pub symbols: bool,
+    /// 必要なスタックの深さをヘッダに書く
+    pub stack_size: bool,
+    /// 空白とコメントもトークンとして出力する
+    pub trivia: bool,
+    /// 変数への代入を標準エラー出力に書き出す
+    pub trace_vars: bool,
+    /// 警告をエラーにする
+    pub deny_warnings: bool,
+    /// 書き出す中間表現。パイプラインの順に並べ、重複はない。
+    pub emit: Vec<Artifact>,
+    /// ソースが変わるたびに実行し直す
+    pub watch: bool,
+    /// トークン列をタスクの出力例と同じ書式で読み書きする
+    pub compat: bool,
+    /// トークン列を以前の Display の書式で読み書きする
+    pub legacy_tokens: bool,
+    /// トークン列を比べるときに位置のどこまでを比べるか
+    pub positions: Positions,
+}
+
+/// 引数解析の結果
+#[derive(Debug)]
+pub enum Parsed {
+    Run(Options),
+    Help,
+}
+
+#[derive(Debug)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Command {
+    pub fn usage(&self) -> String {
+        let mut s = format!(
+            "{}\n\nUsage: {} [OPTIONS] [INPUT [OUTPUT]]\n\nOptions:\n",
+            self.description, self.name
+        );
+        s += "    -i, --input <FILE>     read from FILE instead of stdin\n";
+        s += "    -o, --output <FILE>    write to FILE instead of stdout\n";
+        s += "        --stdin            read from stdin (default)\n";
+        if self.flags.contains(&Flag::Format) {
+            s += "        --format <FORMAT>  output format: text (default), debug\n";
+        }
+        if self.flags.contains(&Flag::Trace) {
+            s += "        --trace            trace execution to stderr\n";
+        }
+        if self.flags.contains(&Flag::TraceVars) {
+            s += "        --trace-vars       trace variable assignments to stderr\n";
+        }
+        if self.flags.contains(&Flag::Optimize) {
+            s += "        --optimize         enable optimizations\n";
+        }
+        if self.flags.contains(&Flag::Target) {
+            s += "        --target <TARGET>  output language: vm (default), c\n";
+        }
+        if self.flags.contains(&Flag::Symbols) {
+            s += "        --symbols          append the variable table as comments\n";
+        }
+        if self.flags.contains(&Flag::StackSize) {
+            s += "        --stack-size       write the required stack depth in the header\n";
+        }
+        if self.flags.contains(&Flag::Trivia) {
+            s += "        --trivia           also write whitespace and comment tokens\n";
+        }
+        if self.flags.contains(&Flag::Compat) {
+            s += "        --compat           use the aligned token format of the task samples\n";
+        }
+        if self.flags.contains(&Flag::LegacyTokens) {
+            s += "        --legacy-tokens    use the token format of older releases\n";
+        }
+        if self.flags.contains(&Flag::IgnorePositions) {
+            s += "        --ignore-columns   compare only the line numbers of tokens\n";
+            s += "        --ignore-positions compare only the kinds and values of tokens\n";
+        }
+        if self.flags.contains(&Flag::DenyWarnings) {
+            s += "        --deny-warnings    treat warnings as errors\n";
+        }
+        if self.flags.contains(&Flag::Emit) {
+            s += "        --emit <LIST>      comma-separated artifacts to write: tokens, ast,\n";
+            s += "                           asm, bytecode (default: all)\n";
+        }
+        if self.flags.contains(&Flag::Watch) {
+            s += "        --watch            rerun whenever the source changes and show how\n";
+            s += "                           the output changed\n";
+        }
+        if self.flags.contains(&Flag::Dialect) {
+            s += "        --dialect <NAME>   language dialect: extended (default), rosetta,\n";
+            s += "                           increment (extended with ++ and --),\n";
+            s += "                           unicode (extended with non-ASCII identifiers),\n";
+            s += "                           float (extended with floating-point numbers)\n";
+        }
+        if self.flags.contains(&Flag::Width) {
+            s += "        --width <BITS>     integer width: 32 (default), 64\n";
+        }
+        if self.flags.contains(&Flag::Highlight) {
+            s += "        --highlight <STYLE>\n";
+            s += "                           write the highlighted source: ansi, html\n";
+        }
+        if self.flags.contains(&Flag::Expected) {
+            s += "        --expected <FILE>  compare the output with FILE\n";
+        }
+        if self.flags.contains(&Flag::Profile) {
+            s += "        --profile          print an execution profile to stderr\n";
+        }
+        if self.flags.contains(&Flag::Runs) {
+            s += &format!(
+                "        --runs <N>         number of timed runs (default: {})\n",
+                DEFAULT_RUNS
+            );
+        }
+        s += "        --diagnostics <FORMAT>\n";
+        s += "                           error format on stderr: text (default), json\n";
+        s += "    -h, --help             print this help\n";
+        s
+    }
+
+    /// args はプログラム名を含まない引数列
+    pub fn parse<I>(&self, args: I) -> result::Result<Parsed, UsageError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options {
+            input: None,
+            output: None,
+            format: Format::Text,
+            trace: false,
+            optimize: false,
+            profile: false,
+            target: Target::Vm,
+            dialect: Dialect::default(),
+            expected: None,
+            diagnostics: DiagnosticFormat::Text,
+            highlight: None,
+            runs: DEFAULT_RUNS,
+            symbols: false,
+            stack_size: false,
+            trivia: false,
+            trace_vars: false,
+            deny_warnings: false,
+            emit: Artifact::ALL.to_vec(),
+            watch: false,
+            compat: false,
+            legacy_tokens: false,
+            positions: Positions::Exact,
+        };
+        let mut use_stdin = false;
+        let mut positional = Vec::new();
+        let mut width = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // --name=value 形式を --name value と同じに扱う
+            let (name, inline_value) = match arg.find('=') {
+                Some(pos) if arg.starts_with("--") => {
+                    (arg[..pos].to_string(), Some(arg[pos + 1..].to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| match inline_value.clone().or_else(|| args.next()) {
+                Some(v) => Ok(v),
+                None => Err(UsageError(format!("{} requires a value", name))),
+            };
+
+            match &name[..] {
+                "-h" | "--help" => return Ok(Parsed::Help),
+                "-i" | "--input" => options.input = Some(value(&name)?),
+                "-o" | "--output" => options.output = Some(value(&name)?),
+                "--stdin" => use_stdin = true,
+                "--diagnostics" => {
+                    options.diagnostics = match &value(&name)?[..] {
+                        "text" => DiagnosticFormat::Text,
+                        "json" => DiagnosticFormat::Json,
+                        d => return Err(UsageError(format!("unknown diagnostics: {}", d))),
+                    }
+                }
+                "--format" if self.flags.contains(&Flag::Format) => {
+                    options.format = match &value(&name)?[..] {
+                        "text" => Format::Text,
+                        "debug" => Format::Debug,
+                        f => return Err(UsageError(format!("unknown format: {}", f))),
+                    }
+                }
+                "--target" if self.flags.contains(&Flag::Target) => {
+                    options.target = match &value(&name)?[..] {
+                        "vm" => Target::Vm,
+                        "c" => Target::C,
+                        t => return Err(UsageError(format!("unknown target: {}", t))),
+                    }
+                }
+                "--dialect" if self.flags.contains(&Flag::Dialect) => {
+                    let name = value(&name)?;
+                    options.dialect = Dialect::from_name(&name)
+                        .ok_or_else(|| UsageError(format!("unknown dialect: {}", name)))?;
+                }
+                "--width" if self.flags.contains(&Flag::Width) => {
+                    let bits = value(&name)?;
+                    width = Some(
+                        bits.parse()
+                            .ok()
+                            .and_then(Width::from_bits)
+                            .ok_or_else(|| UsageError(format!("unknown width: {}", bits)))?,
+                    );
+                }
+                "--emit" if self.flags.contains(&Flag::Emit) => {
+                    let list = value(&name)?;
+                    let mut emit = Vec::new();
+                    for name in list.split(',').map(str::trim) {
+                        let artifact = Artifact::from_name(name)
+                            .ok_or_else(|| UsageError(format!("unknown artifact: {}", name)))?;
+                        emit.push(artifact);
+                    }
+                    options.emit = Artifact::ALL
+                        .iter()
+                        .copied()
+                        .filter(|a| emit.contains(a))
+                        .collect();
+                }
+                "--highlight" if self.flags.contains(&Flag::Highlight) => {
+                    options.highlight = match &value(&name)?[..] {
+                        "ansi" => Some(Style::Ansi),
+                        "html" => Some(Style::Html),
+                        h => return Err(UsageError(format!("unknown style: {}", h))),
+                    }
+                }
+                "--expected" if self.flags.contains(&Flag::Expected) => {
+                    options.expected = Some(value(&name)?)
+                }
+                "--runs" if self.flags.contains(&Flag::Runs) => {
+                    let runs = value(&name)?;
+                    options.runs = match runs.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(UsageError(format!("invalid number of runs: {}", runs))),
+                    }
+                }
+                "--trace" if self.flags.contains(&Flag::Trace) => options.trace = true,
+                "--trace-vars" if self.flags.contains(&Flag::TraceVars) => {
+                    options.trace_vars = true
+                }
+                "--optimize" if self.flags.contains(&Flag::Optimize) => options.optimize = true,
+                "--symbols" if self.flags.contains(&Flag::Symbols) => options.symbols = true,
+                "--stack-size" if self.flags.contains(&Flag::StackSize) => {
+                    options.stack_size = true
+                }
+                "--profile" if self.flags.contains(&Flag::Profile) => options.profile = true,
+                "--trivia" if self.flags.contains(&Flag::Trivia) => options.trivia = true,
+                "--compat" if self.flags.contains(&Flag::Compat) => options.compat = true,
+                "--legacy-tokens" if self.flags.contains(&Flag::LegacyTokens) => {
+                    options.legacy_tokens = true
+                }
+                "--ignore-columns" if self.flags.contains(&Flag::IgnorePositions) => {
+                    options.positions = Positions::Lines
+                }
+                "--ignore-positions" if self.flags.contains(&Flag::IgnorePositions) => {
+                    options.positions = Positions::Ignore
+                }
+                "--watch" if self.flags.contains(&Flag::Watch) => options.watch = true,
+                "--deny-warnings" if self.flags.contains(&Flag::DenyWarnings) => {
+                    options.deny_warnings = true
+                }
+                "-" => positional.push(arg),
+                _ if name.starts_with('-') => {
+                    return Err(UsageError(format!("unknown option: {}", name)));
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        // --dialect の後に指定しても消えないように、幅は最後に設定する
+        if let Some(width) = width {
+            options.dialect.width = width;
+        }
+
+        // 互換性のため、位置引数の INPUT OUTPUT も受け付ける
+        let mut positional = positional.into_iter();
+        if let Some(input) = positional.next() {
+            if options.input.is_some() {
+                return Err(UsageError("input is specified twice".to_string()));
+            }
+            if input != "-" {
+                options.input = Some(input);
+            }
+        }
+        if let Some(output) = positional.next() {
+            if options.output.is_some() {
+                return Err(UsageError("output is specified twice".to_string()));
+            }
+            if output != "-" {
+                options.output = Some(output);
+            }
+        }
+        if let Some(extra) = positional.next() {
+            return Err(UsageError(format!("unexpected argument: {}", extra)));
+        }
+        if use_stdin && options.input.is_some() {
+            return Err(UsageError(
+                "--stdin cannot be used with an input file".to_string(),
+            ));
+        }
+
+        Ok(Parsed::Run(options))
+    }
+
+    /// プロセスの引数を解析する。--help や誤りの場合はここで終了する。
+    pub fn parse_or_exit(&self) -> Options {
+        self.parse_args_or_exit(env::args().skip(1))
+    }
+
+    /// parse_or_exit と同じだが、サブコマンドのように引数の一部を解析する場合に使う。
+    pub fn parse_args_or_exit<I>(&self, args: I) -> Options
+    where
+        I: IntoIterator<Item = String>,
+    {
+        match self.parse(args) {
+            Ok(Parsed::Run(options)) => options,
+            Ok(Parsed::Help) => {
+                print!("{}", self.usage());
+                process::exit(EXIT_SUCCESS);
+            }
+            Err(e) => {
+                eprintln!("{}: {}\n", self.name, e);
+                eprint!("{}", self.usage());
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+
+    /// 引数を解析して run を実行し、結果を終了コードに変換する。
+    pub fn main<F>(&self, run: F)
+    where
+        F: FnOnce(&Options) -> Result<()>,
+    {
+        self.main_with_args(env::args().skip(1), run)
+    }
+
+    /// main と同じだが、引数 args を解析する。
+    pub fn main_with_args<I, F>(&self, args: I, run: F)
+    where
+        I: IntoIterator<Item = String>,
+        F: FnOnce(&Options) -> Result<()>,
+    {
+        let options = self.parse_args_or_exit(args);
+        if let Err(e) = run(&options) {
+            // 出力先が先に閉じられた (head にパイプでつないだ場合など) のはエラーにしない
+            if e.is_output_closed() {
+                process::exit(EXIT_SUCCESS);
+            }
+            match options.diagnostics {
+                DiagnosticFormat::Text => match e.location() {
+                    Some(location) => eprintln!("{}: {}: {}", self.name, location, e),
+                    None => eprintln!("{}: {}", self.name, e),
+                },
+                DiagnosticFormat::Json => eprintln!("{}", e.to_json()),
+            }
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+impl Options {
+    /// 集めた警告を --diagnostics の形式で標準エラー出力に書く。
+    /// --deny-warnings でエラーにした警告があれば Err を返す。
+    pub fn report(&self, name: &str, diagnostics: &Diagnostics) -> Result<()> {
+        for diagnostic in diagnostics.iter() {
+            match self.diagnostics {
+                DiagnosticFormat::Text => eprintln!("{}: {}", name, diagnostic),
+                DiagnosticFormat::Json => eprintln!("{}", diagnostic.to_json()),
+            }
+        }
+        diagnostics.check()
+    }
+
+    /// トークン列の書式
+    pub fn token_format(&self) -> TokenFormat {
+        if self.compat {
+            TokenFormat::Compat
+        } else if self.legacy_tokens {
+            TokenFormat::Legacy
+        } else {
+            TokenFormat::Plain
+        }
+    }
+
+    /// 入力をすべて読み込む
+    pub fn read_input(&self) -> Result<String> {
+        String::from_utf8(self.read_input_bytes()?)
+            .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+    }
+
+    /// 入力をすべてバイト列として読み込む
+    pub fn read_input_bytes(&self) -> Result<Vec<u8>> {
+        let mut reader: Box<dyn Read> = match self.input {
+            None => Box::new(stdin()),
+            Some(ref filename) => Box::new(BufReader::new(File::open(filename).map_err(|e| {
+                CompileError::new(ErrorKind::IOError, format!("{}: {}", filename, e))
+            })?)),
+        };
+
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+        Ok(bytes)
+    }
+
+    /// --expected で指定したファイルを読み込む
+    pub fn read_expected(&self) -> Result<Option<Vec<u8>>> {
+        match self.expected {
+            None => Ok(None),
+            Some(ref filename) => std::fs::read(filename)
+                .map(Some)
+                .map_err(|e| CompileError::new(ErrorKind::IOError, format!("{}: {}", filename, e))),
+        }
+    }
+
+    pub fn create_output(&self) -> Result<Box<dyn Write>> {
+        match self.output {
+            None => Ok(Box::new(BufWriter::new(stdout()))),
+            Some(ref filename) => Ok(Box::new(BufWriter::new(File::create(filename).map_err(
+                |e| CompileError::new(ErrorKind::IOError, format!("{}: {}", filename, e)),
+            )?))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMAND: Command = Command {
+        name: "test",
+        description: "test command",
+        flags: &[Flag::Format, Flag::Dialect, Flag::Highlight, Flag::Runs],
+    };
+
+    fn parse(args: &[&str]) -> result::Result<Parsed, UsageError> {
+        COMMAND.parse(args.iter().map(|s| s.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options {
+        match parse(args) {
+            Ok(Parsed::Run(options)) => options,
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_positional() {
+        let o = options(&[]);
+        assert_eq!(None, o.input);
+        assert_eq!(None, o.output);
+
+        let o = options(&["in.t", "out.lex"]);
+        assert_eq!(Some("in.t".to_string()), o.input);
+        assert_eq!(Some("out.lex".to_string()), o.output);
+
+        let o = options(&["-", "out.lex"]);
+        assert_eq!(None, o.input);
+        assert_eq!(Some("out.lex".to_string()), o.output);
+    }
+
+    #[test]
+    fn test_named() {
+        let o = options(&["--input", "in.t", "-o", "out.lex", "--format=debug"]);
+        assert_eq!(Some("in.t".to_string()), o.input);
+        assert_eq!(Some("out.lex".to_string()), o.output);
+        assert_eq!(Format::Debug, o.format);
+        assert_eq!(Dialect::extended(), o.dialect);
+
+        let o = options(&["--stdin", "--output=out.lex"]);
+        assert_eq!(None, o.input);
+        assert_eq!(Some("out.lex".to_string()), o.output);
+
+        let o = options(&["--dialect", "rosetta"]);
+        assert_eq!(Dialect::rosetta(), o.dialect);
+        assert_eq!(DiagnosticFormat::Text, o.diagnostics);
+
+        let o = options(&["--diagnostics=json"]);
+        assert_eq!(DiagnosticFormat::Json, o.diagnostics);
+        assert_eq!(None, o.highlight);
+
+        let o = options(&["--highlight", "html"]);
+        assert_eq!(Some(Style::Html), o.highlight);
+        assert_eq!(DEFAULT_RUNS, o.runs);
+
+        let o = options(&["--runs=3"]);
+        assert_eq!(3, o.runs);
+        assert!(!o.trivia);
+    }
+
+    #[test]
+    fn test_emit() {
+        let command = Command {
+            name: "emit",
+            description: "emit command",
+            flags: &[Flag::Emit],
+        };
+        let emit = |args: &[&str]| match command.parse(args.iter().map(|s| s.to_string())) {
+            Ok(Parsed::Run(options)) => Ok(options.emit),
+            Ok(Parsed::Help) => panic!("unexpected help"),
+            Err(e) => Err(e.to_string()),
+        };
+        assert_eq!(Ok(Artifact::ALL.to_vec()), emit(&[]));
+        assert_eq!(
+            Ok(vec![Artifact::Tokens, Artifact::Bytecode]),
+            emit(&["--emit", "bytecode, tokens,bytecode"])
+        );
+        assert_eq!(Ok(vec![Artifact::Asm]), emit(&["--emit=asm"]));
+        assert_eq!(
+            Err("unknown artifact: c".to_string()),
+            emit(&["--emit", "asm,c"])
+        );
+        assert_eq!(Err("unknown artifact: ".to_string()), emit(&["--emit="]));
+    }
+
+    #[test]
+    fn test_width() {
+        let command = Command {
+            name: "width",
+            description: "width command",
+            flags: &[Flag::Dialect, Flag::Width],
+        };
+        let width = |args: &[&str]| match command.parse(args.iter().map(|s| s.to_string())) {
+            Ok(Parsed::Run(options)) => Ok(options.dialect.width),
+            Ok(Parsed::Help) => panic!("unexpected help"),
+            Err(e) => Err(e.to_string()),
+        };
+        assert_eq!(Ok(Width::W32), width(&[]));
+        assert_eq!(Ok(Width::W64), width(&["--width", "64"]));
+        // 後から指定した --dialect で幅は戻らない
+        assert_eq!(
+            Ok(Width::W64),
+            width(&["--width=64", "--dialect", "rosetta"])
+        );
+        assert_eq!(
+            Err("unknown width: 16".to_string()),
+            width(&["--width", "16"])
+        );
+        assert!(parse(&["--width", "64"]).is_err());
+    }
+
+    #[test]
+    fn test_compat() {
+        let command = Command {
+            name: "compat",
+            description: "compat command",
+            flags: &[Flag::Compat],
+        };
+        let token_format = |args: &[&str]| match command.parse(args.iter().map(|s| s.to_string())) {
+            Ok(Parsed::Run(options)) => options.token_format(),
+            r => panic!("unexpected result: {:?}", r),
+        };
+        assert_eq!(TokenFormat::Plain, token_format(&[]));
+        assert_eq!(TokenFormat::Compat, token_format(&["--compat"]));
+        assert!(command.usage().contains("--compat"));
+        assert!(!command.usage().contains("--legacy-tokens"));
+        assert!(command.parse(vec!["--legacy-tokens".to_string()]).is_err());
+
+        let command = Command {
+            name: "legacy",
+            description: "legacy command",
+            flags: &[Flag::LegacyTokens],
+        };
+        match command.parse(vec!["--legacy-tokens".to_string()]) {
+            Ok(Parsed::Run(options)) => assert_eq!(TokenFormat::Legacy, options.token_format()),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(command.usage().contains("--legacy-tokens"));
+        assert!(!command.usage().contains("--compat"));
+    }
+
+    #[test]
+    fn test_ignore_positions() {
+        let command = Command {
+            name: "diff",
+            description: "diff command",
+            flags: &[Flag::IgnorePositions, Flag::Expected],
+        };
+        let positions = |args: &[&str]| match command.parse(args.iter().map(|s| s.to_string())) {
+            Ok(Parsed::Run(options)) => options.positions,
+            r => panic!("unexpected result: {:?}", r),
+        };
+        assert_eq!(Positions::Exact, positions(&[]));
+        assert_eq!(Positions::Lines, positions(&["--ignore-columns"]));
+        assert_eq!(Positions::Ignore, positions(&["--ignore-positions"]));
+        assert!(command.usage().contains("--ignore-columns"));
+        assert!(!COMMAND.usage().contains("--ignore-columns"));
+        assert!(parse(&["--ignore-positions"]).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(&["--input"]).is_err());
+        assert!(parse(&["--format", "xml"]).is_err());
+        assert!(parse(&["--dialect=c"]).is_err());
+        assert!(parse(&["--diagnostics", "xml"]).is_err());
+        assert!(parse(&["--highlight=rtf"]).is_err());
+        assert!(parse(&["--runs", "0"]).is_err());
+        assert!(parse(&["--runs", "many"]).is_err());
+        assert!(parse(&["--trace"]).is_err());
+        assert!(parse(&["--trivia"]).is_err());
+        assert!(parse(&["--compat"]).is_err());
+        assert!(parse(&["--legacy-tokens"]).is_err());
+        assert!(parse(&["--trace-vars"]).is_err());
+        assert!(parse(&["--deny-warnings"]).is_err());
+        assert!(parse(&["--emit", "asm"]).is_err());
+        assert!(parse(&["--watch"]).is_err());
+        assert!(parse(&["-i", "a", "b"]).is_err());
+        assert!(parse(&["--stdin", "a"]).is_err());
+        assert!(parse(&["a", "b", "c"]).is_err());
+        assert!(matches!(parse(&["a", "--help"]), Ok(Parsed::Help)));
+    }
+}
@@ -0,0 +1,392 @@
+//! 各段階が見つけた警告を集める。
+//! 警告は行末などに "/* allow(unused-variable) */" と書くと、その行では出さない。
+//! "/* allow */" はその行のすべての警告を抑制する。
+
+use super::dialect::Dialect;
+use super::error::*;
+use super::token::{Token, TokenKind};
+use super::LexicalAnalyzer;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// 診断の重大さ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    /// --deny-warnings でエラーにした警告
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// 警告の種類。抑制するコメントにはこの名前を書く。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// 代入だけして読まない変数
+    UnusedVariable,
+    /// 自分への代入の中でしか読まない変数
+    WriteOnlyVariable,
+    /// 代入より前に読むことがある変数
+    UninitializedVariable,
+    /// 値が常に同じ if や while の条件
+    ConstantCondition,
+    /// exit や終わらないループの後の文
+    UnreachableStatement,
+    /// a < b < c のような比較の連なり
+    ChainedComparison,
+}
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnusedVariable,
+        Lint::WriteOnlyVariable,
+        Lint::UninitializedVariable,
+        Lint::ConstantCondition,
+        Lint::UnreachableStatement,
+        Lint::ChainedComparison,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::WriteOnlyVariable => "write-only-variable",
+            Lint::UninitializedVariable => "uninitialized-variable",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::UnreachableStatement => "unreachable-statement",
+            Lint::ChainedComparison => "chained-comparison",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == name)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// 1 つの警告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub lint: Lint,
+    /// 見つけた段階の名前。 ErrorKind::stage と同じ名前を使う。
+    pub stage: &'static str,
+    pub message: String,
+    /// ソース上の行。分からなければ None。
+    pub line: Option<usize>,
+    /// ソース上の列。文の単位で見つけた警告では None。
+    pub column: Option<usize>,
+    /// line のあるファイルの名前。 include したファイルの警告で使う。
+    pub file: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn warning<S>(
+        lint: Lint,
+        stage: &'static str,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: S,
+    ) -> Self
+    where
+        S: Into<String>,
+    {
+        Diagnostic {
+            severity: Severity::Warning,
+            lint,
+            stage,
+            message: message.into(),
+            line,
+            column,
+            file: None,
+        }
+    }
+
+    /// CompileError::to_json と同じ形の 1 行の JSON。 severity と lint も付ける。
+    pub fn to_json(&self) -> String {
+        let number = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+        let file = match &self.file {
+            Some(file) => format!(r#","file":{}"#, json_string(file)),
+            None => String::new(),
+        };
+        format!(
+            r#"{{"stage":{},"severity":{},"lint":{},"message":{},"line":{},"column":{}{}}}"#,
+            json_string(self.stage),
+            json_string(&self.severity.to_string()),
+            json_string(self.lint.name()),
+            json_string(&self.message),
+            number(self.line),
+            number(self.column),
+            file
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Some(file), Some(_)) = (&self.file, self.line) {
+            write!(f, "{}:", file)?;
+        }
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: ", line, column)?,
+            (Some(line), None) => write!(f, "{}: ", line)?,
+            _ => {}
+        }
+        write!(f, "{}: {} [{}]", self.severity, self.message, self.lint)
+    }
+}
+
+/// 各段階の警告を集める。抑制された警告は捨て、 deny_warnings ならエラーとして記録する。
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    /// 行ごとに抑制する警告。 None はすべての警告。
+    allowed: HashMap<usize, Vec<Option<Lint>>>,
+    deny_warnings: bool,
+}
+
+impl Diagnostics {
+    pub fn new(deny_warnings: bool) -> Self {
+        Diagnostics {
+            deny_warnings,
+            ..Default::default()
+        }
+    }
+
+    /// トークン列のコメントから、警告を抑制する行を読む。
+    /// コメントが複数行にわたる場合は、そのすべての行で抑制する。
+    pub fn allow_comments(&mut self, tokens: &[Token]) {
+        for token in tokens {
+            let text = match token.kind() {
+                TokenKind::Comment(text) => text,
+                _ => continue,
+            };
+            let lints = match parse_allow(text) {
+                Some(lints) => lints,
+                None => continue,
+            };
+            let first = token.line_number();
+            let last = first + text.matches('\n').count();
+            for line in first..=last {
+                self.allowed
+                    .entry(line)
+                    .or_default()
+                    .extend(lints.iter().copied());
+            }
+        }
+    }
+
+    /// src を字句解析して、コメントから警告を抑制する行を読む。
+    /// 字句解析のエラーより後ろのコメントは読まない。
+    pub fn allow_comments_in(&mut self, src: &str, dialect: &Dialect) {
+        let tokens = LexicalAnalyzer::with_dialect(src.chars(), dialect.clone())
+            .keep_trivia()
+            .map_while(|token| token.ok())
+            .collect::<Vec<_>>();
+        self.allow_comments(&tokens);
+    }
+
+    /// 警告を記録する。その行で抑制されていれば捨てる。
+    pub fn push(&mut self, mut diagnostic: Diagnostic) {
+        let allowed = diagnostic
+            .line
+            .and_then(|line| self.allowed.get(&line))
+            .is_some_and(|lints| {
+                lints
+                    .iter()
+                    .any(|lint| lint.is_none_or(|lint| lint == diagnostic.lint))
+            });
+        if allowed {
+            return;
+        }
+        if self.deny_warnings {
+            diagnostic.severity = Severity::Error;
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn warn<S>(
+        &mut self,
+        lint: Lint,
+        stage: &'static str,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: S,
+    ) where
+        S: Into<String>,
+    {
+        self.push(Diagnostic::warning(lint, stage, line, column, message));
+    }
+
+    /// 警告の行を locate で元のファイルの行に直す。
+    /// include を展開したソースの行を、元のファイルの行に戻すために使う。
+    pub fn relocate<F>(&mut self, locate: F)
+    where
+        F: Fn(usize) -> Option<(String, usize)>,
+    {
+        for diagnostic in &mut self.diagnostics {
+            if diagnostic.file.is_some() {
+                continue;
+            }
+            if let Some((file, line)) = diagnostic.line.and_then(&locate) {
+                diagnostic.file = Some(file);
+                diagnostic.line = Some(line);
+            }
+        }
+    }
+
+    /// 警告の列を column で元のソースの列に直す。 column には行と列を渡す。
+    /// #define を展開したソースの列を戻すために、 relocate より先に使う。
+    pub fn relocate_columns<F>(&mut self, column: F)
+    where
+        F: Fn(usize, usize) -> usize,
+    {
+        for diagnostic in &mut self.diagnostics {
+            if diagnostic.file.is_some() {
+                continue;
+            }
+            if let (Some(line), Some(c)) = (diagnostic.line, diagnostic.column) {
+                diagnostic.column = Some(column(line, c));
+            }
+        }
+    }
+
+    /// 記録した順に返す
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// エラーとして記録した警告があれば、その数を伝える Err を返す。
+    pub fn check(&self) -> Result<()> {
+        let errors = self
+            .diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        match errors {
+            0 => Ok(()),
+            1 => Err(CompileError::new(
+                ErrorKind::DeniedWarning,
+                "1 warning treated as an error",
+            )),
+            n => Err(CompileError::new(
+                ErrorKind::DeniedWarning,
+                format!("{} warnings treated as errors", n),
+            )),
+        }
+    }
+}
+
+/// "/* allow(a, b) */" なら Some([Some(a), Some(b)])、 "/* allow */" なら Some([None])。
+/// 抑制の指定でないコメントと、知らない警告の名前は None。
+fn parse_allow(comment: &str) -> Option<Vec<Option<Lint>>> {
+    let body = comment
+        .strip_prefix("/*")?
+        .strip_suffix("*/")
+        .unwrap_or_default()
+        .trim();
+    let rest = body.strip_prefix("allow")?.trim();
+    if rest.is_empty() {
+        return Some(vec![None]);
+    }
+    rest.strip_prefix('(')?
+        .strip_suffix(')')?
+        .split(',')
+        .map(|name| Lint::from_name(name.trim()).map(Some))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_allow() {
+        assert_eq!(Some(vec![None]), parse_allow("/* allow */"));
+        assert_eq!(
+            Some(vec![
+                Some(Lint::UnusedVariable),
+                Some(Lint::ConstantCondition)
+            ]),
+            parse_allow("/*allow(unused-variable, constant-condition)*/")
+        );
+        assert_eq!(None, parse_allow("/* allowed */"));
+        assert_eq!(None, parse_allow("/* allow(unknown) */"));
+        assert_eq!(None, parse_allow("/* x = 1 */"));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let src = "a = 1; /* allow(unused-variable) */\nb = 2; /* allow */\n/* allow(\nconstant-condition) */ c = 3;\n";
+        let mut diagnostics = Diagnostics::new(false);
+        diagnostics.allow_comments_in(src, &Dialect::default());
+        for line in 1..=4 {
+            diagnostics.warn(Lint::UnusedVariable, "checker", Some(line), None, "unused");
+            diagnostics.warn(
+                Lint::ConstantCondition,
+                "checker",
+                Some(line),
+                Some(1),
+                "constant",
+            );
+        }
+        assert_eq!(
+            vec![
+                "1:1: warning: constant [constant-condition]",
+                "3: warning: unused [unused-variable]",
+                "4: warning: unused [unused-variable]",
+            ],
+            diagnostics
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert!(diagnostics.check().is_ok());
+        assert_eq!(
+            r#"{"stage":"checker","severity":"warning","lint":"unused-variable","message":"unused","line":3,"column":null}"#,
+            diagnostics.iter().nth(1).unwrap().to_json()
+        );
+
+        let mut denied = Diagnostics::new(true);
+        denied.warn(Lint::UnreachableStatement, "checker", None, None, "a");
+        denied.warn(Lint::UnreachableStatement, "checker", Some(2), None, "b");
+        assert_eq!(
+            "error: a [unreachable-statement]",
+            denied.iter().next().unwrap().to_string()
+        );
+        denied.warn(Lint::UnreachableStatement, "checker", Some(3), Some(4), "c");
+        denied.relocate_columns(|line, column| line + column);
+        denied.relocate(|line| Some(("lib.t".to_string(), line + 10)));
+        assert_eq!(
+            "lib.t:12: error: b [unreachable-statement]",
+            denied.iter().nth(1).unwrap().to_string()
+        );
+        let e = denied.check().err().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::DeniedWarning));
+        assert_eq!(
+            "lib.t:13:7: error: c [unreachable-statement]",
+            denied.iter().nth(2).unwrap().to_string()
+        );
+        assert_eq!("3 warnings treated as errors", e.message());
+    }
+}
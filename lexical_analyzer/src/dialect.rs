@@ -0,0 +1,365 @@
+use super::token::TokenKind;
+use super::width::Width;
+
+/// キーワード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    If,
+    Else,
+    While,
+    Do,
+    Print,
+    Putc,
+    Rand,
+    Clock,
+    Getc,
+    Geti,
+    Printf,
+    Exit,
+    Assert,
+    Int,
+    Float,
+}
+
+impl Keyword {
+    const ALL: [Keyword; 15] = [
+        Keyword::If,
+        Keyword::Else,
+        Keyword::While,
+        Keyword::Do,
+        Keyword::Print,
+        Keyword::Putc,
+        Keyword::Rand,
+        Keyword::Clock,
+        Keyword::Getc,
+        Keyword::Geti,
+        Keyword::Printf,
+        Keyword::Exit,
+        Keyword::Assert,
+        Keyword::Int,
+        Keyword::Float,
+    ];
+
+    /// 既定の綴り
+    pub fn default_name(self) -> &'static str {
+        match self {
+            Keyword::If => "if",
+            Keyword::Else => "else",
+            Keyword::While => "while",
+            Keyword::Do => "do",
+            Keyword::Print => "print",
+            Keyword::Putc => "putc",
+            Keyword::Rand => "rand",
+            Keyword::Clock => "clock",
+            Keyword::Getc => "getc",
+            Keyword::Geti => "geti",
+            Keyword::Printf => "printf",
+            Keyword::Exit => "exit",
+            Keyword::Assert => "assert",
+            Keyword::Int => "int",
+            Keyword::Float => "float",
+        }
+    }
+
+    /// タスクの文法にないキーワードなら true。タスクの文法では識別子なので、
+    /// 構文解析器は "rand = 3;" のように識別子として書いたものを識別子として読む。
+    pub fn is_soft(self) -> bool {
+        !matches!(
+            self,
+            Keyword::If | Keyword::Else | Keyword::While | Keyword::Print | Keyword::Putc
+        )
+    }
+
+    fn token_kind(self) -> TokenKind {
+        match self {
+            Keyword::If => TokenKind::KeywordIf,
+            Keyword::Else => TokenKind::KeywordElse,
+            Keyword::While => TokenKind::KeywordWhile,
+            Keyword::Do => TokenKind::KeywordDo,
+            Keyword::Print => TokenKind::KeywordPrint,
+            Keyword::Putc => TokenKind::KeywordPutc,
+            Keyword::Rand => TokenKind::KeywordRand,
+            Keyword::Clock => TokenKind::KeywordClock,
+            Keyword::Getc => TokenKind::KeywordGetc,
+            Keyword::Geti => TokenKind::KeywordGeti,
+            Keyword::Printf => TokenKind::KeywordPrintf,
+            Keyword::Exit => TokenKind::KeywordExit,
+            Keyword::Assert => TokenKind::KeywordAssert,
+            Keyword::Int => TokenKind::KeywordInt,
+            Keyword::Float => TokenKind::KeywordFloat,
+        }
+    }
+}
+
+/// 言語の方言。Rosetta Code の "Compiler" タスクの文法に対する拡張の有無と、
+/// キーワードの綴りを決める。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    /// do-while 文
+    pub do_while: bool,
+    /// +=, -=, *=, /=, %=
+    pub compound_assign: bool,
+    /// ++, --。 5--3 を 5 - (-3) と読めなくなるので、 extended にも含めず明示して有効にする。
+    pub increment: bool,
+    /// &, |, ^, <<, >>
+    pub bitwise: bool,
+    /// ** による整数のべき乗。右に結合する。
+    pub power: bool,
+    /// ^ をべき乗の演算子にする。 bitwise の ^ (排他的論理和) は使えなくなる。
+    pub caret_power: bool,
+    /// rand や clock 、入力を読む getc と geti などの組み込み関数
+    pub builtins: bool,
+    /// putc(a, b, c) のように、 putc に複数の式を渡す
+    pub putc_list: bool,
+    /// printf("%d\n", x) のような書式付きの出力
+    pub printf: bool,
+    /// exit(1) のように、終了コードを指定して実行を終える文
+    pub exit: bool,
+    /// assert(x > 0, "message") のように、条件が偽なら実行を止める文
+    pub assert: bool,
+    /// s = "abc"; print(s); のように、文字列を変数に入れる
+    pub string_variables: bool,
+    /// 文字列と文字のリテラルの中の \" 。タスクの文法のエスケープシーケンスは \n と \\ だけ。
+    pub quote_escape: bool,
+    /// ASCII 以外の文字を含む識別子。
+    /// 先頭は alphabetic な文字か '_'、 2 文字目からは alphanumeric な文字か '_' で、
+    /// Unicode の XID_Start と XID_Continue を char の分類で近似する。
+    pub unicode_identifiers: bool,
+    /// 1.5e3 のような浮動小数点数のリテラルと、 int(x), float(x) による変換。
+    /// 整数と混ぜた算術では整数を浮動小数点数にしてから計算する。
+    pub floats: bool,
+    /// 整数の値の幅。 64 ビットなら i32 に収まらないリテラルも書ける。
+    pub width: Width,
+    /// Keyword::ALL と同じ順に並べたキーワードの綴り
+    keywords: Vec<String>,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::extended()
+    }
+}
+
+impl Dialect {
+    /// タスクの文法で書いたプログラムの意味を変えない拡張をすべて有効にした方言。
+    /// ++ と -- は含めない。
+    pub fn extended() -> Self {
+        Dialect {
+            do_while: true,
+            compound_assign: true,
+            increment: false,
+            bitwise: true,
+            power: true,
+            caret_power: false,
+            builtins: true,
+            putc_list: true,
+            printf: true,
+            exit: true,
+            assert: true,
+            string_variables: true,
+            quote_escape: true,
+            unicode_identifiers: false,
+            floats: false,
+            width: Width::W32,
+            keywords: Keyword::ALL
+                .iter()
+                .map(|k| k.default_name().to_string())
+                .collect(),
+        }
+    }
+
+    /// タスクの文法どおりの方言
+    pub fn rosetta() -> Self {
+        Dialect {
+            do_while: false,
+            compound_assign: false,
+            increment: false,
+            bitwise: false,
+            power: false,
+            builtins: false,
+            putc_list: false,
+            printf: false,
+            exit: false,
+            assert: false,
+            string_variables: false,
+            quote_escape: false,
+            ..Dialect::extended()
+        }
+    }
+
+    /// extended に ASCII 以外の識別子を加えた方言
+    pub fn unicode() -> Self {
+        Dialect {
+            unicode_identifiers: true,
+            ..Dialect::extended()
+        }
+    }
+
+    /// extended に ++ と -- を加えた方言
+    pub fn increment() -> Self {
+        Dialect {
+            increment: true,
+            ..Dialect::extended()
+        }
+    }
+
+    /// extended に浮動小数点数を加えた方言
+    pub fn float() -> Self {
+        Dialect {
+            floats: true,
+            ..Dialect::extended()
+        }
+    }
+
+    /// 名前から方言を返す。 "extended", "rosetta", "increment", "unicode", "float" のいずれか。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "extended" => Some(Dialect::extended()),
+            "rosetta" => Some(Dialect::rosetta()),
+            "increment" => Some(Dialect::increment()),
+            "unicode" => Some(Dialect::unicode()),
+            "float" => Some(Dialect::float()),
+            _ => None,
+        }
+    }
+
+    /// keyword の綴りを name に変える。元の綴りは識別子になる。
+    pub fn rename_keyword(&mut self, keyword: Keyword, name: &str) {
+        let index = Keyword::ALL.iter().position(|&k| k == keyword).unwrap();
+        self.keywords[index] = name.to_string();
+    }
+
+    pub fn keyword_name(&self, keyword: Keyword) -> &str {
+        let index = Keyword::ALL.iter().position(|&k| k == keyword).unwrap();
+        &self.keywords[index]
+    }
+
+    /// name がこの方言で有効なキーワードならそのトークンを返す。
+    pub fn keyword(&self, name: &str) -> Option<TokenKind> {
+        Keyword::ALL
+            .iter()
+            .zip(&self.keywords)
+            .find(|&(_, k)| k == name)
+            .map(|(&keyword, _)| keyword.token_kind())
+            .filter(|kind| self.allows(kind))
+    }
+
+    /// kind が識別子としても読めるキーワード (Keyword::is_soft) なら、この方言での綴り
+    pub fn soft_keyword_name(&self, kind: &TokenKind) -> Option<&str> {
+        Keyword::ALL
+            .iter()
+            .zip(&self.keywords)
+            .find(|&(keyword, _)| keyword.is_soft() && keyword.token_kind() == *kind)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// c が識別子の先頭に使える文字なら true
+    pub fn is_identifier_start(&self, c: char) -> bool {
+        c == '_' || c.is_ascii_alphabetic() || (self.unicode_identifiers && c.is_alphabetic())
+    }
+
+    /// c が識別子の 2 文字目から使える文字なら true
+    pub fn is_identifier_continue(&self, c: char) -> bool {
+        c == '_' || c.is_ascii_alphanumeric() || (self.unicode_identifiers && c.is_alphanumeric())
+    }
+
+    /// kind がこの方言で使えるトークンなら true
+    pub fn allows(&self, kind: &TokenKind) -> bool {
+        match kind {
+            TokenKind::KeywordDo => self.do_while,
+            TokenKind::OpAddAssign
+            | TokenKind::OpSubtractAssign
+            | TokenKind::OpMultiplyAssign
+            | TokenKind::OpDivideAssign
+            | TokenKind::OpModAssign => self.compound_assign,
+            TokenKind::OpIncrement | TokenKind::OpDecrement => self.increment,
+            TokenKind::OpBitAnd
+            | TokenKind::OpBitOr
+            | TokenKind::OpShiftLeft
+            | TokenKind::OpShiftRight => self.bitwise,
+            TokenKind::OpBitXor => self.bitwise && !self.caret_power,
+            TokenKind::OpPower => self.power || self.caret_power,
+            TokenKind::KeywordRand
+            | TokenKind::KeywordClock
+            | TokenKind::KeywordGetc
+            | TokenKind::KeywordGeti => self.builtins,
+            TokenKind::KeywordPrintf => self.printf,
+            TokenKind::KeywordExit => self.exit,
+            TokenKind::KeywordAssert => self.assert,
+            TokenKind::KeywordInt | TokenKind::KeywordFloat | TokenKind::Float(_) => self.floats,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword() {
+        let mut dialect = Dialect::extended();
+        assert_eq!(Some(TokenKind::KeywordDo), dialect.keyword("do"));
+        assert_eq!(None, dialect.keyword("count"));
+
+        dialect.rename_keyword(Keyword::Print, "say");
+        assert_eq!("say", dialect.keyword_name(Keyword::Print));
+        assert_eq!(Some(TokenKind::KeywordPrint), dialect.keyword("say"));
+        assert_eq!(None, dialect.keyword("print"));
+
+        let dialect = Dialect::rosetta();
+        assert_eq!(None, dialect.keyword("do"));
+        assert_eq!(Some(TokenKind::KeywordWhile), dialect.keyword("while"));
+        assert!(!dialect.allows(&TokenKind::OpShiftLeft));
+        assert!(dialect.allows(&TokenKind::OpAnd));
+        // 組み込み関数の名前は識別子になる
+        assert_eq!(None, dialect.keyword("rand"));
+        assert_eq!(None, dialect.keyword("clock"));
+        assert_eq!(None, dialect.keyword("geti"));
+        assert_eq!(
+            Some(TokenKind::KeywordGeti),
+            Dialect::extended().keyword("geti")
+        );
+
+        // int と float は浮動小数点数の方言でだけキーワードになる
+        assert_eq!(None, Dialect::extended().keyword("float"));
+        let dialect = Dialect::from_name("float").unwrap();
+        assert_eq!(Some(TokenKind::KeywordFloat), dialect.keyword("float"));
+        assert_eq!(Some(TokenKind::KeywordInt), dialect.keyword("int"));
+    }
+
+    #[test]
+    fn test_soft_keyword() {
+        let mut dialect = Dialect::extended();
+        assert_eq!(
+            Some("rand"),
+            dialect.soft_keyword_name(&TokenKind::KeywordRand)
+        );
+        assert_eq!(Some("do"), dialect.soft_keyword_name(&TokenKind::KeywordDo));
+        assert_eq!(None, dialect.soft_keyword_name(&TokenKind::KeywordWhile));
+        assert_eq!(None, dialect.soft_keyword_name(&TokenKind::OpAdd));
+
+        dialect.rename_keyword(Keyword::Geti, "readint");
+        assert_eq!(
+            Some("readint"),
+            dialect.soft_keyword_name(&TokenKind::KeywordGeti)
+        );
+    }
+
+    #[test]
+    fn test_identifier_chars() {
+        let dialect = Dialect::extended();
+        assert!(dialect.is_identifier_start('_'));
+        assert!(!dialect.is_identifier_start('1'));
+        assert!(dialect.is_identifier_continue('1'));
+        assert!(!dialect.is_identifier_start('é'));
+
+        let dialect = Dialect::from_name("unicode").unwrap();
+        assert!(dialect.is_identifier_start('é'));
+        assert!(dialect.is_identifier_start('変'));
+        assert!(dialect.is_identifier_continue('٣'));
+        assert!(!dialect.is_identifier_start('٣'));
+        assert!(!dialect.is_identifier_start('🦀'));
+        assert!(!dialect.is_identifier_continue('　'));
+    }
+}
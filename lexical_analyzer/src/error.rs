@@ -4,20 +4,53 @@ use std::{error, fmt, result};
 
 pub type Result<T> = result::Result<T, CompileError>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ErrorKind {
+    IOError,
     ReadError,
     LexicalAnalyzerError,
     SyntaxError,
+    /// 型の検査で見つけた誤り
+    TypeError,
     InterpretationError,
     CodeGenerationError,
     VirtualMachineError,
+    /// --deny-warnings でエラーにした警告
+    DeniedWarning,
+    /// 組み立てたパイプラインの誤り
+    PipelineError,
+    /// 処理系の誤り (捕まえた panic など)。入力したプログラムの誤りではない。
+    InternalError,
+}
+
+impl ErrorKind {
+    /// エラーが起きた段階の名前
+    pub fn stage(&self) -> &'static str {
+        match self {
+            ErrorKind::IOError => "io",
+            ErrorKind::ReadError => "input",
+            ErrorKind::LexicalAnalyzerError => "lexer",
+            ErrorKind::SyntaxError => "parser",
+            ErrorKind::TypeError => "checker",
+            ErrorKind::InterpretationError => "interpreter",
+            ErrorKind::CodeGenerationError => "codegen",
+            ErrorKind::VirtualMachineError => "vm",
+            ErrorKind::DeniedWarning => "lint",
+            ErrorKind::PipelineError => "pipeline",
+            ErrorKind::InternalError => "internal",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct CompileError {
     kind: ErrorKind,
     repr: Box<dyn Error + Send + Sync>,
+    /// ソース上の位置 (行, 列)。分からなければ None、列だけ分からなければ (行, None)。
+    /// Result を小さく保つため、行と列を別々の Option にはしない。
+    position: Option<(usize, Option<usize>)>,
+    /// position のあるファイルの名前。 include したファイルのエラーで使う。
+    file: Option<String>,
 }
 
 impl CompileError {
@@ -28,8 +61,125 @@ impl CompileError {
         CompileError {
             kind,
             repr: error.into(),
+            position: None,
+            file: None,
+        }
+    }
+
+    /// 位置がまだ分かっていなければ (line, column) にする。
+    pub fn with_position(mut self, line: usize, column: usize) -> Self {
+        if self.position.is_none() {
+            self.position = Some((line, Some(column)));
+        }
+        self
+    }
+
+    /// with_position と同じだが、列が分からない場合に行だけを付ける。
+    /// 文の行しか持たない AST から生成したコードのエラーなどで使う。
+    pub fn with_line(mut self, line: usize) -> Self {
+        if self.position.is_none() {
+            self.position = Some((line, None));
+        }
+        self
+    }
+
+    /// 行をファイル file の line に置き換える。列はそのまま。
+    /// include を展開したソースの位置を、元のファイルの位置に戻すために使う。
+    pub fn relocate(mut self, file: &str, line: usize) -> Self {
+        self.position = Some((line, self.column()));
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// 列を column に置き換える。行はそのまま。列の分からないエラーには何もしない。
+    /// #define を展開したソースの列を、元のソースの列に戻すために使う。
+    pub fn relocate_column(mut self, column: usize) -> Self {
+        if let Some((line, Some(_))) = self.position {
+            self.position = Some((line, Some(column)));
+        }
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn message(&self) -> String {
+        self.repr.to_string()
+    }
+
+    /// (行, 列)。列が分からなければ None。
+    pub fn position(&self) -> Option<(usize, usize)> {
+        self.line().zip(self.column())
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.position.map(|(line, _)| line)
+    }
+
+    fn column(&self) -> Option<usize> {
+        self.position.and_then(|(_, column)| column)
+    }
+
+    /// 分かっている位置を "file:line:column" の形にする。ファイルや列が分からなければ省く。
+    pub fn location(&self) -> Option<String> {
+        let mut location = self.line()?.to_string();
+        if let Some(file) = &self.file {
+            location = format!("{}:{}", file, location);
+        }
+        if let Some(column) = self.column() {
+            location += &format!(":{}", column);
+        }
+        Some(location)
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// 出力先が閉じられたことによるエラーなら true
+    pub fn is_output_closed(&self) -> bool {
+        self.repr
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(crate::output::is_closed)
+    }
+
+    /// stage, kind, message, line, column を持つ 1 行の JSON。
+    /// ファイルが分かっていれば file も付ける。
+    pub fn to_json(&self) -> String {
+        let number = |n: Option<usize>| n.map_or("null".to_string(), |n| n.to_string());
+        let (line, column) = (number(self.line()), number(self.column()));
+        let file = match &self.file {
+            Some(file) => format!(r#","file":{}"#, json_string(file)),
+            None => String::new(),
+        };
+        format!(
+            r#"{{"stage":{},"kind":{},"message":{},"line":{},"column":{}{}}}"#,
+            json_string(self.kind.stage()),
+            json_string(&format!("{:?}", self.kind)),
+            json_string(&self.message()),
+            line,
+            column,
+            file
+        )
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
         }
     }
+    json.push('"');
+    json
 }
 
 impl fmt::Display for CompileError {
@@ -43,7 +193,57 @@ impl error::Error for CompileError {
         "compiler error"
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn cause(&self) -> Option<&dyn error::Error> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let e = CompileError::new(ErrorKind::SyntaxError, "\"(\" is expected\n");
+        assert_eq!(
+            r#"{"stage":"parser","kind":"SyntaxError","message":"\"(\" is expected\n","line":null,"column":null}"#,
+            e.to_json()
+        );
+
+        // 先に設定した位置を優先する
+        let e = CompileError::new(ErrorKind::LexicalAnalyzerError, "a\u{1}")
+            .with_position(2, 3)
+            .with_position(4, 5);
+        assert_eq!(Some((2, 3)), e.position());
+        assert_eq!(
+            r#"{"stage":"lexer","kind":"LexicalAnalyzerError","message":"a\u0001","line":2,"column":3}"#,
+            e.to_json()
+        );
+
+        assert_eq!(Some("2:3".to_string()), e.location());
+
+        let e = e.relocate("lib/a.t", 1);
+        assert_eq!(Some("lib/a.t"), e.file());
+        assert_eq!(
+            r#"{"stage":"lexer","kind":"LexicalAnalyzerError","message":"a\u0001","line":1,"column":3,"file":"lib/a.t"}"#,
+            e.to_json()
+        );
+        assert_eq!(Some("lib/a.t:1:3".to_string()), e.location());
+        let e = e.relocate_column(7);
+        assert_eq!(Some("lib/a.t:1:7".to_string()), e.location());
+
+        // 行だけ分かっているエラー
+        let e = CompileError::new(ErrorKind::CodeGenerationError, "x")
+            .with_line(4)
+            .with_position(5, 6);
+        assert_eq!(None, e.position());
+        assert_eq!(Some(4), e.line());
+        assert_eq!(Some("4".to_string()), e.location());
+        let e = e.relocate_column(7);
+        assert_eq!(Some("4".to_string()), e.location());
+        assert_eq!(
+            r#"{"stage":"codegen","kind":"CodeGenerationError","message":"x","line":4,"column":null}"#,
+            e.to_json()
+        );
+    }
+}
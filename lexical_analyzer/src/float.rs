@@ -0,0 +1,80 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// 浮動小数点数の値。仮想マシンの 1 つの値に収まるように f32 で持つ。
+/// トークンや AST のノードと一緒に比べられるように、ビット列が同じなら等しいとする。
+/// NaN 同士も等しく、 0.0 と -0.0 は区別する。
+#[derive(Clone, Copy)]
+pub struct Float(pub f32);
+
+impl Float {
+    pub fn value(self) -> f32 {
+        self.0
+    }
+
+    /// 仮想マシンのスタックや変数に置くときのビット列
+    pub fn to_bits(self) -> i32 {
+        self.0.to_bits() as i32
+    }
+
+    pub fn from_bits(bits: i32) -> Self {
+        Float(f32::from_bits(bits as u32))
+    }
+}
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl Hash for Float {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// format_float と同じ形式
+impl fmt::Display for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_float(self.0))
+    }
+}
+
+impl fmt::Debug for Float {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format_float(self.0))
+    }
+}
+
+/// print で出力する形式。インタプリタ、仮想マシン、レジスタマシンで同じ文字列にする。
+/// 読み戻すと同じ値になる最短の桁数で書き、整数の値でも "2.0" のように小数点を付ける。
+/// 大きな数と小さな数は "1e20" のような指数表記になる。
+pub fn format_float(value: f32) -> String {
+    format!("{:?}", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!("1.5", format_float(1.5));
+        assert_eq!("2.0", format_float(2.0));
+        assert_eq!("-0.0", format_float(-0.0));
+        assert_eq!("0.1", format_float(0.1));
+        assert_eq!("1e20", format_float(1e20));
+        assert_eq!("inf", format_float(f32::INFINITY));
+        assert_eq!("NaN", format_float(f32::NAN));
+    }
+
+    #[test]
+    fn test_equality() {
+        assert_eq!(Float(f32::NAN), Float(f32::NAN));
+        assert_ne!(Float(0.0), Float(-0.0));
+        assert_eq!(Float(1.5), Float::from_bits(Float(1.5).to_bits()));
+    }
+}
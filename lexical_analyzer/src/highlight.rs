@@ -0,0 +1,222 @@
+use super::dialect::Dialect;
+use super::relex::{line_starts, offset};
+use super::token::TokenKind;
+use super::LexicalAnalyzer;
+
+/// 出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// 端末用のエスケープシーケンス
+    Ansi,
+    /// クラスを付けた span 要素
+    Html,
+}
+
+/// 色分けの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Keyword,
+    Operator,
+    Punctuation,
+    Identifier,
+    Integer,
+    String,
+    Comment,
+    Error,
+}
+
+impl Class {
+    fn of(kind: &TokenKind) -> Option<Class> {
+        match kind {
+            TokenKind::KeywordIf
+            | TokenKind::KeywordElse
+            | TokenKind::KeywordWhile
+            | TokenKind::KeywordDo
+            | TokenKind::KeywordPrint
+            | TokenKind::KeywordPutc
+            | TokenKind::KeywordRand
+            | TokenKind::KeywordClock
+            | TokenKind::KeywordGetc
+            | TokenKind::KeywordGeti
+            | TokenKind::KeywordPrintf
+            | TokenKind::KeywordExit
+            | TokenKind::KeywordAssert
+            | TokenKind::KeywordInt
+            | TokenKind::KeywordFloat => Some(Class::Keyword),
+            TokenKind::LeftParen
+            | TokenKind::RightParen
+            | TokenKind::LeftBrace
+            | TokenKind::RightBrace
+            | TokenKind::Semicolon
+            | TokenKind::Comma => Some(Class::Punctuation),
+            TokenKind::Identifier(_) => Some(Class::Identifier),
+            TokenKind::Integer(_) | TokenKind::Float(_) => Some(Class::Integer),
+            TokenKind::String(_) => Some(Class::String),
+            TokenKind::Comment(_) => Some(Class::Comment),
+            TokenKind::Whitespace(_) | TokenKind::EndOfInput => None,
+            _ => Some(Class::Operator),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Class::Keyword => "keyword",
+            Class::Operator => "operator",
+            Class::Punctuation => "punctuation",
+            Class::Identifier => "identifier",
+            Class::Integer => "integer",
+            Class::String => "string",
+            Class::Comment => "comment",
+            Class::Error => "error",
+        }
+    }
+
+    fn ansi_color(self) -> Option<&'static str> {
+        match self {
+            Class::Keyword => Some("\x1b[1;34m"),
+            Class::Operator => Some("\x1b[33m"),
+            Class::Integer => Some("\x1b[35m"),
+            Class::String => Some("\x1b[32m"),
+            Class::Comment => Some("\x1b[90m"),
+            Class::Error => Some("\x1b[1;31m"),
+            Class::Punctuation | Class::Identifier => None,
+        }
+    }
+}
+
+struct Writer {
+    style: Style,
+    out: String,
+}
+
+impl Writer {
+    fn plain(&mut self, text: &str) {
+        match self.style {
+            Style::Ansi => self.out.push_str(text),
+            Style::Html => {
+                for c in text.chars() {
+                    match c {
+                        '&' => self.out.push_str("&amp;"),
+                        '<' => self.out.push_str("&lt;"),
+                        '>' => self.out.push_str("&gt;"),
+                        '"' => self.out.push_str("&quot;"),
+                        c => self.out.push(c),
+                    }
+                }
+            }
+        }
+    }
+
+    fn span(&mut self, class: Class, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.style {
+            Style::Ansi => match class.ansi_color() {
+                Some(color) => {
+                    self.out.push_str(color);
+                    self.out.push_str(text);
+                    self.out.push_str("\x1b[0m");
+                }
+                None => self.out.push_str(text),
+            },
+            Style::Html => {
+                self.out
+                    .push_str(&format!("<span class=\"tk-{}\">", class.name()));
+                self.plain(text);
+                self.out.push_str("</span>");
+            }
+        }
+    }
+}
+
+/// ソースを色分けする。字句解析のエラーがあれば、そこから後ろをエラーとして表示する。
+pub fn highlight(src: &str, style: Style) -> String {
+    highlight_with(src, style, &Dialect::default())
+}
+
+pub fn highlight_with(src: &str, style: Style, dialect: &Dialect) -> String {
+    let line_starts = line_starts(src);
+    let mut writer = Writer {
+        style,
+        out: String::new(),
+    };
+    if style == Style::Html {
+        writer.out.push_str("<pre class=\"tiny\">");
+    }
+
+    let mut lexer = LexicalAnalyzer::with_dialect(src.chars(), dialect.clone()).keep_trivia();
+    let mut end = 0;
+    loop {
+        match lexer.next_token() {
+            Ok(token) => {
+                if *token.kind() == TokenKind::EndOfInput {
+                    break;
+                }
+                let start = offset(
+                    src,
+                    &line_starts,
+                    token.line_number(),
+                    token.column_number(),
+                );
+                // 読み終えた位置がトークンの終わり
+                end = src.len() - lexer.remaining();
+                match Class::of(token.kind()) {
+                    Some(class) => writer.span(class, &src[start..end]),
+                    None => writer.plain(&src[start..end]),
+                }
+            }
+            Err(_) => {
+                // 空白は読み終えているので、 end からがエラーのトークン
+                writer.span(Class::Error, &src[end..]);
+                break;
+            }
+        }
+    }
+
+    if style == Style::Html {
+        writer.out.push_str("</pre>\n");
+    }
+    writer.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html() {
+        assert_eq!(
+            concat!(
+                "<pre class=\"tiny\">",
+                "<span class=\"tk-keyword\">if</span> ",
+                "<span class=\"tk-punctuation\">(</span>",
+                "<span class=\"tk-identifier\">a</span> ",
+                "<span class=\"tk-operator\">&lt;=</span> ",
+                "<span class=\"tk-integer\">'x'</span>",
+                "<span class=\"tk-punctuation\">)</span> ",
+                "<span class=\"tk-comment\">/* &amp; */</span>\n",
+                "  <span class=\"tk-keyword\">print</span>",
+                "<span class=\"tk-punctuation\">(</span>",
+                "<span class=\"tk-string\">&quot;é\\n&quot;</span>",
+                "<span class=\"tk-punctuation\">)</span>",
+                "<span class=\"tk-punctuation\">;</span>\n",
+                "</pre>\n"
+            ),
+            highlight("if (a <= 'x') /* & */\n  print(\"é\\n\");\n", Style::Html)
+        );
+    }
+
+    #[test]
+    fn test_ansi() {
+        assert_eq!(
+            "\x1b[1;34mwhile\x1b[0m x \x1b[33m+\x1b[0m \x1b[35m1\x1b[0m \x1b[1;31m# rest\x1b[0m",
+            highlight("while x + 1 # rest", Style::Ansi)
+        );
+        // 閉じていないコメントはエラー
+        assert_eq!(
+            "x \x1b[1;31m/* abc\x1b[0m",
+            highlight("x /* abc", Style::Ansi)
+        );
+    }
+}
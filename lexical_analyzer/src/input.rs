@@ -0,0 +1,128 @@
+use crate::error::*;
+use crate::width::Width;
+use std::io::{BufRead, BufReader, Read};
+
+/// getc と geti の読み込み元。仮想マシンとレジスタマシンで同じ読み方をする。
+/// geti が数字の直後の 1 文字を読み残せるように、バッファを通して読む。
+/// エラーは呼び出し側が渡した kind のエラーにする。
+#[derive(Default)]
+pub struct Input {
+    /// None なら常に EOF
+    reader: Option<BufReader<Box<dyn Read>>>,
+}
+
+fn input_error(kind: ErrorKind, message: String) -> CompileError {
+    CompileError::new(kind, format!("input error: {}", message))
+}
+
+impl Input {
+    pub fn new(reader: Box<dyn Read>) -> Self {
+        Input {
+            reader: Some(BufReader::new(reader)),
+        }
+    }
+
+    /// 次の 1 バイトを読まずに返す。EOF なら None。
+    fn peek(&mut self, kind: ErrorKind) -> Result<Option<u8>> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        match reader.fill_buf() {
+            Ok(buf) => Ok(buf.first().copied()),
+            Err(e) => Err(input_error(kind, e.to_string())),
+        }
+    }
+
+    fn consume(&mut self) {
+        if let Some(reader) = self.reader.as_mut() {
+            reader.consume(1);
+        }
+    }
+
+    /// 1 バイト読む。EOF なら -1。
+    pub fn read_char(&mut self, kind: ErrorKind) -> Result<i32> {
+        let c = self.peek(kind)?;
+        self.consume();
+        Ok(c.map_or(-1, i32::from))
+    }
+
+    /// 空白を読み飛ばして 10 進の整数を読む。数字の直後の文字は読み残す。
+    /// 整数がない場合と i32 に収まらない場合はエラーにする。
+    pub fn read_integer(&mut self, kind: ErrorKind) -> Result<i32> {
+        self.read_integer_in(kind, Width::W32)
+            .map(|value| value as i32)
+    }
+
+    /// read_integer と同じだが、値の幅 width に収まらない場合をエラーにする
+    pub fn read_integer_in(&mut self, kind: ErrorKind, width: Width) -> Result<i64> {
+        while self.peek(kind)?.is_some_and(|c| c.is_ascii_whitespace()) {
+            self.consume();
+        }
+        let mut text = String::new();
+        if let Some(c @ (b'-' | b'+')) = self.peek(kind)? {
+            text.push(c as char);
+            self.consume();
+        }
+        while let Some(c) = self.peek(kind)?.filter(u8::is_ascii_digit) {
+            text.push(c as char);
+            self.consume();
+        }
+        if !text.ends_with(|c: char| c.is_ascii_digit()) {
+            let found = match self.peek(kind)? {
+                Some(c) => format!("{:?}", c as char),
+                None => "end of input".to_string(),
+            };
+            return Err(input_error(
+                kind,
+                format!("expected an integer, found {}", found),
+            ));
+        }
+        text.parse()
+            .ok()
+            .filter(|&value| width.contains(value))
+            .ok_or_else(|| input_error(kind, format!("integer {} is out of range", text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input() {
+        let kind = ErrorKind::VirtualMachineError;
+        let mut input = Input::new(Box::new(&b"  12\n-3+4x"[..]));
+        assert_eq!(12, input.read_integer(kind).unwrap());
+        assert_eq!('\n' as i32, input.read_char(kind).unwrap());
+        assert_eq!(-3, input.read_integer(kind).unwrap());
+        assert_eq!(4, input.read_integer(kind).unwrap());
+        assert_eq!(
+            "input error: expected an integer, found 'x'",
+            input.read_integer(kind).unwrap_err().message()
+        );
+        assert_eq!('x' as i32, input.read_char(kind).unwrap());
+        assert_eq!(-1, input.read_char(kind).unwrap());
+        assert_eq!(
+            "input error: expected an integer, found end of input",
+            input.read_integer(kind).unwrap_err().message()
+        );
+
+        let mut input = Input::new(Box::new(&b"2147483648"[..]));
+        let e = input
+            .read_integer(ErrorKind::InterpretationError)
+            .unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::InterpretationError));
+        assert_eq!(
+            "input error: integer 2147483648 is out of range",
+            e.message()
+        );
+
+        let mut input = Input::new(Box::new(&b"2147483648"[..]));
+        assert_eq!(2147483648, input.read_integer_in(kind, Width::W64).unwrap());
+
+        let mut input = Input::default();
+        assert_eq!(-1, input.read_char(kind).unwrap());
+        assert!(input.read_integer(kind).is_err());
+    }
+}
@@ -1,7 +1,26 @@
+pub mod cli;
+pub mod diagnostic;
+pub mod dialect;
 pub mod error;
+pub mod float;
+pub mod highlight;
+pub mod input;
+pub mod limits;
+pub mod output;
+mod relex;
+pub mod report;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod snapshot;
+pub mod symbol;
 pub mod token;
+pub mod token_diff;
+pub mod trivia;
+pub mod width;
 
+use dialect::Dialect;
 use error::*;
+use float::Float;
+use symbol::Symbol;
 use token::{Token, TokenKind};
 
 use std::str::Chars;
@@ -15,34 +34,57 @@ pub struct LexicalAnalyzer<'a> {
     line_number: usize,
     /// 現在の列数
     column_number: usize,
+    /// 読んでいるトークンの始まりの (行数, 列数)。エラーの位置に使う。
+    token_start: (usize, usize),
+    /// 有効な拡張とキーワード
+    dialect: Dialect,
+    /// 空白とコメントをトークンとして返すなら true
+    trivia: bool,
+    /// EndOfInput かエラーを返したら true。 Iterator として読むときに使う。
+    finished: bool,
 }
 
+/// 閉じていないコメントのエラーのメッセージ。
+/// エラーの位置はコメントの始まりなので、続きの入力を待つかどうかはこれで見分ける。
+pub const UNTERMINATED_COMMENT: &str =
+    "End-of-file in comment. Closing comment characters not found";
+
 /// c が '0' - '9' なら true
 fn is_number(c: char) -> bool {
-    c.is_digit(10)
-}
-
-/// c が [_,a-z,A-Z] なら true
-fn is_alpha(c: char) -> bool {
-    c == '_' || (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z')
-}
-
-/// c が [_,a-z,A-Z,0-9] なら true
-fn is_alnum(c: char) -> bool {
-    is_alpha(c) || is_number(c)
+    c.is_ascii_digit()
 }
 
 impl<'a> LexicalAnalyzer<'a> {
-    pub fn new(mut stream: Chars<'a>) -> Self {
+    pub fn new(stream: Chars<'a>) -> Self {
+        Self::with_dialect(stream, Dialect::default())
+    }
+
+    pub fn with_dialect(mut stream: Chars<'a>, dialect: Dialect) -> Self {
         let next_char = stream.next();
         LexicalAnalyzer {
             next_char,
             stream,
             line_number: 1,
             column_number: 1,
+            token_start: (1, 1),
+            dialect,
+            trivia: false,
+            finished: false,
         }
     }
 
+    /// 空白とコメントを読み飛ばさず、 Whitespace と Comment のトークンとして返すようにする。
+    /// フォーマッタや色分けのように、ソースをそのまま再現したい場合に使う。
+    pub fn keep_trivia(mut self) -> Self {
+        self.trivia = true;
+        self
+    }
+
+    /// まだ読んでいない部分 (先読みした一文字を含む) のバイト数
+    fn remaining(&self) -> usize {
+        self.stream.as_str().len() + self.next_char.map_or(0, char::len_utf8)
+    }
+
     /// stream を一文字進め、next_char に格納する。
     fn read_char(&mut self) {
         if self.next_char == Some('\n') {
@@ -57,12 +99,11 @@ impl<'a> LexicalAnalyzer<'a> {
         assert!(self.next_char == Some('\\'));
         self.read_char();
         match self.next_char {
-            Some(c) if c == '\\' => Ok('\\'),
-            Some(c) if c == 'n' => Ok('\n'),
-            Some(_) => Err(CompileError::new(
-                ErrorKind::LexicalAnalyzerError,
-                "Unknown escape sequence",
-            )),
+            Some(c) => escape::unescape_char(c)
+                .filter(|&c| c != '"' || self.dialect.quote_escape)
+                .ok_or_else(|| {
+                    CompileError::new(ErrorKind::LexicalAnalyzerError, "Unknown escape sequence")
+                }),
             None => Err(CompileError::new(
                 ErrorKind::LexicalAnalyzerError,
                 "unexpected EOI",
@@ -72,14 +113,9 @@ impl<'a> LexicalAnalyzer<'a> {
 
     /// 空白文字を読みとばす。
     fn discard_whitespace(&mut self) {
-        loop {
-            match self.next_char {
-                Some(c) => {
-                    if !c.is_whitespace() {
-                        break;
-                    }
-                }
-                None => break,
+        while let Some(c) = self.next_char {
+            if !c.is_whitespace() {
+                break;
             }
             self.read_char();
         }
@@ -102,7 +138,7 @@ impl<'a> LexicalAnalyzer<'a> {
                             // コメントの途中で EOF
                             return Err(CompileError::new(
                                 ErrorKind::LexicalAnalyzerError,
-                                "End-of-file in comment. Closing comment characters not found",
+                                UNTERMINATED_COMMENT,
                             ));
                         }
                     }
@@ -112,7 +148,7 @@ impl<'a> LexicalAnalyzer<'a> {
                     // コメントの途中で EOF
                     return Err(CompileError::new(
                         ErrorKind::LexicalAnalyzerError,
-                        "End-of-file in comment. Closing comment characters not found",
+                        UNTERMINATED_COMMENT,
                     ));
                 }
             }
@@ -120,7 +156,7 @@ impl<'a> LexicalAnalyzer<'a> {
         }
     }
 
-    /// 一文字目が '<' のトークン
+    /// 一文字目が '<' のトークン。'<<' は左シフト
     fn read_less(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
         self.read_char();
 
@@ -131,12 +167,19 @@ impl<'a> LexicalAnalyzer<'a> {
                 line_number,
                 column_number,
             ))
+        } else if self.dialect.bitwise && self.next_char == Some('<') {
+            self.read_char();
+            Ok(Token::new(
+                TokenKind::OpShiftLeft,
+                line_number,
+                column_number,
+            ))
         } else {
             Ok(Token::new(TokenKind::OpLess, line_number, column_number))
         }
     }
 
-    /// 一文字目が '>' のトークン
+    /// 一文字目が '>' のトークン。'>>' は右シフト
     fn read_greater(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
         self.read_char();
 
@@ -147,6 +190,13 @@ impl<'a> LexicalAnalyzer<'a> {
                 line_number,
                 column_number,
             ))
+        } else if self.dialect.bitwise && self.next_char == Some('>') {
+            self.read_char();
+            Ok(Token::new(
+                TokenKind::OpShiftRight,
+                line_number,
+                column_number,
+            ))
         } else {
             Ok(Token::new(TokenKind::OpGreater, line_number, column_number))
         }
@@ -180,13 +230,15 @@ impl<'a> LexicalAnalyzer<'a> {
         }
     }
 
-    /// 一文字目が '&' のトークン
+    /// 一文字目が '&' のトークン。'&&' でなければビット積
     fn read_and(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
         self.read_char();
 
         if self.next_char == Some('&') {
             self.read_char();
             Ok(Token::new(TokenKind::OpAnd, line_number, column_number))
+        } else if self.dialect.bitwise {
+            Ok(Token::new(TokenKind::OpBitAnd, line_number, column_number))
         } else {
             Err(CompileError::new(
                 ErrorKind::LexicalAnalyzerError,
@@ -195,13 +247,15 @@ impl<'a> LexicalAnalyzer<'a> {
         }
     }
 
-    /// 一文字目が '|' のトークン
+    /// 一文字目が '|' のトークン。'||' でなければビット和
     fn read_or(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
         self.read_char();
 
         if self.next_char == Some('|') {
             self.read_char();
             Ok(Token::new(TokenKind::OpOr, line_number, column_number))
+        } else if self.dialect.bitwise {
+            Ok(Token::new(TokenKind::OpBitOr, line_number, column_number))
         } else {
             Err(CompileError::new(
                 ErrorKind::LexicalAnalyzerError,
@@ -210,18 +264,94 @@ impl<'a> LexicalAnalyzer<'a> {
         }
     }
 
-    /// '/' の次の文字が '*' 以外: OpDivide
-    /// '/' の次の文字が '*'     : コメントを読み飛ばし、次のトークンを返す
-    fn read_div(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
+    /// 一文字目が '+' か '-' のトークン。同じ文字が続けばインクリメント・デクリメント
+    fn read_additive(
+        &mut self,
+        operator: TokenKind,
+        assign: TokenKind,
+        step: TokenKind,
+        line_number: usize,
+        column_number: usize,
+    ) -> Result<Token> {
+        let c = self.next_char;
+        self.read_char();
+
+        if self.dialect.increment && self.next_char == c {
+            self.read_char();
+            Ok(Token::new(step, line_number, column_number))
+        } else if self.dialect.compound_assign && self.next_char == Some('=') {
+            self.read_char();
+            Ok(Token::new(assign, line_number, column_number))
+        } else {
+            Ok(Token::new(operator, line_number, column_number))
+        }
+    }
+
+    /// 一文字目が '*' のトークン。'**' はべき乗
+    fn read_star(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
+        self.read_char();
+
+        if self.dialect.power && self.next_char == Some('*') {
+            self.read_char();
+            Ok(Token::new(TokenKind::OpPower, line_number, column_number))
+        } else if self.dialect.compound_assign && self.next_char == Some('=') {
+            self.read_char();
+            Ok(Token::new(
+                TokenKind::OpMultiplyAssign,
+                line_number,
+                column_number,
+            ))
+        } else {
+            Ok(Token::new(
+                TokenKind::OpMultiply,
+                line_number,
+                column_number,
+            ))
+        }
+    }
+
+    /// 一文字目が演算子で、'=' が続けば複合代入になるトークン
+    fn read_operator(
+        &mut self,
+        operator: TokenKind,
+        assign: TokenKind,
+        line_number: usize,
+        column_number: usize,
+    ) -> Result<Token> {
+        self.read_char();
+
+        if self.dialect.compound_assign && self.next_char == Some('=') {
+            self.read_char();
+            Ok(Token::new(assign, line_number, column_number))
+        } else {
+            Ok(Token::new(operator, line_number, column_number))
+        }
+    }
+
+    /// '/' の次の文字が '*'     : コメントを読み飛ばし、None を返す
+    /// '/' の次の文字が '='     : OpDivideAssign
+    /// '/' の次の文字がそれ以外 : OpDivide
+    fn read_div(&mut self, line_number: usize, column_number: usize) -> Result<Option<Token>> {
         self.read_char();
 
         if self.next_char == Some('*') {
             // comment
             self.read_char();
             self.discard_comment()?;
-            self.next_token()
+            Ok(None)
+        } else if self.dialect.compound_assign && self.next_char == Some('=') {
+            self.read_char();
+            Ok(Some(Token::new(
+                TokenKind::OpDivideAssign,
+                line_number,
+                column_number,
+            )))
         } else {
-            Ok(Token::new(TokenKind::OpDivide, line_number, column_number))
+            Ok(Some(Token::new(
+                TokenKind::OpDivide,
+                line_number,
+                column_number,
+            )))
         }
     }
 
@@ -232,69 +362,92 @@ impl<'a> LexicalAnalyzer<'a> {
         identifier.push(self.next_char.unwrap());
         self.read_char();
 
-        loop {
-            if let Some(c) = self.next_char {
-                if is_alnum(c) {
-                    identifier.push(c);
-                    self.read_char();
-                } else {
-                    break;
-                }
-            } else {
-                // End of Input
+        while let Some(c) = self.next_char {
+            if !self.dialect.is_identifier_continue(c) {
                 break;
             }
+            identifier.push(c);
+            self.read_char();
         }
 
-        Ok(Token::new(
-            match &identifier[..] {
-                "if" => TokenKind::KeywordIf,
-                "else" => TokenKind::KeywordElse,
-                "while" => TokenKind::KeywordWhile,
-                "print" => TokenKind::KeywordPrint,
-                "putc" => TokenKind::KeywordPutc,
-                _ => TokenKind::Identifier(identifier),
-            },
-            line_number,
-            column_number,
-        ))
+        let kind = self
+            .dialect
+            .keyword(&identifier)
+            .unwrap_or_else(|| TokenKind::Identifier(Symbol::intern(&identifier)));
+        Ok(Token::new(kind, line_number, column_number))
     }
 
-    /// 数値リテラルを読み込む
+    /// 数値リテラルを読み込む。浮動小数点数の方言では 1.5, 1.5e3, 2e-3 のような小数も読む。
     fn read_integer_literal(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
         let mut number_string = String::new();
-        number_string.push(self.next_char.unwrap());
-        self.read_char();
+        self.read_digits(&mut number_string)?;
 
-        loop {
-            match self.next_char {
-                Some(c) if is_number(c) => {
+        let mut is_float = false;
+        if self.dialect.floats {
+            if self.next_char == Some('.') {
+                is_float = true;
+                number_string.push('.');
+                self.read_char();
+                self.read_digits(&mut number_string)?;
+            }
+            if let Some(c @ ('e' | 'E')) = self.next_char {
+                is_float = true;
+                number_string.push(c);
+                self.read_char();
+                if let Some(c @ ('+' | '-')) = self.next_char {
                     number_string.push(c);
                     self.read_char();
                 }
-                Some(c) if c.is_alphabetic() => {
+                self.read_digits(&mut number_string)?;
+            }
+        }
+        if self.next_char.is_some_and(char::is_alphabetic) {
+            return Err(CompileError::new(
+                ErrorKind::LexicalAnalyzerError,
+                "Invalid number. Starts like a number, but ends in non-numeric-characters",
+            ));
+        }
+
+        let kind = if is_float {
+            // f32 に収まらない数は inf になるので、エラーにする
+            match number_string.parse::<f32>() {
+                Ok(value) if value.is_finite() => TokenKind::Float(Float(value)),
+                _ => {
                     return Err(CompileError::new(
                         ErrorKind::LexicalAnalyzerError,
-                        "Invalid number. Starts like a number, but ends in non-numeric-characters",
-                    ));
+                        "float literal is out of range.",
+                    ))
                 }
+            }
+        } else {
+            // 値の幅に収まらない数はエラーにする
+            match number_string.parse() {
+                Ok(num) if self.dialect.width.contains(num) => TokenKind::Integer(num),
                 _ => {
-                    break;
+                    return Err(CompileError::new(
+                        ErrorKind::LexicalAnalyzerError,
+                        "invalid number.",
+                    ))
                 }
             }
-        }
+        };
+        Ok(Token::new(kind, line_number, column_number))
+    }
 
-        match number_string.parse() {
-            Ok(num) => Ok(Token::new(
-                TokenKind::Integer(num),
-                line_number,
-                column_number,
-            )),
-            Err(_) => Err(CompileError::new(
+    /// 1 文字以上の数字を読んで s に足す
+    fn read_digits(&mut self, s: &mut String) -> Result<()> {
+        let len = s.len();
+        while let Some(c) = self.next_char.filter(|&c| is_number(c)) {
+            s.push(c);
+            self.read_char();
+        }
+        if s.len() == len {
+            return Err(CompileError::new(
                 ErrorKind::LexicalAnalyzerError,
-                "invalid number.",
-            )),
+                "Invalid number. Digits are expected",
+            ));
         }
+        Ok(())
     }
 
     fn read_char_literal(&mut self, line_number: usize, column_number: usize) -> Result<Token> {
@@ -314,10 +467,10 @@ impl<'a> LexicalAnalyzer<'a> {
                     self.read_escaped_sequence()?
                 } else {
                     c
-                } as i32;
+                } as i64;
                 self.read_char();
                 match self.next_char {
-                    Some(c) if c == '\'' => {
+                    Some('\'') => {
                         self.read_char();
                         Ok(Token::new(
                             TokenKind::Integer(n),
@@ -351,7 +504,11 @@ impl<'a> LexicalAnalyzer<'a> {
             match self.next_char {
                 Some('"') => {
                     self.read_char();
-                    return Ok(Token::new(TokenKind::String(s), line_number, column_number));
+                    return Ok(Token::new(
+                        TokenKind::String(Symbol::intern(&s)),
+                        line_number,
+                        column_number,
+                    ));
                 }
                 Some('\n') => {
                     return Err(CompileError::new(
@@ -377,28 +534,88 @@ impl<'a> LexicalAnalyzer<'a> {
         }
     }
 
+    /// 次のトークンを読む。エラーには、読んでいたトークンの始まりの位置を付ける。
     pub fn next_token(&mut self) -> Result<Token> {
-        self.discard_whitespace();
+        self.read_token().map_err(|e| {
+            let (line, column) = self.token_start;
+            e.with_position(line, column)
+        })
+    }
 
-        let start_line = self.line_number;
-        let start_column = self.column_number;
-        match self.next_char {
-            Some('*') => {
-                self.read_char();
-                Ok(Token::new(TokenKind::OpMultiply, start_line, start_column))
-            }
-            Some('%') => {
-                self.read_char();
-                Ok(Token::new(TokenKind::OpMod, start_line, start_column))
-            }
-            Some('+') => {
-                self.read_char();
-                Ok(Token::new(TokenKind::OpAdd, start_line, start_column))
+    /// 現在の位置をトークンの始まりとして覚える
+    fn start_token(&mut self) -> (usize, usize) {
+        self.token_start = (self.line_number, self.column_number);
+        self.token_start
+    }
+
+    /// 空白かコメントを 1 つ読み、そのトークンを返す。どちらでもなければ None。
+    fn read_trivia(&mut self) -> Result<Option<Token>> {
+        let (line_number, column_number) = self.start_token();
+        let first = match self.next_char {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let rest = self.stream.as_str();
+        let start = self.remaining();
+        let kind: fn(String) -> TokenKind = if first.is_whitespace() {
+            self.discard_whitespace();
+            TokenKind::Whitespace
+        } else if first == '/' && rest.starts_with('*') {
+            self.read_char();
+            self.read_char();
+            self.discard_comment()?;
+            TokenKind::Comment
+        } else {
+            return Ok(None);
+        };
+        let text = format!(
+            "{}{}",
+            first,
+            &rest[..start - self.remaining() - first.len_utf8()]
+        );
+        Ok(Some(Token::new(kind(text), line_number, column_number)))
+    }
+
+    fn read_token(&mut self) -> Result<Token> {
+        if self.trivia {
+            if let Some(token) = self.read_trivia()? {
+                return Ok(token);
             }
-            Some('-') => {
-                self.read_char();
-                Ok(Token::new(TokenKind::OpSubtract, start_line, start_column))
+        }
+        self.discard_whitespace();
+
+        // コメントが続いても再帰しないよう、ここで読み飛ばす
+        while self.next_char == Some('/') {
+            let (start_line, start_column) = self.start_token();
+            match self.read_div(start_line, start_column)? {
+                Some(token) => return Ok(token),
+                None => self.discard_whitespace(),
             }
+        }
+
+        let (start_line, start_column) = self.start_token();
+        match self.next_char {
+            Some('*') => self.read_star(start_line, start_column),
+            Some('%') => self.read_operator(
+                TokenKind::OpMod,
+                TokenKind::OpModAssign,
+                start_line,
+                start_column,
+            ),
+            Some('+') => self.read_additive(
+                TokenKind::OpAdd,
+                TokenKind::OpAddAssign,
+                TokenKind::OpIncrement,
+                start_line,
+                start_column,
+            ),
+            Some('-') => self.read_additive(
+                TokenKind::OpSubtract,
+                TokenKind::OpSubtractAssign,
+                TokenKind::OpDecrement,
+                start_line,
+                start_column,
+            ),
             Some('(') => {
                 self.read_char();
                 Ok(Token::new(TokenKind::LeftParen, start_line, start_column))
@@ -429,11 +646,17 @@ impl<'a> LexicalAnalyzer<'a> {
             Some('!') => self.read_not(start_line, start_column),
             Some('&') => self.read_and(start_line, start_column),
             Some('|') => self.read_or(start_line, start_column),
-
-            Some('/') => self.read_div(start_line, start_column),
+            Some('^') if self.dialect.caret_power => {
+                self.read_char();
+                Ok(Token::new(TokenKind::OpPower, start_line, start_column))
+            }
+            Some('^') if self.dialect.bitwise => {
+                self.read_char();
+                Ok(Token::new(TokenKind::OpBitXor, start_line, start_column))
+            }
 
             Some(c) => {
-                if is_alpha(c) {
+                if self.dialect.is_identifier_start(c) {
                     self.read_identifier(start_line, start_column)
                 } else if is_number(c) {
                     self.read_integer_literal(start_line, start_column)
@@ -454,9 +677,46 @@ impl<'a> LexicalAnalyzer<'a> {
     }
 }
 
+/// ソース全体を字句解析し、EndOfInput までのトークン列を返す。
+/// どんな入力に対しても panic せず、エラーは Err で返す (ファジング用の入口)。
+pub fn lex_all(src: &str) -> Result<Vec<Token>> {
+    lex_all_with(src, &Dialect::default())
+}
+
+pub fn lex_all_with(src: &str, dialect: &Dialect) -> Result<Vec<Token>> {
+    lex_tokens(LexicalAnalyzer::with_dialect(src.chars(), dialect.clone()))
+}
+
+/// lex_all_with と同じだが、空白とコメントもトークンとして返す。
+pub fn lex_all_with_trivia(src: &str, dialect: &Dialect) -> Result<Vec<Token>> {
+    lex_tokens(LexicalAnalyzer::with_dialect(src.chars(), dialect.clone()).keep_trivia())
+}
+
+fn lex_tokens(lexer: LexicalAnalyzer) -> Result<Vec<Token>> {
+    lexer.collect()
+}
+
+/// EndOfInput までのトークンを順に返す。エラーを返した後は何も返さない。
+impl Iterator for LexicalAnalyzer<'_> {
+    type Item = Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.next_token();
+        self.finished = match &result {
+            Ok(token) => *token.kind() == TokenKind::EndOfInput,
+            Err(_) => true,
+        };
+        Some(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use width::Width;
 
     #[test]
     fn test_empty_file() {
@@ -476,6 +736,31 @@ mod tests {
         assert_eq!(TokenKind::EndOfInput, *token.kind());
     }
 
+    #[test]
+    fn test_iterator() {
+        let tokens = LexicalAnalyzer::new("x = 1;".chars())
+            .collect::<Result<Vec<Token>>>()
+            .unwrap();
+        let kinds = tokens.iter().map(Token::kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                &TokenKind::Identifier(Symbol::intern("x")),
+                &TokenKind::OpAssign,
+                &TokenKind::Integer(1),
+                &TokenKind::Semicolon,
+                &TokenKind::EndOfInput
+            ],
+            kinds
+        );
+
+        // エラーの後は止まる
+        let mut lexer = LexicalAnalyzer::new("x = 'ab';".chars());
+        assert!(lexer.next().unwrap().is_ok());
+        assert!(lexer.next().unwrap().is_ok());
+        assert!(lexer.next().unwrap().is_err());
+        assert!(lexer.next().is_none());
+    }
+
     #[test]
     fn test_whitespace() {
         let s = "\n\t ".to_string();
@@ -549,7 +834,7 @@ mod tests {
     #[test]
     /// 2 文字目で確定できるトークン
     fn test_two_chars() {
-        let s = "<<=>>====!!=&&||".to_string();
+        let s = "< <=> >====!!=&&||".to_string();
 
         let mut lexer = LexicalAnalyzer::new(s.chars());
 
@@ -560,55 +845,215 @@ mod tests {
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(2, token.column_number());
+        assert_eq!(3, token.column_number());
         assert_eq!(TokenKind::OpLessEqual, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(4, token.column_number());
+        assert_eq!(5, token.column_number());
         assert_eq!(TokenKind::OpGreater, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(5, token.column_number());
+        assert_eq!(7, token.column_number());
         assert_eq!(TokenKind::OpGreaterEqual, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(7, token.column_number());
+        assert_eq!(9, token.column_number());
         assert_eq!(TokenKind::OpEqual, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(9, token.column_number());
+        assert_eq!(11, token.column_number());
         assert_eq!(TokenKind::OpAssign, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(10, token.column_number());
+        assert_eq!(12, token.column_number());
         assert_eq!(TokenKind::OpNot, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(11, token.column_number());
+        assert_eq!(13, token.column_number());
         assert_eq!(TokenKind::OpNotEqual, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(13, token.column_number());
+        assert_eq!(15, token.column_number());
         assert_eq!(TokenKind::OpAnd, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(15, token.column_number());
+        assert_eq!(17, token.column_number());
         assert_eq!(TokenKind::OpOr, *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
-        assert_eq!(17, token.column_number());
+        assert_eq!(19, token.column_number());
         assert_eq!(TokenKind::EndOfInput, *token.kind());
     }
 
+    #[test]
+    fn test_compound_assign() {
+        let s = "+= -= *= /= %= + =".to_string();
+
+        let mut lexer = LexicalAnalyzer::new(s.chars());
+        for &(column, ref kind) in &[
+            (1, TokenKind::OpAddAssign),
+            (4, TokenKind::OpSubtractAssign),
+            (7, TokenKind::OpMultiplyAssign),
+            (10, TokenKind::OpDivideAssign),
+            (13, TokenKind::OpModAssign),
+            (16, TokenKind::OpAdd),
+            (18, TokenKind::OpAssign),
+        ] {
+            let token = lexer.next_token().unwrap();
+            assert_eq!(1, token.line_number());
+            assert_eq!(column, token.column_number());
+            assert_eq!(*kind, *token.kind());
+        }
+    }
+
+    #[test]
+    fn test_increment() {
+        let s = "++ -- +-+ ---".to_string();
+
+        let mut lexer = LexicalAnalyzer::with_dialect(s.chars(), Dialect::increment());
+        for &(column, ref kind) in &[
+            (1, TokenKind::OpIncrement),
+            (4, TokenKind::OpDecrement),
+            (7, TokenKind::OpAdd),
+            (8, TokenKind::OpSubtract),
+            (9, TokenKind::OpAdd),
+            (11, TokenKind::OpDecrement),
+            (13, TokenKind::OpSubtract),
+        ] {
+            let token = lexer.next_token().unwrap();
+            assert_eq!(1, token.line_number());
+            assert_eq!(column, token.column_number());
+            assert_eq!(*kind, *token.kind());
+        }
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let s = "& | ^ << >> && || <<= >>=".to_string();
+
+        let mut lexer = LexicalAnalyzer::new(s.chars());
+        for &(column, ref kind) in &[
+            (1, TokenKind::OpBitAnd),
+            (3, TokenKind::OpBitOr),
+            (5, TokenKind::OpBitXor),
+            (7, TokenKind::OpShiftLeft),
+            (10, TokenKind::OpShiftRight),
+            (13, TokenKind::OpAnd),
+            (16, TokenKind::OpOr),
+            (19, TokenKind::OpShiftLeft),
+            (21, TokenKind::OpAssign),
+            (23, TokenKind::OpShiftRight),
+            (25, TokenKind::OpAssign),
+            (26, TokenKind::EndOfInput),
+        ] {
+            let token = lexer.next_token().unwrap();
+            assert_eq!(1, token.line_number());
+            assert_eq!(column, token.column_number());
+            assert_eq!(*kind, *token.kind());
+        }
+    }
+
+    #[test]
+    fn test_power() {
+        let kinds = |src: &str, dialect: &Dialect| {
+            lex_all_with(src, dialect)
+                .unwrap()
+                .into_iter()
+                .map(|token| format!("{:?}", token.kind()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(
+            "OpPower OpMultiplyAssign OpMultiply OpPower OpMultiply EndOfInput",
+            kinds("** *= * *** ", &Dialect::extended())
+        );
+        let mut dialect = Dialect::extended();
+        dialect.power = false;
+        assert_eq!(
+            "OpMultiply OpMultiply OpBitXor EndOfInput",
+            kinds("** ^", &dialect)
+        );
+        // ^ をべき乗にすると、排他的論理和は書けない
+        dialect.caret_power = true;
+        assert_eq!(
+            "OpPower OpMultiply OpMultiply EndOfInput",
+            kinds("^ **", &dialect)
+        );
+        assert!(!dialect.allows(&TokenKind::OpBitXor));
+        assert!(!Dialect::rosetta().allows(&TokenKind::OpPower));
+    }
+
+    #[test]
+    fn test_dialect() {
+        let kinds = |src: &str, dialect: &Dialect| {
+            lex_all_with(src, dialect)
+                .unwrap()
+                .into_iter()
+                .map(|token| format!("{:?}", token.kind()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        // 拡張を無効にすると、タスクの文法どおりに 1 文字ずつの演算子になる
+        let rosetta = Dialect::rosetta();
+        assert_eq!(
+            "Identifier(\"do\") OpAdd OpAdd OpAdd OpAssign OpLess OpLess EndOfInput",
+            kinds("do ++ += <<", &rosetta)
+        );
+        assert!(lex_all_with("a & b", &rosetta).is_err());
+        assert!(lex_all_with("a ^ b", &rosetta).is_err());
+        assert_eq!(
+            "KeywordDo OpAdd OpAdd OpAddAssign OpShiftLeft EndOfInput",
+            kinds("do ++ += <<", &Dialect::extended())
+        );
+        assert_eq!(
+            "KeywordDo OpIncrement OpAddAssign OpShiftLeft EndOfInput",
+            kinds("do ++ += <<", &Dialect::increment())
+        );
+        // 既定の方言でもタスクの文法の 5--3 は 5 - (-3) と読む
+        assert_eq!(
+            "Identifier(\"x\") OpAssign Integer(5) OpSubtract OpSubtract Integer(3) Semicolon EndOfInput",
+            kinds("x = 5--3;", &Dialect::default())
+        );
+        assert_eq!(
+            "Identifier(\"printf\") EndOfInput",
+            kinds("printf", &rosetta)
+        );
+        assert_eq!(
+            "KeywordPrintf EndOfInput",
+            kinds("printf", &Dialect::extended())
+        );
+        assert_eq!("Identifier(\"exit\") EndOfInput", kinds("exit", &rosetta));
+        assert_eq!(
+            "KeywordExit EndOfInput",
+            kinds("exit", &Dialect::extended())
+        );
+        assert_eq!(
+            "Identifier(\"assert\") EndOfInput",
+            kinds("assert", &rosetta)
+        );
+        assert_eq!(
+            "KeywordAssert EndOfInput",
+            kinds("assert", &Dialect::extended())
+        );
+
+        let mut dialect = Dialect::extended();
+        dialect.rename_keyword(dialect::Keyword::While, "mientras");
+        assert_eq!(
+            "KeywordWhile Identifier(\"while\") EndOfInput",
+            kinds("mientras while", &dialect)
+        );
+    }
+
     #[test]
     fn test_comment() {
         let s = "//**a*b**// /* a / b *//".to_string();
@@ -631,6 +1076,39 @@ mod tests {
         assert_eq!(TokenKind::EndOfInput, *token.kind());
     }
 
+    #[test]
+    fn test_trivia() {
+        let s = "x /* a\n b */\t= 1;\n";
+        let tokens = lex_all_with_trivia(s, &Dialect::default()).unwrap();
+        assert_eq!(
+            vec![
+                "1 1 Identifier x",
+                "1 2 Whitespace \" \"",
+                "1 3 Comment \"/* a\\n b */\"",
+                "2 6 Whitespace \"\t\"",
+                "2 7 Op_assign",
+                "2 8 Whitespace \" \"",
+                "2 9 Integer 1",
+                "2 10 Semicolon",
+                "2 11 Whitespace \"\\n\"",
+                "3 1 End_of_input",
+            ],
+            tokens
+                .iter()
+                .map(|token| token.to_string())
+                .collect::<Vec<_>>()
+        );
+        // 表示したトリビアは読み戻せる
+        let token = Token::from_line(&tokens[2].to_string()).unwrap();
+        assert_eq!(TokenKind::Comment("/* a\n b */".to_string()), *token.kind());
+
+        // トリビアを残しても、閉じていないコメントはエラー。位置はコメントの始まり
+        let e = lex_all_with_trivia("x /* a", &Dialect::default()).unwrap_err();
+        assert_eq!(Some((1, 3)), e.position());
+        // 既定では読み飛ばす
+        assert!(lex_all(s).unwrap().iter().all(|t| !t.kind().is_trivia()));
+    }
+
     #[test]
     fn test_identifier() {
         let s = "ifprint fred42".to_string();
@@ -640,12 +1118,18 @@ mod tests {
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
         assert_eq!(1, token.column_number());
-        assert_eq!(TokenKind::Identifier("ifprint".to_string()), *token.kind());
+        assert_eq!(
+            TokenKind::Identifier(Symbol::intern("ifprint")),
+            *token.kind()
+        );
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
         assert_eq!(9, token.column_number());
-        assert_eq!(TokenKind::Identifier("fred42".to_string()), *token.kind());
+        assert_eq!(
+            TokenKind::Identifier(Symbol::intern("fred42")),
+            *token.kind()
+        );
 
         let token = lexer.next_token().unwrap();
         assert_eq!(1, token.line_number());
@@ -655,7 +1139,7 @@ mod tests {
 
     #[test]
     fn test_keyword() {
-        let s = "if else while print putc".to_string();
+        let s = "if else while print putc do".to_string();
 
         let mut lexer = LexicalAnalyzer::new(s.chars());
 
@@ -683,6 +1167,11 @@ mod tests {
         assert_eq!(1, token.line_number());
         assert_eq!(21, token.column_number());
         assert_eq!(TokenKind::KeywordPutc, *token.kind());
+
+        let token = lexer.next_token().unwrap();
+        assert_eq!(1, token.line_number());
+        assert_eq!(26, token.column_number());
+        assert_eq!(TokenKind::KeywordDo, *token.kind());
     }
 
     #[test]
@@ -715,6 +1204,43 @@ mod tests {
         assert_eq!(1, token.line_number());
         assert_eq!(8, token.column_number());
         assert_eq!(TokenKind::EndOfInput, *token.kind());
+
+        // i32 に収まらないリテラルは 64 ビットの幅でだけ書ける
+        assert!(lex_all("2147483648").is_err());
+        let mut dialect = Dialect::extended();
+        dialect.width = Width::W64;
+        let tokens = lex_all_with("2147483648 9223372036854775807", &dialect).unwrap();
+        assert_eq!(TokenKind::Integer(1 << 31), *tokens[0].kind());
+        assert_eq!(TokenKind::Integer(i64::MAX), *tokens[1].kind());
+        assert!(lex_all_with("9223372036854775808", &dialect).is_err());
+    }
+
+    #[test]
+    fn test_float_literal() {
+        let dialect = Dialect::float();
+        let tokens = lex_all_with("1.5 1.5e3 2e-3 7E+1 0.25>=3", &dialect).unwrap();
+        assert_eq!(
+            vec![
+                "1 1 Float 1.5",
+                "1 5 Float 1500.0",
+                "1 11 Float 0.002",
+                "1 16 Float 70.0",
+                "1 21 Float 0.25",
+                "1 25 Op_greaterequal",
+                "1 27 Integer 3",
+                "1 28 End_of_input",
+            ],
+            tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>()
+        );
+        let token = Token::from_line("1 1 Float 1500.0").unwrap();
+        assert_eq!(TokenKind::Float(Float(1.5e3)), *token.kind());
+
+        for src in ["1.", "1.x", "1e", "1e+", "1.5f", "1e39"] {
+            assert!(lex_all_with(src, &dialect).is_err(), "{}", src);
+        }
+        // 浮動小数点数の方言でなければ、 1.5 は読めない
+        assert!(lex_all("1.5").is_err());
+        assert!(lex_all("1e3").is_err());
     }
 
     #[test]
@@ -764,7 +1290,7 @@ print("Hello, World!\n");
         assert_eq!(4, token.line_number());
         assert_eq!(7, token.column_number());
         assert_eq!(
-            TokenKind::String("Hello, World!\n".to_string()),
+            TokenKind::String(Symbol::intern("Hello, World!\n")),
             *token.kind()
         );
 
@@ -800,7 +1326,7 @@ print(phoenix_number, "\n");
         assert_eq!(4, token.line_number());
         assert_eq!(1, token.column_number());
         assert_eq!(
-            TokenKind::Identifier("phoenix_number".to_string()),
+            TokenKind::Identifier(Symbol::intern("phoenix_number")),
             *token.kind()
         );
 
@@ -833,7 +1359,7 @@ print(phoenix_number, "\n");
         assert_eq!(5, token.line_number());
         assert_eq!(7, token.column_number());
         assert_eq!(
-            TokenKind::Identifier("phoenix_number".to_string()),
+            TokenKind::Identifier(Symbol::intern("phoenix_number")),
             *token.kind()
         );
 
@@ -845,7 +1371,7 @@ print(phoenix_number, "\n");
         let token = lexer.next_token().unwrap();
         assert_eq!(5, token.line_number());
         assert_eq!(23, token.column_number());
-        assert_eq!(TokenKind::String("\n".to_string()), *token.kind());
+        assert_eq!(TokenKind::String(Symbol::intern("\n")), *token.kind());
 
         let token = lexer.next_token().unwrap();
         assert_eq!(5, token.line_number());
@@ -863,9 +1389,8 @@ print(phoenix_number, "\n");
         assert_eq!(TokenKind::EndOfInput, *token.kind());
     }
 
-    #[test]
-    fn test_case_3() {
-        let s = r#"/*
+    /// タスクのテストケース 3。すべての種類のトークンを含む。
+    const TEST_CASE_3: &str = r#"/*
   All lexical tokens - not syntactically correct, but that will
   have to wait until syntax analysis
  */
@@ -887,8 +1412,11 @@ print(phoenix_number, "\n");
 /* character literal */  '\n'
 /* character literal */  '\\'
 /* character literal */  ' '
-"#
-        .to_string();
+"#;
+
+    #[test]
+    fn test_case_3() {
+        let s = TEST_CASE_3.to_string();
 
         let mut lexer = LexicalAnalyzer::new(s.chars());
 
@@ -1031,7 +1559,7 @@ print(phoenix_number, "\n");
         assert_eq!(18, token.line_number());
         assert_eq!(40, token.column_number());
         assert_eq!(
-            TokenKind::String("String literal".to_string()),
+            TokenKind::String(Symbol::intern("String literal")),
             *token.kind()
         );
 
@@ -1044,7 +1572,7 @@ print(phoenix_number, "\n");
         assert_eq!(19, token.line_number());
         assert_eq!(40, token.column_number());
         assert_eq!(
-            TokenKind::Identifier("variable_name".to_string()),
+            TokenKind::Identifier(Symbol::intern("variable_name")),
             *token.kind()
         );
 
@@ -1119,7 +1647,7 @@ print("Print a slash n - \\n.\n");
         assert_eq!(3, token.line_number());
         assert_eq!(7, token.column_number());
         assert_eq!(
-            TokenKind::String("\nHello World\nGood Bye\nok\n".to_string()),
+            TokenKind::String(Symbol::intern("\nHello World\nGood Bye\nok\n")),
             *token.kind()
         );
 
@@ -1147,7 +1675,7 @@ print("Print a slash n - \\n.\n");
         assert_eq!(4, token.line_number());
         assert_eq!(7, token.column_number());
         assert_eq!(
-            TokenKind::String("Print a slash n - \\n.\n".to_string()),
+            TokenKind::String(Symbol::intern("Print a slash n - \\n.\n")),
             *token.kind()
         );
 
@@ -1166,4 +1694,283 @@ print("Print a slash n - \\n.\n");
         assert_eq!(1, token.column_number());
         assert_eq!(TokenKind::EndOfInput, *token.kind());
     }
+
+    #[test]
+    fn test_lex_all() {
+        let tokens = lex_all("a = 1; /* c1 */ /* c2 */").unwrap();
+        assert_eq!(5, tokens.len());
+        assert_eq!(TokenKind::EndOfInput, *tokens[4].kind());
+
+        // 再帰していたころはコメントが大量に続くとスタックが溢れた
+        let src = "/**/".repeat(100_000);
+        let tokens = lex_all(&src).unwrap();
+        assert_eq!(1, tokens.len());
+
+        assert!(lex_all("\"abc").is_err());
+        assert!(lex_all("/* abc").is_err());
+        assert!(lex_all("99999999999").is_err());
+
+        // エラーには見つけた位置が付く
+        let e = lex_all("x = 1;\n  y = #;").unwrap_err();
+        assert_eq!(Some((2, 7)), e.position());
+        // 読み終えた位置ではなく、トークンの始まりの位置
+        let e = lex_all("x = \"abc\ny\";").unwrap_err();
+        assert_eq!(Some((1, 5)), e.position());
+        let e = lex_all("x = 1; /* abc\n\n").unwrap_err();
+        assert_eq!(Some((1, 8)), e.position());
+        let e = lex_all("x = 'ab';").unwrap_err();
+        assert_eq!(Some((1, 5)), e.position());
+    }
+
+    #[test]
+    fn test_string_escape() {
+        let tokens = lex_all("print(\"say \\\"hi\\\"\tand\\\\\\n\");").unwrap();
+        assert_eq!(
+            TokenKind::String(Symbol::intern("say \"hi\"\tand\\\n")),
+            *tokens[2].kind()
+        );
+        // 書き出したトークンを読み直すと同じ文字列になる
+        let line = tokens[2].to_string();
+        assert_eq!("1 7 String \"say \\\"hi\\\"\tand\\\\\\n\"", line);
+        assert_eq!(tokens[2].kind(), Token::from_line(&line).unwrap().kind());
+
+        assert!(lex_all(r#""\t""#).is_err());
+
+        // タスクの文法には \" がない
+        let rosetta = Dialect::rosetta();
+        for src in [r#""say \"hi\"""#, r#"'\"'"#] {
+            let e = lex_all_with(src, &rosetta).unwrap_err();
+            assert_eq!("Unknown escape sequence", e.message());
+        }
+        let tokens = lex_all_with(r#""a\\b\n" '\\'"#, &rosetta).unwrap();
+        assert_eq!(
+            TokenKind::String(Symbol::intern("a\\b\n")),
+            *tokens[0].kind()
+        );
+        assert_eq!(TokenKind::Integer('\\' as i64), *tokens[1].kind());
+        assert_eq!(
+            TokenKind::Integer('"' as i64),
+            *lex_all(r#"'\"'"#).unwrap()[0].kind()
+        );
+    }
+
+    #[test]
+    fn test_unicode() {
+        let positions = |tokens: &[Token]| {
+            tokens
+                .iter()
+                .map(|token| token.to_string())
+                .collect::<Vec<_>>()
+        };
+        // 列は Unicode のスカラー値で数える
+        let tokens = lex_all("s = \"é🦀\"; putc('🦀', 'é');").unwrap();
+        assert_eq!(
+            vec![
+                "1 1 Identifier s",
+                "1 3 Op_assign",
+                "1 5 String \"é🦀\"",
+                "1 9 Semicolon",
+                "1 11 Keyword_putc",
+                "1 15 LeftParen",
+                "1 16 Integer 129408",
+                "1 19 Comma",
+                "1 21 Integer 233",
+                "1 24 RightParen",
+                "1 25 Semicolon",
+                "1 26 End_of_input",
+            ],
+            positions(&tokens)
+        );
+
+        // ASCII 以外の識別子は unicode の方言でだけ使える
+        let e = lex_all("x = 1; 変数 = 2;").unwrap_err();
+        assert_eq!("Unrecognized character: 変", e.message());
+        assert_eq!(Some((1, 8)), e.position());
+        let tokens = lex_all_with("変数 = (café_2, x);", &Dialect::unicode()).unwrap();
+        assert_eq!(
+            vec![
+                "1 1 Identifier 変数",
+                "1 4 Op_assign",
+                "1 6 LeftParen",
+                "1 7 Identifier café_2",
+                "1 13 Comma",
+                "1 15 Identifier x",
+                "1 16 RightParen",
+                "1 17 Semicolon",
+                "1 18 End_of_input",
+            ],
+            positions(&tokens)
+        );
+        // 数字で始まるものや、記号は識別子にならない
+        assert!(lex_all_with("٣x", &Dialect::unicode()).is_err());
+        assert!(lex_all_with("a🦀", &Dialect::unicode()).is_err());
+    }
+
+    #[test]
+    fn test_from_line_error() {
+        assert!(Token::from_line("1 1 Integer 42").is_ok());
+        assert!(Token::from_line("x 1 Integer 42").is_err());
+        assert!(Token::from_line("1 -1 Integer 42").is_err());
+        assert!(Token::from_line("1 1 Integer 4x2").is_err());
+        assert!(Token::from_line("1 1 Unknown").is_err());
+        assert!(Token::from_line("").is_err());
+    }
+
+    #[test]
+    fn test_compat() {
+        use token::TokenFormat;
+
+        // タスクに載っているテストケース 3 の出力
+        let expected = r#"    5     16   Keyword_print
+    5     40   Op_subtract
+    6     16   Keyword_putc
+    6     40   Op_less
+    7     16   Keyword_if
+    7     40   Op_greater
+    8     16   Keyword_else
+    8     40   Op_lessequal
+    9     16   Keyword_while
+    9     40   Op_greaterequal
+   10     16   LeftBrace
+   10     40   Op_equal
+   11     16   RightBrace
+   11     40   Op_notequal
+   12     16   LeftParen
+   12     40   Op_and
+   13     16   RightParen
+   13     40   Op_or
+   14     16   Op_subtract
+   14     40   Semicolon
+   15     16   Op_not
+   15     40   Comma
+   16     16   Op_multiply
+   16     40   Op_assign
+   17     16   Op_divide
+   17     40   Integer             42
+   18     16   Op_mod
+   18     40   String              "String literal"
+   19     16   Op_add
+   19     40   Identifier          variable_name
+   20     26   Integer             10
+   21     26   Integer             92
+   22     26   Integer             32
+   23      1   End_of_input
+"#;
+        let tokens = lex_all(TEST_CASE_3).unwrap();
+        let lines: String = tokens
+            .iter()
+            .map(|t| t.to_line(TokenFormat::Compat) + "\n")
+            .collect();
+        assert_eq!(expected, lines);
+
+        // 読み戻すと同じトークンになる
+        for (line, token) in expected.lines().zip(&tokens) {
+            let read = Token::from_line_with(line, TokenFormat::Compat).unwrap();
+            assert_eq!(token.kind(), read.kind());
+            assert_eq!(
+                (token.line_number(), token.column_number()),
+                (read.line_number(), read.column_number())
+            );
+        }
+
+        // 他の実装の揃え方や名前の書き方
+        for line in &[
+            "23 1 End_of_input",
+            "   23\t 1\tEnd_Of_Input",
+            "23 1 EndOfInput",
+            "23 1 END_OF_INPUT   ",
+        ] {
+            let token = Token::from_line_with(line, TokenFormat::Compat).unwrap();
+            assert_eq!(TokenKind::EndOfInput, *token.kind(), "{}", line);
+            assert_eq!((23, 1), (token.line_number(), token.column_number()));
+        }
+        let token = Token::from_line_with("1\t5\tOp_LessEqual", TokenFormat::Compat).unwrap();
+        assert_eq!(TokenKind::OpLessEqual, *token.kind());
+        let token = Token::from_line_with("2 3 identifier\t\tcount", TokenFormat::Compat).unwrap();
+        assert_eq!(
+            TokenKind::Identifier(Symbol::intern("count")),
+            *token.kind()
+        );
+        let token = Token::from_line_with("2 3 String  \"a b\\n\"", TokenFormat::Compat).unwrap();
+        assert_eq!(TokenKind::String(Symbol::intern("a b\n")), *token.kind());
+
+        // Plain では名前の違いを許さない
+        assert!(Token::from_line("23 1 EndOfInput").is_err());
+        assert!(Token::from_line_with("1 1 Keyword_goto", TokenFormat::Compat).is_err());
+    }
+
+    #[test]
+    fn test_token_display() {
+        use token::{TokenFormat, NAMES};
+
+        // すべての種類を Display で書いて、 from_line で読み戻す
+        for name in NAMES {
+            let line = match *name {
+                "Integer" => "3 7 Integer 42".to_string(),
+                "Float" => "3 7 Float 1.5".to_string(),
+                "Identifier" => "3 7 Identifier count".to_string(),
+                "String" | "Comment" | "Whitespace" => format!("3 7 {} \"a \\\"b\\\"\\n\"", name),
+                _ => format!("3 7 {}", name),
+            };
+            let token = Token::from_line(&line).unwrap();
+            assert_eq!(*name, token.kind().name());
+            assert_eq!(line, token.to_string());
+            assert_eq!(line, token.to_line(TokenFormat::Plain));
+
+            for format in &[TokenFormat::Plain, TokenFormat::Compat, TokenFormat::Legacy] {
+                let read = Token::from_line_with(&token.to_line(*format), *format).unwrap();
+                assert_eq!(token.kind(), read.kind(), "{} ({:?})", line, format);
+                assert_eq!(
+                    (3, 7),
+                    (read.line_number(), read.column_number()),
+                    "{} ({:?})",
+                    line,
+                    format
+                );
+            }
+        }
+
+        let token = Token::from_line("1 1 Op_multiply").unwrap();
+        assert_eq!("1 1 Op_multiply", token.to_string());
+        let token = Token::from_line("2 1 End_of_input").unwrap();
+        assert_eq!("2 1 End_of_input", token.to_string());
+    }
+
+    #[test]
+    fn test_token_legacy() {
+        use token::TokenFormat;
+
+        // 以前の Display は一部の演算子の前に "Identifier " を付けていた
+        let tokens = lex_all("a * b <= c < d;").unwrap();
+        let lines: Vec<String> = tokens
+            .iter()
+            .map(|t| t.to_line(TokenFormat::Legacy))
+            .collect();
+        assert_eq!(
+            vec![
+                "1 1 Identifier a",
+                "1 3 Identifier Op_multiply",
+                "1 5 Identifier b",
+                "1 7 Identifier Op_lessequal",
+                "1 10 Identifier c",
+                "1 12 Identifier Op_less",
+                "1 14 Identifier d",
+                "1 15 Semicolon",
+                "1 16 End_of_input",
+            ],
+            lines
+        );
+
+        let token =
+            Token::from_line_with("1 3 Identifier Op_multiply", TokenFormat::Legacy).unwrap();
+        assert_eq!(TokenKind::OpMultiply, *token.kind());
+        let token = Token::from_line_with("1 3 Op_multiply", TokenFormat::Legacy).unwrap();
+        assert_eq!(TokenKind::OpMultiply, *token.kind());
+        // Plain ではその名前の識別子のまま
+        let token = Token::from_line("1 3 Identifier Op_multiply").unwrap();
+        assert_eq!(
+            TokenKind::Identifier(Symbol::intern("Op_multiply")),
+            *token.kind()
+        );
+    }
 }
@@ -0,0 +1,93 @@
+use crate::error::*;
+
+/// 信頼できないプログラムを実行するときの資源の上限。 None の項目は制限しない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// 変数の数。仮想マシンでは読み込み時にヘッダの値を調べる。
+    pub data_size: Option<usize>,
+    /// 文字列プールの文字列のバイト数の合計
+    pub string_pool_size: Option<usize>,
+    /// 出力先に書き出すバイト数の合計
+    pub output_size: Option<usize>,
+    /// 再帰の深さ。仮想マシンでは関数呼び出しの深さ、 AST インタプリタでは AST の深さ。
+    pub recursion_depth: Option<usize>,
+}
+
+impl Limits {
+    /// 変数の数が上限を超えるならエラーにする
+    pub fn check_data_size(&self, kind: ErrorKind, size: usize) -> Result<()> {
+        match self.data_size {
+            Some(limit) if size > limit => Err(CompileError::new(
+                kind,
+                format!("data size {} exceeds the limit of {}", size, limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 文字列プールのバイト数が上限を超えるならエラーにする
+    pub fn check_string_pool_size(&self, kind: ErrorKind, size: usize) -> Result<()> {
+        match self.string_pool_size {
+            Some(limit) if size > limit => Err(CompileError::new(
+                kind,
+                format!(
+                    "string pool size {} bytes exceeds the limit of {} bytes",
+                    size, limit
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// written バイト書き出した後に、さらに len バイト書き出せるか調べる。
+    /// 上限を超えるなら、何も書き出さずにエラーにする。
+    pub fn check_output(&self, kind: ErrorKind, written: usize, len: usize) -> Result<()> {
+        match self.output_size {
+            Some(limit) if written + len > limit => Err(CompileError::new(
+                kind,
+                format!("output exceeds the limit of {} bytes", limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 再帰の深さの上限。 default より大きい上限は default にする。
+    pub fn recursion_depth_or(&self, default: usize) -> usize {
+        self.recursion_depth
+            .map_or(default, |depth| depth.min(default))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let kind = || ErrorKind::VirtualMachineError;
+        let unlimited = Limits::default();
+        assert!(unlimited.check_data_size(kind(), usize::MAX).is_ok());
+        assert!(unlimited.check_output(kind(), 1 << 40, 1 << 40).is_ok());
+        assert_eq!(500, unlimited.recursion_depth_or(500));
+
+        let limits = Limits {
+            data_size: Some(10),
+            string_pool_size: Some(100),
+            output_size: Some(5),
+            recursion_depth: Some(20),
+        };
+        assert!(limits.check_data_size(kind(), 10).is_ok());
+        let e = limits.check_data_size(kind(), 11).unwrap_err();
+        assert_eq!("data size 11 exceeds the limit of 10", e.message());
+        let e = limits.check_string_pool_size(kind(), 101).unwrap_err();
+        assert_eq!(
+            "string pool size 101 bytes exceeds the limit of 100 bytes",
+            e.message()
+        );
+        assert!(limits.check_output(kind(), 3, 2).is_ok());
+        let e = limits.check_output(kind(), 3, 3).unwrap_err();
+        assert_eq!("output exceeds the limit of 5 bytes", e.message());
+        assert_eq!(20, limits.recursion_depth_or(500));
+        assert_eq!(10, limits.recursion_depth_or(10));
+    }
+}
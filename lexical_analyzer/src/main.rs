@@ -1,17 +1,38 @@
-use lexical_analyzer::error::Result;
+use lexical_analyzer::cli::{Command, Flag, Format, Options};
+use lexical_analyzer::error::*;
+use lexical_analyzer::highlight::highlight_with;
 use lexical_analyzer::token::TokenKind;
 use lexical_analyzer::LexicalAnalyzer;
 
-use std::env;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
-
-fn analyze(src: String, out: &mut Write) -> Result<()> {
-    let mut lex = LexicalAnalyzer::new(src.chars());
+use std::io::Write;
+
+const COMMAND: Command = Command {
+    name: "lexical_analyzer",
+    description: "Reads tiny-language source code and writes the token list.",
+    flags: &[
+        Flag::Format,
+        Flag::Dialect,
+        Flag::Width,
+        Flag::Highlight,
+        Flag::Trivia,
+        Flag::Compat,
+        Flag::LegacyTokens,
+    ],
+};
+
+fn analyze(src: String, out: &mut dyn Write, options: &Options) -> Result<()> {
+    let mut lex = LexicalAnalyzer::with_dialect(src.chars(), options.dialect.clone());
+    if options.trivia {
+        lex = lex.keep_trivia();
+    }
     loop {
         let token = lex.next_token()?;
 
-        out.write_fmt(format_args!("{}\n", token)).unwrap();
+        match options.format {
+            Format::Text => writeln!(out, "{}", token.to_line(options.token_format())),
+            Format::Debug => writeln!(out, "{:?}", token),
+        }
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
 
         if *token.kind() == TokenKind::EndOfInput {
             break;
@@ -21,25 +42,20 @@ fn analyze(src: String, out: &mut Write) -> Result<()> {
     Ok(())
 }
 
+fn run(options: &Options) -> Result<()> {
+    let src = options.read_input()?;
+    let mut writer = options.create_output()?;
+
+    match options.highlight {
+        Some(style) => write!(writer, "{}", highlight_with(&src, style, &options.dialect))
+            .map_err(|e| CompileError::new(ErrorKind::IOError, e))?,
+        None => analyze(src, &mut writer, options)?,
+    }
+    writer
+        .flush()
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+}
+
 fn main() {
-    let mut reader: Box<dyn BufRead> = match env::args().nth(1) {
-        None => Box::new(BufReader::new(stdin())),
-        Some(filename) => Box::new(BufReader::new(
-            File::open(filename).expect("cannot open file"),
-        )),
-    };
-
-    let mut writer: Box<dyn Write> = match env::args().nth(2) {
-        None => Box::new(BufWriter::new(stdout())),
-        Some(filename) => Box::new(BufWriter::new(
-            File::create(filename).expect("cannot create file"),
-        )),
-    };
-
-    let mut str_in = String::new();
-    reader
-        .read_to_string(&mut str_in)
-        .expect("cannot read source code");
-
-    analyze(str_in, &mut writer).expect("lexcal analyzer failed.");
+    COMMAND.main(run);
 }
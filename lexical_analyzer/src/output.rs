@@ -0,0 +1,122 @@
+use crate::error::*;
+use std::io;
+
+/// 出力先が閉じられたとき (head にパイプでつないだ場合など) の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputPolicy {
+    /// エラーにする
+    #[default]
+    Fail,
+    /// 実行をそこで止め、正常に終わったことにする
+    Stop,
+    /// 以降の出力を捨てて実行を続ける
+    Discard,
+}
+
+/// 出力先が閉じられたことによるエラーなら true
+pub fn is_closed(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::WriteZero
+    )
+}
+
+/// 書き込みのエラーを kind のエラーにする。
+/// CompileError::is_output_closed で判定できるように io::Error の種類を残す。
+pub fn output_error(kind: ErrorKind, e: io::Error) -> CompileError {
+    CompileError::new(
+        kind,
+        io::Error::new(e.kind(), format!("output error: {}", e)),
+    )
+}
+
+impl OutputPolicy {
+    /// 書き込みの結果を扱う。 Discard なら閉じた出力先への書き込みを成功にする。
+    pub fn write(self, kind: ErrorKind, result: io::Result<()>) -> Result<()> {
+        match result {
+            Err(e) if self == OutputPolicy::Discard && is_closed(&e) => Ok(()),
+            result => result.map_err(|e| output_error(kind, e)),
+        }
+    }
+
+    /// 実行全体の結果を扱う。 Stop なら出力先が閉じられて止まった実行を成功にする。
+    pub fn finish<T: Default>(self, result: Result<T>) -> Result<T> {
+        match result {
+            Err(e) if self == OutputPolicy::Stop && e.is_output_closed() => Ok(T::default()),
+            result => result,
+        }
+    }
+}
+
+/// テスト用の、 limit バイト書いたあとは閉じられたことになる出力先
+#[cfg(any(test, feature = "test-utils"))]
+#[derive(Debug, Default)]
+pub struct ClosedAfter {
+    /// 閉じられるまでに書かれたバイト
+    pub written: Vec<u8>,
+    limit: usize,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl ClosedAfter {
+    pub fn new(limit: usize) -> Self {
+        ClosedAfter {
+            written: Vec::new(),
+            limit,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl io::Write for ClosedAfter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written.len() + buf.len() > self.limit {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let closed = || Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        let kind = || ErrorKind::InterpretationError;
+
+        let e = OutputPolicy::Fail.write(kind(), closed()).unwrap_err();
+        assert!(e.is_output_closed());
+        assert!(e.message().starts_with("output error: "));
+        assert!(OutputPolicy::Stop.write(kind(), closed()).is_err());
+        assert!(OutputPolicy::Discard.write(kind(), closed()).is_ok());
+
+        // 閉じた以外のエラーは捨てない
+        let other = Err(io::Error::other("disk full"));
+        let e = OutputPolicy::Discard.write(kind(), other).unwrap_err();
+        assert!(!e.is_output_closed());
+
+        let stopped = || Err::<(), _>(OutputPolicy::Fail.write(kind(), closed()).unwrap_err());
+        assert!(OutputPolicy::Fail.finish(stopped()).is_err());
+        assert!(OutputPolicy::Stop.finish(stopped()).is_ok());
+        let failed = Err::<(), _>(CompileError::new(kind(), "error"));
+        assert!(OutputPolicy::Stop.finish(failed).is_err());
+    }
+
+    #[test]
+    fn test_closed_after() {
+        use std::io::Write;
+
+        let mut out = ClosedAfter::new(3);
+        assert!(out.write_all(b"ab").is_ok());
+        let e = out.write_all(b"cd").unwrap_err();
+        assert!(is_closed(&e));
+        assert_eq!(b"ab", &out.written[..]);
+    }
+}
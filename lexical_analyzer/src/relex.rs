@@ -0,0 +1,254 @@
+use super::dialect::Dialect;
+use super::error::*;
+use super::token::{Token, TokenKind};
+use super::LexicalAnalyzer;
+
+use std::ops::Range;
+
+/// 各行の先頭のバイト単位の位置
+pub(crate) fn line_starts(src: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// 行と列 (どちらも 1 から) をバイト単位の位置に変換する
+pub(crate) fn offset(src: &str, line_starts: &[usize], line: usize, column: usize) -> usize {
+    let start = line_starts[line - 1];
+    src[start..]
+        .char_indices()
+        .nth(column - 1)
+        .map_or(src.len(), |(i, _)| start + i)
+}
+
+impl LexicalAnalyzer<'_> {
+    /// src の range (バイト単位) を new_text に置き換えたソースのトークン列を返す。
+    /// previous_tokens は src を lex_all_with で解析した結果で、変わらない部分はそのまま使う。
+    /// 編集した位置の直前のトークンから解析し直し、古いトークンの始まりと位置が揃えば、
+    /// そこから後ろは古いトークンの行と列をずらしてつなぐ。
+    /// 文字列やコメントの開始と終了を書き換えると、遠くのトークンまで解析し直す。
+    pub fn relex(
+        src: &str,
+        range: Range<usize>,
+        new_text: &str,
+        previous_tokens: Vec<Token>,
+        dialect: &Dialect,
+    ) -> Result<Vec<Token>> {
+        relex_with(src, range, new_text, previous_tokens, dialect, false)
+    }
+
+    /// relex と同じだが、 previous_tokens は lex_all_with_trivia で解析した結果とする。
+    pub fn relex_with_trivia(
+        src: &str,
+        range: Range<usize>,
+        new_text: &str,
+        previous_tokens: Vec<Token>,
+        dialect: &Dialect,
+    ) -> Result<Vec<Token>> {
+        relex_with(src, range, new_text, previous_tokens, dialect, true)
+    }
+}
+
+fn relex_with(
+    src: &str,
+    range: Range<usize>,
+    new_text: &str,
+    previous_tokens: Vec<Token>,
+    dialect: &Dialect,
+    trivia: bool,
+) -> Result<Vec<Token>> {
+    let edited = format!("{}{}{}", &src[..range.start], new_text, &src[range.end..]);
+    let old_line_starts = line_starts(src);
+    let edited_line_starts = line_starts(&edited);
+    let offsets: Vec<usize> = previous_tokens
+        .iter()
+        .map(|t| offset(src, &old_line_starts, t.line_number(), t.column_number()))
+        .collect();
+    // 編集した位置より前から始まる最後のトークン。
+    // 編集でこのトークンが伸びたり縮んだりするので、ここから解析し直す。
+    // 前にトークンがなければ先頭から解析する。
+    let restart = offsets.iter().rposition(|&o| o < range.start);
+    let (restart, restart_offset, line_number, column_number) = match restart {
+        Some(i) => (
+            i,
+            offsets[i],
+            previous_tokens[i].line_number(),
+            previous_tokens[i].column_number(),
+        ),
+        None => (0, 0, 1, 1),
+    };
+    // 編集した部分より後ろで、古いソースの位置 = 新しいソースの位置 - delta
+    let delta = new_text.len() as isize - range.len() as isize;
+    let edited_end = range.start + new_text.len();
+
+    let mut lexer =
+        LexicalAnalyzer::with_dialect(edited[restart_offset..].chars(), dialect.clone());
+    lexer.line_number = line_number;
+    lexer.column_number = column_number;
+    lexer.trivia = trivia;
+
+    let mut previous_tokens = previous_tokens;
+    let mut suffix = Vec::new();
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token()?;
+        let start = offset(
+            &edited,
+            &edited_line_starts,
+            token.line_number(),
+            token.column_number(),
+        );
+        if start >= edited_end {
+            let old = (start as isize - delta) as usize;
+            if let Ok(i) = offsets[restart..].binary_search(&old) {
+                // 古いトークンの始まりと揃ったので、残りのソースは同じトークンになる
+                suffix = previous_tokens.split_off(restart + i);
+                let line_delta = token.line_number() as isize - suffix[0].line_number() as isize;
+                let same_line = suffix[0].line_number();
+                let column_delta =
+                    token.column_number() as isize - suffix[0].column_number() as isize;
+                suffix = suffix
+                    .into_iter()
+                    .map(|t| {
+                        let column = if t.line_number() == same_line {
+                            (t.column_number() as isize + column_delta) as usize
+                        } else {
+                            t.column_number()
+                        };
+                        let line = (t.line_number() as isize + line_delta) as usize;
+                        Token::new(t.kind, line, column)
+                    })
+                    .collect();
+                break;
+            }
+        }
+        let end = *token.kind() == TokenKind::EndOfInput;
+        tokens.push(token);
+        if end {
+            break;
+        }
+    }
+
+    previous_tokens.truncate(restart);
+    previous_tokens.extend(tokens);
+    previous_tokens.extend(suffix);
+    Ok(previous_tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lex_all_with, lex_all_with_trivia};
+
+    /// relex の結果が、編集後のソースを全部解析し直した結果と同じか調べる
+    fn check(src: &str, range: Range<usize>, new_text: &str) -> Vec<Token> {
+        let dialect = Dialect::extended();
+        let edited = format!("{}{}{}", &src[..range.start], new_text, &src[range.end..]);
+        let previous = lex_all_with(src, &dialect).unwrap();
+        let tokens =
+            LexicalAnalyzer::relex(src, range.clone(), new_text, previous, &dialect).unwrap();
+        assert_eq!(
+            format!("{:?}", lex_all_with(&edited, &dialect).unwrap()),
+            format!("{:?}", tokens),
+            "{:?} -> {:?}",
+            src,
+            edited
+        );
+
+        let previous = lex_all_with_trivia(src, &dialect).unwrap();
+        let trivia =
+            LexicalAnalyzer::relex_with_trivia(src, range, new_text, previous, &dialect).unwrap();
+        assert_eq!(
+            format!("{:?}", lex_all_with_trivia(&edited, &dialect).unwrap()),
+            format!("{:?}", trivia)
+        );
+        tokens
+    }
+
+    #[test]
+    fn test_relex() {
+        let src = "count = 1;\nwhile (count < 10) {\n    print(count, \"\\n\");\n    count = count + 1;\n}\n";
+        // 識別子を伸ばす、縮める
+        check(src, 0..5, "n");
+        check(src, 5..5, "er");
+        // 行を加える、消す
+        check(src, 10..10, "\nx = 2;");
+        check(src, 11..33, "");
+        // 演算子がつながる
+        check("a = b < = c;", 7..8, "");
+        // 先頭と末尾
+        check(src, 0..0, "  /* head */ ");
+        check(src, src.len()..src.len(), "x = 0;");
+        check("  x;", 0..2, "");
+    }
+
+    #[test]
+    fn test_relex_string() {
+        // 文字列の終わりを消すと、続くトークンは文字列の中になる
+        // 後ろのコメントの中の '"' が文字列の終わりになる
+        let src = "s = \"ab\"; x = 1; t = \"cd\"; y = 2; /* \" */\n";
+        let tokens = check(src, 7..8, "");
+        assert!(matches!(tokens[2].kind, TokenKind::String(s) if &*s.text() == "ab; x = 1; t = "));
+        assert!(matches!(tokens[4].kind, TokenKind::String(s) if &*s.text() == "; y = 2; /* "));
+        check(src, 4..5, "");
+        // 文字列の中の編集は、その文字列のトークンだけが変わる
+        check(src, 5..6, "A\\n");
+    }
+
+    #[test]
+    fn test_relex_comment() {
+        let src = "a = 1; /* note */ b = 2;\nc = 3; /* end */\nd = 4;\n";
+        // コメントの終わりを消すと、次のコメントの終わりまでがコメントになる
+        let tokens = check(src, 14..16, "");
+        assert_eq!(
+            vec![
+                (1, 1),
+                (1, 3),
+                (1, 5),
+                (1, 6),
+                (3, 1),
+                (3, 3),
+                (3, 5),
+                (3, 6),
+                (4, 1)
+            ],
+            tokens
+                .iter()
+                .map(|t| (t.line_number(), t.column_number()))
+                .collect::<Vec<_>>()
+        );
+        // コメントを始めると、遠くのコメントの終わりまで消える
+        check(src, 6..6, " /*");
+        // 除算をコメントの始まりにする
+        check("x = a / b; y = 1; /* c */", 7..7, "*");
+        check("x = a /* b */ + c;", 6..7, "");
+    }
+
+    #[test]
+    fn test_relex_every_edit() {
+        // すべての位置で 1 文字の挿入と削除を試す
+        let src = "if (a <= b) { s = \"x\"; } /* c */ else c = 'q' + 17;\n";
+        for i in 0..=src.len() {
+            for text in &["\"", "/", "*", "=", " ", "\n", "'", "z", "9"] {
+                let edited = format!("{}{}{}", &src[..i], text, &src[i..]);
+                if lex_all_with(&edited, &Dialect::extended()).is_ok() {
+                    check(src, i..i, text);
+                }
+            }
+            if i < src.len() {
+                let edited = format!("{}{}", &src[..i], &src[i + 1..]);
+                if lex_all_with(&edited, &Dialect::extended()).is_ok() {
+                    check(src, i..i + 1, "");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_relex_error() {
+        let src = "s = \"ab\";";
+        let previous = lex_all_with(src, &Dialect::default()).unwrap();
+        let e = LexicalAnalyzer::relex(src, 0..0, "\"", previous, &Dialect::default()).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::LexicalAnalyzerError));
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+
+/// 実行を終えたときの統計と状態。 AST インタプリタと仮想マシンが返す。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunReport {
+    /// 実行した手順の数。 AST インタプリタでは文の数、仮想マシンでは命令の数。
+    pub steps: u64,
+    /// 実行を終えた命令の数。 AST インタプリタでは評価したノードの数、
+    /// 仮想マシンでは steps と同じ。
+    pub instructions_retired: u64,
+    /// 終了時の変数の名前と値。名前の順に並べ、文字列の値は引用符で囲む。
+    pub variables: Vec<(String, String)>,
+    /// exit で止まったならその終了コード、それ以外は 0
+    pub exit_code: i32,
+}
+
+impl RunReport {
+    /// 変数 name の値。なければ None。
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} steps, {} instructions retired, exit code {}",
+            self.steps, self.instructions_retired, self.exit_code
+        )?;
+        for (name, value) in &self.variables {
+            writeln!(f, "{} = {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let report = RunReport {
+            steps: 3,
+            instructions_retired: 10,
+            variables: vec![
+                ("i".to_string(), "4".to_string()),
+                ("s".to_string(), "\"a\"".to_string()),
+            ],
+            exit_code: 1,
+        };
+        assert_eq!(Some("4"), report.variable("i"));
+        assert_eq!(None, report.variable("x"));
+        assert_eq!(
+            "3 steps, 10 instructions retired, exit code 1\ni = 4\ns = \"a\"\n",
+            report.to_string()
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! テスト用の、プログラムの出力のスナップショット。
+//! 長い出力は、全体のハッシュと先頭の数行だけを期待値としてテストに書く。
+//!
+//! ```text
+//! bytes: 10941
+//! fnv1a64: 0123456789abcdef
+//! (先頭の PREFIX_LINES 行)
+//! ...
+//! ```
+
+use std::fmt;
+
+/// スナップショットに残す先頭の行数
+pub const PREFIX_LINES: usize = 5;
+
+/// 出力の大きさとハッシュと先頭の数行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    len: usize,
+    hash: u64,
+    /// 先頭の PREFIX_LINES 行。 UTF-8 でないバイトは置き換える。
+    prefix: String,
+    /// 先頭の数行より後にも出力があれば true
+    truncated: bool,
+}
+
+impl Snapshot {
+    pub fn new(output: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(output);
+        let end = text
+            .match_indices('\n')
+            .nth(PREFIX_LINES - 1)
+            .map_or(text.len(), |(i, _)| i + 1);
+        Snapshot {
+            len: output.len(),
+            hash: fnv1a64(output),
+            prefix: text[..end].to_string(),
+            truncated: end < text.len(),
+        }
+    }
+}
+
+/// FNV-1a の 64 ビットのハッシュ。
+/// std の Hasher と違ってバージョンや環境で変わらないので、期待値に書いておける。
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "bytes: {}", self.len)?;
+        writeln!(f, "fnv1a64: {:016x}", self.hash)?;
+        f.write_str(&self.prefix)?;
+        if self.truncated {
+            if !self.prefix.ends_with('\n') {
+                writeln!(f)?;
+            }
+            writeln!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// output のスナップショットが expected と一致することを確かめる。
+/// 一致しなければ、期待値に貼り付けられる形で実際のスナップショットを示して panic する。
+#[track_caller]
+pub fn assert_snapshot(output: &[u8], expected: &str) {
+    let actual = Snapshot::new(output).to_string();
+    if actual != expected {
+        panic!(
+            "snapshot mismatch\n--- expected\n{}--- actual\n{}---",
+            expected, actual
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        assert_eq!(0xcbf29ce484222325, fnv1a64(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, fnv1a64(b"a"));
+
+        let short = Snapshot::new(b"1\n2\n3");
+        assert_eq!(
+            "bytes: 5\nfnv1a64: dbd536d5af6ff2c7\n1\n2\n3",
+            short.to_string()
+        );
+
+        let output: String = (1..=100).map(|i| format!("{}\n", i)).collect();
+        assert_snapshot(
+            output.as_bytes(),
+            "bytes: 292\nfnv1a64: 706c028b5eb82172\n1\n2\n3\n4\n5\n...\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "snapshot mismatch")]
+    fn test_mismatch() {
+        assert_snapshot(b"2\n", "bytes: 2\nfnv1a64: 0000000000000000\n1\n");
+    }
+}
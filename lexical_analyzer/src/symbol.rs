@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// intern した文字列を表す番号。比較とハッシュは番号だけで行う。
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// 文字列と Symbol の対応表。文字列は表が持ち、表を捨てると解放する。
+#[derive(Default)]
+pub struct Interner {
+    symbols: HashMap<Arc<str>, Symbol>,
+    strings: Vec<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// s に対応する Symbol を返す。初めて見る文字列なら登録する。
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(s) {
+            return symbol;
+        }
+
+        let s: Arc<str> = Arc::from(s);
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(Arc::clone(&s));
+        self.symbols.insert(s, symbol);
+        symbol
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+/// 字句解析から実行までの各段階で共有する Interner
+fn with_interner<T, F>(f: F) -> T
+where
+    F: FnOnce(&mut Interner) -> T,
+{
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+    let mut interner = INTERNER
+        .get_or_init(|| Mutex::new(Interner::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    f(&mut interner)
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        with_interner(|interner| interner.intern(s))
+    }
+
+    /// 対応する文字列。表が持つ文字列を共有するので、複製しない。
+    pub fn text(self) -> Arc<str> {
+        with_interner(|interner| Arc::clone(&interner.strings[self.0 as usize]))
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+
+/// 番号ではなく文字列を表示する
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.text(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interner() {
+        let mut interner = Interner::new();
+        let a = interner.intern("count");
+        let b = interner.intern("n");
+        assert_eq!(a, interner.intern("count"));
+        assert_ne!(a, b);
+        assert_eq!("count", interner.resolve(a));
+        assert_eq!("n", interner.resolve(b));
+        assert_eq!(2, interner.len());
+        // 表を捨てると、その文字列も解放する
+        let text = Arc::clone(&interner.strings[a.0 as usize]);
+        drop(interner);
+        assert_eq!(1, Arc::strong_count(&text));
+    }
+
+    #[test]
+    fn test_symbol() {
+        let a = Symbol::intern("Hello, World!\n");
+        assert_eq!(a, Symbol::from("Hello, World!\n"));
+        assert_eq!("Hello, World!\n", &*a.text());
+        assert_eq!("\"Hello, World!\\n\"", format!("{:?}", a));
+        assert_eq!("Hello, World!\n", format!("{}", a));
+    }
+}
@@ -1,8 +1,10 @@
 use super::error::*;
+use super::float::Float;
+use super::symbol::Symbol;
 use std::fmt;
-use std::str::Chars;
+use std::str::{Chars, FromStr};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenKind {
     OpMultiply,
     OpDivide,
@@ -18,8 +20,22 @@ pub enum TokenKind {
     OpNotEqual,
     OpNot,
     OpAssign,
+    OpAddAssign,
+    OpSubtractAssign,
+    OpMultiplyAssign,
+    OpDivideAssign,
+    OpModAssign,
+    OpIncrement,
+    OpDecrement,
     OpAnd,
     OpOr,
+    OpBitAnd,
+    OpBitOr,
+    OpBitXor,
+    OpShiftLeft,
+    OpShiftRight,
+    /// ** (方言によっては ^) によるべき乗
+    OpPower,
     LeftParen,
     RightParen,
     LeftBrace,
@@ -29,14 +45,95 @@ pub enum TokenKind {
     KeywordIf,
     KeywordElse,
     KeywordWhile,
+    KeywordDo,
     KeywordPrint,
     KeywordPutc,
-    Identifier(String),
-    Integer(i32),
-    String(String),
+    KeywordRand,
+    KeywordClock,
+    /// getc() で入力から 1 文字読む
+    KeywordGetc,
+    /// geti() で入力から整数を読む
+    KeywordGeti,
+    KeywordPrintf,
+    KeywordExit,
+    KeywordAssert,
+    /// int(x) で浮動小数点数を整数にする
+    KeywordInt,
+    /// float(x) で整数を浮動小数点数にする
+    KeywordFloat,
+    Identifier(Symbol),
+    Integer(i64),
+    Float(Float),
+    String(Symbol),
+    /// `/*` と `*/` を含むコメント。トリビアを残す字句解析でだけ現れる。
+    /// 編集のたびに変わる文字列なので、 intern しない。
+    Comment(String),
+    /// 連続する空白文字。トリビアを残す字句解析でだけ現れる。
+    Whitespace(String),
     EndOfInput,
 }
 
+/// ソースでの綴り。キーワードは既定の綴り、値を持つ種類は値を書く。
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            TokenKind::OpMultiply => "*",
+            TokenKind::OpDivide => "/",
+            TokenKind::OpMod => "%",
+            TokenKind::OpAdd => "+",
+            TokenKind::OpSubtract => "-",
+            TokenKind::OpLess => "<",
+            TokenKind::OpLessEqual => "<=",
+            TokenKind::OpGreater => ">",
+            TokenKind::OpGreaterEqual => ">=",
+            TokenKind::OpEqual => "==",
+            TokenKind::OpNotEqual => "!=",
+            TokenKind::OpNot => "!",
+            TokenKind::OpAssign => "=",
+            TokenKind::OpAddAssign => "+=",
+            TokenKind::OpSubtractAssign => "-=",
+            TokenKind::OpMultiplyAssign => "*=",
+            TokenKind::OpDivideAssign => "/=",
+            TokenKind::OpModAssign => "%=",
+            TokenKind::OpIncrement => "++",
+            TokenKind::OpDecrement => "--",
+            TokenKind::OpAnd => "&&",
+            TokenKind::OpOr => "||",
+            TokenKind::OpBitAnd => "&",
+            TokenKind::OpBitOr => "|",
+            TokenKind::OpBitXor => "^",
+            TokenKind::OpShiftLeft => "<<",
+            TokenKind::OpShiftRight => ">>",
+            TokenKind::OpPower => "**",
+            TokenKind::LeftParen => "(",
+            TokenKind::RightParen => ")",
+            TokenKind::LeftBrace => "{",
+            TokenKind::RightBrace => "}",
+            TokenKind::Semicolon => ";",
+            TokenKind::Comma => ",",
+            TokenKind::KeywordIf => "if",
+            TokenKind::KeywordElse => "else",
+            TokenKind::KeywordWhile => "while",
+            TokenKind::KeywordDo => "do",
+            TokenKind::KeywordPrint => "print",
+            TokenKind::KeywordPutc => "putc",
+            TokenKind::KeywordRand => "rand",
+            TokenKind::KeywordClock => "clock",
+            TokenKind::KeywordGetc => "getc",
+            TokenKind::KeywordGeti => "geti",
+            TokenKind::KeywordPrintf => "printf",
+            TokenKind::KeywordExit => "exit",
+            TokenKind::KeywordAssert => "assert",
+            TokenKind::KeywordInt => "int",
+            TokenKind::KeywordFloat => "float",
+            TokenKind::Comment(s) | TokenKind::Whitespace(s) => s,
+            TokenKind::EndOfInput => "end of input",
+            _ => return write!(f, "{}", self.value().unwrap_or_default()),
+        };
+        write!(f, "{}", text)
+    }
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub kind: TokenKind,
@@ -61,14 +158,9 @@ impl<'a> TokenReader<'a> {
     }
 
     fn discard_whitespace(&mut self) {
-        loop {
-            match self.next_char {
-                Some(c) => {
-                    if !c.is_whitespace() {
-                        break;
-                    }
-                }
-                None => break,
+        while let Some(c) = self.next_char {
+            if !c.is_whitespace() {
+                break;
             }
             self.read_char();
         }
@@ -91,6 +183,23 @@ impl<'a> TokenReader<'a> {
         Ok(element)
     }
 
+    /// まだ読んでいない部分
+    fn rest(&self) -> String {
+        self.next_char
+            .map(|c| format!("{}{}", c, self.stream.as_str()))
+            .unwrap_or_default()
+    }
+
+    fn next_number<T: FromStr>(&mut self, name: &str) -> Result<T> {
+        let element = self.next_element()?;
+        element.parse().map_err(|_| {
+            CompileError::new(
+                ErrorKind::ReadError,
+                format!("invalid {}: {:?}", name, element),
+            )
+        })
+    }
+
     fn read_string(&mut self) -> Result<String> {
         self.discard_whitespace();
 
@@ -108,10 +217,9 @@ impl<'a> TokenReader<'a> {
                 }
                 Some('\\') => {
                     self.read_char();
-                    match self.next_char {
-                        Some('n') => s.push('\n'),
-                        Some('\\') => s.push('\\'),
-                        _ => {
+                    match self.next_char.and_then(escape::unescape_char) {
+                        Some(c) => s.push(c),
+                        None => {
                             return Err(CompileError::new(
                                 ErrorKind::ReadError,
                                 "unknown escape sequence",
@@ -132,6 +240,155 @@ impl<'a> TokenReader<'a> {
     }
 }
 
+/// トークン列の 1 行の書式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenFormat {
+    /// 行、列、種類、値を空白 1 つで区切る
+    #[default]
+    Plain,
+    /// タスクの出力例と同じく、行と列を右に揃え、値の位置も揃える。
+    /// 読むときは、他の実装の出力にある揃え方や種類の名前の書き方の違いを許す。
+    Compat,
+    /// 以前の Display の書式。 Plain と同じだが、一部の演算子の名前の前に
+    /// "Identifier " が付く。読むときは、その名前の識別子を演算子として読む。
+    Legacy,
+}
+
+/// 種類の名前の書き方の違いを除いたもの。大文字と小文字、 '_' を区別しない。
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|&c| c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// 種類とタスクで定められた名前の表から、 TokenKind::name と TokenKind::from_name、
+/// 名前の一覧 NAMES を作る。 Display と from_line はどちらもこの表を使う。
+macro_rules! token_names {
+    (
+        simple { $($simple:ident => $simple_name:literal,)* }
+        value { $($value:ident => $value_name:literal,)* }
+    ) => {
+        /// from_line が読む種類の名前
+        pub(crate) const NAMES: &[&str] = &[$($simple_name,)* $($value_name,)*];
+
+        impl TokenKind {
+            /// タスクで定められた種類の名前
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(TokenKind::$simple => $simple_name,)*
+                    $(TokenKind::$value(_) => $value_name,)*
+                }
+            }
+
+            /// 値を持たない種類を名前から作る。値を持つ種類や知らない名前なら None。
+            fn from_name(name: &str) -> Option<TokenKind> {
+                match name {
+                    $($simple_name => Some(TokenKind::$simple),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+token_names! {
+    simple {
+        OpMultiply => "Op_multiply",
+        OpDivide => "Op_divide",
+        OpMod => "Op_mod",
+        OpAdd => "Op_add",
+        OpSubtract => "Op_subtract",
+        OpLess => "Op_less",
+        OpLessEqual => "Op_lessequal",
+        OpGreater => "Op_greater",
+        OpGreaterEqual => "Op_greaterequal",
+        OpEqual => "Op_equal",
+        OpNotEqual => "Op_notequal",
+        OpNot => "Op_not",
+        OpAssign => "Op_assign",
+        OpAddAssign => "Op_addassign",
+        OpSubtractAssign => "Op_subtractassign",
+        OpMultiplyAssign => "Op_multiplyassign",
+        OpDivideAssign => "Op_divideassign",
+        OpModAssign => "Op_modassign",
+        OpIncrement => "Op_increment",
+        OpDecrement => "Op_decrement",
+        OpAnd => "Op_and",
+        OpOr => "Op_or",
+        OpBitAnd => "Op_bitand",
+        OpBitOr => "Op_bitor",
+        OpBitXor => "Op_bitxor",
+        OpShiftLeft => "Op_shiftleft",
+        OpShiftRight => "Op_shiftright",
+        OpPower => "Op_power",
+        LeftParen => "LeftParen",
+        RightParen => "RightParen",
+        LeftBrace => "LeftBrace",
+        RightBrace => "RightBrace",
+        Semicolon => "Semicolon",
+        Comma => "Comma",
+        KeywordIf => "Keyword_if",
+        KeywordElse => "Keyword_else",
+        KeywordWhile => "Keyword_while",
+        KeywordDo => "Keyword_do",
+        KeywordPrint => "Keyword_print",
+        KeywordPutc => "Keyword_putc",
+        KeywordRand => "Keyword_rand",
+        KeywordPrintf => "Keyword_printf",
+        KeywordExit => "Keyword_exit",
+        KeywordAssert => "Keyword_assert",
+        KeywordClock => "Keyword_clock",
+        KeywordGetc => "Keyword_getc",
+        KeywordGeti => "Keyword_geti",
+        KeywordInt => "Keyword_int",
+        KeywordFloat => "Keyword_float",
+        EndOfInput => "End_of_input",
+    }
+    value {
+        Integer => "Integer",
+        Float => "Float",
+        Identifier => "Identifier",
+        String => "String",
+        Comment => "Comment",
+        Whitespace => "Whitespace",
+    }
+}
+
+/// Legacy で、前に "Identifier " を付けて書いていた種類
+const LEGACY_IDENTIFIER_NAMES: &[&str] = &[
+    "Op_multiply",
+    "Op_divide",
+    "Op_mod",
+    "Op_add",
+    "Op_subtract",
+    "Op_less",
+    "Op_lessequal",
+    "Op_greater",
+];
+
+/// Compat で、種類の名前から値までの幅
+const COMPAT_NAME_WIDTH: usize = 20;
+
+impl TokenKind {
+    /// 空白とコメントなら true
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Comment(_) | TokenKind::Whitespace(_))
+    }
+
+    /// 種類の名前の後に書く値。値を持たない種類なら None。
+    fn value(&self) -> Option<String> {
+        match self {
+            TokenKind::Identifier(identifier) => Some(identifier.to_string()),
+            TokenKind::Integer(i) => Some(i.to_string()),
+            TokenKind::Float(value) => Some(value.to_string()),
+            TokenKind::String(s) => Some(escape::quote(&s.text())),
+            TokenKind::Comment(s) | TokenKind::Whitespace(s) => Some(escape::quote(s)),
+            _ => None,
+        }
+    }
+}
+
 impl Token {
     pub(crate) fn new(kind: TokenKind, line_number: usize, column_number: usize) -> Token {
         Token {
@@ -141,118 +398,104 @@ impl Token {
         }
     }
 
+    /// format の書式の 1 行を読む
+    pub fn from_line_with(line: &str, format: TokenFormat) -> Result<Token> {
+        match format {
+            TokenFormat::Plain => Token::from_line(line),
+            TokenFormat::Compat => {
+                let mut reader = TokenReader::new(line.trim().chars());
+                let line_number: usize = reader.next_number("line number")?;
+                let column_number: usize = reader.next_number("column number")?;
+                let name = reader.next_element()?;
+                let canonical = NAMES
+                    .iter()
+                    .find(|n| normalize_name(n) == normalize_name(&name))
+                    .ok_or_else(|| {
+                        CompileError::new(
+                            ErrorKind::ReadError,
+                            format!("unknown token kind: {}", name),
+                        )
+                    })?;
+                reader.discard_whitespace();
+                Token::from_line(&format!(
+                    "{} {} {} {}",
+                    line_number,
+                    column_number,
+                    canonical,
+                    reader.rest()
+                ))
+            }
+            TokenFormat::Legacy => {
+                let token = Token::from_line(line)?;
+                match token.kind {
+                    TokenKind::Identifier(ref identifier)
+                        if LEGACY_IDENTIFIER_NAMES.contains(&&*identifier.text()) =>
+                    {
+                        Ok(Token::new(
+                            TokenKind::from_name(&identifier.text()).unwrap(),
+                            token.line_number,
+                            token.column_number,
+                        ))
+                    }
+                    _ => Ok(token),
+                }
+            }
+        }
+    }
+
+    /// format の書式の 1 行。改行は含まない。
+    pub fn to_line(&self, format: TokenFormat) -> String {
+        match format {
+            TokenFormat::Plain => self.to_string(),
+            TokenFormat::Compat => {
+                let position = format!("{:>5}  {:>5}   ", self.line_number, self.column_number);
+                match self.kind.value() {
+                    Some(value) => format!(
+                        "{}{:<width$}{}",
+                        position,
+                        self.kind.name(),
+                        value,
+                        width = COMPAT_NAME_WIDTH
+                    ),
+                    None => format!("{}{}", position, self.kind.name()),
+                }
+            }
+            TokenFormat::Legacy if LEGACY_IDENTIFIER_NAMES.contains(&self.kind.name()) => format!(
+                "{} {} Identifier {}",
+                self.line_number,
+                self.column_number,
+                self.kind.name()
+            ),
+            TokenFormat::Legacy => self.to_string(),
+        }
+    }
+
     pub fn from_line(line: &str) -> Result<Token> {
         let cs = line.trim().chars();
 
         let mut reader = TokenReader::new(cs);
-        let buf = reader.next_element()?;
-        let line_number: usize = buf.parse().unwrap();
+        let line_number: usize = reader.next_number("line number")?;
+        let column_number: usize = reader.next_number("column number")?;
 
         let buf = reader.next_element()?;
-        let column_number: usize = buf.parse().unwrap();
-
-        let buf = reader.next_element()?;
-        match &buf[..] {
-            "Op_multiply" => Ok(Token::new(
-                TokenKind::OpMultiply,
-                line_number,
-                column_number,
-            )),
-            "Op_divide" => Ok(Token::new(TokenKind::OpDivide, line_number, column_number)),
-            "Op_mod" => Ok(Token::new(TokenKind::OpMod, line_number, column_number)),
-            "Op_add" => Ok(Token::new(TokenKind::OpAdd, line_number, column_number)),
-            "Op_subtract" => Ok(Token::new(
-                TokenKind::OpSubtract,
-                line_number,
-                column_number,
-            )),
-            "Op_less" => Ok(Token::new(TokenKind::OpLess, line_number, column_number)),
-            "Op_lessequal" => Ok(Token::new(
-                TokenKind::OpLessEqual,
-                line_number,
-                column_number,
-            )),
-            "Op_greater" => Ok(Token::new(TokenKind::OpGreater, line_number, column_number)),
-            "Op_greaterequal" => Ok(Token::new(
-                TokenKind::OpGreaterEqual,
-                line_number,
-                column_number,
-            )),
-            "Op_equal" => Ok(Token::new(TokenKind::OpEqual, line_number, column_number)),
-            "Op_notequal" => Ok(Token::new(
-                TokenKind::OpNotEqual,
-                line_number,
-                column_number,
-            )),
-            "Op_not" => Ok(Token::new(TokenKind::OpNot, line_number, column_number)),
-            "Op_assign" => Ok(Token::new(TokenKind::OpAssign, line_number, column_number)),
-            "Op_and" => Ok(Token::new(TokenKind::OpAnd, line_number, column_number)),
-            "Op_or" => Ok(Token::new(TokenKind::OpOr, line_number, column_number)),
-            "LeftParen" => Ok(Token::new(TokenKind::LeftParen, line_number, column_number)),
-            "RightParen" => Ok(Token::new(
-                TokenKind::RightParen,
-                line_number,
-                column_number,
-            )),
-            "LeftBrace" => Ok(Token::new(TokenKind::LeftBrace, line_number, column_number)),
-            "RightBrace" => Ok(Token::new(
-                TokenKind::RightBrace,
-                line_number,
-                column_number,
-            )),
-            "Semicolon" => Ok(Token::new(TokenKind::Semicolon, line_number, column_number)),
-            "Comma" => Ok(Token::new(TokenKind::Comma, line_number, column_number)),
-            "Keyword_if" => Ok(Token::new(TokenKind::KeywordIf, line_number, column_number)),
-            "Keyword_else" => Ok(Token::new(
-                TokenKind::KeywordElse,
-                line_number,
-                column_number,
-            )),
-            "Keyword_while" => Ok(Token::new(
-                TokenKind::KeywordWhile,
-                line_number,
-                column_number,
-            )),
-            "Keyword_print" => Ok(Token::new(
-                TokenKind::KeywordPrint,
-                line_number,
-                column_number,
-            )),
-            "Keyword_putc" => Ok(Token::new(
-                TokenKind::KeywordPutc,
-                line_number,
-                column_number,
-            )),
-            "Integer" => {
-                let i = reader.next_element()?.parse().unwrap();
-                Ok(Token::new(
-                    TokenKind::Integer(i),
-                    line_number,
-                    column_number,
-                ))
-            }
-            "Identifier" => {
-                let identifier = reader.next_element()?;
-                Ok(Token::new(
-                    TokenKind::Identifier(identifier),
-                    line_number,
-                    column_number,
+        if let Some(kind) = TokenKind::from_name(&buf) {
+            return Ok(Token::new(kind, line_number, column_number));
+        }
+        let kind = match &buf[..] {
+            "Integer" => TokenKind::Integer(reader.next_number("integer")?),
+            "Float" => TokenKind::Float(Float(reader.next_number("float")?)),
+            "Identifier" => TokenKind::Identifier(Symbol::intern(&reader.next_element()?)),
+            "String" => TokenKind::String(Symbol::intern(&reader.read_string()?)),
+            "Comment" => TokenKind::Comment(reader.read_string()?),
+            "Whitespace" => TokenKind::Whitespace(reader.read_string()?),
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::ReadError,
+                    format!("unknown token kind: {}", buf),
                 ))
             }
-            "String" => {
-                let s = reader.read_string()?;
-                Ok(Token::new(TokenKind::String(s), line_number, column_number))
-            }
-            "End_of_input" => Ok(Token::new(
-                TokenKind::EndOfInput,
-                line_number,
-                column_number,
-            )),
-            _ => Err(CompileError::new(
-                ErrorKind::ReadError,
-                format!("unknown token kind: {}", buf),
-            )),
-        }
+        };
+        Ok(Token::new(kind, line_number, column_number))
     }
 
     pub fn kind(&self) -> &TokenKind {
@@ -268,130 +511,19 @@ impl Token {
     }
 }
 
+/// TokenFormat::Plain の書式
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.kind {
-            TokenKind::OpMultiply => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_multiply"
-            ),
-            TokenKind::OpDivide => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_divide"
-            ),
-            TokenKind::OpMod => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_mod"
-            ),
-            TokenKind::OpAdd => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_add"
-            ),
-            TokenKind::OpSubtract => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_subtract"
-            ),
-            TokenKind::OpLess => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_less"
-            ),
-            TokenKind::OpLessEqual => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_lessequal"
-            ),
-            TokenKind::OpGreater => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, "Op_greater"
-            ),
-            TokenKind::OpGreaterEqual => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Op_greaterequal"
-            ),
-            TokenKind::OpEqual => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Op_equal"
-            ),
-            TokenKind::OpNotEqual => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Op_notequal"
-            ),
-            TokenKind::OpNot => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Op_not"
-            ),
-            TokenKind::OpAssign => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Op_assign"
-            ),
-            TokenKind::OpAnd => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Op_and"
-            ),
-            TokenKind::OpOr => write!(f, "{} {} {}", self.line_number, self.column_number, "Op_or"),
-            TokenKind::KeywordIf => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Keyword_if"
-            ),
-            TokenKind::KeywordElse => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Keyword_else"
-            ),
-            TokenKind::KeywordWhile => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Keyword_while"
-            ),
-            TokenKind::KeywordPrint => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Keyword_print"
-            ),
-            TokenKind::KeywordPutc => write!(
-                f,
-                "{} {} {}",
-                self.line_number, self.column_number, "Keyword_putc"
-            ),
-            TokenKind::Identifier(ref identifier) => write!(
-                f,
-                "{} {} Identifier {}",
-                self.line_number, self.column_number, identifier
-            ),
-            TokenKind::Integer(val) => write!(
-                f,
-                "{} {} Integer {}",
-                self.line_number, self.column_number, val
-            ),
-            TokenKind::String(ref s) => write!(
-                f,
-                "{} {} String {:?}",
-                self.line_number, self.column_number, s
-            ),
-            TokenKind::EndOfInput => write!(
-                f,
-                "{} {} End_of_input",
-                self.line_number, self.column_number
-            ),
-            _ => write!(
-                f,
-                "{} {} {:?}",
-                self.line_number, self.column_number, self.kind
-            ),
+        write!(
+            f,
+            "{} {} {}",
+            self.line_number,
+            self.column_number,
+            self.kind.name()
+        )?;
+        match self.kind.value() {
+            Some(value) => write!(f, " {}", value),
+            None => Ok(()),
         }
     }
 }
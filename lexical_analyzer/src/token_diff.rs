@@ -0,0 +1,140 @@
+use super::error::*;
+use super::token::Token;
+
+use std::fmt;
+
+/// トークンの位置の比べ方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Positions {
+    /// 行と列を比べる
+    Exact,
+    /// 行だけを比べる
+    Lines,
+    /// 比べない
+    Ignore,
+}
+
+/// 一覧の 1 行。読み込んだトークンと、一覧の何行目かを持つ。
+#[derive(Debug)]
+pub struct Entry {
+    /// 1 から始まる一覧の行数
+    pub listing_line: usize,
+    pub text: String,
+    pub token: Token,
+}
+
+/// 2 つの一覧で最初に意味が食い違ったトークン。一方が先に終わった場合は None。
+#[derive(Debug)]
+pub struct Divergence {
+    /// 0 から始まるトークンの番号
+    pub index: usize,
+    pub left: Option<Entry>,
+    pub right: Option<Entry>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "token {} differs", self.index)?;
+        for (name, entry) in &[("left", &self.left), ("right", &self.right)] {
+            match entry {
+                Some(e) => writeln!(f, "  {} line {}: {}", name, e.listing_line, e.text.trim())?,
+                None => writeln!(f, "  {}: end of listing", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// トークン一覧を読み込む。空行は読み飛ばす。
+pub fn read_listing(listing: &str) -> Result<Vec<Entry>> {
+    listing
+        .lines()
+        .enumerate()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(i, text)| {
+            let token = Token::from_line(text).map_err(|e| {
+                CompileError::new(ErrorKind::ReadError, format!("line {}: {}", i + 1, e))
+            })?;
+            Ok(Entry {
+                listing_line: i + 1,
+                text: text.to_string(),
+                token,
+            })
+        })
+        .collect()
+}
+
+fn same(left: &Token, right: &Token, positions: Positions) -> bool {
+    left.kind() == right.kind()
+        && match positions {
+            Positions::Exact => {
+                left.line_number() == right.line_number()
+                    && left.column_number() == right.column_number()
+            }
+            Positions::Lines => left.line_number() == right.line_number(),
+            Positions::Ignore => true,
+        }
+}
+
+/// 2 つのトークン一覧を比べ、最初の食い違いを返す。
+/// 空白の違いは無視し、位置は positions に従って比べる。
+pub fn diff_listings(left: &str, right: &str, positions: Positions) -> Result<Option<Divergence>> {
+    let mut left = read_listing(left)?.into_iter();
+    let mut right = read_listing(right)?.into_iter();
+    let mut index = 0;
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return Ok(None),
+            (Some(l), Some(r)) if same(&l.token, &r.token, positions) => index += 1,
+            (l, r) => {
+                return Ok(Some(Divergence {
+                    index,
+                    left: l,
+                    right: r,
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_listings() {
+        let left = "1 1 Identifier count\n1 7 Op_assign\n1 9 Integer 1\n2 1 End_of_input\n";
+        // 空白と空行の違いは無視する
+        let right = "    1      1 Identifier     count\n\n    1      7 Op_assign\n    1      9 Integer             1\n    2      1 End_of_input\n";
+        assert!(diff_listings(left, right, Positions::Exact)
+            .unwrap()
+            .is_none());
+
+        let right = "1 1 Identifier count\n1 8 Op_assign\n1 9 Integer 1\n2 1 End_of_input\n";
+        let divergence = diff_listings(left, right, Positions::Exact)
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, divergence.index);
+        assert_eq!(2, divergence.right.unwrap().listing_line);
+        assert!(diff_listings(left, right, Positions::Lines)
+            .unwrap()
+            .is_none());
+
+        let right = "3 3 Identifier count\n3 3 Op_assign\n3 3 Integer 2\n3 3 End_of_input\n";
+        let divergence = diff_listings(left, right, Positions::Ignore)
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, divergence.index);
+
+        // 一方が先に終わる
+        let right = "1 1 Identifier count\n";
+        let divergence = diff_listings(left, right, Positions::Exact)
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, divergence.index);
+        assert!(divergence.left.is_some());
+        assert!(divergence.right.is_none());
+
+        assert!(diff_listings(left, "1 1 Unknown\n", Positions::Exact).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use super::token::{Token, TokenKind};
+
+/// 前後の空白とコメントを付けたトークン
+#[derive(Debug)]
+pub struct TriviaToken {
+    /// トークンより前で、前のトークンの trailing にならなかったもの
+    pub leading: Vec<Token>,
+    pub token: Token,
+    /// トークンの後から、改行を含む最初のトリビアの前まで
+    pub trailing: Vec<Token>,
+}
+
+/// トリビアを残して字句解析したトークン列の、空白とコメントを前後のトークンに付ける。
+/// 行末までのトリビアは前のトークンの trailing に、それより後は次のトークンの leading になる。
+/// 最後のトークンは EndOfInput で、ファイルの終わりのコメントはその leading になる。
+pub fn attach_trivia(tokens: Vec<Token>) -> Vec<TriviaToken> {
+    let mut attached: Vec<TriviaToken> = Vec::new();
+    let mut leading = Vec::new();
+    // 前のトークンの trailing に付けている途中なら true
+    let mut trailing = false;
+    for token in tokens {
+        let text = match token.kind() {
+            TokenKind::Comment(text) | TokenKind::Whitespace(text) => text,
+            _ => {
+                attached.push(TriviaToken {
+                    leading: std::mem::take(&mut leading),
+                    token,
+                    trailing: Vec::new(),
+                });
+                trailing = true;
+                continue;
+            }
+        };
+        if text.contains('\n') {
+            trailing = false;
+        }
+        match attached.last_mut() {
+            Some(previous) if trailing => previous.trailing.push(token),
+            _ => leading.push(token),
+        }
+    }
+    attached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::lex_all_with_trivia;
+
+    #[test]
+    fn test_attach_trivia() {
+        let src = "/* head */\nx = 1; /* one */ \n\n  /* two\n */ print(x);\n/* tail */";
+        let tokens = lex_all_with_trivia(src, &Dialect::default()).unwrap();
+        let text = |tokens: &[Token]| {
+            tokens
+                .iter()
+                .map(|token| match token.kind() {
+                    TokenKind::Comment(s) | TokenKind::Whitespace(s) => s.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect::<String>()
+        };
+        let attached: Vec<(String, String, String)> = attach_trivia(tokens)
+            .iter()
+            .map(|t| {
+                (
+                    text(&t.leading),
+                    format!("{:?}", t.token.kind()),
+                    text(&t.trailing),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "/* head */\n".into(),
+                    "Identifier(\"x\")".into(),
+                    " ".into()
+                ),
+                ("".into(), "OpAssign".into(), " ".into()),
+                ("".into(), "Integer(1)".into(), "".into()),
+                ("".into(), "Semicolon".into(), " /* one */".to_string()),
+                (
+                    " \n\n  /* two\n */ ".into(),
+                    "KeywordPrint".into(),
+                    "".into()
+                ),
+                ("".into(), "LeftParen".into(), "".into()),
+                ("".into(), "Identifier(\"x\")".into(), "".into()),
+                ("".into(), "RightParen".into(), "".into()),
+                ("".into(), "Semicolon".into(), "".into()),
+                ("\n/* tail */".into(), "EndOfInput".into(), "".into()),
+            ],
+            attached
+        );
+    }
+}
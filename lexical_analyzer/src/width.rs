@@ -0,0 +1,182 @@
+use std::fmt;
+
+/// 整数の値の幅。トークンや AST は i64 で値を持ち、演算の結果をこの幅で折り返す。
+/// タスクの出力と合わせるため、既定は 32 ビット。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Width {
+    #[default]
+    W32,
+    /// 大きな階乗などが桁あふれしないように、 64 ビットで計算する
+    W64,
+}
+
+impl Width {
+    pub fn bits(self) -> u32 {
+        match self {
+            Width::W32 => 32,
+            Width::W64 => 64,
+        }
+    }
+
+    /// ビット数から幅を返す。 32 か 64 のいずれか。
+    pub fn from_bits(bits: u32) -> Option<Width> {
+        match bits {
+            32 => Some(Width::W32),
+            64 => Some(Width::W64),
+            _ => None,
+        }
+    }
+
+    pub fn min(self) -> i64 {
+        match self {
+            Width::W32 => i32::MIN as i64,
+            Width::W64 => i64::MIN,
+        }
+    }
+
+    pub fn max(self) -> i64 {
+        match self {
+            Width::W32 => i32::MAX as i64,
+            Width::W64 => i64::MAX,
+        }
+    }
+
+    /// value がこの幅で表せるなら true
+    pub fn contains(self, value: i64) -> bool {
+        (self.min()..=self.max()).contains(&value)
+    }
+
+    /// value の下位のビットだけを残し、この幅の符号付き整数にする
+    pub fn wrap(self, value: i64) -> i64 {
+        match self {
+            Width::W32 => value as i32 as i64,
+            Width::W64 => value,
+        }
+    }
+
+    pub fn add(self, a: i64, b: i64) -> i64 {
+        self.wrap(a.wrapping_add(b))
+    }
+
+    pub fn sub(self, a: i64, b: i64) -> i64 {
+        self.wrap(a.wrapping_sub(b))
+    }
+
+    pub fn mul(self, a: i64, b: i64) -> i64 {
+        self.wrap(a.wrapping_mul(b))
+    }
+
+    pub fn neg(self, a: i64) -> i64 {
+        self.wrap(a.wrapping_neg())
+    }
+
+    /// 0 で割ると None。最小値を -1 で割ると折り返して最小値になる。
+    pub fn div(self, a: i64, b: i64) -> Option<i64> {
+        if b == 0 {
+            None
+        } else {
+            Some(self.wrap(a.wrapping_div(b)))
+        }
+    }
+
+    /// 0 で割ると None
+    pub fn rem(self, a: i64, b: i64) -> Option<i64> {
+        if b == 0 {
+            None
+        } else {
+            Some(self.wrap(a.wrapping_rem(b)))
+        }
+    }
+
+    /// a の b 乗。繰り返し二乗法で計算し、あふれたら折り返す。
+    /// 負の指数では整数の除算と同じく 0 の方向に丸め、 0 の負の乗は 0 除算として None。
+    pub fn pow(self, a: i64, b: i64) -> Option<i64> {
+        if b < 0 {
+            return match a {
+                0 => None,
+                1 => Some(1),
+                -1 => Some(if b % 2 == 0 { 1 } else { -1 }),
+                _ => Some(0),
+            };
+        }
+        let mut result: i64 = 1;
+        let mut base = a;
+        let mut exponent = b;
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.wrapping_mul(base);
+            }
+            base = base.wrapping_mul(base);
+            exponent >>= 1;
+        }
+        Some(self.wrap(result))
+    }
+
+    /// シフト量は幅のビット数で割った余りを使う
+    pub fn shl(self, a: i64, b: i64) -> i64 {
+        match self {
+            Width::W32 => (a as i32).wrapping_shl(b as u32) as i64,
+            Width::W64 => a.wrapping_shl(b as u32),
+        }
+    }
+
+    /// 算術シフト。シフト量は幅のビット数で割った余りを使う。
+    pub fn shr(self, a: i64, b: i64) -> i64 {
+        match self {
+            Width::W32 => (a as i32).wrapping_shr(b as u32) as i64,
+            Width::W64 => a.wrapping_shr(b as u32),
+        }
+    }
+}
+
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.bits())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(i32::MIN as i64, Width::W32.add(i32::MAX as i64, 1));
+        assert_eq!(1 << 31, Width::W64.add(i32::MAX as i64, 1));
+        assert_eq!(0, Width::W32.mul(65536, 65536));
+        assert_eq!(1 << 32, Width::W64.mul(65536, 65536));
+        assert_eq!(Some(i32::MIN as i64), Width::W32.div(i32::MIN as i64, -1));
+        assert_eq!(Some(i64::MIN), Width::W64.div(i64::MIN, -1));
+        assert_eq!(None, Width::W64.rem(1, 0));
+        assert_eq!(2, Width::W32.shl(1, 33));
+        assert_eq!(1 << 33, Width::W64.shl(1, 33));
+        assert_eq!(-4, Width::W64.shr(-16, 2));
+    }
+
+    #[test]
+    fn test_pow() {
+        assert_eq!(Some(1024), Width::W32.pow(2, 10));
+        assert_eq!(Some(-27), Width::W32.pow(-3, 3));
+        assert_eq!(Some(1), Width::W32.pow(0, 0));
+        // 2 の 32 乗は 32 ビットでは 0 に折り返す
+        assert_eq!(Some(0), Width::W32.pow(2, 32));
+        assert_eq!(Some(1 << 32), Width::W64.pow(2, 32));
+        assert_eq!(Some(i32::MIN as i64), Width::W32.pow(2, 31));
+        assert_eq!(Some(3i64.pow(20) as i32 as i64), Width::W32.pow(3, 20));
+        // 負の指数
+        assert_eq!(Some(0), Width::W32.pow(2, -1));
+        assert_eq!(Some(-1), Width::W32.pow(-1, -3));
+        assert_eq!(Some(1), Width::W32.pow(1, -5));
+        assert_eq!(None, Width::W32.pow(0, -1));
+    }
+
+    #[test]
+    fn test_range() {
+        assert!(Width::W32.contains(i32::MIN as i64));
+        assert!(!Width::W32.contains(i32::MAX as i64 + 1));
+        assert!(Width::W64.contains(i64::MAX));
+        assert_eq!(Some(Width::W64), Width::from_bits(64));
+        assert_eq!(None, Width::from_bits(16));
+        assert_eq!("32", Width::default().to_string());
+    }
+}
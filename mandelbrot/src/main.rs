@@ -0,0 +1,241 @@
+use bitmap::{Bitmap, Color};
+use std::fs::File;
+use std::io::BufWriter;
+
+/// 描く範囲 (複素平面上の左下と右上)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Area {
+    re_min: f64,
+    im_min: f64,
+    re_max: f64,
+    im_max: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Palette {
+    Gray,
+    Hsv,
+    Fire,
+}
+
+impl Palette {
+    /// 発散までの反復回数から色を決める。発散しない点は黒。
+    fn color(self, iterations: Option<u32>, max_iterations: u32) -> Color {
+        let n = match iterations {
+            Some(n) => n,
+            None => return Color::BLACK,
+        };
+        let t = n as f64 / max_iterations as f64;
+        match self {
+            Palette::Gray => {
+                let v = (t.sqrt() * 255.0).round() as u8;
+                Color::new(v, v, v)
+            }
+            Palette::Hsv => Color::from_hsv(360.0 * t, 1.0, 1.0),
+            Palette::Fire => {
+                let channel = |offset: f64| ((t * 3.0 - offset).clamp(0.0, 1.0) * 255.0) as u8;
+                Color::new(channel(0.0), channel(1.0), channel(2.0))
+            }
+        }
+    }
+}
+
+struct Options {
+    width: usize,
+    height: usize,
+    max_iterations: u32,
+    area: Area,
+    palette: Palette,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 800,
+            height: 600,
+            max_iterations: 256,
+            area: Area {
+                re_min: -2.5,
+                im_min: -1.25,
+                re_max: 1.0,
+                im_max: 1.25,
+            },
+            palette: Palette::Hsv,
+        }
+    }
+}
+
+/// c が発散するまでの反復回数。max_iterations 回で発散しなければ None。
+fn escape_time(re: f64, im: f64, max_iterations: u32) -> Option<u32> {
+    let (mut zr, mut zi) = (0.0, 0.0);
+    for n in 0..max_iterations {
+        if zr * zr + zi * zi > 4.0 {
+            return Some(n);
+        }
+        let t = zr * zr - zi * zi + re;
+        zi = 2.0 * zr * zi + im;
+        zr = t;
+    }
+    None
+}
+
+fn render(options: &Options) -> Bitmap {
+    let mut bmp = Bitmap::new(options.width, options.height);
+    let area = options.area;
+    let dx = (area.re_max - area.re_min) / options.width as f64;
+    let dy = (area.im_max - area.im_min) / options.height as f64;
+    for y in 0..options.height {
+        // 画像の上が虚部の大きい側
+        let im = area.im_max - (y as f64 + 0.5) * dy;
+        for x in 0..options.width {
+            let re = area.re_min + (x as f64 + 0.5) * dx;
+            let n = escape_time(re, im, options.max_iterations);
+            *bmp.color_mut(x, y) = options.palette.color(n, options.max_iterations);
+        }
+    }
+    bmp
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut i = 0;
+    while i < args.len() {
+        let value = args
+            .get(i + 1)
+            .ok_or_else(|| format!("{} requires an argument", args[i]))?;
+        let number = |v: &str| {
+            v.parse::<f64>()
+                .map_err(|_| format!("invalid number: {}", v))
+        };
+        match args[i].as_str() {
+            "--size" => {
+                let (w, h) = value
+                    .split_once('x')
+                    .ok_or_else(|| format!("invalid size: {}", value))?;
+                options.width = w.parse().map_err(|_| format!("invalid size: {}", value))?;
+                options.height = h.parse().map_err(|_| format!("invalid size: {}", value))?;
+            }
+            "--iterations" => {
+                options.max_iterations = value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid iterations: {}", value))?;
+            }
+            "--area" => {
+                let v = value
+                    .split(',')
+                    .map(number)
+                    .collect::<Result<Vec<f64>, String>>()?;
+                if v.len() != 4 || v[0] >= v[2] || v[1] >= v[3] {
+                    return Err(format!("invalid area: {}", value));
+                }
+                options.area = Area {
+                    re_min: v[0],
+                    im_min: v[1],
+                    re_max: v[2],
+                    im_max: v[3],
+                };
+            }
+            "--palette" => {
+                options.palette = match value.as_str() {
+                    "gray" => Palette::Gray,
+                    "hsv" => Palette::Hsv,
+                    "fire" => Palette::Fire,
+                    _ => return Err(format!("unknown palette: {}", value)),
+                };
+            }
+            _ => return Err(format!("unknown option: {}", args[i])),
+        }
+        i += 2;
+    }
+    Ok(options)
+}
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [options] filename", name);
+    println!();
+    println!("    --size WxH                          image size (default: 800x600)");
+    println!("    --iterations n                      maximum iterations (default: 256)");
+    println!("    --area re_min,im_min,re_max,im_max  area to render (default: -2.5,-1.25,1,1.25)");
+    println!("    --palette <gray|hsv|fire>           color palette (default: hsv)");
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        print_usage(&args[0]);
+        return;
+    }
+
+    let filename = &args[args.len() - 1];
+    let options = match parse_options(&args[1..args.len() - 1]) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let bmp = render(&options);
+    let f = File::create(filename).unwrap_or_else(|_| panic!("{} cannot be created.", filename));
+    bmp.write_ppm(&mut BufWriter::new(f))
+        .expect("write PPM failed.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_time() {
+        assert_eq!(None, escape_time(0.0, 0.0, 100));
+        assert_eq!(None, escape_time(-1.0, 0.0, 100));
+        assert_eq!(Some(1), escape_time(2.0, 2.0, 100));
+        assert_eq!(Some(3), escape_time(1.0, 0.0, 100));
+    }
+
+    #[test]
+    fn test_render() {
+        let options = Options {
+            width: 7,
+            height: 5,
+            max_iterations: 50,
+            palette: Palette::Gray,
+            ..Options::default()
+        };
+        let bmp = render(&options);
+        // 中央付近は集合の内側、角は外側
+        assert_eq!(Color::BLACK, *bmp.color(4, 2));
+        assert_ne!(Color::BLACK, *bmp.color(0, 0));
+        // 実軸に関して対称
+        for x in 0..7 {
+            assert_eq!(bmp.color(x, 0), bmp.color(x, 4));
+            assert_eq!(bmp.color(x, 1), bmp.color(x, 3));
+        }
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args: Vec<String> = [
+            "--size",
+            "40x30",
+            "--area",
+            "-1,-1,1,1",
+            "--palette",
+            "fire",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let options = parse_options(&args).unwrap();
+        assert_eq!((40, 30), (options.width, options.height));
+        assert_eq!(Palette::Fire, options.palette);
+        assert_eq!(2.0, options.area.re_max - options.area.re_min);
+
+        let args = vec!["--area".to_string(), "1,1,0,0".to_string()];
+        assert!(parse_options(&args).is_err());
+        let args = vec!["--iterations".to_string()];
+        assert!(parse_options(&args).is_err());
+    }
+}
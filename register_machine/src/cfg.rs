@@ -0,0 +1,584 @@
+//! 基本ブロックの制御フローグラフ。
+//! AST からは文と条件式を、命令列からは命令の添字を要素とするグラフを作る。
+
+use super::Instruction;
+use syntax_analyzer::ast_node::*;
+use syntax_analyzer::unparser::{unparse, unparse_expr};
+use virtual_machine_interpreter::DecodedOp;
+
+use std::fmt::Write;
+
+/// ブロックの番号。 blocks の添字。
+pub type BlockId = usize;
+
+/// 途中に分岐も合流もない要素の並び
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock<T> {
+    pub items: Vec<T>,
+    /// 条件分岐で終わるブロックでは、条件が 0 でないときの後続が先
+    successors: Vec<BlockId>,
+    predecessors: Vec<BlockId>,
+}
+
+impl<T> BasicBlock<T> {
+    pub fn successors(&self) -> &[BlockId] {
+        &self.successors
+    }
+
+    pub fn predecessors(&self) -> &[BlockId] {
+        &self.predecessors
+    }
+}
+
+/// 制御フローグラフ。ブロック 0 が入口で、後続のないブロックが出口になる。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlFlowGraph<T> {
+    blocks: Vec<BasicBlock<T>>,
+}
+
+impl<T> ControlFlowGraph<T> {
+    fn new() -> Self {
+        let mut graph = ControlFlowGraph { blocks: Vec::new() };
+        graph.new_block();
+        graph
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock {
+            items: Vec::new(),
+            successors: Vec::new(),
+            predecessors: Vec::new(),
+        });
+        self.blocks.len() - 1
+    }
+
+    fn add_edge(&mut self, from: BlockId, to: BlockId) {
+        self.blocks[from].successors.push(to);
+        self.blocks[to].predecessors.push(from);
+    }
+
+    pub fn entry(&self) -> BlockId {
+        0
+    }
+
+    pub fn blocks(&self) -> &[BasicBlock<T>] {
+        &self.blocks
+    }
+
+    pub fn block(&self, id: BlockId) -> &BasicBlock<T> {
+        &self.blocks[id]
+    }
+
+    pub fn successors(&self, id: BlockId) -> &[BlockId] {
+        &self.blocks[id].successors
+    }
+
+    pub fn predecessors(&self, id: BlockId) -> &[BlockId] {
+        &self.blocks[id].predecessors
+    }
+
+    /// 入口からたどれるブロックなら true
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![self.entry()];
+        while let Some(id) = stack.pop() {
+            if !reachable[id] {
+                reachable[id] = true;
+                stack.extend(self.successors(id).iter().copied());
+            }
+        }
+        reachable
+    }
+
+    /// 入口からたどれない、要素のあるブロック
+    pub fn unreachable_blocks(&self) -> Vec<BlockId> {
+        self.reachable()
+            .iter()
+            .enumerate()
+            .filter(|&(id, &reachable)| !reachable && !self.blocks[id].items.is_empty())
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Graphviz の DOT 形式にする。要素は label で 1 行ずつの文字列にする。
+    pub fn to_dot<F>(&self, label: F) -> String
+    where
+        F: Fn(&T) -> String,
+    {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box];\n");
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut text = format!("B{}\\l", id);
+            for item in &block.items {
+                text.push_str(&escape(&label(item)));
+                text.push_str("\\l");
+            }
+            writeln!(dot, "    b{} [label=\"{}\"];", id, text).unwrap();
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                writeln!(dot, "    b{} -> b{};", id, successor).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// DOT の文字列の中で使えるようにする
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\l"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// 命令列の命令の、制御の移り方
+pub trait Branch {
+    /// ジャンプ先の命令の添字
+    fn target(&self) -> Option<usize>;
+    /// 次の命令に進むことがあるなら true。呼び出しから戻って進むのは含めない。
+    fn falls_through(&self) -> bool;
+    /// 呼び出す関数の先頭の命令の添字。関数から戻ると次の命令に進む。
+    fn call(&self) -> Option<usize> {
+        None
+    }
+    /// 呼び出し元に戻る命令なら true
+    fn returns(&self) -> bool {
+        false
+    }
+}
+
+impl Branch for Instruction {
+    fn target(&self) -> Option<usize> {
+        match *self {
+            Instruction::Jump(target) | Instruction::JumpIfZero(_, target) => Some(target),
+            _ => None,
+        }
+    }
+
+    fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Instruction::Jump(_) | Instruction::Halt | Instruction::Exit(_)
+        )
+    }
+}
+
+impl Branch for DecodedOp {
+    fn target(&self) -> Option<usize> {
+        match *self {
+            DecodedOp::Jmp(target) | DecodedOp::Jz(target) | DecodedOp::TailCall(target, _) => {
+                Some(target)
+            }
+            _ => None,
+        }
+    }
+
+    fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            DecodedOp::Jmp(_)
+                | DecodedOp::Call(_)
+                | DecodedOp::TailCall(..)
+                | DecodedOp::Ret
+                | DecodedOp::Halt
+                | DecodedOp::Exit
+        )
+    }
+
+    fn call(&self) -> Option<usize> {
+        match *self {
+            DecodedOp::Call(target) => Some(target),
+            _ => None,
+        }
+    }
+
+    fn returns(&self) -> bool {
+        *self == DecodedOp::Ret
+    }
+}
+
+impl ControlFlowGraph<usize> {
+    /// 命令列からグラフを作る。ブロックの要素は命令の添字で、ブロックは命令の順に並ぶ。
+    /// 呼び出しは関数の先頭へ、関数から戻る命令はその関数を呼んだ命令の次へつなぐ。
+    pub fn from_instructions<I: Branch>(instructions: &[I]) -> Self {
+        let valid = |index: &usize| *index < instructions.len();
+        // ジャンプ先と呼び出し先、分岐や停止の次の命令がブロックの先頭になる
+        let mut leader = vec![false; instructions.len()];
+        for (index, instruction) in instructions.iter().enumerate() {
+            for target in instruction.target().into_iter().chain(instruction.call()) {
+                if valid(&target) {
+                    leader[target] = true;
+                }
+            }
+            if (instruction.target().is_some() || !instruction.falls_through())
+                && index + 1 < instructions.len()
+            {
+                leader[index + 1] = true;
+            }
+        }
+
+        let mut graph = ControlFlowGraph::new();
+        let mut block_of = vec![0; instructions.len()];
+        for index in 0..instructions.len() {
+            if leader[index] && index > 0 {
+                graph.new_block();
+            }
+            block_of[index] = graph.blocks.len() - 1;
+            graph.blocks.last_mut().unwrap().items.push(index);
+        }
+
+        // 関数の中のつながり。呼び出しは関数に入らずに次の命令へ進むものとする
+        let mut local: Vec<Vec<BlockId>> = Vec::with_capacity(graph.blocks.len());
+        // (呼び出し先のブロック, 戻り先のブロック)
+        let mut calls = Vec::new();
+        for id in 0..graph.blocks.len() {
+            let mut edges = Vec::new();
+            if let Some(&last) = graph.blocks[id].items.last() {
+                let instruction = &instructions[last];
+                if instruction.falls_through() && last + 1 < instructions.len() {
+                    graph.add_edge(id, block_of[last + 1]);
+                    edges.push(block_of[last + 1]);
+                }
+                if let Some(target) = instruction.target().filter(valid) {
+                    graph.add_edge(id, block_of[target]);
+                    edges.push(block_of[target]);
+                }
+                if let Some(callee) = instruction.call().filter(valid) {
+                    graph.add_edge(id, block_of[callee]);
+                    if last + 1 < instructions.len() {
+                        edges.push(block_of[last + 1]);
+                        calls.push((block_of[callee], block_of[last + 1]));
+                    }
+                }
+            }
+            local.push(edges);
+        }
+
+        // 呼び出し先から関数の中でたどれる、戻る命令のブロックを戻り先につなぐ
+        for (callee, return_to) in calls {
+            let mut visited = vec![false; graph.blocks.len()];
+            let mut stack = vec![callee];
+            while let Some(id) = stack.pop() {
+                if visited[id] {
+                    continue;
+                }
+                visited[id] = true;
+                stack.extend(local[id].iter().copied());
+                let returns = graph.blocks[id]
+                    .items
+                    .last()
+                    .is_some_and(|&last| instructions[last].returns());
+                if returns && !graph.blocks[id].successors.contains(&return_to) {
+                    graph.add_edge(id, return_to);
+                }
+            }
+        }
+        graph
+    }
+}
+
+impl<'a> ControlFlowGraph<&'a ASTNode> {
+    /// AST からグラフを作る。ブロックの要素は代入と出力の文、 if と while の条件式。
+    /// 条件式はブロックの最後に置く。
+    pub fn from_ast(ast: &'a ASTNode) -> Self {
+        let mut builder = AstBuilder {
+            graph: ControlFlowGraph::new(),
+            current: 0,
+        };
+        builder.statement(ast);
+        builder.graph
+    }
+
+    /// DOT 形式にする。要素はソースの形で書く。
+    pub fn ast_to_dot(&self) -> String {
+        self.to_dot(|node| ast_label(node))
+    }
+}
+
+/// 文ならソースの文の形、式なら式の形
+fn ast_label(node: &ASTNode) -> String {
+    match node.kind() {
+        NodeKind::Assign
+        | NodeKind::Prtc
+        | NodeKind::Prti
+        | NodeKind::Prts
+        | NodeKind::Exit
+        | NodeKind::Assert => unparse(node).trim_end().to_string(),
+        _ => unparse_expr(node),
+    }
+}
+
+struct AstBuilder<'a> {
+    graph: ControlFlowGraph<&'a ASTNode>,
+    /// 文を加えているブロック
+    current: BlockId,
+}
+
+impl<'a> AstBuilder<'a> {
+    /// 後続をつないだ新しいブロックに移る
+    fn follow(&mut self, from: BlockId) -> BlockId {
+        let block = self.graph.new_block();
+        self.graph.add_edge(from, block);
+        self.current = block;
+        block
+    }
+
+    /// ジャンプして戻ってくる先のブロック。今のブロックが空ならそれを使う。
+    fn start_block(&mut self) -> BlockId {
+        if self.graph.blocks[self.current].items.is_empty() {
+            self.current
+        } else {
+            self.follow(self.current)
+        }
+    }
+
+    fn statement(&mut self, ast: &'a ASTNode) {
+        match ast.kind() {
+            NodeKind::Sequence => {
+                for item in ast.sequence_items() {
+                    self.statement(item);
+                }
+            }
+            NodeKind::If => {
+                let condition = ast.lhs().unwrap();
+                self.graph.blocks[self.current].items.push(condition);
+                let branch = self.current;
+                let body = ast.rhs().unwrap();
+
+                self.follow(branch);
+                self.statement(body.lhs().unwrap());
+                let then_end = self.current;
+                let else_end = match body.rhs() {
+                    Some(else_clause) => {
+                        self.follow(branch);
+                        self.statement(else_clause);
+                        self.current
+                    }
+                    None => branch,
+                };
+                let join = self.follow(then_end);
+                self.graph.add_edge(else_end, join);
+            }
+            NodeKind::While => {
+                let header = self.start_block();
+                self.graph.blocks[header].items.push(ast.lhs().unwrap());
+                self.follow(header);
+                self.statement(ast.rhs().unwrap());
+                self.graph.add_edge(self.current, header);
+                self.follow(header);
+            }
+            NodeKind::DoWhile => {
+                let body = self.start_block();
+                self.statement(ast.rhs().unwrap());
+                self.graph.blocks[self.current]
+                    .items
+                    .push(ast.lhs().unwrap());
+                let branch = self.current;
+                self.graph.add_edge(branch, body);
+                self.follow(branch);
+            }
+            NodeKind::Exit => {
+                // 後に続く文には、どこからも到達しない
+                self.graph.blocks[self.current].items.push(ast);
+                self.current = self.graph.new_block();
+            }
+            _ => self.graph.blocks[self.current].items.push(ast),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Program;
+    use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+    /// 各ブロックの要素と後続
+    fn shape<T, F: Fn(&T) -> String>(
+        graph: &ControlFlowGraph<T>,
+        label: F,
+    ) -> Vec<(Vec<String>, Vec<BlockId>)> {
+        graph
+            .blocks()
+            .iter()
+            .map(|block| {
+                (
+                    block.items.iter().map(&label).collect(),
+                    block.successors().to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_ast() {
+        let ast = syntax_analyzer::parse_str(
+            r#"i = 0;
+while (i < 3) {
+    if (i % 2) print(i); else putc('x');
+    i = i + 1;
+}
+do i = i - 1; while (i);
+"#,
+        )
+        .unwrap();
+        let graph = ControlFlowGraph::from_ast(&ast);
+        assert_eq!(
+            vec![
+                (strings(&["i = 0;"]), vec![1]),
+                (strings(&["i < 3"]), vec![2, 6]),
+                (strings(&["i % 2"]), vec![3, 4]),
+                (strings(&["print(i);"]), vec![5]),
+                (strings(&["putc(120);"]), vec![5]),
+                (strings(&["i = i + 1;"]), vec![1]),
+                (strings(&["i = i - 1;", "i"]), vec![6, 7]),
+                (strings(&[]), vec![]),
+            ],
+            shape(&graph, |node| ast_label(node))
+        );
+        assert_eq!(&[0, 5], graph.predecessors(1));
+        assert_eq!(&[1, 6], graph.predecessors(6));
+        assert!(graph.unreachable_blocks().is_empty());
+
+        // else のない if は条件のブロックから合流する
+        let ast = syntax_analyzer::parse_str("if (a) b = 1; c = 2;").unwrap();
+        let graph = ControlFlowGraph::from_ast(&ast);
+        assert_eq!(
+            vec![
+                (strings(&["a"]), vec![1, 2]),
+                (strings(&["b = 1;"]), vec![2]),
+                (strings(&["c = 2;"]), vec![]),
+            ],
+            shape(&graph, |node| ast_label(node))
+        );
+    }
+
+    #[test]
+    fn test_from_instructions() {
+        let ast = syntax_analyzer::parse_str(
+            "count = 1; while (count < 10) { print(count); count = count + 1; }",
+        )
+        .unwrap();
+        let program = Program::lower(&ast).unwrap();
+        let graph = ControlFlowGraph::from_instructions(&program.instructions);
+        assert_eq!(
+            vec![
+                (strings(&["0"]), vec![1]),
+                (strings(&["1", "2"]), vec![2, 3]),
+                (strings(&["3", "4", "5"]), vec![1]),
+                (strings(&["6"]), vec![]),
+            ],
+            shape(&graph, |index| index.to_string())
+        );
+
+        // 仮想マシンの命令でも同じ形になる
+        let code = code_generator::CodeGenerator::generate(&ast).unwrap();
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        let ops: Vec<DecodedOp> = vm
+            .instructions()
+            .unwrap()
+            .into_iter()
+            .map(|(op, _)| op)
+            .collect();
+        let graph = ControlFlowGraph::from_instructions(&ops);
+        assert_eq!(4, graph.blocks().len());
+        assert_eq!(&[0, 2], graph.predecessors(1));
+        assert_eq!(&[2, 3], graph.successors(1));
+    }
+
+    #[test]
+    fn test_calls() {
+        let code = r#"Datasize: 0 Strings: 0
+push 3
+call f
+prti
+call g
+halt
+f:  enter 1
+    fetchl 0
+    jz done
+    fetchl 0
+    push 1
+    sub
+    call f
+    leave
+    ret
+done:
+    push 0
+    leave
+    ret
+g:  enter 0
+    push 1
+    exit
+    halt
+"#;
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        let ops: Vec<DecodedOp> = vm
+            .instructions()
+            .unwrap()
+            .into_iter()
+            .map(|(op, _)| op)
+            .collect();
+        let graph = ControlFlowGraph::from_instructions(&ops);
+        assert_eq!(
+            vec![
+                (strings(&["0", "1"]), vec![3]),
+                (strings(&["2", "3"]), vec![7]),
+                (strings(&["4"]), vec![]),
+                (strings(&["5", "6", "7"]), vec![4, 6]),
+                (strings(&["8", "9", "10", "11"]), vec![3]),
+                (strings(&["12", "13"]), vec![1, 5]),
+                (strings(&["14", "15", "16"]), vec![1, 5]),
+                (strings(&["17", "18", "19"]), vec![]),
+                (strings(&["20"]), vec![]),
+            ],
+            shape(&graph, |index| index.to_string())
+        );
+        // 関数から戻らなければ、呼び出しの次には到達しない
+        assert_eq!(vec![2, 8], graph.unreachable_blocks());
+    }
+
+    #[test]
+    fn test_unreachable_blocks() {
+        let instructions = vec![
+            Instruction::Jump(2),
+            Instruction::PrintInt(crate::Operand::Constant(1)),
+            Instruction::Halt,
+        ];
+        let graph = ControlFlowGraph::from_instructions(&instructions);
+        assert_eq!(vec![1], graph.unreachable_blocks());
+        assert_eq!(vec![true, false, true], graph.reachable());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let ast = syntax_analyzer::parse_str("while (x > 0) print(\"a\\n\");").unwrap();
+        let graph = ControlFlowGraph::from_ast(&ast);
+        assert_eq!(
+            r#"digraph cfg {
+    node [shape=box];
+    b0 [label="B0\lx > 0\l"];
+    b1 [label="B1\lprint(\"a\\n\");\l"];
+    b2 [label="B2\l"];
+    b0 -> b1;
+    b0 -> b2;
+    b1 -> b0;
+}
+"#,
+            graph.ast_to_dot()
+        );
+    }
+}
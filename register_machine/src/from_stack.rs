@@ -0,0 +1,205 @@
+use super::*;
+
+use lexical_analyzer::width::Width;
+use virtual_machine_interpreter::{DecodedOp, VirtualMachineInterpreter};
+
+impl Program {
+    /// 仮想マシンに読み込んだスタックマシンのコードを変換する。
+    /// 変数はそのままの番号のレジスタに、深さ d のスタックの要素は data_size + d 番のレジスタに置く。
+    /// 命令は 1 つずつ対応するので、ジャンプ先の添字は変わらない。
+    /// レジスタマシンは 32 ビットの値だけを扱うので、 64 ビットのコードはエラーにする。
+    pub fn from_vm(vm: &VirtualMachineInterpreter) -> Result<Self> {
+        if vm.width() != Width::W32 {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                "64-bit integers are not supported by the register machine",
+            ));
+        }
+        let data_size = vm.data().len();
+        let stack_size = vm.max_stack_depth()?;
+        let instructions = vm.instructions()?;
+        if instructions.iter().any(|(op, _)| is_frame_op(op)) {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                "function calls are not supported by the register machine",
+            ));
+        }
+        if instructions
+            .iter()
+            .any(|(op, _)| matches!(op, DecodedOp::ExternalCall(_)))
+        {
+            return Err(CompileError::new(
+                ErrorKind::CodeGenerationError,
+                "host functions are not supported by the register machine",
+            ));
+        }
+        let instructions = instructions
+            .into_iter()
+            .map(|(op, depth)| {
+                let depth = match depth {
+                    Some(depth) => depth,
+                    // 到達しない命令
+                    None => return Instruction::Halt,
+                };
+                // 上から n 番目 (1 から数える) のスタックの要素
+                let top = |n: usize| data_size + depth - n;
+                let top_operand = |n: usize| Operand::Register(top(n));
+                let binary =
+                    |op: BinaryOp| Instruction::Binary(op, top(2), top_operand(2), top_operand(1));
+                match op {
+                    DecodedOp::Fetch(index) => {
+                        Instruction::Move(data_size + depth, Operand::Register(index))
+                    }
+                    DecodedOp::Store(index) => Instruction::Move(index, top_operand(1)),
+                    // 文字列の値は文字列プールの番号のまま扱う
+                    DecodedOp::Push(value) => {
+                        Instruction::Move(data_size + depth, Operand::Constant(value as i32))
+                    }
+                    DecodedOp::PushS(value) => {
+                        Instruction::Move(data_size + depth, Operand::Constant(value))
+                    }
+                    DecodedOp::PushF(value) => {
+                        Instruction::Move(data_size + depth, Operand::Constant(value.to_bits()))
+                    }
+                    DecodedOp::Add => binary(BinaryOp::Add),
+                    DecodedOp::Sub => binary(BinaryOp::Sub),
+                    DecodedOp::Mul => binary(BinaryOp::Mul),
+                    DecodedOp::Div => binary(BinaryOp::Div),
+                    DecodedOp::Mod => binary(BinaryOp::Mod),
+                    DecodedOp::Lt => binary(BinaryOp::Lt),
+                    DecodedOp::Gt => binary(BinaryOp::Gt),
+                    DecodedOp::Le => binary(BinaryOp::Le),
+                    DecodedOp::Ge => binary(BinaryOp::Ge),
+                    DecodedOp::Eq => binary(BinaryOp::Eq),
+                    DecodedOp::Ne => binary(BinaryOp::Ne),
+                    DecodedOp::And => binary(BinaryOp::And),
+                    DecodedOp::Or => binary(BinaryOp::Or),
+                    DecodedOp::BitAnd => binary(BinaryOp::BitAnd),
+                    DecodedOp::BitOr => binary(BinaryOp::BitOr),
+                    DecodedOp::BitXor => binary(BinaryOp::BitXor),
+                    DecodedOp::Shl => binary(BinaryOp::Shl),
+                    DecodedOp::Shr => binary(BinaryOp::Shr),
+                    DecodedOp::Pow => binary(BinaryOp::Pow),
+                    DecodedOp::Neg => Instruction::Unary(UnaryOp::Neg, top(1), top_operand(1)),
+                    DecodedOp::Not => Instruction::Unary(UnaryOp::Not, top(1), top_operand(1)),
+                    DecodedOp::FAdd => binary(BinaryOp::FAdd),
+                    DecodedOp::FSub => binary(BinaryOp::FSub),
+                    DecodedOp::FMul => binary(BinaryOp::FMul),
+                    DecodedOp::FDiv => binary(BinaryOp::FDiv),
+                    DecodedOp::FLt => binary(BinaryOp::FLt),
+                    DecodedOp::FGt => binary(BinaryOp::FGt),
+                    DecodedOp::FLe => binary(BinaryOp::FLe),
+                    DecodedOp::FGe => binary(BinaryOp::FGe),
+                    DecodedOp::FEq => binary(BinaryOp::FEq),
+                    DecodedOp::FNe => binary(BinaryOp::FNe),
+                    DecodedOp::FNeg => Instruction::Unary(UnaryOp::FNeg, top(1), top_operand(1)),
+                    DecodedOp::Itof => Instruction::Unary(UnaryOp::Itof, top(1), top_operand(1)),
+                    DecodedOp::Ftoi => Instruction::Unary(UnaryOp::Ftoi, top(1), top_operand(1)),
+                    DecodedOp::Jmp(target) => Instruction::Jump(target),
+                    DecodedOp::Jz(target) => Instruction::JumpIfZero(top_operand(1), target),
+                    DecodedOp::Prtc => Instruction::PrintChar(top_operand(1)),
+                    DecodedOp::Prts => Instruction::PrintString(top_operand(1)),
+                    DecodedOp::Prti => Instruction::PrintInt(top_operand(1)),
+                    DecodedOp::PrtiWidth(width) => {
+                        Instruction::PrintIntWidth(top_operand(1), width)
+                    }
+                    DecodedOp::Prtf => Instruction::PrintFloat(top_operand(1)),
+                    DecodedOp::Halt => Instruction::Halt,
+                    DecodedOp::Getc => Instruction::GetChar(data_size + depth),
+                    DecodedOp::Geti => Instruction::GetInt(data_size + depth),
+                    DecodedOp::Fetchi => Instruction::Load(top(1), top_operand(1)),
+                    DecodedOp::Storei => Instruction::Store(top_operand(2), top_operand(1)),
+                    DecodedOp::Rand => Instruction::Rand(top(1), top_operand(1)),
+                    DecodedOp::Clock => Instruction::Clock(data_size + depth),
+                    DecodedOp::Exit => Instruction::Exit(top_operand(1)),
+                    DecodedOp::Assert => Instruction::Assert(top_operand(2), top_operand(1)),
+                    op => unreachable!("{:?}", op),
+                }
+            })
+            .collect();
+
+        Ok(Program {
+            instructions,
+            data_size,
+            registers: data_size + stack_size,
+            strings: vm.strings().to_vec(),
+        })
+    }
+}
+
+/// コールスタックやフレームを使う命令なら true
+fn is_frame_op(op: &DecodedOp) -> bool {
+    matches!(
+        op,
+        DecodedOp::Call(_)
+            | DecodedOp::Ret
+            | DecodedOp::Enter(_)
+            | DecodedOp::Leave
+            | DecodedOp::FetchL(_)
+            | DecodedOp::StoreL(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_vm() {
+        let s = r#"Datasize: 1 Strings: 1
+"\n"
+    push 1
+    store [0]
+loop: fetch [0]
+    push 10
+    lt
+    jz end
+    fetch [0]
+    prti
+    push 0
+    prts
+    fetch [0]
+    push 1
+    add
+    store [0]
+    jmp loop
+end: halt
+"#;
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        let program = Program::from_vm(&vm).unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Registers: 3 Strings: 1
+"\n"
+    0 r1 = 1
+    1 r0 = r1
+    2 r1 = r0
+    3 r2 = 10
+    4 r1 = lt r1, r2
+    5 jz r1, 15
+    6 r1 = r0
+    7 prti r1
+    8 r1 = 0
+    9 prts r1
+   10 r1 = r0
+   11 r2 = 1
+   12 r1 = add r1, r2
+   13 r0 = r1
+   14 jmp 2
+   15 halt
+"#,
+            program.to_string()
+        );
+
+        let mut out = Vec::new();
+        RegisterMachine::new(program)
+            .unwrap()
+            .execute(&mut out)
+            .unwrap();
+        assert_eq!(b"1\n2\n3\n4\n5\n6\n7\n8\n9\n", &out[..]);
+
+        // 関数呼び出しは変換しない
+        let s = "Datasize: 0 Strings: 0\npush 1\ncall f\nprti\nhalt\nf: enter 1\nfetchl 0\nleave\nret\n";
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert!(Program::from_vm(&vm).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use lexical_analyzer::error::*;
+use lexical_analyzer::float::Float;
+use lexical_analyzer::width::Width;
+use std::fmt;
+
+/// レジスタの番号
+pub type Register = usize;
+
+/// 命令の入力。レジスタか定数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Constant(i32),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(r) => write!(f, "r{}", r),
+            Operand::Constant(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    /// 浮動小数点数の符号を反転する。値は f32 のビット列。
+    FNeg,
+    /// 整数を浮動小数点数にする
+    Itof,
+    /// 浮動小数点数を 0 の方向に丸めて整数にする
+    Ftoi,
+}
+
+impl UnaryOp {
+    pub fn apply(self, a: i32) -> i32 {
+        match self {
+            UnaryOp::Neg => a.wrapping_neg(),
+            UnaryOp::Not => (a == 0) as i32,
+            UnaryOp::FNeg => Float(-Float::from_bits(a).value()).to_bits(),
+            UnaryOp::Itof => Float(a as f32).to_bits(),
+            UnaryOp::Ftoi => Float::from_bits(a).value() as i32,
+        }
+    }
+
+    fn mnemonic(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "neg",
+            UnaryOp::Not => "not",
+            UnaryOp::FNeg => "fneg",
+            UnaryOp::Itof => "itof",
+            UnaryOp::Ftoi => "ftoi",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Pow,
+    /// 浮動小数点数の演算。値は f32 のビット列で、比較の結果は整数。
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FLt,
+    FGt,
+    FLe,
+    FGe,
+    FEq,
+    FNe,
+}
+
+fn division_by_zero() -> CompileError {
+    CompileError::new(ErrorKind::VirtualMachineError, "division by zero")
+}
+
+impl BinaryOp {
+    /// 仮想マシンと同じ規則で計算する。 0 で割ることと 0 の負の乗はエラーにし、
+    /// 最小値を -1 で割ると折り返す。
+    pub fn apply(self, a: i32, b: i32) -> Result<i32> {
+        let value = match self {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div | BinaryOp::Mod => {
+                let value = if self == BinaryOp::Div {
+                    Width::W32.div(a.into(), b.into())
+                } else {
+                    Width::W32.rem(a.into(), b.into())
+                };
+                value.ok_or_else(division_by_zero)? as i32
+            }
+            BinaryOp::Lt => (a < b) as i32,
+            BinaryOp::Gt => (a > b) as i32,
+            BinaryOp::Le => (a <= b) as i32,
+            BinaryOp::Ge => (a >= b) as i32,
+            BinaryOp::Eq => (a == b) as i32,
+            BinaryOp::Ne => (a != b) as i32,
+            BinaryOp::And => (a != 0 && b != 0) as i32,
+            BinaryOp::Or => (a != 0 || b != 0) as i32,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::Shl => a.wrapping_shl(b as u32),
+            BinaryOp::Shr => a.wrapping_shr(b as u32),
+            BinaryOp::Pow => Width::W32
+                .pow(a.into(), b.into())
+                .ok_or_else(division_by_zero)? as i32,
+            _ => {
+                let (x, y) = (Float::from_bits(a).value(), Float::from_bits(b).value());
+                match self {
+                    BinaryOp::FAdd => Float(x + y).to_bits(),
+                    BinaryOp::FSub => Float(x - y).to_bits(),
+                    BinaryOp::FMul => Float(x * y).to_bits(),
+                    BinaryOp::FDiv => Float(x / y).to_bits(),
+                    BinaryOp::FLt => (x < y) as i32,
+                    BinaryOp::FGt => (x > y) as i32,
+                    BinaryOp::FLe => (x <= y) as i32,
+                    BinaryOp::FGe => (x >= y) as i32,
+                    BinaryOp::FEq => (x == y) as i32,
+                    BinaryOp::FNe => (x != y) as i32,
+                    _ => unreachable!(),
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    fn mnemonic(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Mod => "mod",
+            BinaryOp::Lt => "lt",
+            BinaryOp::Gt => "gt",
+            BinaryOp::Le => "le",
+            BinaryOp::Ge => "ge",
+            BinaryOp::Eq => "eq",
+            BinaryOp::Ne => "ne",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::BitAnd => "band",
+            BinaryOp::BitOr => "bor",
+            BinaryOp::BitXor => "bxor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "shr",
+            BinaryOp::Pow => "pow",
+            BinaryOp::FAdd => "fadd",
+            BinaryOp::FSub => "fsub",
+            BinaryOp::FMul => "fmul",
+            BinaryOp::FDiv => "fdiv",
+            BinaryOp::FLt => "flt",
+            BinaryOp::FGt => "fgt",
+            BinaryOp::FLe => "fle",
+            BinaryOp::FGe => "fge",
+            BinaryOp::FEq => "feq",
+            BinaryOp::FNe => "fne",
+        }
+    }
+}
+
+/// 3 番地コードの命令。結果を書くレジスタを最初に置く。ジャンプ先は命令列の添字。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Move(Register, Operand),
+    Unary(UnaryOp, Register, Operand),
+    Binary(BinaryOp, Register, Operand, Operand),
+    Jump(usize),
+    JumpIfZero(Operand, usize),
+    PrintChar(Operand),
+    PrintInt(Operand),
+    /// 幅を揃えて整数を出力する (仮想マシンの prtiw)
+    PrintIntWidth(Operand, i32),
+    /// 値を f32 のビット列として浮動小数点数を出力する (仮想マシンの prtf)
+    PrintFloat(Operand),
+    /// 文字列プールの番号の文字列を出力する
+    PrintString(Operand),
+    GetChar(Register),
+    /// 入力から整数を読む (仮想マシンの geti)
+    GetInt(Register),
+    /// 番号が値で与えられる変数を読む (仮想マシンの fetchi)
+    Load(Register, Operand),
+    /// 番号が 1 つ目の値で与えられる変数に書く (仮想マシンの storei)
+    Store(Operand, Operand),
+    Rand(Register, Operand),
+    Clock(Register),
+    Halt,
+    /// 値を終了コードにして止まる
+    Exit(Operand),
+    /// 1 つ目の値が 0 なら、2 つ目の番号の文字列をエラーにして止まる
+    Assert(Operand, Operand),
+}
+
+impl Instruction {
+    /// 結果を書くレジスタ
+    pub fn destination(&self) -> Option<Register> {
+        match *self {
+            Instruction::Move(dst, _)
+            | Instruction::Unary(_, dst, _)
+            | Instruction::Binary(_, dst, _, _)
+            | Instruction::GetChar(dst)
+            | Instruction::GetInt(dst)
+            | Instruction::Load(dst, _)
+            | Instruction::Rand(dst, _)
+            | Instruction::Clock(dst) => Some(dst),
+            _ => None,
+        }
+    }
+
+    /// 結果を書くレジスタを r に変える。結果を書かない命令なら何もしない。
+    pub fn set_destination(&mut self, r: Register) {
+        match self {
+            Instruction::Move(dst, _)
+            | Instruction::Unary(_, dst, _)
+            | Instruction::Binary(_, dst, _, _)
+            | Instruction::GetChar(dst)
+            | Instruction::GetInt(dst)
+            | Instruction::Load(dst, _)
+            | Instruction::Rand(dst, _)
+            | Instruction::Clock(dst) => *dst = r,
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Move(dst, src) => write!(f, "r{} = {}", dst, src),
+            Instruction::Unary(op, dst, a) => write!(f, "r{} = {} {}", dst, op.mnemonic(), a),
+            Instruction::Binary(op, dst, a, b) => {
+                write!(f, "r{} = {} {}, {}", dst, op.mnemonic(), a, b)
+            }
+            Instruction::Jump(target) => write!(f, "jmp {}", target),
+            Instruction::JumpIfZero(a, target) => write!(f, "jz {}, {}", a, target),
+            Instruction::PrintChar(a) => write!(f, "prtc {}", a),
+            Instruction::PrintInt(a) => write!(f, "prti {}", a),
+            Instruction::PrintIntWidth(a, width) => write!(f, "prtiw {}, {}", a, width),
+            Instruction::PrintFloat(a) => write!(f, "prtf {}", a),
+            Instruction::PrintString(a) => write!(f, "prts {}", a),
+            Instruction::GetChar(dst) => write!(f, "r{} = getc", dst),
+            Instruction::GetInt(dst) => write!(f, "r{} = geti", dst),
+            Instruction::Load(dst, index) => write!(f, "r{} = load {}", dst, index),
+            Instruction::Store(index, value) => write!(f, "store {}, {}", index, value),
+            Instruction::Rand(dst, n) => write!(f, "r{} = rand {}", dst, n),
+            Instruction::Clock(dst) => write!(f, "r{} = clock", dst),
+            Instruction::Halt => write!(f, "halt"),
+            Instruction::Exit(a) => write!(f, "exit {}", a),
+            Instruction::Assert(a, message) => write!(f, "assert {}, {}", a, message),
+        }
+    }
+}
+
+/// レジスタマシンのプログラム
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// 変数の数。レジスタ 0 から data_size - 1 までを変数に使う。
+    pub data_size: usize,
+    /// 変数を含めたレジスタの数
+    pub registers: usize,
+    pub strings: Vec<String>,
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Datasize: {} Registers: {} Strings: {}",
+            self.data_size,
+            self.registers,
+            self.strings.len()
+        )?;
+        for s in &self.strings {
+            writeln!(f, "{}", escape::quote(s))?;
+        }
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            writeln!(f, "{:5} {}", index, instruction)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let program = Program {
+            instructions: vec![
+                Instruction::Move(0, Operand::Constant(1)),
+                Instruction::Binary(BinaryOp::Lt, 1, Operand::Register(0), Operand::Constant(10)),
+                Instruction::JumpIfZero(Operand::Register(1), 5),
+                Instruction::PrintString(Operand::Constant(0)),
+                Instruction::Jump(1),
+                Instruction::Halt,
+            ],
+            data_size: 1,
+            registers: 2,
+            strings: vec!["\n".to_string()],
+        };
+        assert_eq!(
+            r#"Datasize: 1 Registers: 2 Strings: 1
+"\n"
+    0 r0 = 1
+    1 r1 = lt r0, 10
+    2 jz r1, 5
+    3 prts 0
+    4 jmp 1
+    5 halt
+"#,
+            program.to_string()
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(-3, UnaryOp::Neg.apply(3));
+        assert_eq!(1, UnaryOp::Not.apply(0));
+        assert_eq!(-2, BinaryOp::Div.apply(-7, 3).unwrap());
+        assert_eq!(-1, BinaryOp::Mod.apply(-7, 3).unwrap());
+        assert_eq!(1, BinaryOp::Or.apply(0, 5).unwrap());
+        assert_eq!(8, BinaryOp::Shl.apply(1, 35).unwrap());
+        assert_eq!(i32::MIN, BinaryOp::Pow.apply(2, 31).unwrap());
+        assert_eq!(i32::MIN, BinaryOp::Div.apply(i32::MIN, -1).unwrap());
+        assert_eq!(0, BinaryOp::Mod.apply(i32::MIN, -1).unwrap());
+        let e = BinaryOp::Div.apply(1, 0).unwrap_err();
+        assert_eq!("division by zero", e.message());
+        assert!(BinaryOp::Mod.apply(1, 0).is_err());
+        let e = BinaryOp::Pow.apply(0, -1).unwrap_err();
+        assert_eq!("division by zero", e.message());
+    }
+}
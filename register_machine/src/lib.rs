@@ -0,0 +1,510 @@
+use isa::random::Random;
+use lexical_analyzer::error::*;
+use lexical_analyzer::float::{format_float, Float};
+use lexical_analyzer::input::Input;
+use virtual_machine_interpreter::pad_integer;
+
+use std::io::{Read, Write};
+use std::time::Instant;
+
+pub mod cfg;
+mod from_stack;
+mod ir;
+mod lower;
+
+pub use ir::{BinaryOp, Instruction, Operand, Program, Register, UnaryOp};
+
+fn output_error(e: std::io::Error) -> CompileError {
+    CompileError::new(
+        ErrorKind::VirtualMachineError,
+        format!("output error: {}", e),
+    )
+}
+
+/// 3 番地コードを実行するレジスタマシン。
+/// 仮想マシンと同じ出力と実行時エラーになるように作っている。
+pub struct RegisterMachine {
+    program: Program,
+    registers: Vec<i32>,
+    /// getc と geti の読み込み元。設定しなければ常に EOF。
+    input: Input,
+    /// rand の擬似乱数
+    random: Random,
+    /// clock の基準時刻。 execute を呼ぶたびに設定し直す。
+    start: Instant,
+    /// 最後の実行が exit 命令で止まったときの終了コード
+    exit_code: i32,
+}
+
+impl RegisterMachine {
+    pub fn interpret(program: Program, out: &mut dyn Write) -> Result<()> {
+        Self::new(program)?.execute(out)
+    }
+
+    /// program を検査し、実行可能な状態のマシンを返す。
+    pub fn new(program: Program) -> Result<Self> {
+        Self::validate(&program)?;
+        Ok(RegisterMachine {
+            registers: vec![0; program.registers],
+            program,
+            input: Input::default(),
+            random: Random::default(),
+            start: Instant::now(),
+            exit_code: 0,
+        })
+    }
+
+    /// レジスタの番号とジャンプ先が範囲内にあり、最後が halt であることを確かめる
+    fn validate(program: &Program) -> Result<()> {
+        let invalid = |index: usize, message: String| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("{}: {}", index, message),
+            )
+        };
+        if program.data_size > program.registers {
+            return Err(invalid(
+                0,
+                format!(
+                    "data size {} is larger than the number of registers {}",
+                    program.data_size, program.registers
+                ),
+            ));
+        }
+
+        for (index, instruction) in program.instructions.iter().enumerate() {
+            let mut registers: Vec<Register> = instruction.destination().into_iter().collect();
+            let mut target = None;
+            let mut operand = |operand: &Operand| {
+                if let Operand::Register(r) = *operand {
+                    registers.push(r);
+                }
+            };
+            match instruction {
+                Instruction::Move(_, a)
+                | Instruction::Unary(_, _, a)
+                | Instruction::PrintChar(a)
+                | Instruction::PrintInt(a)
+                | Instruction::PrintIntWidth(a, _)
+                | Instruction::PrintFloat(a)
+                | Instruction::PrintString(a)
+                | Instruction::Exit(a)
+                | Instruction::Load(_, a)
+                | Instruction::Rand(_, a) => operand(a),
+                Instruction::Binary(_, _, a, b)
+                | Instruction::Store(a, b)
+                | Instruction::Assert(a, b) => {
+                    operand(a);
+                    operand(b);
+                }
+                Instruction::JumpIfZero(a, t) => {
+                    operand(a);
+                    target = Some(*t);
+                }
+                Instruction::Jump(t) => target = Some(*t),
+                Instruction::GetChar(_)
+                | Instruction::GetInt(_)
+                | Instruction::Clock(_)
+                | Instruction::Halt => {}
+            }
+
+            if let Some(r) = registers.into_iter().find(|&r| r >= program.registers) {
+                return Err(invalid(
+                    index,
+                    format!(
+                        "register r{} is out of range (registers: {})",
+                        r, program.registers
+                    ),
+                ));
+            }
+            if let Some(t) = target.filter(|&t| t >= program.instructions.len()) {
+                return Err(invalid(
+                    index,
+                    format!("jump target {} is not an instruction", t),
+                ));
+            }
+        }
+
+        match program.instructions.last() {
+            Some(Instruction::Halt) => Ok(()),
+            _ => Err(invalid(
+                program.instructions.len().saturating_sub(1),
+                "code does not end with halt".to_string(),
+            )),
+        }
+    }
+
+    /// getc と geti で読む入力を設定する。
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = Input::new(input);
+    }
+
+    /// rand の種を設定する。
+    pub fn set_seed(&mut self, seed: u32) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// 変数の値。添字はコード生成で割り当てた番号。
+    pub fn data(&self) -> &[i32] {
+        &self.registers[..self.program.data_size]
+    }
+
+    /// 最後の実行の終了コード。 exit 命令で止まったならその値、それ以外は 0。
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    /// 0 以上 n 未満の擬似乱数
+    fn rand(&mut self, n: i32) -> Result<i32> {
+        self.random.next(n).ok_or_else(|| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("rand: {} is not positive", n),
+            )
+        })
+    }
+
+    /// 入力から 1 バイト読む。EOF なら -1。
+    fn read_char(&mut self) -> Result<i32> {
+        self.input.read_char(ErrorKind::VirtualMachineError)
+    }
+
+    /// 入力から整数を読む。整数がなければエラーにする。
+    fn read_int(&mut self) -> Result<i32> {
+        self.input.read_integer(ErrorKind::VirtualMachineError)
+    }
+
+    /// load, store の変数の番号を検査する
+    fn data_index(&self, index: i32) -> Result<usize> {
+        if index >= 0 && (index as usize) < self.program.data_size {
+            Ok(index as usize)
+        } else {
+            Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("data index {} is out of range", index),
+            ))
+        }
+    }
+
+    /// 文字列プールの番号を検査して、その文字列を返す
+    fn string(&self, index: i32) -> Result<String> {
+        match self.program.strings.get(index as usize) {
+            Some(s) if index >= 0 => Ok(s.clone()),
+            _ => Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("illegal string index: {}", index),
+            )),
+        }
+    }
+
+    #[inline(always)]
+    fn value(&self, operand: Operand) -> i32 {
+        match operand {
+            Operand::Register(r) => self.registers[r],
+            Operand::Constant(value) => value,
+        }
+    }
+
+    /// 先頭から halt まで実行する。実行のたびにレジスタを 0 に戻す。
+    pub fn execute(&mut self, out: &mut dyn Write) -> Result<()> {
+        self.start = Instant::now();
+        self.registers.iter_mut().for_each(|r| *r = 0);
+        self.exit_code = 0;
+
+        let mut pc = 0;
+        loop {
+            let instruction = self.program.instructions[pc];
+            pc += 1;
+            match instruction {
+                Instruction::Move(dst, a) => self.registers[dst] = self.value(a),
+                Instruction::Unary(op, dst, a) => self.registers[dst] = op.apply(self.value(a)),
+                Instruction::Binary(op, dst, a, b) => {
+                    self.registers[dst] = op.apply(self.value(a), self.value(b))?
+                }
+                Instruction::Jump(target) => pc = target,
+                Instruction::JumpIfZero(a, target) => {
+                    if self.value(a) == 0 {
+                        pc = target;
+                    }
+                }
+                Instruction::PrintChar(a) => {
+                    let value = self.value(a);
+                    match std::char::from_u32(value as u32) {
+                        Some(c) => write!(out, "{}", c).map_err(output_error)?,
+                        None => {
+                            return Err(CompileError::new(
+                                ErrorKind::VirtualMachineError,
+                                format!("illegal character value: {}", value),
+                            ))
+                        }
+                    }
+                }
+                Instruction::PrintInt(a) => {
+                    write!(out, "{}", self.value(a)).map_err(output_error)?
+                }
+                Instruction::PrintIntWidth(a, width) => {
+                    write!(out, "{}", pad_integer(self.value(a) as i64, width))
+                        .map_err(output_error)?
+                }
+                Instruction::PrintFloat(a) => {
+                    let value = Float::from_bits(self.value(a)).value();
+                    write!(out, "{}", format_float(value)).map_err(output_error)?
+                }
+                Instruction::PrintString(a) => {
+                    let s = self.string(self.value(a))?;
+                    out.write_all(s.as_bytes()).map_err(output_error)?
+                }
+                Instruction::GetChar(dst) => self.registers[dst] = self.read_char()?,
+                Instruction::GetInt(dst) => self.registers[dst] = self.read_int()?,
+                Instruction::Load(dst, index) => {
+                    let index = self.data_index(self.value(index))?;
+                    self.registers[dst] = self.registers[index];
+                }
+                Instruction::Store(index, a) => {
+                    let index = self.data_index(self.value(index))?;
+                    self.registers[index] = self.value(a);
+                }
+                Instruction::Rand(dst, n) => self.registers[dst] = self.rand(self.value(n))?,
+                Instruction::Clock(dst) => {
+                    self.registers[dst] = self.start.elapsed().as_millis() as i32
+                }
+                Instruction::Halt => break,
+                Instruction::Exit(a) => {
+                    self.exit_code = self.value(a);
+                    break;
+                }
+                Instruction::Assert(a, message) => {
+                    if self.value(a) == 0 {
+                        return Err(CompileError::new(
+                            ErrorKind::VirtualMachineError,
+                            self.string(self.value(message))?,
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use virtual_machine_interpreter::VirtualMachineInterpreter;
+
+    /// AST から変換したコードと、仮想マシンのコードから変換したコードで実行し、
+    /// どちらも仮想マシンと同じ出力になることを確かめる
+    fn run(src: &str) -> String {
+        run_with(src, &syntax_analyzer::ParserOptions::default())
+    }
+
+    fn run_with(src: &str, options: &syntax_analyzer::ParserOptions) -> String {
+        let ast = syntax_analyzer::parse_str_with(src, options).unwrap();
+        let mut out = Vec::new();
+        RegisterMachine::interpret(Program::lower(&ast).unwrap(), &mut out).unwrap();
+
+        let code = code_generator::CodeGenerator::generate(&ast).unwrap();
+        let mut vm_out = Vec::new();
+        VirtualMachineInterpreter::interpret(code.lines(), Box::new(std::io::empty()), &mut vm_out)
+            .unwrap();
+        assert_eq!(vm_out, out);
+
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        let mut converted_out = Vec::new();
+        RegisterMachine::interpret(Program::from_vm(&vm).unwrap(), &mut converted_out).unwrap();
+        assert_eq!(vm_out, converted_out);
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_execute() {
+        assert_eq!(
+            "gcd: 21\n",
+            run(r#"a = 1071; b = 1029;
+while (b != 0) { t = b; b = a % b; a = t; }
+print("gcd: ", a, "\n");"#)
+        );
+        assert_eq!(
+            "-7 1 0 1 24 -2 6\n",
+            run(r#"x = 7;
+print(-x, " ", !0, " ", !x, " ", x > 3 && x < 10, " ", 3 << 3, " ", -7 / 3, " ", x ^ 1);
+putc('\n');"#)
+        );
+        assert_eq!(
+            "38 58 13\n",
+            run(
+                r#"i = 0; do { print(rand(100)); i = i + 1; if (i < 3) print(" "); } while (i < 3);
+print("\n");"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_string_variables() {
+        let src = r#"yes = "yes"; no = "no"; answer = no;
+i = 0;
+while (i < 3) { if (i == 1) answer = yes; print(answer, " "); i = i + 1; }"#;
+        assert_eq!("no yes yes ", run(src));
+    }
+
+    #[test]
+    fn test_exit() {
+        let src = "i = 0; while (1) { print(i); if (i == 2) exit(i + 40); i = i + 1; }";
+        assert_eq!("012", run(src));
+
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let mut machine = RegisterMachine::new(Program::lower(&ast).unwrap()).unwrap();
+        machine.execute(&mut Vec::new()).unwrap();
+        assert_eq!(42, machine.exit_code());
+
+        let code = code_generator::CodeGenerator::generate(&ast).unwrap();
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        let mut machine = RegisterMachine::new(Program::from_vm(&vm).unwrap()).unwrap();
+        machine.execute(&mut Vec::new()).unwrap();
+        assert_eq!(42, machine.exit_code());
+    }
+
+    #[test]
+    fn test_assert() {
+        let src = "i = 0;\nwhile (1) {\n    assert(i < 2, \"too many\");\n    print(i);\n    i = i + 1;\n}";
+        let ast = syntax_analyzer::parse_str(src).unwrap();
+        let code = code_generator::CodeGenerator::generate(&ast).unwrap();
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        for program in [
+            Program::lower(&ast).unwrap(),
+            Program::from_vm(&vm).unwrap(),
+        ] {
+            let mut out = Vec::new();
+            let e = RegisterMachine::interpret(program, &mut out).unwrap_err();
+            assert_eq!("assertion failed at line 3: too many", e.message());
+            assert_eq!(b"01", &out[..]);
+        }
+    }
+
+    #[test]
+    fn test_input() {
+        let code = "Datasize: 0 Strings: 0\ngeti\ngeti\nadd\nprti\ngetc\nprti\nhalt\n";
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        let program = Program::from_vm(&vm).unwrap();
+        assert_eq!("r0 = geti", program.instructions[0].to_string());
+
+        let mut machine = RegisterMachine::new(program).unwrap();
+        machine.set_input(Box::new(&b"40 2\n"[..]));
+        let mut out = Vec::new();
+        machine.execute(&mut out).unwrap();
+        assert_eq!(b"4210", &out[..]);
+    }
+
+    #[test]
+    fn test_print_width() {
+        let code =
+            "Datasize: 0 Strings: 0\npush 7\nprtiw 3\npush 8\nprtiw -2\npush 9\nprti\nhalt\n";
+        let vm = VirtualMachineInterpreter::load(code.lines()).unwrap();
+        let program = Program::from_vm(&vm).unwrap();
+        assert_eq!("prtiw r0, 3", program.instructions[1].to_string());
+
+        let mut out = Vec::new();
+        RegisterMachine::interpret(program, &mut out).unwrap();
+        assert_eq!(b"  78 9", &out[..]);
+    }
+
+    #[test]
+    fn test_floats() {
+        let options = syntax_analyzer::ParserOptions {
+            dialect: lexical_analyzer::dialect::Dialect::float(),
+            ..syntax_analyzer::ParserOptions::default()
+        };
+        let src = r#"x = 1.5e3 / 4; y = 0; y = y + x;
+i = 0; while (i < 3) { y = y / 2; i = i + 1; }
+print(x, " ", int(x), " ", y, " ", float(7) / 2, " ", x > 100, " ", -x, " ", int(-2.5), "\n");"#;
+        assert_eq!(
+            "375.0 375 46.875 3.5 1 -375.0 -2\n",
+            run_with(src, &options)
+        );
+    }
+
+    #[test]
+    fn test_registers_are_reset() {
+        let src = "i = 0; while (i < 3) i = i + 1; print(i);";
+        let program = Program::lower(&syntax_analyzer::parse_str(src).unwrap()).unwrap();
+        let mut machine = RegisterMachine::new(program).unwrap();
+        for _ in 0..2 {
+            let mut out = Vec::new();
+            machine.execute(&mut out).unwrap();
+            assert_eq!(b"3", &out[..]);
+        }
+        assert_eq!(&[3], machine.data());
+    }
+
+    #[test]
+    fn test_errors() {
+        let src = "x = 0; y = rand(x);";
+        let program = Program::lower(&syntax_analyzer::parse_str(src).unwrap()).unwrap();
+        match RegisterMachine::interpret(program, &mut Vec::new()) {
+            Err(e) => assert!(e.to_string().contains("rand: 0 is not positive")),
+            Ok(_) => panic!("rand(0) is accepted"),
+        }
+
+        let src = "x = 0; y = 5 / x;";
+        let program = Program::lower(&syntax_analyzer::parse_str(src).unwrap()).unwrap();
+        match RegisterMachine::interpret(program, &mut Vec::new()) {
+            Err(e) => assert!(e.to_string().contains("division by zero"), "{}", e),
+            Ok(_) => panic!("division by zero is accepted"),
+        }
+        let src = "x = 0; n = -1; y = x ** n;";
+        let program = Program::lower(&syntax_analyzer::parse_str(src).unwrap()).unwrap();
+        match RegisterMachine::interpret(program, &mut Vec::new()) {
+            Err(e) => assert!(e.to_string().contains("division by zero"), "{}", e),
+            Ok(_) => panic!("0 ** -1 is accepted"),
+        }
+        // 最小値を -1 で割ると折り返す
+        assert_eq!(
+            "-2147483648 0\n",
+            run("x = -2147483647 - 1; y = -1; print(x / y, \" \", x % y, \"\\n\");")
+        );
+
+        let program = |instructions| Program {
+            instructions,
+            data_size: 1,
+            registers: 2,
+            strings: Vec::new(),
+        };
+        let invalid = [
+            (
+                vec![
+                    Instruction::Move(2, Operand::Constant(0)),
+                    Instruction::Halt,
+                ],
+                "0: register r2 is out of range",
+            ),
+            (
+                vec![Instruction::Jump(5), Instruction::Halt],
+                "0: jump target 5 is not an instruction",
+            ),
+            (
+                vec![Instruction::PrintInt(Operand::Register(0))],
+                "0: code does not end with halt",
+            ),
+        ];
+        for (instructions, message) in invalid.iter() {
+            match RegisterMachine::new(program(instructions.clone())) {
+                Err(e) => assert!(e.to_string().contains(message), "{}", e),
+                Ok(_) => panic!("invalid code is accepted: {}", message),
+            }
+        }
+
+        let store = program(vec![
+            Instruction::Store(Operand::Constant(1), Operand::Constant(5)),
+            Instruction::Halt,
+        ]);
+        match RegisterMachine::interpret(store, &mut Vec::new()) {
+            Err(e) => assert!(e.to_string().contains("data index 1 is out of range")),
+            Ok(_) => panic!("store out of data is accepted"),
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use super::*;
+
+use lexical_analyzer::float::Float;
+use lexical_analyzer::symbol::Symbol;
+use syntax_analyzer::ast_node::*;
+use syntax_analyzer::typed::{self, Type};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// 変数に、コード生成と同じ順で番号を割り当てる。
+/// 代入より前に読まれる変数があれば、文の行を付けたエラーにする。
+fn number_variables(ast: &ASTNode, variables: &mut HashMap<Symbol, Register>) -> Result<()> {
+    number_node(ast, variables).map_err(|e| ast.locate(e))
+}
+
+fn number_node(ast: &ASTNode, variables: &mut HashMap<Symbol, Register>) -> Result<()> {
+    match ast.kind() {
+        NodeKind::Identifier(identifier) => {
+            if !variables.contains_key(identifier) {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    format!("unknown identifier: {}", identifier),
+                ));
+            }
+        }
+        NodeKind::Sequence => {
+            for item in ast.sequence_items() {
+                number_variables(item, variables)?;
+            }
+        }
+        NodeKind::Assign => {
+            number_variables(ast.rhs().unwrap(), variables)?;
+            if let NodeKind::Identifier(identifier) = *ast.lhs().unwrap().kind() {
+                let next = variables.len();
+                variables.entry(identifier).or_insert(next);
+            }
+        }
+        NodeKind::DoWhile => {
+            number_variables(ast.rhs().unwrap(), variables)?;
+            number_variables(ast.lhs().unwrap(), variables)?;
+        }
+        _ => {
+            if let Some(lhs) = ast.lhs() {
+                number_variables(lhs, variables)?;
+            }
+            if let Some(rhs) = ast.rhs() {
+                number_variables(rhs, variables)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// AST から命令列を作る。式の途中の値には、それぞれ新しいレジスタを割り当てる。
+struct Lowering {
+    variables: HashMap<Symbol, Register>,
+    /// 次に割り当てるレジスタ
+    next_register: Register,
+    strings: Vec<Symbol>,
+    instructions: Vec<Instruction>,
+    /// 式のノードの型。浮動小数点数の命令を選ぶのに使う。
+    types: HashMap<*const ASTNode, Type>,
+}
+
+impl Program {
+    /// AST をレジスタマシンの命令列に変換する。
+    /// 変数の番号は CodeGenerator が割り当てる data の添字と同じになる。
+    pub fn lower(ast: &ASTNode) -> Result<Self> {
+        let mut variables = HashMap::new();
+        number_variables(ast, &mut variables)?;
+        let types = typed::check(ast)?.expression_types();
+        let mut lowering = Lowering {
+            next_register: variables.len(),
+            variables,
+            strings: Vec::new(),
+            instructions: Vec::new(),
+            types,
+        };
+        lowering.statement(ast)?;
+        lowering.instructions.push(Instruction::Halt);
+
+        Ok(Program {
+            instructions: lowering.instructions,
+            data_size: lowering.variables.len(),
+            registers: lowering.next_register,
+            strings: lowering.strings.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+}
+
+impl Lowering {
+    fn new_register(&mut self) -> Register {
+        self.next_register += 1;
+        self.next_register - 1
+    }
+
+    fn variable(&self, identifier: Symbol) -> Result<Register> {
+        self.variables.get(&identifier).copied().ok_or_else(|| {
+            CompileError::new(
+                ErrorKind::CodeGenerationError,
+                format!("unknown identifier: {}", identifier),
+            )
+        })
+    }
+
+    fn is_float(&self, ast: &ASTNode) -> bool {
+        self.types.get(&(ast as *const ASTNode)) == Some(&Type::Float)
+    }
+
+    /// 式 ast の値 value を浮動小数点数にする。整数の定数はその場で変換する。
+    fn convert_to_float(&mut self, ast: &ASTNode, value: Operand) -> Operand {
+        if self.is_float(ast) {
+            return value;
+        }
+        match value {
+            Operand::Constant(value) => Operand::Constant(Float(value as f32).to_bits()),
+            Operand::Register(_) => {
+                let r = self.new_register();
+                self.instructions
+                    .push(Instruction::Unary(UnaryOp::Itof, r, value));
+                Operand::Register(r)
+            }
+        }
+    }
+
+    /// 文字列プールの番号。なければ加える。
+    fn intern_string(&mut self, s: Symbol) -> usize {
+        match self.strings.iter().position(|&st| st == s) {
+            Some(index) => index,
+            None => {
+                self.strings.push(s);
+                self.strings.len() - 1
+            }
+        }
+    }
+
+    /// ジャンプ先が決まっていないジャンプ命令を出力し、その位置を返す
+    fn emit_jump(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    /// index のジャンプ命令のジャンプ先を次に出力する命令にする
+    fn bind(&mut self, index: usize) {
+        let next = self.instructions.len();
+        match &mut self.instructions[index] {
+            Instruction::Jump(target) | Instruction::JumpIfZero(_, target) => *target = next,
+            _ => unreachable!(),
+        }
+    }
+
+    /// 文を命令にする。エラーには文の行を付ける。
+    fn statement(&mut self, ast: &ASTNode) -> Result<()> {
+        self.statement_body(ast).map_err(|e| ast.locate(e))
+    }
+
+    fn statement_body(&mut self, ast: &ASTNode) -> Result<()> {
+        match ast.kind() {
+            NodeKind::Sequence => {
+                for item in ast.sequence_items() {
+                    self.statement(item)?;
+                }
+            }
+            NodeKind::Assign => {
+                let mut value = self.expression(ast.rhs().unwrap())?;
+                if self.is_float(ast.lhs().unwrap()) {
+                    value = self.convert_to_float(ast.rhs().unwrap(), value);
+                }
+                let variable = match *ast.lhs().unwrap().kind() {
+                    NodeKind::Identifier(identifier) => self.variable(identifier)?,
+                    _ => {
+                        return Err(CompileError::new(
+                            ErrorKind::CodeGenerationError,
+                            "identifier is expected",
+                        ))
+                    }
+                };
+                match value {
+                    // 直前の命令が値を書いた途中のレジスタを、変数に書き換える
+                    Operand::Register(r) if r >= self.variables.len() => self
+                        .instructions
+                        .last_mut()
+                        .unwrap()
+                        .set_destination(variable),
+                    _ => self.instructions.push(Instruction::Move(variable, value)),
+                }
+            }
+            NodeKind::If => {
+                let condition = self.expression(ast.lhs().unwrap())?;
+                let else_jump = self.emit_jump(Instruction::JumpIfZero(condition, 0));
+                let body = ast.rhs().unwrap();
+                self.statement(body.lhs().unwrap())?;
+                match body.rhs() {
+                    Some(else_clause) => {
+                        let end_jump = self.emit_jump(Instruction::Jump(0));
+                        self.bind(else_jump);
+                        self.statement(else_clause)?;
+                        self.bind(end_jump);
+                    }
+                    None => self.bind(else_jump),
+                }
+            }
+            NodeKind::While => {
+                let entry = self.instructions.len();
+                let condition = self.expression(ast.lhs().unwrap())?;
+                let exit_jump = self.emit_jump(Instruction::JumpIfZero(condition, 0));
+                self.statement(ast.rhs().unwrap())?;
+                self.instructions.push(Instruction::Jump(entry));
+                self.bind(exit_jump);
+            }
+            NodeKind::DoWhile => {
+                let entry = self.instructions.len();
+                self.statement(ast.rhs().unwrap())?;
+                // 条件が 0 でなければ先頭に戻る
+                let condition = self.expression(ast.lhs().unwrap())?;
+                let negated = self.new_register();
+                self.instructions
+                    .push(Instruction::Unary(UnaryOp::Not, negated, condition));
+                self.instructions
+                    .push(Instruction::JumpIfZero(Operand::Register(negated), entry));
+            }
+            NodeKind::Prtc => {
+                let value = self.expression(ast.lhs().unwrap())?;
+                self.instructions.push(Instruction::PrintChar(value));
+            }
+            NodeKind::Prti => {
+                let value = self.expression(ast.lhs().unwrap())?;
+                if self.is_float(ast.lhs().unwrap()) {
+                    self.instructions.push(Instruction::PrintFloat(value));
+                } else {
+                    self.instructions.push(Instruction::PrintInt(value));
+                }
+            }
+            NodeKind::Exit => {
+                let value = self.expression(ast.lhs().unwrap())?;
+                self.instructions.push(Instruction::Exit(value));
+            }
+            NodeKind::Assert => {
+                let condition = self.expression(ast.lhs().unwrap())?;
+                let index = self.intern_string(Symbol::intern(&ast.assertion_message()));
+                self.instructions.push(Instruction::Assert(
+                    condition,
+                    Operand::Constant(index as i32),
+                ));
+            }
+            NodeKind::Prts => {
+                let value = self.expression(ast.lhs().unwrap())?;
+                self.instructions.push(Instruction::PrintString(value));
+            }
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::CodeGenerationError,
+                    "unknown instruction",
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, ast: &ASTNode) -> Result<Operand> {
+        // 被演算子のどちらかが浮動小数点数なら、浮動小数点数の命令にする
+        let float = ast.children().any(|child| self.is_float(child));
+        let float_op = match ast.kind() {
+            NodeKind::Negate => Some(UnaryOp::FNeg),
+            _ => None,
+        };
+        let float_binary_op = match ast.kind() {
+            NodeKind::Multiply => Some(BinaryOp::FMul),
+            NodeKind::Divide => Some(BinaryOp::FDiv),
+            NodeKind::Add => Some(BinaryOp::FAdd),
+            NodeKind::Subtract => Some(BinaryOp::FSub),
+            NodeKind::Less => Some(BinaryOp::FLt),
+            NodeKind::LessEqual => Some(BinaryOp::FLe),
+            NodeKind::Greater => Some(BinaryOp::FGt),
+            NodeKind::GreaterEqual => Some(BinaryOp::FGe),
+            NodeKind::Equal => Some(BinaryOp::FEq),
+            NodeKind::NotEqual => Some(BinaryOp::FNe),
+            _ => None,
+        };
+        let unary_op = match ast.kind() {
+            NodeKind::Negate => Some(UnaryOp::Neg),
+            NodeKind::Not => Some(UnaryOp::Not),
+            _ => None,
+        };
+        let binary_op = match ast.kind() {
+            NodeKind::Multiply => Some(BinaryOp::Mul),
+            NodeKind::Divide => Some(BinaryOp::Div),
+            NodeKind::Mod => Some(BinaryOp::Mod),
+            NodeKind::Add => Some(BinaryOp::Add),
+            NodeKind::Subtract => Some(BinaryOp::Sub),
+            NodeKind::Less => Some(BinaryOp::Lt),
+            NodeKind::LessEqual => Some(BinaryOp::Le),
+            NodeKind::Greater => Some(BinaryOp::Gt),
+            NodeKind::GreaterEqual => Some(BinaryOp::Ge),
+            NodeKind::Equal => Some(BinaryOp::Eq),
+            NodeKind::NotEqual => Some(BinaryOp::Ne),
+            NodeKind::And => Some(BinaryOp::And),
+            NodeKind::Or => Some(BinaryOp::Or),
+            NodeKind::BitAnd => Some(BinaryOp::BitAnd),
+            NodeKind::BitOr => Some(BinaryOp::BitOr),
+            NodeKind::BitXor => Some(BinaryOp::BitXor),
+            NodeKind::ShiftLeft => Some(BinaryOp::Shl),
+            NodeKind::ShiftRight => Some(BinaryOp::Shr),
+            NodeKind::Power => Some(BinaryOp::Pow),
+            _ => None,
+        };
+
+        let instruction = match ast.kind() {
+            NodeKind::Identifier(identifier) => {
+                return Ok(Operand::Register(self.variable(*identifier)?))
+            }
+            // レジスタマシンは 32 ビットの値だけを扱う
+            NodeKind::Integer(value) => {
+                return i32::try_from(*value).map(Operand::Constant).map_err(|_| {
+                    CompileError::new(
+                        ErrorKind::CodeGenerationError,
+                        "64-bit integers are not supported by the register machine",
+                    )
+                })
+            }
+            // 浮動小数点数の値は f32 のビット列で表す
+            NodeKind::Float(value) => return Ok(Operand::Constant(value.to_bits())),
+            // 文字列の値は文字列プールの番号で表す
+            NodeKind::String(s) => {
+                return Ok(Operand::Constant(self.intern_string(*s) as i32));
+            }
+            NodeKind::Rand => {
+                let n = self.expression(ast.lhs().unwrap())?;
+                Instruction::Rand(self.new_register(), n)
+            }
+            NodeKind::Clock => Instruction::Clock(self.new_register()),
+            NodeKind::Getc => Instruction::GetChar(self.new_register()),
+            NodeKind::Geti => Instruction::GetInt(self.new_register()),
+            NodeKind::ToInt | NodeKind::ToFloat => {
+                let operand = ast.lhs().unwrap();
+                let a = self.expression(operand)?;
+                match (ast.kind(), self.is_float(operand)) {
+                    (NodeKind::ToInt, true) => {
+                        Instruction::Unary(UnaryOp::Ftoi, self.new_register(), a)
+                    }
+                    (NodeKind::ToFloat, false) => return Ok(self.convert_to_float(operand, a)),
+                    // すでに目的の型になっている
+                    _ => return Ok(a),
+                }
+            }
+            _ if float && (float_op.is_some() || float_binary_op.is_some()) => {
+                if let Some(op) = float_op {
+                    let a = self.expression(ast.lhs().unwrap())?;
+                    Instruction::Unary(op, self.new_register(), a)
+                } else {
+                    let (lhs, rhs) = (ast.lhs().unwrap(), ast.rhs().unwrap());
+                    let a = self.expression(lhs)?;
+                    let a = self.convert_to_float(lhs, a);
+                    let b = self.expression(rhs)?;
+                    let b = self.convert_to_float(rhs, b);
+                    Instruction::Binary(float_binary_op.unwrap(), self.new_register(), a, b)
+                }
+            }
+            _ => {
+                if let Some(op) = unary_op {
+                    let a = self.expression(ast.lhs().unwrap())?;
+                    Instruction::Unary(op, self.new_register(), a)
+                } else if let Some(op) = binary_op {
+                    let a = self.expression(ast.lhs().unwrap())?;
+                    let b = self.expression(ast.rhs().unwrap())?;
+                    Instruction::Binary(op, self.new_register(), a, b)
+                } else {
+                    return Err(CompileError::new(
+                        ErrorKind::CodeGenerationError,
+                        "unknown instruction",
+                    ));
+                }
+            }
+        };
+        self.instructions.push(instruction);
+        Ok(Operand::Register(instruction.destination().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower(src: &str) -> Program {
+        Program::lower(&syntax_analyzer::parse_str(src).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_lower() {
+        let program = lower(
+            r#"count = 1;
+while (count < 10) {
+    print(count * 2 + 1, "\n");
+    count = count + 1;
+}
+"#,
+        );
+        assert_eq!(
+            r#"Datasize: 1 Registers: 5 Strings: 1
+"\n"
+    0 r0 = 1
+    1 r1 = lt r0, 10
+    2 jz r1, 9
+    3 r2 = mul r0, 2
+    4 r3 = add r2, 1
+    5 prti r3
+    6 prts 0
+    7 r0 = add r0, 1
+    8 jmp 1
+    9 halt
+"#,
+            program.to_string()
+        );
+    }
+
+    #[test]
+    fn test_lower_input() {
+        let program = lower(r#"a = geti(); b = geti(); print(a + b, "\n", getc());"#);
+        assert_eq!("r0 = geti", program.instructions[0].to_string());
+        let mut machine = RegisterMachine::new(program).unwrap();
+        machine.set_input(Box::new(&b"2 3\nx"[..]));
+        let mut out = Vec::new();
+        machine.execute(&mut out).unwrap();
+        assert_eq!(b"5\n10", &out[..]);
+    }
+
+    #[test]
+    fn test_lower_control() {
+        let program = lower("x = -1; if (x) y = x; else { do y = rand(6); while (!y); }");
+        assert_eq!(
+            r#"Datasize: 2 Registers: 6 Strings: 0
+    0 r0 = neg 1
+    1 jz r0, 4
+    2 r1 = r0
+    3 jmp 8
+    4 r1 = rand 6
+    5 r4 = not r1
+    6 r5 = not r4
+    7 jz r5, 4
+    8 halt
+"#,
+            program.to_string()
+        );
+
+        let ast = syntax_analyzer::parse_str("x = 1;\nx = y;").unwrap();
+        match Program::lower(&ast) {
+            Err(e) => {
+                assert!(e.to_string().contains("unknown identifier: y"));
+                assert_eq!(Some(2), e.line());
+            }
+            Ok(_) => panic!("undefined variable is accepted"),
+        }
+    }
+
+    #[test]
+    fn test_lower_floats() {
+        let options = syntax_analyzer::ParserOptions {
+            dialect: lexical_analyzer::dialect::Dialect::float(),
+            ..syntax_analyzer::ParserOptions::default()
+        };
+        let src = r#"x = 1; x = x / 4.0; print(-x, int(x * 2), "\n");"#;
+        let ast = syntax_analyzer::parse_str_with(src, &options).unwrap();
+        let program = Program::lower(&ast).unwrap();
+        assert_eq!(
+            r#"Datasize: 1 Registers: 5 Strings: 1
+"\n"
+    0 r0 = 1065353216
+    1 r0 = fdiv r0, 1082130432
+    2 r2 = fneg r0
+    3 prtf r2
+    4 r3 = fmul r0, 1073741824
+    5 r4 = ftoi r3
+    6 prti r4
+    7 prts 0
+    8 halt
+"#,
+            program.to_string()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use super::*;
+
+/// クイックソート。中央の要素を軸に Lomuto の方法で分割する。
+/// 短いほうを再帰で、長いほうをループで整列して、再帰の深さを log n に抑える。
+pub struct QuickSort;
+
+impl Sorter for QuickSort {
+    fn name(&self) -> &'static str {
+        "quick sort"
+    }
+
+    fn sort_tracked<T: Ord + Clone>(&self, data: &mut Tracked<T>) {
+        quick_sort(data, 0, data.len());
+    }
+}
+
+/// lo から hi の手前までを整列する
+fn quick_sort<T: Ord>(data: &mut Tracked<T>, mut lo: usize, mut hi: usize) {
+    while hi - lo > 1 {
+        let last = hi - 1;
+        data.swap(lo + (hi - lo) / 2, last);
+        let mut p = lo;
+        for i in lo..last {
+            if data.compare(i, last) == Ordering::Less {
+                if i != p {
+                    data.swap(i, p);
+                }
+                p += 1;
+            }
+        }
+        data.swap(p, last);
+        if p - lo < hi - p {
+            quick_sort(data, lo, p);
+            lo = p + 1;
+        } else {
+            quick_sort(data, p + 1, hi);
+            hi = p;
+        }
+    }
+}
+
+/// トップダウンのマージソート。左半分を取り出しておき、右半分と併合しながら書き戻す。
+pub struct MergeSort;
+
+impl Sorter for MergeSort {
+    fn name(&self) -> &'static str {
+        "merge sort"
+    }
+
+    fn sort_tracked<T: Ord + Clone>(&self, data: &mut Tracked<T>) {
+        merge_sort(data, 0, data.len());
+    }
+}
+
+fn merge_sort<T: Ord + Clone>(data: &mut Tracked<T>, lo: usize, hi: usize) {
+    if hi - lo <= 1 {
+        return;
+    }
+    let mid = lo + (hi - lo) / 2;
+    merge_sort(data, lo, mid);
+    merge_sort(data, mid, hi);
+
+    let left = data.to_vec(lo, mid);
+    let (mut i, mut j) = (0, mid);
+    for k in lo..hi {
+        // 等しければ左を先にして安定にする
+        if i < left.len() && (j == hi || data.compare_value(&left[i], j) != Ordering::Greater) {
+            data.write(k, left[i].clone());
+            i += 1;
+        } else if i < left.len() {
+            let value = data.get(j).clone();
+            data.write(k, value);
+            j += 1;
+        } else {
+            // 残りの右半分はもう正しい位置にある
+            break;
+        }
+    }
+}
+
+/// ヒープソート。最大ヒープを作り、根を末尾と入れ替えることを繰り返す。
+pub struct HeapSort;
+
+impl Sorter for HeapSort {
+    fn name(&self) -> &'static str {
+        "heap sort"
+    }
+
+    fn sort_tracked<T: Ord + Clone>(&self, data: &mut Tracked<T>) {
+        let n = data.len();
+        for root in (0..n / 2).rev() {
+            sift_down(data, root, n);
+        }
+        for end in (1..n).rev() {
+            data.swap(0, end);
+            sift_down(data, 0, end);
+        }
+    }
+}
+
+/// root から下を、 end の手前までを範囲とする最大ヒープに直す
+fn sift_down<T: Ord>(data: &mut Tracked<T>, mut root: usize, end: usize) {
+    loop {
+        let mut child = 2 * root + 1;
+        if child >= end {
+            break;
+        }
+        if child + 1 < end && data.compare(child, child + 1) == Ordering::Less {
+            child += 1;
+        }
+        if data.compare(root, child) != Ordering::Less {
+            break;
+        }
+        data.swap(root, child);
+        root = child;
+    }
+}
+
+/// シェルソート。間隔を半分ずつにしながら、間隔ごとに挿入ソートする。
+pub struct ShellSort;
+
+impl Sorter for ShellSort {
+    fn name(&self) -> &'static str {
+        "shell sort"
+    }
+
+    fn sort_tracked<T: Ord + Clone>(&self, data: &mut Tracked<T>) {
+        let n = data.len();
+        let mut gap = n / 2;
+        while gap > 0 {
+            for i in gap..n {
+                let mut j = i;
+                while j >= gap && data.compare(j - gap, j) == Ordering::Greater {
+                    data.swap(j - gap, j);
+                    j -= gap;
+                }
+            }
+            gap /= 2;
+        }
+    }
+}
+
+/// バブルソート。入れ替えのなかった周回で止める。
+pub struct BubbleSort;
+
+impl Sorter for BubbleSort {
+    fn name(&self) -> &'static str {
+        "bubble sort"
+    }
+
+    fn sort_tracked<T: Ord + Clone>(&self, data: &mut Tracked<T>) {
+        let mut end = data.len();
+        while end > 1 {
+            // 最後に入れ替えた位置より後ろは整列済み
+            let mut last_swap = 0;
+            for i in 1..end {
+                if data.compare(i - 1, i) == Ordering::Greater {
+                    data.swap(i - 1, i);
+                    last_swap = i;
+                }
+            }
+            end = last_swap;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check<S: Sorter>(sorter: S) {
+        for n in 0..40 {
+            for seed in 1..4 {
+                let mut v = shuffled(n, seed);
+                sorter.sort(&mut v);
+                assert_eq!((0..n).collect::<Vec<_>>(), v, "{} n={}", sorter.name(), n);
+            }
+        }
+        let mut v = vec!["pear", "apple", "fig", "apple", "kiwi"];
+        sorter.sort(&mut v);
+        assert_eq!(vec!["apple", "apple", "fig", "kiwi", "pear"], v);
+        let mut v = vec![5; 8];
+        sorter.sort(&mut v);
+        assert_eq!(vec![5; 8], v);
+    }
+
+    #[test]
+    fn test_sorters() {
+        check(QuickSort);
+        check(MergeSort);
+        check(HeapSort);
+        check(ShellSort);
+        check(BubbleSort);
+    }
+
+    #[test]
+    fn test_stable() {
+        // 1 つ目の要素だけで比べる
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        struct Key(u8, char);
+        impl PartialOrd for Key {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Key {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+        let input = vec![Key(2, 'a'), Key(1, 'b'), Key(2, 'c'), Key(1, 'd')];
+        let tags = |v: &[Key]| v.iter().map(|k| k.1).collect::<String>();
+        let mut v = input.clone();
+        MergeSort.sort(&mut v);
+        assert_eq!("bdac", tags(&v));
+        let mut v = input;
+        BubbleSort.sort(&mut v);
+        assert_eq!("bdac", tags(&v));
+    }
+
+    #[test]
+    fn test_counters() {
+        // 整列済みの列は 1 周で終わる
+        let mut v: Vec<i32> = (0..10).collect();
+        let counters = BubbleSort.sort(&mut v);
+        assert_eq!(9, counters.comparisons);
+        assert_eq!(0, counters.swaps);
+
+        let mut v: Vec<i32> = (0..10).rev().collect();
+        let counters = BubbleSort.sort(&mut v);
+        assert_eq!(45, counters.comparisons);
+        assert_eq!(45, counters.swaps);
+
+        let mut v = vec![4, 3, 2, 1];
+        let counters = MergeSort.sort(&mut v);
+        assert_eq!(0, counters.swaps);
+        assert_eq!(4, counters.comparisons);
+        assert_eq!(8, counters.writes);
+    }
+
+    #[test]
+    fn test_sort_with() {
+        let mut v = vec![3, 1, 2];
+        let mut frames = Vec::new();
+        let counters = QuickSort.sort_with(&mut v, |data, step| {
+            if let Step::Swap(..) = step {
+                frames.push(data.to_vec());
+            }
+        });
+        assert_eq!(counters.swaps as usize, frames.len());
+        assert_eq!(Some(&vec![1, 2, 3]), frames.last());
+        assert_eq!(vec![1, 2, 3], v);
+    }
+}
@@ -0,0 +1,234 @@
+mod algorithms;
+mod visualize;
+
+pub use algorithms::{BubbleSort, HeapSort, MergeSort, QuickSort, ShellSort};
+pub use visualize::draw_bars;
+
+use std::cmp::Ordering;
+use std::fmt;
+
+/// 整列中の操作の回数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub comparisons: u64,
+    pub swaps: u64,
+    /// 値を取り出しておいて書き戻した回数。マージソートが使う。
+    pub writes: u64,
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} comparisons, {} swaps, {} writes",
+            self.comparisons, self.swaps, self.writes
+        )
+    }
+}
+
+/// 整列中の 1 回の操作。コールバックに、操作した後の列と一緒に渡す。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// i 番目と j 番目を比べた
+    Compare(usize, usize),
+    /// 取り出しておいた値と i 番目を比べた
+    CompareValue(usize),
+    /// i 番目と j 番目を入れ替えた
+    Swap(usize, usize),
+    /// i 番目に値を書いた
+    Write(usize),
+}
+
+impl Step {
+    /// 操作した位置
+    pub fn indices(self) -> Vec<usize> {
+        match self {
+            Step::Compare(i, j) | Step::Swap(i, j) => vec![i, j],
+            Step::CompareValue(i) | Step::Write(i) => vec![i],
+        }
+    }
+}
+
+/// 操作のたびに呼ぶコールバック
+type Callback<'a, T> = &'a mut dyn FnMut(&[T], Step);
+
+/// 操作を数えながら列を並べ替える。整列のアルゴリズムはこれを通して列に触る。
+pub struct Tracked<'a, T> {
+    data: &'a mut [T],
+    counters: Counters,
+    on_step: Option<Callback<'a, T>>,
+}
+
+impl<'a, T: Ord> Tracked<'a, T> {
+    pub fn new(data: &'a mut [T]) -> Self {
+        Tracked {
+            data,
+            counters: Counters::default(),
+            on_step: None,
+        }
+    }
+
+    /// 操作のたびに on_step を呼ぶ
+    pub fn with_callback(data: &'a mut [T], on_step: Callback<'a, T>) -> Self {
+        Tracked {
+            data,
+            counters: Counters::default(),
+            on_step: Some(on_step),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn get(&self, i: usize) -> &T {
+        &self.data[i]
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    fn notify(&mut self, step: Step) {
+        if let Some(on_step) = self.on_step.as_mut() {
+            on_step(self.data, step);
+        }
+    }
+
+    /// i 番目と j 番目を比べる
+    pub fn compare(&mut self, i: usize, j: usize) -> Ordering {
+        self.counters.comparisons += 1;
+        let ordering = self.data[i].cmp(&self.data[j]);
+        self.notify(Step::Compare(i, j));
+        ordering
+    }
+
+    /// 列の外に取り出しておいた value と i 番目を比べる
+    pub fn compare_value(&mut self, value: &T, i: usize) -> Ordering {
+        self.counters.comparisons += 1;
+        let ordering = value.cmp(&self.data[i]);
+        self.notify(Step::CompareValue(i));
+        ordering
+    }
+
+    pub fn swap(&mut self, i: usize, j: usize) {
+        self.counters.swaps += 1;
+        self.data.swap(i, j);
+        self.notify(Step::Swap(i, j));
+    }
+
+    pub fn write(&mut self, i: usize, value: T) {
+        self.counters.writes += 1;
+        self.data[i] = value;
+        self.notify(Step::Write(i));
+    }
+}
+
+impl<'a, T: Ord + Clone> Tracked<'a, T> {
+    /// lo から hi の手前までを複製する。数えない。
+    pub fn to_vec(&self, lo: usize, hi: usize) -> Vec<T> {
+        self.data[lo..hi].to_vec()
+    }
+}
+
+/// 整列のアルゴリズム
+pub trait Sorter {
+    fn name(&self) -> &'static str;
+
+    /// data を昇順に並べる
+    fn sort_tracked<T: Ord + Clone>(&self, data: &mut Tracked<T>);
+
+    /// v を昇順に並べ、操作の回数を返す
+    fn sort<T: Ord + Clone>(&self, v: &mut [T]) -> Counters {
+        let mut tracked = Tracked::new(v);
+        self.sort_tracked(&mut tracked);
+        tracked.counters()
+    }
+
+    /// v を昇順に並べ、操作のたびに on_step を呼ぶ
+    fn sort_with<T, F>(&self, v: &mut [T], mut on_step: F) -> Counters
+    where
+        T: Ord + Clone,
+        F: FnMut(&[T], Step),
+    {
+        let mut tracked = Tracked::with_callback(v, &mut on_step);
+        self.sort_tracked(&mut tracked);
+        tracked.counters()
+    }
+}
+
+/// 決まった種 seed から作る 0..n の並べ替え。 seed は 0 以外にする。
+pub fn shuffled(n: usize, seed: u64) -> Vec<usize> {
+    let mut v: Vec<usize> = (0..n).collect();
+    let mut x = seed;
+    for i in (1..n).rev() {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        v.swap(i, (x % (i as u64 + 1)) as usize);
+    }
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffled() {
+        for n in 0..10 {
+            let mut v = shuffled(n, 1);
+            assert_eq!(v, shuffled(n, 1));
+            v.sort_unstable();
+            assert_eq!((0..n).collect::<Vec<_>>(), v);
+        }
+        assert_ne!(shuffled(10, 1), shuffled(10, 2));
+    }
+
+    #[test]
+    fn test_tracked() {
+        let mut v = vec![3, 1, 2];
+        let mut steps = Vec::new();
+        let mut on_step = |data: &[i32], step| steps.push((data.to_vec(), step));
+        let mut tracked = Tracked::with_callback(&mut v, &mut on_step);
+        assert_eq!(Ordering::Greater, tracked.compare(0, 1));
+        tracked.swap(0, 1);
+        let saved = tracked.to_vec(2, 3);
+        assert_eq!(Ordering::Less, tracked.compare_value(&saved[0], 1));
+        tracked.write(1, 5);
+        assert_eq!(
+            Counters {
+                comparisons: 2,
+                swaps: 1,
+                writes: 1
+            },
+            tracked.counters()
+        );
+        assert_eq!(
+            vec![
+                (vec![3, 1, 2], Step::Compare(0, 1)),
+                (vec![1, 3, 2], Step::Swap(0, 1)),
+                (vec![1, 3, 2], Step::CompareValue(1)),
+                (vec![1, 5, 2], Step::Write(1)),
+            ],
+            steps
+        );
+        assert_eq!(vec![1, 5, 2], v);
+    }
+
+    #[test]
+    fn test_counters() {
+        let counters = Counters {
+            comparisons: 10,
+            swaps: 3,
+            writes: 0,
+        };
+        assert_eq!("10 comparisons, 3 swaps, 0 writes", counters.to_string());
+        assert_eq!(vec![2, 5], Step::Swap(2, 5).indices());
+        assert_eq!(vec![4], Step::Write(4).indices());
+    }
+}
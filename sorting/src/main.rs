@@ -0,0 +1,74 @@
+use sorting::{
+    draw_bars, shuffled, BubbleSort, HeapSort, MergeSort, QuickSort, ShellSort, Sorter, Step,
+};
+
+fn print_usage(name: &str) {
+    println!("Usage: {} [n] [--trace algorithm]", name);
+    println!();
+    println!("    sorts n (default: 100) shuffled numbers with each algorithm");
+    println!("    and prints the number of operations");
+    println!();
+    println!("    --trace algorithm  draw the numbers after every swap or write");
+    println!("                       (quick, merge, heap, shell or bubble)");
+}
+
+/// v を sorter で並べ、入れ替えや書き込みのたびに棒グラフを描く
+fn trace<S: Sorter>(sorter: S, mut v: Vec<usize>) {
+    let height = v.len().min(20);
+    let counters = sorter.sort_with(&mut v, |data, step| match step {
+        Step::Swap(..) | Step::Write(..) => {
+            println!("{}", draw_bars(data, height, &step.indices()));
+        }
+        _ => {}
+    });
+    println!("{}: {}", sorter.name(), counters);
+}
+
+fn report<S: Sorter>(sorter: S, mut v: Vec<usize>) {
+    let counters = sorter.sort(&mut v);
+    println!("{:>12}: {}", sorter.name(), counters);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut n = 100;
+    let mut traced = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--trace" if i + 1 < args.len() => {
+                traced = Some(args[i + 1].clone());
+                i += 1;
+            }
+            s => match s.parse() {
+                Ok(value) => n = value,
+                Err(_) => {
+                    print_usage(&args[0]);
+                    return;
+                }
+            },
+        }
+        i += 1;
+    }
+
+    // 0 の棒は見えないので 1..=n を並べる
+    let v: Vec<usize> = shuffled(n, 0x2545_f491_4f6c_dd1d)
+        .into_iter()
+        .map(|x| x + 1)
+        .collect();
+    match traced.as_deref() {
+        None => {
+            report(QuickSort, v.clone());
+            report(MergeSort, v.clone());
+            report(HeapSort, v.clone());
+            report(ShellSort, v.clone());
+            report(BubbleSort, v);
+        }
+        Some("quick") => trace(QuickSort, v),
+        Some("merge") => trace(MergeSort, v),
+        Some("heap") => trace(HeapSort, v),
+        Some("shell") => trace(ShellSort, v),
+        Some("bubble") => trace(BubbleSort, v),
+        Some(name) => println!("unknown algorithm: {}", name),
+    }
+}
@@ -0,0 +1,37 @@
+use text_canvas::TextCanvas;
+
+/// values を高さ height の棒グラフにする。 i 番目の値は i 列目の棒で、最大値が height 行になる。
+/// marked にある列は '*'、それ以外は '#' で描く。
+pub fn draw_bars(values: &[usize], height: usize, marked: &[usize]) -> TextCanvas {
+    let mut canvas = TextCanvas::new(values.len(), height);
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    for (x, &value) in values.iter().enumerate() {
+        // value * height は usize に収まらないことがあるので u128 で計算する。
+        // value <= max なので結果は height 以下になる
+        let bar = (value as u128 * height as u128).div_ceil(max as u128) as usize;
+        let c = if marked.contains(&x) { '*' } else { '#' };
+        for y in height - bar..height {
+            canvas.plot(x as i32, y as i32, c);
+        }
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_bars() {
+        let canvas = draw_bars(&[1, 4, 0, 2], 4, &[3]);
+        assert_eq!(" #\n #\n # *\n## *\n", canvas.to_string());
+
+        let canvas = draw_bars(&[10, 5], 2, &[]);
+        assert_eq!("#\n##\n", canvas.to_string());
+        assert_eq!("\n", draw_bars(&[], 1, &[]).to_string());
+
+        // 掛け算が usize に収まらない値
+        let canvas = draw_bars(&[usize::MAX, usize::MAX / 2, 1], 2, &[]);
+        assert_eq!("#\n###\n", canvas.to_string());
+    }
+}
@@ -1,17 +1,24 @@
+use lexical_analyzer::error::*;
+use lexical_analyzer::float::Float;
+use lexical_analyzer::symbol::Symbol;
+use lexical_analyzer::width::Width;
 use std::fmt;
 use std::str::Lines;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NodeKind {
-    Identifier(String),
-    String(String),
-    Integer(i32),
+    Identifier(Symbol),
+    String(Symbol),
+    Integer(i64),
+    Float(Float),
     Sequence,
     If,
     Prtc,
     Prts,
     Prti,
     While,
+    /// lhs が条件、rhs が本体。本体を実行してから条件を調べる。
+    DoWhile,
     Assign,
     Negate,
     Not,
@@ -28,6 +35,29 @@ pub enum NodeKind {
     NotEqual,
     And,
     Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    /// lhs の rhs 乗。整数だけで、負の指数では 0 の方向に丸める。
+    Power,
+    /// 0 以上 lhs 未満の擬似乱数
+    Rand,
+    /// 実行を始めてからのミリ秒
+    Clock,
+    /// 入力から読んだ 1 バイト。入力の終わりでは -1。
+    Getc,
+    /// 入力から読んだ 10 進の整数。前の空白は読み飛ばす。
+    Geti,
+    /// lhs の浮動小数点数を 0 の方向に丸めた整数。範囲外の値は飽和し、 NaN は 0 になる。
+    ToInt,
+    /// lhs の整数を浮動小数点数にする
+    ToFloat,
+    /// lhs を終了コードとして実行を終える
+    Exit,
+    /// lhs が 0 なら、 rhs の文字列を添えたエラーで実行を止める
+    Assert,
     None,
 }
 
@@ -36,6 +66,8 @@ pub struct ASTNode {
     pub(crate) kind: NodeKind,
     pub(crate) lhs: Option<Box<ASTNode>>,
     pub(crate) rhs: Option<Box<ASTNode>>,
+    /// 文が始まるソースの行。ソースから解析した文にだけある。
+    pub(crate) line: Option<usize>,
 }
 
 impl ASTNode {
@@ -56,23 +88,365 @@ impl ASTNode {
             None => None,
         }
     }
+
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// 位置のないエラー e に、この文の行を付ける。行を持たないノードなら e をそのまま返す。
+    /// 深い AST を再帰でたどる関数のスタックフレームを大きくしないように、インライン展開しない。
+    #[cold]
+    #[inline(never)]
+    pub fn locate(&self, e: CompileError) -> CompileError {
+        match self.line {
+            Some(line) => e.with_line(line),
+            None => e,
+        }
+    }
+
+    /// 定数だけからなる式なら、既定の 32 ビットの幅で計算した値を返す。
+    /// 0 除算やオーバーフローを起こす式は実行時まで評価を遅らせるため None。
+    pub fn constant_value(&self) -> Option<i64> {
+        self.constant_value_in(Width::default())
+    }
+
+    /// constant_value と同じだが、値の幅が width の場合の値を返す
+    pub fn constant_value_in(&self, width: Width) -> Option<i64> {
+        let value = match self.kind() {
+            NodeKind::Integer(value) => Some(*value),
+            NodeKind::Negate => self.lhs()?.constant_value_in(width)?.checked_neg(),
+            NodeKind::Not => Some((self.lhs()?.constant_value_in(width)? == 0) as i64),
+            NodeKind::Multiply
+            | NodeKind::Divide
+            | NodeKind::Mod
+            | NodeKind::Add
+            | NodeKind::Subtract
+            | NodeKind::Less
+            | NodeKind::LessEqual
+            | NodeKind::Greater
+            | NodeKind::GreaterEqual
+            | NodeKind::Equal
+            | NodeKind::NotEqual
+            | NodeKind::And
+            | NodeKind::Or
+            | NodeKind::BitAnd
+            | NodeKind::BitOr
+            | NodeKind::BitXor
+            | NodeKind::ShiftLeft
+            | NodeKind::ShiftRight
+            | NodeKind::Power => {
+                let lhs = self.lhs()?.constant_value_in(width)?;
+                let rhs = self.rhs()?.constant_value_in(width)?;
+                match self.kind() {
+                    NodeKind::Multiply => lhs.checked_mul(rhs),
+                    NodeKind::Divide => lhs.checked_div(rhs),
+                    NodeKind::Mod => lhs.checked_rem(rhs),
+                    NodeKind::Add => lhs.checked_add(rhs),
+                    NodeKind::Subtract => lhs.checked_sub(rhs),
+                    NodeKind::Less => Some((lhs < rhs) as i64),
+                    NodeKind::LessEqual => Some((lhs <= rhs) as i64),
+                    NodeKind::Greater => Some((lhs > rhs) as i64),
+                    NodeKind::GreaterEqual => Some((lhs >= rhs) as i64),
+                    NodeKind::Equal => Some((lhs == rhs) as i64),
+                    NodeKind::NotEqual => Some((lhs != rhs) as i64),
+                    NodeKind::And => Some((lhs != 0 && rhs != 0) as i64),
+                    NodeKind::Or => Some((lhs != 0 || rhs != 0) as i64),
+                    NodeKind::BitAnd => Some(lhs & rhs),
+                    NodeKind::BitOr => Some(lhs | rhs),
+                    NodeKind::BitXor => Some(lhs ^ rhs),
+                    NodeKind::ShiftLeft => Some(width.shl(lhs, rhs)),
+                    NodeKind::ShiftRight => Some(width.shr(lhs, rhs)),
+                    NodeKind::Power => width.pow(lhs, rhs),
+                    _ => unreachable!(),
+                }
+            }
+            _ => None,
+        };
+        value.filter(|&value| width.contains(value))
+    }
+
+    /// assert 文が失敗したときのエラーの文。行が分かればそれも含める。
+    /// インタプリタと仮想マシンで同じ文になるように、ここで組み立てる。
+    pub fn assertion_message(&self) -> String {
+        let message = match self.rhs().map(|rhs| rhs.kind()) {
+            Some(NodeKind::String(s)) => s.to_string(),
+            _ => String::new(),
+        };
+        match self.line {
+            Some(line) => format!("assertion failed at line {}: {}", line, message),
+            None => format!("assertion failed: {}", message),
+        }
+    }
+}
+
+impl ASTNode {
+    /// Sequence の lhs 側の連なりをたどり、実行順に並べた文を返す。
+    /// 文の数だけ深くなる Sequence を再帰せずに処理するために使う。
+    pub fn sequence_items(&self) -> Vec<&ASTNode> {
+        let mut items = Vec::new();
+        let mut node = self;
+        loop {
+            if let Some(rhs) = node.rhs() {
+                items.push(rhs);
+            }
+            match node.lhs() {
+                Some(lhs) if matches!(lhs.kind(), NodeKind::Sequence) => node = lhs,
+                Some(lhs) => {
+                    items.push(lhs);
+                    break;
+                }
+                None => break,
+            }
+        }
+        items.reverse();
+        items
+    }
+
+    /// sequence_items と同じ順に、 Sequence の連なりを外した文を返す。
+    pub fn into_sequence_items(self) -> Vec<ASTNode> {
+        let mut items = Vec::new();
+        let mut node = self;
+        loop {
+            if let Some(rhs) = node.rhs {
+                items.push(*rhs);
+            }
+            match node.lhs {
+                Some(lhs) if matches!(lhs.kind, NodeKind::Sequence) => node = *lhs,
+                Some(lhs) => {
+                    items.push(*lhs);
+                    break;
+                }
+                None => break,
+            }
+        }
+        items.reverse();
+        items
+    }
+
+    /// lhs, rhs の順に子を返す。
+    pub fn children(&self) -> impl Iterator<Item = &ASTNode> {
+        self.lhs().into_iter().chain(self.rhs())
+    }
+}
+
+/// AST を組み立てる関数。作ったノードには行を付けない。
+impl ASTNode {
+    pub fn new(kind: NodeKind, lhs: Option<ASTNode>, rhs: Option<ASTNode>) -> Self {
+        ASTNode {
+            kind,
+            lhs: lhs.map(Box::new),
+            rhs: rhs.map(Box::new),
+            line: None,
+        }
+    }
+
+    pub fn leaf_int(value: i64) -> Self {
+        Self::new(NodeKind::Integer(value), None, None)
+    }
+
+    pub fn leaf_float(value: f32) -> Self {
+        Self::new(NodeKind::Float(Float(value)), None, None)
+    }
+
+    /// value はエスケープを解いた文字列
+    pub fn leaf_string(value: &str) -> Self {
+        Self::new(NodeKind::String(Symbol::intern(value)), None, None)
+    }
+
+    pub fn leaf_identifier(name: &str) -> Self {
+        Self::new(NodeKind::Identifier(Symbol::intern(name)), None, None)
+    }
+
+    /// 子が lhs だけのノード。 Negate, Not, Prtc, Prti, Prts, Rand, ToInt, ToFloat に使う。
+    pub fn unary(kind: NodeKind, operand: ASTNode) -> Self {
+        Self::new(kind, Some(operand), None)
+    }
+
+    pub fn binary(kind: NodeKind, lhs: ASTNode, rhs: ASTNode) -> Self {
+        Self::new(kind, Some(lhs), Some(rhs))
+    }
+
+    /// name = value
+    pub fn assign(name: &str, value: ASTNode) -> Self {
+        Self::binary(NodeKind::Assign, Self::leaf_identifier(name), value)
+    }
+
+    /// then と else は rhs の If ノードに持つ。
+    pub fn if_else(condition: ASTNode, then: ASTNode, otherwise: Option<ASTNode>) -> Self {
+        Self::binary(
+            NodeKind::If,
+            condition,
+            Self::new(NodeKind::If, Some(then), otherwise),
+        )
+    }
+
+    /// items を順に実行する Sequence。構文解析器と同じく lhs 側に連ねる。
+    pub fn sequence<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = ASTNode>,
+    {
+        items
+            .into_iter()
+            .fold(Self::new(NodeKind::Sequence, None, None), |node, item| {
+                if node.lhs.is_none() && node.rhs.is_none() {
+                    Self::new(NodeKind::Sequence, None, Some(item))
+                } else {
+                    Self::new(NodeKind::Sequence, Some(node), Some(item))
+                }
+            })
+    }
+
+    /// 文が始まるソースの行を付ける。
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.line = Some(line);
+        self
+    }
+}
+
+/// 種類と子が同じなら等しい。行は比べないので、組み立てた AST と解析した AST も比べられる。
+impl PartialEq for ASTNode {
+    fn eq(&self, other: &Self) -> bool {
+        // Sequence の連なりが深くても再帰しないように、比べる組をスタックに積む
+        let mut pending = vec![(self, other)];
+        while let Some((a, b)) = pending.pop() {
+            if a.kind != b.kind {
+                return false;
+            }
+            for (x, y) in [(a.lhs(), b.lhs()), (a.rhs(), b.rhs())] {
+                match (x, y) {
+                    (Some(x), Some(y)) => pending.push((x, y)),
+                    (None, None) => {}
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Eq for ASTNode {}
+
+/// 入れ子の Sequence を 1 つの連なりに平らにし、空の文と空のブロックを取り除く。
+/// 実行の結果は変わらない。 if や while の本体が空になった場合は空の Sequence を残す。
+pub fn normalize(node: ASTNode) -> ASTNode {
+    Normalizer.fold(node)
+}
+
+struct Normalizer;
+
+impl Fold for Normalizer {
+    fn fold(&mut self, node: ASTNode) -> ASTNode {
+        let node = fold_children(self, node);
+        if !matches!(node.kind, NodeKind::Sequence) {
+            return node;
+        }
+        let mut items = Vec::new();
+        for item in node.into_sequence_items() {
+            if matches!(item.kind, NodeKind::Sequence) {
+                // 子は正規化済みなので、中の文は Sequence ではない
+                items.extend(item.into_sequence_items());
+            } else {
+                items.push(item);
+            }
+        }
+        ASTNode::sequence(items)
+    }
+}
+
+/// AST を読むだけのパス。 visit を上書きし、子をたどるところで walk を呼ぶ。
+pub trait Visitor<'a> {
+    /// 既定では子をたどる。
+    fn visit(&mut self, node: &'a ASTNode) {
+        walk(self, node);
+    }
+}
+
+/// node の子を順に visitor に渡す。
+/// Sequence は文の数だけ深くなるので、再帰せずに sequence_items の文を渡す。
+pub fn walk<'a, V>(visitor: &mut V, node: &'a ASTNode)
+where
+    V: Visitor<'a> + ?Sized,
+{
+    if matches!(node.kind, NodeKind::Sequence) {
+        for item in node.sequence_items() {
+            visitor.visit(item);
+        }
+    } else {
+        for child in node.children() {
+            visitor.visit(child);
+        }
+    }
+}
+
+/// AST を作り直すパス。 fold を上書きし、変えないノードは fold_children に渡す。
+pub trait Fold {
+    /// 既定では子を作り直す。
+    fn fold(&mut self, node: ASTNode) -> ASTNode {
+        fold_children(self, node)
+    }
+}
+
+/// node の子をそれぞれ folder で作り直す。種類と行はそのまま残す。
+/// Sequence の連なりは再帰せずにたどり、文を実行順に渡す。
+pub fn fold_children<F>(folder: &mut F, node: ASTNode) -> ASTNode
+where
+    F: Fold + ?Sized,
+{
+    let mut fold = |child: Option<Box<ASTNode>>| child.map(|c| Box::new(folder.fold(*c)));
+    if !matches!(node.kind, NodeKind::Sequence) {
+        let lhs = fold(node.lhs);
+        let rhs = fold(node.rhs);
+        return ASTNode {
+            kind: node.kind,
+            lhs,
+            rhs,
+            line: node.line,
+        };
+    }
+
+    // lhs 側の Sequence を外しておき、先頭の文から作り直して連ね直す
+    let mut spine = Vec::new();
+    let mut node = node;
+    let first = loop {
+        spine.push((node.kind, node.rhs, node.line));
+        match node.lhs {
+            Some(lhs) if matches!(lhs.kind, NodeKind::Sequence) => node = *lhs,
+            lhs => break fold(lhs),
+        }
+    };
+    let mut lhs = first;
+    while let Some((kind, rhs, line)) = spine.pop() {
+        lhs = Some(Box::new(ASTNode {
+            kind,
+            lhs,
+            rhs: fold(rhs),
+            line,
+        }));
+    }
+    *lhs.unwrap()
 }
 
 impl fmt::Display for ASTNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.kind {
-            NodeKind::Identifier(ref i) => write!(f, "Identifier {}\n", i)?,
-            NodeKind::String(ref s) => write!(f, "String {:?}\n", s)?,
-            NodeKind::Integer(ref i) => write!(f, "Integer {}\n", i)?,
+            NodeKind::Identifier(ref i) => writeln!(f, "Identifier {}", i)?,
+            NodeKind::String(ref s) => writeln!(f, "String {}", escape::quote(&s.text()))?,
+            NodeKind::Integer(ref i) => writeln!(f, "Integer {}", i)?,
+            NodeKind::Float(ref value) => writeln!(f, "Float {}", value)?,
             _ => {
-                write!(f, "{:?}\n", self.kind)?;
+                // assert の失敗の文には行が入るので、 "Assert 3" のように行を残す。
+                // Assert は Rosetta Code の形式にないノードなので、他の実装との互換性は崩さない。
+                match (&self.kind, self.line) {
+                    (NodeKind::Assert, Some(line)) => writeln!(f, "Assert {}", line)?,
+                    (kind, _) => writeln!(f, "{:?}", kind)?,
+                }
                 match &self.lhs {
                     Some(l) => l.fmt(f)?,
-                    None => write!(f, ";\n")?,
+                    None => writeln!(f, ";")?,
                 }
                 match &self.rhs {
                     Some(r) => r.fmt(f)?,
-                    None => write!(f, ";\n")?,
+                    None => writeln!(f, ";")?,
                 }
             }
         }
@@ -80,118 +454,435 @@ impl fmt::Display for ASTNode {
     }
 }
 
+/// ASTReader::read_ast が読む入れ子の深さの上限。
+/// 読んだ AST をたどる処理がスタックを使い切らないように、それぞれの上限より大きく取る。
+pub const MAX_READ_DEPTH: usize = 10_000;
+
+/// 平坦化した AST の 1 行
+enum Element {
+    /// 子を持たない節。 ";" なら None。
+    Leaf(Option<ASTNode>),
+    /// 続く 2 つの節を子に持つ節の種類と、 Assert の行
+    Interior(NodeKind, Option<usize>),
+}
+
+impl Element {
+    fn leaf(node: ASTNode) -> Self {
+        Element::Leaf(Some(node))
+    }
+}
+
 pub struct ASTReader<'a> {
     stream: Lines<'a>,
+    /// 最後に読んだ行の番号
+    line: usize,
 }
 
 impl<'a> ASTReader<'a> {
-    fn make_interior_node(&mut self, kind: NodeKind) -> Option<ASTNode> {
-        let lhs = self.make_node();
-        let lhs = if let Some(node) = lhs {
-            Some(Box::new(node))
-        } else {
-            None
-        };
+    /// 読んでいる行の位置を付けた ReadError
+    fn error(&self, message: String) -> CompileError {
+        CompileError::new(ErrorKind::ReadError, message).with_line(self.line)
+    }
 
-        let rhs = self.make_node();
-        let rhs = if let Some(node) = rhs {
-            Some(Box::new(node))
-        } else {
-            None
-        };
+    /// depth より 1 段深い入れ子に入る。 MAX_READ_DEPTH を超えるならエラー。
+    fn enter(&self, depth: usize) -> Result<usize> {
+        if depth >= MAX_READ_DEPTH {
+            return Err(self.error(format!("AST is too deep (limit: {})", MAX_READ_DEPTH)));
+        }
+        Ok(depth + 1)
+    }
 
-        Some(ASTNode { kind, lhs, rhs })
+    /// 種類の後に書いた値。値がなければエラー。
+    fn value<'b>(&self, kind: &str, value: Option<&'b str>) -> Result<&'b str> {
+        match value.map(str::trim) {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(self.error(format!("{} needs a value", kind))),
+        }
     }
 
-    fn make_identifier(&mut self, identifier: &str) -> Option<ASTNode> {
-        Some(ASTNode {
-            kind: NodeKind::Identifier(identifier.to_string()),
-            lhs: None,
-            rhs: None,
-        })
+    fn make_integer(&self, num_str: &str) -> Result<ASTNode> {
+        let val = num_str
+            .parse()
+            .map_err(|_| self.error(format!("invalid integer: {:?}", num_str)))?;
+        Ok(ASTNode::leaf_int(val))
     }
 
-    fn make_integer(&mut self, num_str: &str) -> Option<ASTNode> {
-        let val = num_str.parse().unwrap();
-        Some(ASTNode {
-            kind: NodeKind::Integer(val),
-            lhs: None,
-            rhs: None,
-        })
+    fn make_float(&self, num_str: &str) -> Result<ASTNode> {
+        let val = num_str
+            .parse()
+            .map_err(|_| self.error(format!("invalid float: {:?}", num_str)))?;
+        Ok(ASTNode::leaf_float(val))
     }
 
-    fn make_string(&mut self, s: &str) -> Option<ASTNode> {
-        let mut value = String::new();
-        let mut cs = s.chars();
-        let mut next_char = cs.next();
+    fn make_string(&self, s: &str) -> Result<ASTNode> {
+        let value = match escape::unquote(s) {
+            Ok((value, _)) => value,
+            Err(_) => return Err(self.error(format!("invalid string: {}", s))),
+        };
+        Ok(ASTNode::leaf_string(&value))
+    }
 
-        if Some('"') != next_char {
-            unreachable!();
+    /// 1 行読む
+    fn read_element(&mut self) -> Result<Element> {
+        let line = match self.stream.next() {
+            Some(line) => line,
+            None => {
+                return Err(CompileError::new(
+                    ErrorKind::ReadError,
+                    "unexpected end of AST",
+                ))
+            }
+        };
+        self.line += 1;
+        let mut elements = line.trim().splitn(2, ' ');
+        let kind = elements.next().unwrap_or_default();
+        let value = elements.next();
+        match kind {
+            ";" => Ok(Element::Leaf(None)),
+            "Identifier" => {
+                let identifier = self.value(kind, value)?;
+                Ok(Element::Leaf(Some(ASTNode::leaf_identifier(identifier))))
+            }
+            "Integer" => self
+                .make_integer(self.value(kind, value)?)
+                .map(Element::leaf),
+            "Float" => self.make_float(self.value(kind, value)?).map(Element::leaf),
+            "String" => self
+                .make_string(self.value(kind, value)?)
+                .map(Element::leaf),
+            "Sequence" => Ok(Element::Interior(NodeKind::Sequence, None)),
+            "If" => Ok(Element::Interior(NodeKind::If, None)),
+            "Prtc" => Ok(Element::Interior(NodeKind::Prtc, None)),
+            "Prts" => Ok(Element::Interior(NodeKind::Prts, None)),
+            "Prti" => Ok(Element::Interior(NodeKind::Prti, None)),
+            "While" => Ok(Element::Interior(NodeKind::While, None)),
+            "DoWhile" => Ok(Element::Interior(NodeKind::DoWhile, None)),
+            "Assign" => Ok(Element::Interior(NodeKind::Assign, None)),
+            "Negate" => Ok(Element::Interior(NodeKind::Negate, None)),
+            "Not" => Ok(Element::Interior(NodeKind::Not, None)),
+            "Multiply" => Ok(Element::Interior(NodeKind::Multiply, None)),
+            "Divide" => Ok(Element::Interior(NodeKind::Divide, None)),
+            "Mod" => Ok(Element::Interior(NodeKind::Mod, None)),
+            "Add" => Ok(Element::Interior(NodeKind::Add, None)),
+            "Subtract" => Ok(Element::Interior(NodeKind::Subtract, None)),
+            "Less" => Ok(Element::Interior(NodeKind::Less, None)),
+            "LessEqual" => Ok(Element::Interior(NodeKind::LessEqual, None)),
+            "Greater" => Ok(Element::Interior(NodeKind::Greater, None)),
+            "GreaterEqual" => Ok(Element::Interior(NodeKind::GreaterEqual, None)),
+            "Equal" => Ok(Element::Interior(NodeKind::Equal, None)),
+            "NotEqual" => Ok(Element::Interior(NodeKind::NotEqual, None)),
+            "And" => Ok(Element::Interior(NodeKind::And, None)),
+            "Or" => Ok(Element::Interior(NodeKind::Or, None)),
+            "BitAnd" => Ok(Element::Interior(NodeKind::BitAnd, None)),
+            "BitOr" => Ok(Element::Interior(NodeKind::BitOr, None)),
+            "BitXor" => Ok(Element::Interior(NodeKind::BitXor, None)),
+            "ShiftLeft" => Ok(Element::Interior(NodeKind::ShiftLeft, None)),
+            "ShiftRight" => Ok(Element::Interior(NodeKind::ShiftRight, None)),
+            "Power" => Ok(Element::Interior(NodeKind::Power, None)),
+            "Rand" => Ok(Element::Interior(NodeKind::Rand, None)),
+            "Clock" => Ok(Element::Interior(NodeKind::Clock, None)),
+            "Getc" => Ok(Element::Interior(NodeKind::Getc, None)),
+            "Geti" => Ok(Element::Interior(NodeKind::Geti, None)),
+            "ToInt" => Ok(Element::Interior(NodeKind::ToInt, None)),
+            "ToFloat" => Ok(Element::Interior(NodeKind::ToFloat, None)),
+            "Exit" => Ok(Element::Interior(NodeKind::Exit, None)),
+            "Assert" => {
+                let line = value.and_then(|line| line.trim().parse().ok());
+                Ok(Element::Interior(NodeKind::Assert, line))
+            }
+            _ => Err(self.error(format!("unknown node: {:?}", line.trim()))),
         }
-        next_char = cs.next();
+    }
+
+    /// 平坦化した AST を読む。 ";" だけなら空のプログラムとする。
+    /// 読めない行があれば、その行の番号を付けた ReadError を返す。
+    /// 長い文の並びや深い式でもスタックを使い切らないように、再帰せずに読む。
+    pub fn read_ast(stream: Lines) -> Result<ASTNode> {
+        let mut reader = ASTReader { stream, line: 0 };
+        // 子を読んでいる途中の節。種類、 Assert の行、読み終えた左の子。
+        let mut stack: Vec<(NodeKind, Option<usize>, Option<Option<ASTNode>>)> = Vec::new();
+        // 入れ子の深さ。文の並びの Sequence の連なりは、左の子を読んでいる間は数えない。
+        let mut depth = 0;
         loop {
-            match next_char {
-                Some('"') => break,
-                Some('\\') => {
-                    let escape = cs.next();
-                    match escape {
-                        Some('n') => value.push('\n'),
-                        Some('\\') => value.push('\\'),
-                        _ => unreachable!(),
+            let mut node = match reader.read_element()? {
+                Element::Leaf(node) => node,
+                Element::Interior(kind, line) => {
+                    if kind != NodeKind::Sequence {
+                        depth = reader.enter(depth)?;
+                    }
+                    stack.push((kind, line, None));
+                    continue;
+                }
+            };
+            // 子がそろった節を組み立てて、親の子にする
+            loop {
+                match stack.last_mut() {
+                    None => return Ok(node.unwrap_or_else(|| ASTNode::sequence(Vec::new()))),
+                    Some((kind, _, lhs @ None)) => {
+                        if *kind == NodeKind::Sequence {
+                            depth = reader.enter(depth)?;
+                        }
+                        *lhs = Some(node);
+                        break;
+                    }
+                    Some(_) => {
+                        depth -= 1;
+                        let (kind, line, lhs) = stack.pop().unwrap();
+                        let parent = ASTNode::new(kind, lhs.unwrap(), node);
+                        node = Some(match line {
+                            Some(line) => parent.with_line(line),
+                            None => parent,
+                        });
                     }
                 }
-                Some(c) => value.push(c),
-                None => unreachable!(),
             }
-            next_char = cs.next();
-        }
-        Some(ASTNode {
-            kind: NodeKind::String(value),
-            lhs: None,
-            rhs: None,
-        })
-    }
-
-    fn make_node(&mut self) -> Option<ASTNode> {
-        match self.stream.next() {
-            Some(line) => {
-                let elements: Vec<&str> = line.trim().splitn(2, ' ').collect();
-                match elements[0] {
-                    ";" => None,
-                    "Identifier" => self.make_identifier(elements[1].trim()),
-                    "Integer" => self.make_integer(elements[1].trim()),
-                    "String" => self.make_string(elements[1].trim()),
-                    "Sequence" => self.make_interior_node(NodeKind::Sequence),
-                    "If" => self.make_interior_node(NodeKind::If),
-                    "Prtc" => self.make_interior_node(NodeKind::Prtc),
-                    "Prts" => self.make_interior_node(NodeKind::Prts),
-                    "Prti" => self.make_interior_node(NodeKind::Prti),
-                    "While" => self.make_interior_node(NodeKind::While),
-                    "Assign" => self.make_interior_node(NodeKind::Assign),
-                    "Negate" => self.make_interior_node(NodeKind::Negate),
-                    "Not" => self.make_interior_node(NodeKind::Not),
-                    "Multiply" => self.make_interior_node(NodeKind::Multiply),
-                    "Divide" => self.make_interior_node(NodeKind::Divide),
-                    "Mod" => self.make_interior_node(NodeKind::Mod),
-                    "Add" => self.make_interior_node(NodeKind::Add),
-                    "Subtract" => self.make_interior_node(NodeKind::Subtract),
-                    "Less" => self.make_interior_node(NodeKind::Less),
-                    "LessEqual" => self.make_interior_node(NodeKind::LessEqual),
-                    "Greater" => self.make_interior_node(NodeKind::Greater),
-                    "GreaterEqual" => self.make_interior_node(NodeKind::GreaterEqual),
-                    "Equal" => self.make_interior_node(NodeKind::Equal),
-                    "NotEqual" => self.make_interior_node(NodeKind::NotEqual),
-                    "And" => self.make_interior_node(NodeKind::And),
-                    "Or" => self.make_interior_node(NodeKind::Or),
-                    _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_str;
+
+    #[test]
+    fn test_builder() {
+        let built = ASTNode::sequence(vec![
+            ASTNode::assign("x", ASTNode::leaf_int(1)),
+            ASTNode::if_else(
+                ASTNode::binary(
+                    NodeKind::Less,
+                    ASTNode::leaf_identifier("x"),
+                    ASTNode::leaf_int(2),
+                ),
+                // print は Sequence にする
+                ASTNode::sequence(vec![ASTNode::unary(
+                    NodeKind::Prts,
+                    ASTNode::leaf_string("a\n"),
+                )]),
+                Some(ASTNode::sequence(vec![ASTNode::unary(
+                    NodeKind::Prti,
+                    ASTNode::unary(NodeKind::Negate, ASTNode::leaf_identifier("x")),
+                )])),
+            ),
+        ]);
+        let parsed = parse_str(r#"x = 1; if (x < 2) print("a\n"); else print(-x);"#).unwrap();
+        assert_eq!(parsed, built);
+
+        assert_eq!(parse_str("").unwrap(), ASTNode::sequence(vec![]));
+        assert_eq!(Some(3), ASTNode::leaf_int(0).with_line(3).line());
+    }
+
+    #[test]
+    fn test_children() {
+        let node = ASTNode::binary(NodeKind::Add, ASTNode::leaf_int(1), ASTNode::leaf_int(2));
+        let values: Vec<_> = node.children().map(|c| format!("{}", c)).collect();
+        assert_eq!(vec!["Integer 1\n", "Integer 2\n"], values);
+        assert_eq!(0, ASTNode::leaf_int(1).children().count());
+        let node = ASTNode::unary(NodeKind::Not, ASTNode::leaf_int(1));
+        assert_eq!(1, node.children().count());
+    }
+
+    /// 変数の名前を読んだ順に集める
+    struct Identifiers(Vec<String>);
+
+    impl Visitor<'_> for Identifiers {
+        fn visit(&mut self, node: &ASTNode) {
+            match node.kind() {
+                NodeKind::Identifier(identifier) => self.0.push(identifier.to_string()),
+                _ => walk(self, node),
+            }
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let ast = parse_str("a = b + c; while (d) { e = -f; }").unwrap();
+        let mut identifiers = Identifiers(Vec::new());
+        identifiers.visit(&ast);
+        assert_eq!(vec!["a", "b", "c", "d", "e", "f"], identifiers.0);
+
+        // 文の数だけ再帰しない
+        let ast = parse_str(&"x = y;".repeat(2_000)).unwrap();
+        let mut identifiers = Identifiers(Vec::new());
+        identifiers.visit(&ast);
+        assert_eq!(4_000, identifiers.0.len());
+    }
+
+    /// 整数どうしの足し算を計算しておく
+    struct FoldAdd;
+
+    impl Fold for FoldAdd {
+        fn fold(&mut self, node: ASTNode) -> ASTNode {
+            let node = fold_children(self, node);
+            match (
+                node.kind(),
+                node.lhs().map(ASTNode::kind),
+                node.rhs().map(ASTNode::kind),
+            ) {
+                (NodeKind::Add, Some(NodeKind::Integer(a)), Some(NodeKind::Integer(b))) => {
+                    ASTNode::leaf_int(a.wrapping_add(*b))
                 }
+                _ => node,
             }
-            None => None,
         }
     }
 
-    pub fn read_ast(stream: Lines) -> ASTNode {
-        let mut reader = ASTReader { stream };
-        reader.make_node().unwrap()
+    #[test]
+    fn test_fold() {
+        let ast = parse_str("x = 1 + 2 + 3;\nprint(x + (4 + 5));").unwrap();
+        let folded = FoldAdd.fold(ast);
+        let expected = parse_str("x = 6;\nprint(x + 9);").unwrap();
+        assert_eq!(expected, folded);
+        // 文の行は残す
+        assert_eq!(Some(2), folded.sequence_items()[1].line());
+
+        // 何も変えなければ同じ AST になる
+        struct Identity;
+        impl Fold for Identity {}
+        let src = "{ x = 1; ; } if (x) { putc(x); } else while (x) x = x - 1; do ; while (0);";
+        let ast = parse_str(src).unwrap();
+        let expected = parse_str(src).unwrap();
+        assert_eq!(expected, Identity.fold(ast));
+
+        let ast = parse_str(&"x = 1 + 1;".repeat(2_000)).unwrap();
+        let folded = FoldAdd.fold(ast);
+        assert_eq!(2_000, folded.sequence_items().len());
+    }
+
+    #[test]
+    fn test_constant_value() {
+        // 最初の代入の右辺を 32 ビットと 64 ビットの幅で畳み込んだ値
+        let value = |src: &str| {
+            let ast = parse_str(src).unwrap();
+            let rhs = ast.sequence_items()[0].rhs().unwrap();
+            (rhs.constant_value(), rhs.constant_value_in(Width::W64))
+        };
+        assert_eq!((None, Some(1 << 32)), value("x = 65536 * 65536;"));
+        assert_eq!((Some(2), Some(1 << 33)), value("x = 1 << 33;"));
+        assert_eq!((Some(1), Some(1)), value("x = -2147483647 - 1 < 0;"));
+        assert_eq!((None, None), value("x = 7 / (1 - 1);"));
+    }
+
+    #[test]
+    fn test_eq() {
+        let parse = |src| parse_str(src).unwrap();
+        assert_eq!(parse("x = 1 + 2;"), parse("x = (1 + 2);"));
+        // 行は比べない
+        assert_eq!(parse("x = 1;\ny = 2;"), parse("x = 1; y = 2;"));
+        assert_ne!(parse("x = 1 + 2;"), parse("x = 2 + 1;"));
+        assert_ne!(parse("x = 1;"), parse("x = 1; y = 2;"));
+        assert_ne!(parse("if (x) y = 1;"), parse("if (x) y = 1; else ;"));
+
+        let src = "x = 1;".repeat(2_000);
+        assert_eq!(parse(&src), parse(&src));
+    }
+
+    #[test]
+    fn test_assert_line() {
+        // 平坦化した AST を読み直しても、 assert の失敗の文に行が残る
+        let ast = parse_str("x = 1;\nassert(x > 1, \"big\");").unwrap();
+        let flat = ast.to_string();
+        assert!(flat.contains("\nAssert 2\n"), "{}", flat);
+        let read = ASTReader::read_ast(flat.lines()).unwrap();
+        assert_eq!(ast, read);
+        assert_eq!(flat, read.to_string());
+        let assertion = read.sequence_items()[1];
+        assert_eq!(
+            "assertion failed at line 2: big",
+            assertion.assertion_message()
+        );
+
+        // 行のない Assert も読める
+        let read = ASTReader::read_ast("Assert\nInteger 0\n;\n".lines()).unwrap();
+        assert_eq!("assertion failed: ", read.assertion_message());
+    }
+
+    #[test]
+    fn test_read_ast_error() {
+        // 空のプログラムは ";" だけ
+        assert_eq!(
+            ASTNode::sequence(Vec::new()),
+            ASTReader::read_ast(";\n".lines()).unwrap()
+        );
+
+        for &(src, line, message) in &[
+            ("", None, "unexpected end of AST"),
+            ("Sequence\n;\n", None, "unexpected end of AST"),
+            ("Bogus\n", Some(1), "unknown node: \"Bogus\""),
+            (
+                "Prti\nInteger abc\n;\n",
+                Some(2),
+                "invalid integer: \"abc\"",
+            ),
+            (
+                "Assign\nIdentifier\nInteger 1\n",
+                Some(2),
+                "Identifier needs a value",
+            ),
+            ("Prts\nString \"abc\n;\n", Some(2), "invalid string: \"abc"),
+        ] {
+            let e = ASTReader::read_ast(src.lines()).unwrap_err();
+            assert!(matches!(e.kind(), ErrorKind::ReadError), "{:?}", src);
+            assert_eq!(line, e.line(), "{:?}", src);
+            assert_eq!(message, e.message(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn test_read_deep_ast() {
+        let negate = |depth| {
+            format!(
+                "Prti\n{}Integer 1\n{};\n",
+                "Negate\n".repeat(depth),
+                ";\n".repeat(depth)
+            )
+        };
+        let ast = ASTReader::read_ast(negate(MAX_READ_DEPTH - 1).lines()).unwrap();
+        assert!(matches!(ast.kind(), NodeKind::Prti));
+        let e = ASTReader::read_ast(negate(20_000).lines()).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::ReadError));
+        assert_eq!(
+            format!("AST is too deep (limit: {})", MAX_READ_DEPTH),
+            e.message()
+        );
+        assert_eq!(Some(MAX_READ_DEPTH + 1), e.line());
+
+        // 文の並びは長くても深さに数えない
+        let count = MAX_READ_DEPTH + 1;
+        let flat = format!(
+            "{};\n{}",
+            "Sequence\n".repeat(count),
+            "Assign\nIdentifier x\nInteger 1\n".repeat(count)
+        );
+        let ast = ASTReader::read_ast(flat.lines()).unwrap();
+        assert_eq!(count, ast.sequence_items().len());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let parse = |src| normalize(parse_str(src).unwrap());
+        assert_eq!(
+            parse("a = 1; b = 2; c = 3;"),
+            parse("a = 1; { b = 2; { c = 3; } }")
+        );
+        assert_eq!(parse("a = 1; b = 2;"), parse("; a = 1; {} ; { ; b = 2; }"));
+        assert_eq!(parse(""), parse(";;{{}}"));
+        // print の引数の並びも文の並びに入れる
+        assert_eq!(
+            parse(r#"print("a"); print(1);"#),
+            parse(r#"print("a", 1);"#)
+        );
+
+        // 本体が空になっても Sequence は残す
+        let ast = parse("while (x) { ; }");
+        let body = ast.sequence_items()[0].rhs().unwrap();
+        assert_eq!(&ASTNode::sequence(vec![]), body);
+        assert_eq!(parse("while (x) {}"), ast);
+        assert_ne!(parse("while (x) { y = 1; }"), ast);
+
+        // 行は残す
+        let ast = parse("{\n\nx = 1;\n}");
+        assert_eq!(Some(3), ast.sequence_items()[0].line());
     }
 }
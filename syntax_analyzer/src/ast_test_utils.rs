@@ -0,0 +1,134 @@
+use super::ast_node::{ASTNode, NodeKind};
+
+/// AST を S 式のような形で組み立てる。このクレートのテストで木の形を直接書くのに使う。
+///
+/// - `(Int 1)`, `(Float 1.5)`, `(Str "s")`, `(Ident "x")` は葉
+/// - `(Add (Int 1) (Ident "x"))` は NodeKind::Add のノードで、子は lhs, rhs の順
+/// - 子がなければ省略するか `_` と書く。 `(Sequence _ (Int 1))` は lhs のない Sequence
+///
+/// いちばん外側の括弧は省略できる。
+#[macro_export]
+macro_rules! ast {
+    (@child _) => {
+        None
+    };
+    (@child $node:tt) => {
+        Some($crate::ast!($node))
+    };
+    ((Int $value:expr)) => {
+        $crate::ast_node::ASTNode::leaf_int($value)
+    };
+    ((Float $value:expr)) => {
+        $crate::ast_node::ASTNode::leaf_float($value)
+    };
+    ((Str $value:expr)) => {
+        $crate::ast_node::ASTNode::leaf_string($value)
+    };
+    ((Ident $name:expr)) => {
+        $crate::ast_node::ASTNode::leaf_identifier($name)
+    };
+    (($kind:ident)) => {
+        $crate::ast_node::ASTNode::new($crate::ast_node::NodeKind::$kind, None, None)
+    };
+    (($kind:ident $lhs:tt)) => {
+        $crate::ast_node::ASTNode::new(
+            $crate::ast_node::NodeKind::$kind,
+            $crate::ast!(@child $lhs),
+            None,
+        )
+    };
+    (($kind:ident $lhs:tt $rhs:tt)) => {
+        $crate::ast_node::ASTNode::new(
+            $crate::ast_node::NodeKind::$kind,
+            $crate::ast!(@child $lhs),
+            $crate::ast!(@child $rhs),
+        )
+    };
+    ($kind:ident $($rest:tt)*) => {
+        $crate::ast!(($kind $($rest)*))
+    };
+}
+
+/// actual が ast! で書いた形の木か調べる。行は比べない。
+/// 違っていれば、両方を ast! と同じ形で表示して panic する。
+#[macro_export]
+macro_rules! assert_ast {
+    ($actual:expr, $($expected:tt)+) => {{
+        let actual: $crate::ast_node::ASTNode = $actual;
+        let expected = $crate::ast!($($expected)+);
+        assert!(
+            actual == expected,
+            "AST mismatch\nexpected: {}\n  actual: {}",
+            $crate::ast_test_utils::sexp(&expected),
+            $crate::ast_test_utils::sexp(&actual)
+        );
+    }};
+}
+
+/// ast! と同じ形の 1 行の文字列。後ろの子がなければ省略する。
+pub fn sexp(node: &ASTNode) -> String {
+    match node.kind() {
+        NodeKind::Integer(value) => format!("(Int {})", value),
+        NodeKind::Float(value) => format!("(Float {})", value),
+        NodeKind::String(value) => format!("(Str {:?})", value),
+        NodeKind::Identifier(name) => format!("(Ident {:?})", name),
+        kind => {
+            let child = |child: Option<&ASTNode>| child.map_or("_".to_string(), sexp);
+            match (node.lhs(), node.rhs()) {
+                (None, None) => format!("({:?})", kind),
+                (lhs, None) => format!("({:?} {})", kind, child(lhs)),
+                (lhs, rhs) => format!("({:?} {} {})", kind, child(lhs), child(rhs)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ast() {
+        assert_eq!(
+            ASTNode::binary(
+                NodeKind::Add,
+                ASTNode::leaf_int(1),
+                ASTNode::leaf_identifier("x")
+            ),
+            ast!(Add (Int 1) (Ident "x"))
+        );
+        assert_eq!(
+            ASTNode::sequence(vec![ASTNode::unary(
+                NodeKind::Prts,
+                ASTNode::leaf_string("a\n")
+            )]),
+            ast!((Sequence _ (Prts (Str "a\n"))))
+        );
+        assert_eq!(ASTNode::leaf_int(i64::MIN), ast!(Int i64::MIN));
+        assert_eq!(ASTNode::leaf_float(1.5), ast!(Float 1.5));
+        assert_eq!(
+            ASTNode::new(NodeKind::Sequence, None, None),
+            ast!(Sequence _ _)
+        );
+        assert_ast!(
+            crate::parse_str("x = -a;").unwrap(),
+            (Sequence _ (Assign (Ident "x") (Negate (Ident "a"))))
+        );
+    }
+
+    #[test]
+    fn test_sexp() {
+        let node = ast!(If (Less (Ident "a") (Int 10)) (If (Prts (Str "\"")) _));
+        assert_eq!(
+            r#"(If (Less (Ident "a") (Int 10)) (If (Prts (Str "\""))))"#,
+            sexp(&node)
+        );
+        assert_eq!("(Sequence _ (Clock))", sexp(&ast!(Sequence _ (Clock))));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected: (Add (Int 1) (Int 2))\n  actual: (Add (Int 2) (Int 1))")]
+    fn test_assert_ast() {
+        assert_ast!(ast!(Add (Int 2) (Int 1)), Add (Int 1) (Int 2));
+    }
+}
@@ -0,0 +1,37 @@
+use lexical_analyzer::cli::{Command, Flag, Options};
+use lexical_analyzer::error::*;
+use lexical_analyzer::trivia::attach_trivia;
+use syntax_analyzer::unparser::unparse_with_trivia;
+use syntax_analyzer::ParserOptions;
+
+use std::io::Write;
+
+const COMMAND: Command = Command {
+    name: "tinyfmt",
+    description: "Reads tiny-language source code and writes it formatted, keeping comments.",
+    flags: &[Flag::Dialect],
+};
+
+fn run(options: &Options) -> Result<()> {
+    let src = options.read_input()?;
+    let mut writer = options.create_output()?;
+
+    let parser_options = ParserOptions {
+        dialect: options.dialect.clone(),
+        ..Default::default()
+    };
+    let ast = syntax_analyzer::parse_str_with(&src, &parser_options)?;
+    // コメントは AST に残らないので、トリビアを残して字句解析し直して拾う
+    let tokens = lexical_analyzer::lex_all_with_trivia(&src, &options.dialect)?;
+    write!(
+        writer,
+        "{}",
+        unparse_with_trivia(&ast, &attach_trivia(tokens))
+    )
+    .and_then(|_| writer.flush())
+    .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+}
+
+fn main() {
+    COMMAND.main(run);
+}
@@ -0,0 +1,124 @@
+//! 式だけを計算する電卓。 Rosetta Code の "Arithmetic evaluation" のタスク。
+//! 字句解析器と演算子優先順位パーザで式を解析し、文は受け付けない。
+
+use super::ast_node::{ASTNode, NodeKind};
+use super::{parse_expression_str_with, ParserOptions};
+use lexical_analyzer::error::*;
+use lexical_analyzer::width::Width;
+
+/// src の整数の式を 64 ビットで計算する。
+/// 演算はインタプリタと同じで、桁あふれは折り返し、比較や論理演算の結果は 0 か 1。
+/// 変数や浮動小数点数、 rand() などの組み込み関数は使えない。
+pub fn eval_expression(src: &str) -> Result<i64> {
+    let mut options = ParserOptions::default();
+    options.dialect.width = Width::W64;
+    let node = parse_expression_str_with(src, &options)?;
+    evaluate(&node)
+}
+
+fn evaluate(node: &ASTNode) -> Result<i64> {
+    let width = Width::W64;
+    let operand = |child: Option<&ASTNode>| evaluate(child.expect("operand is missing"));
+    match node.kind() {
+        NodeKind::Integer(value) => Ok(*value),
+        NodeKind::Negate => Ok(width.neg(operand(node.lhs())?)),
+        NodeKind::Not => Ok((operand(node.lhs())? == 0) as i64),
+        // 論理演算はインタプリタと同じく短絡評価する
+        NodeKind::And => Ok((operand(node.lhs())? != 0 && operand(node.rhs())? != 0) as i64),
+        NodeKind::Or => Ok((operand(node.lhs())? != 0 || operand(node.rhs())? != 0) as i64),
+        NodeKind::Multiply
+        | NodeKind::Divide
+        | NodeKind::Mod
+        | NodeKind::Add
+        | NodeKind::Subtract
+        | NodeKind::Less
+        | NodeKind::LessEqual
+        | NodeKind::Greater
+        | NodeKind::GreaterEqual
+        | NodeKind::Equal
+        | NodeKind::NotEqual
+        | NodeKind::BitAnd
+        | NodeKind::BitOr
+        | NodeKind::BitXor
+        | NodeKind::ShiftLeft
+        | NodeKind::ShiftRight
+        | NodeKind::Power => {
+            let lhs = operand(node.lhs())?;
+            let rhs = operand(node.rhs())?;
+            let division_by_zero =
+                || CompileError::new(ErrorKind::InterpretationError, "division by zero");
+            Ok(match node.kind() {
+                NodeKind::Multiply => width.mul(lhs, rhs),
+                NodeKind::Divide => width.div(lhs, rhs).ok_or_else(division_by_zero)?,
+                NodeKind::Mod => width.rem(lhs, rhs).ok_or_else(division_by_zero)?,
+                NodeKind::Add => width.add(lhs, rhs),
+                NodeKind::Subtract => width.sub(lhs, rhs),
+                NodeKind::Less => (lhs < rhs) as i64,
+                NodeKind::LessEqual => (lhs <= rhs) as i64,
+                NodeKind::Greater => (lhs > rhs) as i64,
+                NodeKind::GreaterEqual => (lhs >= rhs) as i64,
+                NodeKind::Equal => (lhs == rhs) as i64,
+                NodeKind::NotEqual => (lhs != rhs) as i64,
+                NodeKind::BitAnd => lhs & rhs,
+                NodeKind::BitOr => lhs | rhs,
+                NodeKind::BitXor => lhs ^ rhs,
+                NodeKind::ShiftLeft => width.shl(lhs, rhs),
+                NodeKind::ShiftRight => width.shr(lhs, rhs),
+                NodeKind::Power => width.pow(lhs, rhs).ok_or_else(division_by_zero)?,
+                _ => unreachable!(),
+            })
+        }
+        NodeKind::Identifier(name) => Err(CompileError::new(
+            ErrorKind::InterpretationError,
+            format!("unknown identifier: {}", name),
+        )),
+        kind => Err(CompileError::new(
+            ErrorKind::InterpretationError,
+            format!("{:?} cannot be used in a calculator expression", kind),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_expression() {
+        assert_eq!(7, eval_expression("1 + 2 * 3").unwrap());
+        assert_eq!(9, eval_expression("(1 + 2) * 3").unwrap());
+        assert_eq!(-7, eval_expression(" -(2 * 3) - 1 ").unwrap());
+        // 除算と剰余は 0 の方向に丸める
+        assert_eq!(-3, eval_expression("-7 / 2").unwrap());
+        assert_eq!(-1, eval_expression("-7 % 2").unwrap());
+        assert_eq!(1, eval_expression("1 < 2 && !(3 == 4)").unwrap());
+        assert_eq!(6, eval_expression("(1 << 3) - (5 & 3) ^ 1").unwrap());
+        // べき乗は右に結合し、単項演算子より強く結合する
+        assert_eq!(1 << 62, eval_expression("2 ** 62").unwrap());
+        assert_eq!(2, eval_expression("2 ** 3 ** 0").unwrap());
+        assert_eq!(-4, eval_expression("-2 ** 2").unwrap());
+        // 64 ビットで計算し、あふれたら折り返す
+        assert_eq!(1 << 40, eval_expression("1 << 40").unwrap());
+        assert_eq!(
+            i64::MIN,
+            eval_expression("9223372036854775807 + 1").unwrap()
+        );
+        // 右辺が 0 でも、短絡評価で割らない
+        assert_eq!(0, eval_expression("0 && 1 / 0").unwrap());
+    }
+
+    #[test]
+    fn test_eval_expression_errors() {
+        let message = |src: &str| eval_expression(src).unwrap_err().message().to_string();
+        assert_eq!("division by zero", message("1 / (2 - 2)"));
+        assert_eq!("division by zero", message("0 ** -1"));
+        assert_eq!("unknown identifier: x", message("x + 1"));
+        assert!(eval_expression("1.5 + 1").is_err());
+        assert!(message("rand(6)").contains("Rand"));
+        // 文や、式の後に残ったトークンは受け付けない
+        assert!(eval_expression("x = 1;").is_err());
+        assert!(eval_expression("1 + 2;").is_err());
+        assert!(eval_expression("(1 + 2").is_err());
+        assert!(eval_expression("").is_err());
+    }
+}
@@ -1,215 +1,464 @@
 pub mod ast_node;
-
+#[cfg(test)]
+mod ast_test_utils;
+pub mod calculator;
+pub mod lint;
+pub mod operators;
+pub mod typed;
+pub mod unparser;
+
+use lexical_analyzer::diagnostic::{Diagnostic, Diagnostics, Lint};
+use lexical_analyzer::dialect::Dialect;
 use lexical_analyzer::error::*;
+use lexical_analyzer::symbol::Symbol;
 use lexical_analyzer::token::*;
-use std::vec::IntoIter;
+use lexical_analyzer::LexicalAnalyzer;
+use std::borrow::Borrow;
+use std::fmt;
+use std::iter::{Map, Peekable};
+use std::slice::Iter;
 
 use ast_node::*;
+use operators::{OperatorTable, UNARY_PRECEDENCE};
 
-struct Operator {
-    kind: NodeKind,
-    right_associative: bool,
-    precedence: i32,
+/// 文の先頭になり得るトークンなら true
+fn is_stmt_start(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Semicolon
+            | TokenKind::Identifier(_)
+            | TokenKind::OpIncrement
+            | TokenKind::OpDecrement
+            | TokenKind::KeywordWhile
+            | TokenKind::KeywordDo
+            | TokenKind::KeywordIf
+            | TokenKind::KeywordPrint
+            | TokenKind::KeywordPutc
+            | TokenKind::KeywordPrintf
+            | TokenKind::KeywordExit
+            | TokenKind::KeywordAssert
+            | TokenKind::LeftBrace
+            // 組み込み関数の名前も、代入する変数の名前として文の先頭になる
+            | TokenKind::KeywordRand
+            | TokenKind::KeywordClock
+            | TokenKind::KeywordGetc
+            | TokenKind::KeywordGeti
+            | TokenKind::KeywordInt
+            | TokenKind::KeywordFloat
+    )
 }
 
-fn operator(op: &TokenKind) -> &'static Operator {
-    // or
-    const OR: Operator = Operator {
-        kind: NodeKind::Or,
-        right_associative: false,
-        precedence: 10,
-    };
-    // and
-    const AND: Operator = Operator {
-        kind: NodeKind::And,
-        right_associative: false,
-        precedence: 20,
-    };
-    // equality
-    const EQUAL: Operator = Operator {
-        kind: NodeKind::Equal,
-        right_associative: false,
-        precedence: 30,
-    };
-    const NOT_EQUAL: Operator = Operator {
-        kind: NodeKind::NotEqual,
-        right_associative: false,
-        precedence: 30,
-    };
-    // relational
-    const LESS: Operator = Operator {
-        kind: NodeKind::Less,
-        right_associative: false,
-        precedence: 40,
-    };
-    const LESS_EQUAL: Operator = Operator {
-        kind: NodeKind::LessEqual,
-        right_associative: false,
-        precedence: 40,
-    };
-    const GREATER: Operator = Operator {
-        kind: NodeKind::Greater,
-        right_associative: false,
-        precedence: 40,
-    };
-    const GREATER_EQUAL: Operator = Operator {
-        kind: NodeKind::GreaterEqual,
-        right_associative: false,
-        precedence: 40,
-    };
-    // addition
-    const ADD: Operator = Operator {
-        kind: NodeKind::Add,
-        right_associative: false,
-        precedence: 50,
-    };
-    const SUBTRACT: Operator = Operator {
-        kind: NodeKind::Subtract,
-        right_associative: false,
-        precedence: 50,
-    };
-    // multiplication
-    const MULTIPLY: Operator = Operator {
-        kind: NodeKind::Multiply,
-        right_associative: false,
-        precedence: 60,
-    };
-    const DIVIDE: Operator = Operator {
-        kind: NodeKind::Divide,
-        right_associative: false,
-        precedence: 60,
-    };
-    const MOD: Operator = Operator {
-        kind: NodeKind::Mod,
-        right_associative: false,
-        precedence: 60,
-    };
-    // sentinel として使うため、 precedence -1
-    const NOT_OPERATOR: Operator = Operator {
-        kind: NodeKind::None,
-        right_associative: false,
-        precedence: -1,
+/// identifier = expr、operator があれば identifier = identifier operator expr
+fn assign_node(identifier: Symbol, operator: Option<NodeKind>, expr: ASTNode) -> ASTNode {
+    let identifier_node = || ASTNode::new(NodeKind::Identifier(identifier), None, None);
+    let rhs = match operator {
+        Some(kind) => ASTNode::binary(kind, identifier_node(), expr),
+        None => expr,
     };
+    ASTNode::binary(NodeKind::Assign, identifier_node(), rhs)
+}
 
-    match op {
-        TokenKind::OpOr => &OR,
-        TokenKind::OpAnd => &AND,
-        TokenKind::OpEqual => &EQUAL,
-        TokenKind::OpNotEqual => &NOT_EQUAL,
-        TokenKind::OpLess => &LESS,
-        TokenKind::OpLessEqual => &LESS_EQUAL,
-        TokenKind::OpGreater => &GREATER,
-        TokenKind::OpGreaterEqual => &GREATER_EQUAL,
-        TokenKind::OpAdd => &ADD,
-        TokenKind::OpSubtract => &SUBTRACT,
-        TokenKind::OpMultiply => &MULTIPLY,
-        TokenKind::OpDivide => &DIVIDE,
-        TokenKind::OpMod => &MOD,
-        _ => &NOT_OPERATOR,
+/// printf の書式を区切ったもの
+#[derive(Debug, PartialEq, Eq)]
+enum FormatPiece {
+    /// そのまま出力する文字列。 %% は % にしてある。
+    Text(String),
+    /// %d
+    Integer,
+    /// %s
+    String,
+    /// %c
+    Char,
+}
+
+impl fmt::Display for FormatPiece {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatPiece::Text(text) => write!(f, "{}", text),
+            FormatPiece::Integer => write!(f, "%d"),
+            FormatPiece::String => write!(f, "%s"),
+            FormatPiece::Char => write!(f, "%c"),
+        }
     }
 }
 
-pub struct SyntaxAnalyzer {
-    token_iter: IntoIter<Token>,
-    next_token: Token,
+/// printf の書式を区切る。空の Text は作らない。
+fn parse_format(format: &str) -> Result<Vec<FormatPiece>> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        let directive = match chars.next() {
+            Some('%') => {
+                text.push('%');
+                continue;
+            }
+            Some('d') => FormatPiece::Integer,
+            Some('s') => FormatPiece::String,
+            Some('c') => FormatPiece::Char,
+            Some(c) => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    format!("printf: unknown conversion %{}", c),
+                ))
+            }
+            None => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    "printf: the format ends with %",
+                ))
+            }
+        };
+        if !text.is_empty() {
+            pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+        }
+        pieces.push(directive);
+    }
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    Ok(pieces)
 }
 
-impl SyntaxAnalyzer {
-    pub fn parse(mut token_iter: IntoIter<Token>) -> Result<ASTNode> {
-        match token_iter.next() {
+/// 文や式の入れ子の深さの既定の上限
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct ParserOptions {
+    /// 文や式の入れ子の深さの上限。これを超えるとスタックを使い切る前にエラーにする。
+    pub max_depth: usize,
+    /// 受け付ける拡張。無効な拡張のトークンはエラーにする。
+    pub dialect: Dialect,
+    /// 二項演算子の優先順位と結合の向き
+    pub operators: OperatorTable,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        ParserOptions {
+            max_depth: DEFAULT_MAX_DEPTH,
+            dialect: Dialect::default(),
+            operators: OperatorTable::default(),
+        }
+    }
+}
+
+/// 比較演算子 (==, !=, <, <=, >, >=) か
+fn is_comparison(kind: &NodeKind) -> bool {
+    matches!(
+        kind,
+        NodeKind::Equal
+            | NodeKind::NotEqual
+            | NodeKind::Less
+            | NodeKind::LessEqual
+            | NodeKind::Greater
+            | NodeKind::GreaterEqual
+    )
+}
+
+pub struct SyntaxAnalyzer<T, I: Iterator> {
+    /// 識別子としても読めるキーワードを見分けるため、もう 1 つ先まで覗けるようにする
+    token_iter: Peekable<I>,
+    next_token: T,
+    /// 現在の入れ子の深さ
+    depth: usize,
+    max_depth: usize,
+    dialect: Dialect,
+    operators: OperatorTable,
+    /// 解析中に見つけた警告。解析を終えてから Diagnostics に渡す。
+    warnings: Vec<Diagnostic>,
+}
+
+/// parse_ref で読むトークン列
+type RefTokens<'a> = Map<Iter<'a, Token>, fn(&'a Token) -> Result<&'a Token>>;
+
+impl<'a> SyntaxAnalyzer<&'a Token, RefTokens<'a>> {
+    /// トークン列を消費せずに解析する。
+    pub fn parse_ref(tokens: &'a [Token]) -> Result<ASTNode> {
+        Self::parse_ref_with(tokens, &ParserOptions::default())
+    }
+
+    pub fn parse_ref_with(tokens: &'a [Token], options: &ParserOptions) -> Result<ASTNode> {
+        Self::parse_with(tokens.iter().map(Ok as fn(_) -> _), options)
+    }
+
+    pub fn parse_ref_with_diagnostics(
+        tokens: &'a [Token],
+        options: &ParserOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<ASTNode> {
+        Self::parse_with_diagnostics(tokens.iter().map(Ok as fn(_) -> _), options, diagnostics)
+    }
+}
+
+impl<T, I> SyntaxAnalyzer<T, I>
+where
+    T: Borrow<Token>,
+    I: Iterator<Item = Result<T>>,
+{
+    /// token_iter のトークンを読みながら解析する。
+    /// 字句解析器をそのまま渡せば、トークン列を作らずに解析でき、字句解析のエラーもそのまま返す。
+    pub fn parse(token_iter: I) -> Result<ASTNode> {
+        Self::parse_with(token_iter, &ParserOptions::default())
+    }
+
+    pub fn parse_with(token_iter: I, options: &ParserOptions) -> Result<ASTNode> {
+        Self::parse_with_diagnostics(token_iter, options, &mut Diagnostics::default())
+    }
+
+    /// parse_with と同じく解析し、見つけた警告を diagnostics に記録する。
+    /// エラーで止まった場合も、それまでに見つけた警告は記録する。
+    pub fn parse_with_diagnostics(
+        mut token_iter: I,
+        options: &ParserOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<ASTNode> {
+        match token_iter.next().transpose()? {
             Some(next_token) => {
                 let mut parser = SyntaxAnalyzer {
-                    token_iter,
+                    token_iter: token_iter.peekable(),
                     next_token,
+                    depth: 0,
+                    max_depth: options.max_depth,
+                    dialect: options.dialect.clone(),
+                    operators: options.operators.clone(),
+                    warnings: Vec::new(),
                 };
-                parser.parse_stmt_list()
+                let result = parser.check_dialect().and_then(|_| {
+                    let mut node = parser.parse_stmt_list()?;
+
+                    if *parser.next_kind() != TokenKind::EndOfInput {
+                        return Err(CompileError::new(
+                            ErrorKind::SyntaxError,
+                            format!("unexpected token: {:?}", parser.next_token.borrow()),
+                        ));
+                    }
+                    if parser.dialect.string_variables {
+                        typed::resolve_string_prints(&mut node);
+                    }
+                    Ok(node)
+                });
+                // エラーは先読みしていたトークンの位置で見つかったものとする
+                for warning in parser.warnings.drain(..) {
+                    diagnostics.push(warning);
+                }
+                let token = parser.next_token.borrow();
+                result.map_err(|e| e.with_position(token.line_number(), token.column_number()))
             }
             None => Ok(ASTNode {
                 kind: NodeKind::Sequence,
                 lhs: None,
                 rhs: None,
+                line: None,
             }),
         }
     }
 
-    fn read_token(&mut self) -> Result<Token> {
-        let next_token = self.token_iter.next();
+    /// 文の並びではなく 1 つの式として解析する。式の後にトークンが残っていればエラー。
+    pub fn parse_expression_with(mut token_iter: I, options: &ParserOptions) -> Result<ASTNode> {
+        let next_token = match token_iter.next().transpose()? {
+            Some(next_token) => next_token,
+            None => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    "expression is expected",
+                ))
+            }
+        };
+        let mut parser = SyntaxAnalyzer {
+            token_iter: token_iter.peekable(),
+            next_token,
+            depth: 0,
+            max_depth: options.max_depth,
+            dialect: options.dialect.clone(),
+            operators: options.operators.clone(),
+            warnings: Vec::new(),
+        };
+        let result = parser.check_dialect().and_then(|_| {
+            let node = parser.parse_expr()?;
+            if *parser.next_kind() != TokenKind::EndOfInput {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    format!("unexpected token: {:?}", parser.next_token.borrow()),
+                ));
+            }
+            Ok(node)
+        });
+        let token = parser.next_token.borrow();
+        result.map_err(|e| e.with_position(token.line_number(), token.column_number()))
+    }
+
+    /// position の位置に警告を記録する
+    fn warn(&mut self, lint: Lint, (line, column): (usize, usize), message: &str) {
+        self.warnings.push(Diagnostic::warning(
+            lint,
+            ErrorKind::SyntaxError.stage(),
+            Some(line),
+            Some(column),
+            message,
+        ));
+    }
+
+    /// 入れ子を一段深くして f を呼ぶ。
+    fn nested<F>(&mut self, f: F) -> Result<ASTNode>
+    where
+        F: FnOnce(&mut Self) -> Result<ASTNode>,
+    {
+        if self.depth >= self.max_depth {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                format!("nesting is too deep (limit: {})", self.max_depth),
+            ));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// 先読みしたトークンの (行, 列)
+    fn position(&self) -> (usize, usize) {
+        let token = self.next_token.borrow();
+        (token.line_number(), token.column_number())
+    }
+
+    fn next_kind(&self) -> &TokenKind {
+        self.next_token.borrow().kind()
+    }
+
+    /// 先読みしたトークンの次のトークンの種類。読めなければ None で、エラーは読むときに返す。
+    fn following_kind(&mut self) -> Option<&TokenKind> {
+        match self.token_iter.peek() {
+            Some(Ok(token)) => Some(token.borrow().kind()),
+            _ => None,
+        }
+    }
+
+    /// 読んだトークンの種類が kind の識別子の名前。
+    /// 拡張のキーワードも、先読みしたトークンが '(' でなければタスクの文法どおり識別子とする。
+    fn identifier(&self, kind: &TokenKind) -> Option<Symbol> {
+        match kind {
+            TokenKind::Identifier(identifier) => Some(*identifier),
+            _ if *self.next_kind() != TokenKind::LeftParen => {
+                self.dialect.soft_keyword_name(kind).map(Symbol::intern)
+            }
+            _ => None,
+        }
+    }
+
+    /// 文の先頭の拡張のキーワードを、代入する変数の名前として読むなら true。
+    /// do は文が続くので、代入の演算子が続く場合だけ変数とする。
+    fn is_assigned_keyword(&mut self) -> bool {
+        let kind = self.next_kind().clone();
+        if self.dialect.soft_keyword_name(&kind).is_none() {
+            return false;
+        }
+        match self.following_kind() {
+            Some(following) if kind == TokenKind::KeywordDo => matches!(
+                following,
+                TokenKind::OpAssign
+                    | TokenKind::OpAddAssign
+                    | TokenKind::OpSubtractAssign
+                    | TokenKind::OpMultiplyAssign
+                    | TokenKind::OpDivideAssign
+                    | TokenKind::OpModAssign
+            ),
+            Some(following) => *following != TokenKind::LeftParen,
+            None => false,
+        }
+    }
+
+    fn read_token(&mut self) -> Result<T> {
+        let next_token = self.token_iter.next().transpose()?;
         match next_token {
-            Some(t) => Ok(std::mem::replace(&mut self.next_token, t)),
+            Some(t) => {
+                let token = std::mem::replace(&mut self.next_token, t);
+                self.check_dialect()?;
+                Ok(token)
+            }
             None => Err(CompileError::new(ErrorKind::SyntaxError, "unexpected EOF")),
         }
     }
 
+    /// 先読みしたトークンが方言で使えるか調べる
+    fn check_dialect(&self) -> Result<()> {
+        let token = self.next_token.borrow();
+        if self.dialect.allows(token.kind()) {
+            Ok(())
+        } else {
+            Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                format!("'{}' is not supported in this dialect", token.kind()),
+            )
+            .with_position(token.line_number(), token.column_number()))
+        }
+    }
+
     fn parse_stmt_list(&mut self) -> Result<ASTNode> {
-        let mut node;
-        match self.next_token.kind() {
-            TokenKind::Semicolon
-            | TokenKind::Identifier(_)
-            | TokenKind::KeywordWhile
-            | TokenKind::KeywordIf
-            | TokenKind::KeywordPrint
-            | TokenKind::KeywordPutc
-            | TokenKind::LeftBrace => {
-                node = ASTNode {
-                    kind: NodeKind::Sequence,
-                    lhs: None,
-                    rhs: Some(Box::new(self.parse_stmt()?)),
-                }
-            }
-            _ => {
-                return Ok(ASTNode {
-                    kind: NodeKind::Sequence,
-                    lhs: None,
-                    rhs: None,
-                });
-            }
+        let mut node = ASTNode {
+            kind: NodeKind::Sequence,
+            lhs: None,
+            rhs: None,
+            line: None,
         };
+        if !is_stmt_start(self.next_kind()) {
+            return Ok(node);
+        }
+        node.rhs = Some(Box::new(self.parse_stmt()?));
 
-        loop {
-            match self.next_token.kind() {
-                TokenKind::Semicolon
-                | TokenKind::Identifier(_)
-                | TokenKind::KeywordWhile
-                | TokenKind::KeywordIf
-                | TokenKind::KeywordPrint
-                | TokenKind::KeywordPutc
-                | TokenKind::LeftBrace => {
-                    node = ASTNode {
-                        kind: NodeKind::Sequence,
-                        lhs: Some(Box::new(node)),
-                        rhs: Some(Box::new(self.parse_stmt()?)),
-                    }
-                }
-                _ => {
-                    break;
-                }
-            };
+        while is_stmt_start(self.next_kind()) {
+            node = ASTNode {
+                kind: NodeKind::Sequence,
+                lhs: Some(Box::new(node)),
+                rhs: Some(Box::new(self.parse_stmt()?)),
+                line: None,
+            }
         }
 
         Ok(node)
     }
 
     fn parse_stmt(&mut self) -> Result<ASTNode> {
-        match self.next_token.kind() {
+        let line = self.next_token.borrow().line_number();
+        let mut node = self.nested(Self::parse_stmt_body)?;
+        node.line = Some(line);
+        Ok(node)
+    }
+
+    fn parse_stmt_body(&mut self) -> Result<ASTNode> {
+        if self.is_assigned_keyword() {
+            return self.parse_assign_stmt();
+        }
+        match self.next_kind() {
             TokenKind::Semicolon => {
                 self.read_token()?;
                 Ok(ASTNode {
                     kind: NodeKind::Sequence,
                     lhs: None,
                     rhs: None,
+                    line: None,
                 })
             }
             TokenKind::Identifier(_) => self.parse_assign_stmt(),
+            TokenKind::OpIncrement | TokenKind::OpDecrement => self.parse_increment_stmt(),
             TokenKind::KeywordWhile => self.parse_while_stmt(),
+            TokenKind::KeywordDo => self.parse_do_while_stmt(),
             TokenKind::KeywordIf => self.parse_if_stmt(),
             TokenKind::KeywordPrint => self.parse_print_stmt(),
             TokenKind::KeywordPutc => self.parse_putc_stmt(),
+            TokenKind::KeywordPrintf => self.parse_printf_stmt(),
+            TokenKind::KeywordExit => self.parse_exit_stmt(),
+            TokenKind::KeywordAssert => self.parse_assert_stmt(),
             TokenKind::LeftBrace => {
                 self.read_token()?;
 
                 let node = self.parse_stmt_list()?;
 
-                if *self.next_token.kind() != TokenKind::RightBrace {
+                if *self.next_kind() != TokenKind::RightBrace {
                     return Err(CompileError::new(
                         ErrorKind::SyntaxError,
                         "'}' is expected.",
@@ -220,55 +469,97 @@ impl SyntaxAnalyzer {
             }
             _ => Err(CompileError::new(
                 ErrorKind::SyntaxError,
-                format!("unexpected token: {:?}", self.next_token),
+                format!("unexpected token: {:?}", self.next_token.borrow()),
             )),
         }
     }
 
     fn parse_assign_stmt(&mut self) -> Result<ASTNode> {
-        let Token { kind, .. } = self.read_token()?;
-
-        match kind {
-            TokenKind::Identifier(identifier) => {
-                let lhs = ASTNode {
-                    kind: NodeKind::Identifier(identifier),
-                    lhs: None,
-                    rhs: None,
+        let token = self.read_token()?;
+
+        match self.identifier(token.borrow().kind()) {
+            Some(identifier) => {
+                // 複合代入は "a = a op expr" に、後置の "a++" は "a = a + 1" に展開する
+                let (operator, postfix) = match self.next_kind() {
+                    TokenKind::OpAssign => (None, false),
+                    TokenKind::OpAddAssign => (Some(NodeKind::Add), false),
+                    TokenKind::OpSubtractAssign => (Some(NodeKind::Subtract), false),
+                    TokenKind::OpMultiplyAssign => (Some(NodeKind::Multiply), false),
+                    TokenKind::OpDivideAssign => (Some(NodeKind::Divide), false),
+                    TokenKind::OpModAssign => (Some(NodeKind::Mod), false),
+                    TokenKind::OpIncrement => (Some(NodeKind::Add), true),
+                    TokenKind::OpDecrement => (Some(NodeKind::Subtract), true),
+                    _ => {
+                        return Err(CompileError::new(
+                            ErrorKind::SyntaxError,
+                            "'=' is expected.",
+                        ))
+                    }
                 };
-
-                if *self.next_token.kind() != TokenKind::OpAssign {
-                    return Err(CompileError::new(
-                        ErrorKind::SyntaxError,
-                        "'=' is expected.",
-                    ));
-                }
                 self.read_token()?;
 
-                let rhs = self.parse_expr()?;
-
-                if *self.next_token.kind() != TokenKind::Semicolon {
-                    return Err(CompileError::new(
-                        ErrorKind::SyntaxError,
-                        "';' is expected.",
-                    ));
-                }
-                self.read_token()?;
+                let expr = if postfix {
+                    ASTNode::leaf_int(1)
+                } else {
+                    self.parse_expr()?
+                };
+                self.expect_semicolon()?;
 
-                Ok(ASTNode {
-                    kind: NodeKind::Assign,
-                    lhs: Some(Box::new(lhs)),
-                    rhs: Some(Box::new(rhs)),
-                })
+                Ok(assign_node(identifier, operator, expr))
             }
-            _ => Err(CompileError::new(
+            None => Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "Identifier is expected",
             )),
         }
     }
 
+    /// 前置の "++a;" と "--a;"
+    fn parse_increment_stmt(&mut self) -> Result<ASTNode> {
+        let operator = match self.next_kind() {
+            TokenKind::OpIncrement => NodeKind::Add,
+            TokenKind::OpDecrement => NodeKind::Subtract,
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    "'++' or '--' is expected.",
+                ))
+            }
+        };
+        self.read_token()?;
+
+        let token = self.read_token()?;
+        let identifier = match self.identifier(token.borrow().kind()) {
+            Some(identifier) => identifier,
+            None => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    "Identifier is expected",
+                ))
+            }
+        };
+        self.expect_semicolon()?;
+
+        Ok(assign_node(
+            identifier,
+            Some(operator),
+            ASTNode::leaf_int(1),
+        ))
+    }
+
+    fn expect_semicolon(&mut self) -> Result<()> {
+        if *self.next_kind() != TokenKind::Semicolon {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "';' is expected.",
+            ));
+        }
+        self.read_token()?;
+        Ok(())
+    }
+
     fn parse_while_stmt(&mut self) -> Result<ASTNode> {
-        if *self.next_token.kind() != TokenKind::KeywordWhile {
+        if *self.next_kind() != TokenKind::KeywordWhile {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "\"while\" is expected.",
@@ -283,11 +574,49 @@ impl SyntaxAnalyzer {
             kind: NodeKind::While,
             lhs: Some(Box::new(lhs)),
             rhs: Some(Box::new(rhs)),
+            line: None,
+        })
+    }
+
+    fn parse_do_while_stmt(&mut self) -> Result<ASTNode> {
+        if *self.next_kind() != TokenKind::KeywordDo {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "\"do\" is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let rhs = self.parse_stmt()?;
+
+        if *self.next_kind() != TokenKind::KeywordWhile {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "\"while\" is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let lhs = self.parse_paren_expr()?;
+
+        if *self.next_kind() != TokenKind::Semicolon {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "';' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        Ok(ASTNode {
+            kind: NodeKind::DoWhile,
+            lhs: Some(Box::new(lhs)),
+            rhs: Some(Box::new(rhs)),
+            line: None,
         })
     }
 
     fn parse_if_stmt(&mut self) -> Result<ASTNode> {
-        if *self.next_token.kind() != TokenKind::KeywordIf {
+        if *self.next_kind() != TokenKind::KeywordIf {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "\"if\" is expected.",
@@ -300,10 +629,11 @@ impl SyntaxAnalyzer {
             kind: NodeKind::If,
             lhs: Some(Box::new(condition)),
             rhs: None,
+            line: None,
         };
 
         let if_clause = Some(Box::new(self.parse_stmt()?));
-        let else_clause = if *self.next_token.kind() == TokenKind::KeywordElse {
+        let else_clause = if *self.next_kind() == TokenKind::KeywordElse {
             self.read_token()?;
             Some(Box::new(self.parse_stmt()?))
         } else {
@@ -314,13 +644,14 @@ impl SyntaxAnalyzer {
             kind: NodeKind::If,
             lhs: if_clause,
             rhs: else_clause,
+            line: None,
         }));
 
         Ok(node)
     }
 
     fn parse_print_stmt(&mut self) -> Result<ASTNode> {
-        if *self.next_token.kind() != TokenKind::KeywordPrint {
+        if *self.next_kind() != TokenKind::KeywordPrint {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "\"print\" is expected.",
@@ -328,7 +659,7 @@ impl SyntaxAnalyzer {
         }
         self.read_token()?;
 
-        if *self.next_token.kind() != TokenKind::LeftParen {
+        if *self.next_kind() != TokenKind::LeftParen {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "'(' is expected.",
@@ -338,7 +669,7 @@ impl SyntaxAnalyzer {
 
         let node = self.parse_prt_list()?;
 
-        if *self.next_token.kind() != TokenKind::RightParen {
+        if *self.next_kind() != TokenKind::RightParen {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "')' is expected.",
@@ -346,7 +677,7 @@ impl SyntaxAnalyzer {
         }
         self.read_token()?;
 
-        if *self.next_token.kind() != TokenKind::Semicolon {
+        if *self.next_kind() != TokenKind::Semicolon {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "';' is expected.",
@@ -358,7 +689,7 @@ impl SyntaxAnalyzer {
     }
 
     fn parse_putc_stmt(&mut self) -> Result<ASTNode> {
-        if *self.next_token.kind() != TokenKind::KeywordPutc {
+        if *self.next_kind() != TokenKind::KeywordPutc {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "\"putc\" is expected.",
@@ -366,9 +697,13 @@ impl SyntaxAnalyzer {
         }
         self.read_token()?;
 
-        let lhs = self.parse_paren_expr()?;
+        let node = if self.dialect.putc_list {
+            self.parse_putc_list()?
+        } else {
+            ASTNode::unary(NodeKind::Prtc, self.parse_paren_expr()?)
+        };
 
-        if *self.next_token.kind() != TokenKind::Semicolon {
+        if *self.next_kind() != TokenKind::Semicolon {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "';' is expected.",
@@ -376,20 +711,244 @@ impl SyntaxAnalyzer {
         }
         self.read_token()?;
 
-        Ok(ASTNode {
-            kind: NodeKind::Prtc,
-            lhs: Some(Box::new(lhs)),
-            rhs: None,
-        })
+        Ok(node)
+    }
+
+    /// putc の括弧で囲んだ式の並び。式が 1 つなら Prtc、
+    /// 複数なら print と同じく Prtc を並べた Sequence にする。
+    fn parse_putc_list(&mut self) -> Result<ASTNode> {
+        if *self.next_kind() != TokenKind::LeftParen {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "'(' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let mut args = Vec::new();
+        loop {
+            let line = self.next_token.borrow().line_number();
+            args.push(ASTNode::unary(NodeKind::Prtc, self.parse_expr()?).with_line(line));
+            if *self.next_kind() != TokenKind::Comma {
+                break;
+            }
+            self.read_token()?;
+        }
+
+        if *self.next_kind() != TokenKind::RightParen {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "')' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        if args.len() == 1 {
+            Ok(args.pop().unwrap())
+        } else {
+            Ok(ASTNode::sequence(args))
+        }
+    }
+
+    /// exit(expr);
+    fn parse_exit_stmt(&mut self) -> Result<ASTNode> {
+        if *self.next_kind() != TokenKind::KeywordExit {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "\"exit\" is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let node = ASTNode::unary(NodeKind::Exit, self.parse_paren_expr()?);
+
+        if *self.next_kind() != TokenKind::Semicolon {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "';' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        Ok(node)
+    }
+
+    /// assert(expr, "message");
+    fn parse_assert_stmt(&mut self) -> Result<ASTNode> {
+        if *self.next_kind() != TokenKind::KeywordAssert {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "\"assert\" is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        if *self.next_kind() != TokenKind::LeftParen {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "'(' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let condition = self.parse_expr()?;
+
+        if *self.next_kind() != TokenKind::Comma {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "',' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        if !matches!(self.next_kind(), TokenKind::String(_)) {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "message string is expected.",
+            ));
+        }
+        let message = self.make_string_node()?;
+
+        if *self.next_kind() != TokenKind::RightParen {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "')' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        if *self.next_kind() != TokenKind::Semicolon {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "';' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        Ok(ASTNode::binary(NodeKind::Assert, condition, message))
+    }
+
+    /// printf("format", args...); を Prts, Prti, Prtc の Sequence に展開する。
+    /// 書式の誤りと、引数の数や種類が書式に合わないことはここでエラーにする。
+    fn parse_printf_stmt(&mut self) -> Result<ASTNode> {
+        if *self.next_kind() != TokenKind::KeywordPrintf {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "\"printf\" is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        if *self.next_kind() != TokenKind::LeftParen {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "'(' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let format_position = self.position();
+        let format = match *self.next_kind() {
+            TokenKind::String(format) => format,
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    "format string is expected.",
+                ))
+            }
+        };
+        let pieces = parse_format(&format.text())?;
+        self.read_token()?;
+
+        let mut args = Vec::new();
+        while *self.next_kind() == TokenKind::Comma {
+            self.read_token()?;
+            let position = self.position();
+            let arg = match self.next_kind() {
+                TokenKind::String(_) => self.make_string_node()?,
+                _ => self.parse_expr()?,
+            };
+            args.push((arg, position));
+        }
+
+        if *self.next_kind() != TokenKind::RightParen {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "')' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        if *self.next_kind() != TokenKind::Semicolon {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                "';' is expected.",
+            ));
+        }
+        self.read_token()?;
+
+        let directives = pieces
+            .iter()
+            .filter(|piece| !matches!(piece, FormatPiece::Text(_)))
+            .count();
+        if directives != args.len() {
+            return Err(CompileError::new(
+                ErrorKind::SyntaxError,
+                format!(
+                    "printf: the format needs {} arguments, but {} are given",
+                    directives,
+                    args.len()
+                ),
+            )
+            .with_position(format_position.0, format_position.1));
+        }
+
+        let mut args = args.into_iter().enumerate();
+        let mut nodes = Vec::new();
+        for piece in pieces {
+            let node = match piece {
+                FormatPiece::Text(text) => {
+                    ASTNode::unary(NodeKind::Prts, ASTNode::leaf_string(&text))
+                        .with_line(format_position.0)
+                }
+                directive => {
+                    let (index, (arg, (line, column))) = args.next().unwrap();
+                    let is_string = matches!(arg.kind(), NodeKind::String(_));
+                    // 文字列を入れる変数かどうかは、型の検査で確かめる
+                    let is_variable = self.dialect.string_variables
+                        && matches!(arg.kind(), NodeKind::Identifier(_));
+                    let kind = match directive {
+                        FormatPiece::Integer if !is_string => NodeKind::Prti,
+                        FormatPiece::Char if !is_string => NodeKind::Prtc,
+                        FormatPiece::String if is_string || is_variable => NodeKind::Prts,
+                        _ => {
+                            return Err(CompileError::new(
+                                ErrorKind::SyntaxError,
+                                format!(
+                                    "printf: argument {} does not match {}",
+                                    index + 1,
+                                    directive
+                                ),
+                            )
+                            .with_position(line, column))
+                        }
+                    };
+                    ASTNode::unary(kind, arg).with_line(line)
+                }
+            };
+            nodes.push(node);
+        }
+        Ok(ASTNode::sequence(nodes))
     }
 
     fn make_string_node(&mut self) -> Result<ASTNode> {
-        let Token { kind, .. } = self.read_token()?;
-        if let TokenKind::String(s) = kind {
+        let token = self.read_token()?;
+        if let TokenKind::String(s) = *token.borrow().kind() {
             Ok(ASTNode {
                 kind: NodeKind::String(s),
                 lhs: None,
                 rhs: None,
+                line: None,
             })
         } else {
             unreachable!()
@@ -397,16 +956,19 @@ impl SyntaxAnalyzer {
     }
 
     fn parse_prt_list(&mut self) -> Result<ASTNode> {
-        let node = match self.next_token.kind() {
+        let line = Some(self.next_token.borrow().line_number());
+        let node = match self.next_kind() {
             TokenKind::String(_) => ASTNode {
                 kind: NodeKind::Prts,
                 lhs: Some(Box::new(self.make_string_node()?)),
                 rhs: None,
+                line,
             },
             _ => ASTNode {
                 kind: NodeKind::Prti,
                 lhs: Some(Box::new(self.parse_expr()?)),
                 rhs: None,
+                line,
             },
         };
 
@@ -414,21 +976,25 @@ impl SyntaxAnalyzer {
             kind: NodeKind::Sequence,
             lhs: None,
             rhs: Some(Box::new(node)),
+            line: None,
         };
 
-        while *self.next_token.kind() == TokenKind::Comma {
+        while *self.next_kind() == TokenKind::Comma {
             self.read_token()?;
 
-            let node = match self.next_token.kind() {
+            let line = Some(self.next_token.borrow().line_number());
+            let node = match self.next_kind() {
                 TokenKind::String(_) => ASTNode {
                     kind: NodeKind::Prts,
                     lhs: Some(Box::new(self.make_string_node()?)),
                     rhs: None,
+                    line,
                 },
                 _ => ASTNode {
                     kind: NodeKind::Prti,
                     lhs: Some(Box::new(self.parse_expr()?)),
                     rhs: None,
+                    line,
                 },
             };
 
@@ -436,13 +1002,14 @@ impl SyntaxAnalyzer {
                 kind: NodeKind::Sequence,
                 lhs: Some(Box::new(lhs)),
                 rhs: Some(Box::new(node)),
+                line: None,
             };
         }
         Ok(lhs)
     }
 
     fn parse_paren_expr(&mut self) -> Result<ASTNode> {
-        if *self.next_token.kind() != TokenKind::LeftParen {
+        if *self.next_kind() != TokenKind::LeftParen {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "'(' is expected.",
@@ -452,7 +1019,7 @@ impl SyntaxAnalyzer {
 
         let node = self.parse_expr()?;
 
-        if *self.next_token.kind() != TokenKind::RightParen {
+        if *self.next_kind() != TokenKind::RightParen {
             return Err(CompileError::new(
                 ErrorKind::SyntaxError,
                 "')' is expected.",
@@ -465,54 +1032,123 @@ impl SyntaxAnalyzer {
     ///  演算子優先順位パーザで式を解析する
     fn parse_expr(&mut self) -> Result<ASTNode> {
         let lhs = self.parse_primary()?;
-        self.parse_expr_body(lhs, 0)
+        let node = self.parse_expr_body(lhs, 0)?;
+
+        // "if (a = 1)" や "a = b = 1" のように、式の後に代入が続くのは書き間違い
+        match self.next_kind() {
+            TokenKind::OpAssign
+            | TokenKind::OpAddAssign
+            | TokenKind::OpSubtractAssign
+            | TokenKind::OpMultiplyAssign
+            | TokenKind::OpDivideAssign
+            | TokenKind::OpModAssign => {
+                let (line, column) = self.position();
+                Err(CompileError::new(
+                    ErrorKind::SyntaxError,
+                    "assignment cannot be used as an expression; use '==' to compare",
+                )
+                .with_position(line, column))
+            }
+            _ => Ok(node),
+        }
     }
 
     fn parse_expr_body(&mut self, node: ASTNode, min_precedence: i32) -> Result<ASTNode> {
+        self.parse_operators(node, false, min_precedence)
+            .map(|(node, _)| node)
+    }
+
+    /// node に続く、優先順位が min_precedence 以上の二項演算子を読む。
+    /// compared は node が括弧で囲まれていない比較か。
+    /// 戻り値の bool は、結果が括弧で囲まれていない比較か。
+    /// "a < b < c" のように比較をつないだ式は (a < b) < c と解釈されるので、警告する。
+    fn parse_operators(
+        &mut self,
+        node: ASTNode,
+        compared: bool,
+        min_precedence: i32,
+    ) -> Result<(ASTNode, bool)> {
         let mut lhs = node;
+        let mut lhs_compared = compared;
 
-        let mut next_op = operator(self.next_token.kind());
+        let mut next_op = self.operators.lookup(self.next_kind());
         while next_op.precedence >= min_precedence {
             let op = next_op;
+            let position = self.position();
             self.read_token()?;
 
             let mut rhs = self.parse_primary()?;
-            next_op = operator(self.next_token.kind());
+            let mut rhs_compared = false;
+            next_op = self.operators.lookup(self.next_kind());
 
             while next_op.precedence > op.precedence
                 || ((next_op.precedence == op.precedence) && next_op.right_associative)
             {
-                rhs = self.parse_expr_body(rhs, next_op.precedence)?;
-                next_op = operator(self.next_token.kind());
+                let (node, compared) =
+                    self.parse_operators(rhs, rhs_compared, next_op.precedence)?;
+                rhs = node;
+                rhs_compared = compared;
+                next_op = self.operators.lookup(self.next_kind());
+            }
+
+            let comparison = is_comparison(&op.kind);
+            if comparison && (lhs_compared || rhs_compared) {
+                self.warn(
+                    Lint::ChainedComparison,
+                    position,
+                    "chained comparison: 'a < b < c' compares the result of 'a < b' with c; use '&&' or add parentheses",
+                );
             }
 
             lhs = ASTNode {
                 kind: op.kind.clone(),
                 lhs: Some(Box::new(lhs)),
                 rhs: Some(Box::new(rhs)),
+                line: None,
             };
+            lhs_compared = comparison;
         }
 
-        Ok(lhs)
+        Ok((lhs, lhs_compared))
     }
 
     fn parse_primary(&mut self) -> Result<ASTNode> {
-        let Token { kind, .. } = self.read_token()?;
-        match kind {
-            TokenKind::Identifier(identifier) => Ok(ASTNode {
+        self.nested(Self::parse_primary_body)
+    }
+
+    fn parse_primary_body(&mut self) -> Result<ASTNode> {
+        let token = self.read_token()?;
+        if let Some(identifier) = self.identifier(token.borrow().kind()) {
+            return Ok(ASTNode {
                 kind: NodeKind::Identifier(identifier),
                 lhs: None,
                 rhs: None,
+                line: None,
+            });
+        }
+        match *token.borrow().kind() {
+            TokenKind::Integer(value) => Ok(ASTNode {
+                kind: NodeKind::Integer(value),
+                lhs: None,
+                rhs: None,
+                line: None,
+            }),
+            TokenKind::Float(value) => Ok(ASTNode {
+                kind: NodeKind::Float(value),
+                lhs: None,
+                rhs: None,
+                line: None,
             }),
-            TokenKind::Integer(kind) => Ok(ASTNode {
-                kind: NodeKind::Integer(kind),
+            TokenKind::String(value) if self.dialect.string_variables => Ok(ASTNode {
+                kind: NodeKind::String(value),
                 lhs: None,
                 rhs: None,
+                line: None,
             }),
             TokenKind::LeftParen => {
                 let node = self.parse_expr()?;
 
-                if *self.next_token.kind() != TokenKind::RightParen {
+                if *self.next_kind() != TokenKind::RightParen {
                     return Err(CompileError::new(
                         ErrorKind::SyntaxError,
                         "')' is expected.",
@@ -522,26 +1158,142 @@ impl SyntaxAnalyzer {
 
                 Ok(node)
             }
-
-            TokenKind::OpAdd => self.parse_primary(),
-            TokenKind::OpSubtract => Ok(ASTNode {
-                kind: NodeKind::Negate,
-                lhs: Some(Box::new(self.parse_primary()?)),
+            TokenKind::KeywordRand => Ok(ASTNode {
+                kind: NodeKind::Rand,
+                lhs: Some(Box::new(self.parse_paren_expr()?)),
+                rhs: None,
+                line: None,
+            }),
+            TokenKind::KeywordInt => Ok(ASTNode {
+                kind: NodeKind::ToInt,
+                lhs: Some(Box::new(self.parse_paren_expr()?)),
                 rhs: None,
+                line: None,
             }),
-            TokenKind::OpNot => Ok(ASTNode {
-                kind: NodeKind::Not,
-                lhs: Some(Box::new(self.parse_primary()?)),
+            TokenKind::KeywordFloat => Ok(ASTNode {
+                kind: NodeKind::ToFloat,
+                lhs: Some(Box::new(self.parse_paren_expr()?)),
                 rhs: None,
+                line: None,
             }),
-            _ => Err(CompileError::new(ErrorKind::SyntaxError, "invalid primary")),
+            TokenKind::KeywordClock | TokenKind::KeywordGetc | TokenKind::KeywordGeti => {
+                let kind = match token.borrow().kind() {
+                    TokenKind::KeywordClock => NodeKind::Clock,
+                    TokenKind::KeywordGetc => NodeKind::Getc,
+                    _ => NodeKind::Geti,
+                };
+                // clock, getc, geti は引数を取らない
+                for (expected, message) in &[
+                    (TokenKind::LeftParen, "'(' is expected."),
+                    (TokenKind::RightParen, "')' is expected."),
+                ] {
+                    if self.next_kind() != expected {
+                        return Err(CompileError::new(ErrorKind::SyntaxError, *message));
+                    }
+                    self.read_token()?;
+                }
+                Ok(ASTNode {
+                    kind,
+                    lhs: None,
+                    rhs: None,
+                    line: None,
+                })
+            }
+
+            ref op @ (TokenKind::OpAdd
+            | TokenKind::OpSubtract
+            | TokenKind::OpNot
+            | TokenKind::OpIncrement
+            | TokenKind::OpDecrement) => {
+                // 被演算子には、単項演算子より強く結合する二項演算子だけを取り込む
+                let primary = self.parse_primary()?;
+                let operand = self.parse_expr_body(primary, UNARY_PRECEDENCE + 1)?;
+                Ok(match op {
+                    TokenKind::OpSubtract => ASTNode::unary(NodeKind::Negate, operand),
+                    TokenKind::OpNot => ASTNode::unary(NodeKind::Not, operand),
+                    // 式の中の ++, -- は単項演算子 2 つとして扱う
+                    TokenKind::OpDecrement => {
+                        ASTNode::unary(NodeKind::Negate, ASTNode::unary(NodeKind::Negate, operand))
+                    }
+                    _ => operand,
+                })
+            }
+            _ => Err(CompileError::new(ErrorKind::SyntaxError, "invalid primary")
+                .with_position(token.borrow().line_number(), token.borrow().column_number())),
+        }
+    }
+}
+
+/// ソースを字句解析・構文解析して AST を返す。
+/// どんな入力に対しても panic せず、エラーは Err で返す (ファジング用の入口)。
+pub fn parse_str(src: &str) -> Result<ASTNode> {
+    parse_str_with(src, &ParserOptions::default())
+}
+
+/// options の方言で字句解析・構文解析する。
+pub fn parse_str_with(src: &str, options: &ParserOptions) -> Result<ASTNode> {
+    parse_str_with_diagnostics(src, options, &mut Diagnostics::default())
+}
+
+/// parse_str_with と同じく解析し、警告を diagnostics に記録する。
+pub fn parse_str_with_diagnostics(
+    src: &str,
+    options: &ParserOptions,
+    diagnostics: &mut Diagnostics,
+) -> Result<ASTNode> {
+    let lexer = LexicalAnalyzer::with_dialect(src.chars(), options.dialect.clone());
+    SyntaxAnalyzer::parse_with_diagnostics(lexer, options, diagnostics)
+}
+
+/// src を 1 つの式として字句解析・構文解析する。
+pub fn parse_expression_str(src: &str) -> Result<ASTNode> {
+    parse_expression_str_with(src, &ParserOptions::default())
+}
+
+pub fn parse_expression_str_with(src: &str, options: &ParserOptions) -> Result<ASTNode> {
+    let lexer = LexicalAnalyzer::with_dialect(src.chars(), options.dialect.clone());
+    SyntaxAnalyzer::parse_expression_with(lexer, options)
+}
+
+/// 対話的に入力したソースを解析した結果
+#[derive(Debug)]
+pub enum ParseOutcome {
+    /// 1 つ以上の文を最後まで解析できた
+    Complete(ASTNode),
+    /// 入力の終わりまで読んでも文が終わっていない。閉じていないブロックやコメントなど。
+    /// 続きの行を加えて解析し直せばよい。
+    Incomplete,
+    /// 続きの入力があっても解析できない
+    Error(CompileError),
+}
+
+/// REPL の 1 行 (と、それまでに入力した行) を解析する。
+/// 1 行に複数の文を書いてもよく、文が終わっていなければ Incomplete を返す。
+pub fn parse_interactive(src: &str) -> ParseOutcome {
+    parse_interactive_with(src, &ParserOptions::default())
+}
+
+pub fn parse_interactive_with(src: &str, options: &ParserOptions) -> ParseOutcome {
+    // 続きは次の行になるので、改行を付けて解析する。
+    // 文字列のように行をまたげないものは、ここで行の終わりのエラーになる。
+    let src = format!("{}\n", src);
+    match parse_str_with(&src, options) {
+        Ok(ast) => ParseOutcome::Complete(ast),
+        // 入力の終わりで見つけたエラーと閉じていないコメントは、入力が足りないだけ
+        Err(e)
+            if e.position() == Some((1 + src.matches('\n').count(), 1))
+                || e.message() == lexical_analyzer::UNTERMINATED_COMMENT =>
+        {
+            ParseOutcome::Incomplete
         }
+        Err(e) => ParseOutcome::Error(e),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use lexical_analyzer::diagnostic::Severity;
     use lexical_analyzer::*;
 
     fn create_tokens(s: String) -> Vec<Token> {
@@ -557,11 +1309,19 @@ mod tests {
         }
     }
 
-    fn create_parser(mut token_iter: IntoIter<Token>) -> SyntaxAnalyzer {
-        match token_iter.next() {
+    fn create_parser(
+        token_iter: std::vec::IntoIter<Token>,
+    ) -> SyntaxAnalyzer<Token, impl Iterator<Item = Result<Token>>> {
+        let mut token_iter = token_iter.map(Ok);
+        match token_iter.next().transpose().unwrap() {
             Some(next_token) => SyntaxAnalyzer {
-                token_iter,
+                token_iter: token_iter.peekable(),
                 next_token,
+                depth: 0,
+                max_depth: DEFAULT_MAX_DEPTH,
+                dialect: Dialect::default(),
+                operators: OperatorTable::default(),
+                warnings: Vec::new(),
             },
             None => unreachable!(),
         }
@@ -570,351 +1330,484 @@ mod tests {
     #[test]
     fn test_identifier() {
         let tokens = create_tokens(r#"count"#.to_string());
-        assert_eq!(
-            r#"Identifier count
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Ident "count")
         );
 
         let tokens = create_tokens(r#"+count"#.to_string());
-        assert_eq!(
-            r#"Identifier count
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Ident "count")
         );
 
         let tokens = create_tokens(r#"-count"#.to_string());
-        assert_eq!(
-            r#"Negate
-Identifier count
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Negate (Ident "count"))
         );
 
         let tokens = create_tokens(r#"!count"#.to_string());
-        assert_eq!(
-            r#"Not
-Identifier count
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Not (Ident "count"))
         );
     }
 
     #[test]
     fn test_integer() {
         let tokens = create_tokens(r#"1234"#.to_string());
-        assert_eq!(
-            r#"Integer 1234
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Int 1234)
         );
 
         let tokens = create_tokens(r#"+1234"#.to_string());
-        assert_eq!(
-            r#"Integer 1234
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Int 1234)
         );
 
         let tokens = create_tokens(r#"-1234"#.to_string());
-        assert_eq!(
-            r#"Negate
-Integer 1234
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Negate (Int 1234))
         );
 
         let tokens = create_tokens(r#"!1234"#.to_string());
-        assert_eq!(
-            r#"Not
-Integer 1234
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_primary().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_primary().unwrap(),
+            (Not (Int 1234))
         );
     }
 
     #[test]
     fn test_expr() {
         let tokens = create_tokens(r#"count + 1"#.to_string());
-        assert_eq!(
-            r#"Add
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Add (Ident "count") (Int 1))
         );
 
         let tokens = create_tokens(r#"+count * 1"#.to_string());
-        assert_eq!(
-            r#"Multiply
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Multiply (Ident "count") (Int 1))
         );
 
         let tokens = create_tokens(r#"count < 1"#.to_string());
-        assert_eq!(
-            r#"Less
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Less (Ident "count") (Int 1))
         );
 
         let tokens = create_tokens(r#"count == 1"#.to_string());
-        assert_eq!(
-            r#"Equal
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Equal (Ident "count") (Int 1))
         );
 
         let tokens = create_tokens(r#"count && 1"#.to_string());
-        assert_eq!(
-            r#"And
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (And (Ident "count") (Int 1))
         );
 
         let tokens = create_tokens(r#"count || 1"#.to_string());
-        assert_eq!(
-            r#"Or
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Or (Ident "count") (Int 1))
         );
 
         let tokens = create_tokens(
             r#"a || b && c != d > e - f / g % h + i >= j == k && l || m"#.to_string(),
         );
-        assert_eq!(
-            r#"Or
-Or
-Identifier a
-And
-And
-Identifier b
-Equal
-NotEqual
-Identifier c
-GreaterEqual
-Greater
-Identifier d
-Add
-Subtract
-Identifier e
-Mod
-Divide
-Identifier f
-Identifier g
-Identifier h
-Identifier i
-Identifier j
-Identifier k
-Identifier l
-Identifier m
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Or
+                (Or
+                    (Ident "a")
+                    (And
+                        (And
+                            (Ident "b")
+                            (Equal
+                                (NotEqual
+                                    (Ident "c")
+                                    (GreaterEqual
+                                        (Greater
+                                            (Ident "d")
+                                            (Add
+                                                (Subtract
+                                                    (Ident "e")
+                                                    (Mod
+                                                        (Divide (Ident "f") (Ident "g"))
+                                                        (Ident "h")))
+                                                (Ident "i")))
+                                        (Ident "j")))
+                                (Ident "k")))
+                        (Ident "l")))
+                (Ident "m"))
         );
 
         let tokens = create_tokens(r#"(a * b) + c"#.to_string());
-        assert_eq!(
-            r#"Add
-Multiply
-Identifier a
-Identifier b
-Identifier c
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Add (Multiply (Ident "a") (Ident "b")) (Ident "c"))
         );
 
         let tokens = create_tokens(r#"a * (b + c)"#.to_string());
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Multiply (Ident "a") (Add (Ident "b") (Ident "c")))
+        );
+    }
+
+    #[test]
+    fn test_bitwise_expr() {
+        // 優先順位は || < && < | < ^ < & < 等価 < 比較 < シフト < 加減算
+        let tokens = create_tokens(r#"a || b && c | d ^ e & f == g < h << i + j >> k"#.to_string());
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (Or
+                (Ident "a")
+                (And
+                    (Ident "b")
+                    (BitOr
+                        (Ident "c")
+                        (BitXor
+                            (Ident "d")
+                            (BitAnd
+                                (Ident "e")
+                                (Equal
+                                    (Ident "f")
+                                    (Less
+                                        (Ident "g")
+                                        (ShiftRight
+                                            (ShiftLeft (Ident "h") (Add (Ident "i") (Ident "j")))
+                                            (Ident "k")))))))))
+        );
+
+        let tokens = create_tokens(r#"a & b & c"#.to_string());
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_expr().unwrap(),
+            (BitAnd (BitAnd (Ident "a") (Ident "b")) (Ident "c"))
+        );
+    }
+
+    #[test]
+    fn test_power_expr() {
+        // ** は右に結合し、乗除算と単項演算子より強く結合する
+        assert_ast!(
+            expr("a * b ** c ** d"),
+            (Multiply (Ident "a") (Power (Ident "b") (Power (Ident "c") (Ident "d"))))
+        );
+        assert_ast!(
+            expr("-a ** -b"),
+            (Negate (Power (Ident "a") (Negate (Ident "b"))))
+        );
+        assert_ast!(
+            expr("(-a) ** b"),
+            (Power (Negate (Ident "a")) (Ident "b"))
+        );
+        let ast = parse_str("x = 2 ** 10;").unwrap();
+        let text = format!("{}", ast);
         assert_eq!(
-            r#"Multiply
-Identifier a
-Add
-Identifier b
-Identifier c
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_expr().unwrap()
-            ),
+            text,
+            format!("{}", ASTReader::read_ast(text.lines()).unwrap())
+        );
+
+        let rosetta = ParserOptions {
+            dialect: Dialect::rosetta(),
+            ..Default::default()
+        };
+        assert!(parse_str_with("x = 2 ** 10;", &rosetta).is_err());
+    }
+
+    #[test]
+    fn test_rand_expr() {
+        assert_ast!(
+            expr("rand(n + 1) + 1"),
+            (Add (Rand (Add (Ident "n") (Int 1))) (Int 1))
+        );
+        // '(' が続かなければ、タスクの文法どおり rand は識別子
+        assert_eq!(
+            parse_str("x = rand;").unwrap().sequence_items()[0]
+                .rhs()
+                .unwrap(),
+            &ASTNode::leaf_identifier("rand")
+        );
+        assert!(parse_str("x = rand();").is_err());
+        // rosetta の方言では rand は識別子
+        let rosetta = ParserOptions {
+            dialect: Dialect::rosetta(),
+            ..Default::default()
+        };
+        assert!(parse_str_with("rand = 1; x = rand;", &rosetta).is_ok());
+        assert!(parse_str_with("x = rand(6);", &rosetta).is_err());
+    }
+
+    #[test]
+    fn test_clock_expr() {
+        assert_ast!(
+            expr("clock() - t0"),
+            (Subtract (Clock) (Ident "t0"))
+        );
+        assert!(parse_str("t = clock;").is_ok());
+        assert!(parse_str("t = clock(1);").is_err());
+        let ast = parse_str("t = clock();").unwrap();
+        let text = format!("{}", ast);
+        assert_eq!(
+            text,
+            format!("{}", ASTReader::read_ast(text.lines()).unwrap())
+        );
+    }
+
+    fn expr(s: &str) -> ASTNode {
+        let tokens = create_tokens(s.to_string());
+        create_parser(tokens.into_iter()).parse_expr().unwrap()
+    }
+
+    #[test]
+    fn test_unary_expr() {
+        // 単項演算子はどの二項演算子よりも強く結合する
+        assert_ast!(
+            expr("-a * b"),
+            (Multiply (Negate (Ident "a")) (Ident "b"))
+        );
+        assert_ast!(
+            expr("-(a * b)"),
+            (Negate (Multiply (Ident "a") (Ident "b")))
+        );
+        // -- は - - 、 ++ は + + として扱う
+        assert_ast!(
+            expr("--x"),
+            (Negate (Negate (Ident "x")))
+        );
+        assert_eq!(expr("- -x"), expr("--x"));
+        assert_eq!(expr("x"), expr("++x"));
+        assert_eq!(expr("x"), expr("+ + +x"));
+        assert_ast!(
+            expr("!-x"),
+            (Not (Negate (Ident "x")))
         );
+        assert_ast!(
+            expr("-!---x"),
+            (Negate (Not (Negate (Negate (Negate (Ident "x"))))))
+        );
+    }
+
+    #[test]
+    fn test_precedence_matrix() {
+        // 優先順位の低い順。同じ段の演算子は同じ優先順位
+        let levels: &[&[&str]] = &[
+            &["||"],
+            &["&&"],
+            &["|"],
+            &["^"],
+            &["&"],
+            &["==", "!="],
+            &["<", "<=", ">", ">="],
+            &["<<", ">>"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        for (i, low_level) in levels.iter().enumerate() {
+            for (j, high_level) in levels.iter().enumerate() {
+                for low in low_level.iter() {
+                    for high in high_level.iter() {
+                        let source = format!("a {} b {} c", low, high);
+                        let expected = if i < j {
+                            format!("a {} (b {} c)", low, high)
+                        } else {
+                            // 同じ優先順位なら左結合
+                            format!("(a {} b) {} c", low, high)
+                        };
+                        assert_eq!(expr(&expected), expr(&source), "{}", source);
+
+                        let source = format!("a {} b {} c", high, low);
+                        let expected = if i <= j {
+                            format!("(a {} b) {} c", high, low)
+                        } else {
+                            format!("a {} (b {} c)", high, low)
+                        };
+                        assert_eq!(expr(&expected), expr(&source), "{}", source);
+                    }
+                }
+            }
+            // 単項演算子は二項演算子より強く結合する
+            for op in low_level.iter() {
+                for unary in &["-", "!", "+"] {
+                    let source = format!("{}a {} {}b", unary, op, unary);
+                    let expected = format!("({}a) {} ({}b)", unary, op, unary);
+                    assert_eq!(expr(&expected), expr(&source), "{}", source);
+                }
+            }
+        }
     }
 
     #[test]
     fn test_paren_expr() {
         let tokens = create_tokens(r#"(b + c)"#.to_string());
-        assert_eq!(
-            r#"Add
-Identifier b
-Identifier c
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter())
-                    .parse_paren_expr()
-                    .unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_paren_expr().unwrap(),
+            (Add (Ident "b") (Ident "c"))
         );
     }
 
     #[test]
     fn test_prt_list() {
         let tokens = create_tokens(r#"a + b"#.to_string());
-        assert_eq!(
-            r#"Sequence
-;
-Prti
-Add
-Identifier a
-Identifier b
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_prt_list().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_prt_list().unwrap(),
+            (Sequence _ (Prti (Add (Ident "a") (Ident "b"))))
         );
 
         let tokens = create_tokens(r#""hoge""#.to_string());
-        assert_eq!(
-            r#"Sequence
-;
-Prts
-String "hoge"
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_prt_list().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_prt_list().unwrap(),
+            (Sequence _ (Prts (Str "hoge")))
         );
 
         let tokens = create_tokens(r#"a + b, "hoge", 1"#.to_string());
-        assert_eq!(
-            r#"Sequence
-Sequence
-Sequence
-;
-Prti
-Add
-Identifier a
-Identifier b
-;
-Prts
-String "hoge"
-;
-Prti
-Integer 1
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_prt_list().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_prt_list().unwrap(),
+            (Sequence
+                (Sequence (Sequence _ (Prti (Add (Ident "a") (Ident "b")))) (Prts (Str "hoge")))
+                (Prti (Int 1)))
         );
     }
 
     #[test]
     fn test_putc_stmt() {
         let tokens = create_tokens(r#"putc(a + b);"#.to_string());
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_putc_stmt().unwrap(),
+            (Prtc (Add (Ident "a") (Ident "b")))
+        );
+
+        // 複数の式は print と同じく Sequence に並べる
+        let tokens = create_tokens("putc(a,\n'b');".to_string());
+        let node = create_parser(tokens.into_iter()).parse_putc_stmt().unwrap();
         assert_eq!(
-            r#"Prtc
-Add
-Identifier a
-Identifier b
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_putc_stmt().unwrap()
-            ),
+            ASTNode::sequence(vec![
+                ASTNode::unary(NodeKind::Prtc, ASTNode::leaf_identifier("a")),
+                ASTNode::unary(NodeKind::Prtc, ASTNode::leaf_int(98)),
+            ]),
+            node
+        );
+        let lines: Vec<_> = node.sequence_items().iter().map(|n| n.line()).collect();
+        assert_eq!(vec![Some(1), Some(2)], lines);
+
+        assert!(parse_str("putc();").is_err());
+        assert!(parse_str("putc(1,);").is_err());
+        assert!(parse_str("putc(1, 2;").is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(
+            vec![
+                FormatPiece::Text("x = ".to_string()),
+                FormatPiece::Integer,
+                FormatPiece::Text(", 100%\n".to_string()),
+                FormatPiece::String,
+                FormatPiece::Char,
+            ],
+            parse_format("x = %d, 100%%\n%s%c").unwrap()
+        );
+        assert!(parse_format("").unwrap().is_empty());
+        assert!(parse_format("%x").is_err());
+        assert!(parse_format("100%").is_err());
+    }
+
+    #[test]
+    fn test_printf_stmt() {
+        let parse = |src| ast_node::normalize(parse_str(src).unwrap());
+        assert_eq!(
+            parse(r#"print("x = ", x, ", "); putc(c); print("!", "\n");"#),
+            parse(r#"printf("x = %d, %c%s\n", x, c, "!");"#)
+        );
+        assert_eq!(parse(r#"print("50%");"#), parse(r#"printf("50%%");"#));
+        assert_eq!(parse(""), parse(r#"printf("");"#));
+
+        // 書式と引数の誤りは、その位置のエラーにする
+        let error = |src| parse_str(src).unwrap_err().position();
+        assert_eq!(Some((1, 8)), error(r#"printf("%d %d", 1);"#));
+        assert_eq!(Some((1, 8)), error(r#"printf("", 1);"#));
+        assert_eq!(Some((1, 14)), error(r#"printf("%d", "s");"#));
+        assert_eq!(Some((1, 14)), error(r#"printf("%s", 1);"#));
+        assert_eq!(Some((1, 8)), error(r#"printf("%q", 1);"#));
+        assert!(parse_str("printf(x);").is_err());
+        assert!(parse_str(r#"printf("%d", 1)"#).is_err());
+
+        // %s には文字列を入れる変数も渡せる
+        let ast = parse_str(r#"s = "abc"; printf("[%s]", s);"#).unwrap();
+        assert!(typed::check(&ast).is_ok());
+        assert!(typed::check(&parse_str(r#"n = 1; printf("%s", n);"#).unwrap()).is_err());
+
+        let rosetta = ParserOptions {
+            dialect: Dialect::rosetta(),
+            ..Default::default()
+        };
+        assert!(parse_str_with(r#"printf("a");"#, &rosetta).is_err());
+    }
+
+    #[test]
+    fn test_exit_stmt() {
+        let tokens = create_tokens("exit(n - 1);".to_string());
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_exit_stmt().unwrap(),
+            (Exit (Subtract (Ident "n") (Int 1)))
+        );
+        assert!(parse_str("if (x) exit(1); else print(\"ok\");").is_ok());
+        assert!(parse_str("exit();").is_err());
+        assert!(parse_str("exit(1)").is_err());
+        // 文字列は式として読めるが、終了コードには使えない
+        assert!(typed::check(&parse_str("exit(\"a\");").unwrap()).is_err());
+
+        let rosetta = ParserOptions {
+            dialect: Dialect::rosetta(),
+            ..Default::default()
+        };
+        assert!(parse_str_with("exit(1);", &rosetta).is_err());
+    }
+
+    #[test]
+    fn test_assert_stmt() {
+        let tokens = create_tokens(r#"assert(x > 0, "positive");"#.to_string());
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_assert_stmt().unwrap(),
+            (Assert (Greater (Ident "x") (Int 0)) (Str "positive"))
+        );
+
+        let ast = parse_str("x = 1;\nif (x) assert(x, \"one\");").unwrap();
+        let assertion = ast.sequence_items()[1].rhs().unwrap().lhs().unwrap();
+        assert_eq!(
+            "assertion failed at line 2: one",
+            assertion.assertion_message()
         );
+
+        assert!(parse_str("assert(x);").is_err());
+        assert!(parse_str("assert(x, y);").is_err());
+        assert!(parse_str(r#"assert(x, "a")"#).is_err());
+        // 条件に文字列は使えない
+        assert!(typed::check(&parse_str(r#"assert("a", "b");"#).unwrap()).is_err());
+
+        let rosetta = ParserOptions {
+            dialect: Dialect::rosetta(),
+            ..Default::default()
+        };
+        assert!(parse_str_with(r#"assert(1, "a");"#, &rosetta).is_err());
     }
 
     #[test]
     fn test_print_stmt() {
         let tokens = create_tokens(r#"print("count is: ", count, "\n");"#.to_string());
-        assert_eq!(
-            r#"Sequence
-Sequence
-Sequence
-;
-Prts
-String "count is: "
-;
-Prti
-Identifier count
-;
-Prts
-String "\n"
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter())
-                    .parse_print_stmt()
-                    .unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_print_stmt().unwrap(),
+            (Sequence
+                (Sequence (Sequence _ (Prts (Str "count is: "))) (Prti (Ident "count")))
+                (Prts (Str "\n")))
         );
     }
 
@@ -926,25 +1819,9 @@ String "\n"
 "#
             .to_string(),
         );
-        assert_eq!(
-            r#"If
-Not
-Mod
-Identifier i
-Integer 15
-;
-If
-Sequence
-;
-Prts
-String "FizzBuzz"
-;
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_if_stmt().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_if_stmt().unwrap(),
+            (If (Not (Mod (Ident "i") (Int 15))) (If (Sequence _ (Prts (Str "FizzBuzz")))))
         );
 
         let tokens = create_tokens(
@@ -959,53 +1836,21 @@ else
 "#
             .to_string(),
         );
-        assert_eq!(
-            r#"If
-Not
-Mod
-Identifier i
-Integer 15
-;
-If
-Sequence
-;
-Prts
-String "FizzBuzz"
-;
-If
-Not
-Mod
-Identifier i
-Integer 3
-;
-If
-Sequence
-;
-Prts
-String "Fizz"
-;
-If
-Not
-Mod
-Identifier i
-Integer 5
-;
-If
-Sequence
-;
-Prts
-String "Buzz"
-;
-Sequence
-;
-Prti
-Identifier i
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_if_stmt().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_if_stmt().unwrap(),
+            (If
+                (Not (Mod (Ident "i") (Int 15)))
+                (If
+                    (Sequence _ (Prts (Str "FizzBuzz")))
+                    (If
+                        (Not (Mod (Ident "i") (Int 3)))
+                        (If
+                            (Sequence _ (Prts (Str "Fizz")))
+                            (If
+                                (Not (Mod (Ident "i") (Int 5)))
+                                (If
+                                    (Sequence _ (Prts (Str "Buzz")))
+                                    (Sequence _ (Prti (Ident "i")))))))))
         );
     }
 
@@ -1018,101 +1863,130 @@ Identifier i
 }"#
             .to_string(),
         );
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_while_stmt().unwrap(),
+            (While
+                (Less (Ident "count") (Int 10))
+                (Sequence
+                    (Sequence
+                        _
+                        (Sequence
+                            (Sequence (Sequence _ (Prts (Str "count is: "))) (Prti (Ident "count")))
+                            (Prts (Str "\n"))))
+                    (Assign (Ident "count") (Add (Ident "count") (Int 1)))))
+        );
+    }
+
+    #[test]
+    fn test_do_while_stmt() {
+        let tokens = create_tokens(r#"do { count = count + 1; } while (count < 10);"#.to_string());
+        let ast = create_parser(tokens.into_iter())
+            .parse_do_while_stmt()
+            .unwrap();
+        // 平坦化した AST を読み直しても同じになる
+        let flattened = format!("{}", ast);
+        assert_eq!(ast, ASTReader::read_ast(flattened.lines()).unwrap());
+        assert_ast!(
+            ast,
+            (DoWhile
+                (Less (Ident "count") (Int 10))
+                (Sequence _ (Assign (Ident "count") (Add (Ident "count") (Int 1)))))
+        );
+
+        for src in &[
+            "do count = 1; (count < 10);",
+            "do count = 1; while count < 10;",
+            "do count = 1; while (count < 10)",
+        ] {
+            let tokens = create_tokens(src.to_string());
+            assert!(create_parser(tokens.into_iter())
+                .parse_do_while_stmt()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_compound_assign_stmt() {
+        for &(compound, expanded) in &[
+            ("a += b * 2;", "a = a + (b * 2);"),
+            ("a -= 1 - b;", "a = a - (1 - b);"),
+            ("a *= b + 1;", "a = a * (b + 1);"),
+            ("a /= 2;", "a = a / 2;"),
+            ("a %= b;", "a = a % b;"),
+        ] {
+            let tokens = create_tokens(compound.to_string());
+            let compound = create_parser(tokens.into_iter())
+                .parse_assign_stmt()
+                .unwrap();
+            let tokens = create_tokens(expanded.to_string());
+            let expanded = create_parser(tokens.into_iter())
+                .parse_assign_stmt()
+                .unwrap();
+            assert_eq!(expanded, compound);
+        }
+
+        let tokens = create_tokens("a + 1;".to_string());
+        assert!(create_parser(tokens.into_iter())
+            .parse_assign_stmt()
+            .is_err());
+    }
+
+    #[test]
+    fn test_increment_stmt() {
+        let options = ParserOptions {
+            dialect: Dialect::increment(),
+            ..Default::default()
+        };
+        let parse = |src| parse_str_with(src, &options);
+        for &(increment, expanded) in &[
+            ("++i;", "i = i + 1;"),
+            ("i++;", "i = i + 1;"),
+            ("--i;", "i = i - 1;"),
+            ("i--;", "i = i - 1;"),
+        ] {
+            let expanded = parse(expanded).unwrap();
+            assert_eq!(expanded, parse(increment).unwrap());
+        }
+
+        assert!(parse("++1;").is_err());
+        assert!(parse("i++ 1;").is_err());
+        assert!(parse("++i").is_err());
+
+        // 既定の方言では ++ と -- を使えず、タスクの文法どおり 5--3 は 5 - (-3)
+        assert!(parse_str("i++;").is_err());
         assert_eq!(
-            r#"While
-Less
-Identifier count
-Integer 10
-Sequence
-Sequence
-;
-Sequence
-Sequence
-Sequence
-;
-Prts
-String "count is: "
-;
-Prti
-Identifier count
-;
-Prts
-String "\n"
-;
-Assign
-Identifier count
-Add
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter())
-                    .parse_while_stmt()
-                    .unwrap()
-            ),
+            parse_str("x = 5 - (-3);").unwrap(),
+            parse_str("x = 5--3;").unwrap()
         );
     }
 
     #[test]
     fn test_assign_stmt() {
         let tokens = create_tokens(r#"count = count + 1;"#.to_string());
-        assert_eq!(
-            r#"Assign
-Identifier count
-Add
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter())
-                    .parse_assign_stmt()
-                    .unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_assign_stmt().unwrap(),
+            (Assign (Ident "count") (Add (Ident "count") (Int 1)))
         );
     }
 
     #[test]
     fn test_stmt_list() {
         let tokens = create_tokens(r#""#.to_string());
-        assert_eq!(
-            r#"Sequence
-;
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_stmt_list().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_stmt_list().unwrap(),
+            (Sequence)
         );
 
         let tokens = create_tokens(r#";"#.to_string());
-        assert_eq!(
-            r#"Sequence
-;
-Sequence
-;
-;
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_stmt_list().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_stmt_list().unwrap(),
+            (Sequence _ (Sequence))
         );
 
         let tokens = create_tokens(r#"count = 1;"#.to_string());
-        assert_eq!(
-            r#"Sequence
-;
-Assign
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_stmt_list().unwrap()
-            ),
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_stmt_list().unwrap(),
+            (Sequence _ (Assign (Ident "count") (Int 1)))
         );
 
         let tokens = create_tokens(
@@ -1121,33 +1995,315 @@ Integer 1
 "#
             .to_string(),
         );
+        assert_ast!(
+            create_parser(tokens.into_iter()).parse_stmt_list().unwrap(),
+            (Sequence
+                (Sequence
+                    _
+                    (Sequence
+                        (Sequence (Sequence _ (Prts (Str "count is: "))) (Prti (Ident "count")))
+                        (Prts (Str "\n"))))
+                (Assign (Ident "count") (Add (Ident "count") (Int 1))))
+        );
+    }
+
+    #[test]
+    fn test_parse_ref() {
+        let src = r#"count = 1; while (count < 10) { print("count is: ", count, "\n"); count = count + 1; }"#;
+        let tokens = create_tokens(src.to_string());
+        let borrowed = SyntaxAnalyzer::parse_ref(&tokens).unwrap();
+        let owned =
+            SyntaxAnalyzer::parse(create_tokens(src.to_string()).into_iter().map(Ok)).unwrap();
+        assert_eq!(owned, borrowed);
+
+        let assign = borrowed.sequence_items()[0];
+        match assign.lhs().unwrap().kind() {
+            NodeKind::Identifier(identifier) => assert_eq!("count", &*identifier.text()),
+            kind => panic!("unexpected node: {:?}", kind),
+        }
+
+        let tokens = create_tokens("count = ;".to_string());
+        assert!(SyntaxAnalyzer::parse_ref(&tokens).is_err());
+    }
+
+    #[test]
+    fn test_parse_str() {
+        assert!(parse_str("count = 1;").is_ok());
+        assert!(parse_str("count = 1; }").is_err());
+        assert!(parse_str("print(").is_err());
+        assert!(parse_str("x = ;").is_err());
+        assert_eq!(
+            Some((2, 5)),
+            parse_str("x = 1;\ny = ;").unwrap_err().position()
+        );
+
+        // 深い入れ子はスタックを溢れさせずにエラーになる
+        let src = format!("x = {}1{};", "(".repeat(10_000), ")".repeat(10_000));
+        assert!(parse_str(&src).is_err());
+        let src = format!("x = {}1;", "-".repeat(10_000));
+        assert!(parse_str(&src).is_err());
+        let src = format!("{}{}", "{".repeat(10_000), "}".repeat(10_000));
+        assert!(parse_str(&src).is_err());
+        let src = format!("{};", "while (1) ".repeat(10_000));
+        assert!(parse_str(&src).is_err());
+
+        let src = format!("x = {}1{};", "(".repeat(100), ")".repeat(100));
+        assert!(parse_str(&src).is_ok());
+    }
+
+    #[test]
+    fn test_parse_lexer() {
+        // 字句解析器から直接読んでも、トークン列から読んだ場合と同じ AST になる
+        let src = r#"count = 1; while (count < 10) { print("count is: ", count, "\n"); count = count + 1; }"#;
+        let streamed = SyntaxAnalyzer::parse(LexicalAnalyzer::new(src.chars())).unwrap();
+        let tokens = create_tokens(src.to_string());
+        let borrowed = SyntaxAnalyzer::parse_ref(&tokens).unwrap();
+        assert_eq!(borrowed, streamed);
+
+        // 字句解析のエラーは、字句解析器が付けた位置のまま返す
+        let src = "x = 1;\ny = 'ab';";
+        let e = SyntaxAnalyzer::parse(LexicalAnalyzer::new(src.chars())).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::LexicalAnalyzerError));
+        assert_eq!(lex_all(src).unwrap_err().position(), e.position());
+        let e = SyntaxAnalyzer::parse(LexicalAnalyzer::new("@".chars())).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::LexicalAnalyzerError));
+
+        // 構文のエラーは字句解析のエラーより先に見つかる
+        let e = SyntaxAnalyzer::parse(LexicalAnalyzer::new("x = ; y = 'ab';".chars())).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::SyntaxError));
+    }
+
+    #[test]
+    fn test_line() {
+        let ast = parse_str("x = 1;\nwhile (x)\n  print(x,\n    \"\\n\");").unwrap();
+        let items = ast.sequence_items();
+        assert_eq!(Some(1), items[0].line());
+        assert_eq!(None, items[0].rhs().unwrap().line());
+        assert_eq!(Some(2), items[1].line());
+        // print の引数はそれぞれの行を持つ
+        let print = items[1].rhs().unwrap();
+        assert_eq!(Some(3), print.line());
+        let args = print.sequence_items();
+        assert_eq!(Some(3), args[0].line());
+        assert_eq!(Some(4), args[1].line());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let options = ParserOptions {
+            max_depth: 8,
+            ..Default::default()
+        };
+        let parse = |src: &str| {
+            SyntaxAnalyzer::parse_with(create_tokens(src.to_string()).into_iter().map(Ok), &options)
+        };
+
+        // 文の数は入れ子の深さに数えない
+        assert!(parse(&"x = 1;".repeat(1000)).is_ok());
+        assert!(parse("x = ((((1))));").is_ok());
+        assert!(parse("x = ((((((((1))))))));").is_err());
+        assert!(parse("{{{{{{{{{}}}}}}}}}").is_err());
+    }
+
+    #[test]
+    fn test_dialect() {
+        let rosetta = ParserOptions {
+            dialect: Dialect::rosetta(),
+            ..Default::default()
+        };
+        assert!(parse_str_with("x = 1; while (x < 3) x = x + 1;", &rosetta).is_ok());
+        assert!(parse_str_with("x = 1; x += 1;", &rosetta).is_err());
+        assert!(parse_str_with("x = 1; do x = x + 1; while (x < 3);", &rosetta).is_err());
+        assert!(parse_str_with("x = 1 << 2;", &rosetta).is_err());
+        assert!(parse_str_with("putc(1);", &rosetta).is_ok());
+        assert!(parse_str_with("putc(1, 2);", &rosetta).is_err());
+
+        // タスクの文法で書いたプログラムは、拡張のキーワードを変数に使っても同じ意味になる
+        let src = "rand = 3; do = 2; geti = rand + do; exit = 1; assert = 2;\n\
+                   printf = 3; clock = 4; getc = 5; do = do + 1;\n\
+                   print(rand, do, geti, exit, assert, printf, clock, getc);";
+        let ast = parse_str(src).unwrap();
+        assert_eq!(parse_str_with(src, &rosetta).unwrap(), ast);
+        // 組み込み関数や文としても使える
+        let src = "do = 2; do do = do - 1; while (do); x = rand(do) + geti(); exit(x);";
+        let mut items = parse_str(src).unwrap().into_sequence_items();
+        assert_ast!(items.pop().unwrap(), (Exit (Ident "x")));
+        assert_ast!(
+            items.pop().unwrap(),
+            (Assign (Ident "x") (Add (Rand (Ident "do")) (Geti)))
+        );
+        assert_ast!(
+            items.pop().unwrap(),
+            (DoWhile (Ident "do") (Assign (Ident "do") (Subtract (Ident "do") (Int 1))))
+        );
+
+        let increment = ParserOptions {
+            dialect: Dialect::increment(),
+            ..Default::default()
+        };
+        assert_ast!(
+            parse_str_with("++rand;", &increment).unwrap(),
+            (Sequence _ (Assign (Ident "rand") (Add (Ident "rand") (Int 1))))
+        );
+
+        // 方言で使えないトークンは、その位置と綴りを示す
+        let tokens = lexical_analyzer::lex_all("x = 1;\ny = x ** 2;").unwrap();
+        let e = SyntaxAnalyzer::parse_ref_with(&tokens, &rosetta).unwrap_err();
+        assert_eq!("'**' is not supported in this dialect", e.message());
+        assert_eq!(Some((2, 7)), e.position());
+
+        // トークン列から解析する場合も方言を検査する
+        let tokens = lexical_analyzer::lex_all_with("x = 1; x++;", &Dialect::increment()).unwrap();
+        assert!(SyntaxAnalyzer::parse_ref_with(&tokens, &rosetta).is_err());
+        assert!(SyntaxAnalyzer::parse_ref(&tokens).is_err());
+        let increment = ParserOptions {
+            dialect: Dialect::increment(),
+            ..Default::default()
+        };
+        assert!(SyntaxAnalyzer::parse_ref_with(&tokens, &increment).is_ok());
+    }
+
+    #[test]
+    fn test_chained_comparison() {
+        let warnings = |src: &str| {
+            let mut diagnostics = Diagnostics::default();
+            parse_str_with_diagnostics(src, &ParserOptions::default(), &mut diagnostics).unwrap();
+            diagnostics
+                .iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<_>>()
+        };
+        let message = "chained comparison: 'a < b < c' compares the result of 'a < b' with c; use '&&' or add parentheses [chained-comparison]";
+
+        // 2 つ目の比較演算子の位置で警告する
+        assert_eq!(
+            vec![format!("1:11: warning: {}", message)],
+            warnings("x = a < b < c;")
+        );
         assert_eq!(
-            r#"Sequence
-Sequence
-;
-Sequence
-Sequence
-Sequence
-;
-Prts
-String "count is: "
-;
-Prti
-Identifier count
-;
-Prts
-String "\n"
-;
-Assign
-Identifier count
-Add
-Identifier count
-Integer 1
-"#,
-            format!(
-                "{}",
-                create_parser(tokens.into_iter()).parse_stmt_list().unwrap()
-            ),
+            vec![format!("2:7: warning: {}", message)],
+            warnings("x = 1;\nif (a == b < c) print(x);")
+        );
+        assert_eq!(
+            vec![format!("1:16: warning: {}", message)],
+            warnings("x = a || b < c == d;")
+        );
+        assert_eq!(2, warnings("x = a < b < c < d;").len());
+
+        // 括弧で囲んだ比較や、論理演算子でつないだ比較は警告しない
+        assert!(warnings("x = (a < b) < c;").is_empty());
+        assert!(warnings("x = a < (b < c);").is_empty());
+        assert!(warnings("x = a < b && b < c;").is_empty());
+        assert!(warnings("x = a + b < c * d;").is_empty());
+        assert!(warnings("x = -(a < b) == 0;").is_empty());
+
+        // 警告をエラーにしても解析は続け、解析のエラーより前の警告は残す
+        let mut denied = Diagnostics::new(true);
+        let result = parse_str_with_diagnostics(
+            "y = a < b < c;\nz = ;",
+            &ParserOptions::default(),
+            &mut denied,
+        );
+        assert!(result.is_err());
+        let warning = denied.iter().next().unwrap();
+        assert_eq!(Severity::Error, warning.severity);
+        assert_eq!((Some(1), Some(11)), (warning.line, warning.column));
+        assert!(denied.check().is_err());
+
+        // parse_with は警告を返さずに同じ木を作る
+        assert_eq!(
+            parse_str("x = (a < b) < c;").unwrap(),
+            parse_str("x = a < b < c;").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_assignment_in_expression() {
+        let message = "assignment cannot be used as an expression; use '==' to compare";
+        for (src, position) in &[
+            ("if (x = 1) print(x);", (1, 7)),
+            ("while (x = 0) {}", (1, 10)),
+            ("x = y = 1;", (1, 7)),
+            ("print(x += 1);", (1, 9)),
+            ("x = 1;\nputc(a + b = c);", (2, 12)),
+        ] {
+            let e = parse_str(src).err().unwrap();
+            assert!(matches!(e.kind(), ErrorKind::SyntaxError), "{}", src);
+            assert_eq!(message, e.message(), "{}", src);
+            assert_eq!(Some(*position), e.position(), "{}", src);
+        }
+        assert!(parse_str("if (x == 1) print(x);").is_ok());
+    }
+
+    #[test]
+    fn test_parse_interactive() {
+        let complete = |src: &str| match parse_interactive(src) {
+            ParseOutcome::Complete(ast) => ast,
+            outcome => panic!("{:?}: {:?}", src, outcome),
+        };
+        let incomplete = |src: &str| matches!(parse_interactive(src), ParseOutcome::Incomplete);
+        let error = |src: &str| match parse_interactive(src) {
+            ParseOutcome::Error(e) => e,
+            outcome => panic!("{:?}: {:?}", src, outcome),
+        };
+
+        // 1 行に複数の文
+        assert_ast!(
+            complete("x = 1; print(x);"),
+            (Sequence
+                (Sequence _ (Assign (Ident "x") (Int 1)))
+                (Sequence _ (Prti (Ident "x"))))
         );
+        assert_eq!(parse_str("").unwrap(), complete(""));
+        assert_eq!(parse_str("").unwrap(), complete("  /* c */ "));
+
+        // 続きの入力が必要なもの
+        assert!(incomplete("while (x < 3) {"));
+        assert!(incomplete("if (x) { print(x); } else"));
+        assert!(incomplete("x = 1"));
+        assert!(incomplete("x = (1 +"));
+        assert!(incomplete("x = 1; /* comment"));
+        // 行を足していけば完全になる
+        let mut src = String::new();
+        for line in &["while (x < 3) {", "  print(x);", "  x = x + 1;"] {
+            src += line;
+            assert!(incomplete(&src), "{}", src);
+            src += "\n";
+        }
+        src += "}";
+        assert_eq!(1, complete(&src).sequence_items().len());
+
+        // 続きがあっても解析できないもの
+        assert!(matches!(error("x = ;").kind(), ErrorKind::SyntaxError));
+        assert!(matches!(error("}").kind(), ErrorKind::SyntaxError));
+        assert_eq!(Some((2, 5)), error("{\nx = ;").position());
+        // 文字列は行をまたげない
+        assert!(matches!(
+            error("print(\"abc").kind(),
+            ErrorKind::LexicalAnalyzerError
+        ));
+        assert!(matches!(
+            error("x = @").kind(),
+            ErrorKind::LexicalAnalyzerError
+        ));
+    }
+
+    #[test]
+    fn test_parse_str_random_input() {
+        // 線形合同法による擬似乱数でソース片を作り、panic しないことを確かめる
+        const PIECES: &[&str] = &[
+            "a", "1", "'x'", "\"s\"", "(", ")", "{", "}", ";", ",", "=", "==", "+", "-", "*", "/",
+            "%", "<", "!", "&&", "||", "if", "else", "while", "print", "putc", "/*", "*/", " ",
+            "\n",
+        ];
+        let mut seed: u32 = 12345;
+        for _ in 0..2000 {
+            let mut src = String::new();
+            for _ in 0..32 {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                src += PIECES[(seed >> 16) as usize % PIECES.len()];
+            }
+            let _ = parse_str(&src);
+        }
     }
 }
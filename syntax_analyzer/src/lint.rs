@@ -0,0 +1,454 @@
+//! 実行はできるが、書き間違いの疑いがある箇所を探して警告する。
+//! 警告の位置は文の行で、列は持たない。
+
+use crate::ast_node::*;
+use lexical_analyzer::diagnostic::{Diagnostics, Lint};
+use lexical_analyzer::error::ErrorKind;
+use lexical_analyzer::symbol::Symbol;
+
+use std::collections::HashSet;
+
+/// 警告の段階の名前。型の検査と同じ段階とする。
+fn stage() -> &'static str {
+    ErrorKind::TypeError.stage()
+}
+
+/// ast の警告を diagnostics に記録する。
+pub fn check(ast: &ASTNode, diagnostics: &mut Diagnostics) {
+    let mut variables = Variables::default();
+    variables.visit(ast);
+    variables.report(diagnostics);
+
+    Initialization {
+        diagnostics,
+        report: true,
+        reported: HashSet::new(),
+    }
+    .statement(ast, Some(Assigned::default()));
+
+    Statements { diagnostics }.statement(ast);
+}
+
+/// 代入する変数と読む変数を集める
+#[derive(Default)]
+struct Variables {
+    /// 代入する変数と、最初に代入する文の行。代入の順に並べる。
+    assigned: Vec<(Symbol, Option<usize>)>,
+    assigned_set: HashSet<Symbol>,
+    read: HashSet<Symbol>,
+    /// "x = x + 1" のように、自分への代入の中でだけ読んだ変数
+    read_by_itself: HashSet<Symbol>,
+    /// 右辺をたどっている代入の左辺
+    target: Option<Symbol>,
+}
+
+impl Variables {
+    fn report(&self, diagnostics: &mut Diagnostics) {
+        for (identifier, line) in &self.assigned {
+            if self.read.contains(identifier) {
+                continue;
+            }
+            if self.read_by_itself.contains(identifier) {
+                diagnostics.warn(
+                    Lint::WriteOnlyVariable,
+                    stage(),
+                    *line,
+                    None,
+                    format!(
+                        "variable {} is only read to compute its own new value",
+                        identifier
+                    ),
+                );
+            } else {
+                diagnostics.warn(
+                    Lint::UnusedVariable,
+                    stage(),
+                    *line,
+                    None,
+                    format!("variable {} is assigned but never used", identifier),
+                );
+            }
+        }
+    }
+}
+
+impl Visitor<'_> for Variables {
+    fn visit(&mut self, ast: &ASTNode) {
+        match ast.kind() {
+            NodeKind::Assign => {
+                if let NodeKind::Identifier(identifier) = *ast.lhs().unwrap().kind() {
+                    if self.assigned_set.insert(identifier) {
+                        self.assigned.push((identifier, ast.line()));
+                    }
+                    self.target = Some(identifier);
+                }
+                self.visit(ast.rhs().unwrap());
+                self.target = None;
+            }
+            NodeKind::Identifier(identifier) if self.target == Some(*identifier) => {
+                self.read_by_itself.insert(*identifier);
+            }
+            NodeKind::Identifier(identifier) => {
+                self.read.insert(*identifier);
+            }
+            _ => walk(self, ast),
+        }
+    }
+}
+
+/// ある時点で代入済みの変数
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Assigned {
+    /// どの経路でも代入済みの変数
+    must: HashSet<Symbol>,
+    /// いずれかの経路で代入済みの変数
+    may: HashSet<Symbol>,
+}
+
+impl Assigned {
+    fn insert(&mut self, identifier: Symbol) {
+        self.must.insert(identifier);
+        self.may.insert(identifier);
+    }
+}
+
+/// 2 つの経路の合流。実行が届かない経路 (None) は無視する。
+fn merge(a: Option<Assigned>, b: Option<Assigned>) -> Option<Assigned> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Assigned {
+            must: a.must.intersection(&b.must).copied().collect(),
+            may: a.may.union(&b.may).copied().collect(),
+        }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// 文を実行の順にたどり、代入より前に読む変数を探す。
+/// ループは、入口で代入済みの変数が変わらなくなるまで警告せずにたどってから、警告しながらたどる。
+struct Initialization<'d> {
+    diagnostics: &'d mut Diagnostics,
+    /// false の間は警告しない
+    report: bool,
+    /// 警告は変数ごとに最初に読む箇所にだけ出す
+    reported: HashSet<Symbol>,
+}
+
+impl Initialization<'_> {
+    /// state で ast を実行した後に代入済みの変数。次の文へ進まないなら None。
+    fn statement(&mut self, ast: &ASTNode, state: Option<Assigned>) -> Option<Assigned> {
+        let mut state = state?;
+        let line = ast.line();
+        match ast.kind() {
+            NodeKind::Sequence => ast
+                .sequence_items()
+                .into_iter()
+                .try_fold(state, |state, item| self.statement(item, Some(state))),
+            NodeKind::Assign => {
+                self.expression(ast.rhs().unwrap(), line, &state);
+                if let NodeKind::Identifier(identifier) = *ast.lhs().unwrap().kind() {
+                    state.insert(identifier);
+                }
+                Some(state)
+            }
+            NodeKind::If => {
+                let condition = ast.lhs().unwrap();
+                self.expression(condition, line, &state);
+                let branches = ast.rhs().unwrap();
+                let then = branches.lhs().unwrap();
+                let otherwise = |this: &mut Self, state| match branches.rhs() {
+                    Some(e) => this.statement(e, Some(state)),
+                    None => Some(state),
+                };
+                match condition.constant_value() {
+                    Some(0) => otherwise(self, state),
+                    Some(_) => self.statement(then, Some(state)),
+                    None => {
+                        let then = self.statement(then, Some(state.clone()));
+                        merge(then, otherwise(self, state))
+                    }
+                }
+            }
+            NodeKind::While => {
+                let condition = ast.lhs().unwrap();
+                let body = ast.rhs().unwrap();
+                if condition.constant_value() == Some(0) {
+                    self.expression(condition, line, &state);
+                    return Some(state);
+                }
+                let entry = self.loop_entry(&state, |this, entry| {
+                    this.statement(body, Some(entry.clone()))
+                });
+                self.expression(condition, line, &entry);
+                self.statement(body, Some(entry.clone()));
+                match condition.constant_value() {
+                    Some(_) => None,
+                    None => Some(entry),
+                }
+            }
+            NodeKind::DoWhile => {
+                let condition = ast.lhs().unwrap();
+                let body = ast.rhs().unwrap();
+                let entry = self.loop_entry(&state, |this, entry| {
+                    this.statement(body, Some(entry.clone()))
+                });
+                let exit = self.statement(body, Some(entry))?;
+                self.expression(condition, line, &exit);
+                match condition.constant_value() {
+                    Some(c) if c != 0 => None,
+                    _ => Some(exit),
+                }
+            }
+            NodeKind::Exit => {
+                self.expression(ast.lhs().unwrap(), line, &state);
+                None
+            }
+            NodeKind::Assert => {
+                let condition = ast.lhs().unwrap();
+                self.expression(condition, line, &state);
+                match condition.constant_value() {
+                    Some(0) => None,
+                    _ => Some(state),
+                }
+            }
+            _ => {
+                for child in ast.children() {
+                    self.expression(child, line, &state);
+                }
+                Some(state)
+            }
+        }
+    }
+
+    /// state から入るループの、入口で代入済みの変数。
+    /// body は入口の状態から本体を 1 回実行した後の状態を返す。
+    fn loop_entry<F>(&mut self, state: &Assigned, mut body: F) -> Assigned
+    where
+        F: FnMut(&mut Self, &Assigned) -> Option<Assigned>,
+    {
+        let report = std::mem::replace(&mut self.report, false);
+        let mut entry = state.clone();
+        loop {
+            let next = merge(Some(state.clone()), body(self, &entry)).unwrap();
+            if next == entry {
+                break;
+            }
+            entry = next;
+        }
+        self.report = report;
+        entry
+    }
+
+    /// 式の中で、 state で代入済みでない変数を読んでいれば警告する
+    fn expression(&mut self, ast: &ASTNode, line: Option<usize>, state: &Assigned) {
+        if let NodeKind::Identifier(identifier) = *ast.kind() {
+            if self.report && !state.must.contains(&identifier) && self.reported.insert(identifier)
+            {
+                let message = if state.may.contains(&identifier) {
+                    format!("variable {} may be read before it is assigned", identifier)
+                } else {
+                    format!("variable {} is read before any assignment", identifier)
+                };
+                self.diagnostics
+                    .warn(Lint::UninitializedVariable, stage(), line, None, message);
+            }
+            return;
+        }
+        for child in ast.children() {
+            self.expression(child, line, state);
+        }
+    }
+}
+
+/// 文をたどり、値が常に同じ条件と、実行されない文を探す
+struct Statements<'d> {
+    diagnostics: &'d mut Diagnostics,
+}
+
+impl Statements<'_> {
+    /// ast を実行した後に次の文へ進むことがあるなら true
+    fn statement(&mut self, ast: &ASTNode) -> bool {
+        match ast.kind() {
+            NodeKind::Sequence => {
+                let mut completes = true;
+                let mut warned = false;
+                for item in ast.sequence_items() {
+                    // 続く文も実行されないが、警告は最初の文にだけ出す
+                    if !completes && !warned {
+                        self.diagnostics.warn(
+                            Lint::UnreachableStatement,
+                            stage(),
+                            first_line(item),
+                            None,
+                            "unreachable statement",
+                        );
+                        warned = true;
+                    }
+                    completes = self.statement(item) && completes;
+                }
+                completes
+            }
+            NodeKind::If => {
+                let condition = self.condition(ast, false);
+                let branches = ast.rhs().unwrap();
+                let then = self.statement(branches.lhs().unwrap());
+                let otherwise = branches.rhs().is_none_or(|e| self.statement(e));
+                match condition {
+                    Some(0) => otherwise,
+                    Some(_) => then,
+                    None => then || otherwise,
+                }
+            }
+            NodeKind::While => {
+                // while (1) は終わらないループとしてよく書くので、常に偽の場合だけ警告する
+                let condition = self.condition(ast, true);
+                self.statement(ast.rhs().unwrap());
+                !matches!(condition, Some(c) if c != 0)
+            }
+            NodeKind::DoWhile => {
+                // do ... while (0) は 1 回だけ実行するためによく書くので警告しない
+                let condition = ast.lhs().unwrap().constant_value();
+                self.statement(ast.rhs().unwrap()) && !matches!(condition, Some(c) if c != 0)
+            }
+            NodeKind::Exit => false,
+            NodeKind::Assert => ast.lhs().unwrap().constant_value() != Some(0),
+            _ => true,
+        }
+    }
+
+    /// ast の条件が定数ならその値を返し、警告する。
+    /// only_false なら、常に偽の場合だけ警告する。
+    fn condition(&mut self, ast: &ASTNode, only_false: bool) -> Option<i64> {
+        let value = ast.lhs().unwrap().constant_value()?;
+        if value == 0 || !only_false {
+            let truth = if value == 0 { "false" } else { "true" };
+            self.diagnostics.warn(
+                Lint::ConstantCondition,
+                stage(),
+                ast.line(),
+                None,
+                format!("condition is always {}", truth),
+            );
+        }
+        Some(value)
+    }
+}
+
+/// 文が始まる行。ブロックの行が分からなければ中の最初の文の行。
+fn first_line(ast: &ASTNode) -> Option<usize> {
+    ast.line().or_else(|| {
+        ast.sequence_items()
+            .into_iter()
+            .find_map(|item| item.line())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_str;
+
+    fn warnings(src: &str) -> Vec<String> {
+        let ast = parse_str(src).unwrap();
+        let mut diagnostics = Diagnostics::default();
+        check(&ast, &mut diagnostics);
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_unused_variable() {
+        assert_eq!(
+            vec!["2: warning: variable b is assigned but never used [unused-variable]"],
+            warnings("a = 1;\nb = a;\nb = 2;\nprint(a);")
+        );
+        assert!(warnings("a = 1; while (a < 3) a = a + 1;").is_empty());
+    }
+
+    #[test]
+    fn test_write_only_variable() {
+        // 複合代入や自分を使う代入で読むだけでは、値を使ったことにならない
+        assert_eq!(
+            vec![
+                "1: warning: variable n is only read to compute its own new value [write-only-variable]"
+            ],
+            warnings("n = 0;\ni = 0;\nwhile (i < 3) {\n    n += 1;\n    i = i + 1;\n}")
+        );
+        assert!(warnings("n = 0;\nn = n * 2;\nprint(n);").is_empty());
+        // 別の変数への代入で読めば使ったことになる
+        assert!(warnings("a = 1;\nb = a;\na = a + b;\nprint(b);").is_empty());
+    }
+
+    #[test]
+    fn test_uninitialized_variable() {
+        assert_eq!(
+            vec!["2: warning: variable x is read before any assignment [uninitialized-variable]"],
+            warnings("y = 1;\nprint(x + y);\nx = 2;\nprint(x);")
+        );
+        // 片方の枝だけで代入した変数
+        assert_eq!(
+            vec![
+                "5: warning: variable x may be read before it is assigned [uninitialized-variable]"
+            ],
+            warnings("y = 1;\nif (y > 0) {\n    x = 1;\n}\nprint(x);")
+        );
+        assert!(warnings("y = 1;\nif (y > 0) x = 1; else x = 2;\nprint(x);").is_empty());
+        // 前の周回で代入した変数を読むループ
+        assert_eq!(
+            vec!["4: warning: variable prev may be read before it is assigned [uninitialized-variable]"],
+            warnings(
+                "i = 0;\nwhile (i < 3) {\n    if (i > 0)\n        print(prev);\n    prev = i;\n    i = i + 1;\n}"
+            )
+        );
+        // do-while の本体は必ず 1 回実行する
+        assert!(
+            warnings("i = 0;\ndo {\n    x = i;\n    i = i + 1;\n} while (i < 3);\nprint(x);")
+                .is_empty()
+        );
+        // exit した経路の変数は合流に含めない
+        assert!(warnings("y = 1;\nif (y) x = 1; else exit(1);\nprint(x);").is_empty());
+        // 警告は変数ごとに 1 回
+        assert_eq!(1, warnings("print(x);\nprint(x);").len());
+    }
+
+    #[test]
+    fn test_constant_condition() {
+        assert_eq!(
+            vec![
+                "1: warning: condition is always true [constant-condition]",
+                "2: warning: condition is always false [constant-condition]",
+                "3: warning: condition is always false [constant-condition]",
+            ],
+            warnings("if (1) print(1);\nif (2 < 1) print(2);\nwhile (0) print(3);")
+        );
+        // 終わらないループと 1 回だけのループは警告しない
+        assert!(warnings("while (1) { print(1); exit(0); }").is_empty());
+        assert!(warnings("do print(1); while (0);").is_empty());
+        assert!(warnings("x = 1; if (x) print(x);").is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_statement() {
+        assert_eq!(
+            vec!["3: warning: unreachable statement [unreachable-statement]"],
+            warnings("print(1);\nexit(0);\nprint(2);\nprint(3);")
+        );
+        assert_eq!(
+            vec![
+                "1: warning: condition is always true [constant-condition]",
+                "4: warning: unreachable statement [unreachable-statement]",
+            ],
+            warnings("if (1) exit(1);\nelse\n    print(1);\nprint(2);")
+        );
+        assert_eq!(
+            vec!["5: warning: unreachable statement [unreachable-statement]"],
+            warnings("x = 0;\nwhile (1) {\n    x = x + 1;\n}\n{\n    print(x);\n}")
+        );
+        assert_eq!(
+            vec!["2: warning: unreachable statement [unreachable-statement]"],
+            warnings("x = 1; if (x) exit(1); else exit(2);\nprint(1);")
+        );
+        // 片方の枝だけが終わる if の後は実行される
+        assert!(warnings("x = 1; if (x) exit(1);\nprint(1);").is_empty());
+        assert_eq!(1, warnings("assert(0, \"never\");\nprint(1);").len());
+    }
+}
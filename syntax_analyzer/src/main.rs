@@ -1,35 +1,64 @@
+use lexical_analyzer::cli::{Command, Flag, Format, Options};
+use lexical_analyzer::diagnostic::Diagnostics;
+use lexical_analyzer::error::*;
 use lexical_analyzer::token::*;
-use syntax_analyzer::SyntaxAnalyzer;
+use syntax_analyzer::{lint, ParserOptions, SyntaxAnalyzer};
 
-use std::env;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::io::Write;
 
-fn main() {
-    let mut reader: Box<dyn BufRead> = match env::args().nth(1) {
-        None => Box::new(BufReader::new(stdin())),
-        Some(filename) => Box::new(BufReader::new(
-            File::open(filename).expect("cannot open file"),
-        )),
-    };
+const COMMAND: Command = Command {
+    name: "syntax_analyzer",
+    description: "Reads a token list and writes the flattened AST.",
+    flags: &[
+        Flag::Format,
+        Flag::Dialect,
+        Flag::Width,
+        Flag::DenyWarnings,
+        Flag::Compat,
+        Flag::LegacyTokens,
+    ],
+};
 
-    let mut writer: Box<dyn Write> = match env::args().nth(2) {
-        None => Box::new(BufWriter::new(stdout())),
-        Some(filename) => Box::new(BufWriter::new(
-            File::create(filename).expect("cannot create file"),
-        )),
-    };
+fn run(options: &Options) -> Result<()> {
+    let str_in = options.read_input()?;
+    let mut writer = options.create_output()?;
 
-    let mut str_in = String::new();
-    reader.read_to_string(&mut str_in).expect("read error");
-
-    let tokens: Vec<Token> = str_in
+    // 他の実装の出力には空行が混ざることがある
+    let format = options.token_format();
+    let tokens = str_in
         .lines()
-        .map(|line| Token::from_line(line).unwrap())
-        .collect();
+        .filter(|line| format == TokenFormat::Plain || !line.trim().is_empty())
+        .map(|line| Token::from_line_with(line, format))
+        .collect::<Result<Vec<Token>>>()?;
+
+    // --trivia で書き出したトークン列なら、コメントで警告を抑制できる
+    let mut diagnostics = Diagnostics::new(options.deny_warnings);
+    diagnostics.allow_comments(&tokens);
+    let tokens = tokens
+        .into_iter()
+        .filter(|token| !token.kind().is_trivia())
+        .collect::<Vec<_>>();
 
-    let ast = SyntaxAnalyzer::parse(tokens.into_iter()).expect("parser failed");
-    writer
-        .write_fmt(format_args!("{}", ast))
-        .expect("write failed");
+    let parser_options = ParserOptions {
+        dialect: options.dialect.clone(),
+        ..Default::default()
+    };
+    let result =
+        SyntaxAnalyzer::parse_ref_with_diagnostics(&tokens, &parser_options, &mut diagnostics);
+    if let Ok(ast) = &result {
+        lint::check(ast, &mut diagnostics);
+    }
+    let reported = options.report(COMMAND.name, &diagnostics);
+    let ast = result?;
+    reported?;
+    match options.format {
+        Format::Text => write!(writer, "{}", ast),
+        Format::Debug => writeln!(writer, "{:#?}", ast),
+    }
+    .and_then(|_| writer.flush())
+    .map_err(|e| CompileError::new(ErrorKind::IOError, e))
+}
+
+fn main() {
+    COMMAND.main(run);
 }
@@ -0,0 +1,233 @@
+//! 二項演算子の優先順位と結合の向きの表。
+//! 構文解析器は ParserOptions の表を引くので、表を書き換えれば演算子の優先順位や結合の向きを試せる。
+
+use super::ast_node::NodeKind;
+use lexical_analyzer::token::TokenKind;
+
+/// 単項演算子 (+, -, !) の優先順位。標準の表の ** 以外の二項演算子よりも強く結合する。
+/// これより優先順位の高い二項演算子は、単項演算子の被演算子の中に取り込まれる。
+/// -2 ** 2 は -(2 ** 2) になる。
+pub const UNARY_PRECEDENCE: i32 = 70;
+
+/// 二項演算子の定義
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operator {
+    /// 演算子から作るノードの種類
+    pub kind: NodeKind,
+    /// 同じ優先順位の演算子が並んだとき、右から結合するなら true
+    pub right_associative: bool,
+    /// 大きいほど強く結合する。 0 以上でなければならない。
+    pub precedence: i32,
+}
+
+impl Operator {
+    /// 左結合の演算子
+    pub const fn left(kind: NodeKind, precedence: i32) -> Self {
+        Operator {
+            kind,
+            right_associative: false,
+            precedence,
+        }
+    }
+
+    /// 右結合の演算子
+    pub const fn right(kind: NodeKind, precedence: i32) -> Self {
+        Operator {
+            kind,
+            right_associative: true,
+            precedence,
+        }
+    }
+}
+
+/// 演算子でないトークン。 sentinel として使うため、 precedence -1
+const NOT_OPERATOR: Operator = Operator::left(NodeKind::None, -1);
+
+/// 標準の表。優先順位の低い順
+const STANDARD: &[(TokenKind, Operator)] = &[
+    // or
+    (TokenKind::OpOr, Operator::left(NodeKind::Or, 10)),
+    // and
+    (TokenKind::OpAnd, Operator::left(NodeKind::And, 20)),
+    // bitwise
+    (TokenKind::OpBitOr, Operator::left(NodeKind::BitOr, 22)),
+    (TokenKind::OpBitXor, Operator::left(NodeKind::BitXor, 24)),
+    (TokenKind::OpBitAnd, Operator::left(NodeKind::BitAnd, 26)),
+    // equality
+    (TokenKind::OpEqual, Operator::left(NodeKind::Equal, 30)),
+    (
+        TokenKind::OpNotEqual,
+        Operator::left(NodeKind::NotEqual, 30),
+    ),
+    // relational
+    (TokenKind::OpLess, Operator::left(NodeKind::Less, 40)),
+    (
+        TokenKind::OpLessEqual,
+        Operator::left(NodeKind::LessEqual, 40),
+    ),
+    (TokenKind::OpGreater, Operator::left(NodeKind::Greater, 40)),
+    (
+        TokenKind::OpGreaterEqual,
+        Operator::left(NodeKind::GreaterEqual, 40),
+    ),
+    // shift
+    (
+        TokenKind::OpShiftLeft,
+        Operator::left(NodeKind::ShiftLeft, 45),
+    ),
+    (
+        TokenKind::OpShiftRight,
+        Operator::left(NodeKind::ShiftRight, 45),
+    ),
+    // addition
+    (TokenKind::OpAdd, Operator::left(NodeKind::Add, 50)),
+    (
+        TokenKind::OpSubtract,
+        Operator::left(NodeKind::Subtract, 50),
+    ),
+    // multiplication
+    (
+        TokenKind::OpMultiply,
+        Operator::left(NodeKind::Multiply, 60),
+    ),
+    (TokenKind::OpDivide, Operator::left(NodeKind::Divide, 60)),
+    (TokenKind::OpMod, Operator::left(NodeKind::Mod, 60)),
+    // power
+    (TokenKind::OpPower, Operator::right(NodeKind::Power, 75)),
+];
+
+/// 標準の表での op の定義。演算子でなければ precedence が -1 の sentinel。
+/// 逆解析のように、解析に使った表が分からないところで使う。
+pub(crate) fn operator(op: &TokenKind) -> &'static Operator {
+    STANDARD
+        .iter()
+        .find(|(token, _)| token == op)
+        .map_or(&NOT_OPERATOR, |(_, operator)| operator)
+}
+
+/// トークンから二項演算子の定義を引く表
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorTable {
+    operators: Vec<(TokenKind, Operator)>,
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        OperatorTable::standard()
+    }
+}
+
+impl OperatorTable {
+    /// 言語の標準の演算子の表
+    pub fn standard() -> Self {
+        OperatorTable {
+            operators: STANDARD.to_vec(),
+        }
+    }
+
+    /// token の演算子の定義。二項演算子でなければ None。
+    pub fn get(&self, token: &TokenKind) -> Option<&Operator> {
+        self.operators
+            .iter()
+            .find(|(kind, _)| kind == token)
+            .map(|(_, operator)| operator)
+    }
+
+    /// token の定義を operator にする。表になければ加える。
+    pub fn set(&mut self, token: TokenKind, operator: Operator) {
+        match self.operators.iter_mut().find(|(kind, _)| *kind == token) {
+            Some((_, entry)) => *entry = operator,
+            None => self.operators.push((token, operator)),
+        }
+    }
+
+    /// token を二項演算子でなくし、それまでの定義を返す
+    pub fn remove(&mut self, token: &TokenKind) -> Option<Operator> {
+        let index = self.operators.iter().position(|(kind, _)| kind == token)?;
+        Some(self.operators.remove(index).1)
+    }
+
+    /// 表の演算子を、加えた順に並べる
+    pub fn iter(&self) -> impl Iterator<Item = (&TokenKind, &Operator)> {
+        self.operators
+            .iter()
+            .map(|(token, operator)| (token, operator))
+    }
+
+    /// 解析中に引く定義。演算子でなければ sentinel。
+    pub(crate) fn lookup(&self, token: &TokenKind) -> Operator {
+        self.get(token).cloned().unwrap_or(NOT_OPERATOR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assert_ast, parse_str_with, ParserOptions};
+
+    fn parse(src: &str, operators: &OperatorTable) -> crate::ast_node::ASTNode {
+        let options = ParserOptions {
+            operators: operators.clone(),
+            ..Default::default()
+        };
+        parse_str_with(src, &options).unwrap()
+    }
+
+    #[test]
+    fn test_standard() {
+        let table = OperatorTable::standard();
+        assert_eq!(19, table.iter().count());
+        for (token, operator) in table.iter() {
+            assert_eq!(operator, super::operator(token));
+            // ** だけが右に結合し、単項演算子より強く結合する
+            let power = *token == TokenKind::OpPower;
+            assert_eq!(power, operator.right_associative);
+            assert_eq!(power, operator.precedence > UNARY_PRECEDENCE);
+        }
+        assert_eq!(
+            Some(&Operator::left(NodeKind::Multiply, 60)),
+            table.get(&TokenKind::OpMultiply)
+        );
+        assert_eq!(None, table.get(&TokenKind::OpAssign));
+        assert_eq!(-1, super::operator(&TokenKind::Semicolon).precedence);
+    }
+
+    #[test]
+    fn test_override() {
+        let mut table = OperatorTable::standard();
+        // - を右結合にする
+        table.set(
+            TokenKind::OpSubtract,
+            Operator::right(NodeKind::Subtract, 50),
+        );
+        assert_ast!(
+            parse("x = a - b - c;", &table),
+            (Sequence _ (Assign (Ident "x") (Subtract (Ident "a") (Subtract (Ident "b") (Ident "c")))))
+        );
+        // + を * より強く結合させる
+        table.set(TokenKind::OpAdd, Operator::left(NodeKind::Add, 65));
+        assert_ast!(
+            parse("x = a * b + c;", &table),
+            (Sequence _ (Assign (Ident "x") (Multiply (Ident "a") (Add (Ident "b") (Ident "c")))))
+        );
+        // 単項演算子より強く結合させると、 -a + b は -(a + b)
+        table.set(TokenKind::OpAdd, Operator::left(NodeKind::Add, 80));
+        assert_ast!(
+            parse("x = -a + b;", &table),
+            (Sequence _ (Assign (Ident "x") (Negate (Add (Ident "a") (Ident "b")))))
+        );
+
+        // 表から除いた演算子は使えない
+        assert_eq!(
+            Some(Operator::left(NodeKind::BitXor, 24)),
+            table.remove(&TokenKind::OpBitXor)
+        );
+        assert_eq!(None, table.remove(&TokenKind::OpBitXor));
+        let options = ParserOptions {
+            operators: table,
+            ..Default::default()
+        };
+        assert!(parse_str_with("x = a ^ b;", &options).is_err());
+        assert!(parse_str_with("x = a ^ b;", &ParserOptions::default()).is_ok());
+    }
+}
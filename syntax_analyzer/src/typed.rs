@@ -0,0 +1,544 @@
+//! 型を付けた AST と、それを作る検査。
+//! 文字列は prts の引数と、文字列を入れる変数への代入にしか使えない。
+//! 算術や条件に文字列を使うプログラムは、実行する前にここでエラーにする。
+//! どこかで文字列を代入している変数は文字列を入れる変数になり、整数は代入できない。
+//! 浮動小数点数は算術と比較と print に使え、整数と混ぜると整数を浮動小数点数にして計算する。
+//! 条件や剰余、ビット演算には使えないので、 int(x) で整数にする。
+//! どこかで浮動小数点数を代入している変数は浮動小数点数の変数になり、代入する整数は変換する。
+
+use crate::ast_node::*;
+use lexical_analyzer::error::*;
+use lexical_analyzer::symbol::Symbol;
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Str,
+    /// 比較や論理演算の結果。値は 0 か 1 で、 Int と同じように使える。
+    Bool,
+    Float,
+}
+
+impl Type {
+    /// 数として使える型なら true
+    pub fn is_numeric(self) -> bool {
+        self != Type::Str
+    }
+
+    /// 整数として使える型なら true
+    pub fn is_integer(self) -> bool {
+        matches!(self, Type::Int | Type::Bool)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Str => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Float => write!(f, "float"),
+        }
+    }
+}
+
+/// 型を付けた AST のノード。文と Sequence は型を持たない。
+/// if の子は条件、 then 節、 else 節の順に並ぶ。
+#[derive(Debug)]
+pub struct TypedNode<'a> {
+    node: &'a ASTNode,
+    ty: Option<Type>,
+    children: Vec<TypedNode<'a>>,
+}
+
+impl<'a> TypedNode<'a> {
+    pub fn node(&self) -> &'a ASTNode {
+        self.node
+    }
+
+    pub fn ty(&self) -> Option<Type> {
+        self.ty
+    }
+
+    pub fn children(&self) -> &[TypedNode<'a>] {
+        &self.children
+    }
+
+    /// 型を持つノードの型を、ノードのアドレスで引ける表にする。
+    /// コード生成で、整数と浮動小数点数のどちらの命令にするかを決めるのに使う。
+    pub fn expression_types(&self) -> HashMap<*const ASTNode, Type> {
+        let mut types = HashMap::new();
+        let mut nodes = vec![self];
+        while let Some(node) = nodes.pop() {
+            if let Some(ty) = node.ty {
+                types.insert(node.node as *const ASTNode, ty);
+            }
+            nodes.extend(&node.children);
+        }
+        types
+    }
+}
+
+/// AST に型を付ける。型が合わなければ、文の行を付けたエラーを返す。
+/// 深い AST でもスタックを使い切らないように、再帰せずにたどる。
+pub fn check(ast: &ASTNode) -> Result<TypedNode<'_>> {
+    let variables = variable_types(ast);
+    // Visit で子を積み、子の検査が済んでから Build で型を付ける
+    enum Task<'a> {
+        Visit(&'a ASTNode, Option<usize>),
+        Build(&'a ASTNode, Option<usize>, usize),
+    }
+
+    let mut tasks = vec![Task::Visit(ast, None)];
+    let mut typed: Vec<TypedNode> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Visit(node, line) => {
+                let line = node.line().or(line);
+                let children = children(node);
+                tasks.push(Task::Build(node, line, children.len()));
+                for child in children.into_iter().rev() {
+                    tasks.push(Task::Visit(child, line));
+                }
+            }
+            Task::Build(node, line, count) => {
+                let children = typed.split_off(typed.len() - count);
+                let ty = node_type(node, &children, &variables).map_err(|message| {
+                    let e = CompileError::new(ErrorKind::TypeError, message);
+                    match line {
+                        Some(line) => e.with_line(line),
+                        None => e,
+                    }
+                })?;
+                typed.push(TypedNode { node, ty, children });
+            }
+        }
+    }
+    Ok(typed.pop().unwrap())
+}
+
+/// 変数の型。文字列か文字列を入れる変数を代入している変数を Str、
+/// 浮動小数点数の式を代入している変数を Float、それ以外を Int とする。
+/// 代入が後に書かれた変数から型が伝わることもあるので、変わらなくなるまで繰り返す。
+pub fn variable_types(ast: &ASTNode) -> HashMap<Symbol, Type> {
+    let mut assignments = Vec::new();
+    let mut nodes = vec![ast];
+    while let Some(node) = nodes.pop() {
+        if let (NodeKind::Assign, Some(variable), Some(value)) =
+            (node.kind(), node.lhs(), node.rhs())
+        {
+            if let NodeKind::Identifier(identifier) = variable.kind() {
+                assignments.push((*identifier, value));
+            }
+        }
+        nodes.extend(node.lhs());
+        nodes.extend(node.rhs());
+    }
+
+    let mut types = HashMap::new();
+    loop {
+        let mut changed = false;
+        for (identifier, value) in &assignments {
+            let ty = match value.kind() {
+                NodeKind::String(_) => Some(Type::Str),
+                NodeKind::Identifier(source) if types.get(source) == Some(&Type::Str) => {
+                    Some(Type::Str)
+                }
+                _ if is_float(value, &types) => Some(Type::Float),
+                _ => None,
+            };
+            if let Some(ty) = ty {
+                if types.insert(*identifier, ty).is_none() {
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    for (identifier, _) in assignments {
+        types.entry(identifier).or_insert(Type::Int);
+    }
+    types
+}
+
+/// 式の値が浮動小数点数になるなら true。 types はそれまでに分かった変数の型。
+/// 算術の被演算子のどちらかが浮動小数点数なら、結果も浮動小数点数になる。
+fn is_float(expression: &ASTNode, types: &HashMap<Symbol, Type>) -> bool {
+    let mut nodes = vec![expression];
+    while let Some(node) = nodes.pop() {
+        match node.kind() {
+            NodeKind::Float(_) | NodeKind::ToFloat => return true,
+            NodeKind::Identifier(identifier) if types.get(identifier) == Some(&Type::Float) => {
+                return true
+            }
+            NodeKind::Negate
+            | NodeKind::Multiply
+            | NodeKind::Divide
+            | NodeKind::Add
+            | NodeKind::Subtract => nodes.extend(node.children()),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// 文字列を入れる変数を出力する print を Prti から Prts に書き換える。
+/// 構文解析では変数の型が分からないので、解析し終えた AST に対して行う。
+pub fn resolve_string_prints(ast: &mut ASTNode) {
+    let variables = variable_types(ast);
+    let mut nodes = vec![ast];
+    while let Some(node) = nodes.pop() {
+        let ASTNode { kind, lhs, rhs, .. } = node;
+        if let (NodeKind::Prti, Some(NodeKind::Identifier(identifier))) =
+            (&kind, lhs.as_deref().map(ASTNode::kind))
+        {
+            if variables.get(identifier) == Some(&Type::Str) {
+                *kind = NodeKind::Prts;
+            }
+        }
+        nodes.extend(lhs.as_deref_mut());
+        nodes.extend(rhs.as_deref_mut());
+    }
+}
+
+/// 型を付ける子。 if は条件、 then 節、 else 節の順。
+fn children(ast: &ASTNode) -> Vec<&ASTNode> {
+    match ast.kind() {
+        NodeKind::Sequence => ast.sequence_items(),
+        NodeKind::If => {
+            let body = ast.rhs().unwrap();
+            let mut children = vec![ast.lhs().unwrap(), body.lhs().unwrap()];
+            children.extend(body.rhs());
+            children
+        }
+        _ => ast.lhs().into_iter().chain(ast.rhs()).collect(),
+    }
+}
+
+/// 子の型から ast の型を決める。合わなければエラーの文を返す。
+fn node_type(
+    ast: &ASTNode,
+    children: &[TypedNode],
+    variables: &HashMap<Symbol, Type>,
+) -> std::result::Result<Option<Type>, String> {
+    let check = |index: usize, usage: Usage, ok: fn(Type) -> bool| match children[index].ty {
+        Some(ty) if ok(ty) => Ok(()),
+        Some(ty) => Err(format!("{} used as {}", ty, usage)),
+        None => Err(format!("statement used as {}", usage)),
+    };
+    let number = |index: usize, usage: Usage| check(index, usage, Type::is_numeric);
+    let integer = |index: usize, usage: Usage| check(index, usage, Type::is_integer);
+    // 被演算子のどちらかが浮動小数点数なら浮動小数点数の演算になる
+    let arithmetic = || {
+        if children.iter().any(|child| child.ty == Some(Type::Float)) {
+            Some(Type::Float)
+        } else {
+            Some(Type::Int)
+        }
+    };
+    let operand = Usage::Operand(ast.kind());
+    match ast.kind() {
+        NodeKind::Integer(_) | NodeKind::Clock | NodeKind::Getc | NodeKind::Geti => {
+            Ok(Some(Type::Int))
+        }
+        NodeKind::Float(_) => Ok(Some(Type::Float)),
+        NodeKind::Identifier(identifier) => Ok(Some(
+            variables.get(identifier).copied().unwrap_or(Type::Int),
+        )),
+        NodeKind::String(_) => Ok(Some(Type::Str)),
+        NodeKind::None | NodeKind::Sequence => Ok(None),
+        NodeKind::Negate => number(0, operand).map(|_| arithmetic()),
+        NodeKind::Rand => integer(0, operand).map(|_| Some(Type::Int)),
+        NodeKind::Not => integer(0, operand).map(|_| Some(Type::Bool)),
+        NodeKind::ToInt => number(0, operand).map(|_| Some(Type::Int)),
+        NodeKind::ToFloat => number(0, operand).map(|_| Some(Type::Float)),
+        NodeKind::Multiply | NodeKind::Divide | NodeKind::Add | NodeKind::Subtract => {
+            number(0, operand)?;
+            number(1, operand).map(|_| arithmetic())
+        }
+        NodeKind::Mod
+        | NodeKind::BitAnd
+        | NodeKind::BitOr
+        | NodeKind::BitXor
+        | NodeKind::ShiftLeft
+        | NodeKind::ShiftRight
+        | NodeKind::Power => {
+            integer(0, operand)?;
+            integer(1, operand).map(|_| Some(Type::Int))
+        }
+        NodeKind::Less
+        | NodeKind::LessEqual
+        | NodeKind::Greater
+        | NodeKind::GreaterEqual
+        | NodeKind::Equal
+        | NodeKind::NotEqual => {
+            number(0, operand)?;
+            number(1, operand).map(|_| Some(Type::Bool))
+        }
+        NodeKind::And | NodeKind::Or => {
+            integer(0, operand)?;
+            integer(1, operand).map(|_| Some(Type::Bool))
+        }
+        NodeKind::Assign => match (ast.lhs().unwrap().kind(), children[1].ty) {
+            (NodeKind::Identifier(identifier), ty)
+                if variables.get(identifier) == Some(&Type::Str) =>
+            {
+                match ty {
+                    Some(Type::Str) => Ok(None),
+                    Some(ty) => Err(format!(
+                        "{} used as the value of string variable {}",
+                        ty, identifier
+                    )),
+                    None => Err(format!("statement used as the value of {}", identifier)),
+                }
+            }
+            _ => number(1, Usage::Value(ast.lhs().unwrap())).map(|_| None),
+        },
+        NodeKind::If | NodeKind::While | NodeKind::DoWhile => {
+            integer(0, Usage::Condition).map(|_| None)
+        }
+        NodeKind::Prti => number(0, operand).map(|_| None),
+        NodeKind::Prtc | NodeKind::Exit => integer(0, operand).map(|_| None),
+        NodeKind::Assert => {
+            integer(0, Usage::Condition)?;
+            match children[1].ty {
+                Some(Type::Str) => Ok(None),
+                Some(ty) => Err(format!("{} used as the message of assert", ty)),
+                None => Err("statement used as the message of assert".to_string()),
+            }
+        }
+        NodeKind::Prts => match children[0].ty {
+            Some(Type::Str) => Ok(None),
+            Some(ty) => Err(format!("{} used as {}", ty, operand)),
+            None => Err(format!("statement used as {}", operand)),
+        },
+    }
+}
+
+/// 数が必要な場所
+#[derive(Clone, Copy)]
+enum Usage<'a> {
+    Operand(&'a NodeKind),
+    Condition,
+    /// 代入する値。変数のノードを持つ。
+    Value(&'a ASTNode),
+}
+
+impl<'a> fmt::Display for Usage<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Usage::Operand(kind) => write!(f, "an operand of {:?}", kind),
+            Usage::Condition => write!(f, "a condition"),
+            Usage::Value(variable) => match variable.kind() {
+                NodeKind::Identifier(identifier) => write!(f, "the value of {}", identifier),
+                _ => write!(f, "the value of a variable"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_str;
+    use lexical_analyzer::symbol::Symbol;
+
+    fn check_flattened(s: &str) -> Result<()> {
+        let ast = ASTReader::read_ast(s.lines()).unwrap();
+        check(&ast).map(|_| ())
+    }
+
+    #[test]
+    fn test_check() {
+        let ast = parse_str(r#"x = 1 < 2; if (!x) print("x is ", x + 1, "\n");"#).unwrap();
+        let typed = check(&ast).unwrap();
+        assert_eq!(None, typed.ty());
+        let assign = &typed.children()[0];
+        assert_eq!(Some(Type::Int), assign.children()[0].ty());
+        assert_eq!(Some(Type::Bool), assign.children()[1].ty());
+
+        let if_node = &typed.children()[1];
+        assert_eq!(Some(Type::Bool), if_node.children()[0].ty());
+        let prints = &if_node.children()[1];
+        assert_eq!(
+            vec![Some(Type::Str), Some(Type::Int), Some(Type::Str)],
+            prints
+                .children()
+                .iter()
+                .map(|print| print.children()[0].ty())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_string_variables() {
+        // t の型は、後に書かれた s への代入から決まる
+        let ast = parse_str(
+            r#"i = 0;
+while (i < 2) {
+    if (i) print(t);
+    t = s;
+    s = "abc";
+    i = i + 1;
+}
+print(i);"#,
+        )
+        .unwrap();
+        let types = variable_types(&ast);
+        assert_eq!(Some(&Type::Str), types.get(&Symbol::intern("t")));
+        assert_eq!(Some(&Type::Str), types.get(&Symbol::intern("s")));
+        assert_eq!(Some(&Type::Int), types.get(&Symbol::intern("i")));
+
+        // 構文解析で print(t) を Prts にしている
+        assert!(check(&ast).is_ok());
+        let flattened = ast.to_string();
+        assert!(flattened.contains("Prts\nIdentifier t\n"), "{}", flattened);
+        assert!(flattened.contains("Prti\nIdentifier i\n"), "{}", flattened);
+
+        let mut ast = ASTReader::read_ast(
+            "Sequence\nAssign\nIdentifier s\nString \"a\"\nPrti\nIdentifier s\n;\n".lines(),
+        )
+        .unwrap();
+        assert!(check(&ast).is_err());
+        resolve_string_prints(&mut ast);
+        assert!(check(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_type_errors() {
+        // 文字列を算術に使う
+        let e = check_flattened(
+            r#"Prti
+Add
+Integer 1
+String "a"
+;
+"#,
+        )
+        .unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::TypeError));
+        assert_eq!("string used as an operand of Add", e.message());
+
+        // 文字列を条件に使う
+        let e = check_flattened(
+            r#"While
+String "loop"
+Prts
+String "x"
+;
+"#,
+        )
+        .unwrap_err();
+        assert_eq!("string used as a condition", e.message());
+
+        // 文字列を入れる変数に整数を代入する
+        let e = check_flattened(
+            r#"Sequence
+Sequence
+;
+Assign
+Identifier s
+String "abc"
+Assign
+Identifier s
+Integer 1
+"#,
+        )
+        .unwrap_err();
+        assert_eq!("int used as the value of string variable s", e.message());
+
+        let e = check(&parse_str(r#"s = "abc"; t = s; print(t * 2);"#).unwrap()).unwrap_err();
+        assert_eq!("string used as an operand of Multiply", e.message());
+        assert_eq!(Some(1), e.line());
+
+        let e = check_flattened(
+            r#"Prts
+Integer 65
+;
+"#,
+        )
+        .unwrap_err();
+        assert_eq!("int used as an operand of Prts", e.message());
+        assert_eq!(None, e.line());
+
+        // 行の分かる文では行を付ける
+        let string = |s: &str| ASTNode {
+            kind: NodeKind::String(Symbol::intern(s)),
+            lhs: None,
+            rhs: None,
+            line: None,
+        };
+        let ast = ASTNode {
+            kind: NodeKind::Prti,
+            lhs: Some(Box::new(ASTNode {
+                kind: NodeKind::Negate,
+                lhs: Some(Box::new(string("a"))),
+                rhs: None,
+                line: None,
+            })),
+            rhs: None,
+            line: Some(3),
+        };
+        let e = check(&ast).unwrap_err();
+        assert_eq!("string used as an operand of Negate", e.message());
+        assert_eq!(Some("3".to_string()), e.location());
+
+        // 深い式でもスタックを使い切らない
+        let src = format!("print({});", vec!["1"; 10_000].join(" + "));
+        assert!(check(&parse_str(&src).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_floats() {
+        let parse = |src: &str| {
+            let options = crate::ParserOptions {
+                dialect: lexical_analyzer::dialect::Dialect::float(),
+                ..crate::ParserOptions::default()
+            };
+            crate::parse_str_with(src, &options).unwrap()
+        };
+
+        // y の型は、後に書かれた x への代入から決まる。 n は int(x) なので整数のまま
+        let ast = parse("i = 0; while (i < 2) { y = x * 2; x = 1.5; n = int(x); i = i + 1; }");
+        let types = variable_types(&ast);
+        assert_eq!(Some(&Type::Float), types.get(&Symbol::intern("x")));
+        assert_eq!(Some(&Type::Float), types.get(&Symbol::intern("y")));
+        assert_eq!(Some(&Type::Int), types.get(&Symbol::intern("n")));
+        assert_eq!(Some(&Type::Int), types.get(&Symbol::intern("i")));
+
+        // 整数と混ぜた算術は浮動小数点数、比較は bool になる
+        let ast = parse("print(1 + 2.5 * 2, -1.0 < 3);");
+        let typed = check(&ast).unwrap();
+        let args: Vec<_> = typed.children()[0]
+            .children()
+            .iter()
+            .map(|print| print.children()[0].ty())
+            .collect();
+        assert_eq!(vec![Some(Type::Float), Some(Type::Bool)], args);
+        let types = typed.expression_types();
+        let sum = ast.sequence_items()[0].sequence_items()[0].lhs().unwrap();
+        assert_eq!(Some(&Type::Float), types.get(&(sum as *const ASTNode)));
+        assert_eq!(
+            Some(&Type::Int),
+            types.get(&(sum.lhs().unwrap() as *const ASTNode))
+        );
+
+        // 整数しか使えない所には使えない
+        for (src, message) in [
+            ("x = 1.5 % 2;", "float used as an operand of Mod"),
+            ("if (0.5) x = 1;", "float used as a condition"),
+            ("putc(65.0);", "float used as an operand of Prtc"),
+            ("x = rand(6.0);", "float used as an operand of Rand"),
+            ("x = 1.0 && 1;", "float used as an operand of And"),
+        ] {
+            let e = check(&parse(src)).unwrap_err();
+            assert_eq!((message, Some(1)), (e.message().as_str(), e.line()));
+        }
+        assert!(check(&parse("x = 1.0; x = 2; if (int(x) == 2) print(x);")).is_ok());
+    }
+}
@@ -0,0 +1,490 @@
+use super::ast_node::{ASTNode, NodeKind};
+use super::operators::{operator, UNARY_PRECEDENCE};
+use lexical_analyzer::token::TokenKind;
+use lexical_analyzer::trivia::TriviaToken;
+
+/// 括弧のいらない式 (識別子と整数) の優先順位
+const PRIMARY_PRECEDENCE: i32 = UNARY_PRECEDENCE + 10;
+
+/// 字下げ 1 段分
+const INDENT: &str = "    ";
+
+/// 二項演算子の綴りと、優先順位を引くためのトークン
+fn binary_operator(kind: &NodeKind) -> Option<(&'static str, TokenKind)> {
+    let op = match kind {
+        NodeKind::Or => ("||", TokenKind::OpOr),
+        NodeKind::And => ("&&", TokenKind::OpAnd),
+        NodeKind::BitOr => ("|", TokenKind::OpBitOr),
+        NodeKind::BitXor => ("^", TokenKind::OpBitXor),
+        NodeKind::BitAnd => ("&", TokenKind::OpBitAnd),
+        NodeKind::Equal => ("==", TokenKind::OpEqual),
+        NodeKind::NotEqual => ("!=", TokenKind::OpNotEqual),
+        NodeKind::Less => ("<", TokenKind::OpLess),
+        NodeKind::LessEqual => ("<=", TokenKind::OpLessEqual),
+        NodeKind::Greater => (">", TokenKind::OpGreater),
+        NodeKind::GreaterEqual => (">=", TokenKind::OpGreaterEqual),
+        NodeKind::ShiftLeft => ("<<", TokenKind::OpShiftLeft),
+        NodeKind::ShiftRight => (">>", TokenKind::OpShiftRight),
+        NodeKind::Add => ("+", TokenKind::OpAdd),
+        NodeKind::Subtract => ("-", TokenKind::OpSubtract),
+        NodeKind::Multiply => ("*", TokenKind::OpMultiply),
+        NodeKind::Divide => ("/", TokenKind::OpDivide),
+        NodeKind::Mod => ("%", TokenKind::OpMod),
+        NodeKind::Power => ("**", TokenKind::OpPower),
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// 式の優先順位。外側の演算子より低ければ括弧で囲む。
+fn precedence(node: &ASTNode) -> i32 {
+    match node.kind() {
+        NodeKind::Integer(i) if *i < 0 => UNARY_PRECEDENCE,
+        NodeKind::Float(value) if value.value().is_sign_negative() => UNARY_PRECEDENCE,
+        NodeKind::Negate | NodeKind::Not => UNARY_PRECEDENCE,
+        kind => match binary_operator(kind) {
+            Some((_, token)) => operator(&token).precedence,
+            None => PRIMARY_PRECEDENCE,
+        },
+    }
+}
+
+/// print 文の引数を並べた Sequence なら true
+fn is_print_list(node: &ASTNode) -> bool {
+    let items = node.sequence_items();
+    !items.is_empty()
+        && items
+            .iter()
+            .all(|item| matches!(item.kind(), NodeKind::Prts | NodeKind::Prti))
+}
+
+fn print_arg(node: &ASTNode) -> String {
+    expr(node.lhs().unwrap())
+}
+
+/// 文が始まるソースの行。 print 文の引数の並びは最初の引数の行にする。
+fn statement_line(node: &ASTNode) -> Option<usize> {
+    node.line().or_else(|| {
+        if is_print_list(node) {
+            node.sequence_items()[0].line()
+        } else {
+            None
+        }
+    })
+}
+
+/// 書き戻すソースに入れるコメント
+struct Comment {
+    /// コメントが始まるソースの行
+    line: usize,
+    text: String,
+    /// 同じ行の前のトークンに続くコメントなら true
+    trailing: bool,
+}
+
+/// トリビアを付けたトークン列から、コメントをソースの順に取り出す
+fn collect_comments(tokens: &[TriviaToken]) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    for token in tokens {
+        let trivia = token
+            .leading
+            .iter()
+            .map(|t| (t, false))
+            .chain(token.trailing.iter().map(|t| (t, true)));
+        for (trivia, trailing) in trivia {
+            if let TokenKind::Comment(text) = trivia.kind() {
+                comments.push(Comment {
+                    line: trivia.line_number(),
+                    text: text.clone(),
+                    trailing,
+                });
+            }
+        }
+    }
+    comments
+}
+
+/// 字下げの段数と一緒に行を溜めながら、文をソースに戻す
+struct Unparser {
+    lines: Vec<(usize, String)>,
+    depth: usize,
+    /// まだ書いていないコメント (ソースの順)
+    comments: std::vec::IntoIter<Comment>,
+    pending: Option<Comment>,
+}
+
+impl Unparser {
+    fn new(comments: Vec<Comment>) -> Self {
+        let mut comments = comments.into_iter();
+        let pending = comments.next();
+        Unparser {
+            lines: Vec::new(),
+            depth: 0,
+            comments,
+            pending,
+        }
+    }
+
+    fn line(&mut self, text: String) {
+        self.lines.push((self.depth, text));
+    }
+
+    /// line 行から始まる文の前に来るコメントを書く。 line が None なら残りをすべて書く。
+    /// 前の行に続くコメントは直前に書いた行の後ろに、それ以外は 1 行に 1 つずつ書く。
+    fn comments_before(&mut self, line: Option<usize>) {
+        while let Some(comment) = self.pending.take() {
+            let before = match line {
+                // 同じ行の前の文に続くコメントは、その文の後ろに付けるために次の文まで待つ
+                Some(line) if comment.trailing => comment.line < line,
+                Some(line) => comment.line <= line,
+                None => true,
+            };
+            if !before {
+                self.pending = Some(comment);
+                return;
+            }
+            match self.lines.last_mut() {
+                Some((_, last)) if comment.trailing => {
+                    last.push(' ');
+                    last.push_str(&comment.text);
+                }
+                _ => self.line(comment.text),
+            }
+            self.pending = self.comments.next();
+        }
+    }
+
+    fn stmt_list(&mut self, node: &ASTNode) {
+        for item in node.sequence_items() {
+            self.stmt(item);
+        }
+    }
+
+    /// 1 段字下げして文の並びを書く
+    fn indented(&mut self, f: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        f(self);
+        self.depth -= 1;
+    }
+
+    /// 文を書く
+    fn stmt(&mut self, node: &ASTNode) {
+        if let Some(line) = statement_line(node) {
+            self.comments_before(Some(line));
+        }
+        match node.kind() {
+            NodeKind::Sequence if node.lhs().is_none() && node.rhs().is_none() => {
+                self.line(";".to_string())
+            }
+            NodeKind::Sequence if is_print_list(node) => {
+                let args: Vec<String> = node.sequence_items().into_iter().map(print_arg).collect();
+                self.line(format!("print({});", args.join(", ")))
+            }
+            NodeKind::Sequence => {
+                self.line("{".to_string());
+                self.indented(|unparser| unparser.stmt_list(node));
+                self.line("}".to_string());
+            }
+            NodeKind::Prts | NodeKind::Prti => self.line(format!("print({});", print_arg(node))),
+            NodeKind::Prtc => self.line(format!("putc({});", expr(node.lhs().unwrap()))),
+            NodeKind::Exit => self.line(format!("exit({});", expr(node.lhs().unwrap()))),
+            NodeKind::Assert => match node.rhs().unwrap().kind() {
+                NodeKind::String(message) => self.line(format!(
+                    "assert({}, {});",
+                    expr(node.lhs().unwrap()),
+                    escape::quote(&message.text())
+                )),
+                kind => unreachable!("not a message: {:?}", kind),
+            },
+            NodeKind::Assign => self.line(format!(
+                "{} = {};",
+                expr(node.lhs().unwrap()),
+                expr(node.rhs().unwrap())
+            )),
+            NodeKind::While => {
+                let head = format!("while ({})", expr(node.lhs().unwrap()));
+                self.body(head, node.rhs().unwrap());
+            }
+            NodeKind::DoWhile => {
+                let tail = format!("while ({});", expr(node.lhs().unwrap()));
+                if self.body("do".to_string(), node.rhs().unwrap()) {
+                    // 閉じ括弧と同じ行に続ける
+                    let (_, last) = self.lines.last_mut().unwrap();
+                    last.push(' ');
+                    last.push_str(&tail);
+                } else {
+                    self.line(tail);
+                }
+            }
+            NodeKind::If => self.if_stmt(String::new(), node),
+            kind => unreachable!("not a statement: {:?}", kind),
+        }
+    }
+
+    /// 見出し head に続けて本体を書く。ブロックなら開き括弧を同じ行に置き、 true を返す。
+    fn body(&mut self, head: String, node: &ASTNode) -> bool {
+        if matches!(node.kind(), NodeKind::Sequence) && !is_print_list(node) {
+            if node.lhs().is_none() && node.rhs().is_none() {
+                self.line(format!("{} {{}}", head));
+                return true;
+            }
+            self.line(format!("{} {{", head));
+            self.indented(|unparser| unparser.stmt_list(node));
+            self.line("}".to_string());
+            true
+        } else {
+            self.line(head);
+            self.indented(|unparser| unparser.stmt(node));
+            false
+        }
+    }
+
+    /// prefix に続けて if 文を書く。 else if は字下げせずに続ける。
+    fn if_stmt(&mut self, prefix: String, node: &ASTNode) {
+        let head = format!("{}if ({})", prefix, expr(node.lhs().unwrap()));
+        let clauses = node.rhs().unwrap();
+        let braced = self.body(head, clauses.lhs().unwrap());
+        if let Some(else_clause) = clauses.rhs() {
+            let prefix = if braced {
+                self.lines.pop().unwrap().1 + " else"
+            } else {
+                "else".to_string()
+            };
+            if matches!(else_clause.kind(), NodeKind::If) {
+                self.if_stmt(prefix + " ", else_clause);
+            } else {
+                self.body(prefix, else_clause);
+            }
+        }
+    }
+
+    /// 溜めた行を、字下げして改行を付けた文字列にする
+    fn finish(mut self) -> String {
+        self.comments_before(None);
+        self.lines
+            .into_iter()
+            .map(|(depth, line)| format!("{}{}\n", INDENT.repeat(depth), line))
+            .collect()
+    }
+}
+
+/// 式を、必要な所だけ括弧で囲んで書く
+fn expr(node: &ASTNode) -> String {
+    match node.kind() {
+        NodeKind::Identifier(i) => i.to_string(),
+        NodeKind::Integer(i) => i.to_string(),
+        NodeKind::Float(value) => value.to_string(),
+        NodeKind::String(s) => escape::quote(&s.text()),
+        NodeKind::Rand => format!("rand({})", expr(node.lhs().unwrap())),
+        NodeKind::ToInt => format!("int({})", expr(node.lhs().unwrap())),
+        NodeKind::ToFloat => format!("float({})", expr(node.lhs().unwrap())),
+        NodeKind::Clock => "clock()".to_string(),
+        NodeKind::Getc => "getc()".to_string(),
+        NodeKind::Geti => "geti()".to_string(),
+        NodeKind::Negate | NodeKind::Not => {
+            let operand = node.lhs().unwrap();
+            let op = if matches!(node.kind(), NodeKind::Negate) {
+                "-"
+            } else {
+                "!"
+            };
+            // "- -x" は "--x" と紛らわしいので括弧で囲む
+            if precedence(operand) <= UNARY_PRECEDENCE {
+                format!("{}({})", op, expr(operand))
+            } else {
+                format!("{}{}", op, expr(operand))
+            }
+        }
+        kind => {
+            let (op, token) = binary_operator(kind)
+                .unwrap_or_else(|| unreachable!("not an expression: {:?}", kind));
+            let p = precedence(node);
+            let lhs = node.lhs().unwrap();
+            let rhs = node.rhs().unwrap();
+            // 左結合なら右辺、右結合なら左辺を、同じ優先順位でも括弧で囲む
+            let right_associative = operator(&token).right_associative;
+            let lhs = if precedence(lhs) < p || (right_associative && precedence(lhs) == p) {
+                format!("({})", expr(lhs))
+            } else {
+                expr(lhs)
+            };
+            let rhs = if precedence(rhs) < p || (!right_associative && precedence(rhs) == p) {
+                format!("({})", expr(rhs))
+            } else {
+                expr(rhs)
+            };
+            format!("{} {} {}", lhs, op, rhs)
+        }
+    }
+}
+
+/// AST をソースに戻す。字下げは空白 4 つ、開き括弧は文と同じ行に置く。
+/// 複合代入などの構文糖は展開した形で、コメントは失われる。
+pub fn unparse(ast: &ASTNode) -> String {
+    unparse_with_trivia(ast, &[])
+}
+
+/// unparse と同じだが、トリビアを付けたトークン列 tokens のコメントを書き戻す。
+/// 行の始めからのコメントは次の文の前の行に置き、行の途中のコメントは
+/// その行で最後に書く文の後ろに付ける。
+pub fn unparse_with_trivia(ast: &ASTNode, tokens: &[TriviaToken]) -> String {
+    let mut unparser = Unparser::new(collect_comments(tokens));
+    match ast.kind() {
+        NodeKind::Sequence if !is_print_list(ast) => unparser.stmt_list(ast),
+        _ => unparser.stmt(ast),
+    }
+    unparser.finish()
+}
+
+/// 式をソースに戻す。 unparse と違って改行は付けない。
+pub fn unparse_expr(ast: &ASTNode) -> String {
+    expr(ast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_node::normalize;
+    use crate::parse_str;
+    use lexical_analyzer::trivia::attach_trivia;
+
+    fn format(src: &str) -> String {
+        unparse(&parse_str(src).unwrap())
+    }
+
+    #[test]
+    fn test_unparse() {
+        let src = r#"
+count=1;/* comment */
+while(count<10){print("count is: ",count,"\n");count+=1;}
+if (a) { x = -(-x); } else if (!b) y = (a - b) - (c - d) * 2; else ;
+do { putc('a'); } while (x << 1 | y & 3);
+{ }
+"#;
+        assert_eq!(
+            r#"count = 1;
+while (count < 10) {
+    print("count is: ", count, "\n");
+    count = count + 1;
+}
+if (a) {
+    x = -(-x);
+} else if (!b)
+    y = a - b - (c - d) * 2;
+else {}
+do {
+    putc(97);
+} while (x << 1 | y & 3);
+;
+"#,
+            format(src)
+        );
+
+        let ast = parse_str("y = (a - b) * -c;").unwrap();
+        assert_eq!(
+            "(a - b) * -c",
+            unparse_expr(ast.sequence_items()[0].rhs().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sources = [
+            "x = a - (b - c) + (a || b && c);",
+            "x = -a * b; y = -(a * b); z = !(a < b) == (c >= d);",
+            "x = a / (b * c) % d; y = (a ^ b) & c | d >> (e + 1);",
+            "x = (a ** b) ** c + a ** b ** c * -a ** (-b); y = (-2) ** 2;",
+            r#"print("\\ and \n", -1);"#,
+            "if (a) if (b) x = 1; else x = 2; else { while (1) {} }",
+            "x = -rand(6) * rand(a + 1);",
+            "t = clock() - t0;",
+            "if (n < 0) exit(-n);",
+            r#"assert(x * 2 > y, "x is \"small\"\n");"#,
+            r#"s = "a b"; t = s; print(t, "\n");"#,
+        ];
+        for src in sources.iter() {
+            let ast = parse_str(src).unwrap();
+            let formatted = unparse(&ast);
+            // 書き戻したソースは同じ AST になり、もう一度整形しても変わらない
+            assert_eq!(
+                format!("{}", ast),
+                format!("{}", parse_str(&formatted).unwrap()),
+                "{}",
+                formatted
+            );
+            assert_eq!(formatted, format(&formatted));
+        }
+
+        // putc の並びは、どの方言でも読めるように putc 文のブロックにする
+        let ast = parse_str("if (x) putc(72, 105);").unwrap();
+        let formatted = unparse(&ast);
+        assert_eq!("if (x) {\n    putc(72);\n    putc(105);\n}\n", formatted);
+        assert_eq!(normalize(ast), normalize(parse_str(&formatted).unwrap()));
+    }
+
+    #[test]
+    fn test_comments() {
+        let format = |src: &str| {
+            let tokens = lexical_analyzer::lex_all_with_trivia(src, &Default::default()).unwrap();
+            unparse_with_trivia(&parse_str(src).unwrap(), &attach_trivia(tokens))
+        };
+        let src = r#"/* greatest common divisor */
+a = 1071; b = 1029; /* inputs */
+
+while (b != 0) { /* Euclid */
+  /* swap */
+  t = b;
+  b = a % b; a = t;
+}
+print(a); /* 21 */
+/* end */
+"#;
+        let formatted = format(src);
+        assert_eq!(
+            r#"/* greatest common divisor */
+a = 1071;
+b = 1029; /* inputs */
+while (b != 0) { /* Euclid */
+    /* swap */
+    t = b;
+    b = a % b;
+    a = t;
+}
+print(a); /* 21 */
+/* end */
+"#,
+            formatted
+        );
+        // 書き戻したソースは同じ AST になり、もう一度整形してもコメントごと変わらない
+        assert_eq!(parse_str(src).unwrap(), parse_str(&formatted).unwrap());
+        assert_eq!(formatted, format(&formatted));
+
+        // 行の途中のコメントは、その行で最後に書く文の後ろに移る
+        let src = "if (x) {\n  y = 1;\n} /* then */ else y = 2 /* two */ + 1;\n";
+        let formatted = format(src);
+        assert_eq!(
+            "if (x) {\n    y = 1;\n} else\n    y = 2 + 1; /* then */ /* two */\n",
+            formatted
+        );
+        assert_eq!(formatted, format(&formatted));
+    }
+
+    #[test]
+    fn test_floats() {
+        use crate::{parse_str_with, ParserOptions};
+        use lexical_analyzer::dialect::Dialect;
+
+        let options = ParserOptions {
+            dialect: Dialect::float(),
+            ..ParserOptions::default()
+        };
+        let src = "x = 1.5e3 * float(n) - -0.25;\nprint(int(x / 2.0), \"\\n\");\n";
+        let ast = parse_str_with(src, &options).unwrap();
+        let formatted = unparse(&ast);
+        assert_eq!(
+            "x = 1500.0 * float(n) - -0.25;\nprint(int(x / 2.0), \"\\n\");\n",
+            formatted
+        );
+        assert_eq!(ast, parse_str_with(&formatted, &options).unwrap());
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::io::{Result, Write};
+
+/// 明るさの順に並べた文字。暗いほうから明るいほうへ。
+const SHADES: &[char] = &[' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+/// 枠線に使う文字
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoxStyle {
+    /// + - | で描く
+    Ascii,
+    /// 罫線素片 (┌ ─ ┐ │ └ ┘) で描く
+    Light,
+}
+
+impl BoxStyle {
+    /// 左上、右上、左下、右下、横線、縦線の文字
+    fn chars(self) -> [char; 6] {
+        match self {
+            BoxStyle::Ascii => ['+', '+', '+', '+', '-', '|'],
+            BoxStyle::Light => ['┌', '┐', '└', '┘', '─', '│'],
+        }
+    }
+}
+
+/// 文字を画素とする画像。左上が (0, 0) で、最初は空白で埋まっている。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextCanvas {
+    width: usize,
+    height: usize,
+    cells: Vec<char>,
+}
+
+impl TextCanvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        TextCanvas {
+            width,
+            height,
+            cells: vec![' '; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn fill(&mut self, c: char) {
+        for cell in &mut self.cells {
+            *cell = c;
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> char {
+        self.cells[self.width * y + x]
+    }
+
+    /// 範囲外の座標は無視して (x, y) を c にする
+    pub fn plot(&mut self, x: i32, y: i32, c: char) {
+        self.put(x as i64, y as i64, c);
+    }
+
+    fn put(&mut self, x: i64, y: i64, c: char) {
+        if x >= 0 && y >= 0 && (x as u64) < self.width as u64 && (y as u64) < self.height as u64 {
+            self.cells[self.width * y as usize + x as usize] = c;
+        }
+    }
+
+    /// (x0, y0) から (x1, y1) まで線を引く。はみ出した部分は描かない。
+    /// 長いほうの軸に 1 ずつ進み、短いほうの軸の座標はその位置の値を丸めて求める
+    /// (Bresenham のアルゴリズムと同じ点になる)。
+    /// 長いほうの軸がキャンバスに入る範囲だけをたどるので、はみ出した部分が長くても時間はかからない。
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, c: char) {
+        let (x0, y0, x1, y1) = (x0 as i64, y0 as i64, x1 as i64, y1 as i64);
+        let (dx, dy) = ((x1 - x0).abs(), (y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let x_major = dx >= dy;
+        let (major, minor) = if x_major { (dx, dy) } else { (dy, dx) };
+        let (start, step, size) = if x_major {
+            (x0, sx, self.width as i64)
+        } else {
+            (y0, sy, self.height as i64)
+        };
+
+        // 長いほうの軸の座標 start + step * i が 0 以上 size 未満になる i の範囲
+        let (first, last) = if step >= 0 {
+            (-start, size - 1 - start)
+        } else {
+            (start - (size - 1), start)
+        };
+        for i in first.max(0)..=last.min(major) {
+            // major と minor は 2^32 未満なので、 i128 なら積があふれない
+            let offset = if major == 0 {
+                0
+            } else {
+                ((2 * i as i128 * minor as i128 + major as i128) / (2 * major as i128)) as i64
+            };
+            if x_major {
+                self.put(x0 + sx * i, y0 + sy * offset, c);
+            } else {
+                self.put(x0 + sx * offset, y0 + sy * i, c);
+            }
+        }
+    }
+
+    /// (x0, y0) と (x1, y1) を対角とする枠を style の文字で描く。
+    /// 幅か高さが 1 なら、その向きの線になる。
+    pub fn draw_box(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, style: BoxStyle) {
+        let (left, right) = (x0.min(x1), x0.max(x1));
+        let (top, bottom) = (y0.min(y1), y0.max(y1));
+        let [top_left, top_right, bottom_left, bottom_right, horizontal, vertical] = style.chars();
+        if top == bottom {
+            self.draw_line(left, top, right, top, horizontal);
+        } else if left == right {
+            self.draw_line(left, top, left, bottom, vertical);
+        } else {
+            self.draw_line(left, top, right, top, horizontal);
+            self.draw_line(left, bottom, right, bottom, horizontal);
+            self.draw_line(left, top, left, bottom, vertical);
+            self.draw_line(right, top, right, bottom, vertical);
+            self.plot(left, top, top_left);
+            self.plot(right, top, top_right);
+            self.plot(left, bottom, bottom_left);
+            self.plot(right, bottom, bottom_right);
+        }
+    }
+
+    /// (x, y) から右へ s を 1 文字ずつ置く。はみ出した文字は書かない。
+    pub fn text(&mut self, x: i32, y: i32, s: &str) {
+        for (i, c) in s.chars().enumerate() {
+            let x = x as i64 + i as i64;
+            if x >= self.width as i64 {
+                break;
+            }
+            self.put(x, y as i64, c);
+        }
+    }
+
+    /// 行ごとに、行末の空白を取り除いて out に書き出す
+    pub fn render(&self, out: &mut dyn Write) -> Result<()> {
+        write!(out, "{}", self)
+    }
+
+    /// 行末の空白を取り除いた各行
+    fn rows(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.height).map(move |y| {
+            let row = &self.cells[self.width * y..self.width * (y + 1)];
+            row.iter().collect::<String>().trim_end().to_string()
+        })
+    }
+}
+
+impl fmt::Display for TextCanvas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.rows() {
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+/// 0.0 (暗い) から 1.0 (明るい) の明るさを表す文字。範囲外の値は端に寄せる。
+/// 陰影をつけた絵や、頻度のヒートマップに使う。
+pub fn shade(intensity: f64) -> char {
+    let last = SHADES.len() - 1;
+    let i = if intensity.is_nan() {
+        0
+    } else {
+        (intensity.clamp(0.0, 1.0) * last as f64).round() as usize
+    };
+    SHADES[i]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plot() {
+        let mut canvas = TextCanvas::new(4, 3);
+        assert_eq!(' ', canvas.get(0, 0));
+        canvas.plot(1, 2, '*');
+        canvas.plot(-1, 0, '*');
+        canvas.plot(4, 0, '*');
+        canvas.plot(0, 3, '*');
+        assert_eq!('*', canvas.get(1, 2));
+        assert_eq!("\n\n *\n", canvas.to_string());
+
+        canvas.fill('.');
+        assert_eq!("....\n....\n....\n", canvas.to_string());
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let mut canvas = TextCanvas::new(5, 3);
+        canvas.draw_line(0, 0, 4, 2, '#');
+        assert_eq!("#\n ##\n   ##\n", canvas.to_string());
+
+        // はみ出した部分は描かない
+        let mut canvas = TextCanvas::new(3, 1);
+        canvas.draw_line(-5, 0, 10, 0, '-');
+        assert_eq!("---\n", canvas.to_string());
+        let mut canvas = TextCanvas::new(3, 1);
+        canvas.draw_line(i32::MAX, 0, i32::MIN, 0, '-');
+        assert_eq!("---\n", canvas.to_string());
+        let mut canvas = TextCanvas::new(3, 3);
+        canvas.draw_line(
+            -1_000_000_000,
+            -1_000_000_000,
+            2_000_000_000,
+            2_000_000_000,
+            '\\',
+        );
+        canvas.draw_line(i32::MIN, i32::MAX, i32::MAX, i32::MAX, '*');
+        assert_eq!("\\\n \\\n  \\\n", canvas.to_string());
+
+        // 縦長の線と 1 点
+        let mut canvas = TextCanvas::new(3, 5);
+        canvas.draw_line(2, 4, 0, 0, '|');
+        canvas.draw_line(0, 4, 0, 4, '.');
+        assert_eq!("|\n|\n |\n |\n. |\n", canvas.to_string());
+    }
+
+    #[test]
+    fn test_draw_box() {
+        let mut canvas = TextCanvas::new(6, 4);
+        canvas.draw_box(0, 0, 5, 3, BoxStyle::Light);
+        canvas.text(1, 1, "VM");
+        assert_eq!("┌────┐\n│VM  │\n│    │\n└────┘\n", canvas.to_string());
+
+        let mut canvas = TextCanvas::new(6, 3);
+        canvas.draw_box(4, 2, 1, 0, BoxStyle::Ascii);
+        assert_eq!(" +--+\n |  |\n +--+\n", canvas.to_string());
+
+        // 高さか幅が 1 なら線になる
+        let mut canvas = TextCanvas::new(4, 3);
+        canvas.draw_box(0, 0, 3, 0, BoxStyle::Ascii);
+        canvas.draw_box(1, 1, 1, 2, BoxStyle::Light);
+        assert_eq!("----\n │\n │\n", canvas.to_string());
+    }
+
+    #[test]
+    fn test_text() {
+        let mut canvas = TextCanvas::new(5, 2);
+        canvas.text(3, 0, "hello");
+        canvas.text(-2, 1, "héllo");
+        canvas.text(i32::MAX, 0, "xy");
+        canvas.text(i32::MIN, 1, "xy");
+        assert_eq!("   he\nllo\n", canvas.to_string());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut canvas = TextCanvas::new(3, 2);
+        canvas.plot(0, 0, 'a');
+        canvas.plot(2, 1, 'b');
+        let mut out = Vec::new();
+        canvas.render(&mut out).unwrap();
+        assert_eq!("a\n  b\n", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        TextCanvas::new(0, 2).render(&mut out).unwrap();
+        assert_eq!(b"\n\n", &out[..]);
+    }
+
+    #[test]
+    fn test_shade() {
+        assert_eq!(' ', shade(0.0));
+        assert_eq!(' ', shade(-1.0));
+        assert_eq!(' ', shade(f64::NAN));
+        assert_eq!('=', shade(0.45));
+        assert_eq!('@', shade(1.0));
+        assert_eq!('@', shade(2.0));
+    }
+}
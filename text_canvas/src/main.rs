@@ -0,0 +1,35 @@
+use std::io::{stdout, BufWriter};
+use text_canvas::{shade, TextCanvas};
+
+/// 光源の向き (正規化する前)
+const LIGHT: (f64, f64, f64) = (30.0, 30.0, -50.0);
+
+/// 半径 r の球を、 LIGHT の方向から照らした陰影で描く。
+/// 文字は縦長なので、横方向には 2 倍に引き伸ばす。
+fn draw_sphere(r: f64, k: f64, ambient: f64) -> TextCanvas {
+    let size = (2.0 * r).ceil() as usize + 1;
+    let mut canvas = TextCanvas::new(size * 2, size);
+    let norm = (LIGHT.0 * LIGHT.0 + LIGHT.1 * LIGHT.1 + LIGHT.2 * LIGHT.2).sqrt();
+    let light = (LIGHT.0 / norm, LIGHT.1 / norm, LIGHT.2 / norm);
+    for row in 0..size {
+        let y = row as f64 - r;
+        for col in 0..size * 2 {
+            let x = col as f64 / 2.0 - r;
+            let zz = r * r - x * x - y * y;
+            if zz < 0.0 {
+                continue;
+            }
+            let z = zz.sqrt();
+            let dot = -(light.0 * x + light.1 * y + light.2 * z) / r;
+            let intensity = dot.max(0.0).powf(k) + ambient;
+            canvas.plot(col as i32, row as i32, shade(intensity.max(0.05)));
+        }
+    }
+    canvas
+}
+
+fn main() {
+    let out = stdout();
+    let mut out = BufWriter::new(out.lock());
+    draw_sphere(10.0, 2.0, 0.1).render(&mut out).unwrap();
+}
@@ -0,0 +1,484 @@
+use super::*;
+
+/// オペランドを読み出し済みの命令。ジャンプ先は命令列の添字。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedOp {
+    Fetch(usize),
+    Store(usize),
+    /// push と pushw
+    Push(i64),
+    /// 文字列プールの番号
+    PushS(i32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+    Neg,
+    Not,
+    Jmp(usize),
+    Jz(usize),
+    Prtc,
+    Prts,
+    Prti,
+    /// 幅を揃えて出力する prtiw
+    PrtiWidth(i32),
+    Halt,
+    Getc,
+    Geti,
+    Fetchi,
+    Storei,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Pow,
+    Rand,
+    Clock,
+    Exit,
+    Assert,
+    PushF(Float),
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FNeg,
+    FLt,
+    FGt,
+    FLe,
+    FGe,
+    FEq,
+    FNe,
+    Itof,
+    Ftoi,
+    Prtf,
+    /// 呼び出す関数の enter の添字
+    Call(usize),
+    Ret,
+    /// 引数の数
+    Enter(usize),
+    Leave,
+    /// フレームの先頭からの位置
+    FetchL(usize),
+    StoreL(usize),
+    /// ホスト関数の番号
+    ExternalCall(u32),
+    /// 関数の中で自分自身を呼び、 leave と ret が続くだけの call。
+    /// 引数をフレームの先頭に移し、 enter の次の命令の添字にジャンプする。引数の数も持つ。
+    TailCall(usize, usize),
+}
+
+/// 検査済みのバイトコードを命令列に変換する。
+/// 各命令の元のアドレスも返す (トレースとプロファイル用)。
+pub(crate) fn decode(byte_code: &[u8]) -> (Vec<DecodedOp>, Vec<usize>) {
+    let operand = |address: usize| decode_operand(&byte_code[address + 1..]).unwrap();
+
+    let mut addresses = Vec::new();
+    let mut address = 0;
+    while address < byte_code.len() {
+        addresses.push(address);
+        address += instruction_size(byte_code[address]);
+    }
+    let index_of = |address: usize| addresses.binary_search(&address).unwrap();
+
+    let ops = addresses
+        .iter()
+        .map(|&address| match byte_code[address] {
+            FETCH => DecodedOp::Fetch(operand(address) as usize),
+            STORE => DecodedOp::Store(operand(address) as usize),
+            PUSH => DecodedOp::Push(operand(address) as i64),
+            PUSHW => DecodedOp::Push(decode_wide_operand(&byte_code[address + 1..]).unwrap()),
+            PUSHS => DecodedOp::PushS(operand(address)),
+            ADD => DecodedOp::Add,
+            SUB => DecodedOp::Sub,
+            MUL => DecodedOp::Mul,
+            DIV => DecodedOp::Div,
+            MOD => DecodedOp::Mod,
+            LT => DecodedOp::Lt,
+            GT => DecodedOp::Gt,
+            LE => DecodedOp::Le,
+            GE => DecodedOp::Ge,
+            EQ => DecodedOp::Eq,
+            NE => DecodedOp::Ne,
+            AND => DecodedOp::And,
+            OR => DecodedOp::Or,
+            NEG => DecodedOp::Neg,
+            NOT => DecodedOp::Not,
+            JMP => DecodedOp::Jmp(index_of(
+                (address + 1).wrapping_add(operand(address) as usize),
+            )),
+            JZ => DecodedOp::Jz(index_of(
+                (address + 1).wrapping_add(operand(address) as usize),
+            )),
+            PRTC => DecodedOp::Prtc,
+            PRTS => DecodedOp::Prts,
+            PRTI => DecodedOp::Prti,
+            PRTIW => DecodedOp::PrtiWidth(operand(address)),
+            HALT => DecodedOp::Halt,
+            GETC => DecodedOp::Getc,
+            GETI => DecodedOp::Geti,
+            FETCHI => DecodedOp::Fetchi,
+            STOREI => DecodedOp::Storei,
+            BAND => DecodedOp::BitAnd,
+            BOR => DecodedOp::BitOr,
+            BXOR => DecodedOp::BitXor,
+            SHL => DecodedOp::Shl,
+            SHR => DecodedOp::Shr,
+            POW => DecodedOp::Pow,
+            RAND => DecodedOp::Rand,
+            CLOCK => DecodedOp::Clock,
+            EXIT => DecodedOp::Exit,
+            ASSERT => DecodedOp::Assert,
+            PUSHF => DecodedOp::PushF(Float::from_bits(operand(address))),
+            FADD => DecodedOp::FAdd,
+            FSUB => DecodedOp::FSub,
+            FMUL => DecodedOp::FMul,
+            FDIV => DecodedOp::FDiv,
+            FNEG => DecodedOp::FNeg,
+            FLT => DecodedOp::FLt,
+            FGT => DecodedOp::FGt,
+            FLE => DecodedOp::FLe,
+            FGE => DecodedOp::FGe,
+            FEQ => DecodedOp::FEq,
+            FNE => DecodedOp::FNe,
+            ITOF => DecodedOp::Itof,
+            FTOI => DecodedOp::Ftoi,
+            PRTF => DecodedOp::Prtf,
+            CALL => DecodedOp::Call(index_of(
+                (address + 1).wrapping_add(operand(address) as usize),
+            )),
+            RET => DecodedOp::Ret,
+            ENTER => DecodedOp::Enter(operand(address) as usize),
+            LEAVE => DecodedOp::Leave,
+            FETCHL => DecodedOp::FetchL(operand(address) as usize),
+            STOREL => DecodedOp::StoreL(operand(address) as usize),
+            XCALL => DecodedOp::ExternalCall(operand(address) as u32),
+            _ => unreachable!(),
+        })
+        .collect();
+    (ops, addresses)
+}
+
+/// 自分自身を末尾で呼ぶ call を、フレームを作り直さずに関数の先頭へ戻る TailCall にする。
+/// call の次が leave と ret で、その call に到達するのが呼び出す関数の enter からだけの場合に限る。
+/// トップレベルや他の関数からも到達する命令は、フレームがあるとは限らないのでそのままにする。
+pub(crate) fn fuse_tail_calls(ops: &mut [DecodedOp]) {
+    // 入口から call に入らずに到達する命令
+    let reachable = |start: usize| {
+        let mut seen = vec![false; ops.len()];
+        let mut pending = vec![start];
+        while let Some(index) = pending.pop() {
+            if index >= ops.len() || seen[index] {
+                continue;
+            }
+            seen[index] = true;
+            match ops[index] {
+                DecodedOp::Jmp(target) => pending.push(target),
+                DecodedOp::Jz(target) => pending.extend([target, index + 1]),
+                DecodedOp::Ret | DecodedOp::Halt | DecodedOp::Exit => {}
+                _ => pending.push(index + 1),
+            }
+        }
+        seen
+    };
+
+    let mut entries: Vec<usize> = ops
+        .iter()
+        .filter_map(|op| match *op {
+            DecodedOp::Call(target) => Some(target),
+            _ => None,
+        })
+        .chain(std::iter::once(0))
+        .collect();
+    entries.sort_unstable();
+    entries.dedup();
+
+    // 命令ごとに、到達する入口。複数の入口から到達するなら None
+    let mut owners: Vec<Option<Option<usize>>> = vec![None; ops.len()];
+    for &entry in &entries {
+        for (owner, seen) in owners.iter_mut().zip(reachable(entry)) {
+            if seen {
+                *owner = match owner {
+                    None => Some(Some(entry)),
+                    Some(_) => Some(None),
+                };
+            }
+        }
+    }
+
+    for index in 0..ops.len().saturating_sub(2) {
+        if let DecodedOp::Call(target) = ops[index] {
+            if let DecodedOp::Enter(arguments) = ops[target] {
+                if owners[index] == Some(Some(target))
+                    && ops[index + 1] == DecodedOp::Leave
+                    && ops[index + 2] == DecodedOp::Ret
+                {
+                    ops[index] = DecodedOp::TailCall(target + 1, arguments);
+                }
+            }
+        }
+    }
+}
+
+impl VirtualMachineInterpreter {
+    pub(crate) fn execute_decoded(
+        &mut self,
+        ops: &[DecodedOp],
+        addresses: &[usize],
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let observed = self.trace.is_some() || self.profile.is_some();
+        let mut ip = 0;
+        self.run_decoded(ops, addresses, out, observed, &mut ip)
+            .map_err(|e| {
+                // トレースかプロファイルをしていれば pc は実行中の命令を指している。
+                // そうでなければ ip はエラーを起こした命令の次を指している
+                let address = if observed { self.pc } else { addresses[ip - 1] };
+                self.locate(address, e)
+            })
+    }
+
+    /// ip の命令から実行する。 ip は次に実行する命令を指すように進める。
+    fn run_decoded(
+        &mut self,
+        ops: &[DecodedOp],
+        addresses: &[usize],
+        out: &mut dyn Write,
+        observed: bool,
+        ip: &mut usize,
+    ) -> Result<()> {
+        let width = self.width;
+        loop {
+            if observed {
+                self.pc = addresses[*ip];
+                if self.trace.is_some() {
+                    self.write_trace()?;
+                }
+                if let Some(profile) = self.profile.as_mut() {
+                    profile.record(self.pc, self.byte_code[self.pc], self.sp);
+                }
+            }
+
+            let op = ops[*ip];
+            *ip += 1;
+            self.retired += 1;
+            match op {
+                DecodedOp::Fetch(index) => {
+                    self.stack[self.sp] = self.data[index];
+                    self.tags[self.sp] = self.data_tags[index];
+                    self.sp += 1;
+                }
+                DecodedOp::Store(index) => {
+                    self.sp -= 1;
+                    self.data[index] = self.stack[self.sp];
+                    self.data_tags[index] = self.tags[self.sp];
+                }
+                DecodedOp::Push(v) => {
+                    self.stack[self.sp] = v;
+                    self.tags[self.sp] = Tag::Int;
+                    self.sp += 1;
+                }
+                DecodedOp::PushS(v) => {
+                    self.stack[self.sp] = v as i64;
+                    self.tags[self.sp] = Tag::Str;
+                    self.sp += 1;
+                }
+                DecodedOp::Jmp(target) => *ip = target,
+                DecodedOp::Jz(target) => {
+                    self.sp -= 1;
+                    if self.stack[self.sp] == 0 {
+                        *ip = target;
+                    }
+                }
+                DecodedOp::Add => self.binary_op(|a, b| width.add(a, b)),
+                DecodedOp::Sub => self.binary_op(|a, b| width.sub(a, b)),
+                DecodedOp::Mul => self.binary_op(|a, b| width.mul(a, b)),
+                DecodedOp::Div | DecodedOp::Mod | DecodedOp::Pow => {
+                    let opcode = match op {
+                        DecodedOp::Div => DIV,
+                        DecodedOp::Mod => MOD,
+                        _ => POW,
+                    };
+                    let operand0 = self.stack[self.sp - 2];
+                    let operand1 = self.stack[self.sp - 1];
+                    self.stack[self.sp - 2] = self.divide(opcode, operand0, operand1)?;
+                    self.tags[self.sp - 2] = Tag::Int;
+                    self.sp -= 1;
+                }
+                DecodedOp::Lt => self.binary_op(|a, b| (a < b) as i64),
+                DecodedOp::Gt => self.binary_op(|a, b| (a > b) as i64),
+                DecodedOp::Le => self.binary_op(|a, b| (a <= b) as i64),
+                DecodedOp::Ge => self.binary_op(|a, b| (a >= b) as i64),
+                DecodedOp::Eq => self.binary_op(|a, b| (a == b) as i64),
+                DecodedOp::Ne => self.binary_op(|a, b| (a != b) as i64),
+                DecodedOp::And => self.binary_op(|a, b| (a != 0 && b != 0) as i64),
+                DecodedOp::Or => self.binary_op(|a, b| (a != 0 || b != 0) as i64),
+                DecodedOp::BitAnd => self.binary_op(|a, b| a & b),
+                DecodedOp::BitOr => self.binary_op(|a, b| a | b),
+                DecodedOp::BitXor => self.binary_op(|a, b| a ^ b),
+                DecodedOp::Shl => self.binary_op(|a, b| width.shl(a, b)),
+                DecodedOp::Shr => self.binary_op(|a, b| width.shr(a, b)),
+                DecodedOp::Neg => self.unary_op(|a| width.neg(a)),
+                DecodedOp::Not => self.unary_op(|a| (a == 0) as i64),
+                DecodedOp::Prtc => {
+                    self.sp -= 1;
+                    let value = self.stack[self.sp];
+                    match to_char(value) {
+                        Some(c) => self.write(out, &c.to_string())?,
+                        None => {
+                            return Err(CompileError::new(
+                                ErrorKind::VirtualMachineError,
+                                format!("illegal character value: {}", value),
+                            ))
+                        }
+                    }
+                }
+                DecodedOp::Prti => {
+                    self.sp -= 1;
+                    self.write(out, &self.stack[self.sp].to_string())?;
+                }
+                DecodedOp::PrtiWidth(width) => {
+                    self.sp -= 1;
+                    self.write(out, &pad_integer(self.stack[self.sp], width))?;
+                }
+                DecodedOp::Prts => {
+                    self.sp -= 1;
+                    let index = self.stack[self.sp];
+                    match self.string_pool.get(index as usize) {
+                        Some(s) if index >= 0 => self.write(out, s)?,
+                        _ => {
+                            return Err(CompileError::new(
+                                ErrorKind::VirtualMachineError,
+                                format!("illegal string index: {}", index),
+                            ))
+                        }
+                    }
+                }
+                DecodedOp::Halt => break,
+                DecodedOp::Assert => {
+                    self.sp -= 2;
+                    self.check_assertion(self.stack[self.sp], self.stack[self.sp + 1])?;
+                }
+                DecodedOp::Exit => {
+                    self.sp -= 1;
+                    self.exit_code = Some(self.stack[self.sp] as i32);
+                    break;
+                }
+                DecodedOp::Getc => {
+                    self.stack[self.sp] = self.read_char()?;
+                    self.tags[self.sp] = Tag::Int;
+                    self.sp += 1;
+                }
+                DecodedOp::Geti => {
+                    self.stack[self.sp] = self.read_int()?;
+                    self.tags[self.sp] = Tag::Int;
+                    self.sp += 1;
+                }
+                DecodedOp::Fetchi => {
+                    let index = self.data_index(self.stack[self.sp - 1])?;
+                    self.stack[self.sp - 1] = self.data[index];
+                    self.tags[self.sp - 1] = self.data_tags[index];
+                }
+                DecodedOp::Storei => {
+                    let index = self.data_index(self.stack[self.sp - 2])?;
+                    self.data[index] = self.stack[self.sp - 1];
+                    self.data_tags[index] = self.tags[self.sp - 1];
+                    self.sp -= 2;
+                }
+                DecodedOp::Rand => {
+                    self.stack[self.sp - 1] = self.rand(self.stack[self.sp - 1])?;
+                    self.tags[self.sp - 1] = Tag::Int;
+                }
+                DecodedOp::Clock => {
+                    self.stack[self.sp] = self.clock();
+                    self.tags[self.sp] = Tag::Int;
+                    self.sp += 1;
+                }
+                DecodedOp::PushF(v) => {
+                    self.stack[self.sp] = v.to_bits() as i64;
+                    self.tags[self.sp] = Tag::Float;
+                    self.sp += 1;
+                }
+                DecodedOp::FAdd => self.float_op(|a, b| a + b),
+                DecodedOp::FSub => self.float_op(|a, b| a - b),
+                DecodedOp::FMul => self.float_op(|a, b| a * b),
+                DecodedOp::FDiv => self.float_op(|a, b| a / b),
+                DecodedOp::FLt => self.float_compare(|a, b| a < b),
+                DecodedOp::FGt => self.float_compare(|a, b| a > b),
+                DecodedOp::FLe => self.float_compare(|a, b| a <= b),
+                DecodedOp::FGe => self.float_compare(|a, b| a >= b),
+                DecodedOp::FEq => self.float_compare(|a, b| a == b),
+                DecodedOp::FNe => self.float_compare(|a, b| a != b),
+                DecodedOp::FNeg => self.convert(FNEG),
+                DecodedOp::Itof => self.convert(ITOF),
+                DecodedOp::Ftoi => self.convert(FTOI),
+                DecodedOp::Prtf => {
+                    self.sp -= 1;
+                    let value = float_value(self.stack[self.sp]);
+                    self.write(out, &format_float(value))?;
+                }
+                // コールスタックには、バイトコードで実行した場合と同じくアドレスを積む
+                DecodedOp::Call(target) => {
+                    self.push_return(addresses[*ip])?;
+                    *ip = target;
+                }
+                DecodedOp::Ret => {
+                    let address = self.pop_return()?;
+                    *ip = addresses.binary_search(&address).unwrap();
+                }
+                DecodedOp::Enter(arguments) => self.enter(arguments)?,
+                DecodedOp::Leave => self.leave()?,
+                DecodedOp::FetchL(index) => {
+                    self.stack[self.sp] = self.stack[self.fp + index];
+                    self.tags[self.sp] = self.tags[self.fp + index];
+                    self.sp += 1;
+                }
+                DecodedOp::StoreL(index) => {
+                    self.sp -= 1;
+                    self.stack[self.fp + index] = self.stack[self.sp];
+                    self.tags[self.fp + index] = self.tags[self.sp];
+                }
+                DecodedOp::ExternalCall(id) => self.external_call(id)?,
+                // 戻り先とフレームポインタは呼び出し元のものをそのまま使う
+                DecodedOp::TailCall(target, arguments) => {
+                    let base = self.sp - arguments;
+                    self.stack.copy_within(base..self.sp, self.fp);
+                    self.tags.copy_within(base..self.sp, self.fp);
+                    self.sp = self.fp + arguments;
+                    *ip = target;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn binary_op<F>(&mut self, f: F)
+    where
+        F: FnOnce(i64, i64) -> i64,
+    {
+        let operand0 = self.stack[self.sp - 2];
+        let operand1 = self.stack[self.sp - 1];
+        self.stack[self.sp - 2] = f(operand0, operand1);
+        self.tags[self.sp - 2] = Tag::Int;
+        self.sp -= 1;
+    }
+
+    #[inline(always)]
+    fn unary_op<F>(&mut self, f: F)
+    where
+        F: FnOnce(i64) -> i64,
+    {
+        self.stack[self.sp - 1] = f(self.stack[self.sp - 1]);
+        self.tags[self.sp - 1] = Tag::Int;
+    }
+}
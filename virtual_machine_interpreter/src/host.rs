@@ -0,0 +1,136 @@
+use super::*;
+
+/// ホスト関数。スタックの上の引数を受け取り、戻り値を返す。
+type Function = Box<dyn FnMut(&[i64]) -> Result<i64>>;
+
+/// 埋め込み側が登録した関数。 xcall で呼ぶ。
+pub(crate) struct HostFunction {
+    /// スタックから取り出す引数の数
+    arguments: usize,
+    function: Function,
+}
+
+impl VirtualMachineInterpreter {
+    /// 番号 id のホスト関数を登録する。 `xcall id` は arguments 個の値をスタックから取り出し、
+    /// 積んだ順に並べて function に渡し、戻り値を値の幅に折り返して積む。
+    /// function のエラーはそのまま実行のエラーになる。
+    /// 同じ番号の関数を登録し直すと置き換える。スタックの深さは次に実行するときに調べ直す。
+    pub fn register_host_fn<F>(&mut self, id: u32, arguments: usize, function: F)
+    where
+        F: FnMut(&[i64]) -> Result<i64> + 'static,
+    {
+        self.host_functions.insert(
+            id,
+            HostFunction {
+                arguments,
+                function: Box::new(function),
+            },
+        );
+        self.stack_pending = self.uses_host_functions();
+    }
+
+    /// 番号 id のホスト関数の引数の数。登録していなければ None。
+    pub(crate) fn host_arguments(&self, id: u32) -> Option<usize> {
+        self.host_functions.get(&id).map(|host| host.arguments)
+    }
+
+    /// バイトコードに xcall があれば true
+    pub(crate) fn uses_host_functions(&self) -> bool {
+        let mut address = 0;
+        while address < self.byte_code.len() {
+            if self.byte_code[address] == XCALL {
+                return true;
+            }
+            address += instruction_size(self.byte_code[address]);
+        }
+        false
+    }
+
+    /// xcall 命令。引数を取り出して番号 id のホスト関数を呼び、戻り値を積む。
+    pub(crate) fn external_call(&mut self, id: u32) -> Result<()> {
+        let host = self.host_functions.get_mut(&id).ok_or_else(|| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("host function {} is not registered", id),
+            )
+        })?;
+        let base = self.sp - host.arguments;
+        let value = (host.function)(&self.stack[base..self.sp])?;
+        self.stack[base] = self.width.wrap(value);
+        self.tags[base] = Tag::Int;
+        self.sp = base + 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_register_host_fn() {
+        let s = r#"Datasize: 0 Strings: 0
+    push 7
+    push 5
+    xcall 1
+    prti
+    push 65
+    xcall 2
+    push 3
+    xcall 1
+    prti
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            vm.register_host_fn(1, 2, |args| Ok(args[0] - args[1]));
+            let written = Rc::clone(&log);
+            vm.register_host_fn(2, 1, move |args| {
+                written.borrow_mut().push(args[0]);
+                Ok(args[0] + 1)
+            });
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!("263", String::from_utf8(out).unwrap());
+            assert_eq!(vec![65], *log.borrow());
+        }
+    }
+
+    #[test]
+    fn test_host_fn_errors() {
+        let s = r#"Datasize: 0 Strings: 0
+    push 1
+    xcall 3
+    prti
+    halt
+"#;
+        // 登録していない関数は実行の前に検出する
+        let mut vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        let mut out = Vec::new();
+        let error = vm.execute(&mut out).err().unwrap();
+        assert_eq!("5: host function 3 is not registered", error.message());
+
+        // 引数がスタックに足りない
+        vm.register_host_fn(3, 2, |args| Ok(args[0] + args[1]));
+        let error = vm.execute(&mut out).err().unwrap();
+        assert_eq!("5: stack underflow", error.message());
+
+        // ホスト関数のエラーはそのまま返す
+        vm.register_host_fn(3, 1, |_| {
+            Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                "file not found",
+            ))
+        });
+        let error = vm.execute(&mut out).err().unwrap();
+        assert_eq!("file not found", error.message());
+        assert!(out.is_empty());
+
+        let s = "Datasize: 0 Strings: 0\n    xcall -1\n    halt\n";
+        let error = VirtualMachineInterpreter::load(s.lines()).err().unwrap();
+        assert_eq!("0: host function id -1 is negative", error.message());
+    }
+}
@@ -0,0 +1,287 @@
+//! バイトコードのファイル形式。アセンブリを組み立てた結果をそのまま保存して読み戻す。
+//!
+//! 数はすべてリトルエンディアンで、ホストのバイト順によらない。
+//!
+//! ```text
+//! magic        4 バイト  "TVMB"
+//! version      1 バイト  FORMAT_VERSION
+//! width        1 バイト  整数の値の幅のビット数 (32 か 64)
+//! data size    u32
+//! strings      u32 の個数と、個数分の (u32 のバイト数, UTF-8 のバイト列)
+//! code         u32 のバイト数と、バイトコード
+//! symbols      u32 の個数と、個数分の (u32 の data の添字, u32 のバイト数, 変数名)
+//! ```
+//!
+//! 形式を変えるときは FORMAT_VERSION を上げ、古い版のファイルはエラーにする。
+
+use super::*;
+use std::convert::TryInto;
+
+/// ファイルの先頭の 4 バイト
+pub const MAGIC: &[u8; 4] = b"TVMB";
+
+/// ファイル形式の版
+pub const FORMAT_VERSION: u8 = 2;
+
+/// bytes がバイトコードのファイルなら true。アセンブリと見分けるために使う。
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn invalid(message: String) -> CompileError {
+    CompileError::new(
+        ErrorKind::VirtualMachineError,
+        format!("invalid bytecode: {}", message),
+    )
+}
+
+fn push_u32(dst: &mut Vec<u8>, value: usize) {
+    dst.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+fn push_bytes(dst: &mut Vec<u8>, bytes: &[u8]) {
+    push_u32(dst, bytes.len());
+    dst.extend_from_slice(bytes);
+}
+
+/// ファイルを先頭から読む
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| invalid(format!("truncated at offset {}", self.position)))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let position = self.position;
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| invalid(format!("string at offset {} is not UTF-8", position)))
+    }
+}
+
+impl VirtualMachineInterpreter {
+    /// 読み込んだプログラムをバイトコードのファイル形式で返す。
+    /// 実行中の状態 (data の値やスタック) は含めない。
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.width.bits() as u8);
+        push_u32(&mut bytes, self.data.len());
+        push_u32(&mut bytes, self.string_pool.len());
+        for s in &self.string_pool {
+            push_bytes(&mut bytes, s.as_bytes());
+        }
+        push_bytes(&mut bytes, &self.byte_code);
+
+        let mut symbols: Vec<(&String, &usize)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|&(name, &index)| (index, name));
+        push_u32(&mut bytes, symbols.len());
+        for (name, &index) in symbols {
+            push_u32(&mut bytes, index);
+            push_bytes(&mut bytes, name.as_bytes());
+        }
+        bytes
+    }
+
+    /// バイトコードのファイルを読み込み、実行可能な状態の VM を返す。
+    /// アセンブリから読み込んだ場合と同じ検査をする。
+    pub fn load_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::load_bytes_with(bytes, Engine::default())
+    }
+
+    pub fn load_bytes_with(bytes: &[u8], engine: Engine) -> Result<Self> {
+        Self::load_bytes_with_limits(bytes, engine, &Limits::default())
+    }
+
+    /// 資源の上限 limits を設けて読み込む。 load_with_limits と同じく扱う。
+    pub fn load_bytes_with_limits(bytes: &[u8], engine: Engine, limits: &Limits) -> Result<Self> {
+        if !is_bytecode(bytes) {
+            return Err(invalid("missing magic number".to_string()));
+        }
+        let mut reader = Reader {
+            bytes,
+            position: MAGIC.len(),
+        };
+        let version = reader.take(1)?[0];
+        if version != FORMAT_VERSION {
+            return Err(invalid(format!(
+                "unsupported format version {} (expected {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let width = reader.take(1)?[0];
+        let width = Width::from_bits(width as u32)
+            .ok_or_else(|| invalid(format!("unsupported width {}", width)))?;
+        let data_size = reader.u32()?;
+        limits.check_data_size(ErrorKind::VirtualMachineError, data_size)?;
+        let string_count = reader.u32()?;
+        let string_pool = (0..string_count)
+            .map(|_| reader.string())
+            .collect::<Result<Vec<_>>>()?;
+        limits.check_string_pool_size(
+            ErrorKind::VirtualMachineError,
+            string_pool.iter().map(String::len).sum(),
+        )?;
+        let byte_code = reader.bytes()?.to_vec();
+        let symbol_count = reader.u32()?;
+        let mut symbols = HashMap::new();
+        for _ in 0..symbol_count {
+            let index = reader.u32()?;
+            symbols.insert(reader.string()?, index);
+        }
+        if reader.position != bytes.len() {
+            return Err(invalid(format!(
+                "unexpected data at offset {}",
+                reader.position
+            )));
+        }
+
+        Self::new(byte_code, string_pool, data_size, symbols, width).prepare(None, engine, limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "Datasize: 1 Strings: 1
+\"n = \"
+    0 push  258
+    5 store [0]
+   10 push  0
+   15 prts
+   16 fetch [0]
+   21 prti
+   22 halt
+; Symbols:
+; 0 n
+";
+
+    #[test]
+    fn test_operand_encoding() {
+        // どのホストでも、オペランドは下位のバイトから並ぶ
+        let vm = VirtualMachineInterpreter::load(SOURCE.lines()).unwrap();
+        assert_eq!(&[PUSH, 2, 1, 0, 0], &vm.byte_code[..5]);
+        assert_eq!(Some(-2), decode_operand(&[0xfe, 0xff, 0xff, 0xff]));
+        assert_eq!(None, decode_operand(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let vm = VirtualMachineInterpreter::load(SOURCE.lines()).unwrap();
+        let bytes = vm.to_bytes();
+        #[rustfmt::skip]
+        let expected: Vec<u8> = [
+            b"TVMB".as_slice(), &[2], &[32],
+            &[1, 0, 0, 0],
+            &[1, 0, 0, 0], &[4, 0, 0, 0], b"n = ",
+            &[23, 0, 0, 0],
+            &[PUSH, 2, 1, 0, 0], &[STORE, 0, 0, 0, 0], &[PUSH, 0, 0, 0, 0], &[PRTS],
+            &[FETCH, 0, 0, 0, 0], &[PRTI], &[HALT],
+            &[1, 0, 0, 0], &[0, 0, 0, 0], &[1, 0, 0, 0], b"n",
+        ]
+        .concat();
+        assert_eq!(expected, bytes);
+
+        // 書き出したファイルから読み込んだ VM は、元と同じように動く
+        for engine in [Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_bytes_with(&bytes, engine).unwrap();
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!(b"n = 258", &out[..]);
+            assert_eq!(Some(258), vm.variable("n"));
+        }
+    }
+
+    #[test]
+    fn test_bytes_limits() {
+        let bytes = VirtualMachineInterpreter::load(SOURCE.lines())
+            .unwrap()
+            .to_bytes();
+        let load = |limits: Limits| {
+            VirtualMachineInterpreter::load_bytes_with_limits(&bytes, Engine::default(), &limits)
+        };
+        let e = load(Limits {
+            data_size: Some(0),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert_eq!("data size 1 exceeds the limit of 0", e.message());
+        let e = load(Limits {
+            string_pool_size: Some(3),
+            ..Default::default()
+        })
+        .err()
+        .unwrap();
+        assert_eq!(
+            "string pool size 4 bytes exceeds the limit of 3 bytes",
+            e.message()
+        );
+        assert!(load(Limits {
+            data_size: Some(1),
+            string_pool_size: Some(4),
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let bytes = VirtualMachineInterpreter::load(SOURCE.lines())
+            .unwrap()
+            .to_bytes();
+        let error = |bytes: &[u8]| {
+            VirtualMachineInterpreter::load_bytes(bytes)
+                .err()
+                .unwrap()
+                .message()
+        };
+
+        assert_eq!("invalid bytecode: missing magic number", error(b"Datasize"));
+        let mut newer = bytes.clone();
+        newer[4] = FORMAT_VERSION + 1;
+        assert_eq!(
+            "invalid bytecode: unsupported format version 3 (expected 2)",
+            error(&newer)
+        );
+        assert_eq!(
+            "invalid bytecode: truncated at offset 26",
+            error(&bytes[..31])
+        );
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(
+            format!(
+                "invalid bytecode: unexpected data at offset {}",
+                bytes.len()
+            ),
+            error(&extra)
+        );
+
+        // 読み込んだバイトコードもアセンブリと同じく検査する
+        let mut broken = bytes.clone();
+        let halt = bytes.iter().rposition(|&b| b == HALT).unwrap();
+        broken[halt] = PRTI;
+        assert!(VirtualMachineInterpreter::load_bytes(&broken).is_err());
+    }
+}
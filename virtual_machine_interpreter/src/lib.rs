@@ -1,39 +1,86 @@
+use isa::random::Random;
 use lexical_analyzer::error::{CompileError, ErrorKind, Result};
-use std::convert::TryInto;
-use std::io::Write;
-use std::str::Lines;
-
-const FETCH: u8 = 0;
-const STORE: u8 = 1;
-const PUSH: u8 = 2;
-const ADD: u8 = 3;
-const SUB: u8 = 4;
-const MUL: u8 = 5;
-const DIV: u8 = 6;
-const MOD: u8 = 7;
-const LT: u8 = 8;
-const GT: u8 = 9;
-const LE: u8 = 10;
-const GE: u8 = 11;
-const EQ: u8 = 12;
-const NE: u8 = 13;
-const AND: u8 = 14;
-const OR: u8 = 15;
-const NEG: u8 = 16;
-const NOT: u8 = 17;
-const JMP: u8 = 18;
-const JZ: u8 = 19;
-const PRTC: u8 = 20;
-const PRTS: u8 = 21;
-const PRTI: u8 = 22;
-const HALT: u8 = 23;
+use lexical_analyzer::float::{format_float, Float};
+use lexical_analyzer::input::Input;
+use lexical_analyzer::limits::Limits;
+use lexical_analyzer::output::OutputPolicy;
+use lexical_analyzer::report::RunReport;
+use lexical_analyzer::width::Width;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::num::{IntErrorKind, ParseIntError};
+use std::str::{FromStr, Lines};
+use std::time::Instant;
 
+use isa::*;
+
+mod decoded;
+mod host;
+mod image;
+mod profile;
+
+pub use decoded::DecodedOp;
+pub use image::{is_bytecode, FORMAT_VERSION, MAGIC};
+pub use profile::Profile;
+
+/// prtiw の幅の絶対値の上限
+const MAX_FIELD_WIDTH: i32 = 1024;
+
+/// ヘッダに "Stack: 深さ" がない場合のスタックの大きさ。これより深くなるプログラムは読み込まない。
 const STACK_SIZE: usize = 1000;
 
+/// ヘッダの "Stack: 深さ" で確保できるスタックの大きさの上限
+const MAX_STACK_SIZE: usize = 1 << 20;
+
+/// コールスタックの深さの上限。再帰が深すぎるプログラムはここで止める。
+/// Limits::recursion_depth でさらに小さくできる。
+const CALL_STACK_SIZE: usize = 10000;
+
+/// 変数名の表の見出し (先頭の ; を除いたもの)
+const SYMBOLS_HEADER: &str = "Symbols:";
+
+/// value を幅 width に揃えた文字列。 width が正なら右に、負なら左に寄せる。
+/// 幅より長い数はそのまま返す。
+pub fn pad_integer(value: i64, width: i32) -> String {
+    let len = width.unsigned_abs() as usize;
+    if width < 0 {
+        format!("{:<len$}", value)
+    } else {
+        format!("{:>len$}", value)
+    }
+}
+
+/// 値の種類。文字列は文字列プールの番号で表し、種類をスタックや変数とは別に持つ。
+/// 文字列プールは読み込んだ後に変わらないので、文字列の値を捨てても片付けるものはない。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Int,
+    Str,
+    /// f32 のビット列
+    Float,
+}
+
+/// 実行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// バイトコードを 1 命令ずつ解釈する
+    #[default]
+    Bytecode,
+    /// 実行前にオペランドを読み出した命令列に変換してから実行する
+    Decoded,
+}
+
 #[derive(Debug)]
 struct Header {
     data_size: usize,
     string_size: usize,
+    /// 必要なスタックの深さ。ヘッダに書かれていなければ None。
+    stack_size: Option<usize>,
+    /// 整数の値の幅。ヘッダに書かれていなければ 32 ビット。
+    width: Width,
 }
 
 pub struct VirtualMachineInterpreter {
@@ -41,470 +88,2814 @@ pub struct VirtualMachineInterpreter {
     sp: usize,
     byte_code: Vec<u8>,
     string_pool: Vec<String>,
-    data: Vec<i32>,
-    stack: [i32; STACK_SIZE],
+    /// 値は 64 ビットで持ち、 32 ビットの幅なら演算の結果を i32 の範囲に折り返す。
+    /// 浮動小数点数は f32 のビット列を i32 として読んだ値で持つ。
+    data: Vec<i64>,
+    /// ヘッダの "Stack: 深さ" の大きさ (なければ STACK_SIZE) で確保する。
+    /// 読み込み時に、深さがこの大きさを超えないことを確かめている。
+    /// 実行中に伸ばすと遅くなるので、大きさは読み込み時に決めて変えない。
+    stack: Box<[i64]>,
+    /// stack の同じ位置の値の種類。値を積む命令が付け、演算の結果は Int にする。
+    tags: Box<[Tag]>,
+    /// data の同じ位置の値の種類。 store で付け、 fetch でスタックに戻す。
+    data_tags: Vec<Tag>,
+    /// 実行した命令を書き出す先。None ならトレースしない。
+    trace: Option<Box<dyn Write>>,
+    /// 実行統計。None ならプロファイルしない。
+    profile: Option<Profile>,
+    /// getc と geti の読み込み元。設定しなければ常に EOF。
+    input: Input,
+    /// rand の擬似乱数
+    random: Random,
+    /// clock の基準時刻。 execute を呼ぶたびに設定し直す。
+    start: Instant,
+    /// 変数名から data の添字への表。アセンブリに "; Symbols:" の表があれば読み込む。
+    symbols: HashMap<String, usize>,
+    /// Engine::Decoded の場合の命令列と、各命令の元のアドレス
+    decoded: Option<(Vec<DecodedOp>, Vec<usize>)>,
+    /// 出力先が閉じられたときの扱い
+    output: OutputPolicy,
+    /// exit 命令で止まったときの終了コード
+    exit_code: Option<i32>,
+    /// 最後に実行した命令。 pc が範囲外になったときのエラーに使う。
+    last_opcode: Option<u8>,
+    /// 整数の値の幅
+    width: Width,
+    /// フレームポインタ。 enter で作ったフレームの先頭のスタックの位置。
+    fp: usize,
+    /// call で積んだ戻り先のアドレス
+    call_stack: Vec<usize>,
+    /// enter で保存したフレームポインタ
+    frames: Vec<usize>,
+    /// 1 つのフレームが使うスタックの深さの上限。 enter でスタックが溢れないか調べるのに使う。
+    frame_size: usize,
+    /// xcall で呼ぶ、埋め込み側が登録した関数
+    host_functions: HashMap<u32, host::HostFunction>,
+    /// ヘッダに書かれた必要なスタックの深さ
+    stack_size: Option<usize>,
+    /// 実行する前にスタックの深さを調べる必要があれば true。
+    /// xcall の作用は登録した関数で決まるので、 xcall があれば実行する直前まで調べない。
+    stack_pending: bool,
+    /// 資源の上限
+    limits: Limits,
+    /// 出力先に書き出したバイト数。 write は &self で呼ぶので Cell に入れる。
+    written: Cell<usize>,
+    /// 実行した命令の数
+    retired: u64,
+    /// 文の最初の命令のアドレスと、その文が始まるソースの行 (アドレス順)。
+    /// 実行時のエラーにソースの行を付けるのに使う。
+    lines: Vec<(usize, usize)>,
 }
 
-impl VirtualMachineInterpreter {
-    pub fn interpret(lines: Lines, out: &mut Write) -> Result<()> {
-        let mut vm = VirtualMachineInterpreter::assemble(lines)?;
-        vm.execute(out)
-    }
+impl VirtualMachineInterpreter {
+    /// アセンブリを読み込んで実行する。 getc と geti は input から読む。
+    pub fn interpret(lines: Lines, input: Box<dyn Read>, out: &mut dyn Write) -> Result<RunReport> {
+        let mut vm = VirtualMachineInterpreter::load(lines)?;
+        vm.set_input(input);
+        vm.execute(out)
+    }
+
+    /// アセンブリを読み込み、実行可能な状態の VM を返す。
+    pub fn load(lines: Lines) -> Result<Self> {
+        Self::load_with(lines, Engine::default())
+    }
+
+    pub fn load_with(lines: Lines, engine: Engine) -> Result<Self> {
+        Self::load_with_limits(lines, engine, &Limits::default())
+    }
+
+    /// 資源の上限 limits を設けて読み込む。変数の数と文字列プールの大きさは読み込み時に、
+    /// 出力のバイト数と関数呼び出しの深さは実行時に調べる。
+    pub fn load_with_limits(lines: Lines, engine: Engine, limits: &Limits) -> Result<Self> {
+        let (vm, header) = Self::assemble(lines, limits)?;
+        vm.prepare(header.stack_size, engine, limits)
+    }
+
+    /// 組み立てた VM を検査し、 engine で実行できるようにする。
+    /// stack_size はヘッダに書かれた必要なスタックの深さ。
+    fn prepare(
+        mut self,
+        stack_size: Option<usize>,
+        engine: Engine,
+        limits: &Limits,
+    ) -> Result<Self> {
+        self.limits = *limits;
+        self.validate()?;
+        if let Some(size) = stack_size {
+            if size > MAX_STACK_SIZE {
+                return Err(CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    format!(
+                        "stack size {} exceeds the limit of {}",
+                        size, MAX_STACK_SIZE
+                    ),
+                ));
+            }
+            self.stack = vec![0; size].into_boxed_slice();
+            self.tags = vec![Tag::Int; size].into_boxed_slice();
+        }
+        self.stack_size = stack_size;
+        if self.uses_host_functions() {
+            self.stack_pending = true;
+        } else {
+            self.check_stack()?;
+        }
+        if engine == Engine::Decoded {
+            let (mut ops, addresses) = decoded::decode(&self.byte_code);
+            decoded::fuse_tail_calls(&mut ops);
+            self.decoded = Some((ops, addresses));
+        }
+        Ok(self)
+    }
+
+    /// スタックの深さが上限とヘッダの値に収まることを確かめ、フレームの大きさを決める。
+    fn check_stack(&mut self) -> Result<()> {
+        let depth = self.max_stack_depth()?;
+        if let Some(stack_size) = self.stack_size.filter(|&size| size < depth) {
+            return Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!(
+                    "stack size in the header is {}, but {} entries are needed",
+                    stack_size, depth
+                ),
+            ));
+        }
+        if depth > self.stack.len() {
+            return Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!(
+                    "stack overflow: {} entries are needed (limit: {})",
+                    depth,
+                    self.stack.len()
+                ),
+            ));
+        }
+        self.frame_size = depth;
+        self.stack_pending = false;
+        Ok(())
+    }
+
+    /// 実行前にバイトコードを検査する。
+    /// 不正な命令を実行して panic したり、でたらめな動作をしたりしないようにする。
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |address: usize, message: String| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("{}: {}", address, message),
+            )
+        };
+
+        // 命令の先頭アドレスとオペランドを集める
+        let mut instructions: Vec<(usize, u8, Option<i32>)> = Vec::new();
+        let mut address = 0;
+        while address < self.byte_code.len() {
+            let opcode = self.byte_code[address];
+            if mnemonic(opcode) == "???" {
+                return Err(invalid(address, format!("illegal instruction: {}", opcode)));
+            }
+            if opcode == PUSHW {
+                if self.width != Width::W64 {
+                    return Err(invalid(
+                        address,
+                        "pushw needs \"Width: 64\" in the header".to_string(),
+                    ));
+                }
+                decode_wide_operand(&self.byte_code[address + 1..])
+                    .ok_or_else(|| invalid(address, "operand is truncated".to_string()))?;
+                instructions.push((address, opcode, None));
+            } else if has_operand(opcode) {
+                let operand = decode_operand(&self.byte_code[address + 1..])
+                    .ok_or_else(|| invalid(address, "operand is truncated".to_string()))?;
+                instructions.push((address, opcode, Some(operand)));
+            } else {
+                instructions.push((address, opcode, None));
+            }
+            address += instruction_size(opcode);
+        }
+
+        let is_boundary = |target: usize| {
+            instructions
+                .binary_search_by_key(&target, |&(address, _, _)| address)
+                .is_ok()
+        };
+        let mut previous: Option<(u8, Option<i32>)> = None;
+        for &(address, opcode, operand) in &instructions {
+            match (opcode, operand) {
+                (JMP, Some(offset)) | (JZ, Some(offset)) | (CALL, Some(offset)) => {
+                    let target = (address + 1).wrapping_add(offset as usize);
+                    if !is_boundary(target) {
+                        return Err(invalid(
+                            address,
+                            format!("jump target {} is not an instruction", target),
+                        ));
+                    }
+                    if opcode == CALL && self.byte_code[target] != ENTER {
+                        return Err(invalid(
+                            address,
+                            format!("call target {} does not start with enter", target),
+                        ));
+                    }
+                }
+                (ENTER, Some(arguments)) if arguments < 0 => {
+                    return Err(invalid(
+                        address,
+                        format!("argument count {} is negative", arguments),
+                    ));
+                }
+                (XCALL, Some(id)) if id < 0 => {
+                    return Err(invalid(
+                        address,
+                        format!("host function id {} is negative", id),
+                    ));
+                }
+                (FETCHL, Some(index)) | (STOREL, Some(index)) if index < 0 => {
+                    return Err(invalid(
+                        address,
+                        format!("local index {} is negative", index),
+                    ));
+                }
+                (FETCH, Some(index)) | (STORE, Some(index))
+                    if index < 0 || index as usize >= self.data.len() =>
+                {
+                    return Err(invalid(
+                        address,
+                        format!(
+                            "data index {} is out of range (data size: {})",
+                            index,
+                            self.data.len()
+                        ),
+                    ));
+                }
+                (PRTIW, Some(width)) if !(-MAX_FIELD_WIDTH..=MAX_FIELD_WIDTH).contains(&width) => {
+                    return Err(invalid(
+                        address,
+                        format!(
+                            "field width {} is out of range (limit: {})",
+                            width, MAX_FIELD_WIDTH
+                        ),
+                    ));
+                }
+                (PUSHS, Some(index)) if index < 0 || index as usize >= self.string_pool.len() => {
+                    return Err(invalid(
+                        address,
+                        format!(
+                            "string index {} is out of range (strings: {})",
+                            index,
+                            self.string_pool.len()
+                        ),
+                    ));
+                }
+                (PRTS, _) | (ASSERT, _) => {
+                    // 直前の push で積んだ文字列の番号だけを検査できる
+                    if let Some((PUSH, Some(index))) = previous {
+                        if index < 0 || index as usize >= self.string_pool.len() {
+                            return Err(invalid(
+                                address,
+                                format!(
+                                    "string index {} is out of range (strings: {})",
+                                    index,
+                                    self.string_pool.len()
+                                ),
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            previous = Some((opcode, operand));
+        }
+
+        // 最後の命令から先に進まないようにする。関数で終わるコードは ret で終わればよい。
+        match instructions.last() {
+            Some(&(_, HALT, _)) | Some(&(_, RET, _)) => Ok(()),
+            Some(&(address, _, _)) => {
+                Err(invalid(address, "code does not end with halt".to_string()))
+            }
+            None => Err(invalid(0, "code does not end with halt".to_string())),
+        }
+    }
+
+    /// 実行した命令ごとに、アドレス・命令・スタックトップを trace に書き出す。
+    pub fn set_trace(&mut self, trace: Box<dyn Write>) {
+        self.trace = Some(trace);
+    }
+
+    /// 命令のアドレスとソースの行の対応を設定する。 lines は (文の最初の命令のアドレス, 行) のアドレス順の列。
+    /// アセンブリの "; line" のコメントから読んだ対応は置き換える。
+    pub fn set_lines(&mut self, lines: Vec<(usize, usize)>) {
+        self.lines = lines;
+    }
+
+    /// address の命令を含む文の行
+    fn line_at(&self, address: usize) -> Option<usize> {
+        let end = self.lines.partition_point(|&(a, _)| a <= address);
+        end.checked_sub(1).map(|i| self.lines[i].1)
+    }
+
+    /// 実行時のエラーに、 address の命令を含む文の行を付ける
+    fn locate(&self, address: usize, e: CompileError) -> CompileError {
+        match self.line_at(address) {
+            Some(line) => e.with_line(line),
+            None => e,
+        }
+    }
+
+    /// getc と geti で読む入力を設定する。
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = Input::new(input);
+    }
+
+    /// rand の種を設定する。
+    pub fn set_seed(&mut self, seed: u32) {
+        self.random = Random::new(seed);
+    }
+
+    /// 出力先が閉じられたときの扱いを設定する。
+    pub fn set_output_policy(&mut self, policy: OutputPolicy) {
+        self.output = policy;
+    }
+
+    /// 0 以上 n 未満の擬似乱数。乱数の範囲は 64 ビットでも i32 までにする。
+    fn rand(&mut self, n: i64) -> Result<i64> {
+        let clamped = n.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        self.random.next(clamped).map(i64::from).ok_or_else(|| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("rand: {} is not positive", n),
+            )
+        })
+    }
+
+    /// execute を呼んでからのミリ秒。値の幅に収まらなくなると折り返す。
+    fn clock(&self) -> i64 {
+        self.width.wrap(self.start.elapsed().as_millis() as i64)
+    }
+
+    /// 入力から 1 バイト読む。EOF なら -1。
+    fn read_char(&mut self) -> Result<i64> {
+        self.input
+            .read_char(ErrorKind::VirtualMachineError)
+            .map(i64::from)
+    }
+
+    /// 入力から整数を読む。整数がない場合と値の幅に収まらない場合はエラーにする。
+    fn read_int(&mut self) -> Result<i64> {
+        self.input
+            .read_integer_in(ErrorKind::VirtualMachineError, self.width)
+    }
+
+    /// div と mod と pow。 0 で割るとエラーにする。
+    #[inline(always)]
+    fn divide(&self, opcode: u8, a: i64, b: i64) -> Result<i64> {
+        let value = match opcode {
+            DIV => self.width.div(a, b),
+            MOD => self.width.rem(a, b),
+            _ => self.width.pow(a, b),
+        };
+        value.ok_or_else(|| CompileError::new(ErrorKind::VirtualMachineError, "division by zero"))
+    }
+
+    /// スタックの上の 2 つの浮動小数点数に f を適用した結果で置き換える
+    #[inline(always)]
+    fn float_op<F>(&mut self, f: F)
+    where
+        F: FnOnce(f32, f32) -> f32,
+    {
+        let operand0 = float_value(self.stack[self.sp - 2]);
+        let operand1 = float_value(self.stack[self.sp - 1]);
+        self.stack[self.sp - 2] = Float(f(operand0, operand1)).to_bits() as i64;
+        self.tags[self.sp - 2] = Tag::Float;
+        self.sp -= 1;
+    }
+
+    /// スタックの上の 2 つの浮動小数点数を比べた結果の 0 か 1 で置き換える
+    #[inline(always)]
+    fn float_compare<F>(&mut self, f: F)
+    where
+        F: FnOnce(f32, f32) -> bool,
+    {
+        let operand0 = float_value(self.stack[self.sp - 2]);
+        let operand1 = float_value(self.stack[self.sp - 1]);
+        self.stack[self.sp - 2] = f(operand0, operand1) as i64;
+        self.tags[self.sp - 2] = Tag::Int;
+        self.sp -= 1;
+    }
+
+    /// itof, ftoi, fneg
+    #[inline(always)]
+    fn convert(&mut self, opcode: u8) {
+        let value = self.stack[self.sp - 1];
+        // ftoi は 0 の方向に丸め、範囲外の値は値の幅の端に、 NaN は 0 にする
+        let (value, tag) = match opcode {
+            ITOF => (Float(value as f32).to_bits() as i64, Tag::Float),
+            FTOI => (
+                (float_value(value) as i64).clamp(self.width.min(), self.width.max()),
+                Tag::Int,
+            ),
+            _ => (Float(-float_value(value)).to_bits() as i64, Tag::Float),
+        };
+        self.stack[self.sp - 1] = value;
+        self.tags[self.sp - 1] = tag;
+    }
+
+    /// fetchi, storei のアドレスを検査する
+    fn data_index(&self, address: i64) -> Result<usize> {
+        if address >= 0 && (address as usize) < self.data.len() {
+            Ok(address as usize)
+        } else {
+            Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("data index {} is out of range", address),
+            ))
+        }
+    }
+
+    /// assert 命令。 condition が 0 なら、 message の番号の文字列をエラーにする。
+    fn check_assertion(&self, condition: i64, message: i64) -> Result<()> {
+        if condition != 0 {
+            return Ok(());
+        }
+        match self.string_pool.get(message as usize) {
+            Some(s) if message >= 0 => Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                s.as_str(),
+            )),
+            _ => Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("illegal string index: {}", message),
+            )),
+        }
+    }
+
+    /// call 命令。戻り先をコールスタックに積む。
+    fn push_return(&mut self, address: usize) -> Result<()> {
+        let limit = self.limits.recursion_depth_or(CALL_STACK_SIZE);
+        if self.call_stack.len() >= limit {
+            return Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("call stack overflow (limit: {})", limit),
+            ));
+        }
+        self.call_stack.push(address);
+        Ok(())
+    }
+
+    /// ret 命令の戻り先
+    fn pop_return(&mut self) -> Result<usize> {
+        self.call_stack
+            .pop()
+            .ok_or_else(|| CompileError::new(ErrorKind::VirtualMachineError, "ret without call"))
+    }
+
+    /// enter 命令。スタックの上の arguments 個の値を引数とするフレームを作る。
+    /// 関数の中の深さは読み込み時に調べてあるので、フレームが収まるかだけを確かめる。
+    fn enter(&mut self, arguments: usize) -> Result<()> {
+        let fp = self.sp - arguments;
+        if fp + self.frame_size > self.stack.len() || self.frames.len() >= CALL_STACK_SIZE {
+            return Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!(
+                    "stack overflow: {} frames are active (limit: {} entries)",
+                    self.frames.len(),
+                    self.stack.len()
+                ),
+            ));
+        }
+        self.frames.push(self.fp);
+        self.fp = fp;
+        Ok(())
+    }
+
+    /// leave 命令。スタックトップをフレームの先頭に移してフレームを捨てる。
+    fn leave(&mut self) -> Result<()> {
+        let fp = self.frames.pop().ok_or_else(|| {
+            CompileError::new(ErrorKind::VirtualMachineError, "leave without enter")
+        })?;
+        self.stack[self.fp] = self.stack[self.sp - 1];
+        self.tags[self.fp] = self.tags[self.sp - 1];
+        self.sp = self.fp + 1;
+        self.fp = fp;
+        Ok(())
+    }
+
+    /// 命令ごとの実行回数とスタックの最大の深さを数える。
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::new(self.byte_code.len()));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    fn write_trace(&mut self) -> Result<()> {
+        let opcode = self.fetch_opcode()?;
+        let mut line = format!("{:5} {}", self.pc, mnemonic(opcode));
+        if has_operand(opcode) {
+            let pc = self.pc;
+            self.pc += 1;
+            let operand = self.get_integer();
+            self.pc = pc;
+            let operand = operand?;
+            if opcode == PUSHF {
+                line += &format!(" {}", Float::from_bits(operand));
+            } else {
+                line += &format!(" {}", operand);
+            }
+        } else if opcode == PUSHW {
+            let pc = self.pc;
+            self.pc += 1;
+            let operand = self.get_wide_integer();
+            self.pc = pc;
+            line += &format!(" {}", operand?);
+        }
+        if self.sp > 0 {
+            let top = self.format_value(self.stack[self.sp - 1], self.tags[self.sp - 1]);
+            line += &format!("\t; sp={} top={}", self.sp, top);
+        } else {
+            line += "\t; sp=0";
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            writeln!(trace, "{}", line).map_err(|e| {
+                CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    format!("trace error: {}", e),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn assemble(mut lines: Lines, limits: &Limits) -> Result<(Self, Header)> {
+        let header;
+
+        if let Some(line) = lines.next() {
+            header = Self::read_header(line).map_err(|e| e.with_line(1))?;
+        } else {
+            return Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                "empty file",
+            ));
+        }
+        // data は読み込み時に確保するので、確保する前に調べる
+        limits.check_data_size(ErrorKind::VirtualMachineError, header.data_size)?;
+
+        let mut string_pool: Vec<String> = Vec::new();
+        let mut string_pool_size = 0;
+        for i in 0..header.string_size {
+            if let Some(line) = lines.next() {
+                let s = Self::read_string(line).map_err(|e| e.with_line(2 + i))?;
+                string_pool_size += s.len();
+                limits.check_string_pool_size(ErrorKind::VirtualMachineError, string_pool_size)?;
+                string_pool.push(s);
+            } else {
+                return Err(CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    "unexpected EOF",
+                ));
+            }
+        }
+
+        let mut byte_code: Vec<u8> = Vec::new();
+        let mut labels: HashMap<&str, usize> = HashMap::new();
+        let mut fixups: Vec<(usize, &str)> = Vec::new();
+        let mut symbols: HashMap<String, usize> = HashMap::new();
+        let mut source_lines: Vec<(usize, usize)> = Vec::new();
+        let mut in_symbols = false;
+        // エラーにはアセンブリの行を付ける
+        let first_line = 2 + header.string_size;
+        for (number, line) in (first_line..).zip(lines) {
+            // ; から行末まではコメント。
+            // "; Symbols:" の後に続くコメントは "; 番号 変数名" の形の変数名の表。
+            // "; line 行: 文" の形のコメントは、次の命令から始まるソースの行
+            if let Some(comment) = line.trim().strip_prefix(';') {
+                let comment = comment.trim();
+                if comment == SYMBOLS_HEADER {
+                    in_symbols = true;
+                } else if in_symbols {
+                    let (index, name) =
+                        Self::read_symbol(comment).map_err(|e| e.with_line(number))?;
+                    symbols.insert(name.to_string(), index);
+                } else if let Some(source_line) = Self::read_line_comment(comment) {
+                    source_lines.push((byte_code.len(), source_line));
+                }
+                continue;
+            }
+            in_symbols = false;
+            let mut line = line.split(';').next().unwrap_or_default().trim();
+            while let Some((label, rest)) = Self::split_label(line) {
+                if labels.insert(label, byte_code.len()).is_some() {
+                    return Err(CompileError::new(
+                        ErrorKind::VirtualMachineError,
+                        format!("duplicate label: {}", label),
+                    )
+                    .with_line(number));
+                }
+                line = rest;
+            }
+            if line.is_empty() {
+                // empty line
+                continue;
+            }
+
+            Self::read_instruction(line, &mut byte_code, &mut fixups)
+                .map_err(|e| e.with_line(number))?;
+        }
+
+        // ラベルかアドレスを参照するジャンプ命令のオフセットを埋める。
+        // ラベル名は数字で始まらないので、数字ならアドレスとする。
+        for (position, label) in fixups {
+            let target = match (labels.get(label), label.parse::<usize>()) {
+                (Some(&target), _) | (None, Ok(target)) => target,
+                (None, Err(_)) => {
+                    return Err(CompileError::new(
+                        ErrorKind::VirtualMachineError,
+                        format!("undefined label: {}", label),
+                    ))
+                }
+            };
+            let offset = target.wrapping_sub(position) as i32;
+            byte_code[position..position + 4].copy_from_slice(&encode_operand(offset));
+        }
+
+        let mut vm = Self::new(
+            byte_code,
+            string_pool,
+            header.data_size,
+            symbols,
+            header.width,
+        );
+        vm.lines = source_lines;
+        Ok((vm, header))
+    }
+
+    /// "line 行: 文" の形のコメントなら、その行
+    fn read_line_comment(comment: &str) -> Option<usize> {
+        let (line, _) = comment.strip_prefix("line ")?.split_once(':')?;
+        line.parse().ok()
+    }
+
+    /// 実行を始める前の状態の VM
+    fn new(
+        byte_code: Vec<u8>,
+        string_pool: Vec<String>,
+        data_size: usize,
+        symbols: HashMap<String, usize>,
+        width: Width,
+    ) -> Self {
+        VirtualMachineInterpreter {
+            pc: 0,
+            sp: 0,
+            byte_code,
+            string_pool,
+            data: vec![0; data_size],
+            stack: vec![0; STACK_SIZE].into_boxed_slice(),
+            tags: vec![Tag::Int; STACK_SIZE].into_boxed_slice(),
+            data_tags: vec![Tag::Int; data_size],
+            trace: None,
+            profile: None,
+            input: Input::default(),
+            random: Random::default(),
+            start: Instant::now(),
+            symbols,
+            decoded: None,
+            output: OutputPolicy::default(),
+            exit_code: None,
+            last_opcode: None,
+            width,
+            fp: 0,
+            call_stack: Vec::new(),
+            frames: Vec::new(),
+            frame_size: 0,
+            host_functions: HashMap::new(),
+            stack_size: None,
+            stack_pending: false,
+            limits: Limits::default(),
+            written: Cell::new(0),
+            retired: 0,
+            lines: Vec::new(),
+        }
+    }
+
+    fn read_symbol(comment: &str) -> Result<(usize, &str)> {
+        let mut words = comment.split_whitespace();
+        match (
+            words.next().and_then(|s| s.parse().ok()),
+            words.next(),
+            words.next(),
+        ) {
+            (Some(index), Some(name), None) => Ok((index, name)),
+            _ => Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("invalid symbol: {}", comment),
+            )),
+        }
+    }
+
+    /// "Datasize: n Strings: m" の形のヘッダを読む。
+    /// 項目の順序と名前の大文字小文字は問わず、 : の前後の空白は省略できる。
+    /// "Stack: 深さ" と "Width: ビット数" は省略でき、知らない項目は後の版のものとして読み飛ばす。
+    fn read_header(line: &str) -> Result<Header> {
+        let error = |message: String| CompileError::new(ErrorKind::VirtualMachineError, message);
+
+        let mut data_size = None;
+        let mut string_size = None;
+        let mut stack_size = None;
+        let mut width = None;
+        for (name, value) in Self::header_fields(line)? {
+            let field = match name.to_ascii_lowercase().as_str() {
+                "datasize" => &mut data_size,
+                "strings" => &mut string_size,
+                "stack" => &mut stack_size,
+                "width" => &mut width,
+                _ => continue,
+            };
+            if field.is_some() {
+                return Err(error(format!("duplicate {} in the header", name)));
+            }
+            *field = Some(value);
+        }
+
+        let size = |value: Option<&str>, what: &str| -> Result<Option<usize>> {
+            value
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| error(format!("invalid {}: {}", what, value)))
+                })
+                .transpose()
+        };
+        let data_size = size(data_size, "data size")?
+            .ok_or_else(|| error("missing Datasize in the header".to_string()))?;
+        let string_size = size(string_size, "string count")?
+            .ok_or_else(|| error("missing Strings in the header".to_string()))?;
+        let stack_size = size(stack_size, "stack size")?;
+        let width = width
+            .map(|value| {
+                value
+                    .parse()
+                    .ok()
+                    .and_then(Width::from_bits)
+                    .ok_or_else(|| error(format!("invalid width: {}", value)))
+            })
+            .transpose()?;
+        Ok(Header {
+            data_size,
+            string_size,
+            stack_size,
+            width: width.unwrap_or_default(),
+        })
+    }
+
+    /// ヘッダを "名前: 値" の組に分ける
+    fn header_fields(line: &str) -> Result<Vec<(&str, &str)>> {
+        let error = |message: String| CompileError::new(ErrorKind::VirtualMachineError, message);
+
+        let mut fields = Vec::new();
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let end = rest
+                .find(|c: char| c == ':' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let (name, after) = rest.split_at(end);
+            if name.is_empty() {
+                return Err(error(
+                    "missing field name before ':' in the header".to_string(),
+                ));
+            }
+            rest = after
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or_else(|| error(format!("expected ':' after {} in the header", name)))?
+                .trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (value, after) = rest.split_at(end);
+            // 値が空か、次の項目の名前なら値がない
+            if value.is_empty() || value.ends_with(':') {
+                return Err(error(format!("missing value for {} in the header", name)));
+            }
+            fields.push((name, value));
+            rest = after.trim_start();
+        }
+        Ok(fields)
+    }
+
+    fn read_string(s: &str) -> Result<String> {
+        escape::unquote(s)
+            .map(|(value, _)| value)
+            .map_err(|e| CompileError::new(ErrorKind::VirtualMachineError, e.to_string()))
+    }
+
+    /// 浮動小数点数を f32 のビット列のオペランドにする
+    fn read_float(s: &str, dst: &mut Vec<u8>) -> Result<()> {
+        if let Ok(val) = s.parse::<f32>() {
+            dst.extend_from_slice(&encode_operand(Float(val).to_bits()));
+            Ok(())
+        } else {
+            Err(CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("cannot convert to float: {}", s),
+            ))
+        }
+    }
+
+    /// pushw の 8 バイトのオペランド
+    fn read_wide_integer(s: &str, mnemonic: &str, dst: &mut Vec<u8>) -> Result<()> {
+        let value = Self::parse_integer(s, mnemonic, i64::MIN, i64::MAX)?;
+        dst.extend_from_slice(&encode_wide_operand(value));
+        Ok(())
+    }
+
+    fn read_integer(s: &str, mnemonic: &str, dst: &mut Vec<u8>) -> Result<()> {
+        let value = Self::parse_integer(s, mnemonic, i32::MIN, i32::MAX)?;
+        dst.extend_from_slice(&encode_operand(value));
+        Ok(())
+    }
+
+    /// 符号付きの 10 進の整数を読む。整数だが min..=max に収まらなければ、
+    /// 命令と範囲を添えてエラーにする。
+    fn parse_integer<T>(s: &str, mnemonic: &str, min: T, max: T) -> Result<T>
+    where
+        T: FromStr<Err = ParseIntError> + Display,
+    {
+        s.parse().map_err(|e: ParseIntError| {
+            let message = match e.kind() {
+                IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => format!(
+                    "operand of {} is out of range: {} (range: {}..={})",
+                    mnemonic, s, min, max
+                ),
+                _ => format!("cannot convert to integer: {}", s),
+            };
+            CompileError::new(ErrorKind::VirtualMachineError, message)
+        })
+    }
+
+    /// 行頭の "name:" を切り出し、ラベル名と残りを返す。
+    fn split_label(line: &str) -> Option<(&str, &str)> {
+        let end = line.find(|c: char| c.is_whitespace()).unwrap_or(line.len());
+        let label = line[..end].strip_suffix(':')?;
+        let mut cs = label.chars();
+        match cs.next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+            _ => return None,
+        }
+        if cs.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            Some((label, line[end..].trim_start()))
+        } else {
+            None
+        }
+    }
+
+    /// "[n]" や "(n)" の括弧の中身を取り出す。
+    fn unwrap_operand(s: &str, open: char, close: char) -> Result<&str> {
+        s.strip_prefix(open)
+            .and_then(|s| s.strip_suffix(close))
+            .ok_or_else(|| {
+                CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    format!("invalid operand: {}", s),
+                )
+            })
+    }
+
+    /// ジャンプ先は "(offset) address" の形式か、アドレスかラベル名だけで指定する。
+    /// オフセットとアドレスの両方があれば、同じ位置を指していることを確かめる。
+    fn read_jump_target<'a>(
+        line: &str,
+        s: &'a str,
+        address: Option<&str>,
+        mnemonic: &str,
+        dst: &mut Vec<u8>,
+        fixups: &mut Vec<(usize, &'a str)>,
+    ) -> Result<()> {
+        if s.starts_with('(') {
+            let position = dst.len();
+            Self::read_integer(Self::unwrap_operand(s, '(', ')')?, mnemonic, dst)?;
+            let offset = decode_operand(&dst[position..]).unwrap();
+            let target = position as i64 + offset as i64;
+            match address.map(str::parse::<i64>) {
+                None => Ok(()),
+                Some(Ok(address)) if address == target => Ok(()),
+                Some(Ok(address)) => Err(CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    format!(
+                        "jump offset and target disagree: {} (offset {} points to {}, target is {})",
+                        line, offset, target, address
+                    ),
+                )),
+                Some(Err(_)) => Err(CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    format!("invalid jump target: {}", line),
+                )),
+            }
+        } else {
+            fixups.push((dst.len(), s));
+            dst.extend_from_slice(&encode_operand(0));
+            Ok(())
+        }
+    }
+
+    /// 先頭のアドレスは省略できる。アドレスは検査せず、命令を順に並べる。
+    fn read_instruction<'a>(
+        s: &'a str,
+        dst: &mut Vec<u8>,
+        fixups: &mut Vec<(usize, &'a str)>,
+    ) -> Result<()> {
+        let mut elements: Vec<&str> = s.split_whitespace().collect();
+        if elements.first().is_some_and(|e| e.parse::<usize>().is_ok()) {
+            elements.remove(0);
+        }
+
+        let invalid_code = || {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("invalid code: {}", s),
+            )
+        };
+        let mnemonic = *elements.first().ok_or_else(invalid_code)?;
+        let operand = elements.get(1).copied();
+
+        let info = isa::from_mnemonic(mnemonic).ok_or_else(|| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("illegal instruction: {}", s),
+            )
+        })?;
+        dst.push(info.opcode);
+        if info.operand == Operand::None {
+            return Ok(());
+        }
+        let operand = operand.ok_or_else(invalid_code)?;
+        match info.operand {
+            Operand::None => {}
+            Operand::Address => {
+                Self::read_integer(Self::unwrap_operand(operand, '[', ']')?, mnemonic, dst)?
+            }
+            Operand::Int | Operand::Str | Operand::Width => {
+                Self::read_integer(operand, mnemonic, dst)?
+            }
+            Operand::Wide => Self::read_wide_integer(operand, mnemonic, dst)?,
+            Operand::Float => Self::read_float(operand, dst)?,
+            Operand::Offset => {
+                Self::read_jump_target(s, operand, elements.get(2).copied(), mnemonic, dst, fixups)?
+            }
+        }
+        Ok(())
+    }
+
+    /// pc の位置の命令。 pc がバイトコードの外なら、最後に実行した命令を添えてエラーにする。
+    /// validate を通ったバイトコードでは起きないが、 panic させずに止める。
+    fn fetch_opcode(&self) -> Result<u8> {
+        self.byte_code.get(self.pc).copied().ok_or_else(|| {
+            let last = self.last_opcode.map_or("none", mnemonic);
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!(
+                    "pc {} is out of range (code size: {}, last opcode: {})",
+                    self.pc,
+                    self.byte_code.len(),
+                    last
+                ),
+            )
+        })
+    }
+
+    /// pc の位置のオペランド。 pc は命令の次を指している。
+    fn get_integer(&self) -> Result<i32> {
+        self.byte_code
+            .get(self.pc..)
+            .and_then(decode_operand)
+            .ok_or_else(|| self.truncated_operand())
+    }
+
+    /// pc の位置の pushw の 8 バイトのオペランド
+    fn get_wide_integer(&self) -> Result<i64> {
+        self.byte_code
+            .get(self.pc..)
+            .and_then(decode_wide_operand)
+            .ok_or_else(|| self.truncated_operand())
+    }
+
+    fn truncated_operand(&self) -> CompileError {
+        let address = self.pc.wrapping_sub(1);
+        let opcode = self.byte_code.get(address).copied().unwrap_or_default();
+        CompileError::new(
+            ErrorKind::VirtualMachineError,
+            format!(
+                "{}: operand of {} is truncated (code size: {})",
+                address,
+                mnemonic(opcode),
+                self.byte_code.len()
+            ),
+        )
+    }
+
+    /// halt か exit まで実行し、実行した命令の数と終了時の変数の値を返す。
+    pub fn execute(&mut self, out: &mut dyn Write) -> Result<RunReport> {
+        if self.exit_code.is_some() {
+            return Ok(self.report());
+        }
+        if self.stack_pending {
+            self.check_stack()?;
+        }
+        self.start = Instant::now();
+        let result = match self.decoded.take() {
+            Some((ops, addresses)) => {
+                let result = self.execute_decoded(&ops, &addresses, out);
+                self.decoded = Some((ops, addresses));
+                result
+            }
+            None => self.execute_bytecode(out),
+        };
+        self.output.finish(result)?;
+        Ok(self.report())
+    }
+
+    /// それまでに実行した命令の数と変数の値。
+    /// 変数名の表があれば名前の順に、なければ "[番号]" を名前として番号の順に並べる。
+    pub fn report(&self) -> RunReport {
+        let variables = if self.symbols.is_empty() {
+            (0..self.data.len())
+                .map(|index| (format!("[{}]", index), self.format_data(index)))
+                .collect()
+        } else {
+            let mut variables: Vec<(String, String)> = self
+                .symbols
+                .iter()
+                .filter(|(_, &index)| index < self.data.len())
+                .map(|(name, &index)| (name.clone(), self.format_data(index)))
+                .collect();
+            variables.sort();
+            variables
+        };
+        RunReport {
+            steps: self.retired,
+            instructions_retired: self.retired,
+            variables,
+            exit_code: self.exit_code(),
+        }
+    }
+
+    /// 各命令を実行する前のスタックの深さを求め、その最大値を返す。
+    /// validate で検査したバイトコードに対して使う。
+    /// 合流する経路で深さが異なる場合と、空のスタックから取り出す場合はエラーにする。
+    pub fn max_stack_depth(&self) -> Result<usize> {
+        let depths = self.stack_depths()?;
+        let mut max_depth = 0;
+        for (address, depth) in depths.iter().enumerate() {
+            if let Some(depth) = *depth {
+                let (pops, pushes) = stack_effect(self.byte_code[address]);
+                max_depth = max_depth.max(depth).max(depth - pops + pushes);
+            }
+        }
+        Ok(max_depth)
+    }
+
+    /// アドレスごとの、命令を実行する前のスタックの深さ。
+    /// 到達しない命令とオペランドのバイトは None。
+    fn stack_depths(&self) -> Result<Vec<Option<usize>>> {
+        let invalid = |address: usize, message: String| {
+            CompileError::new(
+                ErrorKind::VirtualMachineError,
+                format!("{}: {}", address, message),
+            )
+        };
+
+        let mut depths: Vec<Option<usize>> = vec![None; self.byte_code.len()];
+        let mut pending = vec![(0, 0)];
+        while let Some((address, depth)) = pending.pop() {
+            match depths[address] {
+                Some(d) if d == depth => continue,
+                Some(d) => {
+                    return Err(invalid(
+                        address,
+                        format!("stack depth is {} or {} depending on the path", d, depth),
+                    ))
+                }
+                None => depths[address] = Some(depth),
+            }
+
+            let opcode = self.byte_code[address];
+            let operand = || decode_operand(&self.byte_code[address + 1..]).unwrap();
+            // 関数の中の深さはフレームの先頭から数える。
+            // call は enter のオペランドの数の引数を取り出して戻り値を 1 つ積み、
+            // leave はフレームを捨てて戻り値を 1 つ残す。
+            // xcall は登録したホスト関数の引数の数を取り出して戻り値を 1 つ積む。
+            let (pops, pushes) = match opcode {
+                CALL => {
+                    let target = (address + 1).wrapping_add(operand() as usize);
+                    let arguments = decode_operand(&self.byte_code[target + 1..]).unwrap() as usize;
+                    if depth >= arguments {
+                        pending.push((target, arguments));
+                    }
+                    (arguments, 1)
+                }
+                LEAVE => (depth.max(1), 1),
+                XCALL => match self.host_arguments(operand() as u32) {
+                    Some(arguments) => (arguments, 1),
+                    None => {
+                        return Err(invalid(
+                            address,
+                            format!("host function {} is not registered", operand()),
+                        ))
+                    }
+                },
+                _ => stack_effect(opcode),
+            };
+            if depth < pops {
+                return Err(invalid(address, "stack underflow".to_string()));
+            }
+            if opcode == FETCHL || opcode == STOREL {
+                let index = operand() as usize;
+                if index + pops >= depth {
+                    return Err(invalid(
+                        address,
+                        format!("local {} is outside the frame (depth: {})", index, depth),
+                    ));
+                }
+            }
+            let depth = depth - pops + pushes;
+
+            let next = address + instruction_size(opcode);
+            if opcode == JMP || opcode == JZ {
+                pending.push(((address + 1).wrapping_add(operand() as usize), depth));
+            }
+            if opcode != JMP && opcode != HALT && opcode != EXIT && opcode != RET {
+                pending.push((next, depth));
+            }
+        }
+        Ok(depths)
+    }
+
+    /// 読み込んだ命令列と、各命令を実行する前のスタックの深さ。
+    /// ジャンプ先は命令列の添字。到達しない命令の深さは None。
+    pub fn instructions(&self) -> Result<Vec<(DecodedOp, Option<usize>)>> {
+        let depths = self.stack_depths()?;
+        let (ops, addresses) = decoded::decode(&self.byte_code);
+        Ok(ops
+            .into_iter()
+            .zip(addresses)
+            .map(|(op, address)| (op, depths[address]))
+            .collect())
+    }
+
+    /// 文字列プール
+    pub fn strings(&self) -> &[String] {
+        &self.string_pool
+    }
+
+    /// 1 命令だけ実行する。 halt に達しているか exit で止まっていれば、何もせずに false を返す。
+    /// Engine に関わらずバイトコードを解釈する。
+    pub fn step(&mut self, out: &mut dyn Write) -> Result<bool> {
+        if self.exit_code.is_some() {
+            return Ok(false);
+        }
+        if self.stack_pending {
+            self.check_stack()?;
+        }
+        self.step_bytecode(out)
+    }
+
+    /// 終了コード。 exit 命令で止まったならその値、それ以外は 0。
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code.unwrap_or(0)
+    }
+
+    /// 次に実行する命令のアドレス
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// 整数の値の幅
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// 変数の値。添字はコード生成で割り当てた番号。
+    pub fn data(&self) -> &[i64] {
+        &self.data
+    }
+
+    /// 変数の値の種類。添字は data と同じ。
+    pub fn data_tags(&self) -> &[Tag] {
+        &self.data_tags
+    }
+
+    /// index 番目の変数の値。文字列なら引用符で囲む。
+    pub fn format_data(&self, index: usize) -> String {
+        self.format_value(self.data[index], self.data_tags[index])
+    }
+
+    fn format_value(&self, value: i64, tag: Tag) -> String {
+        match (tag, self.string_pool.get(value as usize)) {
+            (Tag::Str, Some(s)) if value >= 0 => format!("{:?}", s),
+            (Tag::Float, _) => Float::from_bits(value as i32).to_string(),
+            _ => value.to_string(),
+        }
+    }
+
+    /// 変数 name の値。アセンブリに変数名の表がなければ None。
+    pub fn variable(&self, name: &str) -> Option<i64> {
+        self.symbols
+            .get(name)
+            .and_then(|&index| self.data.get(index).copied())
+    }
+
+    /// text を書き出す。書き込みのエラーは出力の方針に従って扱う。
+    /// 出力のバイト数が上限を超えるなら、何も書き出さずにエラーにする。
+    #[inline(always)]
+    fn write(&self, out: &mut dyn Write, text: &str) -> Result<()> {
+        let written = self.written.get();
+        self.limits
+            .check_output(ErrorKind::VirtualMachineError, written, text.len())?;
+        self.written.set(written + text.len());
+        self.output.write(
+            ErrorKind::VirtualMachineError,
+            out.write_all(text.as_bytes()),
+        )
+    }
+
+    fn execute_bytecode(&mut self, out: &mut dyn Write) -> Result<()> {
+        loop {
+            // エラーには、それを起こした命令の先頭のアドレスの行を付ける
+            let address = self.pc;
+            match self.step_bytecode(out) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) => return Err(self.locate(address, e)),
+            }
+        }
+    }
+
+    /// 1 命令実行する。halt と exit なら pc をその命令に置いたまま false を返す。
+    #[inline(always)]
+    fn step_bytecode(&mut self, out: &mut dyn Write) -> Result<bool> {
+        if self.trace.is_some() {
+            self.write_trace()?;
+        }
+        let opcode = self.fetch_opcode()?;
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(self.pc, opcode, self.sp);
+        }
+        self.last_opcode = Some(opcode);
+        self.retired += 1;
+        self.pc += 1;
+
+        match opcode {
+            FETCH => {
+                let index = self.get_integer()?;
+                self.stack[self.sp] = self.data[index as usize];
+                self.tags[self.sp] = self.data_tags[index as usize];
+                self.sp += 1;
+                self.pc += 4;
+            }
+            STORE => {
+                let v = self.stack[self.sp - 1];
+                self.sp -= 1;
+                let index = self.get_integer()?;
+                self.data[index as usize] = v;
+                self.data_tags[index as usize] = self.tags[self.sp];
+                self.pc += 4;
+            }
+            PUSH => {
+                let v = self.get_integer()?;
+                self.stack[self.sp] = v as i64;
+                self.tags[self.sp] = Tag::Int;
+                self.sp += 1;
+                self.pc += 4;
+            }
+            PUSHW => {
+                let v = self.get_wide_integer()?;
+                self.stack[self.sp] = v;
+                self.tags[self.sp] = Tag::Int;
+                self.sp += 1;
+                self.pc += 8;
+            }
+            PUSHS => {
+                let v = self.get_integer()?;
+                self.stack[self.sp] = v as i64;
+                self.tags[self.sp] = Tag::Str;
+                self.sp += 1;
+                self.pc += 4;
+            }
+            JMP => {
+                let offset = self.get_integer()?;
+                self.pc = self.pc.wrapping_add(offset as usize);
+            }
+            JZ => {
+                let condition = self.stack[self.sp - 1];
+                self.sp -= 1;
+
+                if condition == 0 {
+                    let offset = self.get_integer()?;
+                    self.pc = self.pc.wrapping_add(offset as usize);
+                } else {
+                    self.pc += 4;
+                }
+            }
+            ADD => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.width.add(operand0, operand1);
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            SUB => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.width.sub(operand0, operand1);
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            MUL => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.width.mul(operand0, operand1);
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            DIV => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.divide(DIV, operand0, operand1)?;
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            MOD | POW => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.divide(opcode, operand0, operand1)?;
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            LT => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 < operand1 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            GT => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 > operand1 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            LE => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 <= operand1 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            GE => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 >= operand1 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            EQ => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 == operand1 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            NE => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 != operand1 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            AND => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 != 0 && operand1 != 0 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            OR => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = if operand0 != 0 || operand1 != 0 { 1 } else { 0 };
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            BAND => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = operand0 & operand1;
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            BOR => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = operand0 | operand1;
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            BXOR => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = operand0 ^ operand1;
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            SHL => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.width.shl(operand0, operand1);
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            SHR => {
+                let operand0 = self.stack[self.sp - 2];
+                let operand1 = self.stack[self.sp - 1];
+                self.stack[self.sp - 2] = self.width.shr(operand0, operand1);
+                self.tags[self.sp - 2] = Tag::Int;
+                self.sp -= 1;
+            }
+            NEG => {
+                self.stack[self.sp - 1] = self.width.neg(self.stack[self.sp - 1]);
+                self.tags[self.sp - 1] = Tag::Int;
+            }
+            NOT => {
+                self.stack[self.sp - 1] = if self.stack[self.sp - 1] == 0 { 1 } else { 0 };
+                self.tags[self.sp - 1] = Tag::Int;
+            }
+            PRTC => {
+                match to_char(self.stack[self.sp - 1]) {
+                    Some(c) => self.write(out, &c.to_string())?,
+                    None => {
+                        return Err(CompileError::new(
+                            ErrorKind::VirtualMachineError,
+                            format!("illegal character value: {}", self.stack[self.sp - 1]),
+                        ));
+                    }
+                }
+                self.sp -= 1;
+            }
+            PRTI => {
+                self.write(out, &self.stack[self.sp - 1].to_string())?;
+                self.sp -= 1;
+            }
+            PRTIW => {
+                let width = self.get_integer()?;
+                self.write(out, &pad_integer(self.stack[self.sp - 1], width))?;
+                self.sp -= 1;
+                self.pc += 4;
+            }
+            PRTS => {
+                let index = self.stack[self.sp - 1];
+                let s = match self.string_pool.get(index as usize) {
+                    Some(s) if index >= 0 => s,
+                    _ => {
+                        return Err(CompileError::new(
+                            ErrorKind::VirtualMachineError,
+                            format!("illegal string index: {}", index),
+                        ))
+                    }
+                };
+                self.write(out, s)?;
+                self.sp -= 1;
+            }
+            HALT => {
+                self.pc -= 1;
+                return Ok(false);
+            }
+            ASSERT => {
+                self.sp -= 2;
+                self.check_assertion(self.stack[self.sp], self.stack[self.sp + 1])?;
+            }
+            EXIT => {
+                self.sp -= 1;
+                self.exit_code = Some(self.stack[self.sp] as i32);
+                self.pc -= 1;
+                return Ok(false);
+            }
+            GETC => {
+                self.stack[self.sp] = self.read_char()?;
+                self.tags[self.sp] = Tag::Int;
+                self.sp += 1;
+            }
+            GETI => {
+                self.stack[self.sp] = self.read_int()?;
+                self.tags[self.sp] = Tag::Int;
+                self.sp += 1;
+            }
+            FETCHI => {
+                let index = self.data_index(self.stack[self.sp - 1])?;
+                self.stack[self.sp - 1] = self.data[index];
+                self.tags[self.sp - 1] = self.data_tags[index];
+            }
+            STOREI => {
+                let index = self.data_index(self.stack[self.sp - 2])?;
+                self.data[index] = self.stack[self.sp - 1];
+                self.data_tags[index] = self.tags[self.sp - 1];
+                self.sp -= 2;
+            }
+            RAND => {
+                self.stack[self.sp - 1] = self.rand(self.stack[self.sp - 1])?;
+                self.tags[self.sp - 1] = Tag::Int;
+            }
+            CLOCK => {
+                self.stack[self.sp] = self.clock();
+                self.tags[self.sp] = Tag::Int;
+                self.sp += 1;
+            }
+            PUSHF => {
+                let v = self.get_integer()?;
+                self.stack[self.sp] = v as i64;
+                self.tags[self.sp] = Tag::Float;
+                self.sp += 1;
+                self.pc += 4;
+            }
+            FADD => self.float_op(|a, b| a + b),
+            FSUB => self.float_op(|a, b| a - b),
+            FMUL => self.float_op(|a, b| a * b),
+            FDIV => self.float_op(|a, b| a / b),
+            FLT => self.float_compare(|a, b| a < b),
+            FGT => self.float_compare(|a, b| a > b),
+            FLE => self.float_compare(|a, b| a <= b),
+            FGE => self.float_compare(|a, b| a >= b),
+            FEQ => self.float_compare(|a, b| a == b),
+            FNE => self.float_compare(|a, b| a != b),
+            FNEG | ITOF | FTOI => self.convert(opcode),
+            PRTF => {
+                let value = float_value(self.stack[self.sp - 1]);
+                self.write(out, &format_float(value))?;
+                self.sp -= 1;
+            }
+            CALL => {
+                let offset = self.get_integer()?;
+                self.push_return(self.pc + 4)?;
+                self.pc = self.pc.wrapping_add(offset as usize);
+            }
+            RET => self.pc = self.pop_return()?,
+            ENTER => {
+                let arguments = self.get_integer()?;
+                self.enter(arguments as usize)?;
+                self.pc += 4;
+            }
+            LEAVE => self.leave()?,
+            XCALL => {
+                let id = self.get_integer()?;
+                self.external_call(id as u32)?;
+                self.pc += 4;
+            }
+            FETCHL => {
+                let index = self.fp + self.get_integer()? as usize;
+                self.stack[self.sp] = self.stack[index];
+                self.tags[self.sp] = self.tags[index];
+                self.sp += 1;
+                self.pc += 4;
+            }
+            STOREL => {
+                let index = self.fp + self.get_integer()? as usize;
+                self.sp -= 1;
+                self.stack[index] = self.stack[self.sp];
+                self.tags[index] = self.tags[self.sp];
+                self.pc += 4;
+            }
+            _ => {
+                return Err(CompileError::new(
+                    ErrorKind::VirtualMachineError,
+                    format!("illegal instruction: {}", opcode),
+                ))
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// スタックや data の値を f32 のビット列として読む
+#[inline(always)]
+fn float_value(value: i64) -> f32 {
+    Float::from_bits(value as i32).value()
+}
+
+/// prtc で出力する文字。文字のコードでなければ None。
+#[inline(always)]
+fn to_char(value: i64) -> Option<char> {
+    u32::try_from(value).ok().and_then(std::char::from_u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexical_analyzer::output::ClosedAfter;
+    use lexical_analyzer::snapshot::assert_snapshot;
+
+    /// どちらの Engine でも同じ出力になることを確かめる
+    fn interpret(s: &str, out: &mut Vec<u8>) {
+        let mut vm = VirtualMachineInterpreter::load_with(s.lines(), Engine::Bytecode).unwrap();
+        vm.execute(out).unwrap();
+
+        let mut decoded_out = Vec::new();
+        let mut vm = VirtualMachineInterpreter::load_with(s.lines(), Engine::Decoded).unwrap();
+        vm.execute(&mut decoded_out).unwrap();
+        assert_eq!(out, &decoded_out);
+    }
+
+    #[test]
+    fn test_case_4() {
+        let s = r#"Datasize: 1 Strings: 2
+"count is: "
+"\n"
+    0 push  1
+    5 store [0]
+   10 fetch [0]
+   15 push  10
+   20 lt
+   21 jz     (43) 65
+   26 push  0
+   31 prts
+   32 fetch [0]
+   37 prti
+   38 push  1
+   43 prts
+   44 fetch [0]
+   49 push  1
+   54 add
+   55 store [0]
+   60 jmp    (-51) 10
+   65 halt"#
+            .to_string();
+        let mut out: Vec<u8> = Vec::new();
+
+        interpret(&s, &mut out);
+        println!("{:?}", out);
+    }
+
+    #[test]
+    fn test_labels() {
+        let s = r#"Datasize: 1 Strings: 0
+; line 1: i = 1;
+    push 1
+    store [0]
+loop:  ; line 2
+    fetch [0]
+    push 4
+    lt
+    jz end
+    fetch [0]
+    prti
+    fetch [0]
+    push 1
+    add
+    store [0]
+    jmp loop
+end: halt
+"#;
+        let mut out = Vec::new();
+        interpret(s, &mut out);
+        assert_eq!(b"123", &out[..]);
+
+        let s = "Datasize: 0 Strings: 0\n    jmp nowhere\n    halt\n";
+        assert!(VirtualMachineInterpreter::load(s.lines()).is_err());
+
+        let s = "Datasize: 0 Strings: 0\na: halt\na: halt\n";
+        assert!(VirtualMachineInterpreter::load(s.lines()).is_err());
+
+        let s = "Datasize: 0 Strings: 0\n0 push\n";
+        assert!(VirtualMachineInterpreter::load(s.lines()).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let load = |s: &str| VirtualMachineInterpreter::load(s.lines());
+
+        assert!(load("Datasize: 1 Strings: 1\n\"a\"\npush 0\nprts\nfetch [0]\nhalt\n").is_ok());
+
+        // 命令の途中へのジャンプ
+        match load("Datasize: 0 Strings: 0\n0 jmp (1) 2\n5 halt\n") {
+            Err(e) => assert!(e.to_string().contains("0: jump target 2")),
+            Ok(_) => panic!("jump into an operand is accepted"),
+        }
+        // data の範囲外
+        assert!(load("Datasize: 1 Strings: 0\nfetch [1]\nhalt\n").is_err());
+        assert!(load("Datasize: 1 Strings: 0\nstore [-1]\nhalt\n").is_err());
+        // 文字列の範囲外
+        assert!(load("Datasize: 0 Strings: 0\npush 0\nprts\nhalt\n").is_err());
+        // halt で終わらない
+        assert!(load("Datasize: 0 Strings: 0\npush 0\n").is_err());
+        assert!(load("Datasize: 0 Strings: 0\n").is_err());
+    }
+
+    #[test]
+    fn test_prtiw() {
+        // 掛け算の表のように、幅を揃えて並べる
+        let s = r#"Datasize: 0 Strings: 1
+"|"
+    push 7
+    prtiw 3
+    push -12
+    prtiw 4
+    push 12345
+    prtiw 2
+    push 0
+    prts
+    push 5
+    prtiw -3
+    push 0
+    prts
+    halt
+"#;
+        let mut out = Vec::new();
+        interpret(s, &mut out);
+        assert_eq!(b"  7 -1212345|5  |", &out[..]);
+
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert_eq!(
+            (DecodedOp::PrtiWidth(3), Some(1)),
+            vm.instructions().unwrap()[1]
+        );
+
+        let e = VirtualMachineInterpreter::load(
+            "Datasize: 0 Strings: 0
+push 1
+prtiw 1025
+halt
+"
+            .lines(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            "5: field width 1025 is out of range (limit: 1024)",
+            e.message()
+        );
+        assert!(VirtualMachineInterpreter::load(
+            "Datasize: 0 Strings: 0
+prtiw
+halt
+"
+            .lines()
+        )
+        .is_err());
+
+        assert_eq!("-3", pad_integer(-3, 0));
+        assert_eq!("42  ", pad_integer(42, -4));
+    }
+
+    #[test]
+    fn test_width() {
+        let s = r#"Datasize: 0 Strings: 0 Stack: 2 Width: 64
+    pushw 4294967296
+    push 3
+    mul
+    prti
+    push 2147483647
+    push 1
+    add
+    prti
+    halt
+"#;
+        let mut out = Vec::new();
+        interpret(s, &mut out);
+        assert_eq!(b"128849018882147483648", &out[..]);
+
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert_eq!(Width::W64, vm.width());
+        let instructions = vm.instructions().unwrap();
+        assert_eq!((DecodedOp::Push(1 << 32), Some(0)), instructions[0]);
+        assert_eq!((DecodedOp::Push(3), Some(1)), instructions[1]);
+        // バイトコードのファイルにも幅を書く
+        let vm = VirtualMachineInterpreter::load_bytes(&vm.to_bytes()).unwrap();
+        assert_eq!(Width::W64, vm.width());
+
+        // 32 ビットの幅では折り返し、 pushw は使えない
+        let s = s
+            .replace(" Width: 64", "")
+            .replace("pushw 4294967296", "push 1");
+        let mut out = Vec::new();
+        interpret(&s, &mut out);
+        assert_eq!(b"3-2147483648", &out[..]);
+        let e = VirtualMachineInterpreter::load(
+            "Datasize: 0 Strings: 0\npushw 1\nprti\nhalt\n".lines(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!("0: pushw needs \"Width: 64\" in the header", e.message());
+        let e = VirtualMachineInterpreter::load("Datasize: 0 Strings: 0 Width: 16\nhalt\n".lines())
+            .err()
+            .unwrap();
+        assert_eq!("invalid width: 16", e.message());
+        assert!(VirtualMachineInterpreter::load(
+            "Datasize: 0 Strings: 0 Width: 64 Width: 64\nhalt\n".lines()
+        )
+        .is_err());
+
+        for engine in [Engine::Bytecode, Engine::Decoded] {
+            let s = "Datasize: 0 Strings: 0\npush 1\npush 0\ndiv\nprti\nhalt\n";
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let e = vm.execute(&mut Vec::new()).unwrap_err();
+            assert_eq!("division by zero", e.message());
+            assert_eq!(None, e.line());
+
+            // "; line" のコメントがあれば、実行時のエラーにソースの行が付く
+            let s = "Datasize: 0 Strings: 0\n; line 1: x = 1;\npush 1\nprti\n\
+                     ; line 2: print(1 / 0);\npush 1\npush 0\ndiv\nprti\nhalt\n";
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let e = vm.execute(&mut Vec::new()).unwrap_err();
+            assert_eq!(Some(2), e.line());
+
+            // set_lines で対応を渡すこともできる
+            let s = "Datasize: 0 Strings: 0\npush 1\nprti\npush 1\npush 0\ndiv\nprti\nhalt\n";
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            vm.set_lines(vec![(0, 3), (6, 4), (17, 5)]);
+            let e = vm.execute(&mut Vec::new()).unwrap_err();
+            assert_eq!(Some(4), e.line());
+        }
+
+        // 読み込みのエラーにはアセンブリの行が付く
+        let s = "Datasize: 0 Strings: 1\n\"a\"\npush 1\nbogus\nhalt\n";
+        let e = VirtualMachineInterpreter::load(s.lines()).err().unwrap();
+        assert_eq!(Some(4), e.line());
+    }
+
+    #[test]
+    fn test_floats() {
+        let s = r#"Datasize: 1 Strings: 1
+" "
+    pushf 1.5e3
+    push 4
+    itof
+    fdiv
+    store [0]
+    fetch [0]
+    prtf
+    push 0
+    prts
+    fetch [0]
+    fneg
+    ftoi
+    prti
+    push 0
+    prts
+    fetch [0]
+    pushf 375
+    fge
+    prti
+    push 0
+    prts
+    pushf 1
+    pushf 0
+    fdiv
+    prtf
+    halt
+"#;
+        let mut out = Vec::new();
+        interpret(s, &mut out);
+        assert_eq!(b"375.0 -375 1 inf", &out[..]);
+
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert_eq!(
+            (DecodedOp::PushF(Float(1500.0)), Some(0)),
+            vm.instructions().unwrap()[0]
+        );
+        assert!(VirtualMachineInterpreter::load(
+            "Datasize: 0 Strings: 0
+pushf x
+halt
+"
+            .lines()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_pc_out_of_range() {
+        // validate を通さずに実行しても panic せず、場所と直前の命令を伝える
+        let run = |byte_code: Vec<u8>| {
+            let mut vm =
+                VirtualMachineInterpreter::new(byte_code, vec![], 0, HashMap::new(), Width::W32);
+            vm.execute(&mut Vec::new()).err().unwrap().message()
+        };
+
+        assert_eq!(
+            "pc 102 is out of range (code size: 6, last opcode: jmp)",
+            run([[JMP].as_slice(), &encode_operand(101), &[HALT]].concat())
+        );
+        assert_eq!(
+            "pc 5 is out of range (code size: 5, last opcode: push)",
+            run(vec![PUSH, 1, 0, 0, 0])
+        );
+        assert_eq!(
+            "pc 0 is out of range (code size: 0, last opcode: none)",
+            run(vec![])
+        );
+        assert_eq!(
+            "5: operand of push is truncated (code size: 8)",
+            run(vec![PUSH, 1, 0, 0, 0, PUSH, 2, 0])
+        );
+    }
+
+    #[test]
+    fn test_input() {
+        // 入力をそのまま出力する
+        let s = r#"Datasize: 1 Strings: 0
+loop:
+    push 0
+    getc
+    storei
+    push 0
+    fetchi
+    push 0
+    lt
+    jz echo
+    jmp end
+echo:
+    fetch [0]
+    prtc
+    jmp loop
+end:
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            vm.set_input(Box::new(&b"abc"[..]));
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!(b"abc", &out[..]);
+        }
+
+        let s = "Datasize: 1 Strings: 0\npush 1\nfetchi\nhalt\n";
+        let mut vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert!(vm.execute(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_geti() {
+        // A+B
+        let s = "Datasize: 0 Strings: 0\ngeti\ngeti\nadd\nprti\ngetc\nprti\nhalt\n";
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            vm.set_input(Box::new(&b" 2 -5\n"[..]));
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            // 数字の直後の改行は getc で読める
+            assert_eq!(b"-310", &out[..]);
+        }
+
+        let mut out = Vec::new();
+        VirtualMachineInterpreter::interpret(s.lines(), Box::new(&b"40 2"[..]), &mut out).unwrap();
+        assert_eq!(b"42-1", &out[..]);
+
+        let e = VirtualMachineInterpreter::interpret(s.lines(), Box::new(&b"1 a"[..]), &mut out)
+            .err()
+            .unwrap();
+        assert!(matches!(e.kind(), ErrorKind::VirtualMachineError));
+        assert_eq!("input error: expected an integer, found 'a'", e.message());
+    }
+
+    #[test]
+    fn test_closed_output() {
+        let s = r#"Datasize: 1 Strings: 0
+    push 1
+    prti
+    push 2
+    prti
+    push 3
+    prti
+    push 42
+    store [0]
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let run = |policy| {
+                let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+                vm.set_output_policy(policy);
+                let mut out = ClosedAfter::new(2);
+                let result = vm.execute(&mut out);
+                assert_eq!(b"12", &out.written[..]);
+                (result, vm.data()[0])
+            };
+
+            let (result, _) = run(OutputPolicy::Fail);
+            let e = result.unwrap_err();
+            assert!(e.is_output_closed());
+            assert!(e.message().starts_with("output error: "));
+
+            // Stop は書けなくなったところで正常に終わる
+            let (result, x) = run(OutputPolicy::Stop);
+            assert!(result.is_ok());
+            assert_eq!(0, x);
+
+            // Discard は最後まで実行する
+            let (result, x) = run(OutputPolicy::Discard);
+            assert!(result.is_ok());
+            assert_eq!(42, x);
+        }
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let s = r#"Datasize: 0 Strings: 0
+    push 12
+    push 10
+    band
+    prti
+    push 12
+    push 10
+    bor
+    prti
+    push 12
+    push 10
+    bxor
+    prti
+    push 1
+    push 4
+    shl
+    prti
+    push -16
+    push 2
+    shr
+    prti
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!("814616-4", String::from_utf8(out).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_pow() {
+        let s = r#"Datasize: 0 Strings: 0
+    push 3
+    push 4
+    pow
+    prti
+    push 2
+    push 31
+    pow
+    prti
+    push -2
+    push -1
+    pow
+    prti
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!("81-21474836480", String::from_utf8(out).unwrap());
+
+            let s = "Datasize: 0 Strings: 0\npush 0\npush -1\npow\nprti\nhalt\n";
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let e = vm.execute(&mut Vec::new()).unwrap_err();
+            assert_eq!("division by zero", e.message());
+        }
+    }
+
+    #[test]
+    fn test_rand() {
+        let s = r#"Datasize: 0 Strings: 0
+    push 100
+    rand
+    prti
+    push 100
+    rand
+    prti
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!("3858", String::from_utf8(out).unwrap());
+
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            vm.set_seed(7);
+            let mut seeded = Vec::new();
+            vm.execute(&mut seeded).unwrap();
+            assert_ne!(b"3858", &seeded[..]);
+
+            let s = "Datasize: 0 Strings: 0\n    push 0\n    rand\n    halt\n";
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            assert!(vm.execute(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let s = "Datasize: 1 Strings: 0\n0 push 7\n5 store [0]\n10 fetch [0]\n15 prti\n16 halt\n";
+        let mut vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(0, vm.pc());
+        assert!(vm.step(&mut out).unwrap());
+        assert_eq!(5, vm.pc());
+        assert!(vm.step(&mut out).unwrap());
+        assert_eq!(&[7], vm.data());
+        while vm.step(&mut out).unwrap() {}
+        assert_eq!(16, vm.pc());
+        assert_eq!(b"7", &out[..]);
+        // halt の後は進まない
+        assert!(!vm.step(&mut out).unwrap());
+        assert_eq!(16, vm.pc());
+    }
+
+    #[test]
+    fn test_assert() {
+        let s = r#"Datasize: 0 Strings: 1
+"assertion failed at line 2: too big"
+    push 1
+    push 0
+    assert
+    push 7
+    prti
+    push 0
+    push 0
+    assert
+    push 8
+    prti
+    halt
+"#;
+        for engine in [Engine::Bytecode, Engine::Decoded].iter() {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), *engine).unwrap();
+            let mut out = Vec::new();
+            let e = vm.execute(&mut out).unwrap_err();
+            assert_eq!("assertion failed at line 2: too big", e.message());
+            assert_eq!(b"7", &out[..]);
+        }
+
+        let s = "Datasize: 0 Strings: 1\n\"a\"\npush 1\npush 1\nassert\nhalt\n";
+        assert!(VirtualMachineInterpreter::load(s.lines()).is_err());
+    }
+
+    #[test]
+    fn test_string_values() {
+        let s = r#"Datasize: 2 Strings: 2
+"abc"
+"de"
+    pushs 0
+    store [0]
+    fetch [0]
+    store [1]
+    push 1
+    prts
+    fetch [1]
+    prts
+    push 1
+    store [1]
+    halt
+"#;
+        for engine in [Engine::Bytecode, Engine::Decoded].iter() {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), *engine).unwrap();
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            assert_eq!(b"deabc", &out[..]);
+            assert_eq!(&[Tag::Str, Tag::Int], vm.data_tags());
+            assert_eq!("\"abc\"", vm.format_data(0));
+            assert_eq!("1", vm.format_data(1));
+        }
+
+        let s = "Datasize: 0 Strings: 1\n\"a\"\npushs 1\nprts\nhalt\n";
+        let e = VirtualMachineInterpreter::load(s.lines()).err().unwrap();
+        assert_eq!(
+            "0: string index 1 is out of range (strings: 1)",
+            e.message()
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let s = r#"Datasize: 0 Strings: 0
+    push 1
+    prti
+    push 3
+    exit
+    push 2
+    prti
+    halt
+"#;
+        for engine in [Engine::Bytecode, Engine::Decoded].iter() {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), *engine).unwrap();
+            let mut out = Vec::new();
+            assert_eq!(0, vm.exit_code());
+            vm.execute(&mut out).unwrap();
+            assert_eq!(b"1", &out[..]);
+            assert_eq!(3, vm.exit_code());
+            // 止まった後は進まない
+            vm.execute(&mut out).unwrap();
+            assert!(!vm.step(&mut out).unwrap());
+            assert_eq!(b"1", &out[..]);
+        }
+
+        let mut vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        while vm.step(&mut Vec::new()).unwrap() {}
+        assert_eq!(11, vm.pc());
+        assert_eq!(3, vm.exit_code());
+
+        // exit の後に続く命令はスタックの深さに数えない
+        assert_eq!(1, vm.max_stack_depth().unwrap());
+        assert!(
+            VirtualMachineInterpreter::load("Datasize: 0 Strings: 0\nexit\nhalt\n".lines())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let s = r#"Datasize: 2 Strings: 0
+0 push 7
+5 store [1]
+10 halt
+; line 3: this comment is not a symbol
+; Symbols:
+; 0 a
+; 1 b
+"#;
+        let mut vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        vm.execute(&mut Vec::new()).unwrap();
+        assert_eq!(Some(0), vm.variable("a"));
+        assert_eq!(Some(7), vm.variable("b"));
+        assert_eq!(None, vm.variable("c"));
+
+        let s = "Datasize: 0 Strings: 0\nhalt\n; Symbols:\n; a\n";
+        assert!(VirtualMachineInterpreter::load(s.lines()).is_err());
+    }
+
+    #[test]
+    fn test_stack_depth() {
+        let load = |s: &str| VirtualMachineInterpreter::load(s.lines());
+
+        let s = "Datasize: 0 Strings: 0\npush 1\npush 2\npush 3\nmul\nadd\nprti\nhalt\n";
+        assert_eq!(3, load(s).unwrap().max_stack_depth().unwrap());
+        // ループの先頭で合流しても深さは変わらない
+        let s = "Datasize: 1 Strings: 0\nloop: fetch [0]\njz end\npush 0\nstore [0]\njmp loop\nend: halt\n";
+        assert_eq!(1, load(s).unwrap().max_stack_depth().unwrap());
+
+        // ヘッダの深さが足りない
+        let s = "Datasize: 0 Strings: 0 Stack: 1\npush 1\npush 2\nadd\nprti\nhalt\n";
+        assert!(load(s).is_err());
+        let s = "Datasize: 0 Strings: 0 Stack: 2\npush 1\npush 2\nadd\nprti\nhalt\n";
+        assert!(load(s).is_ok());
+
+        match load("Datasize: 0 Strings: 0\npush 1\nadd\nhalt\n") {
+            Err(e) => assert!(e.to_string().contains("5: stack underflow")),
+            Ok(_) => panic!("stack underflow is accepted"),
+        }
+        // 回るたびに深くなるループ
+        assert!(load("Datasize: 0 Strings: 0\nloop: push 1\njmp loop\nhalt\n").is_err());
+
+        let mut s = "Datasize: 0 Strings: 0\n".to_string();
+        s += &"push 1\n".repeat(STACK_SIZE + 1);
+        s += "halt\n";
+        match load(&s) {
+            Err(e) => assert!(e.to_string().contains("stack overflow")),
+            Ok(_) => panic!("stack overflow is accepted"),
+        }
+        // ヘッダの深さだけスタックを確保する
+        let s = s.replacen(
+            "Strings: 0",
+            &format!("Strings: 0 Stack: {}", STACK_SIZE + 1),
+            1,
+        );
+        assert!(load(&s).is_ok());
+        let s = format!(
+            "Datasize: 0 Strings: 0 Stack: {}\nhalt\n",
+            MAX_STACK_SIZE + 1
+        );
+        assert_eq!(
+            format!(
+                "stack size {} exceeds the limit of {}",
+                MAX_STACK_SIZE + 1,
+                MAX_STACK_SIZE
+            ),
+            load(&s).err().unwrap().message()
+        );
+    }
+
+    #[test]
+    fn test_call() {
+        // ack(m, n) を再帰で計算する。引数はフレームの 0 番と 1 番
+        let ackermann = |m: i32, n: i32| {
+            format!(
+                r#"Datasize: 0 Strings: 0
+    push {}
+    push {}
+    call ack
+    prti
+    halt
+ack:
+    enter 2
+    fetchl 0
+    jz m_zero
+    fetchl 1
+    jz n_zero
+    ; ack(m - 1, ack(m, n - 1))
+    fetchl 0
+    push 1
+    sub
+    fetchl 0
+    fetchl 1
+    push 1
+    sub
+    call ack
+    call ack
+    leave
+    ret
+m_zero:
+    fetchl 1
+    push 1
+    add
+    leave
+    ret
+n_zero:
+    ; ack(m - 1, 1)
+    fetchl 0
+    push 1
+    sub
+    push 1
+    call ack
+    leave
+    ret
+"#,
+                m, n
+            )
+        };
+        let mut out = Vec::new();
+        interpret(&ackermann(2, 3), &mut out);
+        assert_eq!(b"9", &out[..]);
+        let mut out = Vec::new();
+        interpret(&ackermann(3, 3), &mut out);
+        assert_eq!(b"61", &out[..]);
+
+        // storel で引数を書き換え、 leave は値の種類も残す
+        let s = r#"Datasize: 0 Strings: 1
+"a"
+    pushs 0
+    push 0
+    call f
+    prts
+    halt
+f:
+    enter 2
+    fetchl 0
+    storel 1
+    push 99
+    fetchl 1
+    leave
+    ret
+"#;
+        let mut out = Vec::new();
+        interpret(s, &mut out);
+        assert_eq!(b"a", &out[..]);
+        // 関数の中の深さはフレームの先頭から数える
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert_eq!(4, vm.max_stack_depth().unwrap());
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let load = |s: &str| VirtualMachineInterpreter::load(s.lines());
+        let load_error = |s: &str| match load(s) {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("accepted: {}", s),
+        };
+        let run = |s: &str| {
+            let mut vm = load(s).ok().unwrap();
+            vm.execute(&mut Vec::new()).unwrap_err().to_string()
+        };
+
+        // 呼び出し先は enter で始まる
+        let e = load_error("Datasize: 0 Strings: 0\ncall f\nhalt\nf: push 1\nret\n");
+        assert!(e.contains("does not start with enter"), "{}", e);
+        // 引数が足りない
+        let e = load_error("Datasize: 0 Strings: 0\ncall f\nhalt\nf: enter 1\nleave\nret\n");
+        assert!(e.contains("stack underflow"), "{}", e);
+        // フレームの外の値は読めない
+        let e = load_error(
+            "Datasize: 0 Strings: 0\npush 1\ncall f\nhalt\nf: enter 1\nfetchl 1\nleave\nret\n",
+        );
+        assert!(e.contains("outside the frame"), "{}", e);
+        assert!(
+            load("Datasize: 0 Strings: 0\npush 1\ncall f\nhalt\nf: enter -1\nleave\nret\n")
+                .is_err()
+        );
 
-    fn assemble(mut lines: Lines) -> Result<Self> {
-        let header;
+        assert!(run("Datasize: 0 Strings: 0\nret\nhalt\n").contains("ret without call"));
+        assert!(
+            run("Datasize: 0 Strings: 0\npush 1\nleave\nhalt\n").contains("leave without enter")
+        );
+        // 終わらない再帰
+        let e = run("Datasize: 0 Strings: 0\ncall f\nhalt\nf: enter 0\ncall f\nleave\nret\n");
+        assert!(e.contains("call stack overflow"), "{}", e);
+        let e = run("Datasize: 0 Strings: 0\npush 1\ncall f\nhalt\nf: enter 1\nfetchl 0\ncall f\nleave\nret\n");
+        assert!(e.contains("stack overflow"), "{}", e);
+    }
 
-        if let Some(line) = lines.next() {
-            header = Self::read_header(line)?;
-        } else {
-            return Err(CompileError::new(
-                ErrorKind::VirtualMachineError,
-                "empty file",
-            ));
-        }
+    #[test]
+    fn test_operand_literals() {
+        let error = |s: &str| {
+            VirtualMachineInterpreter::load(s.lines())
+                .err()
+                .unwrap()
+                .message()
+        };
 
-        let mut string_pool: Vec<String> = Vec::new();
-        for _ in 0..header.string_size {
-            if let Some(line) = lines.next() {
-                string_pool.push(Self::read_string(line)?);
-            } else {
-                return Err(CompileError::new(
-                    ErrorKind::VirtualMachineError,
-                    "unexpected EOF",
-                ));
-            }
-        }
+        let s = "Datasize: 0 Strings: 0 Width: 64\npush -2147483648\nprti\npush +2147483647\nprti\npushw -9223372036854775808\nprti\nhalt\n";
+        let mut out = Vec::new();
+        interpret(s, &mut out);
+        assert_eq!(b"-21474836482147483647-9223372036854775808", &out[..]);
 
-        let mut byte_code: Vec<u8> = Vec::new();
-        for line in lines {
-            let line = line.trim();
-            if line.len() == 0 {
-                // empty line
-                continue;
-            }
+        assert_eq!(
+            "operand of push is out of range: 2147483648 (range: -2147483648..=2147483647)",
+            error("Datasize: 0 Strings: 0\npush 2147483648\nhalt\n")
+        );
+        assert_eq!(
+            "operand of fetch is out of range: -2147483649 (range: -2147483648..=2147483647)",
+            error("Datasize: 1 Strings: 0\nfetch [-2147483649]\nhalt\n")
+        );
+        assert_eq!(
+            "operand of pushw is out of range: 9223372036854775808 (range: -9223372036854775808..=9223372036854775807)",
+            error("Datasize: 0 Strings: 0 Width: 64\npushw 9223372036854775808\nhalt\n")
+        );
+        assert_eq!(
+            "cannot convert to integer: 1x",
+            error("Datasize: 0 Strings: 0\npush 1x\nhalt\n")
+        );
+        assert_eq!(
+            "operand of jmp is out of range: 4294967296 (range: -2147483648..=2147483647)",
+            error("Datasize: 0 Strings: 0\njmp (4294967296) 0\nhalt\n")
+        );
+    }
 
-            Self::read_instruction(line, &mut byte_code)?;
-        }
+    #[test]
+    fn test_jump_forms() {
+        // "(offset) address" と、アドレスかラベルだけの形が同じバイトコードになる
+        let forms = [
+            "0 push 1\n5 jz (10) 16\n10 push 2\n15 prti\n16 halt\n",
+            "0 push 1\n5 jz 16\n10 push 2\n15 prti\n16 halt\n",
+            "push 1\njz end\npush 2\nprti\nend: halt\n",
+        ];
+        let byte_code: Vec<Vec<u8>> = forms
+            .iter()
+            .map(|code| {
+                let s = format!("Datasize: 0 Strings: 0\n{}", code);
+                VirtualMachineInterpreter::load(s.lines())
+                    .unwrap()
+                    .byte_code
+            })
+            .collect();
+        assert_eq!(byte_code[0], byte_code[1]);
+        assert_eq!(byte_code[0], byte_code[2]);
 
-        Ok(VirtualMachineInterpreter {
-            pc: 0,
-            sp: 0,
-            byte_code,
-            string_pool,
-            data: vec![0; header.data_size],
-            stack: [0; STACK_SIZE],
-        })
+        // オフセットとアドレスが食い違っていればエラーにする
+        let s = "Datasize: 0 Strings: 0\n0 push 1\n5 jz (10) 99\n10 push 2\n15 prti\n16 halt\n";
+        let e = VirtualMachineInterpreter::load(s.lines()).err().unwrap();
+        assert_eq!(
+            "jump offset and target disagree: 5 jz (10) 99 (offset 10 points to 16, target is 99)",
+            e.message()
+        );
+        let s = "Datasize: 0 Strings: 0\n0 jmp (0) end\nend: halt\n";
+        let e = VirtualMachineInterpreter::load(s.lines()).err().unwrap();
+        assert_eq!("invalid jump target: 0 jmp (0) end", e.message());
+        let s = "Datasize: 0 Strings: 0\njmp 3\nhalt\n";
+        let e = VirtualMachineInterpreter::load(s.lines()).err().unwrap();
+        assert_eq!("0: jump target 3 is not an instruction", e.message());
     }
 
-    fn read_header(line: &str) -> Result<Header> {
-        let data_size: usize;
-        let string_size: usize;
+    #[test]
+    fn test_run_report() {
+        let s = r#"Datasize: 2 Strings: 1
+"s"
+    pushs 0
+    store [1]
+    push 3
+    store [0]
+    fetch [0]
+    exit
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let report = vm.execute(&mut Vec::new()).unwrap();
+            assert_eq!(6, report.steps);
+            assert_eq!(6, report.instructions_retired);
+            assert_eq!(3, report.exit_code);
+            // 変数名の表がなければ番号で示す
+            assert_eq!(
+                vec![
+                    ("[0]".to_string(), "3".to_string()),
+                    ("[1]".to_string(), "\"s\"".to_string()),
+                ],
+                report.variables
+            );
 
-        let sizes: Vec<&str> = line.trim().split_whitespace().collect();
-        if sizes.len() != 4 {
-            Err(CompileError::new(
-                ErrorKind::VirtualMachineError,
-                "invalid datasize format.",
-            ))
-        } else {
-            data_size = sizes[1].parse().or_else(|_| {
-                Err(CompileError::new(
-                    ErrorKind::VirtualMachineError,
-                    "invalid data size",
-                ))
-            })?;
-            string_size = sizes[3].parse().or_else(|_| {
-                Err(CompileError::new(
-                    ErrorKind::VirtualMachineError,
-                    "invalid string data size",
-                ))
-            })?;
-            Ok(Header {
-                data_size,
-                string_size,
-            })
+            let s = format!("{}; Symbols:\n; 0 n\n; 1 name\n", s);
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let report = vm.execute(&mut Vec::new()).unwrap();
+            assert_eq!(Some("3"), report.variable("n"));
+            assert_eq!(Some("\"s\""), report.variable("name"));
         }
     }
 
-    fn read_string(s: &str) -> Result<String> {
-        let mut value = String::new();
-        let mut cs = s.chars();
-        let mut next_char = cs.next();
+    #[test]
+    fn test_header() {
+        let header = |line: &str| VirtualMachineInterpreter::read_header(line);
+        let error = |line: &str| header(line).unwrap_err().message();
 
-        if Some('"') != next_char {
-            return Err(CompileError::new(
-                ErrorKind::VirtualMachineError,
-                "invalid string",
-            ));
-        }
-        next_char = cs.next();
-        loop {
-            match next_char {
-                Some('"') => break,
-                Some('\\') => {
-                    let escape = cs.next();
-                    match escape {
-                        Some('n') => value.push('\n'),
-                        Some('\\') => value.push('\\'),
-                        _ => {
-                            return Err(CompileError::new(
-                                ErrorKind::VirtualMachineError,
-                                "invalid escape",
-                            ))
-                        }
-                    }
-                }
-                Some(c) => value.push(c),
-                None => {
-                    return Err(CompileError::new(
-                        ErrorKind::VirtualMachineError,
-                        "\" not found",
-                    ))
-                }
-            }
-            next_char = cs.next();
+        let h = header("Datasize: 3 Strings: 2").unwrap();
+        assert_eq!(
+            (3, 2, None, Width::W32),
+            (h.data_size, h.string_size, h.stack_size, h.width)
+        );
+        // 空白、大文字小文字、順序の違いと知らない項目
+        for line in [
+            "Datasize:3 Strings:2 Stack:4",
+            "  datasize : 3\tSTRINGS:  2  Stack: 4  ",
+            "Stack: 4 Strings: 2 Registers: 9 DataSize: 3",
+        ] {
+            let h = header(line).unwrap();
+            assert_eq!(
+                (3, 2, Some(4)),
+                (h.data_size, h.string_size, h.stack_size),
+                "{}",
+                line
+            );
         }
+        assert_eq!(
+            Width::W64,
+            header("Width: 64 Datasize: 0 Strings: 0").unwrap().width
+        );
 
-        Ok(value)
+        assert_eq!("missing Datasize in the header", error(""));
+        assert_eq!("missing Strings in the header", error("Datasize: 1"));
+        assert_eq!("invalid data size: x", error("Datasize: x Strings: 0"));
+        assert_eq!("invalid string count: -1", error("Datasize: 0 Strings: -1"));
+        assert_eq!(
+            "invalid stack size: 1.5",
+            error("Datasize: 0 Strings: 0 Stack: 1.5")
+        );
+        assert_eq!(
+            "duplicate stack in the header",
+            error("Datasize: 0 Strings: 0 Stack: 1 stack: 2")
+        );
+        assert_eq!(
+            "expected ':' after Datasize in the header",
+            error("Datasize 1 Strings: 0")
+        );
+        assert_eq!(
+            "missing value for Datasize in the header",
+            error("Datasize: Strings: 0")
+        );
+        assert_eq!(
+            "missing value for Strings in the header",
+            error("Datasize: 0 Strings:")
+        );
+        assert_eq!("missing field name before ':' in the header", error(": 1"));
     }
 
-    fn read_integer(s: &str, dst: &mut Vec<u8>) -> Result<()> {
-        if let Ok(val) = s.parse::<i32>() {
-            dst.extend_from_slice(&val.to_ne_bytes());
-            Ok(())
-        } else {
-            Err(CompileError::new(
-                ErrorKind::VirtualMachineError,
-                format!("cannot convert to integer: {}", s),
-            ))
-        }
-    }
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            data_size: Some(2),
+            string_pool_size: Some(8),
+            output_size: Some(10),
+            recursion_depth: Some(5),
+        };
+        let load = |s: &str, engine| {
+            VirtualMachineInterpreter::load_with_limits(s.lines(), engine, &limits)
+        };
+        let load_error = |s: &str| load(s, Engine::Bytecode).err().unwrap().message();
+
+        assert_eq!(
+            "data size 3 exceeds the limit of 2",
+            load_error("Datasize: 3 Strings: 0\nhalt\n")
+        );
+        assert_eq!(
+            "string pool size 9 bytes exceeds the limit of 8 bytes",
+            load_error("Datasize: 0 Strings: 2\n\"hello\"\n\"abcd\"\n    halt\n")
+        );
 
-    fn read_instruction(s: &str, dst: &mut Vec<u8>) -> Result<()> {
-        let mnemonic: Vec<&str> = s.split_whitespace().collect();
+        // 上限を超える出力は書き出さずに止める
+        let s = r#"Datasize: 0 Strings: 1
+"hello"
+loop: push 0
+    prts
+    jmp loop
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = load(s, engine).ok().unwrap();
+            let mut out = Vec::new();
+            let e = vm.execute(&mut out).unwrap_err();
+            assert_eq!("output exceeds the limit of 10 bytes", e.message());
+            assert_eq!(b"hellohello", &out[..]);
 
-        if mnemonic.len() == 1 {
-            return Err(CompileError::new(
-                ErrorKind::VirtualMachineError,
-                "invalid code",
-            ));
+            // 末尾呼び出しではない再帰
+            let s = "Datasize: 0 Strings: 0\ncall f\nhalt\nf: enter 0\ncall f\nneg\nleave\nret\n";
+            let mut vm = load(s, engine).ok().unwrap();
+            let e = vm.execute(&mut Vec::new()).unwrap_err();
+            assert_eq!("call stack overflow (limit: 5)", e.message());
         }
+    }
 
-        match mnemonic[1] {
-            "fetch" => {
-                dst.push(FETCH);
-                let v = mnemonic[2];
-                Self::read_integer(&v[1..v.len() - 1], dst)?;
-            }
-            "store" => {
-                dst.push(STORE);
-                let v = mnemonic[2];
-                Self::read_integer(&v[1..v.len() - 1], dst)?;
-            }
-            "push" => {
-                dst.push(PUSH);
-                Self::read_integer(mnemonic[2], dst)?;
-            }
-            "jmp" => {
-                dst.push(JMP);
-                let v = mnemonic[2];
-                &Self::read_integer(&v[1..v.len() - 1], dst)?;
-            }
-            "jz" => {
-                dst.push(JZ);
-                let v = mnemonic[2];
-                &Self::read_integer(&v[1..v.len() - 1], dst)?;
-            }
-            "add" => {
-                dst.push(ADD);
-            }
-            "sub" => {
-                dst.push(SUB);
-            }
-            "mul" => {
-                dst.push(MUL);
-            }
-            "div" => {
-                dst.push(DIV);
-            }
-            "mod" => {
-                dst.push(MOD);
-            }
-            "lt" => {
-                dst.push(LT);
-            }
-            "gt" => {
-                dst.push(GT);
-            }
-            "le" => {
-                dst.push(LE);
-            }
-            "ge" => {
-                dst.push(GE);
-            }
-            "eq" => {
-                dst.push(EQ);
-            }
-            "ne" => {
-                dst.push(NE);
-            }
-            "and" => {
-                dst.push(AND);
-            }
-            "or" => {
-                dst.push(OR);
-            }
-            "neg" => {
-                dst.push(NEG);
-            }
-            "not" => {
-                dst.push(NOT);
-            }
-            "prtc" => {
-                dst.push(PRTC);
-            }
-            "prti" => {
-                dst.push(PRTI);
-            }
-            "prts" => {
-                dst.push(PRTS);
-            }
-            "halt" => {
-                dst.push(HALT);
-            }
-            _ => {
-                dbg!(mnemonic);
-                return Err(CompileError::new(
-                    ErrorKind::VirtualMachineError,
-                    "illegal instruction",
-                ));
-            }
+    #[test]
+    fn test_tail_call() {
+        let limits = Limits {
+            recursion_depth: Some(5),
+            ..Limits::default()
+        };
+        let load = |s: &str, engine| {
+            VirtualMachineInterpreter::load_with_limits(s.lines(), engine, &limits)
+                .ok()
+                .unwrap()
+        };
+        // 1 から n までの和を、引数に累積して求める
+        let sum = |n: i32| {
+            format!(
+                r#"Datasize: 0 Strings: 0
+    push {}
+    push 0
+    call f
+    prti
+    halt
+f:
+    enter 2
+    fetchl 0
+    jz done
+    fetchl 0
+    push 1
+    sub
+    fetchl 1
+    fetchl 0
+    add
+    call f
+    leave
+    ret
+done:
+    fetchl 1
+    leave
+    ret
+"#,
+                n
+            )
         };
-        Ok(())
-    }
 
-    fn get_integer(&self) -> Result<i32> {
-        if let Ok(v) = &self.byte_code[self.pc..(self.pc + 4)].try_into() {
-            Ok(i32::from_ne_bytes(*v))
-        } else {
-            Err(CompileError::new(
-                ErrorKind::VirtualMachineError,
-                "invalid integer value",
-            ))
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut out = Vec::new();
+            load(&sum(3), engine).execute(&mut out).unwrap();
+            assert_eq!(b"6", &out[..]);
         }
+        // Decoded では自分自身の末尾呼び出しでコールスタックが伸びない
+        let mut out = Vec::new();
+        load(&sum(1000), Engine::Decoded).execute(&mut out).unwrap();
+        assert_eq!(b"500500", &out[..]);
+        let e = load(&sum(1000), Engine::Bytecode)
+            .execute(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!("call stack overflow (limit: 5)", e.message());
+
+        // トップレベルからも到達する call はそのまま
+        let mut ops = vec![
+            DecodedOp::Call(3),
+            DecodedOp::Leave,
+            DecodedOp::Ret,
+            DecodedOp::Enter(0),
+            DecodedOp::Jmp(0),
+        ];
+        decoded::fuse_tail_calls(&mut ops);
+        assert_eq!(DecodedOp::Call(3), ops[0]);
+        let mut ops = vec![
+            DecodedOp::Call(2),
+            DecodedOp::Halt,
+            DecodedOp::Enter(1),
+            DecodedOp::Call(2),
+            DecodedOp::Leave,
+            DecodedOp::Ret,
+        ];
+        decoded::fuse_tail_calls(&mut ops);
+        assert_eq!(DecodedOp::TailCall(3, 1), ops[3]);
     }
 
-    fn execute(&mut self, out: &mut Write) -> Result<()> {
-        loop {
-            let opcode = self.byte_code[self.pc as usize];
-            self.pc += 1;
+    #[test]
+    fn test_instructions() {
+        let s = r#"Datasize: 1 Strings: 1
+"\n"
+loop: fetch [0]
+    jz end
+    push 0
+    prts
+    jmp loop
+    push 1
+end: halt
+"#;
+        let vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        assert_eq!(
+            vec![
+                (DecodedOp::Fetch(0), Some(0)),
+                (DecodedOp::Jz(6), Some(1)),
+                (DecodedOp::Push(0), Some(0)),
+                (DecodedOp::Prts, Some(1)),
+                (DecodedOp::Jmp(0), Some(0)),
+                (DecodedOp::Push(1), None),
+                (DecodedOp::Halt, Some(0)),
+            ],
+            vm.instructions().unwrap()
+        );
+        assert_eq!(&["\n".to_string()], vm.strings());
+    }
 
-            match opcode {
-                FETCH => {
-                    let index = self.get_integer()?;
-                    self.stack[self.sp] = self.data[index as usize];
-                    self.sp += 1;
-                    self.pc += 4;
-                }
-                STORE => {
-                    let v = self.stack[self.sp - 1];
-                    self.sp -= 1;
-                    let index = self.get_integer()?;
-                    self.data[index as usize] = v;
-                    self.pc += 4;
-                }
-                PUSH => {
-                    let v = self.get_integer()?;
-                    self.stack[self.sp] = v;
-                    self.sp += 1;
-                    self.pc += 4;
-                }
-                JMP => {
-                    let offset = self.get_integer()?;
-                    self.pc = self.pc.wrapping_add(offset as usize);
-                }
-                JZ => {
-                    let condition = self.stack[self.sp - 1];
-                    self.sp -= 1;
-
-                    if condition == 0 {
-                        let offset = self.get_integer()?;
-                        self.pc = self.pc.wrapping_add(offset as usize);
-                    } else {
-                        self.pc += 4;
-                    }
-                }
-                ADD => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = operand0 + operand1;
-                    self.sp -= 1;
-                }
-                SUB => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = operand0 - operand1;
-                    self.sp -= 1;
-                }
-                MUL => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = operand0 * operand1;
-                    self.sp -= 1;
-                }
-                DIV => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = operand0 / operand1;
-                    self.sp -= 1;
-                }
-                MOD => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = operand0 % operand1;
-                    self.sp -= 1;
-                }
-                LT => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 < operand1 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                GT => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 > operand1 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                LE => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 <= operand1 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                GE => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 >= operand1 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                EQ => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 == operand1 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                NE => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 != operand1 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                AND => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 != 0 && operand1 != 0 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                OR => {
-                    let operand0 = self.stack[self.sp - 2];
-                    let operand1 = self.stack[self.sp - 1];
-                    self.stack[self.sp - 2] = if operand0 != 0 || operand1 != 0 { 1 } else { 0 };
-                    self.sp -= 1;
-                }
-                NEG => {
-                    self.stack[self.sp - 1] = -self.stack[self.sp - 1];
-                }
-                NOT => self.stack[self.sp - 1] = if self.stack[self.sp - 1] == 0 { 1 } else { 0 },
-                PRTC => {
-                    match std::char::from_u32(self.stack[self.sp - 1] as u32) {
-                        Some(c) => match out.write(format!("{}", c).as_bytes()) {
-                            Err(e) => {
-                                return Err(CompileError::new(
-                                    ErrorKind::VirtualMachineError,
-                                    format!("output error: {}", e),
-                                ));
-                            }
-                            _ => {}
-                        },
-                        None => {
-                            return Err(CompileError::new(
-                                ErrorKind::VirtualMachineError,
-                                format!("illegal character value: {}", self.stack[self.sp - 1]),
-                            ));
-                        }
-                    }
-                    self.sp -= 1;
-                }
-                PRTI => {
-                    match out.write(format!("{}", self.stack[self.sp - 1]).as_bytes()) {
-                        Err(e) => {
-                            return Err(CompileError::new(
-                                ErrorKind::VirtualMachineError,
-                                format!("output error: {}", e),
-                            ));
-                        }
-                        _ => {}
-                    }
-                    self.sp -= 1;
-                }
-                PRTS => {
-                    match out.write(self.string_pool[self.stack[self.sp - 1] as usize].as_bytes()) {
-                        Err(e) => {
-                            return Err(CompileError::new(
-                                ErrorKind::VirtualMachineError,
-                                format!("output error: {}", e),
-                            ));
-                        }
-                        _ => {}
-                    }
-                    self.sp -= 1;
-                }
-                HALT => break,
-                _ => {
-                    return Err(CompileError::new(
-                        ErrorKind::VirtualMachineError,
-                        format!("illegal instruction: {}", opcode),
-                    ))
-                }
-            }
+    #[test]
+    fn test_clock() {
+        let s = r#"Datasize: 1 Strings: 0
+    clock
+    store [0]
+    clock
+    fetch [0]
+    sub
+    prti
+    halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            let start = Instant::now();
+            let mut out = Vec::new();
+            vm.execute(&mut out).unwrap();
+            let elapsed = start.elapsed().as_millis() as i32;
+            let value: i32 = String::from_utf8(out).unwrap().parse().unwrap();
+            assert!(0 <= value && value <= elapsed);
         }
-        Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn test_case_4() {
-        let s = r#"Datasize: 1 Strings: 2
-"count is: "
-"\n"
-    0 push  1
+    fn test_profile() {
+        let s = r#"Datasize: 1 Strings: 0
+    0 push  0
     5 store [0]
    10 fetch [0]
-   15 push  10
+   15 push  3
    20 lt
-   21 jz     (43) 65
-   26 push  0
-   31 prts
-   32 fetch [0]
-   37 prti
-   38 push  1
-   43 prts
-   44 fetch [0]
-   49 push  1
-   54 add
-   55 store [0]
-   60 jmp    (-51) 10
-   65 halt"#
+   21 jz     (25) 47
+   26 fetch [0]
+   31 push  1
+   36 add
+   37 store [0]
+   42 jmp    (-33) 10
+   47 halt
+"#;
+        for &engine in &[Engine::Bytecode, Engine::Decoded] {
+            let mut vm = VirtualMachineInterpreter::load_with(s.lines(), engine).unwrap();
+            vm.enable_profile();
+            vm.execute(&mut Vec::new()).unwrap();
+
+            let profile = vm.profile().unwrap();
+            assert_eq!(4, profile.address_count(10));
+            assert_eq!(3, profile.address_count(36));
+            assert_eq!(4, profile.opcode_count(JZ));
+            assert_eq!(2, profile.max_stack_depth());
+            assert_eq!(2 + 4 * 4 + 3 * 5 + 1, profile.total());
+            assert_eq!((10, FETCH, 4), profile.hotspots(1)[0]);
+            assert!(profile.to_string().contains("max stack depth: 2"));
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let s = r#"Datasize: 0 Strings: 1
+"hi"
+    0 push  42
+    5 prti
+    6 pushs 0
+   11 prts
+   12 halt"#
             .to_string();
+        let trace = Rc::new(RefCell::new(Vec::new()));
         let mut out: Vec<u8> = Vec::new();
 
-        VirtualMachineInterpreter::interpret(s.lines(), &mut out).unwrap();
-        println!("{:?}", out);
+        let mut vm = VirtualMachineInterpreter::load(s.lines()).unwrap();
+        vm.set_trace(Box::new(SharedBuffer(trace.clone())));
+        vm.execute(&mut out).unwrap();
+
+        assert_eq!(b"42hi", &out[..]);
+        assert_eq!(
+            "    0 push 42\t; sp=0\n    5 prti\t; sp=1 top=42\n    6 pushs 0\t; sp=0\n   11 prts\t; sp=1 top=\"hi\"\n   12 halt\t; sp=0\n",
+            String::from_utf8(trace.borrow().clone()).unwrap()
+        );
     }
 
     #[test]
@@ -557,7 +2948,7 @@ mod tests {
             .to_string();
         let mut out: Vec<u8> = Vec::new();
 
-        VirtualMachineInterpreter::interpret(s.lines(), &mut out).unwrap();
+        interpret(&s, &mut out);
         assert_eq!(
             r#"1
 2
@@ -701,37 +3092,19 @@ Buzz
             .to_string();
         let mut out: Vec<u8> = Vec::new();
 
-        VirtualMachineInterpreter::interpret(s.lines(), &mut out).unwrap();
+        interpret(&s, &mut out);
 
-        assert_eq!(
-            r#"99 bottles of beer on the wall
+        assert_snapshot(
+            &out,
+            r#"bytes: 11060
+fnv1a64: 5d6acab96f50f26f
+99 bottles of beer on the wall
 99 bottles of beer
 Take one down, pass it around
 98 bottles of beer on the wall
 
-98 bottles of beer on the wall
-98 bottles of beer
-Take one down, pass it around
-97 bottles of beer on the wall
-"#
-            .as_bytes(),
-            &out[..223]
-        );
-
-        assert_eq!(
-            r#"2 bottles of beer on the wall
-2 bottles of beer
-Take one down, pass it around
-1 bottles of beer on the wall
-
-1 bottles of beer on the wall
-1 bottles of beer
-Take one down, pass it around
-0 bottles of beer on the wall
-
-"#
-            .as_bytes(),
-            &out[10842..]
+...
+"#,
         );
     }
 
@@ -801,7 +3174,7 @@ Take one down, pass it around
             .to_string();
         let mut out: Vec<u8> = Vec::new();
 
-        VirtualMachineInterpreter::interpret(s.lines(), &mut out).unwrap();
+        interpret(&s, &mut out);
 
         assert_eq!(
             r#"3 is prime
@@ -946,7 +3319,7 @@ Total primes found: 26
             .to_string();
         let mut out: Vec<u8> = Vec::new();
 
-        VirtualMachineInterpreter::interpret(s.lines(), &mut out).unwrap();
+        interpret(&s, &mut out);
 
         assert_eq!(r#"1111111111111111111111122222222222222222222222222222222222222222222222222222222222222222222222222211111
 1111111111111111111122222222222222222222222222222222222222222222222222222222222222222222222222222222211
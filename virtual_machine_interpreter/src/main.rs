@@ -1,25 +1,50 @@
-use virtual_machine_interpreter::VirtualMachineInterpreter;
+use lexical_analyzer::cli::{Command, Flag, Options};
+use lexical_analyzer::error::*;
+use virtual_machine_interpreter::{is_bytecode, VirtualMachineInterpreter};
 
-use std::env;
-use std::fs::File;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use std::io::{stderr, stdin, Write};
+use std::process;
 
-fn main() {
-    let mut reader: Box<dyn BufRead> = match env::args().nth(1) {
-        None => Box::new(BufReader::new(stdin())),
-        Some(filename) => Box::new(BufReader::new(
-            File::open(filename).expect("cannot open file"),
-        )),
-    };
+const COMMAND: Command = Command {
+    name: "virtual_machine_interpreter",
+    description: "Reads virtual machine assembly or bytecode and executes it.",
+    flags: &[Flag::Trace, Flag::Profile],
+};
+
+fn run(options: &Options) -> Result<()> {
+    let input = options.read_input_bytes()?;
+    let mut writer = options.create_output()?;
 
-    let mut writer: Box<dyn Write> = match env::args().nth(2) {
-        None => Box::new(BufWriter::new(stdout())),
-        Some(filename) => Box::new(BufWriter::new(
-            File::create(filename).expect("cannot create file"),
-        )),
+    // 先頭がバイトコードのファイルの印でなければアセンブリとして読む
+    let mut vm = if is_bytecode(&input) {
+        VirtualMachineInterpreter::load_bytes(&input)?
+    } else {
+        let str_in =
+            String::from_utf8(input).map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
+        VirtualMachineInterpreter::load(str_in.lines())?
     };
+    vm.set_input(Box::new(stdin()));
+    if options.trace {
+        vm.set_trace(Box::new(stderr()));
+    }
+    if options.profile {
+        vm.enable_profile();
+    }
+    vm.execute(&mut writer)?;
+    writer
+        .flush()
+        .map_err(|e| CompileError::new(ErrorKind::IOError, e))?;
 
-    let mut str_in = String::new();
-    reader.read_to_string(&mut str_in).expect("read failed");
-    VirtualMachineInterpreter::interpret(str_in.lines(), &mut writer).unwrap();
+    if let Some(profile) = vm.profile() {
+        eprint!("{}", profile);
+    }
+    // exit 命令の終了コードをプロセスの終了コードにする
+    if vm.exit_code() != 0 {
+        process::exit(vm.exit_code());
+    }
+    Ok(())
+}
+
+fn main() {
+    COMMAND.main(run);
 }
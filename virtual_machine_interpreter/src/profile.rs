@@ -0,0 +1,113 @@
+use isa::mnemonic;
+
+use std::fmt;
+
+/// 実行した命令の統計
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// opcode ごとの実行回数
+    opcode_counts: [u64; 256],
+    /// アドレスごとの実行回数と、そのアドレスの opcode
+    address_counts: Vec<(u64, u8)>,
+    /// スタックの最大の深さ
+    max_stack_depth: usize,
+}
+
+impl Profile {
+    pub fn new(code_size: usize) -> Self {
+        Profile {
+            opcode_counts: [0; 256],
+            address_counts: vec![(0, 0); code_size],
+            max_stack_depth: 0,
+        }
+    }
+
+    /// address の opcode を実行する直前に呼ぶ
+    pub(crate) fn record(&mut self, address: usize, opcode: u8, stack_depth: usize) {
+        self.opcode_counts[opcode as usize] += 1;
+        if let Some(count) = self.address_counts.get_mut(address) {
+            count.0 += 1;
+            count.1 = opcode;
+        }
+        self.max_stack_depth = self.max_stack_depth.max(stack_depth);
+    }
+
+    /// 実行した命令の総数
+    pub fn total(&self) -> u64 {
+        self.opcode_counts.iter().sum()
+    }
+
+    pub fn opcode_count(&self, opcode: u8) -> u64 {
+        self.opcode_counts[opcode as usize]
+    }
+
+    pub fn address_count(&self, address: usize) -> u64 {
+        self.address_counts.get(address).map_or(0, |count| count.0)
+    }
+
+    pub fn max_stack_depth(&self) -> usize {
+        self.max_stack_depth
+    }
+
+    /// 実行回数の多い順に、(アドレス, opcode, 回数) を最大 n 個返す
+    pub fn hotspots(&self, n: usize) -> Vec<(usize, u8, u64)> {
+        let mut hotspots: Vec<(usize, u8, u64)> = self
+            .address_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| count.0 > 0)
+            .map(|(address, &(count, opcode))| (address, opcode, count))
+            .collect();
+        hotspots.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        hotspots.truncate(n);
+        hotspots
+    }
+}
+
+/// 上位の表示件数
+const HOTSPOTS: usize = 20;
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        writeln!(f, "instructions executed: {}", total)?;
+        writeln!(f, "max stack depth: {}", self.max_stack_depth)?;
+
+        writeln!(f, "\nby opcode:")?;
+        let mut opcodes: Vec<(u8, u64)> = (0..=255u8)
+            .map(|opcode| (opcode, self.opcode_counts[opcode as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (opcode, count) in opcodes {
+            writeln!(
+                f,
+                "  {:<6} {:>12} {:6.2}%",
+                mnemonic(opcode),
+                count,
+                percent(count, total)
+            )?;
+        }
+
+        writeln!(f, "\nhotspots:")?;
+        for (address, opcode, count) in self.hotspots(HOTSPOTS) {
+            writeln!(
+                f,
+                "  {:>5} {:<6} {:>12} {:6.2}%",
+                address,
+                mnemonic(opcode),
+                count,
+                percent(count, total)
+            )?;
+        }
+        Ok(())
+    }
+}